@@ -0,0 +1,39 @@
+/// Copyright (c) 2022 Tetherion
+use std::{path::Path, process::Command};
+
+/// Embeds the git commit and the build profile the binary is built from, leaving out anything
+/// varying between builds of the same sources, e.g. the build time, so that builds are
+/// reproducible
+fn main() {
+    println!("cargo:rerun-if-env-changed=TETHERION_COMMIT");
+    let git_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(".git");
+    let head = git_dir.join("HEAD");
+    println!("cargo:rerun-if-changed={}", head.display());
+    // The branch the HEAD points to moves with every commit
+    if let Some(branch) = std::fs::read_to_string(&head)
+        .ok()
+        .and_then(|head| Some(head.strip_prefix("ref: ")?.trim().to_owned()))
+    {
+        println!("cargo:rerun-if-changed={}", git_dir.join(branch).display());
+    }
+
+    // Source archives have no git history, so they are built with the commit given explicitly
+    let commit = std::env::var("TETHERION_COMMIT")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        })
+        .unwrap_or_else(|| String::from("unknown"));
+    let profile = std::env::var("PROFILE").unwrap_or_else(|_| String::from("unknown"));
+
+    println!("cargo:rustc-env=TETHERION_COMMIT={}", commit);
+    println!("cargo:rustc-env=TETHERION_PROFILE={}", profile);
+}
@@ -0,0 +1,195 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::config::AlertsConfig,
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, HashSet},
+        fmt,
+        time::Instant,
+    },
+};
+
+/// A condition of the node worth the attention of its operator
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Alert {
+    /// The node lost its last connected peer
+    NoPeers,
+
+    /// No block was added to the local blockchain for a while
+    Stale {
+        /// The height of the local blockchain
+        height: u64,
+
+        /// The number of seconds since the latest block was added
+        idle_secs: u64,
+    },
+
+    /// The local blockchain lags behind the best blockchain advertised by the peers
+    TipLag {
+        /// The height of the local blockchain
+        local: u64,
+
+        /// The height of the best blockchain advertised by the peers
+        best: u64,
+    },
+}
+
+impl Alert {
+    /// Gets the kind of the alert, the same for all the alerts raised by a condition
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Alert::NoPeers => "no_peers",
+            Alert::Stale { .. } => "stale",
+            Alert::TipLag { .. } => "tip_lag",
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Alert::NoPeers => write!(f, "alert: no connected peers"),
+            Alert::Stale { height, idle_secs } => write!(
+                f,
+                "alert: no block added for {}s, the local blockchain is stuck at block {}",
+                idle_secs, height
+            ),
+            Alert::TipLag { local, best } => write!(
+                f,
+                "alert: the local blockchain is at block {} while the peers advertise block {}",
+                local, best
+            ),
+        }
+    }
+}
+
+/// Watches the health of the node, raising an alert once each time a condition starts to hold
+#[derive(Debug)]
+pub struct Monitor {
+    config: AlertsConfig,
+
+    /// Whether the node has ever been connected to a peer
+    had_peers: bool,
+
+    /// The height of the local blockchain at the previous check
+    height: u64,
+
+    /// When the height of the local blockchain last changed
+    last_block: Instant,
+
+    /// The height of the best blockchain advertised by the peers
+    best_advertised: u64,
+
+    /// The kinds of the alerts whose conditions still hold
+    raised: HashSet<&'static str>,
+
+    /// The number of alerts raised so far, by kind
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl Monitor {
+    pub fn new(config: AlertsConfig, now: Instant) -> Self {
+        Self {
+            config,
+            had_peers: false,
+            height: 0,
+            last_block: now,
+            best_advertised: 0,
+            raised: HashSet::new(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Records the height of a blockchain, or of a block, advertised by a peer
+    pub fn advertised(&mut self, height: u64) {
+        self.best_advertised = self.best_advertised.max(height);
+    }
+
+    /// Checks the conditions against the number of connected peers and the height of the local
+    /// blockchain, returning the alerts whose conditions started to hold since the previous check
+    pub fn check(&mut self, peers: usize, height: u64, now: Instant) -> Vec<Alert> {
+        if height != self.height {
+            self.height = height;
+            self.last_block = now;
+        }
+        let idle = now.duration_since(self.last_block);
+        let stale_after = self.config.stale_after();
+
+        let conditions = [
+            (self.had_peers && peers == 0).then_some(Alert::NoPeers),
+            (!stale_after.is_zero() && idle >= stale_after).then_some(Alert::Stale {
+                height,
+                idle_secs: idle.as_secs(),
+            }),
+            (self.best_advertised > height + self.config.max_tip_lag).then_some(Alert::TipLag {
+                local: height,
+                best: self.best_advertised,
+            }),
+        ];
+        self.had_peers |= peers > 0;
+
+        let mut alerts = Vec::new();
+        for (kind, alert) in ["no_peers", "stale", "tip_lag"].into_iter().zip(conditions) {
+            match alert {
+                Some(alert) if self.raised.insert(kind) => {
+                    *self.counts.entry(kind).or_default() += 1;
+                    alerts.push(alert);
+                }
+                Some(_) => {}
+                None => {
+                    self.raised.remove(kind);
+                }
+            }
+        }
+        alerts
+    }
+
+    /// Gets the number of alerts raised so far, by kind
+    pub fn counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::time::Duration};
+
+    #[test]
+    fn raise_alerts() {
+        let config = AlertsConfig {
+            stale_after: 60,
+            max_tip_lag: 2,
+        };
+        let start = Instant::now();
+        let mut monitor = Monitor::new(config, start);
+
+        // Being alone at startup is not an alert, losing the peers is
+        assert!(monitor.check(0, 0, start).is_empty());
+        assert!(monitor.check(1, 1, start).is_empty());
+        assert_eq!(monitor.check(0, 1, start), vec![Alert::NoPeers]);
+        assert!(monitor.check(0, 1, start).is_empty());
+
+        monitor.advertised(4);
+        let later = start + Duration::from_secs(60);
+        let kinds: Vec<_> = monitor.check(2, 1, later).iter().map(Alert::kind).collect();
+        assert_eq!(kinds, vec!["stale", "tip_lag"]);
+
+        // A new block clears the stale alert, which is raised again once the node is stuck again
+        assert!(monitor.check(2, 2, later).is_empty());
+        assert_eq!(
+            monitor.check(0, 2, later + Duration::from_secs(60)),
+            vec![
+                Alert::NoPeers,
+                Alert::Stale {
+                    height: 2,
+                    idle_secs: 60
+                }
+            ]
+        );
+        assert_eq!(
+            monitor.counts(),
+            &BTreeMap::from([("no_peers", 2), ("stale", 2), ("tip_lag", 1)])
+        );
+    }
+}
@@ -0,0 +1,172 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::hash, finality::BlockRef, merkle::MerkleProof, payload::Payload,
+        tetherion::Tetherion,
+    },
+    serde::{Deserialize, Serialize},
+    std::{fs, io, path::Path},
+};
+
+/// The evidence of a document having existed when the block anchoring its digest was created
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AnchorReceipt {
+    /// The ID of the block anchoring the digest
+    pub block_id: u64,
+
+    /// The hash of the block anchoring the digest
+    pub block_hash: String,
+
+    /// The timestamp of the block anchoring the digest
+    pub timestamp: i64,
+
+    /// The proof of the anchor being stored in the block
+    pub proof: MerkleProof,
+}
+
+/// Computes the SHA256 digest, in HEX format, of the file at the given path
+pub fn digest_file(path: &Path) -> io::Result<String> {
+    Ok(hex::encode(hash(&fs::read(path)?)))
+}
+
+/// Locates the earliest block anchoring the given digest
+pub fn locate(tetherion: &Tetherion<Payload>, digest: &str) -> Option<AnchorReceipt> {
+    tetherion.blocks().iter().find_map(|block| {
+        let anchored = matches!(block.data(), Payload::Anchor { digest: d } if d == digest);
+        if !anchored {
+            return None;
+        }
+
+        let tx_id = block.tx_ids().into_iter().next()?;
+        Some(AnchorReceipt {
+            block_id: block.id,
+            block_hash: block.hash.clone(),
+            timestamp: block.timestamp(),
+            proof: block.prove(&tx_id)?,
+        })
+    })
+}
+
+/// A block of a chain committed to by a block of another chain
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChainAnchor {
+    /// The block of the anchoring chain storing the anchor
+    pub anchored_in: BlockRef,
+
+    /// The anchored block
+    pub block: BlockRef,
+}
+
+/// Gets the anchors of the chain with the given ID stored in the anchoring chain, the oldest first
+pub fn chain_anchors(anchoring: &Tetherion<Payload>, chain_id: &str) -> Vec<ChainAnchor> {
+    anchoring
+        .blocks()
+        .iter()
+        .filter_map(|block| match block.data() {
+            Payload::ChainAnchor {
+                chain_id: id,
+                height,
+                hash,
+            } if id == chain_id => Some(ChainAnchor {
+                anchored_in: BlockRef {
+                    height: block.id,
+                    hash: block.hash.clone(),
+                },
+                block: BlockRef {
+                    height: *height,
+                    hash: hash.clone(),
+                },
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks that the anchored block is part of the anchored chain, returning the reason if it is not
+pub fn verify_chain_anchor(
+    anchored: &Tetherion<Payload>,
+    anchor: &ChainAnchor,
+) -> Result<(), String> {
+    let first = anchored.blocks()[0].id;
+    let block = anchor
+        .block
+        .height
+        .checked_sub(first)
+        .and_then(|index| anchored.blocks().get(index as usize));
+    match block {
+        Some(block) if block.hash == anchor.block.hash => Ok(()),
+        Some(block) => Err(format!(
+            "the chain has block {} at height {} instead",
+            block.hash, block.id
+        )),
+        None => Err(String::from("the chain has no block at this height")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::block::Block};
+
+    #[test]
+    fn locate_anchor() {
+        const DIFFICULTY: usize = 1;
+
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let digest = hex::encode(hash(b"document"));
+        let previous_hash = tetherion.blocks()[0].hash.clone();
+        let anchor = Payload::Anchor {
+            digest: digest.clone(),
+        };
+        tetherion
+            .add_block(Block::new(1, &previous_hash, anchor, DIFFICULTY))
+            .unwrap();
+
+        let receipt = locate(&tetherion, &digest).unwrap();
+        let block = &tetherion.blocks()[1];
+        assert_eq!(receipt.block_hash, block.hash);
+        assert_eq!(receipt.timestamp, block.timestamp());
+        assert!(receipt.proof.verify(&block.header().merkle_root));
+
+        assert!(locate(&tetherion, &hex::encode(hash(b"other document"))).is_none());
+    }
+
+    #[test]
+    fn verify_chain_anchors() {
+        const DIFFICULTY: usize = 1;
+
+        let genesis = || Tetherion::new(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let mut anchored = genesis();
+        let previous_hash = anchored.blocks()[0].hash.clone();
+        let data = Payload::Text(String::from("a"));
+        anchored
+            .add_block(Block::new(1, &previous_hash, data, DIFFICULTY))
+            .unwrap();
+        let tip = &anchored.blocks()[1];
+
+        let mut anchoring = genesis();
+        let previous_hash = anchoring.blocks()[0].hash.clone();
+        let data = Payload::Text(String::from("b"));
+        anchoring
+            .add_block(Block::new(1, &previous_hash, data, DIFFICULTY))
+            .unwrap();
+        for (id, hash) in [(2, tip.hash.clone()), (3, String::from("00ab"))] {
+            let previous_hash = anchoring.blocks().last().unwrap().hash.clone();
+            let anchor = Payload::ChainAnchor {
+                chain_id: String::from("test1"),
+                height: 1,
+                hash,
+            };
+            anchoring
+                .add_block(Block::new(id, &previous_hash, anchor, DIFFICULTY))
+                .unwrap();
+        }
+
+        let anchors = chain_anchors(&anchoring, "test1");
+        assert_eq!(anchors.len(), 2);
+        assert_eq!(anchors[0].anchored_in.height, 2);
+        assert_eq!(anchors[0].block.hash, tip.hash);
+        assert_eq!(verify_chain_anchor(&anchored, &anchors[0]), Ok(()));
+        assert!(verify_chain_anchor(&anchored, &anchors[1]).is_err());
+        assert!(chain_anchors(&anchoring, "test2").is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{block::Block, codec::Format, payload::Payload},
+    flate2::{read::GzDecoder, write::GzEncoder, Compression},
+    serde::{Deserialize, Serialize},
+    std::{
+        fs,
+        io::{self, Read, Write},
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+/// An immutable compressed file storing consecutive old blocks, one record per block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// The ID of the first block in the segment
+    pub first: u64,
+
+    /// The ID of the last block in the segment
+    pub last: u64,
+
+    /// The hash of the last block in the segment
+    pub last_hash: String,
+
+    /// The SHA256 hash, in HEX format, of the segment's file
+    pub checksum: String,
+}
+
+impl Segment {
+    /// Gets the name of the segment's file, storing the blocks in the given format
+    pub fn file_name(&self, format: Format) -> String {
+        format!(
+            "segment-{:010}-{:010}.{}.gz",
+            self.first,
+            self.last,
+            format.records_extension()
+        )
+    }
+}
+
+/// The segments the oldest blocks of the store are archived to, along with the index listing
+/// them in order
+#[derive(Debug, Clone)]
+pub struct Archive {
+    /// The directory the segments and the index are stored in
+    dir: PathBuf,
+
+    /// The format the blocks are stored in
+    format: Format,
+}
+
+impl Archive {
+    /// The file listing the segments, relative to the archive's directory
+    const INDEX_FILE: &'static str = "index.json";
+
+    pub fn new(dir: &Path, format: Format) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            format,
+        }
+    }
+
+    /// Gets the segments, the oldest first
+    pub fn segments(&self) -> io::Result<Vec<Segment>> {
+        match fs::read(self.dir.join(Archive::INDEX_FILE)) {
+            Ok(content) => serde_json::from_slice(&content).map_err(io::Error::from),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Archives the consecutive blocks to a new segment following the existing ones
+    pub fn push(&self, blocks: &[Arc<Block<Payload>>]) -> io::Result<Segment> {
+        let (first, last) = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(io::Error::other("cannot archive an empty segment")),
+        };
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for block in blocks {
+            let record = self.format.encode_record(block).map_err(io::Error::other)?;
+            encoder.write_all(&record)?;
+        }
+        let content = encoder.finish()?;
+
+        let segment = Segment {
+            first: first.id,
+            last: last.id,
+            last_hash: last.hash.clone(),
+            checksum: hex::encode(crate::block::hash(&content)),
+        };
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(&segment), content)?;
+
+        let mut segments = self.segments()?;
+        segments.push(segment.clone());
+        self.save_index(&segments)?;
+        Ok(segment)
+    }
+
+    /// Reads the records of the segment's file, i.e. the encoded blocks stored in it
+    pub fn read(&self, segment: &Segment) -> io::Result<Vec<Vec<u8>>> {
+        let mut content = Vec::new();
+        GzDecoder::new(fs::File::open(self.path(segment))?).read_to_end(&mut content)?;
+        Ok(self.format.split_records(&content))
+    }
+
+    /// Gets the path of the segment's file
+    pub fn path(&self, segment: &Segment) -> PathBuf {
+        self.dir.join(segment.file_name(self.format))
+    }
+
+    /// Keeps the given number of the oldest segments only, deleting the others
+    pub fn truncate(&self, count: usize) -> io::Result<()> {
+        let mut segments = self.segments()?;
+        if count >= segments.len() {
+            return Ok(());
+        }
+
+        for segment in segments.drain(count..) {
+            log::info!(
+                "dropping archived blocks {}-{}",
+                segment.first,
+                segment.last
+            );
+            fs::remove_file(self.path(&segment))?;
+        }
+        self.save_index(&segments)
+    }
+
+    /// Checks that the segment's file matches its checksum and stores valid consecutive blocks,
+    /// following the previous segment, if any, and ending with the block recorded in the index
+    pub fn verify(&self, segment: &Segment, previous: Option<&Segment>) -> Result<(), String> {
+        if previous.is_some_and(|previous| segment.first != previous.last + 1) {
+            return Err(String::from("segment does not follow the previous one"));
+        }
+
+        let content = fs::read(self.path(segment)).map_err(|err| err.to_string())?;
+        if hex::encode(crate::block::hash(&content)) != segment.checksum {
+            return Err(String::from("checksum mismatch"));
+        }
+
+        let mut previous_hash = previous.map(|previous| previous.last_hash.clone());
+        let mut last: Option<Block<Payload>> = None;
+        for record in self.read(segment).map_err(|err| err.to_string())? {
+            let block: Block<Payload> =
+                self.format.decode(&record).map_err(|err| err.to_string())?;
+            let expected = last.as_ref().map_or(segment.first, |block| block.id + 1);
+            if block.id != expected || !block.has_valid_hash() {
+                return Err(format!("block {} is invalid", block.id));
+            }
+            if previous_hash.is_some_and(|hash| block.previous_hash != hash) {
+                return Err(format!(
+                    "block {} does not follow the previous one",
+                    block.id
+                ));
+            }
+            previous_hash = Some(block.hash.clone());
+            last = Some(block);
+        }
+
+        match last {
+            Some(last) if last.id == segment.last && last.hash == segment.last_hash => Ok(()),
+            _ => Err(String::from("last block does not match the index")),
+        }
+    }
+
+    /// Gets the disk usage of the segments and the index, in bytes
+    pub fn size(&self) -> io::Result<u64> {
+        match fs::read_dir(&self.dir) {
+            Ok(entries) => entries.map(|entry| Ok(entry?.metadata()?.len())).sum(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Replaces the index with the given segments
+    fn save_index(&self, segments: &[Segment]) -> io::Result<()> {
+        let path = self.dir.join(Archive::INDEX_FILE);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(segments).map_err(io::Error::from)?)?;
+        fs::rename(tmp, path)
+    }
+}
@@ -0,0 +1,264 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    libp2p::{core::multiaddr::Protocol, Multiaddr, PeerId},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fmt, fs, io,
+        net::IpAddr,
+        path::{Path, PathBuf},
+        str::FromStr,
+        time::Duration,
+    },
+};
+
+/// The reason of the bans made without one
+const DEFAULT_REASON: &str = "banned manually";
+
+/// What a ban applies to: a peer, or every peer connecting from an IP address
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub enum BanTarget {
+    Peer(PeerId),
+    Ip(IpAddr),
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Peer(peer) => write!(f, "{}", peer),
+            Self::Ip(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+impl FromStr for BanTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse() {
+            return Ok(Self::Ip(ip));
+        }
+        s.parse()
+            .map(Self::Peer)
+            .map_err(|_| format!("invalid peer ID or IP address {}", s))
+    }
+}
+
+impl TryFrom<String> for BanTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<BanTarget> for String {
+    fn from(target: BanTarget) -> Self {
+        target.to_string()
+    }
+}
+
+/// A peer or an IP address the node keeps no connection with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    pub target: BanTarget,
+    pub reason: String,
+
+    /// The time the ban was made at, in seconds since the Unix epoch
+    pub since: i64,
+
+    /// The time the ban expires at, in seconds since the Unix epoch; never if not set
+    pub until: Option<i64>,
+}
+
+impl Ban {
+    /// Checks whether the ban still applies at the given time
+    pub fn is_active(&self, now: i64) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+}
+
+/// The bans in effect, kept in a file so that they survive restarts
+#[derive(Debug, Default)]
+pub struct BanList {
+    /// The file the bans are kept in, if any
+    path: Option<PathBuf>,
+
+    bans: HashMap<BanTarget, Ban>,
+}
+
+impl BanList {
+    /// Loads the bans kept in the file at the given path, if it exists, leaving out the ones
+    /// expired at the given time
+    pub fn load(path: &Path, now: i64) -> io::Result<Self> {
+        let bans: Vec<Ban> = match fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path: Some(path.to_owned()),
+            bans: bans
+                .into_iter()
+                .filter(|ban| ban.is_active(now))
+                .map(|ban| (ban.target, ban))
+                .collect(),
+        })
+    }
+
+    /// Adds the ban, replacing the previous one of the same target, if any
+    pub fn ban(&mut self, ban: Ban) -> io::Result<()> {
+        self.bans.insert(ban.target, ban);
+        self.save()
+    }
+
+    /// Lifts the ban of the target, returning whether there was one
+    pub fn unban(&mut self, target: &BanTarget) -> io::Result<bool> {
+        if self.bans.remove(target).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Gets the ban in effect at the given time on the peer, or on the IP address it connected
+    /// from, if any
+    pub fn find(&self, peer: &PeerId, ip: Option<IpAddr>, now: i64) -> Option<&Ban> {
+        let banned = |target| self.bans.get(&target).filter(|ban| ban.is_active(now));
+        banned(BanTarget::Peer(*peer)).or_else(|| ip.and_then(|ip| banned(BanTarget::Ip(ip))))
+    }
+
+    /// Gets the bans in effect at the given time, sorted by their targets
+    pub fn active(&self, now: i64) -> Vec<&Ban> {
+        let mut bans: Vec<_> = self
+            .bans
+            .values()
+            .filter(|ban| ban.is_active(now))
+            .collect();
+        bans.sort_by_cached_key(|ban| ban.target.to_string());
+        bans
+    }
+
+    /// Forgets about the bans expired at the given time
+    pub fn expire(&mut self, now: i64) -> io::Result<()> {
+        let len = self.bans.len();
+        self.bans.retain(|_, ban| ban.is_active(now));
+        if self.bans.len() == len {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Writes the bans to their file, if any, replacing it at once so that a crash never leaves
+    /// it half written
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut bans: Vec<_> = self.bans.values().collect();
+        bans.sort_by_cached_key(|ban| ban.target.to_string());
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&bans)?)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// Gets the IP address of the address, if it starts with one
+pub fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    match addr.iter().next()? {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    }
+}
+
+/// Parses a duration made of a number and a unit, `s`, `m`, `h` or `d`, e.g. `30m`; seconds if
+/// the unit is left out
+pub fn parse_duration(arg: &str) -> Option<Duration> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let number: u64 = number.parse().ok().filter(|number| *number > 0)?;
+    let secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(number.checked_mul(secs)?))
+}
+
+/// Parses the arguments of `ban <peer ID or IP address> [duration] [reason]` into a ban made at
+/// the given time, which never expires unless given a duration
+pub fn parse_ban(args: &str, now: i64) -> Result<Ban, String> {
+    let mut words = args.split_whitespace().peekable();
+    let target: BanTarget = words
+        .next()
+        .ok_or("usage: ban <peer ID or IP address> [duration] [reason]")?
+        .parse()?;
+    let duration = match words.peek() {
+        Some(word) if word.starts_with(|c: char| c.is_ascii_digit()) => {
+            let duration = parse_duration(word).ok_or(format!("invalid duration {}", word))?;
+            words.next();
+            Some(duration)
+        }
+        _ => None,
+    };
+    let reason = words.collect::<Vec<_>>().join(" ");
+    Ok(Ban {
+        target,
+        reason: if reason.is_empty() {
+            String::from(DEFAULT_REASON)
+        } else {
+            reason
+        },
+        since: now,
+        until: duration.map(|duration| now.saturating_add(duration.as_secs() as i64)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_bans() {
+        let path = std::env::temp_dir().join(format!("tetherion-bans-{}.json", std::process::id()));
+        let now = 1_700_000_000;
+        let peer = PeerId::random();
+        let mut bans = BanList::load(&path, now).unwrap();
+
+        let ban = parse_ban(&format!("{} 1h spamming blocks", peer), now).unwrap();
+        assert_eq!(ban.until, Some(now + 3600));
+        assert_eq!(ban.reason, "spamming blocks");
+        bans.ban(ban).unwrap();
+        bans.ban(parse_ban("10.0.0.1", now).unwrap()).unwrap();
+        assert!(parse_ban("10.0.0.1 0d", now).is_err());
+        assert!(parse_ban("not-a-peer", now).is_err());
+
+        let ip = ip_of(&"/ip4/10.0.0.1/tcp/4001".parse().unwrap());
+        let stranger = PeerId::random();
+        assert_eq!(
+            bans.find(&stranger, ip, now).unwrap().reason,
+            DEFAULT_REASON
+        );
+        assert!(bans.find(&stranger, None, now).is_none());
+        assert!(bans.find(&peer, None, now + 3599).is_some());
+        assert!(bans.find(&peer, None, now + 3600).is_none());
+
+        // The bans are applied again once loaded, but the expired ones
+        let later = now + 7200;
+        let mut loaded = BanList::load(&path, later).unwrap();
+        assert_eq!(loaded.active(later).len(), 1);
+        assert!(loaded
+            .unban(&BanTarget::Ip("10.0.0.1".parse().unwrap()))
+            .unwrap());
+        assert!(BanList::load(&path, later)
+            .unwrap()
+            .active(later)
+            .is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,11 +1,22 @@
 /// Copyright (c) 2022 Tetherion
 use {
+    crate::{
+        config::MiningConfig,
+        merkle::{self, MerkleProof},
+        tetherion::BlockData,
+    },
+    libp2p::identity::ed25519,
     serde::{Deserialize, Serialize},
     sha2::{Digest, Sha256},
+    std::{
+        sync::atomic::{AtomicBool, AtomicU64, Ordering},
+        thread,
+        time::Instant,
+    },
 };
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Block<T: std::fmt::Display> {
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Block<T: BlockData> {
     /// The ID indicating the position of the block in the blockchain
     pub id: u64,
 
@@ -21,32 +32,179 @@ pub struct Block<T: std::fmt::Display> {
     /// The nonce calculated by the Proof of Work consensus algorithm
     nonce: u64,
 
+    /// The root of the Merkle tree built on top of the data stored in the block
+    merkle_root: String,
+
     /// The data stored in the block
     data: T,
+
+    /// The signature of the block's hash, in HEX format, by the authority that sealed the block
+    #[serde(default)]
+    signature: Option<String>,
 }
 
-impl<T: std::fmt::Display> Block<T> {
+/// The part of a block committed to by the block's hash, allowing the Proof of Work and the
+/// linkage of blocks to be validated without the data stored in them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// The ID indicating the position of the block in the blockchain
+    pub id: u64,
+
+    /// The hash value of the block in the blockchain
+    pub hash: String,
+
+    /// The hash value of the previous block in the blockchain
+    pub previous_hash: String,
+
+    /// The timestamp of when the block was created
+    pub timestamp: i64,
+
+    /// The nonce calculated by the Proof of Work consensus algorithm
+    pub nonce: u64,
+
+    /// The root of the Merkle tree built on top of the data stored in the block
+    pub merkle_root: String,
+
+    /// The signature of the block's hash, in HEX format, by the authority that sealed the block
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+impl BlockHeader {
+    /// Checks if the header's hash is the one of its content and has the specified difficulty
+    pub fn is_valid(&self, difficulty: usize) -> bool {
+        has_difficulty(&self.hash, difficulty) && self.has_valid_hash()
+    }
+
+    /// Checks if the header's hash is the one of its content
+    pub fn has_valid_hash(&self) -> bool {
+        self.hash == self.compute_hash()
+    }
+
+    /// Computes the SHA256 hash, in HEX format, of the header's content
+    pub fn compute_hash(&self) -> String {
+        let mut hash_data = self.id.to_string();
+        hash_data.push_str(&self.previous_hash);
+        hash_data.push_str(&self.timestamp.to_string());
+        hash_data.push_str(&self.nonce.to_string());
+        hash_data.push_str(&self.merkle_root);
+        hex::encode(hash(hash_data.as_bytes()))
+    }
+}
+
+impl<T: BlockData> Block<T> {
     pub fn new(id: u64, previous_hash: &str, data: T, difficulty: usize) -> Self {
+        Block::<T>::try_new(
+            id,
+            previous_hash,
+            data,
+            difficulty,
+            &MiningConfig::default(),
+            &AtomicBool::new(false),
+        )
+        .expect("Mining should not be cancelled")
+    }
+
+    /// Creates a new block, unless mining gets cancelled before a valid nonce is found
+    pub fn try_new(
+        id: u64,
+        previous_hash: &str,
+        data: T,
+        difficulty: usize,
+        mining: &MiningConfig,
+        cancelled: &AtomicBool,
+    ) -> Option<Self> {
+        let timestamp = chrono::Utc::now().timestamp();
+        Block::<T>::with_nonce(id, previous_hash, timestamp, 0, data).try_mine(
+            difficulty,
+            mining,
+            cancelled,
+            &AtomicU64::new(0),
+        )
+    }
+
+    /// Mines the block, created with no nonce, trying the nonces from the one stored in
+    /// `progress` on, unless mining gets cancelled before a valid nonce is found. Meanwhile, every
+    /// nonce below the one stored in `progress` has been tried, so that mining the same block can
+    /// be resumed from there.
+    pub fn try_mine(
+        mut self,
+        difficulty: usize,
+        mining: &MiningConfig,
+        cancelled: &AtomicBool,
+        progress: &AtomicU64,
+    ) -> Option<Self> {
+        if self.mine(difficulty, mining, cancelled, progress) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /// Creates a block whose nonce has been found outside of the node, e.g. by an external miner
+    pub fn with_nonce(id: u64, previous_hash: &str, timestamp: i64, nonce: u64, data: T) -> Self {
         let mut block = Self {
-            id: id,
+            id,
             hash: String::from(""),
             previous_hash: String::from(previous_hash),
-            timestamp: chrono::Utc::now().timestamp(),
-            nonce: 0,
-            data: data,
+            timestamp,
+            nonce,
+            merkle_root: Block::<T>::merkle_root_of(&data),
+            data,
+            signature: None,
         };
 
-        block.mine(difficulty);
+        block.hash = block.header().compute_hash();
         block
     }
 
+    /// Creates a block sealed by an authority's signature instead of being mined
+    pub fn sealed(
+        id: u64,
+        previous_hash: &str,
+        timestamp: i64,
+        data: T,
+        keys: &ed25519::Keypair,
+    ) -> Self {
+        let mut block = Block::<T>::with_nonce(id, previous_hash, timestamp, 0, data);
+        block.signature = Some(hex::encode(keys.sign(block.hash.as_bytes())));
+        block
+    }
+
+    /// Reassembles a block from its header and data, whose match is only checked along with the
+    /// block's hash
+    pub fn from_header(header: BlockHeader, data: T) -> Self {
+        Self {
+            id: header.id,
+            hash: header.hash,
+            previous_hash: header.previous_hash,
+            timestamp: header.timestamp,
+            nonce: header.nonce,
+            merkle_root: header.merkle_root,
+            data,
+            signature: header.signature,
+        }
+    }
+
+    /// Gets the block's header
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            id: self.id,
+            hash: self.hash.clone(),
+            previous_hash: self.previous_hash.clone(),
+            timestamp: self.timestamp,
+            nonce: self.nonce,
+            merkle_root: self.merkle_root.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+
     /// Gets the block's timestamp
     pub fn timestamp(&self) -> i64 {
         self.timestamp
     }
 
     /// Gets the data contained in the block
-    #[allow(dead_code)]
     pub fn data(&self) -> &T {
         &self.data
     }
@@ -56,52 +214,192 @@ impl<T: std::fmt::Display> Block<T> {
         Block::<T>::new(0, "genesis", data, difficulty)
     }
 
-    /// Checks if block's hash has the specified difficulty
-    pub fn is_valid(&self, difficulty: usize) -> bool {
-        const HEX_SIZE: usize = 2;
+    /// Checks if block's hash commits to the block's content
+    pub fn has_valid_hash(&self) -> bool {
+        self.header().has_valid_hash() && self.merkle_root == Block::<T>::merkle_root_of(&self.data)
+    }
+
+    /// Computes the hash, in HEX format, the block's content commits to, the Merkle root
+    /// included, which the block's hash should be
+    pub fn compute_hash(&self) -> String {
+        BlockHeader {
+            merkle_root: Block::<T>::merkle_root_of(&self.data),
+            ..self.header()
+        }
+        .compute_hash()
+    }
+
+    /// Computes the root of the Merkle tree built on top of the data
+    pub fn merkle_root_of(data: &T) -> String {
+        merkle::root(&Block::<T>::leaves_of(data))
+    }
+
+    /// Computes the leaves of the Merkle tree built on top of the data, each of them being the
+    /// ID of a transaction stored in the block
+    fn leaves_of(data: &T) -> Vec<String> {
+        data.tx_ids()
+    }
+
+    /// Gets the IDs of the transactions stored in the block
+    pub fn tx_ids(&self) -> Vec<String> {
+        Block::<T>::leaves_of(&self.data)
+    }
 
-        let pattern = &"0".repeat(difficulty * HEX_SIZE);
-        self.hash.starts_with(pattern)
+    /// Creates the proof that the transaction with the given ID is stored in the block
+    pub fn prove(&self, tx_id: &str) -> Option<MerkleProof> {
+        let leaves = Block::<T>::leaves_of(&self.data);
+        let index = leaves.iter().position(|leaf| leaf == tx_id)?;
+        merkle::prove(&leaves, index)
     }
 
-    /// Mines a block by producing a valid nonce and the block's hash
-    fn mine(&mut self, difficulty: usize) {
+    /// Mines a block by producing a valid nonce and the block's hash, splitting the nonce space
+    /// among the configured number of threads. The search starts from the nonce stored in
+    /// `progress`, which is kept up to date with the nonce below which every nonce was tried.
+    /// Returns `false` if mining got cancelled before a valid nonce was found.
+    fn mine(
+        &mut self,
+        difficulty: usize,
+        mining: &MiningConfig,
+        cancelled: &AtomicBool,
+        progress: &AtomicU64,
+    ) -> bool {
         log::info!("Mining the block...");
 
         if self.nonce != 0 {
             panic!("Block should be mined only once, at its creation time");
         }
 
-        loop {
-            self.hash = hex::encode(Block::<T>::hash(self.hash_data().as_bytes()));
-            if self.is_valid(difficulty) {
+        // The input for the hash algorithm is made of the block's ID, previous hash, timestamp,
+        // nonce and Merkle root, so only the nonce changes in between the attempts
+        let start = progress.load(Ordering::Relaxed);
+        let search = NonceSearch {
+            prefix: format!("{}{}{}", self.id, self.previous_hash, self.timestamp),
+            suffix: self.merkle_root.clone(),
+            difficulty,
+            mining,
+            cancelled,
+            found: AtomicBool::new(false),
+            next: (0..mining.threads)
+                .map(|worker| AtomicU64::new(start + worker as u64))
+                .collect(),
+            progress,
+        };
+
+        let result = thread::scope(|scope| {
+            let workers: Vec<_> = (0..mining.threads)
+                .map(|worker| {
+                    let search = &search;
+                    scope.spawn(move || search_nonce(search, worker))
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("Mining thread should not panic"))
+                .next()
+        });
+
+        match result {
+            Some((nonce, hash)) => {
+                self.nonce = nonce;
+                self.hash = hash;
                 log::info!("Valid nonce found: {}", self.nonce);
-                break;
+                true
+            }
+            None => {
+                log::info!("Mining of block {} cancelled", self.id);
+                false
             }
-
-            // Try the next nonce
-            self.nonce += 1;
         }
     }
+}
 
-    /// Creates the input for the hash algorithm
-    fn hash_data(&self) -> String {
-        let mut hash_data = self.id.to_string();
-        hash_data.push_str(&self.previous_hash);
-        hash_data.push_str(&self.timestamp.to_string());
-        hash_data.push_str(&self.nonce.to_string());
-        hash_data.push_str(&self.data.to_string());
-        hash_data
-    }
+/// The search for a valid nonce shared by the mining threads
+struct NonceSearch<'a> {
+    /// The part of the hash input preceding the nonce
+    prefix: String,
+
+    /// The part of the hash input following the nonce
+    suffix: String,
+
+    /// The difficulty the hash must satisfy
+    difficulty: usize,
+
+    /// The mining settings
+    mining: &'a MiningConfig,
+
+    /// The flag signalling the threads to give up
+    cancelled: &'a AtomicBool,
+
+    /// The flag set by the thread finding a valid nonce
+    found: AtomicBool,
+
+    /// The next nonce each thread tries
+    next: Vec<AtomicU64>,
+
+    /// The nonce below which every nonce was tried
+    progress: &'a AtomicU64,
+}
+
+/// Searches for a valid nonce among the ones assigned to the given worker, i.e. the ones equal
+/// to the worker's index modulo the number of mining threads past the starting nonce, until
+/// either this or another worker finds one or mining gets cancelled
+fn search_nonce(search: &NonceSearch, worker: usize) -> Option<(u64, String)> {
+    const BATCH_SIZE: u32 = 1024;
+
+    let mining = search.mining;
+    let mut nonce = search.next[worker].load(Ordering::Relaxed);
+    loop {
+        let started = Instant::now();
+        for _ in 0..BATCH_SIZE {
+            if search.cancelled.load(Ordering::Relaxed) || search.found.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let input = format!("{}{}{}", search.prefix, nonce, search.suffix);
+            let hash = hex::encode(hash(input.as_bytes()));
+            if has_difficulty(&hash, search.difficulty) {
+                search.found.store(true, Ordering::Relaxed);
+                return Some((nonce, hash));
+            }
 
-    /// Creates a SHA256 hash value in HEX format from raw bytes
-    fn hash(data: &[u8]) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        hasher.finalize().as_slice().to_owned()
+            // Try the next nonce assigned to this worker
+            nonce += mining.threads as u64;
+        }
+
+        // Every thread tried the nonces below the lowest one they are about to try
+        search.next[worker].store(nonce, Ordering::Relaxed);
+        let tried = search
+            .next
+            .iter()
+            .map(|next| next.load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(nonce);
+        search.progress.fetch_max(tried, Ordering::Relaxed);
+
+        // Stay idle for the configured share of the time spent hashing
+        if mining.throttle_percent > 0 {
+            let throttle = u32::from(mining.throttle_percent);
+            thread::sleep(started.elapsed() * throttle / (100 - throttle));
+        }
     }
 }
 
+/// Checks if the hash in HEX format has the specified difficulty
+fn has_difficulty(hash: &str, difficulty: usize) -> bool {
+    const HEX_SIZE: usize = 2;
+
+    let pattern = &"0".repeat(difficulty * HEX_SIZE);
+    hash.starts_with(pattern)
+}
+
+/// Creates a SHA256 hash value from raw bytes
+pub fn hash(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize().to_vec()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,8 +416,8 @@ mod tests {
             VALID_DIFFICULTY,
         );
 
-        assert!(block.is_valid(VALID_DIFFICULTY));
-        assert!(!block.is_valid(INVALID_DIFFICULTY));
+        assert!(block.header().is_valid(VALID_DIFFICULTY));
+        assert!(!block.header().is_valid(INVALID_DIFFICULTY));
     }
 
     #[test]
@@ -130,6 +428,110 @@ mod tests {
         let mut block =
             Block::<String>::new(0, "some_previous_hash", String::from("data"), DIFFICULTY);
 
-        block.mine(DIFFICULTY);
+        block.mine(
+            DIFFICULTY,
+            &MiningConfig::default(),
+            &AtomicBool::new(false),
+            &AtomicU64::new(0),
+        );
+    }
+
+    #[test]
+    fn resume_mining() {
+        const DIFFICULTY: usize = 1;
+
+        let unmined = || Block::<String>::with_nonce(1, "some_previous_hash", 0, 0, "data".into());
+        let mine = |progress: &AtomicU64| {
+            unmined()
+                .try_mine(
+                    DIFFICULTY,
+                    &MiningConfig::default(),
+                    &AtomicBool::new(false),
+                    progress,
+                )
+                .unwrap()
+        };
+
+        let progress = AtomicU64::new(0);
+        let first = mine(&progress);
+        assert!(progress.load(Ordering::Relaxed) <= first.header().nonce);
+
+        // The nonces already tried are skipped when resuming
+        let second = mine(&AtomicU64::new(first.header().nonce + 1));
+        assert!(second.header().nonce > first.header().nonce);
+        assert!(second.header().is_valid(DIFFICULTY));
+    }
+
+    #[test]
+    fn cancelled_mining() {
+        const DIFFICULTY: usize = 2;
+
+        let block = Block::<String>::try_new(
+            0,
+            "some_previous_hash",
+            String::from("data"),
+            DIFFICULTY,
+            &MiningConfig::default(),
+            &AtomicBool::new(true),
+        );
+
+        assert!(block.is_none());
+    }
+
+    #[test]
+    fn parallel_mining() {
+        const DIFFICULTY: usize = 2;
+
+        let mining = MiningConfig {
+            threads: 4,
+            throttle_percent: 20,
+            auto_mine: None,
+        };
+        let block = Block::<String>::try_new(
+            1,
+            "some_previous_hash",
+            String::from("data"),
+            DIFFICULTY,
+            &mining,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert!(block.header().is_valid(DIFFICULTY));
+        assert_eq!(block.hash, block.header().compute_hash());
+    }
+
+    #[test]
+    fn with_nonce() {
+        const DIFFICULTY: usize = 1;
+
+        let mined = Block::<String>::new(1, "some_previous_hash", String::from("data"), DIFFICULTY);
+        let block = Block::<String>::with_nonce(
+            mined.id,
+            &mined.previous_hash,
+            mined.timestamp,
+            mined.nonce,
+            mined.data.clone(),
+        );
+
+        assert_eq!(block.hash, mined.hash);
+        assert!(block.header().is_valid(DIFFICULTY));
+    }
+
+    #[test]
+    fn tampered_header() {
+        const DIFFICULTY: usize = 1;
+
+        let block = Block::<String>::new(1, "some_previous_hash", String::from("data"), DIFFICULTY);
+        assert!(block.header().is_valid(DIFFICULTY));
+
+        let mut header = block.header();
+        header.previous_hash = String::from("other_previous_hash");
+        assert!(!header.is_valid(DIFFICULTY));
+
+        let mut tampered = block.clone();
+        tampered.data = String::from("other data");
+        assert!(tampered.header().is_valid(DIFFICULTY));
+        assert!(!tampered.has_valid_hash());
     }
 }
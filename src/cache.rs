@@ -0,0 +1,133 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{block::Block, tetherion::BlockData},
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+    },
+};
+
+/// The counters of a cache's lookups
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of blocks found in the cache
+    pub hits: u64,
+
+    /// The number of blocks not found in the cache
+    pub misses: u64,
+
+    /// The number of blocks in the cache
+    pub len: usize,
+}
+
+/// The most recently validated blocks, by hash, whose hashes are known to match their content.
+/// Once full, the least recently used block is evicted.
+#[derive(Debug)]
+pub struct HashCache<T: BlockData> {
+    /// The maximum number of blocks in the cache
+    capacity: usize,
+
+    /// The blocks along with the tick they were last used at, by hash
+    blocks: HashMap<String, (Arc<Block<T>>, u64)>,
+
+    /// The hashes of the blocks, by the tick they were last used at
+    usage: BTreeMap<u64, String>,
+
+    /// The tick of the latest use
+    tick: u64,
+
+    /// The lookup counters
+    stats: CacheStats,
+}
+
+impl<T: BlockData + PartialEq> HashCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            blocks: HashMap::new(),
+            usage: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Checks whether the very same block has been validated already, marking it as used
+    pub fn contains(&mut self, block: &Block<T>) -> bool {
+        self.tick += 1;
+        match self.blocks.get_mut(&block.hash) {
+            Some((cached, used)) if cached.as_ref() == block => {
+                self.usage.remove(used);
+                self.usage.insert(self.tick, block.hash.clone());
+                *used = self.tick;
+                self.stats.hits += 1;
+                true
+            }
+            _ => {
+                self.stats.misses += 1;
+                false
+            }
+        }
+    }
+
+    /// Adds the block, whose hash matches its content, evicting the least recently used block if
+    /// the cache is full
+    pub fn insert(&mut self, block: Arc<Block<T>>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.tick += 1;
+        if let Some((_, used)) = self.blocks.remove(&block.hash) {
+            self.usage.remove(&used);
+        } else if self.blocks.len() == self.capacity {
+            if let Some((_, hash)) = self.usage.pop_first() {
+                self.blocks.remove(&hash);
+            }
+        }
+        self.usage.insert(self.tick, block.hash.clone());
+        self.blocks.insert(block.hash.clone(), (block, self.tick));
+    }
+
+    /// Gets the lookup counters
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            len: self.blocks.len(),
+            ..self.stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let blocks: Vec<_> = (0..3)
+            .map(|id| Arc::new(Block::new(id, "", format!("block {}", id), 1)))
+            .collect();
+        let mut cache = HashCache::new(2);
+
+        for block in &blocks[..2] {
+            assert!(!cache.contains(block));
+            cache.insert(block.clone());
+        }
+        assert!(cache.contains(&blocks[0]));
+        cache.insert(blocks[2].clone());
+        assert!(!cache.contains(&blocks[1]));
+        assert!(cache.contains(&blocks[0]));
+
+        let mut forged = blocks[2].as_ref().clone();
+        forged.id = 7;
+        assert!(!cache.contains(&forged));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 4,
+                len: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,222 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        anchor::{self, ChainAnchor},
+        config::ApiScope,
+        handle::{HandleError, NodeHandle},
+        node::NodeConfig,
+        payload::Payload,
+        tetherion::Tetherion,
+    },
+    std::time::Duration,
+    tokio::{
+        io::{stdin, AsyncBufReadExt, BufReader},
+        time,
+    },
+};
+
+/// An anchor along with the reason it does not match the anchored chain, if any
+pub type AnchorCheck = (ChainAnchor, Result<(), String>);
+
+/// The chains hosted by a single process, each one run by a node of its own, with its own topics
+/// and store. The commands typed in the terminal are routed to the chain they name with a leading
+/// `--chain <id>`, or to the default chain, i.e. the first one started.
+#[derive(Default)]
+pub struct ChainManager {
+    /// The nodes running the chains, along with the chains' IDs, the default chain first
+    chains: Vec<(String, NodeHandle)>,
+}
+
+impl ChainManager {
+    /// Starts a node running the chain with the given ID on top of the given blockchain
+    pub async fn start(
+        &mut self,
+        chain_id: &str,
+        tetherion: Tetherion<Payload>,
+        config: NodeConfig,
+    ) -> Result<(), HandleError> {
+        let config = NodeConfig {
+            chain_id: chain_id.to_owned(),
+            ..config
+        };
+        let node = NodeHandle::start(tetherion, config).await?;
+        log::info!("chain {} listening on {}", chain_id, node.listen_addr());
+        self.chains.push((chain_id.to_owned(), node));
+        Ok(())
+    }
+
+    /// Gets the node running the chain with the given ID
+    pub fn get(&self, chain_id: &str) -> Option<&NodeHandle> {
+        self.chains
+            .iter()
+            .find(|(id, _)| id == chain_id)
+            .map(|(_, node)| node)
+    }
+
+    /// Gets the IDs of the chains, the default chain first
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.chains.iter().map(|(id, _)| id.as_str())
+    }
+
+    /// Gets the node running the chain with the given ID, or the default chain's one
+    fn get_or_default(&self, chain_id: Option<&str>) -> Result<&NodeHandle, HandleError> {
+        match chain_id {
+            Some(id) => self
+                .get(id)
+                .ok_or_else(|| HandleError::UnknownChain(id.to_owned())),
+            None => self
+                .chains
+                .first()
+                .map(|(_, node)| node)
+                .ok_or(HandleError::Stopped),
+        }
+    }
+
+    /// Executes the line, as if it was typed in the terminal, on the chain it names or on the
+    /// default chain
+    pub fn execute(&self, line: &str) -> Result<(), HandleError> {
+        let (chain_id, cmd) = split_chain(line);
+        self.get_or_default(chain_id)?.execute(cmd)
+    }
+
+    /// Periodically anchors the tip of the chain with the given ID into the target chain, in the
+    /// background of the current runtime. A tip is anchored once, and the genesis never is.
+    pub fn anchor(
+        &self,
+        chain_id: &str,
+        target_id: &str,
+        interval: Duration,
+    ) -> Result<(), HandleError> {
+        let anchored = self
+            .get(chain_id)
+            .ok_or_else(|| HandleError::UnknownChain(chain_id.to_owned()))?
+            .with_scope(ApiScope::Read);
+        let target = self
+            .get(target_id)
+            .ok_or_else(|| HandleError::UnknownChain(target_id.to_owned()))?
+            .with_scope(ApiScope::Admin);
+        let chain_id = chain_id.to_owned();
+        tokio::spawn(async move {
+            let mut interval = time::interval(interval);
+            let mut anchored_tip = None;
+            loop {
+                interval.tick().await;
+                let tip = anchored.snapshot().best();
+                if tip.height == 0 || anchored_tip.as_ref() == Some(&tip) {
+                    continue;
+                }
+                let anchor = Payload::ChainAnchor {
+                    chain_id: chain_id.clone(),
+                    height: tip.height,
+                    hash: tip.hash.clone(),
+                };
+                match target.submit_tx(anchor).await {
+                    Ok(_) => anchored_tip = Some(tip),
+                    Err(HandleError::Stopped) => break,
+                    Err(err) => log::warn!("cannot anchor chain {}: {}", chain_id, err),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Gets the anchors of the chain with the given ID stored in the anchoring chain, or in the
+    /// default chain, each one along with the reason it does not match the anchored chain, if any
+    pub fn verify_anchors(
+        &self,
+        anchoring_id: Option<&str>,
+        chain_id: &str,
+    ) -> Result<Vec<AnchorCheck>, HandleError> {
+        let anchored = self
+            .get(chain_id)
+            .ok_or_else(|| HandleError::UnknownChain(chain_id.to_owned()))?
+            .snapshot();
+        let anchoring = self.get_or_default(anchoring_id)?.snapshot();
+        Ok(anchor::chain_anchors(&anchoring.tetherion, chain_id)
+            .into_iter()
+            .map(|anchor| {
+                let verified = anchor::verify_chain_anchor(&anchored.tetherion, &anchor);
+                (anchor, verified)
+            })
+            .collect())
+    }
+
+    /// Executes the commands read from stdin until it gets closed. `ls chains` lists the chains
+    /// along with their tips, and `verify anchors <id>` checks the anchors of the chain with the
+    /// given ID.
+    pub async fn run(&self) {
+        let mut lines = BufReader::new(stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let (chain_id, cmd) = split_chain(&line);
+            if line.trim() == "ls chains" {
+                self.print_chains();
+            } else if let Some(anchored_id) = cmd.trim().strip_prefix("verify anchors ") {
+                self.print_anchors(chain_id, anchored_id.trim());
+            } else if let Err(err) = self.execute(&line) {
+                eprintln!("{}", err);
+            }
+        }
+    }
+
+    /// Prints the anchors of the chain with the given ID stored in the anchoring chain, and whether
+    /// they match the anchored chain
+    fn print_anchors(&self, anchoring_id: Option<&str>, chain_id: &str) {
+        let anchors = match self.verify_anchors(anchoring_id, chain_id) {
+            Ok(anchors) => anchors,
+            Err(err) => return eprintln!("{}", err),
+        };
+        if anchors.is_empty() {
+            println!("no anchors of chain {}", chain_id);
+        }
+        for (anchor, verified) in anchors {
+            let outcome = match verified {
+                Ok(()) => String::from("valid"),
+                Err(reason) => format!("invalid, {}", reason),
+            };
+            println!(
+                "#{} {} anchored in #{} {}: {}",
+                anchor.block.height,
+                anchor.block.hash,
+                anchor.anchored_in.height,
+                anchor.anchored_in.hash,
+                outcome
+            );
+        }
+    }
+
+    /// Prints the ID and the tip of each chain, the default chain marked with `*`
+    fn print_chains(&self) {
+        for (i, (id, node)) in self.chains.iter().enumerate() {
+            let tip = node.snapshot().best();
+            let marker = if i == 0 { '*' } else { ' ' };
+            println!("{} {} #{} {}", marker, id, tip.height, tip.hash);
+        }
+    }
+}
+
+/// Splits the leading `--chain <id>` option off the command, if any
+fn split_chain(line: &str) -> (Option<&str>, &str) {
+    let Some(rest) = line.trim_start().strip_prefix("--chain ") else {
+        return (None, line);
+    };
+    let rest = rest.trim_start();
+    let (id, cmd) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    (Some(id), cmd.trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_option() {
+        assert_eq!(split_chain("ls c"), (None, "ls c"));
+        assert_eq!(split_chain("--chain test1 ls c"), (Some("test1"), "ls c"));
+        assert_eq!(
+            split_chain("  --chain  test1   create b hi"),
+            (Some("test1"), "create b hi")
+        );
+        assert_eq!(split_chain("--chain test1"), (Some("test1"), ""));
+        assert_eq!(split_chain("--chainy ls c"), (None, "--chainy ls c"));
+    }
+}
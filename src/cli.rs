@@ -0,0 +1,186 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    clap::{Parser, Subcommand},
+    libp2p::Multiaddr,
+    std::path::PathBuf,
+    tetherion::{codec::Format, export::ExportFormat, node::Output},
+};
+
+/// Blockchain implementation in Rust
+#[derive(Parser, Debug)]
+#[command(name = "tetherion", version = tetherion::version::LONG_VERSION, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The TOML configuration file
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// The data directory, holding a directory per chain, `~/.tetherion` if no path is given;
+    /// takes precedence over the configured storage path
+    #[arg(long, global = true, value_name = "PATH", num_args = 0..=1)]
+    pub data_dir: Option<Option<PathBuf>>,
+
+    /// Mines a new block every given number of seconds
+    #[arg(long, value_name = "SECONDS", conflicts_with = "light")]
+    pub auto_mine: Option<u64>,
+
+    /// Runs as a light client, storing and validating only block headers
+    #[arg(long)]
+    pub light: bool,
+
+    /// Acknowledges that the blocks preceding the configured checkpoint are trusted without
+    /// being validated
+    #[arg(long, conflicts_with = "light")]
+    pub trust_checkpoint: bool,
+
+    /// Seals a block as soon as there is data to store, without any work, for developing payload
+    /// logic locally
+    #[arg(long, conflicts_with_all = ["light", "trust_checkpoint"])]
+    pub dev: bool,
+
+    /// The format the messages exchanged with the peers are encoded in, shared by the whole
+    /// network
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = Format::Json)]
+    pub wire_format: Format,
+
+    /// The format the results of the commands read from stdin are printed in; a command may
+    /// override it by ending with `--output <FORMAT>`
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", default_value_t = Output::Text)]
+    pub output: Output,
+
+    /// Shows a live dashboard of the node in the terminal, with an input bar for the commands
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Only allows the dashboard's input bar the commands reading the state of the node
+    #[arg(long, requires = "tui")]
+    pub read_only: bool,
+
+    /// Runs the commands of the file, one per line, once the node has started, as if they were
+    /// typed in the terminal
+    #[arg(long, value_name = "PATH")]
+    pub exec: Option<PathBuf>,
+
+    /// Records the messages received from the peers and the commands run to the journal file,
+    /// for `replay`
+    #[arg(long, value_name = "PATH")]
+    pub journal: Option<PathBuf>,
+
+    /// An address to listen on for incoming connections, e.g. `/ip6/::/tcp/4001`; may be given
+    /// several times, replacing the configured addresses
+    #[arg(long, value_name = "MULTIADDR")]
+    pub listen: Vec<Multiaddr>,
+
+    /// An address the peers can reach the node at, advertised to them, e.g. the public address
+    /// of a load balancer; may be given several times, replacing the configured addresses
+    #[arg(long, value_name = "MULTIADDR")]
+    pub external_address: Vec<Multiaddr>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Launches a local testnet of in-process nodes wired together
+    Dev {
+        /// The number of nodes in the testnet
+        #[arg(long, default_value_t = 4)]
+        nodes: usize,
+
+        /// The difficulty of the testnet blockchain
+        #[arg(long, default_value_t = 1)]
+        difficulty: usize,
+
+        /// The interval, in seconds, at which the first node mines a new block
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+
+    /// Simulates a network of virtual nodes mining and gossiping blocks in virtual time, reporting
+    /// the fork rate and how long the nodes take to converge
+    Sim {
+        /// The number of virtual nodes
+        #[arg(long, default_value_t = 100)]
+        nodes: usize,
+
+        /// The number of random peers each node is connected to
+        #[arg(long, default_value_t = 8)]
+        degree: usize,
+
+        /// The number of blocks mined before the simulation stops
+        #[arg(long, default_value_t = 200)]
+        blocks: u64,
+
+        /// The mean interval, in seconds, between two blocks mined by the whole network
+        #[arg(long, value_name = "SECONDS", default_value_t = 10.0)]
+        interval: f64,
+
+        /// The lowest latency, in milliseconds, of a message between two peers
+        #[arg(long, value_name = "MS", default_value_t = 20)]
+        min_latency: u64,
+
+        /// The highest latency, in milliseconds, of a message between two peers
+        #[arg(long, value_name = "MS", default_value_t = 200)]
+        max_latency: u64,
+
+        /// How unevenly the mining power is distributed: the i-th node gets a power of 1 / i^skew,
+        /// 0 spreading it evenly
+        #[arg(long, default_value_t = 0.0)]
+        power_skew: f64,
+
+        /// Splits the network in two at the given time, in seconds of virtual time
+        #[arg(long, value_name = "SECONDS", requires = "partition_for")]
+        partition_at: Option<f64>,
+
+        /// How long, in seconds, the network stays split
+        #[arg(long, value_name = "SECONDS", requires = "partition_at")]
+        partition_for: Option<f64>,
+
+        /// The share of the nodes on the first side of the split
+        #[arg(long, default_value_t = 0.5)]
+        partition_share: f64,
+
+        /// The seed of the pseudorandom numbers, the same seed replaying the same simulation
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+
+    /// Exports the stored blockchain for analysis
+    Export {
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+
+    /// Imports externally produced blocks into the stored blockchain, mapping their fields as
+    /// configured in the [import] section
+    Import {
+        /// The JSON file with the blocks, either an array or one block per line
+        path: PathBuf,
+    },
+
+    /// Feeds the messages and commands recorded in a journal back through a node, in order,
+    /// without connecting to the network, to reproduce what the recording node went through
+    Replay {
+        /// The journal file recorded with `--journal`
+        path: PathBuf,
+    },
+
+    /// Generates a new Ed25519 keypair for a Proof of Authority node and prints its public key
+    Keygen {
+        /// The file to write the keypair to, in HEX format
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportTarget {
+    /// Writes the metadata of each block, one row per block
+    Chain {
+        /// The format of the exported file
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// The file to write the blocks to
+        path: PathBuf,
+    },
+}
@@ -0,0 +1,236 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    std::fmt,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    Encode(String),
+    Decode(String),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::Encode(reason) => write!(f, "Cannot encode the data: {}", reason),
+            CodecError::Decode(reason) => write!(f, "Cannot decode the data: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// A serialization format of the data exchanged with the peers and stored on disk
+pub trait Codec {
+    /// Encodes the value
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError>;
+
+    /// Decodes a value from the bytes
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+/// JSON, readable by humans
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|err| CodecError::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+    }
+}
+
+/// CBOR, the Concise Binary Object Representation
+pub struct Cbor;
+
+impl Codec for Cbor {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes)
+            .map_err(|err| CodecError::Encode(err.to_string()))?;
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        ciborium::de::from_reader(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+    }
+}
+
+/// Bincode, the most compact format, which does not describe the structure of the data
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        bincode::serialize(value).map_err(|err| CodecError::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        bincode::deserialize(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+    }
+}
+
+/// MessagePack, with structs encoded as maps of their named fields
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        rmp_serde::to_vec_named(value).map_err(|err| CodecError::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        rmp_serde::from_slice(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+    }
+}
+
+/// The serialization format, selected at runtime
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Cbor,
+    Bincode,
+    Msgpack,
+}
+
+impl Format {
+    /// Encodes the value in the format
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Format::Json => Json::encode(value),
+            Format::Cbor => Cbor::encode(value),
+            Format::Bincode => Bincode::encode(value),
+            Format::Msgpack => MessagePack::encode(value),
+        }
+    }
+
+    /// Decodes a value in the format from the bytes
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Format::Json => Json::decode(bytes),
+            Format::Cbor => Cbor::decode(bytes),
+            Format::Bincode => Bincode::decode(bytes),
+            Format::Msgpack => MessagePack::decode(bytes),
+        }
+    }
+
+    /// Gets the extension of the files storing a single value in the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Cbor => "cbor",
+            Format::Bincode => "bincode",
+            Format::Msgpack => "msgpack",
+        }
+    }
+
+    /// Gets the extension of the files storing a sequence of records in the format
+    pub fn records_extension(self) -> &'static str {
+        match self {
+            Format::Json => "jsonl",
+            format => format.extension(),
+        }
+    }
+
+    /// Encodes the value as a record appended to a sequence of records: JSON records are
+    /// terminated by a newline, binary ones are prefixed with their length
+    pub fn encode_record<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, CodecError> {
+        self.encode(value).map(|bytes| self.frame(&bytes))
+    }
+
+    /// Frames the encoded value as a record of a sequence of records
+    pub fn frame(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Format::Json => [bytes, b"\n"].concat(),
+            _ => [&(bytes.len() as u32).to_le_bytes()[..], bytes].concat(),
+        }
+    }
+
+    /// Splits a sequence of records into the encoded values. A truncated last record is kept as
+    /// is, so that decoding it fails.
+    pub fn split_records(self, mut data: &[u8]) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        while !data.is_empty() {
+            let (record, rest) = match self {
+                Format::Json => match data.iter().position(|byte| *byte == b'\n') {
+                    Some(end) => (&data[..end], &data[end + 1..]),
+                    None => (data, &[][..]),
+                },
+                _ => match data.get(..4) {
+                    Some(len) => {
+                        let len = u32::from_le_bytes(len.try_into().expect("4 bytes")) as usize;
+                        match data.get(4..4 + len) {
+                            Some(record) => (record, &data[4 + len..]),
+                            None => (&data[4..], &[][..]),
+                        }
+                    }
+                    None => (data, &[][..]),
+                },
+            };
+            records.push(record.to_vec());
+            data = rest;
+        }
+        records
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            block::Block,
+            payload::{Payload, TokenOp, DEFAULT_CHAIN_ID},
+        },
+        libp2p::identity::ed25519,
+    };
+
+    #[test]
+    fn round_trip() {
+        let keys = ed25519::Keypair::generate();
+        let op = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 100,
+            mintable: true,
+        };
+        let blocks = vec![
+            Block::genesis(Payload::Text(String::from("genesis")), 1),
+            Block::new(
+                1,
+                "00",
+                Payload::token_with_fee(DEFAULT_CHAIN_ID, op, 0, 3, &keys),
+                1,
+            ),
+            Block::new(2, "00", Payload::Binary(vec![0, 255]), 1),
+        ];
+
+        for format in [Format::Json, Format::Cbor, Format::Bincode, Format::Msgpack] {
+            let data: Vec<u8> = blocks
+                .iter()
+                .flat_map(|block| format.encode_record(block).unwrap())
+                .collect();
+            let records = format.split_records(&data);
+            let decoded: Vec<Block<Payload>> = records
+                .iter()
+                .map(|record| format.decode(record).unwrap())
+                .collect();
+            assert_eq!(decoded, blocks, "{} round trip", format);
+            assert!(decoded[1].has_valid_hash());
+
+            let truncated = format.split_records(&data[..data.len() - 2]);
+            assert_eq!(truncated.len(), blocks.len());
+            assert!(format
+                .decode::<Block<Payload>>(truncated.last().unwrap())
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,1862 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::Block,
+        codec::Format,
+        consensus::{
+            ConsensusEngine, InstantSeal, ProofOfAuthority, ProofOfStake, ProofOfWork, Staking,
+        },
+        finality::Finality,
+        logfmt,
+        payload::Payload,
+        registry::PayloadRegistry,
+        secret::Secret,
+        state::{State, StateView},
+        store::BlockStore,
+    },
+    libp2p::{identity::ed25519, multiaddr::Protocol, Multiaddr, PeerId},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashSet,
+        fmt, fs, io, iter,
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    },
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+    Snapshot(serde_json::Error),
+    Key(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "Cannot read the configuration file: {}", err),
+            ConfigError::Parse(err) => write!(f, "Cannot parse the configuration file: {}", err),
+            ConfigError::Invalid(reason) => write!(f, "Invalid configuration: {}", reason),
+            ConfigError::Snapshot(err) => {
+                write!(f, "Cannot parse the checkpoint snapshot: {}", err)
+            }
+            ConfigError::Key(reason) => write!(f, "Cannot load the authority key: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The node configuration, read from a TOML file
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The mining settings
+    pub mining: MiningConfig,
+
+    /// The RPC server settings
+    pub rpc: RpcConfig,
+
+    /// The trusted checkpoint to sync from instead of the genesis block
+    pub checkpoint: Option<CheckpointConfig>,
+
+    /// The consensus settings
+    pub consensus: ConsensusConfig,
+
+    /// The finality settings, blocks are never final if not set
+    pub finality: Option<FinalityConfig>,
+
+    /// The mempool settings
+    pub mempool: MempoolConfig,
+
+    /// The on-disk storage settings
+    pub storage: StorageConfig,
+
+    /// The settings of the validation of the blocks received from the peers
+    pub validation: ValidationConfig,
+
+    /// The limits on serving the local blockchain to the peers
+    pub serving: ServingConfig,
+
+    /// The settings of the gossip with the peers
+    pub gossip: GossipConfig,
+
+    /// The settings of the exchange of peer addresses with the peers
+    pub pex: PexConfig,
+
+    /// The settings of dialing again the peers the node lost
+    pub reconnect: ReconnectConfig,
+
+    /// The limits of the traffic with the peers
+    pub bandwidth: BandwidthConfig,
+
+    /// The mapping of the fields of imported blocks
+    pub import: ImportConfig,
+
+    /// The URLs notified of the chain events
+    pub webhooks: Vec<WebhookConfig>,
+
+    /// The logging settings
+    pub logging: LoggingConfig,
+
+    /// The settings of the alerts on the health of the node
+    pub alerts: AlertsConfig,
+
+    /// The number of blocks transactions need on top of them to be confirmed
+    pub confirmations: ConfirmationsConfig,
+
+    /// The peers the node accepts connections with
+    pub peers: PeersConfig,
+
+    /// The addresses the node listens on and advertises
+    pub network: NetworkConfig,
+
+    /// The chain the node takes part in
+    pub chain: ChainConfig,
+
+    /// The other chains hosted by the same process, each one by a node with keys, topics and a
+    /// store of its own
+    pub chains: Vec<ChainConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainConfig {
+    /// The ID of the chain, naming its directory in the data directory
+    pub id: String,
+
+    /// The ID of another hosted chain the tip of this one is periodically anchored into
+    pub anchor_into: Option<String>,
+
+    /// The interval, in seconds, at which the tip is anchored
+    pub anchor_interval: u64,
+}
+
+impl ChainConfig {
+    /// Gets the interval at which the tip is anchored
+    pub fn anchor_interval(&self) -> Duration {
+        Duration::from_secs(self.anchor_interval)
+    }
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        Self {
+            id: String::from("main"),
+            anchor_into: None,
+            anchor_interval: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiningConfig {
+    /// The number of threads searching for a valid nonce in parallel
+    pub threads: usize,
+
+    /// The share of time, in percent, each mining thread spends idle; 0 disables throttling
+    pub throttle_percent: u8,
+
+    /// The interval, in seconds, at which the node mines a new block on its own; `--auto-mine`
+    /// takes precedence over it
+    pub auto_mine: Option<u64>,
+}
+
+impl MiningConfig {
+    /// Gets the interval at which the node mines a new block on its own, if any
+    pub fn auto_mine(&self) -> Option<Duration> {
+        self.auto_mine.map(Duration::from_secs)
+    }
+}
+
+impl Default for MiningConfig {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            throttle_percent: 0,
+            auto_mine: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// The address the JSON-RPC server listens on; the server is disabled if not set
+    pub listen: Option<SocketAddr>,
+
+    /// The bearer tokens granting access to the server along with their scopes; anyone reaching
+    /// the server has full access if none is set
+    pub tokens: Vec<ApiToken>,
+
+    /// The web origins browsers may call the server from, `*` allowing any; browsers are only
+    /// allowed the same origin if none is set
+    pub cors_origins: Vec<String>,
+
+    /// The certificate the server is reached through over HTTPS; plain HTTP is served if not set
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// The PEM file holding the server's certificate chain, the server's certificate first; a
+    /// self-signed certificate is generated at startup if neither file is set
+    pub cert_file: Option<PathBuf>,
+
+    /// The PEM file holding the certificate's private key
+    pub key_file: Option<PathBuf>,
+}
+
+/// What the holder of an API token is allowed to do
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    /// Reading the blockchain, the state and the status of the node
+    #[default]
+    Read,
+
+    /// Submitting data and blocks and maintaining the node as well
+    Admin,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ApiToken {
+    /// The secret sent in the `Authorization: Bearer <token>` header
+    pub token: Secret<String>,
+
+    /// What the token grants access to
+    #[serde(default)]
+    pub scope: ApiScope,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolConfig {
+    /// The time, in seconds, data may wait to be stored in a block before being dropped
+    pub ttl: u64,
+
+    /// The most payloads waiting to be stored, beyond which the cheapest ones are evicted
+    pub max_entries: usize,
+
+    /// The most token operations waiting for the preceding ones of their accounts, beyond which
+    /// the oldest ones are evicted
+    pub max_orphans: usize,
+
+    /// The most token operations of an account waiting, orphans included
+    pub max_per_account: usize,
+}
+
+impl MempoolConfig {
+    /// Gets the time data may wait to be stored in a block before being dropped
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl)
+    }
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            ttl: 600,
+            max_entries: 5_000,
+            max_orphans: 500,
+            max_per_account: 64,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// The data directory, the blockchain being stored in the `blocks` directory of the chain's
+    /// directory; the blockchain is kept in memory only if neither it nor `--data-dir` is set
+    pub path: Option<PathBuf>,
+
+    /// The period, in seconds, at which the stored blockchain gets compacted
+    pub compaction_interval: u64,
+
+    /// The number of blocks a block needs to be buried under to get archived on compaction;
+    /// blocks are not archived if not set
+    pub archive_depth: Option<u64>,
+
+    /// The number of blocks per archived segment
+    pub segment_size: u64,
+
+    /// The format the blocks are stored in; changing it requires a new path
+    pub format: Format,
+}
+
+impl StorageConfig {
+    /// Gets the period at which the stored blockchain gets compacted
+    pub fn compaction_interval(&self) -> Duration {
+        Duration::from_secs(self.compaction_interval)
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            compaction_interval: 3600,
+            archive_depth: None,
+            segment_size: 1000,
+            format: Format::Json,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    /// The number of threads validating the blocks received from the peers
+    pub workers: usize,
+
+    /// The number of received blocks and blockchains that may wait to be validated; the ones
+    /// received while the queue is full are dropped
+    pub queue_size: usize,
+
+    /// The number of recently validated blocks kept, so that their hashes are not computed
+    /// again when they are received again
+    pub cache_size: usize,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            queue_size: 64,
+            cache_size: 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServingConfig {
+    /// The number of responses to chain requests that may be served at the same time; the
+    /// requests arriving while as many responses are queued are rejected
+    pub max_responses: usize,
+
+    /// The number of responses to chain requests that may be served to a single peer at the same
+    /// time
+    pub max_responses_per_peer: usize,
+
+    /// The number of chain and headers requests a peer may make per minute, the ones above it
+    /// being rejected
+    pub requests_per_minute: u32,
+}
+
+impl Default for ServingConfig {
+    fn default() -> Self {
+        Self {
+            max_responses: 16,
+            max_responses_per_peer: 1,
+            requests_per_minute: 6,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GossipConfig {
+    /// Whether the messages received from the peers are passed on to the other peers; a node not
+    /// relaying publishes its own messages only
+    pub relay: bool,
+
+    /// The time, in milliseconds, the received messages wait before being relayed
+    pub relay_delay: u64,
+
+    /// The upper bound, in milliseconds, of the random time added to the relay delay of each
+    /// message
+    pub relay_jitter: u64,
+
+    /// The number of connected peers messages are published to; every peer if not set
+    pub fanout: Option<usize>,
+
+    /// The time, in seconds, past which the messages are dropped rather than handled or relayed
+    pub ttl: u64,
+}
+
+impl GossipConfig {
+    /// Gets the time the received messages wait before being relayed
+    pub fn relay_delay(&self) -> Duration {
+        Duration::from_millis(self.relay_delay)
+    }
+
+    /// Gets the time past which the messages are dropped
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl)
+    }
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            relay: true,
+            relay_delay: 0,
+            relay_jitter: 0,
+            fanout: None,
+            ttl: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PexConfig {
+    /// Whether the node exchanges the addresses of the peers it knows with its peers
+    pub enabled: bool,
+
+    /// The time, in seconds, between two exchanges, each with the next connected peer
+    pub interval: u64,
+
+    /// The number of peer records sent in an exchange, and accepted from a peer at once
+    pub sample_size: usize,
+
+    /// The number of addresses a peer record may carry
+    pub max_addresses: usize,
+
+    /// The number of peers the address book holds
+    pub max_peers: usize,
+
+    /// The age, in seconds, past which peer records are neither shared nor accepted
+    pub max_record_age: u64,
+
+    /// The number of connections below which the node dials the peers it learns about
+    pub min_connections: usize,
+}
+
+impl PexConfig {
+    /// Gets the time between two exchanges
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+}
+
+impl Default for PexConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 60,
+            sample_size: 16,
+            max_addresses: 8,
+            max_peers: 1024,
+            max_record_age: 3600,
+            min_connections: 8,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    /// Whether the node dials again the peers it got disconnected from and the bootstrap peers
+    /// it cannot reach
+    pub enabled: bool,
+
+    /// The time, in seconds, before the first retry
+    pub initial_delay: u64,
+
+    /// The time, in seconds, the retries are spaced by at most
+    pub max_delay: u64,
+
+    /// The factor the time before the next retry grows by after each retry
+    pub multiplier: u32,
+
+    /// The share, in percent, of the time before each retry randomly taken off it, so that the
+    /// nodes losing the same peer do not dial it in step
+    pub jitter_percent: u8,
+
+    /// The number of retries after which the node gives up on a peer; never if not set
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_delay: 1,
+            max_delay: 300,
+            multiplier: 2,
+            jitter_percent: 20,
+            max_attempts: None,
+        }
+    }
+}
+
+/// The number of connected peers a node on a metered connection publishes messages to at most
+pub const METERED_FANOUT: usize = 2;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthConfig {
+    /// The rate, in KiB per second, the node sends data to all the peers at at most; unlimited
+    /// if not set
+    pub upload: Option<u32>,
+
+    /// The rate, in KiB per second, the node receives data from all the peers at at most;
+    /// unlimited if not set
+    pub download: Option<u32>,
+
+    /// Whether the node runs on a metered connection, e.g. a mobile one, serving no full
+    /// blockchain to the peers and gossiping with few of them, relaying none of their messages
+    pub metered: bool,
+}
+
+impl BandwidthConfig {
+    /// Gets the upload rate cap, in bytes per second
+    pub fn upload_rate(&self) -> Option<u64> {
+        self.upload.map(|kib| u64::from(kib) * 1024)
+    }
+
+    /// Gets the download rate cap, in bytes per second
+    pub fn download_rate(&self) -> Option<u64> {
+        self.download.map(|kib| u64::from(kib) * 1024)
+    }
+
+    /// Gets the gossip settings in effect given the configured ones, reduced on a metered
+    /// connection
+    pub fn gossip(&self, gossip: GossipConfig) -> GossipConfig {
+        if !self.metered {
+            return gossip;
+        }
+        let fanout = gossip
+            .fanout
+            .map_or(METERED_FANOUT, |fanout| fanout.min(METERED_FANOUT));
+        GossipConfig {
+            relay: false,
+            fanout: Some(fanout),
+            ..gossip
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// The number of errors of a kind caused by a peer logged per interval; the following ones
+    /// are only counted
+    pub error_burst: u32,
+
+    /// The length, in seconds, of the intervals errors are throttled over; the number of errors
+    /// not logged is reported at the end of each interval
+    pub error_interval: u64,
+
+    /// The most verbose level logged, overriding `RUST_LOG`; `RUST_LOG` decides if not set
+    pub level: Option<LogLevel>,
+
+    /// The number of characters of a payload shown in the log, the rest being cut off; 0 shows
+    /// payloads in full
+    pub payload_preview: usize,
+
+    /// How the JSON printed by the commands is laid out, e.g. the blocks of `ls c --full`
+    pub json: JsonStyle,
+}
+
+/// The layout of JSON documents
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonStyle {
+    /// Indented over several lines, for humans
+    #[default]
+    Pretty,
+
+    /// On a single line, for scripts and log collectors
+    Compact,
+}
+
+/// The verbosity of the log
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Gets the length of the intervals the errors caused by a peer are throttled over
+    pub fn error_interval(&self) -> Duration {
+        Duration::from_secs(self.error_interval)
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            error_burst: 5,
+            error_interval: 60,
+            level: None,
+            payload_preview: logfmt::DEFAULT_PAYLOAD_PREVIEW,
+            json: JsonStyle::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    /// The time, in seconds, without any block added to the local blockchain after which an
+    /// alert is raised; 0 disables the alert
+    pub stale_after: u64,
+
+    /// The number of blocks the local blockchain may lag behind the best blockchain advertised
+    /// by the peers before an alert is raised
+    pub max_tip_lag: u64,
+}
+
+impl AlertsConfig {
+    /// Gets the time without any block added after which an alert is raised
+    pub fn stale_after(&self) -> Duration {
+        Duration::from_secs(self.stale_after)
+    }
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            stale_after: 600,
+            max_tip_lag: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfirmationsConfig {
+    /// The number of blocks on top of the one storing a transaction for the transaction to be
+    /// confirmed, i.e. unlikely to be reverted by a reorg
+    pub depth: u64,
+}
+
+impl Default for ConfirmationsConfig {
+    fn default() -> Self {
+        Self { depth: 6 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeersConfig {
+    /// The IDs of the only peers the node keeps connections with; any peer is allowed if none
+    /// is set
+    pub allow: Vec<String>,
+
+    /// The IDs of the peers the node never keeps connections with, even if allowed
+    pub deny: Vec<String>,
+
+    /// The IDs of the peers whose addresses are never shared with the other peers, and which
+    /// messages are published to whatever the fanout, e.g. the validator behind a sentry node
+    pub private: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// The addresses the node listens on for incoming connections, at the same time; addresses
+    /// of the preferred IP versions, with ports chosen by the system, if none is set
+    pub listen: Vec<Multiaddr>,
+
+    /// The addresses the peers can reach the node at, advertised to them on top of the ones it
+    /// listens on, e.g. when behind a load balancer or a NAT
+    pub external_addresses: Vec<Multiaddr>,
+
+    /// The IP version the addresses of the peers are dialed in first
+    pub prefer_ip: IpPreference,
+
+    /// The addresses of the peers the node dials at startup
+    pub bootstrap: Vec<Multiaddr>,
+
+    /// The address of the SOCKS5 proxy, e.g. Tor, the connections to the peers are dialed
+    /// through; peers are not discovered on the local network then
+    pub proxy: Option<SocketAddr>,
+
+    /// The directions the node opens connections in
+    pub mode: ConnectionMode,
+
+    /// Whether the node discovers peers on its own, on the local network and through the peer
+    /// exchange; a node behind sentry nodes only connects to the configured ones
+    pub discovery: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            listen: Vec::new(),
+            external_addresses: Vec::new(),
+            prefer_ip: IpPreference::default(),
+            bootstrap: Vec::new(),
+            proxy: None,
+            mode: ConnectionMode::default(),
+            discovery: true,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Gets the addresses the node listens on: the configured ones, or every interface of the
+    /// preferred IP versions, with ports chosen by the system, unless the node is outbound-only
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        if !self.mode.accepts_inbound() {
+            return Vec::new();
+        }
+        if !self.listen.is_empty() {
+            return self.listen.clone();
+        }
+        let any = |addr: &str| addr.parse().expect("can get a local socket");
+        match self.prefer_ip {
+            IpPreference::Auto => vec![any("/ip4/0.0.0.0/tcp/0"), any("/ip6/::/tcp/0")],
+            IpPreference::V4 => vec![any("/ip4/0.0.0.0/tcp/0")],
+            IpPreference::V6 => vec![any("/ip6/::/tcp/0")],
+        }
+    }
+}
+
+/// The IP version the addresses of the peers are dialed in first
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpPreference {
+    /// The addresses are dialed in the order they were learned in, whatever their version; the
+    /// node listens on both IPv4 and IPv6
+    #[default]
+    Auto,
+
+    /// The IPv4 addresses are dialed first; the node listens on IPv4 only
+    V4,
+
+    /// The IPv6 addresses are dialed first; the node listens on IPv6 only
+    V6,
+}
+
+impl IpPreference {
+    /// Orders the addresses in the order they are dialed in, keeping the order of the ones of
+    /// the same version
+    pub fn order(&self, addrs: &mut [Multiaddr]) {
+        let is_v6 = |addr: &Multiaddr| matches!(addr.iter().next(), Some(Protocol::Ip6(_)));
+        match self {
+            Self::Auto => {}
+            Self::V4 => addrs.sort_by_key(is_v6),
+            Self::V6 => addrs.sort_by_key(|addr| !is_v6(addr)),
+        }
+    }
+}
+
+/// The directions a node opens connections in, reported to the peers through identify
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConnectionMode {
+    /// The node dials its peers and accepts their connections
+    #[default]
+    Both,
+
+    /// The node dials its peers but never accepts their connections, listening on no address,
+    /// e.g. a client behind a strict firewall
+    OutboundOnly,
+
+    /// The node accepts the connections of its peers but never dials them, e.g. a sentinel
+    /// listening to the network
+    InboundOnly,
+}
+
+impl ConnectionMode {
+    /// Checks whether the node accepts the connections of its peers
+    pub fn accepts_inbound(&self) -> bool {
+        *self != Self::OutboundOnly
+    }
+
+    /// Checks whether the node dials its peers
+    pub fn dials_out(&self) -> bool {
+        *self != Self::InboundOnly
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Both => "both",
+            Self::OutboundOnly => "outbound-only",
+            Self::InboundOnly => "inbound-only",
+        }
+    }
+
+    /// Gets the agent version reported through identify by a node in this mode: the given one,
+    /// followed by the mode unless the node opens connections in both directions
+    pub fn agent_version(&self, agent: &str) -> String {
+        match self {
+            Self::Both => agent.to_owned(),
+            mode => format!("{} {}", agent, mode.name()),
+        }
+    }
+
+    /// Gets the mode of the peer reporting the agent version through identify
+    pub fn of_agent(agent: &str) -> Self {
+        [Self::OutboundOnly, Self::InboundOnly]
+            .into_iter()
+            .find(|mode| agent.ends_with(&format!(" {}", mode.name())))
+            .unwrap_or(Self::Both)
+    }
+}
+
+/// Checks whether the node can listen on or be dialed at the address, i.e. whether the address
+/// is a TCP one over an IP address, the only transport the node supports
+pub fn is_supported_address(addr: &Multiaddr) -> bool {
+    let mut protocols = addr.iter();
+    matches!(protocols.next(), Some(Protocol::Ip4(_) | Protocol::Ip6(_)))
+        && matches!(protocols.next(), Some(Protocol::Tcp(_)))
+}
+
+/// Checks whether the address is a TCP one over a domain name, which only a proxy resolves
+fn is_domain_address(addr: &Multiaddr) -> bool {
+    let mut protocols = addr.iter();
+    matches!(
+        protocols.next(),
+        Some(Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_))
+    ) && matches!(protocols.next(), Some(Protocol::Tcp(_)))
+}
+
+/// The kinds of chain events a webhook can be notified of
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A block was added to the local blockchain
+    NewBlock,
+
+    /// The local blockchain was replaced by one not containing its latest block
+    Reorg,
+
+    /// A block storing a token operation moving at least the configured amount was added
+    LargeTransaction,
+
+    /// An alert on the health of the node was raised
+    Alert,
+
+    /// The local blockchain changed too much to be reported block by block, posted to the
+    /// webhooks notified of new blocks or reorgs as well
+    Reset,
+}
+
+/// A URL receiving signed JSON POST requests on chain events
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// The HTTP or HTTPS URL the events are posted to
+    pub url: String,
+
+    /// The key the HMAC-SHA256 signature of each request is computed with
+    pub secret: Secret<String>,
+
+    /// The kinds of events posted, all of them by default
+    #[serde(default = "WebhookConfig::all_events")]
+    pub events: Vec<WebhookEvent>,
+
+    /// The amount of tokens from which a token operation is reported as a large transaction
+    #[serde(default = "WebhookConfig::default_large_transaction")]
+    pub large_transaction: u64,
+
+    /// The number of times a failed request is retried, waiting twice as long each time
+    #[serde(default = "WebhookConfig::default_retries")]
+    pub retries: u32,
+}
+
+impl WebhookConfig {
+    fn all_events() -> Vec<WebhookEvent> {
+        vec![
+            WebhookEvent::NewBlock,
+            WebhookEvent::Reorg,
+            WebhookEvent::LargeTransaction,
+            WebhookEvent::Alert,
+            WebhookEvent::Reset,
+        ]
+    }
+
+    fn default_large_transaction() -> u64 {
+        1_000_000
+    }
+
+    fn default_retries() -> u32 {
+        5
+    }
+}
+
+/// How the data of an imported block is turned into a payload
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataEncoding {
+    /// A Tetherion payload, as stored by Tetherion itself
+    #[default]
+    Payload,
+
+    /// Text stored as is, any other JSON value is stored as its JSON text
+    Text,
+
+    /// Binary data in HEX format, e.g. a raw transaction dump
+    Hex,
+}
+
+/// The fields of externally produced blocks, given as JSON pointers into each block, mapped to the
+/// fields of Tetherion blocks when importing them
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImportConfig {
+    /// The ID, or height, of the block
+    pub id: String,
+
+    /// The hash of the previous block
+    pub previous_hash: String,
+
+    /// The timestamp of the block, in seconds
+    pub timestamp: String,
+
+    /// The nonce of the block
+    pub nonce: String,
+
+    /// The data stored in the block
+    pub data: String,
+
+    /// How the data is turned into a payload
+    pub data_encoding: DataEncoding,
+
+    /// The hash of the block, checked against the computed one; not checked if empty
+    pub hash: String,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            id: String::from("/id"),
+            previous_hash: String::from("/previous_hash"),
+            timestamp: String::from("/timestamp"),
+            nonce: String::from("/nonce"),
+            data: String::from("/data"),
+            data_encoding: DataEncoding::Payload,
+            hash: String::from("/hash"),
+        }
+    }
+}
+
+/// A block trusted to be part of the blockchain. The blocks preceding it are neither downloaded
+/// nor validated, which is only safe as long as the checkpoint comes from a trusted source.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointConfig {
+    /// The ID of the block at the checkpoint
+    pub height: u64,
+
+    /// The hash of the block at the checkpoint
+    pub hash: String,
+
+    /// The JSON file containing the block at the checkpoint, which the node starts from since
+    /// the blocks preceding it are never downloaded
+    pub snapshot: PathBuf,
+
+    /// The JSON file containing the state as of the block at the checkpoint, as served by
+    /// `GET /state`, which the blocks following it get applied to. The state starts empty if not
+    /// set.
+    #[serde(default)]
+    pub state: Option<PathBuf>,
+}
+
+impl CheckpointConfig {
+    /// Loads the block at the checkpoint from the snapshot
+    pub fn load_snapshot(&self) -> Result<Block<Payload>, ConfigError> {
+        let content = fs::read_to_string(&self.snapshot).map_err(ConfigError::Io)?;
+        let block: Block<Payload> =
+            serde_json::from_str(&content).map_err(ConfigError::Snapshot)?;
+        if block.id != self.height || block.hash != self.hash {
+            return Err(ConfigError::Invalid(String::from(
+                "checkpoint snapshot does not match checkpoint.height and checkpoint.hash",
+            )));
+        }
+        Ok(block)
+    }
+
+    /// Loads the state as of the block at the checkpoint from its snapshot, if any, validating
+    /// the payloads applied to it with the given registry
+    pub fn load_state(&self, registry: Arc<PayloadRegistry>) -> Result<State, ConfigError> {
+        let path = match &self.state {
+            Some(path) => path,
+            None => return Ok(State::new(registry)),
+        };
+        let content = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let view: StateView = serde_json::from_str(&content).map_err(ConfigError::Snapshot)?;
+        if view.height != self.height {
+            return Err(ConfigError::Invalid(String::from(
+                "checkpoint state is not as of checkpoint.height",
+            )));
+        }
+        Ok(State::from_view(registry, view))
+    }
+}
+
+/// The engine deciding which node is allowed to produce the next block
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// Blocks are mined with Proof of Work
+    #[default]
+    Pow,
+
+    /// Blocks are sealed by the authorities taking turns
+    Poa,
+
+    /// Blocks are sealed by proposers chosen by their stakes
+    Pos,
+
+    /// Blocks are sealed instantly, for local development only
+    Instant,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsensusConfig {
+    /// The consensus engine
+    pub engine: Engine,
+
+    /// The authorities' Ed25519 public keys, in HEX format, in the order they seal blocks; for
+    /// the pos engine, the proposers as long as nobody has staked anything
+    pub authorities: Vec<String>,
+
+    /// The duration, in seconds, of the time slot each authority gets to seal a block in
+    pub slot_duration: u64,
+
+    /// The token whose balances are the stakes for the pos engine
+    pub stake_token: String,
+
+    /// The number of blocks the stakes are frozen for with the pos engine
+    pub epoch_length: u64,
+
+    /// The number of epochs a block producer caught signing two blocks at the same height is
+    /// left out of the schedule for; with the poa engine, an epoch is a round of turns
+    pub jail_epochs: u64,
+
+    /// The file containing the node's Ed25519 keypair, in HEX format, which it signs its blocks
+    /// and payloads with; a new keypair is generated if not set
+    pub key_file: Option<PathBuf>,
+}
+
+impl Default for ConsensusConfig {
+    fn default() -> Self {
+        Self {
+            engine: Engine::default(),
+            authorities: Vec::new(),
+            slot_duration: 5,
+            stake_token: String::new(),
+            epoch_length: 10,
+            jail_epochs: 2,
+            key_file: None,
+        }
+    }
+}
+
+impl ConsensusConfig {
+    /// Creates the consensus engine the blockchain is validated with, mining blocks with the given
+    /// difficulty in case of Proof of Work
+    pub fn engine(&self, difficulty: usize) -> Arc<dyn ConsensusEngine> {
+        match self.engine {
+            Engine::Pow => Arc::new(ProofOfWork::new(difficulty)),
+            Engine::Poa => Arc::new(
+                ProofOfAuthority::new(self.authorities.clone(), self.slot_duration)
+                    .with_exclusion(self.jail_epochs),
+            ),
+            Engine::Pos => Arc::new(
+                ProofOfStake::new(
+                    Staking {
+                        token: self.stake_token.clone(),
+                        epoch_length: self.epoch_length,
+                    },
+                    self.slot_duration,
+                    self.authorities.clone(),
+                )
+                .with_exclusion(self.jail_epochs),
+            ),
+            Engine::Instant => Arc::new(InstantSeal),
+        }
+    }
+
+    /// Loads the node's keypair from the key file, if any
+    pub fn load_keys(&self) -> Result<Option<Secret<ed25519::Keypair>>, ConfigError> {
+        let path = match &self.key_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        // The decoding errors would quote the offending characters of the key
+        let content = Secret::new(fs::read_to_string(path).map_err(ConfigError::Io)?);
+        let mut bytes = hex::decode(content.expose().trim())
+            .map_err(|_| ConfigError::Key(String::from("the key is not in HEX format")))?;
+        ed25519::Keypair::decode(&mut bytes)
+            .map(|keys| Some(Secret::new(keys)))
+            .map_err(|err| ConfigError::Key(err.to_string()))
+    }
+}
+
+/// The voters whose quorum marks blocks as final, so that they can no longer be reverted
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FinalityConfig {
+    /// The voters' Ed25519 public keys, in HEX format
+    pub voters: Vec<String>,
+
+    /// The number of voters needed to finalize a block, more than two thirds of them by default
+    pub quorum: Option<usize>,
+}
+
+impl FinalityConfig {
+    /// Gets the number of voters needed to finalize a block
+    pub fn quorum(&self) -> usize {
+        self.quorum.unwrap_or(self.voters.len() * 2 / 3 + 1)
+    }
+
+    /// Creates the tally of the voters' votes
+    pub fn finality(&self) -> Finality {
+        Finality::new(self.voters.clone(), self.quorum())
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the TOML file at the given path
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Config::parse(&content)
+    }
+
+    /// Parses the configuration from TOML content
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(content).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that the configuration values are within their allowed ranges
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.mining.threads == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "mining.threads should be at least 1",
+            )));
+        }
+        if self.mining.throttle_percent > 99 {
+            return Err(ConfigError::Invalid(String::from(
+                "mining.throttle_percent should be between 0 and 99",
+            )));
+        }
+        if self.mining.auto_mine == Some(0) {
+            return Err(ConfigError::Invalid(String::from(
+                "mining.auto_mine should be at least 1",
+            )));
+        }
+        let mempool = [
+            ("ttl", self.mempool.ttl as usize),
+            ("max_entries", self.mempool.max_entries),
+            ("max_orphans", self.mempool.max_orphans),
+            ("max_per_account", self.mempool.max_per_account),
+        ];
+        if let Some((name, _)) = mempool.iter().find(|(_, value)| *value == 0) {
+            return Err(ConfigError::Invalid(format!(
+                "mempool.{} should be at least 1",
+                name
+            )));
+        }
+        if self.storage.segment_size == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "storage.segment_size should be at least 1",
+            )));
+        }
+        if self.storage.compaction_interval == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "storage.compaction_interval should be at least 1",
+            )));
+        }
+        if self.validation.workers == 0 || self.validation.queue_size == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "validation.workers and validation.queue_size should be at least 1",
+            )));
+        }
+        let serving = [
+            ("max_responses", self.serving.max_responses),
+            (
+                "max_responses_per_peer",
+                self.serving.max_responses_per_peer,
+            ),
+            (
+                "requests_per_minute",
+                self.serving.requests_per_minute as usize,
+            ),
+        ];
+        if let Some((name, _)) = serving.iter().find(|(_, value)| *value == 0) {
+            return Err(ConfigError::Invalid(format!(
+                "serving.{} should be at least 1",
+                name
+            )));
+        }
+        let gossip = [
+            ("fanout", self.gossip.fanout.unwrap_or(1)),
+            ("ttl", self.gossip.ttl as usize),
+        ];
+        if let Some((name, _)) = gossip.iter().find(|(_, value)| *value == 0) {
+            return Err(ConfigError::Invalid(format!(
+                "gossip.{} should be at least 1",
+                name
+            )));
+        }
+        let pex = [
+            ("interval", self.pex.interval as usize),
+            ("sample_size", self.pex.sample_size),
+            ("max_addresses", self.pex.max_addresses),
+            ("max_peers", self.pex.max_peers),
+            ("max_record_age", self.pex.max_record_age as usize),
+        ];
+        if let Some((name, _)) = pex.iter().find(|(_, value)| *value == 0) {
+            return Err(ConfigError::Invalid(format!(
+                "pex.{} should be at least 1",
+                name
+            )));
+        }
+        let reconnect = &self.reconnect;
+        if reconnect.initial_delay == 0 || reconnect.multiplier == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "reconnect.initial_delay and reconnect.multiplier should be at least 1",
+            )));
+        }
+        if reconnect.max_delay < reconnect.initial_delay {
+            return Err(ConfigError::Invalid(String::from(
+                "reconnect.max_delay should be at least reconnect.initial_delay",
+            )));
+        }
+        if reconnect.jitter_percent > 100 {
+            return Err(ConfigError::Invalid(String::from(
+                "reconnect.jitter_percent should be between 0 and 100",
+            )));
+        }
+        if reconnect.max_attempts == Some(0) {
+            return Err(ConfigError::Invalid(String::from(
+                "reconnect.max_attempts should be at least 1",
+            )));
+        }
+        let bandwidth = [
+            ("upload", self.bandwidth.upload),
+            ("download", self.bandwidth.download),
+        ];
+        if let Some((name, _)) = bandwidth.iter().find(|(_, value)| *value == Some(0)) {
+            return Err(ConfigError::Invalid(format!(
+                "bandwidth.{} should be at least 1",
+                name
+            )));
+        }
+        if let Some(tls) = &self.rpc.tls {
+            if tls.cert_file.is_some() != tls.key_file.is_some() {
+                return Err(ConfigError::Invalid(String::from(
+                    "rpc.tls.cert_file and rpc.tls.key_file should be set together",
+                )));
+            }
+        }
+        let is_valid_id = |id: &str| {
+            !id.is_empty()
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                && !BlockStore::owns(id)
+        };
+        let mut ids = HashSet::new();
+        for id in iter::once(&self.chain)
+            .chain(&self.chains)
+            .map(|chain| &chain.id)
+        {
+            if !is_valid_id(id) {
+                return Err(ConfigError::Invalid(format!(
+                    "invalid chain ID {}, expected letters, digits, - and _ only",
+                    id
+                )));
+            }
+            if !ids.insert(id) {
+                return Err(ConfigError::Invalid(format!(
+                    "chain ID {} is used by several chains",
+                    id
+                )));
+            }
+        }
+        for chain in iter::once(&self.chain).chain(&self.chains) {
+            if let Some(target) = &chain.anchor_into {
+                if target == &chain.id || !ids.contains(target) {
+                    return Err(ConfigError::Invalid(format!(
+                        "chain {} should be anchored into another hosted chain, not {}",
+                        chain.id, target
+                    )));
+                }
+            }
+            if chain.anchor_interval == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "anchor_interval of chain {} should be at least 1",
+                    chain.id
+                )));
+            }
+        }
+        if self.consensus.slot_duration == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "consensus.slot_duration should be at least 1",
+            )));
+        }
+        if self.consensus.epoch_length == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "consensus.epoch_length should be at least 1",
+            )));
+        }
+        let import = &self.import;
+        if [
+            &import.id,
+            &import.previous_hash,
+            &import.timestamp,
+            &import.nonce,
+            &import.data,
+        ]
+        .into_iter()
+        .any(|pointer| !pointer.starts_with('/'))
+            || !(import.hash.is_empty() || import.hash.starts_with('/'))
+        {
+            return Err(ConfigError::Invalid(String::from(
+                "the import fields should be JSON pointers, e.g. \"/header/height\"",
+            )));
+        }
+        let is_http = |url: &str| {
+            url.parse::<hyper::Uri>()
+                .is_ok_and(|url| matches!(url.scheme_str(), Some("http" | "https")))
+        };
+        if let Some(webhook) = self.webhooks.iter().find(|webhook| !is_http(&webhook.url)) {
+            return Err(ConfigError::Invalid(format!(
+                "invalid webhook URL {}, expected an HTTP or HTTPS URL",
+                webhook.url
+            )));
+        }
+        if self
+            .webhooks
+            .iter()
+            .any(|webhook| webhook.secret.expose().is_empty())
+        {
+            return Err(ConfigError::Invalid(String::from(
+                "webhooks.secret should not be empty",
+            )));
+        }
+        let engine = self.consensus.engine;
+        if matches!(engine, Engine::Poa | Engine::Pos) && self.consensus.authorities.is_empty() {
+            return Err(ConfigError::Invalid(String::from(
+                "consensus.authorities should not be empty for the poa and pos engines",
+            )));
+        }
+        if engine == Engine::Pos && self.consensus.stake_token.is_empty() {
+            return Err(ConfigError::Invalid(String::from(
+                "consensus.stake_token should be set for the pos engine",
+            )));
+        }
+        if let Some(finality) = &self.finality {
+            if finality.quorum() == 0 || finality.quorum() > finality.voters.len() {
+                return Err(ConfigError::Invalid(String::from(
+                    "finality.quorum should be between 1 and the number of voters",
+                )));
+            }
+        }
+        let voters = self.finality.iter().flat_map(|finality| &finality.voters);
+        if let Some(key) = self.consensus.authorities.iter().chain(voters).find(|key| {
+            !hex::decode(key).is_ok_and(|bytes| ed25519::PublicKey::decode(&bytes).is_ok())
+        }) {
+            return Err(ConfigError::Invalid(format!(
+                "invalid public key {} in consensus.authorities or finality.voters",
+                key
+            )));
+        }
+        let mut addrs = self
+            .network
+            .listen
+            .iter()
+            .chain(&self.network.external_addresses);
+        if let Some(addr) = addrs.find(|addr| !is_supported_address(addr)) {
+            return Err(ConfigError::Invalid(format!(
+                "invalid address {} in network.listen or network.external_addresses",
+                addr
+            )));
+        }
+        let proxied = self.network.proxy.is_some();
+        if let Some(addr) = self
+            .network
+            .bootstrap
+            .iter()
+            .find(|addr| !(is_supported_address(addr) || proxied && is_domain_address(addr)))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "invalid address {} in network.bootstrap, domain names need network.proxy",
+                addr
+            )));
+        }
+        let network = &self.network;
+        let advertised = !network.listen.is_empty() || !network.external_addresses.is_empty();
+        if !network.mode.accepts_inbound() && advertised {
+            return Err(ConfigError::Invalid(String::from(
+                "an outbound-only node should have no network.listen or network.external_addresses",
+            )));
+        }
+        if !network.mode.dials_out() && (!network.bootstrap.is_empty() || proxied) {
+            return Err(ConfigError::Invalid(String::from(
+                "an inbound-only node should have no network.bootstrap or network.proxy",
+            )));
+        }
+        let mut peers = self
+            .peers
+            .allow
+            .iter()
+            .chain(&self.peers.deny)
+            .chain(&self.peers.private);
+        if let Some(peer) = peers.find(|peer| peer.parse::<PeerId>().is_err()) {
+            return Err(ConfigError::Invalid(format!(
+                "invalid peer ID {} in peers.allow, peers.deny or peers.private",
+                peer
+            )));
+        }
+        Ok(())
+    }
+
+    /// Gets the names of the settings differing in the other configuration. The reloadable
+    /// settings are named on their own, the other ones by their section.
+    pub fn changes(&self, other: &Config) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        let mut changed = |name, differs| {
+            if differs {
+                changes.push(name);
+            }
+        };
+        // Unsetting the level hands the log back to `RUST_LOG`, which is only read at startup
+        let level = other.logging.level.is_some() && self.logging.level != other.logging.level;
+        changed("logging.level", level);
+        changed("peers.allow", self.peers.allow != other.peers.allow);
+        changed("peers.deny", self.peers.deny != other.peers.deny);
+        changed("peers.private", self.peers.private != other.peers.private);
+        changed(
+            "mining.auto_mine",
+            self.mining.auto_mine != other.mining.auto_mine,
+        );
+        changed("rpc.tokens", self.rpc.tokens != other.rpc.tokens);
+
+        let reloaded = self.with_reloadable(other);
+        changed("mining", reloaded.mining != other.mining);
+        changed("rpc", reloaded.rpc != other.rpc);
+        changed("checkpoint", reloaded.checkpoint != other.checkpoint);
+        changed("consensus", reloaded.consensus != other.consensus);
+        changed("finality", reloaded.finality != other.finality);
+        changed("mempool", reloaded.mempool != other.mempool);
+        changed("storage", reloaded.storage != other.storage);
+        changed("validation", reloaded.validation != other.validation);
+        changed("serving", reloaded.serving != other.serving);
+        changed("gossip", reloaded.gossip != other.gossip);
+        changed("pex", reloaded.pex != other.pex);
+        changed("reconnect", reloaded.reconnect != other.reconnect);
+        changed("bandwidth", reloaded.bandwidth != other.bandwidth);
+        changed("import", reloaded.import != other.import);
+        changed("webhooks", reloaded.webhooks != other.webhooks);
+        changed("logging", reloaded.logging != other.logging);
+        changed("alerts", reloaded.alerts != other.alerts);
+        changed(
+            "confirmations",
+            reloaded.confirmations != other.confirmations,
+        );
+        changed("network", reloaded.network != other.network);
+        changed("chain", reloaded.chain != other.chain);
+        changed("chains", reloaded.chains != other.chains);
+        changes
+    }
+
+    /// Gets a copy of the configuration whose reloadable settings are the other configuration's
+    pub fn with_reloadable(&self, other: &Config) -> Config {
+        let mut config = self.clone();
+        config.logging.level = other.logging.level.or(self.logging.level);
+        config.peers = other.peers.clone();
+        config.mining.auto_mine = other.mining.auto_mine;
+        config.rpc.tokens = other.rpc.tokens.clone();
+        config
+    }
+}
+
+/// The settings a running node applies when its configuration file is reloaded, on SIGHUP or
+/// `config reload`
+pub const RELOADABLE: &[&str] = &[
+    "logging.level",
+    "peers.allow",
+    "peers.deny",
+    "peers.private",
+    "mining.auto_mine",
+    "rpc.tokens",
+];
+
+/// The outcome of reloading the configuration file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reload {
+    /// The reloadable settings that changed, which the node now runs with
+    pub applied: Vec<&'static str>,
+
+    /// The settings that changed but are only taken into account once the node restarts
+    pub restart_required: Vec<&'static str>,
+}
+
+/// A configuration file along with the configuration the node runs with
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    /// The path of the TOML file
+    pub path: PathBuf,
+
+    /// The configuration loaded at startup, updated with the reloaded settings since
+    pub config: Config,
+}
+
+impl ConfigFile {
+    /// Loads the configuration from the TOML file at the given path
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        Ok(Self {
+            path: path.to_owned(),
+            config: Config::load(path)?,
+        })
+    }
+
+    /// Loads the file again, taking the reloadable settings from it. The running configuration is
+    /// left untouched if the file is no longer valid.
+    pub fn reload(&mut self) -> Result<Reload, ConfigError> {
+        let loaded = Config::load(&self.path)?;
+        let (applied, restart_required): (Vec<_>, Vec<_>) = self
+            .config
+            .changes(&loaded)
+            .into_iter()
+            .partition(|name| RELOADABLE.contains(name));
+        self.config = self.config.with_reloadable(&loaded);
+        Ok(Reload {
+            applied,
+            restart_required,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config = Config::parse(
+            r#"
+            [mining]
+            threads = 4
+            throttle_percent = 50
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.mining,
+            MiningConfig {
+                threads: 4,
+                throttle_percent: 50,
+                auto_mine: None
+            }
+        );
+        assert_eq!(Config::parse("").unwrap().mining, MiningConfig::default());
+
+        let config = Config::parse("[mempool]\nttl = 30").unwrap();
+        assert_eq!(config.mempool.ttl(), Duration::from_secs(30));
+
+        let config = Config::parse("[serving]\nrequests_per_minute = 2").unwrap();
+        assert_eq!(
+            config.serving,
+            ServingConfig {
+                requests_per_minute: 2,
+                ..ServingConfig::default()
+            }
+        );
+
+        let config = Config::parse("[gossip]\nrelay = false\nfanout = 3").unwrap();
+        assert!(!config.gossip.relay);
+        assert_eq!(config.gossip.fanout, Some(3));
+        assert_eq!(config.gossip.ttl(), Duration::from_secs(60));
+
+        let config = Config::parse("[pex]\ninterval = 30\nmax_peers = 100").unwrap();
+        assert_eq!(config.pex.interval(), Duration::from_secs(30));
+        assert_eq!(config.pex.max_peers, 100);
+        assert!(config.pex.enabled);
+
+        let config = Config::parse("[reconnect]\nmax_delay = 60\nmax_attempts = 10").unwrap();
+        assert_eq!(
+            config.reconnect,
+            ReconnectConfig {
+                max_delay: 60,
+                max_attempts: Some(10),
+                ..ReconnectConfig::default()
+            }
+        );
+
+        let config = Config::parse("[bandwidth]\nupload = 64\nmetered = true").unwrap();
+        assert_eq!(config.bandwidth.upload_rate(), Some(65_536));
+        assert_eq!(config.bandwidth.download_rate(), None);
+        let gossip = config.bandwidth.gossip(GossipConfig::default());
+        assert!(!gossip.relay);
+        assert_eq!(gossip.fanout, Some(METERED_FANOUT));
+
+        let config = Config::parse(
+            r#"
+            [network]
+            listen = ["/ip4/0.0.0.0/tcp/4001", "/ip6/::/tcp/4001"]
+            external_addresses = ["/ip4/203.0.113.7/tcp/4001"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.network.listen_addrs().len(), 2);
+        assert_eq!(
+            config.network.external_addresses,
+            vec!["/ip4/203.0.113.7/tcp/4001".parse::<Multiaddr>().unwrap()]
+        );
+
+        let config = Config::parse(
+            r#"
+            [network]
+            proxy = "127.0.0.1:9050"
+            bootstrap = ["/dns/abcdefghijklmnop.onion/tcp/4001"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.network.proxy,
+            Some("127.0.0.1:9050".parse().unwrap())
+        );
+
+        let config = Config::parse("[network]\nmode = \"inbound-only\"").unwrap();
+        assert_eq!(config.network.mode, ConnectionMode::InboundOnly);
+        assert!(!config.network.mode.dials_out());
+        assert!(config.network.discovery);
+        let config = Config::parse("[network]\ndiscovery = false").unwrap();
+        assert!(!config.network.discovery);
+        let agent = ConnectionMode::OutboundOnly.agent_version("tetherion/0.1.0 (abc, release)");
+        assert_eq!(agent, "tetherion/0.1.0 (abc, release) outbound-only");
+        assert_eq!(
+            ConnectionMode::of_agent(&agent),
+            ConnectionMode::OutboundOnly
+        );
+        assert_eq!(
+            ConnectionMode::of_agent("rust-libp2p"),
+            ConnectionMode::Both
+        );
+
+        let config = Config::parse("[network]\nprefer_ip = \"v6\"").unwrap();
+        assert_eq!(
+            config.network.listen_addrs(),
+            vec!["/ip6/::/tcp/0".parse::<Multiaddr>().unwrap()]
+        );
+        let mut addrs: Vec<Multiaddr> = [
+            "/ip4/10.0.0.1/tcp/1",
+            "/ip6/::1/tcp/2",
+            "/ip4/10.0.0.2/tcp/3",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        config.network.prefer_ip.order(&mut addrs);
+        assert_eq!(addrs[0], "/ip6/::1/tcp/2".parse::<Multiaddr>().unwrap());
+        IpPreference::V4.order(&mut addrs);
+        assert_eq!(
+            addrs[1],
+            "/ip4/10.0.0.2/tcp/3".parse::<Multiaddr>().unwrap()
+        );
+
+        let config = Config::parse("[rpc]\nlisten = \"127.0.0.1:8545\"").unwrap();
+        assert_eq!(config.rpc.listen, Some("127.0.0.1:8545".parse().unwrap()));
+        assert!(config.rpc.tokens.is_empty());
+
+        let config = Config::parse(
+            r#"
+            [rpc]
+            cors_origins = ["*"]
+            tokens = [{ token = "secret", scope = "admin" }, { token = "public" }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rpc.cors_origins, vec![String::from("*")]);
+        assert_eq!(
+            config.rpc.tokens[1],
+            ApiToken {
+                token: Secret::new(String::from("public")),
+                scope: ApiScope::Read
+            }
+        );
+
+        let config = Config::parse(
+            "[checkpoint]\nheight = 10\nhash = \"00ab\"\nsnapshot = \"checkpoint.json\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.checkpoint,
+            Some(CheckpointConfig {
+                height: 10,
+                hash: String::from("00ab"),
+                snapshot: PathBuf::from("checkpoint.json"),
+                state: None,
+            })
+        );
+        // The blocks preceding the checkpoint are never downloaded, so it cannot do without the
+        // checkpoint block
+        assert!(Config::parse("[checkpoint]\nheight = 10\nhash = \"00ab\"").is_err());
+
+        let key = hex::encode(ed25519::Keypair::generate().public().encode());
+        let config = Config::parse(&format!(
+            "[consensus]\nengine = \"poa\"\nauthorities = [\"{}\"]\nslot_duration = 2",
+            key
+        ))
+        .unwrap();
+        assert_eq!(config.consensus.engine(1).name(), "poa");
+        assert_eq!(config.consensus.authorities, vec![key]);
+
+        let config = Config::parse("[[chains]]\nid = \"test1\"\nanchor_into = \"main\"").unwrap();
+        assert_eq!(config.chains[0].anchor_into, Some(String::from("main")));
+        assert_eq!(config.chains[0].anchor_interval(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(matches!(
+            Config::parse("[mining]\nthreads = 0"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[mining]\nthrottle_percent = 100"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[rpc.tls]\ncert_file = \"cert.pem\""),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[serving]\nmax_responses_per_peer = 0"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[gossip]\nfanout = 0"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[gossip]\nttl = 0"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[pex]\nsample_size = 0"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[reconnect]\ninitial_delay = 10\nmax_delay = 5"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[bandwidth]\ndownload = 0"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[network]\nlisten = [\"/ip4/0.0.0.0/udp/4001/quic\"]"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[network]\nbootstrap = [\"/dns/example.com/tcp/4001\"]"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse(
+                "[network]\nmode = \"outbound-only\"\nlisten = [\"/ip4/0.0.0.0/tcp/4001\"]"
+            ),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[import]\nid = \"height\""),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[consensus]\nengine = \"poa\""),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse(&format!(
+                "[consensus]\nengine = \"pos\"\nauthorities = [\"{}\"]",
+                hex::encode(ed25519::Keypair::generate().public().encode())
+            )),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[consensus]\nengine = \"poa\"\nauthorities = [\"00ff\"]"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[finality]\nvoters = []"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[mining]\nauto_mine = 0"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[peers]\ndeny = [\"12D3\"]"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[peers]\nprivate = [\"12D3\"]"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[chain]\nid = \"../main\""),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[[chains]]\nid = \"test1\"\n[[chains]]\nid = \"test1\""),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[[chains]]\nid = \"test1\"\nanchor_into = \"test2\""),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::parse("[mining]\nworkers = 2"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn reloadable_changes() {
+        let running = Config::parse("[mining]\nthreads = 2\n[logging]\nlevel = \"info\"").unwrap();
+        let peer = PeerId::random().to_string();
+        let reloaded = Config::parse(&format!(
+            "[mining]\nthreads = 4\nauto_mine = 5\n[logging]\nlevel = \"debug\"\n[peers]\ndeny = [\"{}\"]",
+            peer
+        ))
+        .unwrap();
+
+        assert_eq!(
+            running.changes(&reloaded),
+            vec!["logging.level", "peers.deny", "mining.auto_mine", "mining"]
+        );
+        let applied = running.with_reloadable(&reloaded);
+        assert_eq!(applied.logging.level, Some(LogLevel::Debug));
+        assert_eq!(applied.mining.auto_mine(), Some(Duration::from_secs(5)));
+        assert_eq!(applied.mining.threads, 2);
+        assert_eq!(applied.peers.deny, vec![peer]);
+        assert_eq!(applied.changes(&reloaded), vec!["mining"]);
+
+        let unset = running.with_reloadable(&Config::default());
+        assert_eq!(unset.logging.level, Some(LogLevel::Info));
+    }
+}
@@ -0,0 +1,590 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::{self, BlockHeader},
+        payload,
+        tetherion::InvalidBlockError,
+    },
+    std::{
+        collections::BTreeMap,
+        fmt,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// The rules deciding which blocks are allowed to extend the blockchain and how the node produces
+/// new ones
+pub trait ConsensusEngine: fmt::Debug + Send + Sync {
+    /// Gets the name of the engine
+    fn name(&self) -> &'static str;
+
+    /// Checks if the block with the given header is allowed to follow up the previous block, given
+    /// the validators of the block's epoch. The header's linkage and hash are already known to be
+    /// valid.
+    fn verify(
+        &self,
+        previous: &BlockHeader,
+        header: &BlockHeader,
+        validators: &Validators,
+    ) -> Result<(), InvalidBlockError>;
+
+    /// Whether the node produces blocks by mining them instead of sealing them
+    fn is_mined(&self) -> bool {
+        false
+    }
+
+    /// Checks if the account with the given address is allowed to seal the block following up the
+    /// previous block at the given timestamp
+    fn can_seal(
+        &self,
+        _previous: &BlockHeader,
+        _address: &str,
+        _timestamp: i64,
+        _validators: &Validators,
+    ) -> bool {
+        false
+    }
+
+    /// Gets the staking settings, in case block producers are selected by their stakes
+    fn staking(&self) -> Option<&Staking> {
+        None
+    }
+
+    /// Gets the address of the account allowed to seal the block with the given header, given the
+    /// validators of the block's epoch, in case blocks are sealed by a known account
+    fn producer(&self, _header: &BlockHeader, _validators: &Validators) -> Option<String> {
+        None
+    }
+
+    /// Gets the Proof of Work rules, in case blocks are mined
+    fn proof_of_work(&self) -> Option<&ProofOfWork> {
+        None
+    }
+
+    /// Gets the number of blocks following the one storing the evidence of a producer's
+    /// equivocation during which the producer is left out of the schedule
+    fn exclusion(&self) -> u64 {
+        0
+    }
+}
+
+/// The state the stakes of the accounts are read from
+pub trait Stakes {
+    /// Gets the addresses of the accounts owning the token along with the owned amounts, ordered
+    /// by address
+    fn stakes(&self, token: &str) -> Vec<(String, u64)>;
+
+    /// Gets the addresses of the block producers caught equivocating along with the heights of
+    /// the blocks storing the evidence against them, one entry per offense, ordered by address
+    /// and height
+    fn offenses(&self) -> Vec<(String, u64)> {
+        Vec::new()
+    }
+}
+
+impl Stakes for () {
+    fn stakes(&self, _token: &str) -> Vec<(String, u64)> {
+        Vec::new()
+    }
+}
+
+/// The accounts allowed to produce blocks during an epoch, weighted by their stakes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    /// The hash of the block ending the previous epoch, seeding the pseudo-random selection
+    seed: String,
+
+    /// The addresses of the accounts and their stakes, ordered by address
+    stakes: Vec<(String, u64)>,
+
+    /// The addresses of the block producers left out of the schedule for equivocating
+    excluded: Vec<String>,
+}
+
+impl Validators {
+    pub fn new(seed: String, stakes: Vec<(String, u64)>) -> Self {
+        Self {
+            seed,
+            stakes,
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Leaves the block producers with the given addresses out of the schedule
+    pub fn exclude(mut self, excluded: Vec<String>) -> Self {
+        self.excluded = excluded;
+        self
+    }
+
+    /// Checks if the block producer with the given address is left out of the schedule
+    pub fn is_excluded(&self, address: &str) -> bool {
+        self.excluded.iter().any(|excluded| excluded == address)
+    }
+}
+
+/// The token staked by block producers and the number of blocks the stakes are frozen for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Staking {
+    /// The token whose balances are the stakes
+    pub token: String,
+
+    /// The number of blocks in an epoch
+    pub epoch_length: u64,
+}
+
+/// Blocks are mined by finding a nonce satisfying the blockchain's difficulty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofOfWork {
+    /// The number of leading zero bytes the blocks' hashes must have
+    difficulty: usize,
+
+    /// The difficulties overriding the blockchain's one starting at the given heights, set at
+    /// runtime on dev networks
+    schedule: BTreeMap<u64, usize>,
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: usize) -> Self {
+        Self {
+            difficulty,
+            schedule: BTreeMap::new(),
+        }
+    }
+
+    /// Overrides the difficulty of the blocks starting at the given height
+    pub fn with_override(mut self, height: u64, difficulty: usize) -> Self {
+        self.schedule.split_off(&height);
+        self.schedule.insert(height, difficulty);
+        self
+    }
+
+    /// Gets the difficulty the block at the given height must satisfy
+    pub fn difficulty_at(&self, height: u64) -> usize {
+        self.schedule
+            .range(..=height)
+            .next_back()
+            .map_or(self.difficulty, |(_, difficulty)| *difficulty)
+    }
+
+    /// Gets the heights the difficulty changes at along with the new difficulties, starting with
+    /// the blockchain's difficulty at height 0
+    pub fn schedule(&self) -> Vec<(u64, usize)> {
+        let mut schedule = vec![(0, self.difficulty)];
+        schedule.extend(
+            self.schedule
+                .iter()
+                .map(|(height, difficulty)| (*height, *difficulty)),
+        );
+        schedule
+    }
+}
+
+impl ConsensusEngine for ProofOfWork {
+    fn name(&self) -> &'static str {
+        "pow"
+    }
+
+    fn verify(
+        &self,
+        _previous: &BlockHeader,
+        header: &BlockHeader,
+        _validators: &Validators,
+    ) -> Result<(), InvalidBlockError> {
+        let difficulty = self.difficulty_at(header.id);
+        if !header.is_valid(difficulty) {
+            return Err(InvalidBlockError::InvalidDifficulty {
+                id: header.id,
+                difficulty,
+                hash: header.hash.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn is_mined(&self) -> bool {
+        true
+    }
+
+    fn proof_of_work(&self) -> Option<&ProofOfWork> {
+        Some(self)
+    }
+}
+
+/// Blocks are sealed by a configured set of authorities taking turns, one per time slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofOfAuthority {
+    /// The authorities' Ed25519 public keys, in HEX format, in the order they take turns
+    keys: Vec<String>,
+
+    /// The duration of a time slot, in seconds
+    slot_duration: u64,
+
+    /// The number of rounds of turns an authority caught equivocating is left out for
+    jail_epochs: u64,
+}
+
+impl ProofOfAuthority {
+    pub fn new(keys: Vec<String>, slot_duration: u64) -> Self {
+        debug_assert!(!keys.is_empty() && slot_duration > 0);
+        Self {
+            keys,
+            slot_duration,
+            jail_epochs: 0,
+        }
+    }
+
+    /// Leaves the authorities caught equivocating out for the given number of rounds of turns
+    pub fn with_exclusion(mut self, jail_epochs: u64) -> Self {
+        self.jail_epochs = jail_epochs;
+        self
+    }
+
+    /// Gets the time slot the timestamp falls into
+    pub fn slot(&self, timestamp: i64) -> u64 {
+        slot(timestamp, self.slot_duration)
+    }
+
+    /// Gets the public key of the authority allowed to seal a block in the time slot
+    pub fn author(&self, slot: u64) -> &str {
+        &self.keys[(slot % self.keys.len() as u64) as usize]
+    }
+
+    /// Gets the public key of the authority sealing a block in the time slot, the turn passing to
+    /// the next authority in case the scheduled one is left out
+    fn scheduled<'a>(&'a self, slot: u64, validators: &Validators) -> Option<&'a str> {
+        (0..self.keys.len() as u64)
+            .map(|turn| self.author(slot + turn))
+            .find(|author| !validators.is_excluded(author))
+    }
+}
+
+impl ConsensusEngine for ProofOfAuthority {
+    fn name(&self) -> &'static str {
+        "poa"
+    }
+
+    /// Checks if the header is sealed by the authority whose turn it was
+    fn verify(
+        &self,
+        previous: &BlockHeader,
+        header: &BlockHeader,
+        validators: &Validators,
+    ) -> Result<(), InvalidBlockError> {
+        let author = self.scheduled(self.slot(header.timestamp), validators);
+        verify_seal(previous, header, self.slot_duration, author)
+    }
+
+    fn can_seal(
+        &self,
+        previous: &BlockHeader,
+        address: &str,
+        timestamp: i64,
+        validators: &Validators,
+    ) -> bool {
+        let slot = self.slot(timestamp);
+        slot > self.slot(previous.timestamp) && self.scheduled(slot, validators) == Some(address)
+    }
+
+    fn producer(&self, header: &BlockHeader, validators: &Validators) -> Option<String> {
+        self.scheduled(self.slot(header.timestamp), validators)
+            .map(str::to_owned)
+    }
+
+    /// An authority is left out for the given number of rounds of turns, one block each
+    fn exclusion(&self) -> u64 {
+        self.jail_epochs * self.keys.len() as u64
+    }
+}
+
+/// Blocks are sealed by proposers chosen pseudo-randomly for each time slot, weighted by the
+/// stakes frozen at the beginning of the epoch. Proposers caught equivocating are left out for a
+/// number of epochs, but their stakes are not slashed, so it is a prototype only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofOfStake {
+    /// The staked token and the epoch length
+    staking: Staking,
+
+    /// The duration of a time slot, in seconds
+    slot_duration: u64,
+
+    /// The public keys, in HEX format, of the equally weighted proposers chosen as long as
+    /// nobody has staked anything, e.g. during the first epoch
+    genesis: Vec<String>,
+
+    /// The number of epochs a proposer caught equivocating is left out for
+    jail_epochs: u64,
+}
+
+impl ProofOfStake {
+    pub fn new(staking: Staking, slot_duration: u64, genesis: Vec<String>) -> Self {
+        debug_assert!(staking.epoch_length > 0 && slot_duration > 0);
+        Self {
+            staking,
+            slot_duration,
+            genesis,
+            jail_epochs: 0,
+        }
+    }
+
+    /// Leaves the proposers caught equivocating out for the given number of epochs
+    pub fn with_exclusion(mut self, jail_epochs: u64) -> Self {
+        self.jail_epochs = jail_epochs;
+        self
+    }
+
+    /// Gets the address of the account chosen to propose a block in the time slot
+    pub fn proposer<'a>(&'a self, slot: u64, validators: &'a Validators) -> Option<&'a str> {
+        let stakes: Vec<(&str, u128)> = if validators.stakes.iter().any(|(_, stake)| *stake > 0) {
+            validators
+                .stakes
+                .iter()
+                .map(|(address, stake)| (address.as_str(), *stake as u128))
+                .collect()
+        } else {
+            self.genesis.iter().map(|key| (key.as_str(), 1)).collect()
+        };
+        let stakes: Vec<(&str, u128)> = stakes
+            .into_iter()
+            .filter(|(address, _)| !validators.is_excluded(address))
+            .collect();
+
+        let total: u128 = stakes.iter().map(|(_, stake)| stake).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let digest = block::hash(format!("{}{}", validators.seed, slot).as_bytes());
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        let mut pick = u128::from_be_bytes(bytes) % total;
+        for (address, stake) in stakes {
+            if pick < stake {
+                return Some(address);
+            }
+            pick -= stake;
+        }
+        None
+    }
+}
+
+impl ConsensusEngine for ProofOfStake {
+    fn name(&self) -> &'static str {
+        "pos"
+    }
+
+    /// Checks if the header is sealed by the proposer chosen for its time slot
+    fn verify(
+        &self,
+        previous: &BlockHeader,
+        header: &BlockHeader,
+        validators: &Validators,
+    ) -> Result<(), InvalidBlockError> {
+        let proposer = self.proposer(slot(header.timestamp, self.slot_duration), validators);
+        verify_seal(previous, header, self.slot_duration, proposer)
+    }
+
+    fn can_seal(
+        &self,
+        previous: &BlockHeader,
+        address: &str,
+        timestamp: i64,
+        validators: &Validators,
+    ) -> bool {
+        let current = slot(timestamp, self.slot_duration);
+        current > slot(previous.timestamp, self.slot_duration)
+            && self.proposer(current, validators) == Some(address)
+    }
+
+    fn staking(&self) -> Option<&Staking> {
+        Some(&self.staking)
+    }
+
+    fn producer(&self, header: &BlockHeader, validators: &Validators) -> Option<String> {
+        let slot = slot(header.timestamp, self.slot_duration);
+        self.proposer(slot, validators).map(str::to_owned)
+    }
+
+    fn exclusion(&self) -> u64 {
+        self.jail_epochs * self.staking.epoch_length
+    }
+}
+
+/// Blocks are sealed as soon as there is data to store, without any work or signature checks.
+/// Only meant for local development.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstantSeal;
+
+impl ConsensusEngine for InstantSeal {
+    fn name(&self) -> &'static str {
+        "instant"
+    }
+
+    fn verify(
+        &self,
+        _previous: &BlockHeader,
+        _header: &BlockHeader,
+        _validators: &Validators,
+    ) -> Result<(), InvalidBlockError> {
+        Ok(())
+    }
+
+    fn can_seal(
+        &self,
+        _previous: &BlockHeader,
+        _address: &str,
+        _timestamp: i64,
+        _validators: &Validators,
+    ) -> bool {
+        true
+    }
+}
+
+/// Gets the time slot the timestamp falls into
+fn slot(timestamp: i64, slot_duration: u64) -> u64 {
+    timestamp.max(0) as u64 / slot_duration
+}
+
+/// Checks if the header is signed by the given author, in a later time slot than the previous
+/// header and not in a future one
+fn verify_seal(
+    previous: &BlockHeader,
+    header: &BlockHeader,
+    slot_duration: u64,
+    author: Option<&str>,
+) -> Result<(), InvalidBlockError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64);
+    let header_slot = slot(header.timestamp, slot_duration);
+    let signed = match (author, &header.signature) {
+        (Some(author), Some(signature)) => {
+            payload::verify(author, header.hash.as_bytes(), signature)
+        }
+        _ => false,
+    };
+
+    if !signed
+        || header_slot <= slot(previous.timestamp, slot_duration)
+        || header_slot > slot(now, slot_duration)
+    {
+        return Err(InvalidBlockError::InvalidSeal {
+            id: header.id,
+            timestamp: header.timestamp,
+            previous_timestamp: previous.timestamp,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{block::Block, payload::Payload},
+        libp2p::identity::ed25519,
+    };
+
+    #[test]
+    fn round_robin_seal() {
+        const SLOT_DURATION: u64 = 5;
+
+        let first = ed25519::Keypair::generate();
+        let second = ed25519::Keypair::generate();
+        let engine = ProofOfAuthority::new(
+            vec![
+                hex::encode(first.public().encode()),
+                hex::encode(second.public().encode()),
+            ],
+            SLOT_DURATION,
+        );
+        let validators = Validators::default();
+
+        let data = || Payload::Text(String::from("data"));
+        let genesis = Block::with_nonce(0, "", 0, 0, data()).header();
+        let now = chrono::Utc::now().timestamp();
+        let slot = engine.slot(now);
+        let (author, other) = if engine.author(slot) == hex::encode(first.public().encode()) {
+            (&first, &second)
+        } else {
+            (&second, &first)
+        };
+        let address = hex::encode(author.public().encode());
+        assert!(engine.can_seal(&genesis, &address, now, &validators));
+
+        let block = Block::sealed(1, &genesis.hash, now, data(), author).header();
+        assert!(engine.verify(&genesis, &block, &validators).is_ok());
+        assert!(!engine.can_seal(&block, &address, now, &validators));
+
+        let block = Block::sealed(1, &genesis.hash, now, data(), other).header();
+        assert!(engine.verify(&genesis, &block, &validators).is_err());
+
+        let previous = Block::sealed(1, &genesis.hash, now, data(), author).header();
+        let block = Block::sealed(2, &previous.hash, now, data(), author).header();
+        assert!(engine.verify(&previous, &block, &validators).is_err());
+
+        let future = now + 2 * SLOT_DURATION as i64;
+        let block = Block::sealed(1, &genesis.hash, future, data(), author).header();
+        assert!(engine.verify(&genesis, &block, &validators).is_err());
+
+        // The turn of an authority left out passes to the next one
+        let excluded = Validators::default().exclude(vec![address.clone()]);
+        let block = Block::sealed(1, &genesis.hash, now, data(), other).header();
+        assert!(engine.verify(&genesis, &block, &excluded).is_ok());
+        assert!(!engine.can_seal(&genesis, &address, now, &excluded));
+        assert_eq!(engine.with_exclusion(3).exclusion(), 6);
+    }
+
+    #[test]
+    fn proof_of_work() {
+        const DIFFICULTY: usize = 1;
+
+        let engine = ProofOfWork::new(DIFFICULTY);
+        let validators = Validators::default();
+        let genesis = Block::genesis(Payload::Text(String::from("genesis")), DIFFICULTY).header();
+        let block = Block::new(1, &genesis.hash, Payload::Text(String::new()), DIFFICULTY).header();
+        assert!(engine.is_mined());
+        assert!(engine.verify(&genesis, &block, &validators).is_ok());
+        assert!(ProofOfWork::new(32)
+            .verify(&genesis, &block, &validators)
+            .is_err());
+
+        let tuned = engine.with_override(1, 32).with_override(5, 0);
+        assert_eq!(tuned.schedule(), vec![(0, 1), (1, 32), (5, 0)]);
+        assert_eq!(tuned.difficulty_at(0), DIFFICULTY);
+        assert_eq!(tuned.difficulty_at(4), 32);
+        assert!(tuned.verify(&genesis, &block, &validators).is_err());
+        assert_eq!(tuned.with_override(1, 2).schedule(), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn stake_weighted_proposer() {
+        let engine = ProofOfStake::new(
+            Staking {
+                token: String::from("TTH"),
+                epoch_length: 10,
+            },
+            5,
+            vec![String::from("genesis")],
+        );
+        assert_eq!(engine.proposer(7, &Validators::default()), Some("genesis"));
+
+        let validators = Validators::new(
+            String::from("seed"),
+            vec![(String::from("alice"), 3), (String::from("bob"), 0)],
+        );
+        assert!((0..100).all(|slot| engine.proposer(slot, &validators) == Some("alice")));
+
+        let validators = Validators::new(
+            String::from("seed"),
+            vec![(String::from("alice"), 1), (String::from("bob"), 1)],
+        );
+        let proposers: Vec<_> = (0..100)
+            .map(|slot| engine.proposer(slot, &validators))
+            .collect();
+        assert!(proposers.contains(&Some("alice")) && proposers.contains(&Some("bob")));
+
+        let validators = validators.exclude(vec![String::from("alice")]);
+        assert!((0..100).all(|slot| engine.proposer(slot, &validators) == Some("bob")));
+        assert_eq!(engine.with_exclusion(2).exclusion(), 20);
+    }
+}
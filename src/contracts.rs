@@ -0,0 +1,435 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::block,
+    once_cell::sync::OnceCell,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::Mutex,
+    },
+    wasmtime::{
+        Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits,
+        StoreLimitsBuilder,
+    },
+};
+
+/// The most fuel a contract operation can be given, which bounds the time it takes every node to
+/// execute it
+pub const MAX_FUEL: u64 = 10_000_000;
+
+/// The amount of fuel a contract operation weighs as much as a byte of data for
+pub const FUEL_PER_WEIGHT: u64 = 100;
+
+/// The largest size, in bytes, of the code of a contract
+pub const MAX_CODE_SIZE: usize = 64 * 1024;
+
+/// The largest size, in bytes, of the linear memory of a running contract
+pub const MAX_MEMORY_SIZE: usize = 1024 * 1024;
+
+/// The largest size, in bytes, of the input of a contract operation, as well as of a storage key
+/// or value
+pub const MAX_DATA_SIZE: usize = 4 * 1024;
+
+/// The fuel a contract consumes to write to its storage, on top of the fuel per byte written
+const STORAGE_WRITE_FUEL: u64 = 1_000;
+
+/// The fuel a contract consumes per byte written to its storage
+const STORAGE_BYTE_FUEL: u64 = 100;
+
+/// The function exported by a contract that gets called once the contract is deployed, if any
+pub const INIT_FUNCTION: &str = "init";
+
+/// The module the functions contracts can import are provided by
+const HOST_MODULE: &str = "env";
+
+/// The most compiled contracts kept in memory, so that calling a contract does not compile its
+/// code again, which no fuel is consumed for
+const MAX_CACHED_MODULES: usize = 256;
+
+/// An operation on contracts performed by an account
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractOp {
+    /// Deploys the WebAssembly module, calling its `init` function, if exported. The contract is
+    /// identified by the account's address and the operation's nonce, as `<address>:<nonce>`.
+    Deploy {
+        /// The WebAssembly module, in HEX format
+        #[serde(with = "hex")]
+        code: Vec<u8>,
+
+        /// The input the `init` function reads, in HEX format
+        #[serde(default, with = "hex")]
+        input: Vec<u8>,
+    },
+
+    /// Calls a function exported by the contract
+    Call {
+        contract: String,
+        function: String,
+
+        /// The input the function reads, in HEX format
+        #[serde(default, with = "hex")]
+        input: Vec<u8>,
+    },
+}
+
+/// A deployed contract
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Contract {
+    /// The Ed25519 public key, in HEX format, of the account that deployed the contract
+    pub owner: String,
+
+    /// The WebAssembly module, in HEX format
+    #[serde(with = "hex")]
+    pub code: Vec<u8>,
+
+    /// The entries the contract stored, their keys and values being in HEX format
+    pub storage: BTreeMap<String, String>,
+}
+
+/// The most recently executed contracts, compiled, by the hash of their code. Once full, the least
+/// recently used module is evicted.
+struct ModuleCache {
+    /// The maximum number of modules in the cache
+    capacity: usize,
+
+    /// The modules along with the tick they were last used at, by the hash of their code
+    modules: HashMap<String, (Module, u64)>,
+
+    /// The hashes of the modules' code, by the tick they were last used at
+    usage: BTreeMap<u64, String>,
+
+    /// The tick of the latest use
+    tick: u64,
+}
+
+impl ModuleCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            modules: HashMap::new(),
+            usage: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Gets the module compiled out of the code with the given hash, if cached, marking it as used
+    fn get(&mut self, hash: &str) -> Option<Module> {
+        self.tick += 1;
+        let (module, used) = self.modules.get_mut(hash)?;
+        self.usage.remove(used);
+        *used = self.tick;
+        self.usage.insert(self.tick, hash.to_owned());
+        Some(module.clone())
+    }
+
+    /// Caches the module compiled out of the code with the given hash, evicting the least recently
+    /// used module if the cache is full
+    fn insert(&mut self, hash: String, module: Module) {
+        self.tick += 1;
+        if let Some((_, used)) = self.modules.insert(hash.clone(), (module, self.tick)) {
+            self.usage.remove(&used);
+        }
+        self.usage.insert(self.tick, hash);
+        while self.modules.len() > self.capacity {
+            match self.usage.pop_first() {
+                Some((_, evicted)) => self.modules.remove(&evicted),
+                None => break,
+            };
+        }
+    }
+}
+
+/// What a running contract can access through the functions it imports
+struct Host {
+    /// The input of the contract operation
+    input: Vec<u8>,
+
+    /// The contract's storage, updated as the contract writes to it
+    storage: BTreeMap<String, String>,
+
+    /// The limits on the contract's memory and tables
+    limits: StoreLimits,
+}
+
+/// Gets the engine contracts get compiled with, configured so that every node executes them the
+/// same way: floating-point NaNs get canonicalized, the instructions whose results depend on the
+/// hardware are disabled and fuel gets consumed as instructions get executed
+fn engine() -> &'static Engine {
+    static ENGINE: OnceCell<Engine> = OnceCell::new();
+    ENGINE.get_or_init(|| {
+        let mut config = Config::new();
+        config
+            .consume_fuel(true)
+            .cranelift_nan_canonicalization(true)
+            .wasm_relaxed_simd(false);
+        Engine::new(&config).expect("can create the contract engine")
+    })
+}
+
+/// Checks if the code is a WebAssembly module that can be deployed as a contract
+pub fn validate(code: &[u8]) -> Result<(), String> {
+    if code.len() > MAX_CODE_SIZE {
+        return Err(format!("code exceeds the limit of {} bytes", MAX_CODE_SIZE));
+    }
+    Module::validate(engine(), code).map_err(|e| e.to_string())
+}
+
+/// Compiles the code, validating it first, unless it has been compiled recently already
+fn compile(code: &[u8]) -> Result<Module, String> {
+    static CACHE: OnceCell<Mutex<ModuleCache>> = OnceCell::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(ModuleCache::new(MAX_CACHED_MODULES)));
+    let hash = hex::encode(block::hash(code));
+    if let Some(module) = cache.lock().expect("can lock the module cache").get(&hash) {
+        return Ok(module);
+    }
+
+    // Compiled without holding the lock, so that other contracts get executed meanwhile
+    validate(code)?;
+    let module = Module::from_binary(engine(), code).map_err(|e| e.to_string())?;
+    cache
+        .lock()
+        .expect("can lock the module cache")
+        .insert(hash, module.clone());
+    Ok(module)
+}
+
+/// Executes the function exported by the contract's code with the given input, consuming up to
+/// the given fuel. Returns the contract's storage as the function leaves it, or the reason the
+/// execution failed, the storage being left untouched then. A missing function is only a failure
+/// when the function is not the `init` one.
+pub fn execute(
+    code: &[u8],
+    storage: &BTreeMap<String, String>,
+    function: &str,
+    input: &[u8],
+    fuel: u64,
+) -> Result<BTreeMap<String, String>, String> {
+    if fuel > MAX_FUEL {
+        return Err(format!("fuel exceeds the limit of {}", MAX_FUEL));
+    }
+    if input.len() > MAX_DATA_SIZE {
+        return Err(format!(
+            "input exceeds the limit of {} bytes",
+            MAX_DATA_SIZE
+        ));
+    }
+    let module = compile(code)?;
+    let engine = engine();
+    let host = Host {
+        input: input.to_vec(),
+        storage: storage.clone(),
+        limits: StoreLimitsBuilder::new()
+            .memory_size(MAX_MEMORY_SIZE)
+            .instances(1)
+            .build(),
+    };
+    let mut store = Store::new(engine, host);
+    store.limiter(|host| &mut host.limits);
+    store.set_fuel(fuel).map_err(|e| e.to_string())?;
+
+    let instance = linker(engine)?
+        .instantiate(&mut store, &module)
+        .map_err(|e| e.to_string())?;
+    match instance.get_func(&mut store, function) {
+        Some(func) => func
+            .typed::<(), ()>(&store)
+            .map_err(|e| e.to_string())?
+            .call(&mut store, ())
+            .map_err(|e| e.root_cause().to_string())?,
+        None if function == INIT_FUNCTION => {}
+        None => return Err(format!("function '{}' is not exported", function)),
+    }
+    Ok(store.into_data().storage)
+}
+
+/// Creates the linker providing the functions contracts can import:
+/// - `input_len() -> i32` gets the size of the input
+/// - `read_input(ptr: i32)` copies the input to the memory at the pointer
+/// - `storage_get(key_ptr: i32, key_len: i32, value_ptr: i32, value_cap: i32) -> i32` copies up
+///   to the capacity of the value stored under the key to the memory, returning the value's size
+///   or -1 if the key is not stored
+/// - `storage_set(key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32)` stores the value
+///   under the key
+/// - `storage_remove(key_ptr: i32, key_len: i32)` removes the value stored under the key
+fn linker(engine: &Engine) -> Result<Linker<Host>, String> {
+    let mut linker = Linker::new(engine);
+    linker
+        .func_wrap(HOST_MODULE, "input_len", |caller: Caller<'_, Host>| {
+            caller.data().input.len() as i32
+        })
+        .and_then(|linker| {
+            linker.func_wrap(
+                HOST_MODULE,
+                "read_input",
+                |mut caller: Caller<'_, Host>, ptr: i32| {
+                    let input = caller.data().input.clone();
+                    write(&mut caller, ptr, &input)
+                },
+            )
+        })
+        .and_then(|linker| {
+            linker.func_wrap(
+                HOST_MODULE,
+                "storage_get",
+                |mut caller: Caller<'_, Host>,
+                 key_ptr: i32,
+                 key_len: i32,
+                 value_ptr: i32,
+                 value_cap: i32| {
+                    let key = hex::encode(read(&mut caller, key_ptr, key_len)?);
+                    let value = match caller.data().storage.get(&key) {
+                        Some(value) => hex::decode(value)?,
+                        None => return Ok(-1),
+                    };
+                    let len = value.len().min(value_cap.max(0) as usize);
+                    write(&mut caller, value_ptr, &value[..len])?;
+                    Ok(value.len() as i32)
+                },
+            )
+        })
+        .and_then(|linker| {
+            linker.func_wrap(
+                HOST_MODULE,
+                "storage_set",
+                |mut caller: Caller<'_, Host>,
+                 key_ptr: i32,
+                 key_len: i32,
+                 value_ptr: i32,
+                 value_len: i32| {
+                    let key = read(&mut caller, key_ptr, key_len)?;
+                    let value = read(&mut caller, value_ptr, value_len)?;
+                    charge(&mut caller, (key.len() + value.len()) as u64)?;
+                    caller
+                        .data_mut()
+                        .storage
+                        .insert(hex::encode(key), hex::encode(value));
+                    Ok(())
+                },
+            )
+        })
+        .and_then(|linker| {
+            linker.func_wrap(
+                HOST_MODULE,
+                "storage_remove",
+                |mut caller: Caller<'_, Host>, key_ptr: i32, key_len: i32| {
+                    let key = read(&mut caller, key_ptr, key_len)?;
+                    charge(&mut caller, key.len() as u64)?;
+                    caller.data_mut().storage.remove(&hex::encode(key));
+                    Ok(())
+                },
+            )
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(linker)
+}
+
+/// Consumes the fuel of a storage write of the given number of bytes
+fn charge(caller: &mut Caller<'_, Host>, bytes: u64) -> wasmtime::Result<()> {
+    let cost = STORAGE_WRITE_FUEL + bytes * STORAGE_BYTE_FUEL;
+    let fuel = caller.get_fuel()?;
+    if fuel < cost {
+        caller.set_fuel(0)?;
+        return Err(wasmtime::Error::msg("all fuel consumed"));
+    }
+    caller.set_fuel(fuel - cost)
+}
+
+/// Gets the memory exported by the running contract
+fn memory(caller: &mut Caller<'_, Host>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("contract does not export its memory"))
+}
+
+/// Reads the given number of bytes out of the contract's memory at the pointer
+fn read(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    if !(0..=MAX_DATA_SIZE as i32).contains(&len) {
+        return Err(wasmtime::Error::msg(format!(
+            "data exceeds the limit of {} bytes",
+            MAX_DATA_SIZE
+        )));
+    }
+    let mut data = vec![0; len as usize];
+    memory(caller)?.read(&*caller, ptr as u32 as usize, &mut data)?;
+    Ok(data)
+}
+
+/// Writes the bytes to the contract's memory at the pointer
+fn write(caller: &mut Caller<'_, Host>, ptr: i32, data: &[u8]) -> wasmtime::Result<()> {
+    memory(caller)?.write(&mut *caller, ptr as u32 as usize, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A counter contract, adding the byte it gets as input to the byte stored under key 0
+    const COUNTER: &str = r#"
+        (module
+            (import "env" "input_len" (func $input_len (result i32)))
+            (import "env" "read_input" (func $read_input (param i32)))
+            (import "env" "storage_get" (func $get (param i32 i32 i32 i32) (result i32)))
+            (import "env" "storage_set" (func $set (param i32 i32 i32 i32)))
+            (memory (export "memory") 1)
+            (func (export "init")
+                (i32.store8 (i32.const 1) (i32.const 0))
+                (call $set (i32.const 0) (i32.const 1) (i32.const 1) (i32.const 1)))
+            (func (export "add")
+                (drop (call $get (i32.const 0) (i32.const 1) (i32.const 1) (i32.const 1)))
+                (call $read_input (i32.const 2))
+                (i32.store8 (i32.const 1)
+                    (i32.add (i32.load8_u (i32.const 1)) (i32.load8_u (i32.const 2))))
+                (call $set (i32.const 0) (i32.const 1) (i32.const 1) (i32.const 1)))
+            (func (export "spin") (loop $spin (br $spin))))
+    "#;
+
+    #[test]
+    fn execute_counter() {
+        let code = wat::parse_str(COUNTER).unwrap();
+        let storage = execute(&code, &BTreeMap::new(), INIT_FUNCTION, &[], 10_000).unwrap();
+        assert_eq!(storage.get("00").map(String::as_str), Some("00"));
+
+        let storage = execute(&code, &storage, "add", &[5], 10_000).unwrap();
+        let storage = execute(&code, &storage, "add", &[7], 10_000).unwrap();
+        assert_eq!(storage.get("00").map(String::as_str), Some("0c"));
+
+        assert!(execute(&code, &storage, "missing", &[], 10_000).is_err());
+    }
+
+    #[test]
+    fn metered_fuel() {
+        let code = wat::parse_str(COUNTER).unwrap();
+        assert!(execute(&code, &BTreeMap::new(), "spin", &[], 10_000).is_err());
+        assert!(execute(&code, &BTreeMap::new(), "add", &[1], 10).is_err());
+        assert!(execute(&code, &BTreeMap::new(), "add", &[1], MAX_FUEL + 1).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_code() {
+        assert!(validate(b"not wasm").is_err());
+        let importing = wat::parse_str(r#"(module (import "wasi" "fd_write" (func)))"#).unwrap();
+        assert!(execute(&importing, &BTreeMap::new(), INIT_FUNCTION, &[], 10_000).is_err());
+    }
+
+    #[test]
+    fn cached_modules() {
+        let counter = compile(&wat::parse_str(COUNTER).unwrap()).unwrap();
+        let empty = compile(&wat::parse_str("(module)").unwrap()).unwrap();
+
+        let mut cache = ModuleCache::new(2);
+        cache.insert(String::from("counter"), counter.clone());
+        cache.insert(String::from("empty"), empty.clone());
+        assert!(cache.get("counter").is_some());
+
+        // The least recently used module gets evicted
+        cache.insert(String::from("other"), empty);
+        assert!(cache.get("empty").is_none());
+        assert!(cache.get("counter").is_some());
+        assert!(cache.get("other").is_some());
+        assert_eq!(cache.modules.len(), 2);
+        assert_eq!(cache.usage.len(), 2);
+    }
+}
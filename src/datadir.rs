@@ -0,0 +1,161 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::store::BlockStore,
+    std::{
+        env, fs, io,
+        path::{Path, PathBuf},
+    },
+};
+
+/// The directories a node keeps its data in. Each chain gets a subdirectory of the data directory
+/// named after its ID, so that nodes of several networks can share the data directory:
+///
+/// ```text
+/// <root>/<chain_id>/{blocks,state,wallet,peers,logs}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDir {
+    /// The directory of the chain
+    chain: PathBuf,
+}
+
+impl DataDir {
+    /// The subdirectories of the chain's directory
+    pub const SUBDIRS: &'static [&'static str] = &["blocks", "state", "wallet", "peers", "logs"];
+
+    /// Opens the directory of the chain with the given ID in the data directory, creating the
+    /// missing directories. A block store laid out flat in the data directory, as it used to be,
+    /// is moved to the chain's `blocks` directory first.
+    pub fn open(root: &Path, chain_id: &str) -> io::Result<Self> {
+        let dir = Self {
+            chain: root.join(chain_id),
+        };
+        let moved = dir.migrate(root)?;
+        if moved > 0 {
+            log::info!(
+                "moved the {} entries of the block store in {} to {}",
+                moved,
+                root.display(),
+                dir.blocks().display()
+            );
+        }
+        for subdir in DataDir::SUBDIRS {
+            fs::create_dir_all(dir.chain.join(subdir))?;
+        }
+        Ok(dir)
+    }
+
+    /// Gets the directory of the chain
+    pub fn chain(&self) -> &Path {
+        &self.chain
+    }
+
+    /// Gets the directory the blocks are stored in
+    pub fn blocks(&self) -> PathBuf {
+        self.chain.join("blocks")
+    }
+
+    /// Gets the directory the state derived from the blocks is kept in
+    pub fn state(&self) -> PathBuf {
+        self.chain.join("state")
+    }
+
+    /// Gets the directory the keys of the node are kept in
+    pub fn wallet(&self) -> PathBuf {
+        self.chain.join("wallet")
+    }
+
+    /// Gets the directory what the node learned about its peers is kept in
+    pub fn peers(&self) -> PathBuf {
+        self.chain.join("peers")
+    }
+
+    /// Gets the file the banned peers and IP addresses are kept in
+    pub fn bans(&self) -> PathBuf {
+        self.peers().join("bans.json")
+    }
+
+    /// Gets the directory the logs are written to
+    pub fn logs(&self) -> PathBuf {
+        self.chain.join("logs")
+    }
+
+    /// Moves the entries of a block store laid out flat in the data directory to the chain's
+    /// `blocks` directory, returning the number of moved entries. Nothing is moved if the chain
+    /// already has stored blocks, so that they are never overwritten.
+    fn migrate(&self, root: &Path) -> io::Result<usize> {
+        let flat = match fs::read_dir(root) {
+            Ok(entries) => entries
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<io::Result<Vec<_>>>()?
+                .into_iter()
+                .filter(|name| BlockStore::owns(&name.to_string_lossy()))
+                .collect::<Vec<_>>(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        if flat.is_empty() {
+            return Ok(0);
+        }
+
+        let blocks = self.blocks();
+        fs::create_dir_all(&blocks)?;
+        if fs::read_dir(&blocks)?.next().is_some() {
+            log::warn!(
+                "leaving the block store in {} as is, {} already stores blocks",
+                root.display(),
+                blocks.display()
+            );
+            return Ok(0);
+        }
+        for name in &flat {
+            fs::rename(root.join(name), blocks.join(name))?;
+        }
+        Ok(flat.len())
+    }
+}
+
+/// Gets the default data directory, `~/.tetherion`, if the home directory is known
+pub fn default_root() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".tetherion"))
+}
+
+/// Expands the leading `~` of the path to the home directory, if known
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_flat_layout() {
+        let root = env::temp_dir().join(format!("tetherion-datadir-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("archive")).unwrap();
+        fs::write(root.join("blocks.jsonl"), "{}\n").unwrap();
+        fs::write(root.join("wal.json"), "").unwrap();
+        fs::write(root.join("notes.txt"), "kept").unwrap();
+
+        let dir = DataDir::open(&root, "testnet").unwrap();
+        assert_eq!(dir.chain(), root.join("testnet"));
+        assert!(dir.blocks().join("blocks.jsonl").is_file());
+        assert!(dir.blocks().join("wal.json").is_file());
+        assert!(dir.blocks().join("archive").is_dir());
+        assert!(!root.join("blocks.jsonl").exists());
+        assert!(root.join("notes.txt").is_file());
+        for subdir in DataDir::SUBDIRS {
+            assert!(dir.chain().join(subdir).is_dir());
+        }
+
+        fs::write(root.join("blocks.jsonl"), "{}\n").unwrap();
+        assert_eq!(DataDir::open(&root, "testnet").unwrap(), dir);
+        assert!(root.join("blocks.jsonl").is_file());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        config::MiningConfig,
+        node::{self, NodeConfig},
+        payload::Payload,
+        tetherion::Tetherion,
+    },
+    std::{io::Write, thread, time::Duration},
+    tokio::{
+        runtime,
+        sync::{mpsc, oneshot},
+    },
+};
+
+/// Initializes the global logger so that each log line is prefixed with the name of the node,
+/// i.e. the thread, it comes from
+pub fn init_logger() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder
+        .format(|f, record| {
+            writeln!(
+                f,
+                "[{}] {:<5} {} > {}",
+                thread::current().name().unwrap_or("main"),
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        })
+        .parse_filters(&std::env::var("RUST_LOG").unwrap_or_else(|_| String::from("info")));
+    builder.init();
+}
+
+/// Launches a local testnet of `nodes` nodes sharing the same genesis block, each one dialing
+/// all of the previously started nodes. Only the first node mines blocks, every `interval`, and
+/// reads commands from stdin, forwarding the tuning ones to the other nodes.
+pub fn launch(nodes: usize, difficulty: usize, interval: Duration, mining: MiningConfig) {
+    let tetherion = Tetherion::new(Payload::Text(String::from("genesis")), difficulty);
+    let mut addrs = Vec::new();
+    let mut handles = Vec::new();
+    let (mut senders, mut receivers): (Vec<_>, Vec<_>) = (0..nodes)
+        .map(|_| mpsc::channel(node::COMMAND_QUEUE_SIZE))
+        .unzip();
+    let dev_peers = senders.split_off(1.min(nodes));
+
+    for i in 0..nodes {
+        let name = format!("node-{}", i);
+        let tetherion = tetherion.clone();
+        let config = NodeConfig {
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0"
+                .parse()
+                .expect("can get a local socket")],
+            bootstrap: addrs.clone(),
+            auto_mine: if i == 0 { Some(interval) } else { None },
+            interactive: i == 0,
+            mining,
+            dev: true,
+            dev_peers: if i == 0 {
+                dev_peers.clone()
+            } else {
+                Vec::new()
+            },
+            ..Default::default()
+        };
+        let commands = receivers.remove(0);
+        let (ready_sender, ready_rcv) = oneshot::channel();
+
+        let handle = thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || {
+                runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .thread_name(name)
+                    .build()
+                    .expect("node runtime should be created")
+                    .block_on(node::run(
+                        tetherion,
+                        config,
+                        Some(ready_sender),
+                        Some(commands),
+                    ));
+            })
+            .expect("node thread should be spawned");
+
+        addrs.push(
+            ready_rcv
+                .blocking_recv()
+                .expect("node should report its listen address"),
+        );
+        handles.push(handle);
+    }
+
+    for handle in handles {
+        handle.join().expect("node thread should not panic");
+    }
+}
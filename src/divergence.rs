@@ -0,0 +1,212 @@
+/// Copyright (c) 2022 Tetherion
+use {crate::block::BlockHeader, serde::Serialize};
+
+/// A block of a branch, as reported
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BranchBlock {
+    pub height: u64,
+    pub hash: String,
+    pub timestamp: i64,
+}
+
+impl From<&BlockHeader> for BranchBlock {
+    fn from(header: &BlockHeader) -> Self {
+        Self {
+            height: header.id,
+            hash: header.hash.clone(),
+            timestamp: header.timestamp,
+        }
+    }
+}
+
+/// The blocks of a chain past the common ancestor
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    /// The first block past the common ancestor, if any
+    pub first: Option<BranchBlock>,
+
+    /// The latest block of the chain
+    pub tip: BranchBlock,
+
+    /// The number of blocks past the common ancestor
+    pub blocks: usize,
+
+    /// The work the blocks past the common ancestor took, added up
+    pub work: u128,
+
+    /// Why the first invalid block past the common ancestor is invalid, if any
+    pub invalid: Option<String>,
+}
+
+/// How two chains relate to each other
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The chains are the same
+    Identical,
+
+    /// The remote chain is behind the local one, without diverging from it
+    LocalAhead,
+
+    /// The local chain is behind the remote one, without diverging from it
+    RemoteAhead,
+
+    /// The chains share their first blocks, then diverge
+    Forked,
+
+    /// The chains share no block, e.g. they start from different genesis blocks
+    Unrelated,
+}
+
+/// Where and why the local chain and a remote one diverge
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub verdict: Verdict,
+
+    /// The latest block shared by the chains, if any
+    pub ancestor: Option<BranchBlock>,
+
+    pub local: Branch,
+    pub remote: Branch,
+}
+
+/// Compares the headers of the local chain with the ones of a remote chain, given how much work
+/// each block takes and whether its header is valid. Returns `None` in case either chain has no
+/// header.
+pub fn diverge(
+    local: &[BlockHeader],
+    remote: &[BlockHeader],
+    work: impl Fn(&BlockHeader) -> u128,
+    valid: impl Fn(&BlockHeader) -> bool,
+) -> Option<Divergence> {
+    let (local_tip, remote_tip) = (local.last()?, remote.last()?);
+    // The chains may start at different heights, e.g. from a checkpoint, so they are compared
+    // from the highest of their first blocks
+    let start = local[0].id.max(remote[0].id);
+    let local_start = usize::try_from(start - local[0].id).unwrap_or(usize::MAX);
+    let remote_start = usize::try_from(start - remote[0].id).unwrap_or(usize::MAX);
+    let local_rest = local.get(local_start..).unwrap_or_default();
+    let remote_rest = remote.get(remote_start..).unwrap_or_default();
+    let shared = local_rest
+        .iter()
+        .zip(remote_rest)
+        .take_while(|(local, remote)| local.hash == remote.hash)
+        .count();
+    let ancestor = shared.checked_sub(1).map(|last| &local_rest[last]);
+
+    let branch = |headers: &[BlockHeader], tip: &BlockHeader| {
+        let past = match ancestor {
+            Some(_) => &headers[shared..],
+            None => headers,
+        };
+        Branch {
+            first: past.first().map(BranchBlock::from),
+            tip: BranchBlock::from(tip),
+            blocks: past.len(),
+            work: past
+                .iter()
+                .fold(0, |total: u128, header| total.saturating_add(work(header))),
+            invalid: find_invalid(ancestor, past, &valid),
+        }
+    };
+    let local_branch = branch(local_rest, local_tip);
+    let remote_branch = branch(remote_rest, remote_tip);
+    let verdict = match (ancestor, local_branch.blocks, remote_branch.blocks) {
+        (None, _, _) => Verdict::Unrelated,
+        (Some(_), 0, 0) => Verdict::Identical,
+        (Some(_), _, 0) => Verdict::LocalAhead,
+        (Some(_), 0, _) => Verdict::RemoteAhead,
+        (Some(_), _, _) => Verdict::Forked,
+    };
+    Some(Divergence {
+        verdict,
+        ancestor: ancestor.map(BranchBlock::from),
+        local: local_branch,
+        remote: remote_branch,
+    })
+}
+
+/// Finds the first of the headers that does not follow the previous one or is not valid,
+/// returning why
+fn find_invalid(
+    ancestor: Option<&BlockHeader>,
+    headers: &[BlockHeader],
+    valid: impl Fn(&BlockHeader) -> bool,
+) -> Option<String> {
+    let mut previous = ancestor;
+    for header in headers {
+        if let Some(previous) = previous {
+            if header.id != previous.id + 1 || header.previous_hash != previous.hash {
+                return Some(format!(
+                    "block {} does not follow block {}",
+                    header.id, previous.id
+                ));
+            }
+        }
+        if !valid(header) {
+            return Some(format!(
+                "block {} has an invalid hash or does not meet the difficulty",
+                header.id
+            ));
+        }
+        previous = Some(header);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(hashes: &[&str]) -> Vec<BlockHeader> {
+        let mut previous = String::new();
+        hashes
+            .iter()
+            .enumerate()
+            .map(|(id, hash)| BlockHeader {
+                id: id as u64,
+                hash: hash.to_string(),
+                previous_hash: std::mem::replace(&mut previous, hash.to_string()),
+                timestamp: 1_700_000_000 + id as i64,
+                nonce: 0,
+                merkle_root: String::new(),
+                signature: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find_fork() {
+        let work = |header: &BlockHeader| u128::from(header.id);
+        let valid = |header: &BlockHeader| !header.hash.starts_with("bad");
+        let local = chain(&["g", "a", "b", "c"]);
+
+        let diff = diverge(&local, &chain(&["g", "a", "x", "bad"]), work, valid).unwrap();
+        assert_eq!(diff.verdict, Verdict::Forked);
+        assert_eq!(diff.ancestor.unwrap().hash, "a");
+        assert_eq!(diff.local.first.unwrap().hash, "b");
+        assert_eq!((diff.local.blocks, diff.local.work), (2, 5));
+        assert_eq!(diff.remote.tip.hash, "bad");
+        assert_eq!(diff.local.invalid, None);
+        assert_eq!(
+            diff.remote.invalid.as_deref(),
+            Some("block 3 has an invalid hash or does not meet the difficulty")
+        );
+
+        let diff = diverge(&local, &local[..2], work, valid).unwrap();
+        assert_eq!(diff.verdict, Verdict::LocalAhead);
+        assert_eq!(diff.remote.first, None);
+        let diff = diverge(&local, &local, work, valid).unwrap();
+        assert_eq!(diff.verdict, Verdict::Identical);
+        let diff = diverge(&local, &chain(&["h", "a"]), work, valid).unwrap();
+        assert_eq!(diff.verdict, Verdict::Unrelated);
+        assert_eq!(diff.remote.blocks, 2);
+
+        // A remote chain starting from a checkpoint is compared from there on
+        let remote = chain(&["g", "a", "b", "c", "d"]).split_off(2);
+        let diff = diverge(&local, &remote, work, valid).unwrap();
+        assert_eq!(diff.verdict, Verdict::RemoteAhead);
+        assert_eq!(diff.remote.blocks, 1);
+        assert!(diverge(&local, &[], work, valid).is_none());
+    }
+}
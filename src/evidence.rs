@@ -0,0 +1,150 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{block::BlockHeader, payload},
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+};
+
+/// The number of blocks below the latest observed header whose headers are kept to detect
+/// equivocation
+const HEADER_WINDOW: u64 = 100;
+
+/// The proof that a block producer signed two different blocks at the same height
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Evidence {
+    /// The producer's Ed25519 public key, in HEX format
+    pub offender: String,
+
+    pub first: BlockHeader,
+    pub second: BlockHeader,
+}
+
+impl Evidence {
+    /// Gets the height the producer equivocated at
+    pub fn height(&self) -> u64 {
+        self.first.id
+    }
+
+    /// Checks if the headers are different, at the same height, matching their content and both
+    /// signed by the offender
+    pub fn is_valid(&self) -> bool {
+        self.first.id == self.second.id
+            && self.first.hash != self.second.hash
+            && [&self.first, &self.second]
+                .iter()
+                .all(|header| header.has_valid_hash() && is_signed_by(header, &self.offender))
+    }
+}
+
+/// Checks if the header's hash is signed by the given Ed25519 public key, in HEX format
+fn is_signed_by(header: &BlockHeader, key: &str) -> bool {
+    header
+        .signature
+        .as_ref()
+        .is_some_and(|signature| payload::verify(key, header.hash.as_bytes(), signature))
+}
+
+/// Detects block producers signing different blocks at the same height from the headers the node
+/// observes, and records the evidence of it, detected or received from the peers
+#[derive(Debug, Default)]
+pub struct EvidencePool {
+    /// The first header observed at each recent height from each producer
+    headers: BTreeMap<(u64, String), BlockHeader>,
+
+    /// The recorded evidence, by offender and height
+    recorded: BTreeMap<(String, u64), Evidence>,
+}
+
+impl EvidencePool {
+    /// Observes the header signed by its scheduled producer, returning the evidence of the
+    /// producer's equivocation in case it signed another header at the same height before
+    pub fn observe(&mut self, producer: &str, header: &BlockHeader) -> Option<Evidence> {
+        if !header.has_valid_hash() || !is_signed_by(header, producer) {
+            return None;
+        }
+        let oldest = (header.id.saturating_sub(HEADER_WINDOW), String::new());
+        if self.headers.keys().next().is_some_and(|key| *key < oldest) {
+            self.headers = self.headers.split_off(&oldest);
+        }
+
+        let first = self
+            .headers
+            .entry((header.id, producer.to_owned()))
+            .or_insert_with(|| header.clone());
+        if first.hash == header.hash {
+            return None;
+        }
+        let evidence = Evidence {
+            offender: producer.to_owned(),
+            first: first.clone(),
+            second: header.clone(),
+        };
+        self.add(evidence.clone()).then_some(evidence)
+    }
+
+    /// Records the evidence, returning whether it is valid and was not recorded before
+    pub fn add(&mut self, evidence: Evidence) -> bool {
+        if !evidence.is_valid() {
+            return false;
+        }
+        let key = (evidence.offender.clone(), evidence.height());
+        if self.recorded.contains_key(&key) {
+            return false;
+        }
+        self.recorded.insert(key, evidence);
+        true
+    }
+
+    /// Gets the recorded evidence, ordered by offender and height
+    pub fn recorded(&self) -> impl Iterator<Item = &Evidence> {
+        self.recorded.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            block::Block, consensus::Stakes, payload::Payload, state::State, tetherion::BlockData,
+        },
+        libp2p::identity::ed25519,
+    };
+
+    #[test]
+    fn detect_equivocation() {
+        let keys = ed25519::Keypair::generate();
+        let producer = hex::encode(keys.public().encode());
+        let genesis = Block::genesis(Payload::Text(String::from("genesis")), 0);
+        let sealed = |data: &str| {
+            let data = Payload::Text(data.to_owned());
+            Block::sealed(1, &genesis.hash, 10, data, &keys).header()
+        };
+
+        let mut pool = EvidencePool::default();
+        assert!(pool.observe(&producer, &sealed("first")).is_none());
+        assert!(pool.observe(&producer, &sealed("first")).is_none());
+        // Headers not signed by the producer prove nothing
+        assert!(pool.observe("other", &sealed("second")).is_none());
+
+        let evidence = pool.observe(&producer, &sealed("second")).unwrap();
+        assert_eq!(
+            (evidence.offender.as_str(), evidence.height()),
+            (producer.as_str(), 1)
+        );
+        assert!(evidence.is_valid());
+        assert!(!pool.add(evidence.clone()));
+        assert_eq!(pool.recorded().count(), 1);
+
+        // Stored evidence records the offense, once
+        let mut state = State::default();
+        let payload = Payload::Evidence(Box::new(evidence.clone()));
+        assert!(payload.apply(5, &mut state).is_ok());
+        assert_eq!(state.offenses(), vec![(producer.clone(), 5)]);
+        assert!(payload.apply(6, &mut state).is_err());
+
+        let mut forged = evidence;
+        forged.second = forged.first.clone();
+        assert!(!forged.is_valid());
+    }
+}
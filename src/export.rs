@@ -0,0 +1,233 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{payload::Payload, tetherion::Tetherion},
+    parquet::{
+        column::writer::ColumnWriter,
+        data_type::ByteArray,
+        errors::ParquetError,
+        file::{properties::WriterProperties, writer::SerializedFileWriter},
+        schema::parser::parse_message_type,
+    },
+    serde::Serialize,
+    std::{fs::File, io, path::Path, sync::Arc},
+};
+
+/// The format the blockchain is exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Comma-separated values with a header row
+    Csv,
+
+    /// Apache Parquet columnar file
+    Parquet,
+}
+
+/// The metadata of a single block, flattened for analysis
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockRow {
+    /// The ID of the block
+    pub id: u64,
+
+    /// The hash of the block
+    pub hash: String,
+
+    /// The hash of the previous block
+    pub previous_hash: String,
+
+    /// The timestamp of when the block was created, in seconds
+    pub timestamp: i64,
+
+    /// The nonce found by mining the block
+    pub nonce: u64,
+
+    /// The difficulty of the blockchain
+    pub difficulty: usize,
+
+    /// The size, in bytes, of the block's JSON payload
+    pub payload_size: usize,
+
+    /// The account that sealed the block, if known; mined blocks do not record their miner
+    pub miner: Option<String>,
+}
+
+/// The Parquet schema of the exported rows
+const PARQUET_SCHEMA: &str = "
+    message block {
+        REQUIRED INT64 id;
+        REQUIRED BYTE_ARRAY hash (UTF8);
+        REQUIRED BYTE_ARRAY previous_hash (UTF8);
+        REQUIRED INT64 timestamp;
+        REQUIRED INT64 nonce;
+        REQUIRED INT64 difficulty;
+        REQUIRED INT64 payload_size;
+        OPTIONAL BYTE_ARRAY miner (UTF8);
+    }
+";
+
+/// Flattens the blocks of the blockchain into rows, the oldest first
+pub fn rows(tetherion: &Tetherion<Payload>) -> Vec<BlockRow> {
+    let consensus = tetherion.consensus();
+    tetherion
+        .blocks()
+        .iter()
+        .map(|block| {
+            let header = block.header();
+            BlockRow {
+                miner: consensus.producer(&header, &tetherion.validators(block.id)),
+                id: header.id,
+                hash: header.hash,
+                previous_hash: header.previous_hash,
+                timestamp: header.timestamp,
+                nonce: header.nonce,
+                difficulty: tetherion.difficulty(),
+                payload_size: serde_json::to_vec(block.data())
+                    .expect("can jsonify payload")
+                    .len(),
+            }
+        })
+        .collect()
+}
+
+/// Writes the metadata of the blockchain's blocks to the file at the given path, returning the
+/// number of exported blocks
+pub fn export_chain(
+    tetherion: &Tetherion<Payload>,
+    format: ExportFormat,
+    path: &Path,
+) -> io::Result<usize> {
+    let rows = rows(tetherion);
+    let file = File::create(path)?;
+    match format {
+        ExportFormat::Csv => write_csv(&rows, file)?,
+        ExportFormat::Parquet => write_parquet(&rows, file).map_err(io::Error::other)?,
+    }
+    Ok(rows.len())
+}
+
+/// Writes the rows as CSV, preceded by a header row
+fn write_csv<W: io::Write>(rows: &[BlockRow], writer: W) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()
+}
+
+/// Writes the rows as a single row group of a Parquet file
+fn write_parquet(rows: &[BlockRow], file: File) -> Result<(), ParquetError> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+
+    let int64 = |value: fn(&BlockRow) -> i64| rows.iter().map(value).collect::<Vec<_>>();
+    let text = |value: fn(&BlockRow) -> &str| {
+        rows.iter()
+            .map(|row| ByteArray::from(value(row)))
+            .collect::<Vec<_>>()
+    };
+    let miners: Vec<_> = rows
+        .iter()
+        .filter_map(|row| row.miner.as_deref().map(ByteArray::from))
+        .collect();
+    let defined: Vec<_> = rows.iter().map(|row| row.miner.is_some() as i16).collect();
+
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match (index, column.untyped()) {
+            (0, ColumnWriter::Int64ColumnWriter(writer)) => {
+                writer.write_batch(&int64(|row| row.id as i64), None, None)?
+            }
+            (1, ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                writer.write_batch(&text(|row| &row.hash), None, None)?
+            }
+            (2, ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                writer.write_batch(&text(|row| &row.previous_hash), None, None)?
+            }
+            (3, ColumnWriter::Int64ColumnWriter(writer)) => {
+                writer.write_batch(&int64(|row| row.timestamp), None, None)?
+            }
+            (4, ColumnWriter::Int64ColumnWriter(writer)) => {
+                writer.write_batch(&int64(|row| row.nonce as i64), None, None)?
+            }
+            (5, ColumnWriter::Int64ColumnWriter(writer)) => {
+                writer.write_batch(&int64(|row| row.difficulty as i64), None, None)?
+            }
+            (6, ColumnWriter::Int64ColumnWriter(writer)) => {
+                writer.write_batch(&int64(|row| row.payload_size as i64), None, None)?
+            }
+            (7, ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                writer.write_batch(&miners, Some(&defined), None)?
+            }
+            _ => {
+                return Err(ParquetError::General(format!(
+                    "unexpected column {}",
+                    index
+                )))
+            }
+        };
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::block::Block,
+        parquet::file::reader::{FileReader, SerializedFileReader},
+        std::fs,
+    };
+
+    #[test]
+    fn export_csv_and_parquet() {
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        let previous_hash = tetherion.blocks()[0].hash.clone();
+        let block = Block::new(1, &previous_hash, Payload::Text(String::from("a")), 1);
+        tetherion.add_block(block).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("tetherion-export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("chain.csv");
+        assert_eq!(
+            export_chain(&tetherion, ExportFormat::Csv, &path).unwrap(),
+            2
+        );
+        let csv = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "id,hash,previous_hash,timestamp,nonce,difficulty,payload_size,miner"
+        );
+        assert!(lines[2].starts_with(&format!(
+            "1,{},{},",
+            tetherion.blocks()[1].hash,
+            previous_hash
+        )));
+        assert!(lines[2].ends_with(",1,12,"));
+
+        let path = dir.join("chain.parquet");
+        assert_eq!(
+            export_chain(&tetherion, ExportFormat::Parquet, &path).unwrap(),
+            2
+        );
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        assert_eq!(
+            reader
+                .metadata()
+                .file_metadata()
+                .schema()
+                .get_fields()
+                .len(),
+            8
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
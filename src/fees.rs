@@ -0,0 +1,123 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{block::Block, payload::Payload},
+    serde::Serialize,
+    std::sync::Arc,
+};
+
+/// The number of the latest blocks the paid fees are sampled from
+pub const FEE_WINDOW: usize = 100;
+
+/// The fee a token operation is suggested to pay to be stored within a number of blocks
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FeeEstimate {
+    /// The suggested fee of a token operation
+    pub fee: u64,
+
+    /// The number of blocks the operation should be stored within
+    pub target_blocks: u64,
+
+    /// The number of fees sampled from the recent blocks
+    pub samples: usize,
+
+    /// The number of token operations waiting in the mempool
+    pub pending: usize,
+}
+
+/// Gets the fees paid by the token operations stored in the data
+fn fees(payload: &Payload) -> Vec<u64> {
+    match payload {
+        Payload::Token { fee, .. } => vec![*fee],
+        Payload::Batch(payloads) => payloads.iter().flat_map(fees).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Gets the total fee paid by the token operations stored in the data
+pub fn fee(payload: &Payload) -> u64 {
+    fees(payload).into_iter().sum()
+}
+
+/// Estimates the fee a token operation should pay to be stored within the target number of
+/// blocks, given the latest blocks and the data waiting in the mempool.
+///
+/// The estimate is the fee paid by the recent operations at a percentile rising from the median,
+/// for distant targets, up to the highest fee, for the next block. In case the mempool holds more
+/// operations than the target number of blocks usually store, the operation also has to outbid
+/// the pending ones that would not fit, assuming producers favour the best-paying operations.
+pub fn estimate<'a>(
+    blocks: &[Arc<Block<Payload>>],
+    pending: impl Iterator<Item = &'a Payload>,
+    target_blocks: u64,
+) -> FeeEstimate {
+    let target_blocks = target_blocks.max(1);
+    let recent = &blocks[blocks.len().saturating_sub(FEE_WINDOW)..];
+    let mut samples: Vec<u64> = recent.iter().flat_map(|block| fees(block.data())).collect();
+    samples.sort_unstable();
+    let mut pending: Vec<u64> = pending.flat_map(fees).collect();
+    pending.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut fee = match samples.len() {
+        0 => 0,
+        len => {
+            // The percentile is 50 + 50 / target, rounded up
+            let percentile = 50 + 50_u64.div_ceil(target_blocks);
+            let rank = (len as u64 * percentile).div_ceil(100).max(1);
+            samples[rank as usize - 1]
+        }
+    };
+
+    let per_block = (samples.len() / recent.len().max(1)).max(1) as u64;
+    let capacity = usize::try_from(per_block.saturating_mul(target_blocks)).unwrap_or(usize::MAX);
+    if let Some(outbid) = pending.get(capacity.saturating_sub(1)) {
+        fee = fee.max(outbid + 1);
+    }
+
+    FeeEstimate {
+        fee,
+        target_blocks,
+        samples: samples.len(),
+        pending: pending.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::payload::{TokenOp, DEFAULT_CHAIN_ID},
+        libp2p::identity::ed25519,
+    };
+
+    #[test]
+    fn estimate_fees() {
+        let keys = ed25519::Keypair::generate();
+        let operation = |fee| {
+            let op = TokenOp::Mint {
+                token: String::from("TTH"),
+                amount: 1,
+            };
+            Payload::token_with_fee(DEFAULT_CHAIN_ID, op, 0, fee, &keys)
+        };
+        let mut blocks = vec![Arc::new(Block::genesis(
+            Payload::Text(String::from("genesis")),
+            1,
+        ))];
+        for fee in [4, 1, 3, 2] {
+            let previous = blocks.last().unwrap();
+            let block = Block::new(previous.id + 1, &previous.hash, operation(fee), 1);
+            blocks.push(Arc::new(block));
+        }
+
+        let estimate = |pending: &[Payload], target| estimate(&blocks, pending.iter(), target).fee;
+        assert_eq!(estimate(&[], 1), 4);
+        assert_eq!(estimate(&[], 2), 3);
+        assert_eq!(estimate(&[], 100), 3);
+
+        // Pending operations outbid the recent ones once they do not fit in the target blocks
+        let pending = [operation(10), operation(7), operation(1)];
+        assert_eq!(estimate(&pending, 2), 8);
+        assert_eq!(estimate(&pending, 3), 3);
+        assert_eq!(estimate(&pending[..1], 2), 3);
+    }
+}
@@ -0,0 +1,191 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::Block,
+        payload,
+        tetherion::{BlockData, Tetherion},
+    },
+    libp2p::identity::ed25519,
+    serde::{Deserialize, Serialize},
+    std::collections::{HashMap, HashSet},
+};
+
+/// A voter's signed statement that the block with the given hash is at the given height
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Vote {
+    /// The ID of the block voted for
+    pub height: u64,
+
+    /// The hash of the block voted for
+    pub hash: String,
+
+    /// The voter's Ed25519 public key, in HEX format
+    pub voter: String,
+
+    /// The voter's signature of the height and the hash, in HEX format
+    pub signature: String,
+}
+
+impl Vote {
+    /// Creates the vote for the block, signed by the voter
+    pub fn new<T: BlockData>(block: &Block<T>, keys: &ed25519::Keypair) -> Self {
+        let signature = keys.sign(&Vote::message(block.id, &block.hash));
+        Self {
+            height: block.id,
+            hash: block.hash.clone(),
+            voter: hex::encode(keys.public().encode()),
+            signature: hex::encode(signature),
+        }
+    }
+
+    /// Checks if the vote is signed by its voter
+    pub fn is_signed(&self) -> bool {
+        payload::verify(
+            &self.voter,
+            &Vote::message(self.height, &self.hash),
+            &self.signature,
+        )
+    }
+
+    /// Creates the message a vote's signature is computed over
+    fn message(height: u64, hash: &str) -> Vec<u8> {
+        format!("vote:{}:{}", height, hash).into_bytes()
+    }
+}
+
+/// A reference to a block by its ID and hash
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockRef {
+    /// The ID of the block
+    pub height: u64,
+
+    /// The hash of the block
+    pub hash: String,
+}
+
+/// Tallies the votes gossiped by the configured voters and marks the blocks a quorum of them
+/// voted for as final
+#[derive(Debug, Clone)]
+pub struct Finality {
+    /// The voters' Ed25519 public keys, in HEX format
+    voters: HashSet<String>,
+
+    /// The number of voters needed to finalize a block
+    quorum: usize,
+
+    /// The voters of each block not finalized yet, by the block's ID and hash
+    votes: HashMap<(u64, String), HashSet<String>>,
+
+    /// The latest finalized block
+    finalized: Option<BlockRef>,
+}
+
+impl Finality {
+    pub fn new(voters: Vec<String>, quorum: usize) -> Self {
+        debug_assert!(quorum > 0 && quorum <= voters.len());
+        Self {
+            voters: voters.into_iter().collect(),
+            quorum,
+            votes: HashMap::new(),
+            finalized: None,
+        }
+    }
+
+    /// Gets the latest finalized block, if any
+    pub fn finalized(&self) -> Option<&BlockRef> {
+        self.finalized.as_ref()
+    }
+
+    /// Checks if the account with the given address is one of the voters
+    pub fn is_voter(&self, address: &str) -> bool {
+        self.voters.contains(address)
+    }
+
+    /// Records the vote, returning whether it is a new valid vote of a configured voter for a
+    /// block above the finalized one
+    pub fn add_vote(&mut self, vote: &Vote) -> bool {
+        if !self.is_voter(&vote.voter) || !vote.is_signed() {
+            return false;
+        }
+        if self
+            .finalized
+            .as_ref()
+            .is_some_and(|finalized| vote.height <= finalized.height)
+        {
+            return false;
+        }
+
+        self.votes
+            .entry((vote.height, vote.hash.clone()))
+            .or_default()
+            .insert(vote.voter.clone())
+    }
+
+    /// Finalizes the highest block of the blockchain voted for by a quorum, returning it if the
+    /// finalized block changed
+    pub fn update<T: BlockData>(&mut self, tetherion: &Tetherion<T>) -> Option<&BlockRef> {
+        let block = tetherion.blocks().iter().rev().find(|block| {
+            self.votes
+                .get(&(block.id, block.hash.clone()))
+                .is_some_and(|voters| voters.len() >= self.quorum)
+        })?;
+
+        let height = block.id;
+        self.finalized = Some(BlockRef {
+            height,
+            hash: block.hash.clone(),
+        });
+        self.votes.retain(|(voted, _), _| *voted > height);
+        self.finalized.as_ref()
+    }
+
+    /// Checks if the blockchain contains the finalized block, i.e. adopting it would not revert
+    /// any finalized block
+    pub fn is_compatible<T: BlockData>(&self, tetherion: &Tetherion<T>) -> bool {
+        match &self.finalized {
+            Some(finalized) => tetherion
+                .blocks()
+                .iter()
+                .any(|block| block.id == finalized.height && block.hash == finalized.hash),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorum_finalizes() {
+        const DIFFICULTY: usize = 1;
+
+        let keys: Vec<_> = (0..3).map(|_| ed25519::Keypair::generate()).collect();
+        let voters = keys
+            .iter()
+            .map(|keys| hex::encode(keys.public().encode()))
+            .collect();
+        let mut finality = Finality::new(voters, 2);
+
+        let mut tetherion = Tetherion::<String>::new(String::from("genesis"), DIFFICULTY);
+        let genesis = tetherion.blocks()[0].clone();
+        let block = Block::new(1, &genesis.hash, String::from("data"), DIFFICULTY);
+        tetherion.add_block(block.clone()).unwrap();
+
+        assert!(finality.add_vote(&Vote::new(&block, &keys[0])));
+        assert!(!finality.add_vote(&Vote::new(&block, &keys[0])));
+        assert!(!finality.add_vote(&Vote::new(&block, &ed25519::Keypair::generate())));
+        let mut forged = Vote::new(&block, &keys[1]);
+        forged.hash = genesis.hash.clone();
+        assert!(!finality.add_vote(&forged));
+        assert!(finality.update(&tetherion).is_none());
+
+        assert!(finality.add_vote(&Vote::new(&block, &keys[1])));
+        assert_eq!(finality.update(&tetherion).unwrap().height, 1);
+        assert!(!finality.add_vote(&Vote::new(&genesis, &keys[2])));
+
+        let fork = Tetherion::<String>::new(String::from("genesis"), DIFFICULTY);
+        assert!(finality.is_compatible(&tetherion));
+        assert!(!finality.is_compatible(&fork));
+    }
+}
@@ -0,0 +1,577 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::config::GossipConfig,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+        fmt,
+        hash::{Hash, Hasher},
+        time::{Duration, Instant},
+    },
+};
+
+/// The number of received messages remembered, so that the ones relayed by several peers are
+/// handled and relayed once
+const SEEN_CAPACITY: usize = 4096;
+
+/// The number of received blocks held back from relaying until validated, past which the oldest
+/// ones are dropped, e.g. the ones never validated as they do not follow any known block
+const HELD_CAPACITY: usize = 256;
+
+/// The number of sequence numbers up to the highest one received from an origin that are told
+/// apart, so that the messages taking different paths through the network may arrive out of order
+const REPLAY_WINDOW: u64 = 64;
+
+/// How the messages received from the peers are passed on to the other peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayMode {
+    /// The messages are published again by the node as soon as they are received, the blocks as
+    /// soon as they are validated
+    Immediate,
+
+    /// The messages are published again by the node once the relay delay is over, counted from
+    /// the validation of the blocks
+    Delayed,
+
+    /// The messages are not passed on, the node publishes its own messages only
+    Off,
+}
+
+/// The peers the node gossips with: at most `fanout` of the connected peers, the others standing
+/// by to replace the ones disconnecting, along with the pinned peers gossiped with whatever the
+/// fanout
+#[derive(Debug)]
+pub struct GossipView<P> {
+    fanout: Option<usize>,
+
+    /// The peers messages are published to, in the order they connected in
+    peers: Vec<P>,
+
+    /// The peers messages are published to which do not count towards the fanout
+    pinned: Vec<P>,
+
+    /// The connected peers messages are not published to, in the order they connected in
+    standby: Vec<P>,
+}
+
+impl<P: PartialEq + Clone> GossipView<P> {
+    pub fn new(fanout: Option<usize>) -> Self {
+        Self {
+            fanout,
+            peers: Vec::new(),
+            pinned: Vec::new(),
+            standby: Vec::new(),
+        }
+    }
+
+    /// Adds the peer the node connected to, returning whether messages are published to it,
+    /// which they always are to a pinned peer
+    pub fn connected(&mut self, peer: P, pinned: bool) -> bool {
+        if self.peers.contains(&peer) {
+            return true;
+        }
+        if pinned {
+            self.standby.retain(|standby| standby != &peer);
+            self.pinned.push(peer.clone());
+            self.peers.push(peer);
+            return true;
+        }
+        if self.standby.contains(&peer) {
+            return false;
+        }
+        let unpinned = self.peers.len() - self.pinned.len();
+        if self.fanout.is_none_or(|fanout| unpinned < fanout) {
+            self.peers.push(peer);
+            true
+        } else {
+            self.standby.push(peer);
+            false
+        }
+    }
+
+    /// Removes the peer the node disconnected from, returning the peer standing by promoted in
+    /// its place, if any
+    pub fn disconnected(&mut self, peer: &P) -> Option<P> {
+        self.standby.retain(|standby| standby != peer);
+        let position = self.peers.iter().position(|gossiped| gossiped == peer)?;
+        self.peers.remove(position);
+        if let Some(position) = self.pinned.iter().position(|pinned| pinned == peer) {
+            self.pinned.remove(position);
+            return None;
+        }
+        if self.standby.is_empty() {
+            return None;
+        }
+        let promoted = self.standby.remove(0);
+        self.peers.push(promoted.clone());
+        Some(promoted)
+    }
+
+    /// Checks whether the node is connected to the peer
+    pub fn contains(&self, peer: &P) -> bool {
+        self.peers.contains(peer) || self.standby.contains(peer)
+    }
+
+    /// Gets the peers messages are published to
+    pub fn peers(&self) -> &[P] {
+        &self.peers
+    }
+
+    /// Gets the number of connected peers, gossiped with or standing by
+    pub fn connections(&self) -> usize {
+        self.peers.len() + self.standby.len()
+    }
+}
+
+/// A received message waiting to be relayed
+#[derive(Debug)]
+struct Pending {
+    due: Instant,
+    topics: Vec<String>,
+    data: Vec<u8>,
+}
+
+/// Passes the messages received from the peers on to the other peers, according to the relay
+/// policy
+#[derive(Debug)]
+pub struct Relay {
+    config: GossipConfig,
+
+    /// The hashes of the messages received recently, the oldest first
+    seen: VecDeque<u64>,
+
+    /// The same hashes, for looking them up
+    seen_set: HashSet<u64>,
+
+    /// The messages waiting to be relayed, the earliest due first
+    pending: VecDeque<Pending>,
+
+    /// The block messages waiting for their blocks to be validated, by block hash, as their
+    /// topics and data
+    held: HashMap<String, (Vec<String>, Vec<u8>)>,
+
+    /// The hashes of the held blocks, the oldest first
+    held_order: VecDeque<String>,
+}
+
+impl Relay {
+    pub fn new(config: GossipConfig) -> Self {
+        Self {
+            config,
+            seen: VecDeque::new(),
+            seen_set: HashSet::new(),
+            pending: VecDeque::new(),
+            held: HashMap::new(),
+            held_order: VecDeque::new(),
+        }
+    }
+
+    /// Gets how the received messages are passed on
+    pub fn mode(&self) -> RelayMode {
+        if !self.config.relay {
+            RelayMode::Off
+        } else if self.config.relay_delay == 0 && self.config.relay_jitter == 0 {
+            RelayMode::Immediate
+        } else {
+            RelayMode::Delayed
+        }
+    }
+
+    /// Records the message received at the given time, queueing it to be relayed unless relaying
+    /// is off. Returns false in case the message was received already, which the pubsub layer
+    /// cannot tell once the message got published again by another node relaying it.
+    pub fn received(&mut self, topics: &[String], data: &[u8], now: Instant) -> bool {
+        if !self.remember(data) {
+            return false;
+        }
+        self.queue(topics.to_vec(), data.to_vec(), now);
+        true
+    }
+
+    /// Records the message of the block with the given hash, holding it back from relaying until
+    /// the block is validated, so that invalid blocks stop spreading at the first honest node.
+    /// Returns false in case the message was received already.
+    pub fn hold(&mut self, hash: &str, topics: &[String], data: &[u8]) -> bool {
+        if !self.remember(data) {
+            return false;
+        }
+        if self.mode() == RelayMode::Off {
+            return true;
+        }
+        self.held
+            .insert(hash.to_owned(), (topics.to_vec(), data.to_vec()));
+        self.held_order.push_back(hash.to_owned());
+        if self.held_order.len() > HELD_CAPACITY {
+            if let Some(oldest) = self.held_order.pop_front() {
+                self.held.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Queues the held message of the block with the given hash to be relayed, the block being
+    /// valid
+    pub fn release(&mut self, hash: &str, now: Instant) {
+        if let Some((topics, data)) = self.held.remove(hash) {
+            self.queue(topics, data, now);
+        }
+    }
+
+    /// Drops the held message of the block with the given hash, the block being invalid
+    pub fn reject(&mut self, hash: &str) {
+        self.held.remove(hash);
+    }
+
+    /// Remembers the message, returning false in case it was remembered already. Messages are
+    /// only told apart when they are relayed, the pubsub layer telling them apart otherwise.
+    fn remember(&mut self, data: &[u8]) -> bool {
+        if self.mode() == RelayMode::Off {
+            return true;
+        }
+        let hash = hash(data);
+        if !self.seen_set.insert(hash) {
+            return false;
+        }
+        self.seen.push_back(hash);
+        if self.seen.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.seen.pop_front() {
+                self.seen_set.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Queues the message to be relayed once the relay delay, along with its jitter, is over
+    fn queue(&mut self, topics: Vec<String>, data: Vec<u8>, now: Instant) {
+        if self.mode() == RelayMode::Off {
+            return;
+        }
+        let jitter = match self.config.relay_jitter {
+            0 => 0,
+            jitter => hash(&data) % (jitter + 1),
+        };
+        let due = now + self.config.relay_delay() + Duration::from_millis(jitter);
+        let position = self.pending.partition_point(|pending| pending.due <= due);
+        self.pending.insert(position, Pending { due, topics, data });
+    }
+
+    /// Takes the messages due to be relayed at the given time, as their topics and data
+    pub fn due(&mut self, now: Instant) -> Vec<(Vec<String>, Vec<u8>)> {
+        let count = self.pending.partition_point(|pending| pending.due <= now);
+        self.pending
+            .drain(..count)
+            .map(|pending| (pending.topics, pending.data))
+            .collect()
+    }
+
+    /// Gets the number of messages waiting to be relayed
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// What tells a gossiped message apart from its replays: the node that published it first, and
+/// when
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Stamp {
+    /// The peer ID of the node that published the message first
+    pub origin: String,
+
+    /// The number of the message among the ones the origin published, growing with each message
+    pub seq: u64,
+
+    /// When the origin published the message, in milliseconds since the epoch
+    pub sent_at: i64,
+}
+
+/// Stamps the messages the node publishes
+#[derive(Debug)]
+pub struct Stamper {
+    origin: String,
+    next_seq: u64,
+}
+
+impl Stamper {
+    /// Starts numbering the messages from the given time, in milliseconds since the epoch, times
+    /// a thousand, so that the messages of a restarted node are not taken for replays unless it
+    /// published a thousand messages per millisecond
+    pub fn new(origin: String, now: i64) -> Self {
+        Self {
+            origin,
+            next_seq: u64::try_from(now).unwrap_or_default().saturating_mul(1000),
+        }
+    }
+
+    /// Stamps the next message, published at the given time in milliseconds since the epoch
+    pub fn stamp(&mut self, now: i64) -> Stamp {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        Stamp {
+            origin: self.origin.clone(),
+            seq,
+            sent_at: now,
+        }
+    }
+}
+
+/// The reason a gossiped message is dropped as a replay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The message was published longer than the TTL ago
+    Expired,
+
+    /// The message claims to be published later than the TTL from now, by a node whose clock is
+    /// ahead
+    Early,
+
+    /// The message was received already, or is too old to tell
+    Seen,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Expired => write!(f, "the message outlived its TTL"),
+            Self::Early => write!(f, "the message is stamped too far in the future"),
+            Self::Seen => write!(f, "the message was received already"),
+        }
+    }
+}
+
+/// The sequence numbers received from an origin lately
+#[derive(Debug)]
+struct Window {
+    highest: u64,
+
+    /// The sequence numbers received, the lowest bit standing for the highest one
+    received: u64,
+
+    /// The latest time any message of the origin was published at, in milliseconds since the
+    /// epoch
+    latest: i64,
+}
+
+/// Drops the gossiped messages outliving their TTL and the ones received already, per origin, so
+/// that late replays of stale messages, e.g. blockchains, do not trigger a sync again
+#[derive(Debug)]
+pub struct ReplayGuard {
+    /// The TTL, in milliseconds
+    ttl: i64,
+
+    windows: HashMap<String, Window>,
+}
+
+impl ReplayGuard {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl: i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX),
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Checks the stamp of the message received at the given time, in milliseconds since the
+    /// epoch, recording it as received
+    pub fn check(&mut self, stamp: &Stamp, now: i64) -> Result<(), ReplayError> {
+        if now.saturating_sub(stamp.sent_at) > self.ttl {
+            return Err(ReplayError::Expired);
+        }
+        if stamp.sent_at.saturating_sub(now) > self.ttl {
+            return Err(ReplayError::Early);
+        }
+        let window = match self.windows.get_mut(&stamp.origin) {
+            Some(window) => window,
+            None => {
+                self.windows.insert(
+                    stamp.origin.clone(),
+                    Window {
+                        highest: stamp.seq,
+                        received: 1,
+                        latest: stamp.sent_at,
+                    },
+                );
+                return Ok(());
+            }
+        };
+        if stamp.seq > window.highest {
+            let shift = stamp.seq - window.highest;
+            window.received = if shift < REPLAY_WINDOW {
+                window.received << shift | 1
+            } else {
+                1
+            };
+            window.highest = stamp.seq;
+        } else {
+            let offset = window.highest - stamp.seq;
+            if offset >= REPLAY_WINDOW || window.received & 1 << offset != 0 {
+                return Err(ReplayError::Seen);
+            }
+            window.received |= 1 << offset;
+        }
+        window.latest = window.latest.max(stamp.sent_at);
+        Ok(())
+    }
+
+    /// Forgets about the origins whose messages all outlived the TTL at the given time, in
+    /// milliseconds since the epoch, their replays being dropped as expired anyway
+    pub fn expire(&mut self, now: i64) {
+        let ttl = self.ttl;
+        self.windows
+            .retain(|_, window| now.saturating_sub(window.latest) <= ttl);
+    }
+
+    /// Gets the number of origins messages were received from within the TTL
+    pub fn origins(&self) -> usize {
+        self.windows.len()
+    }
+}
+
+/// Hashes the message, the jitter of its relay being derived from the hash so that a replayed
+/// journal relays it at the same time
+fn hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_fanout() {
+        let mut view = GossipView::new(Some(2));
+        assert!(view.connected("a", false));
+        assert!(view.connected("b", false));
+        assert!(!view.connected("c", false));
+        assert!(view.connected("a", false));
+        assert_eq!(view.connections(), 3);
+        assert!(view.contains(&"c"));
+
+        assert_eq!(view.disconnected(&"a"), Some("c"));
+        assert_eq!(view.peers(), ["b", "c"]);
+        assert_eq!(view.disconnected(&"b"), None);
+        assert_eq!(view.peers(), ["c"]);
+
+        let mut view = GossipView::new(None);
+        assert!((0..100).all(|peer| view.connected(peer, false)));
+    }
+
+    #[test]
+    fn pin_peers() {
+        let mut view = GossipView::new(Some(1));
+        assert!(view.connected("validator", true));
+        assert!(view.connected("a", false));
+        assert!(!view.connected("b", false));
+        assert_eq!(view.peers(), ["validator", "a"]);
+
+        assert_eq!(view.disconnected(&"validator"), None);
+        assert_eq!(view.peers(), ["a"]);
+        assert!(view.connected("validator", true));
+        assert_eq!(view.disconnected(&"a"), Some("b"));
+        assert_eq!(view.peers(), ["validator", "b"]);
+    }
+
+    #[test]
+    fn delay_relay() {
+        let topics = vec![String::from("blocks")];
+        let start = Instant::now();
+
+        let mut relay = Relay::new(GossipConfig::default());
+        assert_eq!(relay.mode(), RelayMode::Immediate);
+        assert!(relay.received(&topics, b"a", start));
+        assert!(!relay.received(&topics, b"a", start));
+        assert_eq!(relay.due(start), vec![(topics.clone(), b"a".to_vec())]);
+        assert_eq!(relay.pending(), 0);
+
+        let mut relay = Relay::new(GossipConfig {
+            relay_delay: 100,
+            relay_jitter: 50,
+            ..GossipConfig::default()
+        });
+        assert_eq!(relay.mode(), RelayMode::Delayed);
+        assert!(relay.received(&topics, b"a", start));
+        assert!(relay.received(&topics, b"b", start));
+        assert!(!relay.received(&topics, b"b", start));
+        assert!(relay.due(start + Duration::from_millis(99)).is_empty());
+        let mut due = relay.due(start + Duration::from_millis(150));
+        due.sort();
+        assert_eq!(
+            due,
+            vec![(topics.clone(), b"a".to_vec()), (topics, b"b".to_vec())]
+        );
+        assert_eq!(relay.pending(), 0);
+
+        let relay = Relay::new(GossipConfig {
+            relay: false,
+            relay_delay: 100,
+            ..GossipConfig::default()
+        });
+        assert_eq!(relay.mode(), RelayMode::Off);
+    }
+
+    #[test]
+    fn relay_valid_blocks() {
+        let topics = vec![String::from("blocks")];
+        let start = Instant::now();
+        let mut relay = Relay::new(GossipConfig::default());
+
+        assert!(relay.hold("valid", &topics, b"valid block"));
+        assert!(relay.hold("invalid", &topics, b"invalid block"));
+        assert!(!relay.hold("valid", &topics, b"valid block"));
+        assert!(relay.due(start).is_empty());
+
+        relay.release("valid", start);
+        relay.reject("invalid");
+        relay.release("invalid", start);
+        assert_eq!(
+            relay.due(start),
+            vec![(topics.clone(), b"valid block".to_vec())]
+        );
+
+        // The blocks never validated are dropped once too many are held
+        for index in 0..=HELD_CAPACITY {
+            let data = index.to_string();
+            assert!(relay.hold(&data, &topics, data.as_bytes()));
+        }
+        relay.release("0", start);
+        relay.release("1", start);
+        assert_eq!(relay.due(start), vec![(topics, b"1".to_vec())]);
+    }
+
+    #[test]
+    fn drop_replays() {
+        let now = 1_700_000_000_000;
+        let mut stamper = Stamper::new(String::from("a"), now);
+        let mut guard = ReplayGuard::new(Duration::from_secs(60));
+        let first = stamper.stamp(now);
+        let second = stamper.stamp(now);
+        let third = stamper.stamp(now);
+
+        assert_eq!(guard.check(&third, now), Ok(()));
+        assert_eq!(guard.check(&first, now), Ok(()));
+        assert_eq!(guard.check(&first, now), Err(ReplayError::Seen));
+        assert_eq!(guard.check(&second, now + 1000), Ok(()));
+        assert_eq!(guard.check(&third, now + 1000), Err(ReplayError::Seen));
+
+        // The sequence numbers too far below the highest one cannot be told apart
+        let late = stamper.stamp(now);
+        for _ in 0..REPLAY_WINDOW {
+            guard.check(&stamper.stamp(now), now).unwrap();
+        }
+        assert_eq!(guard.check(&late, now), Err(ReplayError::Seen));
+
+        let stale = stamper.stamp(now);
+        assert_eq!(guard.check(&stale, now + 60_001), Err(ReplayError::Expired));
+        let early = Stamper::new(String::from("b"), now).stamp(now + 60_001);
+        assert_eq!(guard.check(&early, now), Err(ReplayError::Early));
+
+        // A restarted node numbers its messages past the ones published before
+        let restarted = Stamper::new(String::from("a"), now + 10).stamp(now + 10);
+        assert_eq!(guard.check(&restarted, now + 10), Ok(()));
+
+        guard.expire(now + 60_000);
+        assert_eq!(guard.origins(), 1);
+        guard.expire(now + 60_011);
+        assert_eq!(guard.origins(), 0);
+    }
+}
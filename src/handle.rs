@@ -0,0 +1,352 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        config::ApiScope,
+        finality::BlockRef,
+        mempool::MempoolError,
+        node::{self, NodeConfig},
+        p2p::{self, ChainSnapshot, SyncStatus, TetherionBehaviour},
+        payload::Payload,
+        tetherion::{BlockData, Tetherion},
+        webhook::{self, ChainEvent},
+    },
+    libp2p::{swarm::Swarm, Multiaddr},
+    std::{fmt, sync::Arc, thread, time::Duration},
+    tokio::{
+        runtime,
+        sync::{
+            mpsc::{self, error::TrySendError},
+            oneshot, watch,
+        },
+    },
+};
+
+/// The number of chain events that may wait to be received by a subscriber
+pub const EVENT_QUEUE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandleError {
+    LightClient,
+    Rejected(MempoolError),
+    Stopped,
+    Busy,
+    Forbidden,
+    UnknownChain(String),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleError::LightClient => write!(f, "Light clients do not mine blocks"),
+            HandleError::Rejected(err) => write!(f, "{}", err),
+            HandleError::Stopped => write!(f, "The node is not running"),
+            HandleError::Busy => write!(f, "The node has too many commands queued"),
+            HandleError::Forbidden => write!(f, "The handle's scope does not allow it"),
+            HandleError::UnknownChain(id) => write!(f, "Unknown chain {}", id),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {}
+
+/// A command sent by an embedding application to the node's event loop, which sends the result
+/// back
+#[derive(Debug)]
+pub enum NodeCommand {
+    /// Queues the payload to be stored in a block, replying with the IDs of its transactions
+    Submit {
+        payload: Payload,
+        reply: oneshot::Sender<Result<Vec<String>, HandleError>>,
+    },
+
+    /// Replies with the IDs of the discovered peers
+    Peers { reply: oneshot::Sender<Vec<String>> },
+
+    /// Replies with a receiver of the snapshots of the local blockchain
+    Snapshots {
+        reply: oneshot::Sender<watch::Receiver<Arc<ChainSnapshot>>>,
+    },
+
+    /// Replies with the state of the node
+    Status { reply: oneshot::Sender<NodeStatus> },
+
+    /// Executes the line as if it was typed in the terminal, provided the scope allows the command
+    Execute { line: String, scope: ApiScope },
+
+    /// Overrides the difficulty of the blocks from the height on, as set on another node of the
+    /// same dev network, which resolved the height from its own tip
+    SetDifficulty { height: u64, difficulty: usize },
+}
+
+/// The state of a running node, shown by the dashboard
+#[derive(Debug)]
+pub struct NodeStatus {
+    /// The progress of the synchronization with the peers
+    pub sync: SyncStatus,
+
+    /// The discovered peers, along with the latest round-trip time measured to them, if any
+    pub peers: Vec<(String, Option<Duration>)>,
+
+    /// The number of payloads waiting to be stored in blocks
+    pub mempool: usize,
+
+    /// The hashes per second computed while mining the latest block, if any
+    pub hashrate: Option<f64>,
+}
+
+/// Gets the state of the node
+fn node_status(swarm: &Swarm<TetherionBehaviour>) -> NodeStatus {
+    let behaviour = swarm.behaviour();
+    let mut peers: Vec<_> = behaviour
+        .known_peers()
+        .into_iter()
+        .map(|peer| (peer.to_string(), behaviour.peer_book.rtt(&peer)))
+        .collect();
+    peers.sort();
+    NodeStatus {
+        sync: p2p::sync_status(swarm),
+        peers,
+        mempool: behaviour.miner.pending().count() + behaviour.sealing.iter().count(),
+        hashrate: behaviour.miner.hashrate(),
+    }
+}
+
+/// Executes the command on the node and sends the result back to the application
+pub fn handle_command(command: NodeCommand, swarm: &mut Swarm<TetherionBehaviour>) {
+    let sent = match command {
+        NodeCommand::Submit { payload, reply } => {
+            let result = if swarm.behaviour().light.is_some() {
+                Err(HandleError::LightClient)
+            } else {
+                let tx_ids = payload.tx_ids();
+                p2p::submit_payload(payload, swarm)
+                    .map(|()| tx_ids)
+                    .map_err(HandleError::Rejected)
+            };
+            reply.send(result).is_ok()
+        }
+        NodeCommand::Peers { reply } => reply.send(p2p::get_peers(swarm)).is_ok(),
+        NodeCommand::Snapshots { reply } => reply.send(swarm.behaviour().snapshots()).is_ok(),
+        NodeCommand::Status { reply } => reply.send(node_status(swarm)).is_ok(),
+        NodeCommand::SetDifficulty { height, difficulty } => {
+            if swarm
+                .behaviour_mut()
+                .override_difficulty(height, difficulty)
+            {
+                log::info!("difficulty set to {} from block {}", difficulty, height);
+            }
+            true
+        }
+        NodeCommand::Execute { .. } => unreachable!("lines are executed by the event loop"),
+    };
+
+    if !sent {
+        log::error!("cannot reply to node command");
+    }
+}
+
+/// A node running in the background of an embedding application, driven through its methods
+/// instead of the terminal or the RPC server
+pub struct NodeHandle {
+    /// The first address the node listens on
+    listen_addr: Multiaddr,
+
+    /// The channel the commands are sent to the node's event loop through, which the async
+    /// methods wait for room in while it is full
+    commands: mpsc::Sender<NodeCommand>,
+
+    /// The latest snapshot of the local blockchain
+    snapshots: watch::Receiver<Arc<ChainSnapshot>>,
+
+    /// What the handle is allowed to do to the node
+    scope: ApiScope,
+}
+
+impl NodeHandle {
+    /// Starts a node on top of the given blockchain on a thread of its own, returning once the
+    /// node listens for connections. The node never reads commands from stdin and keeps running
+    /// until the process exits.
+    pub async fn start(
+        tetherion: Tetherion<Payload>,
+        config: NodeConfig,
+    ) -> Result<Self, HandleError> {
+        let config = NodeConfig {
+            interactive: false,
+            ..config
+        };
+        let (ready_sender, ready_rcv) = oneshot::channel();
+        let (commands, command_rcv) = mpsc::channel(node::COMMAND_QUEUE_SIZE);
+
+        thread::Builder::new()
+            .name(String::from("tetherion-node"))
+            .spawn(move || {
+                runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("node runtime should be created")
+                    .block_on(node::run(
+                        tetherion,
+                        config,
+                        Some(ready_sender),
+                        Some(command_rcv),
+                    ));
+            })
+            .expect("node thread should be spawned");
+
+        let listen_addr = ready_rcv.await.map_err(|_| HandleError::Stopped)?;
+        let (reply, reply_rcv) = oneshot::channel();
+        commands
+            .send(NodeCommand::Snapshots { reply })
+            .await
+            .map_err(|_| HandleError::Stopped)?;
+        let snapshots = reply_rcv.await.map_err(|_| HandleError::Stopped)?;
+
+        Ok(Self {
+            listen_addr,
+            commands,
+            snapshots,
+            scope: ApiScope::Admin,
+        })
+    }
+
+    /// Gets another handle to the same node restricted to the scope, e.g. for a monitoring
+    /// integration that should never mine or roll back the blockchain
+    pub fn with_scope(&self, scope: ApiScope) -> Self {
+        Self {
+            listen_addr: self.listen_addr.clone(),
+            commands: self.commands.clone(),
+            snapshots: self.snapshots.clone(),
+            scope: scope.min(self.scope),
+        }
+    }
+
+    /// Gets the first address the node listens on
+    pub fn listen_addr(&self) -> &Multiaddr {
+        &self.listen_addr
+    }
+
+    /// Queues the text to be stored in a new block on top of the local blockchain
+    pub async fn create_block(&self, data: &str) -> Result<(), HandleError> {
+        self.submit_tx(Payload::Text(data.to_owned()))
+            .await
+            .map(|_| ())
+    }
+
+    /// Queues a payload of any supported kind to be stored in a block, returning the IDs of the
+    /// transactions it consists of
+    pub async fn submit_tx(&self, tx: Payload) -> Result<Vec<String>, HandleError> {
+        if self.scope < ApiScope::Admin {
+            return Err(HandleError::Forbidden);
+        }
+        let (reply, reply_rcv) = oneshot::channel();
+        self.commands
+            .send(NodeCommand::Submit { payload: tx, reply })
+            .await
+            .map_err(|_| HandleError::Stopped)?;
+        reply_rcv.await.map_err(|_| HandleError::Stopped)?
+    }
+
+    /// Gets the latest block of the local blockchain
+    pub async fn chain_tip(&self) -> BlockRef {
+        self.snapshots.borrow().best()
+    }
+
+    /// Gets the IDs of the discovered peers
+    pub async fn peers(&self) -> Result<Vec<String>, HandleError> {
+        let (reply, reply_rcv) = oneshot::channel();
+        self.commands
+            .send(NodeCommand::Peers { reply })
+            .await
+            .map_err(|_| HandleError::Stopped)?;
+        reply_rcv.await.map_err(|_| HandleError::Stopped)
+    }
+
+    /// Gets the state of the node
+    pub async fn status(&self) -> Result<NodeStatus, HandleError> {
+        let (reply, reply_rcv) = oneshot::channel();
+        self.commands
+            .send(NodeCommand::Status { reply })
+            .await
+            .map_err(|_| HandleError::Stopped)?;
+        reply_rcv.await.map_err(|_| HandleError::Stopped)
+    }
+
+    /// Executes the line as if it was typed in the terminal, the results being printed to stdout.
+    /// Commands the handle's scope does not allow are refused by the node, and so are lines sent
+    /// while too many commands wait for the node already.
+    pub fn execute(&self, line: &str) -> Result<(), HandleError> {
+        self.commands
+            .try_send(NodeCommand::Execute {
+                line: line.to_owned(),
+                scope: self.scope,
+            })
+            .map_err(|err| match err {
+                TrySendError::Full(_) => HandleError::Busy,
+                TrySendError::Closed(_) => HandleError::Stopped,
+            })
+    }
+
+    /// Gets the latest snapshot of the local blockchain
+    pub fn snapshot(&self) -> Arc<ChainSnapshot> {
+        self.snapshots.borrow().clone()
+    }
+
+    /// Subscribes to the chain events happening from now on, the same ones the webhooks are
+    /// notified of. The events stop once the receiver is dropped.
+    ///
+    /// At most `EVENT_QUEUE_SIZE` events wait to be received. A subscriber falling behind gets
+    /// its events late rather than missing any: the changes of the blockchain meanwhile are
+    /// coalesced into the latest snapshot, whose events are derived once there is room.
+    pub async fn subscribe_events(&self) -> mpsc::Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+        let mut snapshots = self.snapshots.clone();
+        tokio::spawn(async move {
+            let mut previous = snapshots.borrow_and_update().clone();
+            while snapshots.changed().await.is_ok() {
+                let current = snapshots.borrow_and_update().clone();
+                for event in webhook::events(&previous.tetherion, &current.tetherion) {
+                    if sender.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                previous = current;
+            }
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::time::Duration, tokio::time::timeout};
+
+    #[tokio::test]
+    async fn drive_embedded_node() {
+        let tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        let genesis = tetherion.blocks()[0].hash.clone();
+        let config = NodeConfig {
+            listen_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+            ..Default::default()
+        };
+        let node = NodeHandle::start(tetherion, config).await.unwrap();
+        assert_eq!(
+            node.chain_tip().await,
+            BlockRef {
+                height: 0,
+                hash: genesis
+            }
+        );
+
+        let mut events = node.subscribe_events().await;
+        node.create_block("a").await.unwrap();
+        let event = timeout(Duration::from_secs(30), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let tip = node.chain_tip().await;
+        assert_eq!(event, ChainEvent::NewBlock { block: tip.clone() });
+        assert_eq!(tip.height, 1);
+        assert!(node.peers().await.is_ok());
+        assert_eq!(node.status().await.unwrap().sync.best.height, 1);
+    }
+}
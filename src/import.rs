@@ -0,0 +1,193 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::Block,
+        config::{DataEncoding, ImportConfig},
+        payload::Payload,
+        tetherion::{InvalidBlockError, Tetherion},
+    },
+    serde_json::Value,
+    std::fmt,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+    Parse(String),
+    MissingField(String),
+    InvalidField { field: String, reason: String },
+    HashMismatch { id: u64 },
+    Rejected(InvalidBlockError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Parse(reason) => write!(f, "Cannot parse the blocks: {}", reason),
+            ImportError::MissingField(field) => write!(f, "Field {} is missing", field),
+            ImportError::InvalidField { field, reason } => {
+                write!(f, "Field {} is invalid: {}", field, reason)
+            }
+            ImportError::HashMismatch { id } => write!(
+                f,
+                "Block with ID {} has a hash not matching its content",
+                id
+            ),
+            ImportError::Rejected(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// The outcome of an import
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// The number of blocks added to the blockchain
+    pub accepted: usize,
+
+    /// The position of each rejected block in the imported file, along with the reason
+    pub rejected: Vec<(usize, ImportError)>,
+}
+
+/// Parses the externally produced blocks, given either as a JSON array or as one JSON object per
+/// line
+pub fn parse_blocks(content: &str) -> Result<Vec<Value>, ImportError> {
+    if content.trim_start().starts_with('[') {
+        return serde_json::from_str(content).map_err(|err| ImportError::Parse(err.to_string()));
+    }
+
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|err| ImportError::Parse(err.to_string())))
+        .collect()
+}
+
+/// Maps an externally produced block to a Tetherion block, following the configured fields
+pub fn map_block(external: &Value, mapping: &ImportConfig) -> Result<Block<Payload>, ImportError> {
+    let field = |pointer: &str| {
+        external
+            .pointer(pointer)
+            .ok_or_else(|| ImportError::MissingField(pointer.to_owned()))
+    };
+    let invalid = |pointer: &str, reason: &str| ImportError::InvalidField {
+        field: pointer.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let text = |pointer: &str| match field(pointer)? {
+        Value::String(text) => Ok(text.clone()),
+        _ => Err(invalid(pointer, "expected a string")),
+    };
+    // Numbers are accepted as strings as well, which some dumps use for 64-bit values
+    let number = |pointer: &str| {
+        match field(pointer)? {
+            Value::Number(number) => number.as_u64(),
+            Value::String(text) => text.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| invalid(pointer, "expected an unsigned integer"))
+    };
+
+    let data = match (mapping.data_encoding, field(&mapping.data)?) {
+        (DataEncoding::Payload, value) => serde_json::from_value(value.clone())
+            .map_err(|err| invalid(&mapping.data, &err.to_string()))?,
+        (DataEncoding::Text, Value::String(text)) => Payload::Text(text.clone()),
+        (DataEncoding::Text, value) => Payload::Text(value.to_string()),
+        #[cfg(feature = "binary-payload")]
+        (DataEncoding::Hex, Value::String(text)) => Payload::Binary(
+            hex::decode(text).map_err(|err| invalid(&mapping.data, &err.to_string()))?,
+        ),
+        (DataEncoding::Hex, _) => {
+            return Err(invalid(&mapping.data, "expected binary data in HEX format"))
+        }
+    };
+
+    let timestamp = number(&mapping.timestamp)?;
+    let block = Block::with_nonce(
+        number(&mapping.id)?,
+        &text(&mapping.previous_hash)?,
+        i64::try_from(timestamp).map_err(|err| invalid(&mapping.timestamp, &err.to_string()))?,
+        number(&mapping.nonce)?,
+        data,
+    );
+    if !mapping.hash.is_empty() && text(&mapping.hash)? != block.hash {
+        return Err(ImportError::HashMismatch { id: block.id });
+    }
+    Ok(block)
+}
+
+/// Maps the externally produced blocks and adds them to the blockchain in order, refusing the
+/// ones not following the local consensus rules
+pub fn import_blocks(
+    tetherion: &mut Tetherion<Payload>,
+    external: &[Value],
+    mapping: &ImportConfig,
+) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+    for (position, external) in external.iter().enumerate() {
+        let result = map_block(external, mapping)
+            .and_then(|block| tetherion.add_block(block).map_err(ImportError::Rejected));
+        match result {
+            Ok(()) => summary.accepted += 1,
+            Err(err) => summary.rejected.push((position, err)),
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_mapped_blocks() {
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        let genesis = tetherion.blocks()[0].clone();
+        let block = Block::new(1, &genesis.hash, Payload::Binary(vec![0xab, 0xcd]), 1);
+        let header = block.header();
+        let mapping = ImportConfig {
+            id: String::from("/height"),
+            previous_hash: String::from("/header/prev"),
+            timestamp: String::from("/header/time"),
+            nonce: String::from("/header/nonce"),
+            data: String::from("/raw"),
+            data_encoding: DataEncoding::Hex,
+            hash: String::new(),
+        };
+
+        let content = format!(
+            "{}\n{}\n{}\n",
+            serde_json::json!({
+                "height": 1,
+                "header": {
+                    "prev": header.previous_hash,
+                    "time": header.timestamp,
+                    "nonce": header.nonce.to_string(),
+                },
+                "raw": "abcd",
+            }),
+            serde_json::json!({ "height": 2, "raw": "00" }),
+            serde_json::json!({
+                "height": 2,
+                "header": { "prev": genesis.hash, "time": 0, "nonce": 0 },
+                "raw": "00",
+            }),
+        );
+        let external = parse_blocks(&content).unwrap();
+        let summary = import_blocks(&mut tetherion, &external, &mapping);
+
+        assert_eq!(summary.accepted, 1);
+        assert_eq!(tetherion.blocks()[1].hash, header.hash);
+        assert_eq!(
+            summary.rejected[0],
+            (1, ImportError::MissingField(String::from("/header/time")))
+        );
+        assert!(matches!(
+            summary.rejected[1],
+            (
+                2,
+                ImportError::Rejected(InvalidBlockError::InvalidPreviousHash { id: 2, .. })
+            )
+        ));
+    }
+}
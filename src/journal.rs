@@ -0,0 +1,143 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::tetherion::InvalidBlockError,
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{File, OpenOptions},
+        io::{self, BufRead, BufReader, Write},
+        path::Path,
+    },
+};
+
+/// An event the node went through, recorded so that it can be fed back through a node later
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalEvent {
+    /// The node started recording, with the given peer ID
+    Start { peer_id: String },
+
+    /// A message gossiped by a peer, as received
+    Message {
+        /// The peer the message was received from
+        source: String,
+
+        /// The topics the message was published to
+        topics: Vec<String>,
+
+        /// The message, in HEX format, encoded in the wire format
+        data: String,
+    },
+
+    /// A command typed in the terminal, run from a file or executed through the RPC server
+    Command { line: String },
+
+    /// A block sent by a peer got rejected, for the given reason
+    Rejected {
+        /// The peer the block was received from
+        peer: String,
+
+        error: InvalidBlockError,
+    },
+}
+
+/// An event recorded in the journal
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// The time the event happened at, in milliseconds since the epoch
+    pub at: i64,
+
+    /// The event
+    pub event: JournalEvent,
+}
+
+/// Records the events the node goes through to a file, one JSON entry per line, in the order they
+/// happened in
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Opens the journal file at the given path, creating it if needed; the recorded events are
+    /// appended to the ones already in the file
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    /// Appends the event, timestamped with the current time, to the journal
+    pub fn record(&mut self, event: JournalEvent) -> io::Result<()> {
+        let entry = JournalEntry {
+            at: chrono::Utc::now().timestamp_millis(),
+            event,
+        };
+        let mut line = serde_json::to_vec(&entry).expect("can encode journal entry");
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+/// Reads the events recorded in the journal file at the given path, in the order they happened in
+pub fn read(path: &Path) -> io::Result<Vec<JournalEntry>> {
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|err| {
+            let reason = format!("line {}: {}", index + 1, err);
+            io::Error::new(io::ErrorKind::InvalidData, reason)
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::fs};
+
+    #[test]
+    fn record_and_read() {
+        let path = std::env::temp_dir().join(format!("tetherion-journal-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let events = vec![
+            JournalEvent::Start {
+                peer_id: String::from("12D3KooWAbCdEf"),
+            },
+            JournalEvent::Message {
+                source: String::from("12D3KooWGhIjKl"),
+                topics: vec![String::from("blocks")],
+                data: hex::encode(b"{}"),
+            },
+            JournalEvent::Command {
+                line: String::from("ls c"),
+            },
+            JournalEvent::Rejected {
+                peer: String::from("12D3KooWGhIjKl"),
+                error: InvalidBlockError::invalid_previous_hash(3, "00ab", "00cd"),
+            },
+        ];
+        let mut journal = Journal::create(&path).unwrap();
+        for event in &events[..2] {
+            journal.record(event.clone()).unwrap();
+        }
+        // Reopening the journal appends to it
+        Journal::create(&path)
+            .unwrap()
+            .record(events[2].clone())
+            .unwrap();
+        journal.record(events[3].clone()).unwrap();
+
+        let entries = read(&path).unwrap();
+        let read_events: Vec<_> = entries.iter().map(|entry| entry.event.clone()).collect();
+        assert_eq!(read_events, events);
+        assert!(entries.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+        fs::write(&path, "{\"at\": 0}\n").unwrap();
+        assert_eq!(read(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
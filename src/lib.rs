@@ -0,0 +1,79 @@
+/// Copyright (c) 2022 Tetherion
+pub mod alerts;
+pub mod anchor;
+pub mod archive;
+#[cfg(feature = "node")]
+pub mod bans;
+pub mod block;
+pub mod cache;
+#[cfg(feature = "node")]
+pub mod chains;
+pub mod codec;
+pub mod config;
+pub mod consensus;
+#[cfg(feature = "contracts")]
+pub mod contracts;
+pub mod datadir;
+#[cfg(feature = "node")]
+pub mod dev;
+pub mod divergence;
+pub mod evidence;
+pub mod export;
+pub mod fees;
+pub mod finality;
+pub mod gossip;
+#[cfg(feature = "node")]
+pub mod handle;
+pub mod import;
+pub mod journal;
+pub mod light;
+pub mod logfmt;
+pub mod mempool;
+pub mod merkle;
+#[cfg(feature = "node")]
+pub mod miner;
+#[cfg(feature = "node")]
+pub mod node;
+#[cfg(feature = "node")]
+pub mod p2p;
+pub mod payload;
+#[cfg(feature = "node")]
+pub mod peers;
+#[cfg(feature = "node")]
+pub mod pex;
+#[cfg(feature = "node")]
+pub mod printout;
+pub mod priority;
+pub mod propagation;
+#[cfg(feature = "node")]
+pub mod pubsub;
+pub mod receipts;
+pub mod registry;
+#[cfg(feature = "node")]
+pub mod rpc;
+pub mod script;
+pub mod search;
+pub mod secret;
+#[cfg(feature = "node")]
+pub mod serving;
+pub mod sim;
+#[cfg(feature = "node")]
+pub mod socks;
+pub mod state;
+pub mod store;
+pub mod sync;
+pub mod tetherion;
+pub mod throttle;
+#[cfg(feature = "node")]
+pub mod tls;
+pub mod trace;
+#[cfg(feature = "node")]
+pub mod transport;
+#[cfg(feature = "node")]
+pub mod tui;
+pub mod validation;
+pub mod version;
+#[cfg(feature = "node")]
+pub mod webhook;
+pub mod weight;
+pub mod work;
@@ -0,0 +1,233 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::BlockHeader,
+        consensus::{ConsensusEngine, Validators},
+        merkle::MerkleProof,
+        tetherion::InvalidBlockError,
+    },
+    std::sync::Arc,
+};
+
+/// The blockchain as seen by a light client: only block headers are stored and validated,
+/// i.e. consensus rules and linkage, while the data stored in the blocks is left out
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    /// Headers of the blocks in the blockchain, empty until synced with a peer
+    headers: Vec<BlockHeader>,
+
+    /// The rules deciding which blocks are allowed to extend the blockchain
+    consensus: Arc<dyn ConsensusEngine>,
+
+    /// The hash of the configured genesis block, which the headers have to start with
+    genesis: String,
+}
+
+impl HeaderChain {
+    pub fn new(consensus: Arc<dyn ConsensusEngine>, genesis: String) -> Self {
+        Self {
+            headers: Vec::new(),
+            consensus,
+            genesis,
+        }
+    }
+
+    /// Gets all the headers of the blockchain
+    pub fn headers(&self) -> &Vec<BlockHeader> {
+        &self.headers
+    }
+
+    /// Checks if the proof shows the transaction being stored in the known block with the given
+    /// hash
+    pub fn verify_proof(&self, block_hash: &str, proof: &MerkleProof) -> bool {
+        self.headers
+            .iter()
+            .find(|header| header.hash == block_hash)
+            .is_some_and(|header| proof.verify(&header.merkle_root))
+    }
+
+    /// Adds the header of a new block to the blockchain
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<(), InvalidBlockError> {
+        match self.headers.last() {
+            Some(previous) => self.is_valid_header(previous, &header)?,
+            None => self.is_valid_genesis(&header)?,
+        }
+
+        self.headers.push(header);
+        Ok(())
+    }
+
+    /// Replaces the local headers with the remote ones in case the remote ones are valid, start
+    /// with the configured genesis block and took more work to produce, the local ones being
+    /// kept on a tie. Returns whether they got replaced.
+    pub fn sync(&mut self, remote: Vec<BlockHeader>) -> bool {
+        if let Err(err) = self.is_valid(&remote) {
+            log::debug!("Remote headers are invalid: {}", err);
+            return false;
+        }
+
+        let better = !remote.is_empty() && self.work(&remote) > self.work(&self.headers);
+        if better {
+            self.headers = remote;
+        }
+        better
+    }
+
+    /// Gets the work the headers took to produce, added up: the expected number of hashes
+    /// computed to mine each block, or 1 per block in case blocks are not mined
+    pub fn work(&self, headers: &[BlockHeader]) -> u128 {
+        headers.iter().fold(0, |total: u128, header| {
+            let work = match self.consensus.proof_of_work() {
+                Some(pow) => 256u128.saturating_pow(pow.difficulty_at(header.id) as u32),
+                None => 1,
+            };
+            total.saturating_add(work)
+        })
+    }
+
+    /// Checks if the headers form a valid blockchain, starting with the configured genesis block
+    pub fn is_valid(&self, headers: &[BlockHeader]) -> Result<(), InvalidBlockError> {
+        if let Some(genesis) = headers.first() {
+            self.is_valid_genesis(genesis)?;
+        }
+
+        for pair in headers.windows(2) {
+            self.is_valid_header(&pair[0], &pair[1])?;
+        }
+        Ok(())
+    }
+
+    /// Checks if the header is the configured genesis block's header
+    fn is_valid_genesis(&self, header: &BlockHeader) -> Result<(), InvalidBlockError> {
+        if header.id != 0 {
+            return Err(InvalidBlockError::MissingPreviousBlock {
+                id: header.id,
+                previous_hash: header.previous_hash.clone(),
+            });
+        } else if !header.has_valid_hash() {
+            return Err(InvalidBlockError::invalid_header_hash(header));
+        } else if header.hash != self.genesis {
+            return Err(InvalidBlockError::GenesisMismatch {
+                expected: self.genesis.clone(),
+                actual: header.hash.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks if the header is valid regarding the previous block's header
+    fn is_valid_header(
+        &self,
+        previous: &BlockHeader,
+        header: &BlockHeader,
+    ) -> Result<(), InvalidBlockError> {
+        if header.id != previous.id + 1 {
+            return Err(InvalidBlockError::InvalidBlockId {
+                id: header.id,
+                previous_id: previous.id,
+            });
+        } else if header.previous_hash != previous.hash {
+            return Err(InvalidBlockError::invalid_previous_hash(
+                header.id,
+                &previous.hash,
+                &header.previous_hash,
+            ));
+        } else if !header.has_valid_hash() {
+            return Err(InvalidBlockError::invalid_header_hash(header));
+        }
+        self.consensus
+            .verify(previous, header, &Validators::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{consensus::ProofOfWork, tetherion::Tetherion},
+    };
+
+    const DIFFICULTY: usize = 1;
+
+    fn headers(tetherion: &Tetherion<String>) -> Vec<BlockHeader> {
+        tetherion
+            .blocks()
+            .iter()
+            .map(|block| block.header())
+            .collect()
+    }
+
+    #[test]
+    fn add_header() {
+        let tetherion = Tetherion::<String>::new(String::from("genesis"), DIFFICULTY);
+        let genesis = tetherion.blocks()[0].header();
+        let mut chain =
+            HeaderChain::new(Arc::new(ProofOfWork::new(DIFFICULTY)), genesis.hash.clone());
+
+        let mut orphan = genesis.clone();
+        orphan.id = 1;
+        assert!(matches!(
+            chain.add_header(orphan),
+            Err(InvalidBlockError::MissingPreviousBlock { id: 1, .. })
+        ));
+
+        let other = Tetherion::<String>::new(String::from("other"), DIFFICULTY);
+        assert!(matches!(
+            chain.add_header(other.blocks()[0].header()),
+            Err(InvalidBlockError::GenesisMismatch { .. })
+        ));
+
+        chain.add_header(genesis.clone()).unwrap();
+        assert!(matches!(
+            chain.add_header(genesis),
+            Err(InvalidBlockError::InvalidBlockId { .. })
+        ));
+    }
+
+    #[test]
+    fn sync() {
+        let mut tetherion = Tetherion::<String>::new(String::from("genesis"), DIFFICULTY);
+        let genesis_hash = tetherion.blocks()[0].hash.clone();
+        let mut chain = HeaderChain::new(Arc::new(ProofOfWork::new(DIFFICULTY)), genesis_hash);
+        // Headers starting with another genesis block are never taken, even by an empty chain
+        let other = Tetherion::<String>::new(String::from("other"), DIFFICULTY);
+        assert!(!chain.sync(headers(&other)));
+        assert!(chain.sync(headers(&tetherion)));
+
+        let genesis = tetherion.blocks()[0].clone();
+        tetherion
+            .add_block(crate::block::Block::new(
+                1,
+                &genesis.hash,
+                String::from("data"),
+                DIFFICULTY,
+            ))
+            .unwrap();
+        assert!(chain.sync(headers(&tetherion)));
+        assert_eq!(chain.headers().len(), 2);
+        assert_eq!(chain.work(chain.headers()), 2 * 256);
+        // Headers that took as much work are not taken over the local ones
+        assert!(!chain.sync(headers(&tetherion)));
+
+        let mut tampered = headers(&tetherion);
+        tampered[1].nonce += 1;
+        tampered.push(tampered[1].clone());
+        assert!(!chain.sync(tampered));
+        assert!(!chain.sync(vec![genesis.header()]));
+    }
+
+    #[test]
+    fn verify_proof() {
+        let tetherion = Tetherion::<String>::new(String::from("genesis"), DIFFICULTY);
+        let genesis = &tetherion.blocks()[0];
+        let mut chain =
+            HeaderChain::new(Arc::new(ProofOfWork::new(DIFFICULTY)), genesis.hash.clone());
+        chain.sync(headers(&tetherion));
+
+        let tx_id = crate::merkle::leaf_hash(b"genesis");
+        let proof = genesis.prove(&tx_id).unwrap();
+        assert!(chain.verify_proof(&genesis.hash, &proof));
+        assert!(!chain.verify_proof("unknown", &proof));
+        assert!(genesis.prove(&crate::merkle::leaf_hash(b"other")).is_none());
+    }
+}
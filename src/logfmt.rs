@@ -0,0 +1,70 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::config::{JsonStyle, LoggingConfig},
+    serde::Serialize,
+    std::{
+        fmt,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+/// The number of characters of a payload shown in the log unless configured otherwise
+pub const DEFAULT_PAYLOAD_PREVIEW: usize = 120;
+
+/// The number of characters of a payload shown in the log, all of them if 0
+static PAYLOAD_PREVIEW: AtomicUsize = AtomicUsize::new(DEFAULT_PAYLOAD_PREVIEW);
+
+/// Whether the JSON printed by the commands is laid out on a single line
+static COMPACT_JSON: AtomicBool = AtomicBool::new(false);
+
+/// Applies the logging settings to the whole process, the same way the log level is
+pub fn configure(config: &LoggingConfig) {
+    PAYLOAD_PREVIEW.store(config.payload_preview, Ordering::Relaxed);
+    COMPACT_JSON.store(config.json == JsonStyle::Compact, Ordering::Relaxed);
+}
+
+/// Shows the beginning of a value in the log, cut off past the configured number of characters,
+/// so that a large payload does not flood the log; the value is only formatted if logged
+pub struct Preview<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Preview<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = self.0.to_string();
+        f.write_str(&truncate(&text, PAYLOAD_PREVIEW.load(Ordering::Relaxed)))
+    }
+}
+
+/// Shortens the text to the given number of characters, all of them if 0, telling how long the
+/// whole text is
+fn truncate(text: &str, width: usize) -> String {
+    match text.char_indices().nth(width) {
+        Some((end, _)) if width > 0 => format!("{}… ({} bytes)", &text[..end], text.len()),
+        _ => text.to_owned(),
+    }
+}
+
+/// Encodes the value in JSON for the commands to print, pretty or compact as configured
+pub fn to_json<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<String> {
+    if COMPACT_JSON.load(Ordering::Relaxed) {
+        serde_json::to_string(value)
+    } else {
+        serde_json::to_string_pretty(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_previews() {
+        assert_eq!(
+            truncate(r#"{"text":"data"}"#, 9),
+            r#"{"text":"… (15 bytes)"#
+        );
+        assert_eq!(truncate(r#"{"text":"data"}"#, 15), r#"{"text":"data"}"#);
+        assert_eq!(truncate(r#"{"text":"data"}"#, 0), r#"{"text":"data"}"#);
+        // Characters are never split
+        assert_eq!(truncate("ééé", 2), "éé… (6 bytes)");
+    }
+}
@@ -1,125 +1,503 @@
 /// Copyright (c) 2022 Tetherion
-use libp2p::{
-    core::upgrade,
-    futures::StreamExt,
-    mplex,
-    noise::{Keypair, NoiseConfig, X25519Spec},
-    swarm::{Swarm, SwarmBuilder},
-    tcp::TokioTcpConfig,
-    Transport,
-};
-use log::{error, info};
-use std::time::Duration;
-use tokio::{
-    io::{stdin, AsyncBufReadExt, BufReader},
-    select, spawn,
-    sync::mpsc,
-    time::sleep,
+use {
+    clap::Parser,
+    std::{iter, path::Path, sync::Arc, time::Duration},
+    tetherion::{
+        chains::ChainManager,
+        config::{self, ApiScope},
+        consensus,
+        datadir::{self, DataDir},
+        dev, export,
+        handle::NodeHandle,
+        import, journal, logfmt, node,
+        payload::Payload,
+        registry::PayloadRegistry,
+        sim,
+        state::State,
+        store,
+        tetherion::Tetherion,
+        tui,
+    },
 };
 
-mod block;
-mod p2p;
-mod tetherion;
-
-#[tokio::main]
-async fn main() {
-    pretty_env_logger::init();
-
-    info!("Peer Id: {}", p2p::PEER_ID.clone());
-    let (response_sender, mut response_rcv) = mpsc::unbounded_channel();
-    let (init_sender, mut init_rcv) = mpsc::unbounded_channel();
-
-    let auth_keys = Keypair::<X25519Spec>::new()
-        .into_authentic(&p2p::KEYS)
-        .expect("can create auth keys");
-
-    let transp = TokioTcpConfig::new()
-        .upgrade(upgrade::Version::V1)
-        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
-        .multiplex(mplex::MplexConfig::new())
-        .boxed();
-
-    let behaviour = p2p::TetherionBehaviour::new(
-        tetherion::Tetherion::<String>::new(String::from("genesis"), 2),
-        response_sender,
-        init_sender.clone(),
-    )
-    .await;
-
-    let mut swarm = SwarmBuilder::new(transp, behaviour, *p2p::PEER_ID)
-        .executor(Box::new(|fut| {
-            spawn(fut);
-        }))
-        .build();
-
-    let mut stdin = BufReader::new(stdin()).lines();
-
-    Swarm::listen_on(
-        &mut swarm,
-        "/ip4/0.0.0.0/tcp/0"
-            .parse()
-            .expect("can get a local socket"),
-    )
-    .expect("swarm can be started");
-
-    spawn(async move {
-        sleep(Duration::from_secs(1)).await;
-        info!("sending init event");
-        init_sender.send(true).expect("can send init event");
+mod cli;
+
+/// Creates the local blockchain of the chain with the given ID, starting from the configured
+/// checkpoint if its trust has been acknowledged, or a fresh instantly sealed one in dev mode
+fn create_tetherion(
+    cli: &cli::Cli,
+    config: &config::Config,
+    chain_id: &str,
+) -> Result<Tetherion<Payload>, String> {
+    const DIFFICULTY: usize = 2;
+
+    // Payloads signed for another chain are rejected
+    let mut registry = PayloadRegistry::default();
+    registry.set_chain_id(chain_id);
+    let registry = Arc::new(registry);
+    let genesis = |difficulty| {
+        let data = Payload::Text(String::from("genesis"));
+        Tetherion::new(data, difficulty)
+            .with_initial_state(State::new(registry.clone()))
+            .map_err(|err| err.to_string())
+    };
+
+    if cli.dev {
+        return Ok(genesis(0)?.with_consensus(Arc::new(consensus::InstantSeal)));
+    }
+
+    if cli.light && config.consensus.engine == config::Engine::Pos {
+        return Err(String::from(
+            "Light clients cannot follow the pos engine, which needs the balances",
+        ));
+    }
+
+    let tetherion = match (&config.checkpoint, cli.trust_checkpoint) {
+        (None, false) => genesis(DIFFICULTY),
+        (None, true) => Err(String::from(
+            "--trust-checkpoint requires a [checkpoint] configuration",
+        )),
+        (Some(_), false) => Err(String::from(
+            "A checkpoint is configured, pass --trust-checkpoint to skip validating the blocks preceding it",
+        )),
+        (Some(checkpoint), true) => {
+            let block = checkpoint.load_snapshot().map_err(|err| err.to_string())?;
+            let state = checkpoint
+                .load_state(registry.clone())
+                .map_err(|err| err.to_string())?;
+            Tetherion::from_checkpoint(block, DIFFICULTY, state).map_err(|err| err.to_string())
+        }
+    }?;
+    Ok(tetherion.with_consensus(config.consensus.engine(DIFFICULTY)))
+}
+
+/// Opens the directory of the chain with the given ID in the data directory, if any. Nothing is
+/// kept on disk in dev mode and by light clients.
+fn open_data_dir(
+    cli: &cli::Cli,
+    config: &config::Config,
+    chain_id: &str,
+) -> Result<Option<DataDir>, String> {
+    let root = match (&cli.data_dir, &config.storage.path) {
+        _ if cli.dev || cli.light => return Ok(None),
+        (Some(Some(path)), _) | (None, Some(path)) => datadir::expand_home(path),
+        (Some(None), _) => datadir::default_root().ok_or_else(|| {
+            String::from("Cannot locate the home directory, pass --data-dir <PATH>")
+        })?,
+        (None, None) => return Ok(None),
+    };
+    DataDir::open(&root, chain_id)
+        .map(Some)
+        .map_err(|err| format!("Cannot open {}: {}", root.display(), err))
+}
+
+/// Opens the block store in the chain's data directory, if any, and loads the stored blocks on
+/// top of the given blockchain
+fn load_store(
+    config: &config::Config,
+    dir: Option<&DataDir>,
+    tetherion: Tetherion<Payload>,
+) -> Result<(Tetherion<Payload>, Option<store::BlockStore>), String> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return Ok((tetherion, None)),
+    };
+    let path = &dir.blocks();
+    let error = |err: std::io::Error| format!("Cannot load {}: {}", path.display(), err);
+    let store = store::BlockStore::open(path)
+        .map_err(error)?
+        .with_format(config.storage.format);
+    let store = match config.storage.archive_depth {
+        Some(depth) => store.with_archiving(depth, config.storage.segment_size),
+        None => store,
+    };
+    let tetherion = store.load(tetherion).map_err(error)?;
+    Ok((tetherion, Some(store)))
+}
+
+/// Loads the stored blockchain and exports the metadata of its blocks to the file at the given
+/// path, returning the number of exported blocks
+fn export_chain(
+    cli: &cli::Cli,
+    config: &config::Config,
+    format: export::ExportFormat,
+    path: &Path,
+) -> Result<usize, String> {
+    let dir = open_data_dir(cli, config, &config.chain.id)?;
+    let (tetherion, store) = create_tetherion(cli, config, &config.chain.id)
+        .and_then(|tetherion| load_store(config, dir.as_ref(), tetherion))?;
+    if store.is_none() {
+        return Err(String::from(
+            "Exporting the blockchain requires a [storage] path or --data-dir",
+        ));
+    }
+    export::export_chain(&tetherion, format, path)
+        .map_err(|err| format!("Cannot write {}: {}", path.display(), err))
+}
+
+/// Imports the externally produced blocks in the file at the given path into the stored
+/// blockchain, returning the outcome
+fn import_blocks(
+    cli: &cli::Cli,
+    config: &config::Config,
+    path: &Path,
+) -> Result<import::ImportSummary, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    let external = import::parse_blocks(&content).map_err(|err| err.to_string())?;
+
+    let dir = open_data_dir(cli, config, &config.chain.id)?;
+    let (mut tetherion, store) = create_tetherion(cli, config, &config.chain.id)
+        .and_then(|tetherion| load_store(config, dir.as_ref(), tetherion))?;
+    let store = store
+        .ok_or_else(|| String::from("Importing blocks requires a [storage] path or --data-dir"))?;
+    let summary = import::import_blocks(&mut tetherion, &external, &config.import);
+    store
+        .save(&tetherion)
+        .map_err(|err| format!("Cannot store the blockchain: {}", err))?;
+    Ok(summary)
+}
+
+/// Writes a new Ed25519 keypair, in HEX format, to the file at the given path
+fn generate_keys(path: &Path) -> std::io::Result<String> {
+    let keys = libp2p::identity::ed25519::Keypair::generate();
+    std::fs::write(path, hex::encode(keys.encode()))?;
+    Ok(hex::encode(keys.public().encode()))
+}
+
+/// Runs a node for the configured chain and one for each of the other configured chains, routing
+/// the commands read from stdin to them. The other chains' nodes get keys of their own, and neither
+/// serve RPC, notify the webhooks nor record a journal. The chains configured to be anchored into
+/// another one get their tips periodically anchored.
+fn run_chains(
+    cli: &cli::Cli,
+    config: &config::Config,
+    runtime: &tokio::runtime::Runtime,
+    tetherion: Tetherion<Payload>,
+    node_config: node::NodeConfig,
+) -> Result<(), String> {
+    if cli.tui {
+        return Err(String::from(
+            "The dashboard shows a single chain, remove [[chains]]",
+        ));
+    }
+
+    let mut manager = ChainManager::default();
+    runtime
+        .block_on(manager.start(&config.chain.id, tetherion, node_config.clone()))
+        .map_err(|err| err.to_string())?;
+    for chain in &config.chains {
+        let dir = open_data_dir(cli, config, &chain.id)?;
+        let (tetherion, store) = create_tetherion(cli, config, &chain.id)
+            .and_then(|tetherion| load_store(config, dir.as_ref(), tetherion))?;
+        // The listen addresses are the first chain's, the other ones listen on ports chosen by
+        // the system
+        let node_config = node::NodeConfig {
+            listen_addrs: node::NodeConfig::default().listen_addrs,
+            external_addrs: Vec::new(),
+            bootstrap: Vec::new(),
+            keys: None,
+            store,
+            bans: dir.as_ref().map(DataDir::bans),
+            rpc: config::RpcConfig::default(),
+            webhooks: Vec::new(),
+            config_file: None,
+            exec: Vec::new(),
+            journal: None,
+            ..node_config.clone()
+        };
+        runtime
+            .block_on(manager.start(&chain.id, tetherion, node_config))
+            .map_err(|err| err.to_string())?;
+    }
+    let _guard = runtime.enter();
+    for chain in iter::once(&config.chain).chain(&config.chains) {
+        if let Some(target) = &chain.anchor_into {
+            manager
+                .anchor(&chain.id, target, chain.anchor_interval())
+                .map_err(|err| err.to_string())?;
+        }
+    }
+    println!(
+        "hosting chains {}, prefix the commands with --chain <id>",
+        manager.ids().collect::<Vec<_>>().join(", ")
+    );
+    runtime.block_on(manager.run());
+    Ok(())
+}
+
+/// Initializes the global logger. The configured level takes precedence over `RUST_LOG`, every
+/// level being let through the filter so that reloading the configuration can raise it. The
+/// payload previews and the JSON layout are configured along with it.
+fn init_logger(logging: &config::LoggingConfig) {
+    let level = logging.level;
+    let mut builder = pretty_env_logger::formatted_builder();
+    match level {
+        Some(_) => builder.filter_level(log::LevelFilter::Trace),
+        None => builder.parse_filters(&std::env::var("RUST_LOG").unwrap_or_default()),
+    };
+    builder.init();
+    if let Some(level) = level {
+        log::set_max_level(level.into());
+    }
+    logfmt::configure(logging);
+}
+
+fn main() {
+    let cli = cli::Cli::parse();
+    let config_file = cli.config.as_ref().map(|path| {
+        config::ConfigFile::load(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        })
     });
+    let config = config_file
+        .as_ref()
+        .map_or_else(config::Config::default, |file| file.config.clone());
 
-    loop {
-        let evt = {
-            select! {
-                line = stdin.next_line() => Some(p2p::EventType::Input(line.expect("can get line").expect("can read line from stdin"))),
-                response = response_rcv.recv() => {
-                    Some(p2p::EventType::LocalChainResponse(response.expect("response exists")))
-                },
-                _init = init_rcv.recv() => {
-                    Some(p2p::EventType::Init)
+    match cli.command {
+        Some(cli::Command::Dev {
+            nodes,
+            difficulty,
+            interval,
+        }) => {
+            dev::init_logger();
+            dev::launch(
+                nodes,
+                difficulty,
+                Duration::from_secs(interval),
+                config.mining,
+            );
+        }
+        Some(cli::Command::Sim {
+            nodes,
+            degree,
+            blocks,
+            interval,
+            min_latency,
+            max_latency,
+            power_skew,
+            partition_at,
+            partition_for,
+            partition_share,
+            seed,
+        }) => {
+            let ms = |secs: f64| (secs * 1_000.0).round() as u64;
+            let partition = partition_at
+                .zip(partition_for)
+                .map(|(at, duration)| sim::Partition {
+                    start_ms: ms(at),
+                    duration_ms: ms(duration),
+                    share: partition_share,
+                });
+            let config = sim::SimConfig {
+                nodes,
+                degree,
+                blocks,
+                block_interval_ms: ms(interval),
+                latency_ms: (min_latency, max_latency),
+                power_skew,
+                partition,
+                seed,
+            };
+            match sim::run(config) {
+                Ok(report) if cli.output == node::Output::Json => println!(
+                    "{}",
+                    serde_json::to_string(&report).expect("can jsonify simulation report")
+                ),
+                Ok(report) => println!("{}", report),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
                 }
-                event = swarm.select_next_some() => {
-                    info!("Unhandled Swarm Event: {:?}", event);
-                    None
-                },
             }
-        };
-
-        if let Some(event) = evt {
-            match event {
-                p2p::EventType::Init => {
-                    let peers = p2p::get_peers(&swarm);
-
-                    info!("connected nodes: {}", peers.len());
-                    if !peers.is_empty() {
-                        let req = p2p::LocalChainRequest {
-                            from_peer_id: peers
-                                .iter()
-                                .last()
-                                .expect("at least one peer")
-                                .to_string(),
-                        };
-
-                        let json = serde_json::to_string(&req).expect("can jsonify request");
-                        swarm
-                            .behaviour_mut()
-                            .floodsub
-                            .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
-                    }
+        }
+        Some(cli::Command::Export {
+            target: cli::ExportTarget::Chain { format, ref path },
+        }) => match export_chain(&cli, &config, format, path) {
+            Ok(count) => println!("Exported {} blocks to {}", count, path.display()),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(cli::Command::Import { ref path }) => match import_blocks(&cli, &config, path) {
+            Ok(summary) => {
+                for (position, err) in &summary.rejected {
+                    println!("Rejected block #{}: {}", position, err);
                 }
-                p2p::EventType::LocalChainResponse(resp) => {
-                    let json = serde_json::to_string(&resp).expect("can jsonify response");
-                    swarm
-                        .behaviour_mut()
-                        .floodsub
-                        .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
+                println!(
+                    "Imported {} blocks, rejected {}",
+                    summary.accepted,
+                    summary.rejected.len()
+                );
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(cli::Command::Keygen { path }) => match generate_keys(&path) {
+            Ok(public_key) => println!("{}", public_key),
+            Err(err) => {
+                eprintln!("Cannot write {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        None | Some(cli::Command::Replay { .. }) => {
+            init_logger(&config.logging);
+            let replay = match &cli.command {
+                Some(cli::Command::Replay { path }) => match journal::read(path) {
+                    Ok(entries) if entries.is_empty() => {
+                        eprintln!("{} records no events", path.display());
+                        std::process::exit(1);
+                    }
+                    Ok(entries) => Some(entries),
+                    Err(err) => {
+                        eprintln!("Cannot read {}: {}", path.display(), err);
+                        std::process::exit(1);
+                    }
+                },
+                _ => None,
+            };
+            let advertised = !cli.listen.is_empty() || !cli.external_address.is_empty();
+            if !config.network.mode.accepts_inbound() && advertised {
+                eprintln!("An outbound-only node listens on no address");
+                std::process::exit(1);
+            }
+            let listen_addrs = match &cli.listen[..] {
+                [] => config.network.listen_addrs(),
+                given => given.to_vec(),
+            };
+            let external_addrs = match &cli.external_address[..] {
+                [] => config.network.external_addresses.clone(),
+                given => given.to_vec(),
+            };
+            if let Some(addr) = listen_addrs
+                .iter()
+                .chain(&external_addrs)
+                .find(|addr| !config::is_supported_address(addr))
+            {
+                eprintln!(
+                    "Unsupported address {}, only TCP over IPv4 or IPv6 is supported",
+                    addr
+                );
+                std::process::exit(1);
+            }
+            if let Some(path) = &cli.journal {
+                if let Err(err) = journal::Journal::create(path) {
+                    eprintln!("Cannot open {}: {}", path.display(), err);
+                    std::process::exit(1);
                 }
-                p2p::EventType::Input(line) => match line.as_str() {
-                    "ls p" => p2p::handle_print_peers(&swarm),
-                    cmd if cmd.starts_with("ls c") => p2p::handle_print_chain(&swarm),
-                    cmd if cmd.starts_with("create b") => p2p::handle_create_block(cmd, &mut swarm),
-                    _ => error!("unknown command"),
+            }
+            let dir = open_data_dir(&cli, &config, &config.chain.id).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            });
+            let (tetherion, store) = create_tetherion(&cli, &config, &config.chain.id)
+                .and_then(|tetherion| load_store(&config, dir.as_ref(), tetherion))
+                .unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                });
+            let keys = config.consensus.load_keys().unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            });
+            let exec = match &cli.exec {
+                Some(path) => match std::fs::read_to_string(path) {
+                    Ok(content) => node::read_script(&content),
+                    Err(err) => {
+                        eprintln!("Cannot read {}: {}", path.display(), err);
+                        std::process::exit(1);
+                    }
                 },
+                None => Vec::new(),
+            };
+            let node_config = node::NodeConfig {
+                listen_addrs,
+                external_addrs,
+                prefer_ip: config.network.prefer_ip,
+                bootstrap: config.network.bootstrap.clone(),
+                proxy: config.network.proxy,
+                mode: config.network.mode,
+                discovery: config.network.discovery,
+                auto_mine: cli
+                    .auto_mine
+                    .map(Duration::from_secs)
+                    .or(config.mining.auto_mine()),
+                mining: config.mining,
+                rpc: config.rpc.clone(),
+                mempool: config.mempool,
+                light: cli.light,
+                checkpoint: config.checkpoint.clone(),
+                keys,
+                finality: config.finality.clone(),
+                store,
+                bans: dir.as_ref().map(DataDir::bans),
+                storage: config.storage.clone(),
+                validation: config.validation,
+                serving: config.serving,
+                gossip: config.bandwidth.gossip(config.gossip),
+                pex: config.pex,
+                reconnect: config.reconnect,
+                bandwidth: config.bandwidth,
+                wire_format: cli.wire_format,
+                webhooks: config.webhooks.clone(),
+                logging: config.logging,
+                alerts: config.alerts,
+                confirmations: config.confirmations,
+                peers: config.peers.clone(),
+                chain_id: config.chain.id.clone(),
+                config_file,
+                output: cli.output,
+                exec,
+                dev: cli.dev,
+                journal: cli.journal.clone(),
+                ..Default::default()
+            };
+            // A replay runs the configured chain only, in the terminal, starting from the stored
+            // blockchain without changing it
+            let replaying = replay.is_some();
+            let node_config = match replay {
+                Some(replay) => node::NodeConfig {
+                    auto_mine: None,
+                    rpc: config::RpcConfig::default(),
+                    store: None,
+                    bans: None,
+                    webhooks: Vec::new(),
+                    replay,
+                    ..node_config
+                },
+                None => node_config,
+            };
+            let runtime = tokio::runtime::Runtime::new().expect("runtime should be created");
+            if !config.chains.is_empty() && !replaying {
+                if let Err(err) = run_chains(&cli, &config, &runtime, tetherion, node_config) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            if !cli.tui || replaying {
+                runtime.block_on(node::run(tetherion, node_config, None, None));
+                return;
+            }
+
+            let node = runtime
+                .block_on(NodeHandle::start(tetherion, node_config))
+                .unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                });
+            let node = if cli.read_only {
+                node.with_scope(ApiScope::Read)
+            } else {
+                node
+            };
+            if let Err(err) = tui::run(&runtime, &node) {
+                eprintln!("Cannot show the dashboard: {}", err);
+                std::process::exit(1);
             }
         }
     }
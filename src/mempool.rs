@@ -0,0 +1,883 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        config::MempoolConfig,
+        logfmt::Preview,
+        payload::Payload,
+        state::State,
+        tetherion::{BlockData, Tetherion},
+        weight,
+    },
+    std::{
+        cmp::Ordering,
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+        fmt,
+        time::Instant,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    Underpriced {
+        account: String,
+        nonce: u64,
+        fee: u64,
+        pending_fee: u64,
+    },
+    InvalidSignature {
+        account: String,
+        nonce: u64,
+    },
+    Overweight {
+        weight: u64,
+        budget: u64,
+    },
+    Full {
+        limit: usize,
+    },
+    AccountFull {
+        account: String,
+        limit: usize,
+    },
+    LightClient,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::Underpriced {
+                account,
+                nonce,
+                fee,
+                pending_fee,
+            } => write!(
+                f,
+                "Operation {} of account {} is pending with fee {}, which {} does not exceed",
+                nonce, account, pending_fee, fee
+            ),
+            MempoolError::InvalidSignature { account, nonce } => write!(
+                f,
+                "Replacement of operation {} of account {} is not signed by the account",
+                nonce, account
+            ),
+            MempoolError::Overweight { weight, budget } => write!(
+                f,
+                "Payload weighs {}, which exceeds the block weight budget of {}",
+                weight, budget
+            ),
+            MempoolError::Full { limit } => write!(
+                f,
+                "Mempool holds {} payloads already, none of which pays less per weight",
+                limit
+            ),
+            MempoolError::AccountFull { account, limit } => write!(
+                f,
+                "Account {} has {} operations waiting already, the most allowed",
+                account, limit
+            ),
+            MempoolError::LightClient => write!(f, "Light clients do not mine blocks"),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// Checks whether the payload replaces the pending one, i.e. both are token operations of the same
+/// account with the same nonce, the pending one possibly within a batch. A replacement must be
+/// signed for the chain with the given ID and pay a higher fee.
+pub fn replaces(
+    payload: &Payload,
+    pending: &Payload,
+    chain_id: &str,
+) -> Result<bool, MempoolError> {
+    match (payload, pending) {
+        (_, Payload::Batch(payloads)) => {
+            for pending in payloads {
+                if replaces(payload, pending, chain_id)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        (
+            Payload::Token {
+                nonce, fee, from, ..
+            },
+            Payload::Token {
+                nonce: pending_nonce,
+                fee: pending_fee,
+                from: pending_from,
+                ..
+            },
+        ) if from == pending_from && nonce == pending_nonce => {
+            if !payload.is_signed(chain_id) {
+                return Err(MempoolError::InvalidSignature {
+                    account: from.clone(),
+                    nonce: *nonce,
+                });
+            }
+            if fee <= pending_fee {
+                return Err(MempoolError::Underpriced {
+                    account: from.clone(),
+                    nonce: *nonce,
+                    fee: *fee,
+                    pending_fee: *pending_fee,
+                });
+            }
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Counts the token operations of the account among the payloads, batched ones included
+pub fn count_operations<'a>(payloads: impl Iterator<Item = &'a Payload>, account: &str) -> u64 {
+    payloads
+        .map(|payload| match payload {
+            Payload::Token { from, .. } if from == account => 1,
+            Payload::Batch(payloads) => count_operations(payloads.iter(), account),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Combines the payloads packed for a block into the data it stores: a single payload is stored
+/// as is, several ones as a batch
+pub fn combine(mut payloads: Vec<Payload>) -> Payload {
+    match payloads.len() {
+        1 => payloads.remove(0),
+        _ => Payload::Batch(payloads),
+    }
+}
+
+/// Gets the payloads of the blocks the previous blockchain has beyond its latest block shared with
+/// the new one, i.e. the blocks a reorg to the new blockchain disconnects, the oldest first. The
+/// payloads stored in the new blockchain as well are left out, and so are the ones no longer valid
+/// on top of it, so that the remaining ones can be queued again instead of being lost. Nothing is
+/// returned for blockchains sharing no block.
+pub fn disconnected(previous: &Tetherion<Payload>, new: &Tetherion<Payload>) -> Vec<Payload> {
+    let hashes: HashSet<_> = new.blocks().iter().map(|block| &block.hash).collect();
+    let fork = match previous
+        .blocks()
+        .iter()
+        .rposition(|block| hashes.contains(&block.hash))
+    {
+        Some(fork) => fork,
+        None => return Vec::new(),
+    };
+    let fork_id = previous.blocks()[fork].id;
+    let included: HashSet<_> = new
+        .blocks()
+        .iter()
+        .filter(|block| block.id > fork_id)
+        .flat_map(|block| block.tx_ids())
+        .collect();
+
+    let mut state = new.state().clone();
+    let height = new.height() + 1;
+    previous.blocks()[fork + 1..]
+        .iter()
+        .map(|block| block.data())
+        .filter(|payload| {
+            !payload
+                .tx_ids()
+                .iter()
+                .any(|tx_id| included.contains(tx_id))
+        })
+        .filter(|payload| match payload.apply(height, &mut state) {
+            Ok(()) => true,
+            Err(err) => {
+                log::info!(
+                    "dropping payload {} of a disconnected block: {}",
+                    Preview(payload),
+                    err
+                );
+                false
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+/// A payload waiting in the mempool
+#[derive(Debug)]
+struct Entry {
+    /// The payload waiting to be stored in a block
+    payload: Payload,
+
+    /// The time the payload entered the mempool
+    added: Instant,
+}
+
+impl Entry {
+    fn new(payload: Payload) -> Self {
+        Self {
+            payload,
+            added: Instant::now(),
+        }
+    }
+}
+
+/// Gets the account and nonce of every token operation of the payload, batched ones included
+fn operations(payload: &Payload) -> Vec<(&str, u64)> {
+    match payload {
+        Payload::Token { from, nonce, .. } => vec![(from.as_str(), *nonce)],
+        Payload::Batch(payloads) => payloads.iter().flat_map(operations).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The nonces of the token operations of an account waiting in the mempool
+#[derive(Debug, Default)]
+struct AccountNonces {
+    /// The number of waiting operations with each nonce, batched ones included
+    pending: BTreeMap<u64, usize>,
+
+    /// The nonces of the orphans
+    orphans: BTreeSet<u64>,
+}
+
+impl AccountNonces {
+    /// Counts the operations of the account, orphans included
+    fn len(&self) -> usize {
+        self.pending.values().sum::<usize>() + self.orphans.len()
+    }
+}
+
+/// The nonces of the token operations waiting in the mempool by account, which spares scanning
+/// the whole mempool to find the next nonce of an account or the operation a payload replaces
+#[derive(Debug, Default)]
+struct NonceIndex {
+    accounts: HashMap<String, AccountNonces>,
+}
+
+impl NonceIndex {
+    /// Indexes the operations of a payload entering the mempool, or of an orphan
+    fn add(&mut self, payload: &Payload, orphan: bool) {
+        for (account, nonce) in operations(payload) {
+            let nonces = self.accounts.entry(account.to_owned()).or_default();
+            if orphan {
+                nonces.orphans.insert(nonce);
+            } else {
+                *nonces.pending.entry(nonce).or_default() += 1;
+            }
+        }
+    }
+
+    /// Forgets the operations of a payload leaving the mempool, or of an orphan
+    fn remove(&mut self, payload: &Payload, orphan: bool) {
+        for (account, nonce) in operations(payload) {
+            let nonces = match self.accounts.get_mut(account) {
+                Some(nonces) => nonces,
+                None => continue,
+            };
+            if orphan {
+                nonces.orphans.remove(&nonce);
+            } else if let Some(count) = nonces.pending.get_mut(&nonce) {
+                *count -= 1;
+                if *count == 0 {
+                    nonces.pending.remove(&nonce);
+                }
+            }
+            if nonces.len() == 0 {
+                self.accounts.remove(account);
+            }
+        }
+    }
+
+    /// Checks whether an operation of the account with the nonce is waiting, as an orphan or not
+    fn contains(&self, account: &str, nonce: u64) -> bool {
+        self.accounts.get(account).is_some_and(|nonces| {
+            nonces.pending.contains_key(&nonce) || nonces.orphans.contains(&nonce)
+        })
+    }
+
+    /// Checks whether an orphan of the account with the nonce is waiting
+    fn is_orphan(&self, account: &str, nonce: u64) -> bool {
+        self.accounts
+            .get(account)
+            .is_some_and(|nonces| nonces.orphans.contains(&nonce))
+    }
+
+    /// Counts the operations of the account waiting in the mempool, leaving out the orphans
+    fn pending(&self, account: &str) -> u64 {
+        self.accounts.get(account).map_or(0, |nonces| {
+            nonces.pending.values().map(|count| *count as u64).sum()
+        })
+    }
+
+    /// Counts the operations of the account, orphans included
+    fn len(&self, account: &str) -> usize {
+        self.accounts.get(account).map_or(0, AccountNonces::len)
+    }
+
+    /// Gets the highest nonce of the orphans of the account
+    fn last_orphan(&self, account: &str) -> Option<u64> {
+        self.accounts.get(account)?.orphans.last().copied()
+    }
+
+    /// Checks whether each operation of the payload is the latest waiting one of its account, so
+    /// that no other operation depends on it
+    fn is_latest(&self, payload: &Payload) -> bool {
+        operations(payload).into_iter().all(|(account, nonce)| {
+            self.accounts
+                .get(account)
+                .and_then(|nonces| nonces.pending.keys().last())
+                .is_some_and(|last| *last == nonce)
+        })
+    }
+
+    /// Gets the accounts having orphans
+    fn with_orphans(&self) -> Vec<String> {
+        self.accounts
+            .iter()
+            .filter(|(_, nonces)| !nonces.orphans.is_empty())
+            .map(|(account, _)| account.clone())
+            .collect()
+    }
+}
+
+/// The payloads waiting to be stored in blocks, in submission order. Payloads waiting for longer
+/// than the time-to-live are dropped.
+///
+/// Token operations whose nonce is ahead of the account's next one are kept aside as orphans
+/// until the operations filling the gap arrive.
+///
+/// The mempool holds a bounded number of payloads and orphans, and of operations per account. A
+/// full mempool makes room for a payload by evicting the one paying the lowest fee per weight,
+/// provided the new one pays more, and for an orphan by evicting the oldest one.
+#[derive(Debug)]
+pub struct Mempool {
+    /// The waiting payloads, the oldest first
+    entries: VecDeque<Entry>,
+
+    /// The token operations waiting for the preceding operations of their accounts, the oldest
+    /// first
+    orphans: VecDeque<Entry>,
+
+    /// The nonces of the waiting token operations by account
+    index: NonceIndex,
+
+    /// The time-to-live and the limits of the mempool
+    config: MempoolConfig,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            orphans: VecDeque::new(),
+            index: NonceIndex::default(),
+            config,
+        }
+    }
+
+    /// Checks whether there are no waiting payloads
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Gets the waiting payloads, the oldest first, leaving out the orphans
+    pub fn iter(&self) -> impl Iterator<Item = &Payload> {
+        self.entries.iter().map(|entry| &entry.payload)
+    }
+
+    /// Gets the token operations waiting for the preceding operations of their accounts
+    pub fn orphans(&self) -> impl Iterator<Item = &Payload> {
+        self.orphans.iter().map(|entry| &entry.payload)
+    }
+
+    /// Adds the payload to the back of the mempool. A token operation with the same account and
+    /// nonce as a waiting one replaces it in place, returning the replaced payload. The replacement
+    /// expires when the replaced payload would have, so that bumping the fee does not keep an
+    /// operation waiting forever.
+    ///
+    /// The given function returns the next nonce of an account, not counting the operations in
+    /// the mempool. Operations ahead of it are kept as orphans. Replacements must be signed for
+    /// the chain with the given ID.
+    ///
+    /// An account having as many operations waiting as allowed only gets an operation in by
+    /// evicting one of its orphans with a higher nonce. A full mempool evicts a cheaper payload,
+    /// or rejects the payload.
+    pub fn push(
+        &mut self,
+        payload: Payload,
+        chain_id: &str,
+        next_nonce: impl Fn(&str) -> u64,
+    ) -> Result<Option<Payload>, MempoolError> {
+        if let Some(replaced) = self.replace(&payload, chain_id)? {
+            return Ok(Some(replaced));
+        }
+
+        for (account, nonce) in operations(&payload) {
+            if self.index.len(account) < self.config.max_per_account {
+                continue;
+            }
+            match self.index.last_orphan(account) {
+                Some(last) if last > nonce && matches!(payload, Payload::Token { .. }) => {
+                    self.evict_orphan(account, last);
+                }
+                _ => {
+                    return Err(MempoolError::AccountFull {
+                        account: account.to_owned(),
+                        limit: self.config.max_per_account,
+                    })
+                }
+            }
+        }
+
+        if let Payload::Token { nonce, from, .. } = &payload {
+            if *nonce > self.next_nonce(from, &next_nonce) {
+                log::info!(
+                    "keeping operation {} of account {} until the preceding ones arrive",
+                    nonce,
+                    from
+                );
+                if self.orphans.len() >= self.config.max_orphans {
+                    if let Some(oldest) = self.orphans.pop_front() {
+                        log::warn!("evicting orphan {}", Preview(&oldest.payload));
+                        self.index.remove(&oldest.payload, true);
+                    }
+                }
+                self.index.add(&payload, true);
+                self.orphans.push_back(Entry::new(payload));
+                return Ok(None);
+            }
+        }
+
+        if self.entries.len() >= self.config.max_entries {
+            self.make_room(&payload)?;
+        }
+        self.index.add(&payload, false);
+        self.entries.push_back(Entry::new(payload));
+        self.promote(next_nonce);
+        Ok(None)
+    }
+
+    /// Puts the payload back to the front of the mempool, so that it is taken next. The payload
+    /// was admitted before, so it is put back whatever the limits.
+    pub fn push_front(&mut self, payload: Payload) {
+        self.index.add(&payload, false);
+        self.entries.push_front(Entry::new(payload));
+    }
+
+    /// Moves the orphans whose preceding operations are not missing anymore to the back of the
+    /// mempool. The given function returns the next nonce of an account, not counting the
+    /// operations in the mempool.
+    pub fn promote(&mut self, next_nonce: impl Fn(&str) -> u64) {
+        for account in self.index.with_orphans() {
+            loop {
+                let nonce = self.next_nonce(&account, &next_nonce);
+                if !self.index.is_orphan(&account, nonce) {
+                    break;
+                }
+                let entry = match self.take_orphan(&account, nonce) {
+                    Some(entry) => entry,
+                    None => break,
+                };
+                log::info!("promoting orphan {}", Preview(&entry.payload));
+                self.index.add(&entry.payload, false);
+                self.entries.push_back(entry);
+            }
+        }
+    }
+
+    /// Takes the oldest payload that has not expired yet
+    pub fn pop(&mut self) -> Option<Payload> {
+        self.expire();
+        let entry = self.entries.pop_front()?;
+        self.index.remove(&entry.payload, false);
+        Some(entry.payload)
+    }
+
+    /// Takes the payloads that have not expired yet to be stored in the block at the given height,
+    /// on top of the given state, packed within the weight budget by fee per weight, in the order
+    /// they apply in. In case none of them apply, the oldest one is taken anyway, so that it gets
+    /// dropped by the block rejecting it instead of blocking the mempool.
+    pub fn pack(&mut self, height: u64, state: &State, budget: u64) -> Vec<Payload> {
+        self.expire();
+        let mut positions = weight::pack(self.iter(), height, state, budget);
+        if positions.is_empty() && !self.entries.is_empty() {
+            positions.push(0);
+        }
+
+        let mut entries: Vec<_> = self.entries.drain(..).map(Some).collect();
+        let packed: Vec<_> = positions
+            .into_iter()
+            .filter_map(|position| entries[position].take())
+            .map(|entry| entry.payload)
+            .collect();
+        self.entries = entries.into_iter().flatten().collect();
+        for payload in &packed {
+            self.index.remove(payload, false);
+        }
+        packed
+    }
+
+    /// Drops the payloads, orphans included, waiting for longer than the time-to-live, returning
+    /// how many were dropped
+    pub fn expire(&mut self) -> usize {
+        let ttl = self.config.ttl();
+        let mut count = 0;
+        for (entries, orphan) in [(&mut self.entries, false), (&mut self.orphans, true)] {
+            let (alive, expired): (VecDeque<_>, VecDeque<_>) = entries
+                .drain(..)
+                .partition(|entry| entry.added.elapsed() <= ttl);
+            *entries = alive;
+            for entry in expired {
+                log::warn!("dropping expired payload {}", Preview(&entry.payload));
+                self.index.remove(&entry.payload, orphan);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Replaces the waiting token operation, orphan or not, having the same account and nonce as
+    /// the payload, returning the replaced payload
+    fn replace(
+        &mut self,
+        payload: &Payload,
+        chain_id: &str,
+    ) -> Result<Option<Payload>, MempoolError> {
+        match payload {
+            Payload::Token { from, nonce, .. } if self.index.contains(from, *nonce) => {}
+            _ => return Ok(None),
+        }
+
+        for (entries, orphan) in [(&mut self.entries, false), (&mut self.orphans, true)] {
+            for entry in entries.iter_mut() {
+                if replaces(payload, &entry.payload, chain_id)? {
+                    let replaced = std::mem::replace(&mut entry.payload, payload.clone());
+                    self.index.remove(&replaced, orphan);
+                    self.index.add(payload, orphan);
+                    return Ok(Some(replaced));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Evicts the waiting payload paying the lowest fee per weight, the newest among equals, to
+    /// make room for the given one, provided the given one pays more. Only payloads no other
+    /// operation depends on and sharing no account with the given one are evicted, so that no
+    /// remaining operation waits for an evicted one.
+    fn make_room(&mut self, payload: &Payload) -> Result<(), MempoolError> {
+        let accounts: HashSet<_> = operations(payload)
+            .into_iter()
+            .map(|(account, _)| account)
+            .collect();
+        let cheapest = self
+            .entries
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, entry)| {
+                operations(&entry.payload)
+                    .iter()
+                    .all(|(account, _)| !accounts.contains(account))
+                    && self.index.is_latest(&entry.payload)
+            })
+            .min_by(|(_, a), (_, b)| weight::compare_fee_rates(&a.payload, &b.payload));
+
+        match cheapest {
+            Some((position, entry))
+                if weight::compare_fee_rates(payload, &entry.payload) == Ordering::Greater =>
+            {
+                let entry = self.entries.remove(position).expect("entry should exist");
+                log::warn!(
+                    "evicting payload {} to make room for one paying more",
+                    Preview(&entry.payload)
+                );
+                self.index.remove(&entry.payload, false);
+                Ok(())
+            }
+            _ => Err(MempoolError::Full {
+                limit: self.config.max_entries,
+            }),
+        }
+    }
+
+    /// Evicts the orphan of the account with the nonce
+    fn evict_orphan(&mut self, account: &str, nonce: u64) {
+        if let Some(entry) = self.take_orphan(account, nonce) {
+            log::warn!("evicting orphan {}", Preview(&entry.payload));
+        }
+    }
+
+    /// Takes the orphan of the account with the nonce out of the mempool
+    fn take_orphan(&mut self, account: &str, nonce: u64) -> Option<Entry> {
+        let position = self.orphans.iter().position(|entry| {
+            operations(&entry.payload)
+                .first()
+                .is_some_and(|operation| *operation == (account, nonce))
+        })?;
+        let entry = self.orphans.remove(position)?;
+        self.index.remove(&entry.payload, true);
+        Some(entry)
+    }
+
+    /// Gets the next nonce of the account, counting its operations in the mempool
+    fn next_nonce(&self, account: &str, next_nonce: impl Fn(&str) -> u64) -> u64 {
+        next_nonce(account) + self.index.pending(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            block::Block,
+            payload::{TokenOp, DEFAULT_CHAIN_ID},
+            weight::MAX_BLOCK_WEIGHT,
+        },
+        libp2p::identity::ed25519,
+        std::time::Duration,
+    };
+
+    fn transfer(amount: u64) -> TokenOp {
+        TokenOp::Transfer {
+            token: String::from("TTH"),
+            to: String::from("bob"),
+            amount,
+        }
+    }
+
+    #[test]
+    fn replace_by_fee() {
+        let keys = ed25519::Keypair::generate();
+        let mut mempool = Mempool::new(MempoolConfig::default());
+
+        let original = Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer(1), 0, 5, &keys);
+        assert_eq!(
+            mempool.push(original.clone(), DEFAULT_CHAIN_ID, |_| 0),
+            Ok(None)
+        );
+        assert_eq!(
+            mempool.push(
+                Payload::token(DEFAULT_CHAIN_ID, transfer(2), 1, &keys),
+                DEFAULT_CHAIN_ID,
+                |_| 0
+            ),
+            Ok(None)
+        );
+
+        assert!(matches!(
+            mempool.push(
+                Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer(3), 0, 5, &keys),
+                DEFAULT_CHAIN_ID,
+                |_| 0
+            ),
+            Err(MempoolError::Underpriced { pending_fee: 5, .. })
+        ));
+        let mut forged = Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer(3), 0, 9, &keys);
+        if let Payload::Token { fee, .. } = &mut forged {
+            *fee = 10;
+        }
+        assert!(matches!(
+            mempool.push(forged, DEFAULT_CHAIN_ID, |_| 0),
+            Err(MempoolError::InvalidSignature { nonce: 0, .. })
+        ));
+
+        let replacement = Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer(3), 0, 6, &keys);
+        assert_eq!(
+            mempool.push(replacement.clone(), DEFAULT_CHAIN_ID, |_| 0),
+            Ok(Some(original.clone()))
+        );
+        assert_eq!(mempool.pop(), Some(replacement));
+        assert_eq!(mempool.iter().count(), 1);
+
+        // A replacement expires along with the payload it replaces
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        mempool.push(original, DEFAULT_CHAIN_ID, |_| 0).unwrap();
+        let waited = MempoolConfig::default().ttl() + Duration::from_secs(1);
+        mempool.entries[0].added = Instant::now().checked_sub(waited).unwrap();
+        let bumped = Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer(1), 0, 7, &keys);
+        assert!(mempool
+            .push(bumped, DEFAULT_CHAIN_ID, |_| 0)
+            .unwrap()
+            .is_some());
+        assert_eq!(mempool.expire(), 1);
+    }
+
+    #[test]
+    fn promote_orphans() {
+        let keys = ed25519::Keypair::generate();
+        let address = hex::encode(keys.public().encode());
+        let mut mempool = Mempool::new(MempoolConfig::default());
+
+        let first = Payload::token(DEFAULT_CHAIN_ID, transfer(1), 1, &keys);
+        let second = Payload::token(DEFAULT_CHAIN_ID, transfer(2), 2, &keys);
+        mempool
+            .push(second.clone(), DEFAULT_CHAIN_ID, |_| 0)
+            .unwrap();
+        mempool
+            .push(first.clone(), DEFAULT_CHAIN_ID, |_| 0)
+            .unwrap();
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.orphans().count(), 2);
+
+        mempool.promote(|account| u64::from(account == address));
+        assert_eq!(mempool.iter().collect::<Vec<_>>(), vec![&first, &second]);
+        assert_eq!(mempool.orphans().count(), 0);
+    }
+
+    #[test]
+    fn bound_mempool() {
+        let keys: Vec<_> = (0..3).map(|_| ed25519::Keypair::generate()).collect();
+        let address = hex::encode(keys[1].public().encode());
+        let operation = |account: usize, nonce, fee| {
+            Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer(1), nonce, fee, &keys[account])
+        };
+        let text = |text: &str| Payload::Text(text.to_owned());
+        let mut mempool = Mempool::new(MempoolConfig {
+            max_entries: 2,
+            max_orphans: 1,
+            max_per_account: 2,
+            ..MempoolConfig::default()
+        });
+        let mut push = |payload| mempool.push(payload, DEFAULT_CHAIN_ID, |_| 0);
+
+        assert_eq!(push(operation(0, 0, 1)), Ok(None));
+        assert_eq!(push(text("free")), Ok(None));
+        assert_eq!(
+            push(text("also free")),
+            Err(MempoolError::Full { limit: 2 })
+        );
+        // The cheapest payload makes room for one paying more
+        assert_eq!(push(operation(1, 0, 5)), Ok(None));
+
+        // The oldest orphan makes room for a new one
+        assert_eq!(push(operation(1, 2, 5)), Ok(None));
+        assert_eq!(push(operation(2, 5, 5)), Ok(None));
+        assert_eq!(push(operation(1, 4, 5)), Ok(None));
+        assert!(matches!(
+            push(operation(1, 5, 5)),
+            Err(MempoolError::AccountFull { limit: 2, .. })
+        ));
+        // A full account gets the operation filling its gap in by evicting its furthest orphan,
+        // which makes the mempool evict the operation of another account
+        assert_eq!(push(operation(1, 1, 10)), Ok(None));
+
+        assert_eq!(
+            mempool.iter().collect::<Vec<_>>(),
+            vec![&operation(1, 0, 5), &operation(1, 1, 10)]
+        );
+        assert_eq!(mempool.orphans().count(), 0);
+        assert_eq!(mempool.next_nonce(&address, |_| 0), 2);
+        assert_eq!(
+            mempool.pack(1, &State::default(), MAX_BLOCK_WEIGHT).len(),
+            1
+        );
+        assert_eq!(mempool.next_nonce(&address, |_| 0), 1);
+    }
+
+    #[test]
+    fn expire_stale_payloads() {
+        let mut mempool = Mempool::new(MempoolConfig {
+            ttl: 0,
+            ..MempoolConfig::default()
+        });
+        mempool
+            .push(
+                Payload::Text(String::from("stale")),
+                DEFAULT_CHAIN_ID,
+                |_| 0,
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert_eq!(mempool.pop(), None);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn pack_payloads() {
+        let keys = ed25519::Keypair::generate();
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let text = Payload::Text(String::from("text"));
+        let overspending = Payload::token(DEFAULT_CHAIN_ID, transfer(1), 0, &keys);
+        mempool
+            .push(overspending.clone(), DEFAULT_CHAIN_ID, |_| 0)
+            .unwrap();
+        mempool.push(text.clone(), DEFAULT_CHAIN_ID, |_| 0).unwrap();
+
+        let state = State::default();
+        assert_eq!(mempool.pack(1, &state, MAX_BLOCK_WEIGHT), vec![text]);
+        // Nothing applies, so the oldest payload is taken to be rejected
+        assert_eq!(
+            mempool.pack(1, &state, MAX_BLOCK_WEIGHT),
+            vec![overspending]
+        );
+        assert!(mempool.pack(1, &state, MAX_BLOCK_WEIGHT).is_empty());
+
+        let batch = combine(vec![
+            Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer(1), 0, 5, &keys),
+            Payload::Text(String::from("batched")),
+        ]);
+        assert_eq!(
+            count_operations(
+                std::iter::once(&batch),
+                &hex::encode(keys.public().encode())
+            ),
+            1
+        );
+        assert!(matches!(
+            replaces(
+                &Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer(2), 0, 5, &keys),
+                &batch,
+                DEFAULT_CHAIN_ID
+            ),
+            Err(MempoolError::Underpriced { .. })
+        ));
+    }
+
+    #[test]
+    fn resurrect_disconnected_payloads() {
+        const DIFFICULTY: usize = 1;
+
+        let extend = |tetherion: &mut Tetherion<Payload>, payloads: Vec<Payload>| {
+            for data in payloads {
+                let latest_block = tetherion.blocks().last().unwrap();
+                let block = Block::new(latest_block.id + 1, &latest_block.hash, data, DIFFICULTY);
+                tetherion.add_block(block).unwrap();
+            }
+        };
+        let text = |text: &str| Payload::Text(text.to_owned());
+        let keys = ed25519::Keypair::generate();
+        let create = |token: &str| {
+            let op = TokenOp::Create {
+                token: token.to_owned(),
+                supply: 10,
+                mintable: false,
+            };
+            Payload::token(DEFAULT_CHAIN_ID, op, 0, &keys)
+        };
+
+        let mut common = Tetherion::new(text("genesis"), DIFFICULTY);
+        extend(&mut common, vec![text("a")]);
+        let mut previous = common.clone();
+        extend(
+            &mut previous,
+            vec![text("b"), create("TTH"), text("c"), text("d")],
+        );
+        let mut new = common.clone();
+        extend(
+            &mut new,
+            vec![text("c"), create("ABC"), text("e"), text("f"), text("g")],
+        );
+
+        assert_eq!(disconnected(&previous, &new), vec![text("b"), text("d")]);
+        // The token creations use the same nonce, so only one of them can be stored
+        assert_eq!(
+            disconnected(&new, &previous),
+            vec![text("e"), text("f"), text("g")]
+        );
+        assert!(disconnected(&previous, &previous).is_empty());
+        assert!(disconnected(&common, &new).is_empty());
+
+        let unrelated = Tetherion::new(text("other genesis"), DIFFICULTY);
+        assert!(disconnected(&previous, &unrelated).is_empty());
+    }
+}
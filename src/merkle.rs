@@ -0,0 +1,170 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::block::hash,
+    serde::{Deserialize, Serialize},
+};
+
+/// The byte the data of a leaf is prefixed with before being hashed, so that a leaf cannot pass
+/// for a parent node
+const LEAF_PREFIX: u8 = 0x00;
+
+/// The byte the children's hashes of a parent node are prefixed with before being hashed
+const NODE_PREFIX: u8 = 0x01;
+
+/// A Merkle branch proving that a leaf is included in the tree with a given root
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// The hash of the leaf whose inclusion is proven
+    pub leaf: String,
+
+    /// The sibling hashes on the path from the leaf up to the root, the levels where the node
+    /// has no sibling being left out
+    pub branch: Vec<ProofNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProofNode {
+    /// The hash of the sibling node
+    pub hash: String,
+
+    /// Whether the sibling node is the left child of the parent
+    pub left: bool,
+}
+
+impl MerkleProof {
+    /// Checks if the proof leads from the leaf up to the given root
+    pub fn verify(&self, root: &str) -> bool {
+        let computed = self.branch.iter().fold(self.leaf.clone(), |node, sibling| {
+            if sibling.left {
+                parent(&sibling.hash, &node)
+            } else {
+                parent(&node, &sibling.hash)
+            }
+        });
+        computed == root
+    }
+}
+
+/// Creates the hash, in HEX format, of a leaf of the Merkle tree from its raw data, prefixed with
+/// the leaf marker
+pub fn leaf_hash(data: &[u8]) -> String {
+    let mut prefixed = Vec::with_capacity(data.len() + 1);
+    prefixed.push(LEAF_PREFIX);
+    prefixed.extend_from_slice(data);
+    hex::encode(hash(&prefixed))
+}
+
+/// Computes the root, in HEX format, of the Merkle tree built on top of the given leaf hashes.
+///
+/// Each parent node is the hash of the node marker followed by its children's HEX hashes
+/// concatenated; a node without a sibling moves up a level as is, rather than being paired with
+/// itself, so that repeating the last leaves never yields the same root. The root of a single
+/// leaf is the leaf itself.
+pub fn root(leaves: &[String]) -> String {
+    if leaves.is_empty() {
+        return leaf_hash(&[]);
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.remove(0)
+}
+
+/// Computes the level of the tree above the given one, each pair of nodes turning into their
+/// parent and a node without a sibling moving up as is
+fn next_level(level: &[String]) -> Vec<String> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => parent(left, right),
+            [node] => node.clone(),
+            _ => unreachable!("chunks hold one or two nodes"),
+        })
+        .collect()
+}
+
+/// Creates the proof of inclusion of the leaf at the given index
+pub fn prove(leaves: &[String], index: usize) -> Option<MerkleProof> {
+    let leaf = leaves.get(index)?.clone();
+
+    let mut branch = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut index = index;
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if let Some(hash) = level.get(sibling) {
+            branch.push(ProofNode {
+                hash: hash.clone(),
+                left: sibling < index,
+            });
+        }
+
+        level = next_level(&level);
+        index /= 2;
+    }
+
+    Some(MerkleProof { leaf, branch })
+}
+
+/// Creates the hash of a parent node from its children's hashes, prefixed with the node marker
+fn parent(left: &str, right: &str) -> String {
+    let mut prefixed = Vec::with_capacity(1 + left.len() + right.len());
+    prefixed.push(NODE_PREFIX);
+    prefixed.extend_from_slice(left.as_bytes());
+    prefixed.extend_from_slice(right.as_bytes());
+    hex::encode(hash(&prefixed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_leaf_root() {
+        let leaf = leaf_hash(b"data");
+        assert_eq!(root(std::slice::from_ref(&leaf)), leaf);
+    }
+
+    #[test]
+    fn odd_leaves_root() {
+        let leaves: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|data| leaf_hash(data.as_bytes()))
+            .collect();
+
+        let expected = parent(&parent(&leaves[0], &leaves[1]), &leaves[2]);
+        assert_eq!(root(&leaves), expected);
+
+        // Repeating the last leaf, or passing a parent node off as a leaf, changes the root
+        let mut repeated = leaves.clone();
+        repeated.push(leaves[2].clone());
+        assert_ne!(root(&repeated), root(&leaves));
+        let node = parent(&leaves[0], &leaves[1]);
+        assert_ne!(
+            leaf_hash(format!("{}{}", leaves[0], leaves[1]).as_bytes()),
+            node
+        );
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        let leaves: Vec<String> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|data| leaf_hash(data.as_bytes()))
+            .collect();
+        let root = root(&leaves);
+
+        for index in 0..leaves.len() {
+            let proof = prove(&leaves, index).unwrap();
+            assert_eq!(proof.leaf, leaves[index]);
+            assert!(proof.verify(&root));
+        }
+        assert!(prove(&leaves, leaves.len()).is_none());
+
+        let mut tampered = prove(&leaves, 1).unwrap();
+        tampered.leaf = leaf_hash(b"x");
+        assert!(!tampered.verify(&root));
+    }
+}
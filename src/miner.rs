@@ -0,0 +1,574 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::Block,
+        config::MiningConfig,
+        logfmt::Preview,
+        mempool::{self, Mempool, MempoolError},
+        payload::Payload,
+        state::State,
+        tetherion::BlockData,
+        weight::MAX_BLOCK_WEIGHT,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        fs, io,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc,
+        },
+        time::Instant,
+    },
+    tokio::{sync::mpsc, task},
+};
+
+/// A block mined by the miner, tagged with the sequence number of its candidate
+pub type MinedBlock = (u64, Block<Payload>);
+
+/// What is needed to resume mining a candidate block after a restart, so that the same block is
+/// mined from where mining stopped
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CandidateSnapshot {
+    /// The ID of the block being mined
+    pub id: u64,
+
+    /// The hash of the block the candidate is mined on top of
+    pub previous_hash: String,
+
+    /// The timestamp of the candidate, part of its hash
+    pub timestamp: i64,
+
+    /// The difficulty the candidate is mined with
+    pub difficulty: usize,
+
+    /// The payloads packed into the candidate, in the order its data stores them
+    pub entries: Vec<Payload>,
+
+    /// The nonce below which every nonce was tried
+    pub nonce: u64,
+}
+
+/// The block currently being mined
+struct Candidate {
+    /// The sequence number identifying the candidate
+    seq: u64,
+
+    /// The ID of the block being mined
+    id: u64,
+
+    /// The hash of the block the candidate is mined on top of
+    previous_hash: String,
+
+    /// The payloads packed into the candidate, in the order its data stores them
+    entries: Vec<Payload>,
+
+    /// The timestamp of the candidate
+    timestamp: i64,
+
+    /// The nonce below which every nonce was tried, updated by the mining task
+    progress: Arc<AtomicU64>,
+
+    /// The nonce mining the candidate started from
+    first_nonce: u64,
+
+    /// The flag signalling the mining task to give up on the candidate
+    cancelled: Arc<AtomicBool>,
+
+    /// When mining the candidate started
+    started: Instant,
+
+    /// The difficulty the candidate is mined with
+    difficulty: usize,
+}
+
+/// Mines blocks in the background, one at a time, always on top of the latest known block.
+///
+/// Mined blocks are reported through the channel given at creation time. The candidate can be
+/// snapshotted to a file, so that a restarted node resumes mining it instead of starting over.
+pub struct Miner {
+    /// The block currently being mined
+    candidate: Option<Candidate>,
+
+    /// The data waiting to be mined, in submission order
+    queue: Mempool,
+
+    /// The sequence number of the latest candidate
+    seq: u64,
+
+    /// The channel the mined blocks are sent to
+    sender: mpsc::UnboundedSender<MinedBlock>,
+
+    /// The mining settings
+    mining: MiningConfig,
+
+    /// The hashes per second computed while mining the latest block, if any
+    hashrate: Option<f64>,
+
+    /// The file the candidate is snapshotted to, if any
+    snapshot_path: Option<PathBuf>,
+}
+
+impl Miner {
+    pub fn new(
+        sender: mpsc::UnboundedSender<MinedBlock>,
+        mining: MiningConfig,
+        queue: Mempool,
+    ) -> Self {
+        Self {
+            candidate: None,
+            queue,
+            seq: 0,
+            sender,
+            mining,
+            hashrate: None,
+            snapshot_path: None,
+        }
+    }
+
+    /// Snapshots the candidate to the file at the given path whenever mining a new one starts and
+    /// on `checkpoint`, removing the file once the candidate is mined or abandoned
+    pub fn with_snapshots(mut self, path: PathBuf) -> Self {
+        self.snapshot_path = Some(path);
+        self
+    }
+
+    /// Checks whether a block is currently being mined
+    pub fn is_mining(&self) -> bool {
+        self.candidate.is_some()
+    }
+
+    /// Gets the hashes per second computed while mining the latest block, if any. The nonces are
+    /// tried in order, so the nonce found is about the number of hashes computed.
+    pub fn hashrate(&self) -> Option<f64> {
+        self.hashrate
+    }
+
+    /// Gets the data submitted for mining that has not been mined yet
+    pub fn pending(&self) -> impl Iterator<Item = &Payload> {
+        self.candidate
+            .iter()
+            .flat_map(|candidate| candidate.entries.iter())
+            .chain(self.queue.iter())
+    }
+
+    /// Gets the token operations waiting for the preceding operations of their accounts
+    pub fn orphans(&self) -> impl Iterator<Item = &Payload> {
+        self.queue.orphans()
+    }
+
+    /// Queues the data to be mined on top of the given block, having the given state, returning
+    /// the pending data it replaces, if any. Data replacing a payload packed into the candidate
+    /// makes the miner give up on the candidate.
+    pub fn submit(
+        &mut self,
+        data: Payload,
+        tip: &Block<Payload>,
+        difficulty: usize,
+        state: &State,
+    ) -> Result<Option<Payload>, MempoolError> {
+        let mut replaced_entry = None;
+        if let Some(candidate) = &self.candidate {
+            for (index, entry) in candidate.entries.iter().enumerate() {
+                if mempool::replaces(&data, entry, state.chain_id())? {
+                    replaced_entry = Some(index);
+                    break;
+                }
+            }
+        }
+
+        let replaced = if let Some(index) = replaced_entry {
+            let mut candidate = self.candidate.take().expect("Candidate should exist!");
+            log::info!(
+                "Abandoning candidate block {}, its data got replaced",
+                candidate.id
+            );
+            let replaced = candidate.entries.remove(index);
+            self.queue.push_front(data);
+            self.abandon(candidate);
+            Some(replaced)
+        } else {
+            let pending = self
+                .candidate
+                .iter()
+                .flat_map(|candidate| &candidate.entries);
+            let next_nonce = |account: &str| {
+                state.next_nonce(account) + mempool::count_operations(pending.clone(), account)
+            };
+            let replaced = self.queue.push(data, state.chain_id(), next_nonce)?;
+            if let Some(replaced) = &replaced {
+                log::info!("replacing pending data {}", Preview(replaced));
+            }
+            replaced
+        };
+        self.resume(tip, difficulty, state);
+        Ok(replaced)
+    }
+
+    /// Makes sure the miner works on top of the given block, having the given state: the current
+    /// candidate is abandoned if it does not extend the block anymore or the difficulty changed,
+    /// and mining is restarted with the data packed anew
+    pub fn resume(&mut self, tip: &Block<Payload>, difficulty: usize, state: &State) {
+        let pending = self
+            .candidate
+            .iter()
+            .flat_map(|candidate| &candidate.entries);
+        self.queue.promote(|account| {
+            state.next_nonce(account) + mempool::count_operations(pending.clone(), account)
+        });
+
+        if let Some(candidate) = &self.candidate {
+            if candidate.id == tip.id + 1
+                && candidate.previous_hash == tip.hash
+                && candidate.difficulty == difficulty
+            {
+                return;
+            }
+
+            log::info!(
+                "Abandoning candidate block {}, restarting on top of block {}",
+                candidate.id,
+                tip.id
+            );
+            let candidate = self.candidate.take().expect("Candidate should exist!");
+            self.abandon(candidate);
+        }
+
+        let entries = self.queue.pack(tip.id + 1, state, MAX_BLOCK_WEIGHT);
+        if !entries.is_empty() {
+            self.start(entries, tip, difficulty);
+        }
+    }
+
+    /// Accepts the block mined by the mining task. Returns `None` if the block comes from an
+    /// abandoned candidate and should be discarded.
+    pub fn mined(&mut self, (seq, block): MinedBlock) -> Option<Block<Payload>> {
+        match &self.candidate {
+            Some(candidate) if candidate.seq == seq => {
+                let elapsed = candidate.started.elapsed().as_secs_f64();
+                let hashes = (block.header().nonce + 1).saturating_sub(candidate.first_nonce);
+                self.hashrate = Some(hashes as f64 / elapsed.max(f64::EPSILON));
+                self.candidate = None;
+                self.forget_snapshot();
+                Some(block)
+            }
+            _ => None,
+        }
+    }
+
+    /// Puts the data of a mined block that could not be added to the blockchain back into the
+    /// queue, so that it gets mined again
+    pub fn requeue(&mut self, data: Payload) {
+        self.queue.push_front(data);
+    }
+
+    /// Puts the data of the blocks disconnected by a reorg back into the queue, the oldest first,
+    /// ahead of the data submitted since, the candidate's included
+    pub fn resurrect(&mut self, payloads: Vec<Payload>) {
+        if let Some(candidate) = self.candidate.take() {
+            self.abandon(candidate);
+        }
+        for payload in payloads.into_iter().rev() {
+            self.queue.push_front(payload);
+        }
+    }
+
+    /// Resumes mining the candidate snapshotted before the node stopped, if any, in case it still
+    /// extends the given block, having the given state, with the same difficulty and its payloads
+    /// still apply. Otherwise, its payloads are queued again and a candidate is packed anew.
+    pub fn restore(&mut self, tip: &Block<Payload>, difficulty: usize, state: &State) {
+        let snapshot = match self.load_snapshot() {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return,
+            Err(err) => {
+                log::warn!("cannot restore the candidate block: {}", err);
+                self.forget_snapshot();
+                return;
+            }
+        };
+
+        let data = mempool::combine(snapshot.entries.clone());
+        if snapshot.id == tip.id + 1
+            && snapshot.previous_hash == tip.hash
+            && snapshot.difficulty == difficulty
+            && data.weight() <= MAX_BLOCK_WEIGHT
+            && data.apply(snapshot.id, &mut state.clone()).is_ok()
+        {
+            log::info!(
+                "Resuming candidate block {} from nonce {}",
+                snapshot.id,
+                snapshot.nonce
+            );
+            self.launch(snapshot);
+            return;
+        }
+
+        log::info!(
+            "Rebuilding candidate block {}, it does not extend block {} anymore",
+            snapshot.id,
+            tip.id
+        );
+        for entry in snapshot.entries.into_iter().rev() {
+            self.queue.push_front(entry);
+        }
+        self.forget_snapshot();
+        self.resume(tip, difficulty, state);
+    }
+
+    /// Writes the snapshot of the candidate, along with the nonces tried so far, to the file, if
+    /// any
+    pub fn checkpoint(&self) {
+        let (path, candidate) = match (&self.snapshot_path, &self.candidate) {
+            (Some(path), Some(candidate)) => (path, candidate),
+            _ => return,
+        };
+        let snapshot = CandidateSnapshot {
+            id: candidate.id,
+            previous_hash: candidate.previous_hash.clone(),
+            timestamp: candidate.timestamp,
+            difficulty: candidate.difficulty,
+            entries: candidate.entries.clone(),
+            nonce: candidate.progress.load(Ordering::Relaxed),
+        };
+        let content = serde_json::to_vec(&snapshot).expect("can encode candidate");
+        let tmp = path.with_extension("tmp");
+        if let Err(err) = fs::write(&tmp, content).and_then(|()| fs::rename(&tmp, path)) {
+            log::error!("cannot snapshot candidate block {}: {}", candidate.id, err);
+        }
+    }
+
+    /// Reads the candidate snapshotted to the file, if any
+    fn load_snapshot(&self) -> io::Result<Option<CandidateSnapshot>> {
+        let path = match &self.snapshot_path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        match fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Removes the file the candidate is snapshotted to, if any
+    fn forget_snapshot(&self) {
+        if let Some(path) = &self.snapshot_path {
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => log::error!("cannot remove {}: {}", path.display(), err),
+            }
+        }
+    }
+
+    /// Makes the mining task give up on the candidate, putting its payloads back to the front of
+    /// the queue
+    fn abandon(&mut self, candidate: Candidate) {
+        candidate.cancelled.store(true, Ordering::Relaxed);
+        for entry in candidate.entries.into_iter().rev() {
+            self.queue.push_front(entry);
+        }
+        self.forget_snapshot();
+    }
+
+    /// Starts mining a new candidate packing the payloads on top of the given block
+    fn start(&mut self, entries: Vec<Payload>, tip: &Block<Payload>, difficulty: usize) {
+        self.launch(CandidateSnapshot {
+            id: tip.id + 1,
+            previous_hash: tip.hash.clone(),
+            timestamp: chrono::Utc::now().timestamp(),
+            difficulty,
+            entries,
+            nonce: 0,
+        });
+    }
+
+    /// Spawns the mining task for the candidate, trying the nonces from the snapshotted one on
+    fn launch(&mut self, snapshot: CandidateSnapshot) {
+        self.seq += 1;
+        let data = mempool::combine(snapshot.entries.clone());
+
+        let candidate = Candidate {
+            seq: self.seq,
+            id: snapshot.id,
+            previous_hash: snapshot.previous_hash,
+            entries: snapshot.entries,
+            timestamp: snapshot.timestamp,
+            progress: Arc::new(AtomicU64::new(snapshot.nonce)),
+            first_nonce: snapshot.nonce,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            started: Instant::now(),
+            difficulty: snapshot.difficulty,
+        };
+
+        let block = Block::with_nonce(
+            candidate.id,
+            &candidate.previous_hash,
+            candidate.timestamp,
+            0,
+            data,
+        );
+        let (seq, difficulty) = (candidate.seq, candidate.difficulty);
+        let (cancelled, progress) = (candidate.cancelled.clone(), candidate.progress.clone());
+        let sender = self.sender.clone();
+        let mining = self.mining;
+        task::spawn_blocking(move || {
+            if let Some(block) = block.try_mine(difficulty, &mining, &cancelled, &progress) {
+                sender.send((seq, block)).ok();
+            }
+        });
+
+        self.candidate = Some(candidate);
+        self.checkpoint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::config::MempoolConfig, std::time::Duration};
+
+    const DIFFICULTY: usize = 1;
+
+    #[tokio::test]
+    async fn abandon_outdated_candidate() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut miner = Miner::new(
+            sender,
+            MiningConfig::default(),
+            Mempool::new(MempoolConfig::default()),
+        );
+
+        let genesis = Block::genesis(Payload::Text(String::from("genesis")), DIFFICULTY);
+        miner
+            .submit(
+                Payload::Text(String::from("data")),
+                &genesis,
+                DIFFICULTY,
+                &State::default(),
+            )
+            .unwrap();
+        let abandoned_seq = miner.candidate.as_ref().unwrap().seq;
+
+        let tip = Block::new(
+            1,
+            &genesis.hash,
+            Payload::Text(String::from("remote")),
+            DIFFICULTY,
+        );
+        miner.resume(&tip, DIFFICULTY, &State::default());
+
+        let candidate = miner.candidate.as_ref().unwrap();
+        assert_eq!(candidate.id, 2);
+        assert_eq!(candidate.previous_hash, tip.hash);
+
+        loop {
+            let mined = receiver.recv().await.unwrap();
+            if mined.0 == abandoned_seq {
+                assert!(miner.mined(mined).is_none());
+                continue;
+            }
+
+            let block = miner.mined(mined).unwrap();
+            assert_eq!(block.previous_hash, tip.hash);
+            assert_eq!(block.data(), &Payload::Text(String::from("data")));
+            break;
+        }
+        assert!(!miner.is_mining());
+    }
+
+    #[tokio::test]
+    async fn resurrect_ahead_of_pending_data() {
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let mut miner = Miner::new(
+            sender,
+            MiningConfig::default(),
+            Mempool::new(MempoolConfig::default()),
+        );
+        let text = |text: &str| Payload::Text(text.to_owned());
+
+        let genesis = Block::genesis(text("genesis"), DIFFICULTY);
+        for data in ["pending", "queued"] {
+            miner
+                .submit(text(data), &genesis, DIFFICULTY, &State::default())
+                .unwrap();
+        }
+        miner.resurrect(vec![text("a"), text("b")]);
+        assert!(!miner.is_mining());
+
+        let tip = Block::new(1, &genesis.hash, text("remote"), DIFFICULTY);
+        miner.resume(&tip, DIFFICULTY, &State::default());
+        assert_eq!(
+            miner.pending().collect::<Vec<_>>(),
+            vec![&text("a"), &text("b"), &text("pending"), &text("queued")]
+        );
+    }
+
+    #[tokio::test]
+    async fn restore_snapshotted_candidate() {
+        // Hard enough for the candidates not to be mined during the test
+        const HARD_DIFFICULTY: usize = 4;
+
+        let dir = std::env::temp_dir().join(format!("tetherion-miner-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("candidate.json");
+        let miner = || {
+            let (sender, _receiver) = mpsc::unbounded_channel();
+            let queue = Mempool::new(MempoolConfig::default());
+            Miner::new(sender, MiningConfig::default(), queue).with_snapshots(path.clone())
+        };
+        // Stops mining the candidate as a crash would, leaving its snapshot behind
+        let crash = |miner: &mut Miner| {
+            let candidate = miner.candidate.take().unwrap();
+            candidate.cancelled.store(true, Ordering::Relaxed);
+        };
+        let read =
+            || -> CandidateSnapshot { serde_json::from_slice(&fs::read(&path).unwrap()).unwrap() };
+
+        let genesis = Block::genesis(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let state = State::default();
+        let mut first = miner();
+        first
+            .submit(
+                Payload::Text(String::from("data")),
+                &genesis,
+                HARD_DIFFICULTY,
+                &state,
+            )
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        first.checkpoint();
+        crash(&mut first);
+        let snapshot = read();
+        assert_eq!(snapshot.id, 1);
+        assert!(snapshot.nonce > 0);
+
+        let mut restarted = miner();
+        restarted.restore(&genesis, HARD_DIFFICULTY, &state);
+        let candidate = restarted.candidate.as_ref().unwrap();
+        assert_eq!(candidate.timestamp, snapshot.timestamp);
+        assert_eq!(candidate.first_nonce, snapshot.nonce);
+        assert_eq!(candidate.entries, snapshot.entries);
+        crash(&mut restarted);
+
+        // The candidate does not extend the new tip, so it is rebuilt with the same data
+        let tip = Block::new(
+            1,
+            &genesis.hash,
+            Payload::Text(String::from("remote")),
+            DIFFICULTY,
+        );
+        let mut rebuilt = miner();
+        rebuilt.restore(&tip, HARD_DIFFICULTY, &state);
+        let candidate = rebuilt.candidate.as_ref().unwrap();
+        assert_eq!((candidate.id, candidate.first_nonce), (2, 0));
+        assert_eq!(candidate.entries, snapshot.entries);
+        assert_eq!(read().id, 2);
+
+        rebuilt.resurrect(Vec::new());
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1040 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        codec::Format,
+        config::{
+            AlertsConfig, ApiScope, ApiToken, BandwidthConfig, CheckpointConfig, ConfigFile,
+            ConfirmationsConfig, ConnectionMode, FinalityConfig, GossipConfig, IpPreference,
+            LoggingConfig, MempoolConfig, MiningConfig, PeersConfig, PexConfig, ReconnectConfig,
+            RpcConfig, ServingConfig, StorageConfig, ValidationConfig, WebhookConfig,
+        },
+        gossip,
+        handle::{self, NodeCommand},
+        journal::{JournalEntry, JournalEvent},
+        p2p::{self, ChainSnapshot, TetherionBehaviour},
+        payload::Payload,
+        peers::{PeerFilter, Reconnector},
+        rpc,
+        secret::Secret,
+        socks::{ProxiedStream, Socks5Transport},
+        store::BlockStore,
+        tetherion::Tetherion,
+        transport::{Restricted, Throttle, Throttled},
+        webhook,
+    },
+    clap::ValueEnum,
+    libp2p::{
+        core::{either::EitherOutput, upgrade, ConnectedPoint},
+        futures::{future, StreamExt},
+        identity::{self, ed25519},
+        mplex,
+        noise::{Keypair, NoiseConfig, X25519Spec},
+        swarm::{AddressScore, Swarm, SwarmBuilder, SwarmEvent},
+        tcp::TokioTcpConfig,
+        Multiaddr, PeerId, Transport,
+    },
+    log::{debug, error, info, warn},
+    std::{collections::VecDeque, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration},
+    tokio::{
+        io::{stdin, AsyncBufReadExt, BufReader},
+        select,
+        signal::unix::{signal, SignalKind},
+        spawn,
+        sync::{
+            mpsc::{self, error::TrySendError},
+            oneshot, watch,
+        },
+        task::JoinHandle,
+        time::{interval, interval_at, Instant, Interval, MissedTickBehavior},
+    },
+};
+
+/// The format the results of the commands read from stdin are printed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// Lines readable by humans
+    #[default]
+    Text,
+
+    /// A single JSON document per command, printed to stdout
+    Json,
+}
+
+/// Settings of a single Tetherion node
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// The addresses the node listens on for incoming connections, at the same time
+    pub listen_addrs: Vec<Multiaddr>,
+
+    /// The addresses the peers can reach the node at, advertised to them on top of the ones it
+    /// listens on
+    pub external_addrs: Vec<Multiaddr>,
+
+    /// The IP version the addresses of the discovered peers are dialed in first
+    pub prefer_ip: IpPreference,
+
+    /// The SOCKS5 proxy the connections to the peers are dialed through, with mDNS disabled,
+    /// if any
+    pub proxy: Option<SocketAddr>,
+
+    /// Addresses of the peers the node dials at startup
+    pub bootstrap: Vec<Multiaddr>,
+
+    /// The directions the node opens connections in
+    pub mode: ConnectionMode,
+
+    /// Whether the node discovers peers through mDNS and the peer exchange, rather than only
+    /// connecting to the bootstrap peers
+    pub discovery: bool,
+
+    /// The interval at which the node mines a new block on its own
+    pub auto_mine: Option<Duration>,
+
+    /// Whether the node reads commands from stdin
+    pub interactive: bool,
+
+    /// The mining settings
+    pub mining: MiningConfig,
+
+    /// The RPC server settings
+    pub rpc: RpcConfig,
+
+    /// The mempool settings
+    pub mempool: MempoolConfig,
+
+    /// Whether the node runs as a light client, keeping only block headers
+    pub light: bool,
+
+    /// The trusted checkpoint to sync from instead of the genesis block
+    pub checkpoint: Option<CheckpointConfig>,
+
+    /// The keys the node signs its blocks and payloads with; new ones are generated if not set
+    pub keys: Option<Secret<ed25519::Keypair>>,
+
+    /// The voters whose quorum marks blocks as final
+    pub finality: Option<FinalityConfig>,
+
+    /// The store the blocks are kept in on disk, if any
+    pub store: Option<BlockStore>,
+
+    /// The file the banned peers and IP addresses are kept in, if any; the bans are lost on
+    /// restart otherwise
+    pub bans: Option<PathBuf>,
+
+    /// The on-disk storage settings
+    pub storage: StorageConfig,
+
+    /// The settings of the validation of the blocks received from the peers
+    pub validation: ValidationConfig,
+
+    /// The limits on serving the local blockchain to the peers
+    pub serving: ServingConfig,
+
+    /// The settings of the gossip with the peers
+    pub gossip: GossipConfig,
+
+    /// The settings of the exchange of peer addresses with the peers
+    pub pex: PexConfig,
+
+    /// The settings of dialing again the peers the node lost
+    pub reconnect: ReconnectConfig,
+
+    /// The limits of the traffic with the peers; the gossip settings are expected to be reduced
+    /// already on a metered connection
+    pub bandwidth: BandwidthConfig,
+
+    /// The format the messages exchanged with the peers are encoded in
+    pub wire_format: Format,
+
+    /// The URLs notified of the chain events
+    pub webhooks: Vec<WebhookConfig>,
+
+    /// The logging settings
+    pub logging: LoggingConfig,
+
+    /// The settings of the alerts on the health of the node
+    pub alerts: AlertsConfig,
+
+    /// The number of blocks transactions need on top of them to be confirmed
+    pub confirmations: ConfirmationsConfig,
+
+    /// The peers the node keeps connections with
+    pub peers: PeersConfig,
+
+    /// The ID of the chain, naming the topics its messages are gossiped on
+    pub chain_id: String,
+
+    /// The configuration file the reloadable settings are read from again on SIGHUP or
+    /// `config reload`, if any
+    pub config_file: Option<ConfigFile>,
+
+    /// The format the results of the commands are printed in, unless a command asks for another
+    pub output: Output,
+
+    /// The commands run once the node has started, as if they were typed in the terminal
+    pub exec: Vec<String>,
+
+    /// Whether the node belongs to a dev network, whose difficulty can be tuned at runtime
+    pub dev: bool,
+
+    /// The other nodes of an in-process dev network, which the tuning commands are forwarded to
+    pub dev_peers: Vec<mpsc::Sender<NodeCommand>>,
+
+    /// The file the messages received from the peers and the commands are recorded to, if any
+    pub journal: Option<PathBuf>,
+
+    /// The recorded events fed back through the node, in order, instead of connecting to the
+    /// network; the node runs as usual if there are none
+    pub replay: Vec<JournalEntry>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            listen_addrs: vec!["/ip4/0.0.0.0/tcp/0"
+                .parse()
+                .expect("can get a local socket")],
+            external_addrs: Vec::new(),
+            prefer_ip: IpPreference::default(),
+            proxy: None,
+            bootstrap: Vec::new(),
+            mode: ConnectionMode::default(),
+            discovery: true,
+            auto_mine: None,
+            interactive: true,
+            mining: MiningConfig::default(),
+            rpc: RpcConfig::default(),
+            mempool: MempoolConfig::default(),
+            light: false,
+            checkpoint: None,
+            keys: None,
+            finality: None,
+            store: None,
+            bans: None,
+            storage: StorageConfig::default(),
+            validation: ValidationConfig::default(),
+            serving: ServingConfig::default(),
+            gossip: GossipConfig::default(),
+            pex: PexConfig::default(),
+            reconnect: ReconnectConfig::default(),
+            bandwidth: BandwidthConfig::default(),
+            wire_format: Format::default(),
+            webhooks: Vec::new(),
+            logging: LoggingConfig::default(),
+            alerts: AlertsConfig::default(),
+            confirmations: ConfirmationsConfig::default(),
+            peers: PeersConfig::default(),
+            chain_id: String::from(p2p::DEFAULT_CHAIN_ID),
+            config_file: None,
+            output: Output::default(),
+            exec: Vec::new(),
+            dev: false,
+            dev_peers: Vec::new(),
+            journal: None,
+            replay: Vec::new(),
+        }
+    }
+}
+
+/// The period at which a node not mining its blocks checks whether it is allowed to seal one
+const SEAL_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// The period at which the conditions of the alerts are checked
+const ALERT_CHECK_PERIOD: Duration = Duration::from_secs(10);
+
+/// The period at which the progress of mining the candidate block is snapshotted
+const CANDIDATE_CHECKPOINT_PERIOD: Duration = Duration::from_secs(30);
+
+/// The period at which the received messages whose relay delay is over are relayed
+const RELAY_CHECK_PERIOD: Duration = Duration::from_millis(20);
+
+/// The period at which the next batch of the queued bulk messages is published
+const BULK_PUBLISH_PERIOD: Duration = Duration::from_millis(10);
+
+/// The period at which the retries of dialing the lost peers are checked
+const RECONNECT_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// The period at which a node replaying a journal checks whether it is done with the previous
+/// event
+const REPLAY_CHECK_PERIOD: Duration = Duration::from_millis(10);
+
+/// The number of RPC calls that may wait for the event loop, beyond which calls are refused
+const RPC_QUEUE_SIZE: usize = 64;
+
+/// The number of commands of an embedding application that may wait for the event loop
+pub const COMMAND_QUEUE_SIZE: usize = 64;
+
+/// Runs a node on top of the given blockchain until the process is stopped.
+///
+/// The first address the node listens on is reported through `ready`, if given, or an empty
+/// address right away for an outbound-only node. The commands of an embedding application are
+/// received through `commands`, if given.
+pub async fn run(
+    tetherion: Tetherion<Payload>,
+    mut config: NodeConfig,
+    mut ready: Option<oneshot::Sender<Multiaddr>>,
+    mut commands: Option<mpsc::Receiver<NodeCommand>>,
+) {
+    let signing_keys = config
+        .keys
+        .as_ref()
+        .map_or_else(ed25519::Keypair::generate, |keys| keys.expose().clone());
+    let keys = identity::Keypair::Ed25519(signing_keys.clone());
+    let peer_id = PeerId::from(keys.public());
+
+    info!("Peer Id: {}", peer_id);
+    let (response_sender, mut response_rcv) = mpsc::channel(config.serving.max_responses);
+    let (mined_sender, mut mined_rcv) = mpsc::unbounded_channel();
+    let (validated_sender, mut validated_rcv) = mpsc::unbounded_channel();
+    let (rpc_sender, mut rpc_rcv) = mpsc::channel(RPC_QUEUE_SIZE);
+    let (script_sender, mut script_rcv) = mpsc::unbounded_channel();
+    let (hangup_sender, mut hangup_rcv) = mpsc::unbounded_channel();
+    let (tokens, token_rcv) = watch::channel(config.rpc.tokens.clone());
+
+    let auth_keys = Keypair::<X25519Spec>::new()
+        .into_authentic(&keys)
+        .expect("can create auth keys");
+
+    // Through a proxy, the connections are dialed by the proxy but still listened for directly
+    let tcp = TokioTcpConfig::new();
+    let base = match config.proxy {
+        Some(proxy) => Socks5Transport::new(proxy).or_transport(tcp).boxed(),
+        None => tcp
+            .map(|stream, _| EitherOutput::<ProxiedStream, _>::Second(stream))
+            .boxed(),
+    };
+    // The rate caps are shared by all the connections
+    let throttle = Throttle::new(&config.bandwidth);
+    let transp = Restricted::new(base, config.mode)
+        .map(move |stream, _| Throttled::new(stream, throttle.clone()))
+        .upgrade(upgrade::Version::V1)
+        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
+        .multiplex(mplex::MplexConfig::new())
+        .boxed();
+
+    let behaviour = p2p::TetherionBehaviour::new(
+        tetherion,
+        response_sender,
+        mined_sender,
+        validated_sender,
+        &config,
+        signing_keys,
+        peer_id,
+    )
+    .await;
+
+    let mut swarm = SwarmBuilder::new(transp, behaviour, peer_id)
+        .executor(Box::new(|fut| {
+            spawn(fut);
+        }))
+        .build();
+    info!("Account: {}", swarm.behaviour().address());
+
+    let mut stdin = BufReader::new(stdin()).lines();
+
+    let mut bootstrap = Reconnector::new(config.reconnect);
+    // A node replaying a journal gets the messages from the journal only
+    let mut replay: VecDeque<_> = config.replay.drain(..).map(|entry| entry.event).collect();
+    let mut replay_check = None;
+    if replay.is_empty() {
+        if config.mode.accepts_inbound() {
+            for addr in &config.listen_addrs {
+                if let Err(err) = swarm.listen_on(addr.clone()) {
+                    error!("cannot listen on {}: {}", addr, err);
+                }
+            }
+            // Identify advertises the external addresses to the peers along with the listened
+            // ones
+            for addr in &config.external_addrs {
+                swarm.add_external_address(addr.clone(), AddressScore::Infinite);
+            }
+        } else if let Some(ready) = ready.take() {
+            info!("not listening, the node only dials its peers");
+            ready.send(Multiaddr::empty()).ok();
+        }
+
+        if config.mode.dials_out() {
+            for addr in &config.bootstrap {
+                if let Err(err) = swarm.dial_addr(addr.clone()) {
+                    error!("cannot dial {}: {}", addr, err);
+                }
+                // The bootstrap peers are dialed again until the node connects to them
+                bootstrap.schedule(addr.clone(), std::time::Instant::now());
+            }
+        }
+    } else {
+        swarm.behaviour_mut().peer_filter = PeerFilter::isolated();
+    }
+
+    if let Some(addr) = config.rpc.listen {
+        spawn(rpc::serve(
+            addr,
+            config.rpc.clone(),
+            token_rcv,
+            rpc_sender,
+            swarm.behaviour().snapshots(),
+            config.confirmations.depth,
+        ));
+    }
+
+    let mut alert_sender = None;
+    if !config.webhooks.is_empty() {
+        let snapshots = swarm.behaviour().snapshots();
+        let (sender, alerts) = mpsc::unbounded_channel();
+        alert_sender = Some(sender);
+        spawn(webhook::notify(config.webhooks.clone(), snapshots, alerts));
+    }
+
+    if config.config_file.is_some() {
+        spawn(forward_hangups(hangup_sender));
+    }
+
+    // The sync the node starts with waits for a peer to subscribe to the requests, see
+    // `TetherionBehaviour::subscribed`
+    for line in &config.exec {
+        script_sender
+            .send(line.clone())
+            .expect("script receiver exists");
+    }
+    if !replay.is_empty() {
+        info!("replaying {} events", replay.len());
+        replay_check = Some(interval(REPLAY_CHECK_PERIOD));
+    }
+
+    let mut auto_mine = config.auto_mine.map(auto_mine_interval);
+    let mut watching_blocks: Option<JoinHandle<()>> = None;
+    let mut pending_truncation: Option<usize> = None;
+    let mut auto_mined: u64 = 0;
+    let consensus = swarm.behaviour().tetherion.consensus().clone();
+    info!("Consensus: {}", consensus.name());
+    let mut seal = if consensus.is_mined() {
+        None
+    } else {
+        Some(interval(SEAL_CHECK_PERIOD))
+    };
+    let mut compaction = config.store.as_ref().map(|_| {
+        let period = config.storage.compaction_interval();
+        interval_at(Instant::now() + period, period)
+    });
+    let error_interval = config.logging.error_interval();
+    let mut log_flush = interval_at(Instant::now() + error_interval, error_interval);
+    let mut alert_check = interval(ALERT_CHECK_PERIOD);
+    let mut candidate_checkpoint = match &config.store {
+        Some(_) if consensus.is_mined() => Some(interval(CANDIDATE_CHECKPOINT_PERIOD)),
+        _ => None,
+    };
+    let mut relay_check = match swarm.behaviour().relay.mode() {
+        gossip::RelayMode::Delayed => Some(interval(RELAY_CHECK_PERIOD)),
+        _ => None,
+    };
+    let mut bulk_publish = interval(BULK_PUBLISH_PERIOD);
+    bulk_publish.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut reconnect_check = (config.reconnect.enabled && config.mode.dials_out())
+        .then(|| interval(RECONNECT_CHECK_PERIOD));
+    let mut peer_exchange = (config.pex.enabled && config.discovery).then(|| {
+        let period = config.pex.interval();
+        interval_at(Instant::now() + period, period)
+    });
+
+    loop {
+        let idle = swarm.behaviour().is_idle();
+        let bulk = swarm.behaviour().queued_bulk() > 0;
+        let evt = {
+            select! {
+                line = stdin.next_line(), if config.interactive => Some(p2p::EventType::Input(line.expect("can get line").expect("can read line from stdin"), ApiScope::Admin)),
+                response = response_rcv.recv() => {
+                    Some(p2p::EventType::LocalChainResponse(Box::new(response.expect("response exists"))))
+                },
+                _tick = tick(&mut auto_mine) => {
+                    Some(p2p::EventType::AutoMine)
+                }
+                _tick = tick(&mut seal) => {
+                    Some(p2p::EventType::Seal)
+                }
+                _tick = tick(&mut compaction) => {
+                    Some(p2p::EventType::Compact)
+                }
+                _tick = log_flush.tick() => {
+                    Some(p2p::EventType::FlushLogs)
+                }
+                _tick = alert_check.tick() => {
+                    Some(p2p::EventType::CheckAlerts)
+                }
+                _tick = tick(&mut candidate_checkpoint) => {
+                    Some(p2p::EventType::CheckpointCandidate)
+                }
+                _tick = tick(&mut relay_check) => {
+                    Some(p2p::EventType::Relay)
+                }
+                _tick = bulk_publish.tick(), if bulk => {
+                    Some(p2p::EventType::PublishBulk)
+                }
+                _tick = tick(&mut reconnect_check) => {
+                    Some(p2p::EventType::Reconnect)
+                }
+                _tick = tick(&mut peer_exchange) => {
+                    Some(p2p::EventType::ExchangePeers)
+                }
+                _tick = tick(&mut replay_check), if idle => {
+                    match replay.pop_front() {
+                        Some(JournalEvent::Command { line }) => {
+                            Some(p2p::EventType::Input(line, ApiScope::Admin))
+                        }
+                        event => event.map(p2p::EventType::Replay),
+                    }
+                }
+                mined = mined_rcv.recv() => {
+                    Some(p2p::EventType::Mined(mined.expect("mined block exists")))
+                }
+                checked = validated_rcv.recv() => {
+                    Some(p2p::EventType::Validated(checked.expect("validation outcome exists")))
+                }
+                Some(call) = rpc_rcv.recv() => {
+                    Some(p2p::EventType::Rpc(call))
+                }
+                Some(line) = script_rcv.recv() => {
+                    Some(p2p::EventType::Input(line, ApiScope::Admin))
+                }
+                Some(()) = hangup_rcv.recv() => {
+                    Some(p2p::EventType::ReloadConfig)
+                }
+                Some(command) = next_command(&mut commands) => {
+                    match command {
+                        NodeCommand::Execute { line, scope } => {
+                            Some(p2p::EventType::Input(line, scope))
+                        }
+                        command => Some(p2p::EventType::Command(command)),
+                    }
+                }
+                event = swarm.select_next_some() => {
+                    match event {
+                        SwarmEvent::NewListenAddr { address, .. } => {
+                            info!("Listening on {}", address);
+                            if let Some(ready) = ready.take() {
+                                ready.send(address).ok();
+                            }
+                        }
+                        SwarmEvent::ConnectionEstablished { peer_id, .. }
+                            if !swarm.behaviour().peer_filter.allows(&peer_id) =>
+                        {
+                            info!("disconnecting from {}, not allowed by [peers]", peer_id);
+                            let _ = swarm.disconnect_peer_id(peer_id);
+                        }
+                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. }
+                            if swarm
+                                .behaviour()
+                                .ban_of(&peer_id, Some(endpoint.get_remote_address()))
+                                .is_some() =>
+                        {
+                            info!("disconnecting from {}, banned", peer_id);
+                            let _ = swarm.disconnect_peer_id(peer_id);
+                        }
+                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                            let tag = swarm.behaviour().peer_filter.tag(&peer_id);
+                            debug!("connected to {}, a {} peer", peer_id, tag.name());
+                            swarm.behaviour_mut().dialer.connected(&peer_id);
+                            swarm.behaviour_mut().peer_book.connected(peer_id, &endpoint);
+                            swarm.behaviour_mut().gossip_connected(peer_id);
+                            swarm.behaviour_mut().pex.connected(&peer_id);
+                            swarm.behaviour_mut().reconnect.connected(&peer_id);
+                            if let ConnectedPoint::Dialer { address } = &endpoint {
+                                bootstrap.connected(address);
+                            }
+                        }
+                        SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                            let behaviour = swarm.behaviour_mut();
+                            behaviour.peer_book.disconnected(&peer_id);
+                            behaviour.gossip_disconnected(&peer_id);
+                            // The peers disconnected from on purpose, or never accepting
+                            // connections, are not dialed again
+                            if behaviour.allows(&peer_id)
+                                && behaviour.peer_book.mode(&peer_id).accepts_inbound()
+                            {
+                                behaviour
+                                    .reconnect
+                                    .schedule(peer_id, std::time::Instant::now());
+                            }
+                        }
+                        SwarmEvent::UnreachableAddr { address, .. }
+                        | SwarmEvent::UnknownPeerUnreachableAddr { address, .. } => {
+                            info!("cannot reach {}", address);
+                            swarm.behaviour_mut().dialer.failed(&address);
+                        }
+                        event => info!("Unhandled Swarm Event: {:?}", event),
+                    }
+                    None
+                },
+            }
+        };
+
+        if let Some(event) = evt {
+            match event {
+                p2p::EventType::LocalChainResponse(resp) => {
+                    p2p::publish_chain_response(*resp, &mut swarm)
+                }
+                p2p::EventType::AutoMine => {
+                    if swarm.behaviour().miner.is_mining() {
+                        info!("still mining the previous block, skipping auto-mining");
+                    } else {
+                        auto_mined += 1;
+                        p2p::create_block(
+                            Payload::Text(format!("auto-mined block {}", auto_mined)),
+                            &mut swarm,
+                        );
+                    }
+                }
+                p2p::EventType::Mined(mined) => p2p::handle_mined_block(mined, &mut swarm),
+                p2p::EventType::Validated(checked) => {
+                    swarm.behaviour_mut().handle_validated(checked)
+                }
+                p2p::EventType::Rpc(call) => rpc::handle_call(call, &mut swarm),
+                p2p::EventType::Command(command) => handle::handle_command(command, &mut swarm),
+                p2p::EventType::Seal => swarm.behaviour_mut().seal(),
+                p2p::EventType::Compact => p2p::compact_storage(&swarm),
+                p2p::EventType::FlushLogs => {
+                    let behaviour = swarm.behaviour_mut();
+                    behaviour.errors.flush();
+                    behaviour.serving.expire(std::time::Instant::now());
+                    behaviour.pex.expire(std::time::Instant::now());
+                    behaviour
+                        .replays
+                        .expire(chrono::Utc::now().timestamp_millis());
+                    if let Err(err) = behaviour.bans.expire(chrono::Utc::now().timestamp()) {
+                        error!("cannot save the bans: {}", err);
+                    }
+                }
+                p2p::EventType::CheckpointCandidate => swarm.behaviour().miner.checkpoint(),
+                p2p::EventType::Relay => swarm.behaviour_mut().relay_due(),
+                p2p::EventType::PublishBulk => swarm.behaviour_mut().publish_bulk(),
+                p2p::EventType::Reconnect => {
+                    swarm.behaviour_mut().reconnect_due();
+                    for addr in bootstrap.due(std::time::Instant::now()) {
+                        info!("dialing bootstrap peer {} again", addr);
+                        if let Err(err) = swarm.dial_addr(addr.clone()) {
+                            warn!("cannot dial {}: {}", addr, err);
+                        }
+                    }
+                }
+                p2p::EventType::ExchangePeers => {
+                    // Behind a proxy, only the external addresses are shared, not to reveal the
+                    // node's own ones
+                    let listened = swarm.listeners().filter(|_| config.proxy.is_none());
+                    let addresses = swarm
+                        .external_addresses()
+                        .map(|record| &record.addr)
+                        .chain(listened)
+                        .cloned()
+                        .collect();
+                    swarm.behaviour_mut().exchange_peers(addresses);
+                }
+                p2p::EventType::Replay(event) => {
+                    if let Err(err) = swarm.behaviour_mut().replay(event) {
+                        error!("cannot replay event: {}", err);
+                    }
+                }
+                p2p::EventType::ReloadConfig => {
+                    reload_config(&mut config, &mut swarm, &mut auto_mine, &tokens)
+                }
+                p2p::EventType::CheckAlerts => {
+                    for alert in p2p::check_alerts(&mut swarm) {
+                        warn!("{}", alert);
+                        if let Some(sender) = &alert_sender {
+                            let _ = sender.send(alert);
+                        }
+                    }
+                }
+                p2p::EventType::Input(line, scope) => {
+                    swarm
+                        .behaviour_mut()
+                        .record(JournalEvent::Command { line: line.clone() });
+                    if let Some(len) = pending_truncation.take() {
+                        if line.trim() == "yes" && scope == ApiScope::Admin {
+                            p2p::handle_truncate(len, &mut swarm);
+                            continue;
+                        }
+                        println!("truncation cancelled");
+                    }
+                    let (cmd, output) = match split_output(&line) {
+                        Ok((cmd, output)) => (cmd, output.unwrap_or(config.output)),
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            continue;
+                        }
+                    };
+                    if command_scope(cmd) > scope {
+                        eprintln!("`{}` requires the admin scope", cmd);
+                        continue;
+                    }
+                    match cmd {
+                        "ls p" | "ls peers" => p2p::handle_print_peers(&swarm, output),
+                        "ls p -v" | "ls p --verbose" | "ls peers -v" | "ls peers --verbose" => {
+                            p2p::handle_print_peer_details(&swarm, output)
+                        }
+                        "sync status" => p2p::handle_sync_status(&swarm, output),
+                        "ls stats" => p2p::handle_print_stats(&swarm),
+                        "reindex" => p2p::handle_reindex(&mut swarm),
+                        "storage stats" => p2p::handle_storage_stats(&swarm),
+                        "propagation stats" => p2p::handle_propagation_stats(&swarm, output),
+                        "archive verify" => p2p::handle_archive_verify(&swarm),
+                        cmd if cmd.starts_with("mine start") => {
+                            match parse_mine_start(cmd, config.auto_mine) {
+                                Ok(period) => {
+                                    println!("auto-mining a block every {}s", period.as_secs());
+                                    auto_mine = Some(auto_mine_interval(period));
+                                }
+                                Err(err) => eprintln!("{}", err),
+                            }
+                        }
+                        "watch blocks" => {
+                            if watching_blocks.is_none() {
+                                let snapshots = swarm.behaviour().snapshots();
+                                watching_blocks = Some(spawn(watch_blocks(snapshots)));
+                            }
+                            println!("watching blocks, stop with `watch stop`");
+                        }
+                        "watch peers" => {
+                            swarm.behaviour_mut().watch_peers = true;
+                            println!("watching peers, stop with `watch stop`");
+                        }
+                        "watch stop" => {
+                            if let Some(task) = watching_blocks.take() {
+                                task.abort();
+                            }
+                            swarm.behaviour_mut().watch_peers = false;
+                            println!("watching stopped");
+                        }
+                        "mine stop" => {
+                            println!("auto-mining stopped");
+                            auto_mine = None;
+                        }
+                        "config reload" => {
+                            reload_config(&mut config, &mut swarm, &mut auto_mine, &tokens)
+                        }
+                        "difficulty show" => p2p::handle_difficulty_show(&swarm),
+                        cmd if cmd.starts_with("difficulty set") => {
+                            let set = p2p::handle_difficulty_set(cmd, config.dev, &mut swarm);
+                            if let Some((height, difficulty)) = set {
+                                for peer in &config.dev_peers {
+                                    let command = NodeCommand::SetDifficulty { height, difficulty };
+                                    if let Err(TrySendError::Full(_)) = peer.try_send(command) {
+                                        warn!("dev peer is busy, not forwarding {}", cmd);
+                                    }
+                                }
+                            }
+                        }
+                        cmd if cmd.starts_with("verify chain") => {
+                            pending_truncation = p2p::handle_verify_chain(cmd, &swarm);
+                        }
+                        cmd if cmd.starts_with("dial ") => p2p::handle_dial(cmd, &mut swarm),
+                        "ls bans" => p2p::handle_print_bans(&swarm, output),
+                        cmd if cmd.starts_with("diff chain ") => {
+                            p2p::handle_diff_chain(cmd, &mut swarm, output)
+                        }
+                        cmd if cmd.starts_with("ban ") => p2p::handle_ban(cmd, &mut swarm),
+                        cmd if cmd.starts_with("unban ") => p2p::handle_unban(cmd, &mut swarm),
+                        cmd if cmd.starts_with("ls c") => {
+                            p2p::handle_print_chain(cmd, &swarm, output)
+                        }
+                        cmd if cmd.starts_with("proof ") => {
+                            p2p::handle_request_proof(cmd, &mut swarm)
+                        }
+                        cmd if cmd.starts_with("anchor ") => p2p::handle_anchor(cmd, &mut swarm),
+                        cmd if cmd.starts_with("verify-anchor ") => {
+                            p2p::handle_verify_anchor(cmd, &swarm)
+                        }
+                        cmd if cmd.starts_with("register ") => {
+                            p2p::handle_register(cmd, &mut swarm)
+                        }
+                        cmd if cmd.starts_with("resolve ") => p2p::handle_resolve(cmd, &swarm),
+                        cmd if cmd.starts_with("search ") => {
+                            p2p::handle_search(cmd, &mut swarm, output)
+                        }
+                        cmd if cmd.starts_with("estimate fee") => {
+                            p2p::handle_estimate_fee(cmd, &swarm, output)
+                        }
+                        cmd if cmd.starts_with("tx status") => {
+                            p2p::handle_tx_status(cmd, &mut swarm, output)
+                        }
+                        cmd if cmd.starts_with("token ") => p2p::handle_token(cmd, &mut swarm),
+                        cmd if cmd.starts_with("create b") => {
+                            p2p::handle_create_block(cmd, &mut swarm)
+                        }
+                        _ => eprintln!("unknown command"),
+                    }
+                }
+            }
+        }
+
+        if replay_check.is_some() && replay.is_empty() {
+            println!("journal replayed");
+            replay_check = None;
+        }
+
+        // The transport refuses to dial for an inbound-only node, which discards the dials
+        let dials = swarm.behaviour_mut().dialer.take();
+        for addr in dials.into_iter().filter(|_| config.mode.dials_out()) {
+            if let Err(err) = swarm.dial_addr(addr.clone()) {
+                warn!("cannot dial {}: {}", addr, err);
+                swarm.behaviour_mut().dialer.failed(&addr);
+            }
+        }
+        swarm.behaviour_mut().update_search_index();
+        swarm.behaviour().publish_snapshot(false);
+    }
+}
+
+/// Prints a one-line summary of each chain event, derived from consecutive snapshots of the local
+/// blockchain, until the task is aborted
+async fn watch_blocks(mut snapshots: watch::Receiver<Arc<ChainSnapshot>>) {
+    let mut previous = snapshots.borrow_and_update().clone();
+    while snapshots.changed().await.is_ok() {
+        let current = snapshots.borrow_and_update().clone();
+        for event in webhook::events(&previous.tetherion, &current.tetherion) {
+            println!("{} {}", p2p::watch_time(), event);
+        }
+        previous = current;
+    }
+}
+
+/// Forwards the SIGHUP signals received by the process, each one asking the node to reload its
+/// configuration file
+async fn forward_hangups(sender: mpsc::UnboundedSender<()>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            error!("cannot listen to SIGHUP: {}", err);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if sender.send(()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reloads the configuration file and applies the reloadable settings, printing the changed
+/// settings that were applied and the ones that require a restart
+fn reload_config(
+    config: &mut NodeConfig,
+    swarm: &mut Swarm<TetherionBehaviour>,
+    auto_mine: &mut Option<Interval>,
+    tokens: &watch::Sender<Vec<ApiToken>>,
+) {
+    let file = match &mut config.config_file {
+        Some(file) => file,
+        None => {
+            eprintln!("no configuration file to reload, start the node with --config");
+            return;
+        }
+    };
+    let reload = match file.reload() {
+        Ok(reload) => reload,
+        Err(err) => {
+            error!("{}, keeping the running configuration", err);
+            return;
+        }
+    };
+    let reloaded = &file.config;
+
+    if let Some(level) = reloaded.logging.level {
+        log::set_max_level(level.into());
+    }
+    let filter = PeerFilter::new(&reloaded.peers);
+    let denied: Vec<_> = swarm
+        .behaviour()
+        .peer_book
+        .connected_peers()
+        .filter(|peer| !filter.allows(peer))
+        .copied()
+        .collect();
+    for peer in denied {
+        info!("disconnecting from {}, no longer allowed by [peers]", peer);
+        let _ = swarm.disconnect_peer_id(peer);
+    }
+    swarm.behaviour_mut().peer_filter = filter;
+    config.peers = reloaded.peers.clone();
+    if reload.applied.contains(&"mining.auto_mine") {
+        config.auto_mine = reloaded.mining.auto_mine();
+        *auto_mine = config.auto_mine.map(auto_mine_interval);
+    }
+    config.rpc.tokens = reloaded.rpc.tokens.clone();
+    tokens.send_replace(config.rpc.tokens.clone());
+
+    let names = |names: &[&str]| match names {
+        [] => String::from("none"),
+        names => names.join(", "),
+    };
+    println!("configuration reloaded");
+    println!("applied: {}", names(&reload.applied));
+    println!("requiring a restart: {}", names(&reload.restart_required));
+}
+
+/// Reads the commands of a script, one per line, skipping the blank lines and the comments
+/// starting with `#`
+pub fn read_script(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Creates the auto-mining interval whose first tick happens after a full period
+fn auto_mine_interval(period: Duration) -> Interval {
+    interval_at(Instant::now() + period, period)
+}
+
+/// Parses the `mine start [seconds]` command into the auto-mining period, falling back to
+/// the configured period or the default one
+fn parse_mine_start(cmd: &str, configured: Option<Duration>) -> Result<Duration, String> {
+    const DEFAULT_PERIOD: Duration = Duration::from_secs(10);
+
+    let arg = cmd.strip_prefix("mine start").unwrap_or_default().trim();
+    if arg.is_empty() {
+        return Ok(configured.unwrap_or(DEFAULT_PERIOD));
+    }
+
+    match arg.parse::<u64>() {
+        Ok(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+        _ => Err(format!("invalid auto-mining interval: {}", arg)),
+    }
+}
+
+/// The commands only reading the state of the node, by prefix, which any scope may execute
+const READ_COMMANDS: &[&str] = &[
+    "ls ",
+    "sync status",
+    "storage stats",
+    "propagation stats",
+    "archive verify",
+    "difficulty show",
+    "verify chain",
+    "diff chain ",
+    "verify-anchor ",
+    "resolve ",
+    "search ",
+    "proof ",
+    "estimate fee",
+    "tx status",
+    "token balance ",
+    "watch ",
+];
+
+/// Gets the scope needed to execute the command, the commands changing the state of the node or
+/// of the blockchain requiring the admin scope
+pub fn command_scope(cmd: &str) -> ApiScope {
+    if READ_COMMANDS.iter().any(|prefix| cmd.starts_with(prefix)) {
+        ApiScope::Read
+    } else {
+        ApiScope::Admin
+    }
+}
+
+/// Splits the trailing `--output <format>` option off the command, if any
+fn split_output(cmd: &str) -> Result<(&str, Option<Output>), String> {
+    let option = cmd
+        .trim_end()
+        .rsplit_once(char::is_whitespace)
+        .and_then(|(rest, format)| Some((rest.trim_end().strip_suffix("--output")?, format)));
+    match option {
+        Some((cmd, format)) => Output::from_str(format, true)
+            .map(|output| (cmd.trim_end(), Some(output)))
+            .map_err(|_| format!("invalid output format: {}", format)),
+        None => Ok((cmd, None)),
+    }
+}
+
+/// Waits for the next tick of the interval, or forever if there is no interval
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Waits for the next command of the embedding application, or forever if there is no
+/// application
+async fn next_command(commands: &mut Option<mpsc::Receiver<NodeCommand>>) -> Option<NodeCommand> {
+    match commands {
+        Some(commands) => commands.recv().await,
+        None => future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_option() {
+        assert_eq!(split_output("ls chain"), Ok(("ls chain", None)));
+        assert_eq!(
+            split_output("ls chain --output json"),
+            Ok(("ls chain", Some(Output::Json)))
+        );
+        assert_eq!(
+            split_output("sync status  --output Text "),
+            Ok(("sync status", Some(Output::Text)))
+        );
+        assert!(split_output("ls p --output xml").is_err());
+    }
+
+    #[test]
+    fn command_scopes() {
+        for cmd in [
+            "ls c --full",
+            "ls stats",
+            "ls bans",
+            "sync status",
+            "propagation stats",
+            "tx status ab",
+            "watch blocks",
+            "diff chain 12D3KooW",
+        ] {
+            assert_eq!(command_scope(cmd), ApiScope::Read, "{}", cmd);
+        }
+        for cmd in [
+            "create b hi",
+            "token mint TTH 5",
+            "mine start",
+            "reindex",
+            "config reload",
+            "dial x",
+            "ban 10.0.0.1 1h",
+            "unban 10.0.0.1",
+            "yes",
+        ] {
+            assert_eq!(command_scope(cmd), ApiScope::Admin, "{}", cmd);
+        }
+        assert_eq!(command_scope("token balance ab TTH"), ApiScope::Read);
+    }
+
+    #[test]
+    fn mine_start_period() {
+        const CONFIGURED: Duration = Duration::from_secs(3);
+
+        assert_eq!(
+            parse_mine_start("mine start", Some(CONFIGURED)),
+            Ok(CONFIGURED)
+        );
+        assert_eq!(
+            parse_mine_start("mine start 7", Some(CONFIGURED)),
+            Ok(Duration::from_secs(7))
+        );
+        assert!(parse_mine_start("mine start 0", None).is_err());
+        assert!(parse_mine_start("mine start soon", None).is_err());
+    }
+
+    #[test]
+    fn script_commands() {
+        let script = "# bring up the demo\ndial /ip4/127.0.0.1/tcp/4001\n\n  create b hello  \n";
+        assert_eq!(
+            read_script(script),
+            vec!["dial /ip4/127.0.0.1/tcp/4001", "create b hello"]
+        );
+    }
+}
@@ -1,205 +1,3164 @@
 /// Copyright (c) 2022 Tetherion
 use {
-    crate::{block::Block, tetherion::Tetherion},
+    crate::{
+        alerts::{Alert, Monitor},
+        anchor,
+        bans::{self, Ban, BanList, BanTarget},
+        block::{Block, BlockHeader},
+        codec::Format,
+        config::{ApiScope, CheckpointConfig},
+        divergence::{self, Branch, Divergence, Verdict},
+        evidence::{Evidence, EvidencePool},
+        fees::{self, FeeEstimate},
+        finality::{BlockRef, Finality, Vote},
+        gossip::{GossipView, Relay, RelayMode, ReplayGuard, Stamp, Stamper},
+        handle::NodeCommand,
+        journal::{Journal, JournalEvent},
+        light::HeaderChain,
+        logfmt::{self, Preview},
+        mempool::{self, Mempool, MempoolError},
+        merkle::{self, MerkleProof},
+        miner::{MinedBlock, Miner},
+        node::{NodeConfig, Output},
+        payload::{Payload, TokenOp},
+        peers::{self, Dialer, PeerBook, PeerFilter, Reconnector},
+        pex::{AddressBook, PeerRecord},
+        printout,
+        priority::Priority,
+        propagation::{self, PropagationStats},
+        pubsub::Pubsub,
+        receipts::{Receipts, TxStatus},
+        rpc::RpcCall,
+        script,
+        search::{self, SearchIndex},
+        serving::ServingLimiter,
+        state::BalanceView,
+        store::{BlockStore, StorageStats},
+        sync::{self, ChainAssembler, ChainFrame, HandshakeError, SyncHandshake},
+        tetherion::{BlockData, InvalidBlockError, Tetherion},
+        throttle::LogThrottle,
+        trace::{self, Sessions},
+        validation::{Checked, Job, Origin, Validated, ValidationPool},
+        version,
+        weight::{self, MAX_BLOCK_WEIGHT},
+        work::{BlockTemplate, SubmitWorkError, WorkProvider, WorkTemplate},
+    },
     libp2p::{
-        floodsub::{Floodsub, FloodsubEvent, Topic},
-        identity,
+        floodsub::{FloodsubEvent, FloodsubMessage, Topic},
+        identify::{Identify, IdentifyConfig, IdentifyEvent},
+        identity::{self, ed25519},
         mdns::{Mdns, MdnsEvent},
-        swarm::{NetworkBehaviourEventProcess, Swarm},
-        NetworkBehaviour, PeerId,
+        ping::{Ping, PingConfig, PingEvent, PingSuccess},
+        swarm::{toggle::Toggle, NetworkBehaviourEventProcess, Swarm},
+        Multiaddr, NetworkBehaviour, PeerId,
     },
 };
 
-use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use tokio::sync::mpsc;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, IsTerminal, Write},
+    path::Path,
+    sync::Arc,
+};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    watch,
+};
 
-pub static KEYS: Lazy<identity::Keypair> = Lazy::new(identity::Keypair::generate_ed25519);
-pub static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
-pub static CHAIN_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("chains"));
-pub static BLOCK_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("blocks"));
+/// The version of the protocol the nodes speak, reported to the peers through identify
+const PROTOCOL_VERSION: &str = "/tetherion/1.3.0";
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The ID of the chain whose topics keep their historical, unprefixed names
+pub const DEFAULT_CHAIN_ID: &str = "main";
+
+/// The number of queued bulk messages published at a time, so that the messages of high
+/// priority published meanwhile do not wait behind a whole blockchain
+const BULK_BATCH: usize = 4;
+
+/// The topics the messages of a chain are gossiped on, so that the nodes of several chains can
+/// share a network, or a process, without their messages mixing
+#[derive(Debug, Clone)]
+pub struct Topics {
+    pub chain: Topic,
+    pub block: Topic,
+    pub header: Topic,
+    pub vote: Topic,
+    pub evidence: Topic,
+    pub pex: Topic,
+}
+
+impl Topics {
+    /// Creates the topics of the chain with the given ID, prefixed with the ID unless it is the
+    /// default chain
+    pub fn new(chain_id: &str) -> Self {
+        let topic = |name: &str| match chain_id {
+            DEFAULT_CHAIN_ID => Topic::new(name),
+            id => Topic::new(format!("{}/{}", id, name)),
+        };
+        Self {
+            chain: topic("chains"),
+            block: topic("blocks"),
+            header: topic("headers"),
+            vote: topic("votes"),
+            evidence: topic("evidence"),
+            pex: topic("peers"),
+        }
+    }
+}
+
+/// The local blockchain to be sent, frame by frame, to the peer requesting it
+#[derive(Debug)]
 pub struct ChainResponse {
-    pub tetherion: Tetherion<String>,
+    pub tetherion: Tetherion<Payload>,
+    pub receiver: String,
+
+    /// The correlation ID of the sync session the response belongs to
+    pub session: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HeadersResponse {
+    pub headers: Vec<BlockHeader>,
+    pub receiver: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HeadersRequest {
+    pub from_peer_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProofResponse {
+    pub block_hash: String,
+    pub tx_id: String,
+    pub proof: Option<MerkleProof>,
     pub receiver: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct LocalChainRequest {
+pub struct ProofRequest {
+    pub block_hash: String,
+    pub tx_id: String,
     pub from_peer_id: String,
 }
 
+/// A sample of the peer records known to the sender, exchanged with a single peer
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PeerExchange {
+    pub records: Vec<PeerRecord>,
+    pub receiver: String,
+
+    /// Whether the sender asks for the receiver's sample in return
+    pub request: bool,
+}
+
+/// A block framed header first, so that the header's Proof of Work can be checked before the
+/// data is decoded
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlockFrame {
+    pub header: BlockHeader,
+
+    /// The data stored in the block, encoded in the wire format
+    #[serde(with = "hex")]
+    pub data: Vec<u8>,
+
+    /// When the node producing the block published it, in milliseconds since the epoch; the peers
+    /// relay the frame unchanged, so that the receivers can measure how long the block propagated
+    #[serde(default)]
+    pub published_at: Option<i64>,
+}
+
+/// A message exchanged with the peers, encoded in the wire format shared by the network
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Message {
+    ChainRequest(SyncHandshake),
+    ChainFrame(ChainFrame),
+    Block(BlockFrame),
+    Vote(Vote),
+    HeadersRequest(HeadersRequest),
+    HeadersResponse(HeadersResponse),
+    ProofRequest(ProofRequest),
+    ProofResponse(ProofResponse),
+    Evidence(Box<Evidence>),
+    PeerExchange(PeerExchange),
+}
+
+/// A message along with its stamp, as gossiped to the peers
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope<M> {
+    pub stamp: Stamp,
+    pub message: M,
+}
+
+impl Message {
+    /// Gets how urgently the message is sent: the frames of blockchains and the headers sent to
+    /// syncing peers wait for the other messages
+    pub fn priority(&self) -> Priority {
+        match self {
+            Message::ChainFrame(_) | Message::HeadersResponse(_) => Priority::Low,
+            _ => Priority::High,
+        }
+    }
+}
+
+/// An immutable copy of the local blockchain, published whenever the blockchain changes so that
+/// it can be read without going through the node's event loop
+#[derive(Debug, Clone)]
+pub struct ChainSnapshot {
+    /// The local blockchain
+    pub tetherion: Tetherion<Payload>,
+
+    /// The latest finalized block, if any
+    pub finalized: Option<BlockRef>,
+}
+
+impl ChainSnapshot {
+    /// Gets the latest block of the blockchain
+    pub fn best(&self) -> BlockRef {
+        let latest_block = self
+            .tetherion
+            .blocks()
+            .last()
+            .expect("there is at least one block");
+        BlockRef {
+            height: latest_block.id,
+            hash: latest_block.hash.clone(),
+        }
+    }
+}
+
 pub enum EventType {
-    LocalChainResponse(ChainResponse),
-    Input(String),
-    Init,
+    LocalChainResponse(Box<ChainResponse>),
+    /// A command line along with the scope of whoever sent it
+    Input(String, ApiScope),
+    AutoMine,
+    Mined(MinedBlock),
+    Validated(Checked),
+    Rpc(RpcCall),
+    Command(NodeCommand),
+    Seal,
+    Compact,
+    FlushLogs,
+    CheckAlerts,
+    CheckpointCandidate,
+    Relay,
+    PublishBulk,
+    Reconnect,
+    ExchangePeers,
+    /// An event of the journal being replayed
+    Replay(JournalEvent),
+    ReloadConfig,
+}
+
+/// How far the node got with the sync it starts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitialSync {
+    /// No peer to sync with connected yet
+    Pending,
+
+    /// The blockchain, or the block headers of a light client, got requested from the peer
+    Requested(PeerId),
 }
 
 #[derive(NetworkBehaviour)]
 pub struct TetherionBehaviour {
-    pub floodsub: Floodsub,
-    pub mdns: Mdns,
+    /// The gossip of the messages, which the node publishes to the peers it picks, relaying the
+    /// received messages itself
+    pub pubsub: Pubsub,
+    /// The discovery of the peers on the local network, disabled when dialing through a proxy or
+    /// when discovery is off
+    pub mdns: Toggle<Mdns>,
+    pub ping: Ping,
+    pub identify: Identify,
+
+    /// What the node learned about each peer
+    #[behaviour(ignore)]
+    pub peer_book: PeerBook,
+
+    /// The peers the node keeps connections with
+    #[behaviour(ignore)]
+    pub peer_filter: PeerFilter,
+
+    /// The peers and IP addresses the node keeps no connection with, set by hand
+    #[behaviour(ignore)]
+    pub bans: BanList,
+
+    /// Whether the discovered and expired peers are printed, following `watch peers`
+    #[behaviour(ignore)]
+    pub watch_peers: bool,
+
+    /// The log of the errors caused by the peers, throttled so that hostile peers cannot flood it
+    #[behaviour(ignore)]
+    pub errors: LogThrottle,
+
+    /// The watch over the health of the node
+    #[behaviour(ignore)]
+    pub alerts: Monitor,
+
+    /// The correlation IDs of the sync sessions started by the node
+    #[behaviour(ignore)]
+    sessions: Sessions,
+
+    /// The index of the transactions of the local blockchain by account and by word
+    #[behaviour(ignore)]
+    pub search: SearchIndex,
+
+    /// The statuses of the transactions submitted to the node
+    #[behaviour(ignore)]
+    pub receipts: Receipts,
+
+    /// The evidence of the block producers signing two blocks at the same height
+    #[behaviour(ignore)]
+    pub evidence: EvidencePool,
+
+    /// The delays the blocks received from the peers took to propagate
+    #[behaviour(ignore)]
+    pub propagation: PropagationStats,
+
+    #[behaviour(ignore)]
+    /// The channel the responses to chain requests are queued to; requests arriving while the
+    /// queue is full are rejected
+    pub response_sender: mpsc::Sender<ChainResponse>,
+
+    /// The limits on the chain and headers requests served to each peer
+    #[behaviour(ignore)]
+    pub serving: ServingLimiter,
+
+    /// The connected peers messages are published to
+    #[behaviour(ignore)]
+    gossip: GossipView<PeerId>,
+
+    /// The dials to the discovered peers
+    #[behaviour(ignore)]
+    pub dialer: Dialer,
+
+    /// The retries of dialing the peers the node got disconnected from
+    #[behaviour(ignore)]
+    pub reconnect: Reconnector<PeerId>,
+
+    /// The received messages waiting to be relayed to the peers
+    #[behaviour(ignore)]
+    pub relay: Relay,
+
+    /// The bulk messages waiting to be published, along with their topics
+    #[behaviour(ignore)]
+    bulk: VecDeque<(Topic, Vec<u8>)>,
+
+    /// The addresses of the peers learned from the exchanges with the peers
+    #[behaviour(ignore)]
+    pub pex: AddressBook,
+
+    #[behaviour(ignore)]
+    pub tetherion: Tetherion<Payload>,
+
+    #[behaviour(ignore)]
+    pub peer_id: PeerId,
+
+    /// The keys the node signs its payloads with
+    #[behaviour(ignore)]
+    pub keys: ed25519::Keypair,
+
+    #[behaviour(ignore)]
+    pub miner: Miner,
+
+    #[behaviour(ignore)]
+    pub work: WorkProvider,
+
+    /// The data waiting to be sealed into blocks in the node's time slots, the oldest first
+    #[behaviour(ignore)]
+    pub sealing: Mempool,
+
+    /// The block headers, kept instead of the full blockchain when running as a light client
+    #[behaviour(ignore)]
+    pub light: Option<HeaderChain>,
+
+    /// The trusted checkpoint remote blockchains are cut at before being validated
+    #[behaviour(ignore)]
+    pub checkpoint: Option<CheckpointConfig>,
+
+    /// The tally of the finality votes, if blocks can be finalized
+    #[behaviour(ignore)]
+    pub finality: Option<Finality>,
+
+    /// The store the blocks are kept in on disk, if any
+    #[behaviour(ignore)]
+    pub store: Option<BlockStore>,
+
+    /// The latest published snapshot of the local blockchain
+    #[behaviour(ignore)]
+    snapshot: watch::Sender<Arc<ChainSnapshot>>,
+
+    /// The remote blockchains being received frame by frame
+    #[behaviour(ignore)]
+    incoming: ChainAssembler,
+
+    /// The workers validating the blocks and blockchains received from the peers
+    #[behaviour(ignore)]
+    validation: ValidationPool,
+
+    /// The stamps of the messages the node publishes
+    #[behaviour(ignore)]
+    stamper: Stamper,
+
+    /// The stamps of the messages received lately, telling replays apart
+    #[behaviour(ignore)]
+    pub replays: ReplayGuard,
 
+    /// The format the messages exchanged with the peers are encoded in
     #[behaviour(ignore)]
-    pub response_sender: mpsc::UnboundedSender<ChainResponse>,
+    wire_format: Format,
 
+    /// The ID of the chain the node follows
     #[behaviour(ignore)]
-    pub init_sender: mpsc::UnboundedSender<bool>,
+    chain_id: String,
 
+    /// The topics the chain's messages are gossiped on
     #[behaviour(ignore)]
-    pub tetherion: Tetherion<String>,
+    pub topics: Topics,
+
+    /// The journal the inbound messages and the commands are recorded to, if any
+    #[behaviour(ignore)]
+    journal: Option<Journal>,
+
+    /// The peers whose headers are awaited by `diff chain`, along with the format the divergence
+    /// is printed in
+    #[behaviour(ignore)]
+    pending_diffs: HashMap<PeerId, Output>,
+
+    /// The sync the node starts with, made with the first peer listening to the node's requests
+    #[behaviour(ignore)]
+    pub initial_sync: InitialSync,
 }
 
 impl TetherionBehaviour {
     pub async fn new(
-        tetherion: Tetherion<String>,
-        response_sender: mpsc::UnboundedSender<ChainResponse>,
-        init_sender: mpsc::UnboundedSender<bool>,
+        tetherion: Tetherion<Payload>,
+        response_sender: mpsc::Sender<ChainResponse>,
+        mined_sender: mpsc::UnboundedSender<MinedBlock>,
+        validated_sender: mpsc::UnboundedSender<Checked>,
+        config: &NodeConfig,
+        keys: ed25519::Keypair,
+        peer_id: PeerId,
     ) -> Self {
+        let consensus = tetherion.consensus().clone();
+        let genesis = tetherion.blocks()[0].hash.clone();
+        let (snapshot, _) = watch::channel(Arc::new(ChainSnapshot {
+            tetherion: tetherion.clone(),
+            finalized: None,
+        }));
+        let miner = Miner::new(mined_sender, config.mining, Mempool::new(config.mempool));
+        let miner = match &config.store {
+            Some(store) => miner.with_snapshots(store.candidate_path()),
+            None => miner,
+        };
         let mut behaviour = Self {
-            floodsub: Floodsub::new(*PEER_ID),
-            mdns: Mdns::new(Default::default())
-                .await
-                .expect("MDNS should be created"),
+            pubsub: Pubsub::new(peer_id),
+            mdns: if config.proxy.is_some() || !config.discovery {
+                None
+            } else {
+                Some(
+                    Mdns::new(Default::default())
+                        .await
+                        .expect("MDNS should be created"),
+                )
+            }
+            .into(),
+            ping: Ping::new(PingConfig::new()),
+            identify: Identify::new(
+                IdentifyConfig::new(
+                    String::from(PROTOCOL_VERSION),
+                    identity::PublicKey::Ed25519(keys.public()),
+                )
+                .with_agent_version(config.mode.agent_version(version::AGENT_VERSION)),
+            ),
+            peer_book: PeerBook::default(),
+            peer_filter: PeerFilter::new(&config.peers),
+            bans: match &config.bans {
+                Some(path) => {
+                    BanList::load(path, chrono::Utc::now().timestamp()).unwrap_or_else(|err| {
+                        // The file is left as it is, for the bans in it not to be lost
+                        log::error!("cannot load the bans from {}: {}", path.display(), err);
+                        BanList::default()
+                    })
+                }
+                None => BanList::default(),
+            },
+            watch_peers: false,
+            errors: LogThrottle::new(config.logging.error_burst, config.logging.error_interval()),
+            alerts: Monitor::new(config.alerts, std::time::Instant::now()),
+            sessions: Sessions::new(&peer_id.to_string()),
+            search: SearchIndex::default(),
+            receipts: Receipts::new(config.confirmations.depth),
+            evidence: EvidencePool::default(),
+            propagation: PropagationStats::default(),
             response_sender,
-            init_sender,
+            serving: ServingLimiter::new(config.serving).with_metered(config.bandwidth.metered),
+            gossip: GossipView::new(config.gossip.fanout),
+            dialer: Dialer::new(config.prefer_ip),
+            reconnect: Reconnector::new(config.reconnect),
+            relay: Relay::new(config.gossip),
+            bulk: VecDeque::new(),
+            pex: AddressBook::new(config.pex, peer_id),
             tetherion,
+            peer_id,
+            keys,
+            miner,
+            work: WorkProvider::default(),
+            sealing: Mempool::new(config.mempool),
+            light: if config.light {
+                Some(HeaderChain::new(consensus.clone(), genesis))
+            } else {
+                None
+            },
+            checkpoint: config.checkpoint.clone(),
+            finality: match (&config.finality, config.light) {
+                (Some(finality), false) => Some(finality.finality()),
+                _ => None,
+            },
+            store: config.store.clone(),
+            snapshot,
+            incoming: ChainAssembler::default(),
+            validation: ValidationPool::new(config.validation, validated_sender),
+            stamper: Stamper::new(peer_id.to_string(), chrono::Utc::now().timestamp_millis()),
+            replays: ReplayGuard::new(config.gossip.ttl()),
+            wire_format: config.wire_format,
+            chain_id: config.chain_id.clone(),
+            topics: Topics::new(&config.chain_id),
+            journal: config
+                .journal
+                .as_ref()
+                .and_then(|path| match Journal::create(path) {
+                    Ok(journal) => Some(journal),
+                    Err(err) => {
+                        log::error!("cannot open journal {}: {}", path.display(), err);
+                        None
+                    }
+                }),
+            pending_diffs: HashMap::new(),
+            initial_sync: InitialSync::Pending,
         };
-        behaviour.floodsub.subscribe(CHAIN_TOPIC.clone());
-        behaviour.floodsub.subscribe(BLOCK_TOPIC.clone());
+        if !config.light {
+            let topics = &behaviour.topics;
+            behaviour.pubsub.subscribe(topics.chain.clone());
+            behaviour.pubsub.subscribe(topics.vote.clone());
+            behaviour.pubsub.subscribe(topics.evidence.clone());
+        }
+        behaviour.pubsub.subscribe(behaviour.topics.block.clone());
+        behaviour.pubsub.subscribe(behaviour.topics.header.clone());
+        if config.pex.enabled && config.discovery {
+            behaviour.pubsub.subscribe(behaviour.topics.pex.clone());
+        }
+        if consensus.is_mined() && !config.light {
+            behaviour.restore_candidate();
+        }
+        behaviour.record(JournalEvent::Start {
+            peer_id: peer_id.to_string(),
+        });
 
         behaviour
     }
 
-    /// Checks whether remote blockchain is worse than the local one:
-    /// 1. by the validity
-    /// 2. in case both blockchains are valid, by the length
-    /// 3. in case both blockchains are of the same length, by the olderness
-    fn is_better_than(&self, remote: &Tetherion<String>) -> bool {
-        match (self.tetherion.is_valid(), remote.is_valid()) {
-            (Ok(()), Ok(())) => {
-                if self.tetherion.blocks().len() == remote.blocks().len() {
-                    return self.tetherion.creation_timestamp() <= remote.creation_timestamp();
-                }
-                self.tetherion.blocks().len() >= remote.blocks().len()
+    /// Starts mining a block with the given data on top of the local blockchain, tracking the
+    /// status of the transactions it consists of. Fails on light clients, which do not mine.
+    pub fn mine(&mut self, data: Payload) -> Result<(), MempoolError> {
+        let tx_ids = data.tx_ids();
+        self.receipts.record(&tx_ids, TxStatus::Received);
+        if self.light.is_some() {
+            let err = MempoolError::LightClient;
+            let reason = err.to_string();
+            self.receipts.record(&tx_ids, TxStatus::Dropped { reason });
+            return Err(err);
+        }
+        let weight = weight::weight(&data);
+        if weight > MAX_BLOCK_WEIGHT {
+            let err = MempoolError::Overweight {
+                weight,
+                budget: MAX_BLOCK_WEIGHT,
+            };
+            let reason = err.to_string();
+            self.receipts.record(&tx_ids, TxStatus::Dropped { reason });
+            return Err(err);
+        }
+
+        let sealed = !self.tetherion.consensus().is_mined();
+        let queued = if sealed {
+            log::info!("queueing data to be sealed");
+            let state = self.tetherion.state();
+            let replaced = self
+                .sealing
+                .push(data, state.chain_id(), |account| state.next_nonce(account));
+            if let Ok(Some(replaced)) = &replaced {
+                log::info!("replacing pending data {}", Preview(replaced));
             }
-            (Ok(()), Err(err)) => {
-                log::debug!("Remote blockchain is invalid: {}", err);
-                true
+            replaced
+        } else {
+            let latest_block = self
+                .tetherion
+                .blocks()
+                .last()
+                .expect("there is at least one block");
+            self.miner.submit(
+                data,
+                latest_block,
+                self.tetherion.difficulty_at(latest_block.id + 1),
+                self.tetherion.state(),
+            )
+        };
+
+        match queued {
+            Ok(replaced) => {
+                self.receipts.record(&tx_ids, TxStatus::InMempool);
+                if let (Some(replaced), Some(by)) = (replaced, tx_ids.first()) {
+                    let by = by.clone();
+                    self.receipts
+                        .record(&replaced.tx_ids(), TxStatus::Replaced { by });
+                }
             }
-            (Err(err), Ok(())) => {
-                log::debug!("Local blockchain is invalid: {}", err);
-                false
+            Err(err) => {
+                let reason = err.to_string();
+                self.receipts.record(&tx_ids, TxStatus::Dropped { reason });
+                return Err(err);
             }
-            (Err(local_err), Err(remote_err)) => {
-                panic!(
-                    "Local blockchain is invalid: {}, remote blockchain is invalid: {}",
-                    local_err, remote_err
-                );
+        }
+        if sealed {
+            self.seal();
+        }
+        Ok(())
+    }
+
+    /// Stamps the message, encodes it in the wire format and publishes it to the topic
+    pub fn publish(&mut self, topic: Topic, message: &Message) {
+        let envelope = Envelope {
+            stamp: self.stamper.stamp(chrono::Utc::now().timestamp_millis()),
+            message,
+        };
+        let data = self
+            .wire_format
+            .encode(&envelope)
+            .expect("can encode message");
+        match message.priority() {
+            Priority::High => self.publish_data(vec![topic], data),
+            Priority::Low => self.bulk.push_back((topic, data)),
+        }
+    }
+
+    /// Publishes the next batch of the queued bulk messages
+    pub fn publish_bulk(&mut self) {
+        for _ in 0..BULK_BATCH {
+            match self.bulk.pop_front() {
+                Some((topic, data)) => self.publish_data(vec![topic], data),
+                None => break,
             }
         }
     }
-}
 
-// incoming event handler
-impl NetworkBehaviourEventProcess<FloodsubEvent> for TetherionBehaviour {
-    fn inject_event(&mut self, event: FloodsubEvent) {
-        if let FloodsubEvent::Message(msg) = event {
-            if let Ok(resp) = serde_json::from_slice::<ChainResponse>(&msg.data) {
-                if resp.receiver == PEER_ID.to_string() {
-                    log::info!("Response from {}:", msg.source);
+    /// Gets the number of bulk messages waiting to be published
+    pub fn queued_bulk(&self) -> usize {
+        self.bulk.len()
+    }
 
-                    if !self.is_better_than(&resp.tetherion) {
-                        self.tetherion = resp.tetherion;
-                    }
-                }
-            } else if let Ok(resp) = serde_json::from_slice::<LocalChainRequest>(&msg.data) {
-                log::info!("sending local chain to {}", msg.source.to_string());
-                if resp.from_peer_id == PEER_ID.to_string() {
-                    if let Err(e) = self.response_sender.send(ChainResponse {
-                        tetherion: self.tetherion.clone(),
-                        receiver: msg.source.to_string(),
-                    }) {
-                        log::error!("error sending response via channel, {}", e);
-                    }
-                }
-            } else if let Ok(block) = serde_json::from_slice::<Block<String>>(&msg.data) {
-                log::info!("received new block from {}", msg.source.to_string());
-                match self.tetherion.add_block(block) {
-                    Ok(()) => (),
-                    Err(err) => log::error!("Error {}", err),
-                }
+    /// Publishes the encoded message to the topics, sending it to the peers the node gossips with
+    fn publish_data(&mut self, topics: Vec<Topic>, data: Vec<u8>) {
+        self.pubsub.publish(self.gossip.peers(), topics, data);
+    }
+
+    /// Gets the ban in effect on the peer, or on the IP address of the given address of the peer,
+    /// if any
+    pub fn ban_of(&self, peer: &PeerId, addr: Option<&Multiaddr>) -> Option<&Ban> {
+        let ip = addr.and_then(bans::ip_of);
+        self.bans.find(peer, ip, chrono::Utc::now().timestamp())
+    }
+
+    /// Checks whether the node keeps connections with the peer, allowed by the peer filter and
+    /// not banned
+    pub fn allows(&self, peer: &PeerId) -> bool {
+        self.peer_filter.allows(peer) && self.ban_of(peer, None).is_none()
+    }
+
+    /// Dials again the peers whose retry is due, at all the addresses they are known at
+    pub fn reconnect_due(&mut self) {
+        for peer in self.reconnect.due(std::time::Instant::now()) {
+            if self.gossip.contains(&peer) || !self.allows(&peer) {
+                self.reconnect.connected(&peer);
+                continue;
             }
+            log::info!("dialing {} again", peer);
+            self.dialer.dial(peer, self.peer_book.addresses(&peer));
         }
     }
-}
 
-impl NetworkBehaviourEventProcess<MdnsEvent> for TetherionBehaviour {
-    fn inject_event(&mut self, event: MdnsEvent) {
-        match event {
-            MdnsEvent::Discovered(discovered_list) => {
-                for (peer, _addr) in discovered_list {
-                    self.floodsub.add_node_to_partial_view(peer);
+    /// Relays the received messages whose relay delay is over
+    pub fn relay_due(&mut self) {
+        for (topics, data) in self.relay.due(std::time::Instant::now()) {
+            self.publish_data(topics.into_iter().map(Topic::new).collect(), data);
+        }
+    }
+
+    /// Relays the received messages right away, unless relaying is delayed or off
+    fn relay_now(&mut self) {
+        if self.relay.mode() == RelayMode::Immediate {
+            self.relay_due();
+        }
+    }
+
+    /// Relays the message of the block with the given hash, the block being valid
+    fn relay_block(&mut self, hash: &str) {
+        self.relay.release(hash, std::time::Instant::now());
+        self.relay_now();
+    }
+
+    /// Adds the peer the node connected to to the peers it gossips with, unless it gossips with
+    /// as many peers as the fanout already; the private peers are always gossiped with
+    pub fn gossip_connected(&mut self, peer: PeerId) {
+        let private = self.peer_filter.is_private(&peer);
+        self.gossip.connected(peer, private);
+    }
+
+    /// Sends a sample of the known peer records to the next connected peer, asking for its own
+    /// sample in return; the node's record carries the given addresses
+    pub fn exchange_peers(&mut self, addresses: Vec<Multiaddr>) {
+        self.pex.advertise(addresses);
+        let connected = self.peer_book.connected_peers().copied().collect();
+        if let Some(peer) = self.pex.pick(connected, std::time::Instant::now()) {
+            self.send_peer_sample(peer, true);
+        }
+    }
+
+    fn send_peer_sample(&mut self, receiver: PeerId, request: bool) {
+        let time = chrono::Utc::now().timestamp();
+        // The private peers, e.g. a validator behind the node, are never shared
+        let filter = &self.peer_filter;
+        let shared = |peer: &PeerId| peer != &receiver && !filter.is_private(peer);
+        let exchange = PeerExchange {
+            records: self.pex.sample(&self.keys, time, shared),
+            receiver: receiver.to_string(),
+            request,
+        };
+        self.publish(self.topics.pex.clone(), &Message::PeerExchange(exchange));
+    }
+
+    /// Handles the peer records sent by a peer, dialing the peers learned about while the node
+    /// has few connections
+    fn handle_peer_exchange(&mut self, source: PeerId, exchange: PeerExchange) {
+        if exchange.receiver != self.peer_id.to_string() {
+            return;
+        }
+        let learned = match self.pex.received(
+            source,
+            exchange.records,
+            exchange.request,
+            std::time::Instant::now(),
+            chrono::Utc::now().timestamp(),
+        ) {
+            Ok(learned) => learned,
+            Err(err) => {
+                self.errors.log(
+                    log::Level::Warn,
+                    &source.to_string(),
+                    err.kind(),
+                    &format_args!("dropping peer records from {}: {}", source, err),
+                );
+                if err.is_misbehaviour() {
+                    self.peer_book
+                        .penalize(source, peers::INVALID_RECORD_PENALTY);
                 }
+                return;
             }
-            MdnsEvent::Expired(expired_list) => {
-                for (peer, _addr) in expired_list {
-                    if !self.mdns.has_node(&peer) {
-                        self.floodsub.remove_node_from_partial_view(&peer);
-                    }
-                }
+        };
+        if !learned.is_empty() {
+            log::info!("learned about {} peers from {}", learned.len(), source);
+        }
+        let min_connections = self.pex.config().min_connections;
+        let mut dials = min_connections.saturating_sub(self.gossip.connections());
+        for (peer, addrs) in learned {
+            if !self.allows(&peer) {
+                continue;
+            }
+            for addr in &addrs {
+                self.peer_book.discovered(peer, addr.clone());
+            }
+            if dials > 0 && !self.gossip.contains(&peer) {
+                self.dialer.dial(peer, addrs);
+                dials -= 1;
             }
         }
+        if exchange.request {
+            self.send_peer_sample(source, false);
+        }
     }
-}
 
-pub fn get_peers(swarm: &Swarm<TetherionBehaviour>) -> Vec<String> {
-    let nodes = swarm.behaviour().mdns.discovered_nodes();
-    let mut unique_peers = HashSet::new();
-    for peer in nodes {
-        unique_peers.insert(peer);
+    /// Gets the peers discovered on the local network along with the ones connected to
+    pub fn known_peers(&self) -> HashSet<PeerId> {
+        let discovered = self
+            .mdns
+            .as_ref()
+            .into_iter()
+            .flat_map(Mdns::discovered_nodes);
+        discovered
+            .chain(self.peer_book.connected_peers())
+            .copied()
+            .collect()
     }
-    unique_peers.iter().map(|p| p.to_string()).collect()
-}
 
-pub fn handle_print_peers(swarm: &Swarm<TetherionBehaviour>) {
-    log::info!("Peers:");
-    let peers = get_peers(swarm);
-    peers.iter().for_each(|p| log::info!("{}", p));
-}
+    /// Removes the peer the node disconnected from from the peers it gossips with, gossiping
+    /// with one of the other connected peers in its place
+    pub fn gossip_disconnected(&mut self, peer: &PeerId) {
+        if let Some(promoted) = self.gossip.disconnected(peer) {
+            log::debug!("gossiping with {} in place of {}", promoted, peer);
+        }
+    }
 
-pub fn handle_print_chain(swarm: &Swarm<TetherionBehaviour>) {
-    log::info!("Local Tetherion blockchain:");
-    let json = serde_json::to_string_pretty(&swarm.behaviour().tetherion.blocks())
-        .expect("Blocks should be jsonified");
-    log::info!("{}", json);
-}
+    /// Subscribes to the snapshots of the local blockchain
+    pub fn snapshots(&self) -> watch::Receiver<Arc<ChainSnapshot>> {
+        self.snapshot.subscribe()
+    }
 
-pub fn handle_create_block(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
-    if let Some(data) = cmd.strip_prefix("create b") {
-        let behaviour = swarm.behaviour_mut();
-        let latest_block = behaviour
+    /// Publishes a new snapshot of the local blockchain in case its latest or finalized block
+    /// changed since the previous one, or unconditionally if forced to
+    pub fn publish_snapshot(&self, force: bool) {
+        let finalized = self
+            .finality
+            .as_ref()
+            .and_then(|finality| finality.finalized().cloned());
+        let latest_block = self
             .tetherion
             .blocks()
             .last()
             .expect("there is at least one block");
-        let block = Block::<String>::new(
-            latest_block.id + 1,
-            &latest_block.hash,
-            data.to_owned(),
-            behaviour.tetherion.difficulty(),
-        );
-        let json = serde_json::to_string(&block).expect("can jsonify request");
-        match behaviour.tetherion.add_block(block) {
-            Ok(()) => {
-                log::info!("broadcasting new block");
-                behaviour
-                    .floodsub
-                    .publish(BLOCK_TOPIC.clone(), json.as_bytes());
+        let outdated = {
+            let snapshot = self.snapshot.borrow();
+            snapshot.best().hash != latest_block.hash || snapshot.finalized != finalized
+        };
+
+        if force || outdated {
+            self.snapshot.send_replace(Arc::new(ChainSnapshot {
+                tetherion: self.tetherion.clone(),
+                finalized,
+            }));
+        }
+    }
+
+    /// Brings the search index up to date with the local blockchain
+    pub fn update_search_index(&mut self) {
+        self.search.update(self.tetherion.blocks());
+    }
+
+    /// Gets the Ed25519 public key, in HEX format, identifying the node's account
+    pub fn address(&self) -> String {
+        hex::encode(self.keys.public().encode())
+    }
+
+    /// Gets the nonce of the node's next token operation, accounting for the operations still
+    /// waiting to be mined
+    fn next_nonce(&self) -> u64 {
+        let address = self.address();
+        let pending =
+            mempool::count_operations(self.miner.pending().chain(self.sealing.iter()), &address);
+        self.tetherion.state().next_nonce(&address) + pending
+    }
+
+    /// Seals a block with the queued data packed within the weight budget in case the consensus
+    /// rules allow the node to, dropping the data the block turns out to be invalid with
+    pub fn seal(&mut self) {
+        let state = self.tetherion.state();
+        self.sealing.promote(|account| state.next_nonce(account));
+        self.sealing.expire();
+        if self.sealing.is_empty() {
+            return;
+        }
+
+        let latest_block = self
+            .tetherion
+            .blocks()
+            .last()
+            .expect("there is at least one block");
+        let (id, previous_hash) = (latest_block.id + 1, latest_block.hash.clone());
+        let timestamp = chrono::Utc::now().timestamp();
+        let validators = self.tetherion.validators(id);
+        if !self.tetherion.consensus().can_seal(
+            &latest_block.header(),
+            &self.address(),
+            timestamp,
+            &validators,
+        ) {
+            return;
+        }
+
+        loop {
+            let entries = self
+                .sealing
+                .pack(id, self.tetherion.state(), MAX_BLOCK_WEIGHT);
+            if entries.is_empty() {
+                return;
+            }
+            let data = mempool::combine(entries);
+            let tx_ids = data.tx_ids();
+            let block = Block::sealed(id, &previous_hash, timestamp, data, &self.keys);
+            match self.add_local_block(block) {
+                Ok(()) => return,
+                Err(err) => {
+                    log::error!("{}", err);
+                    let reason = err.to_string();
+                    self.receipts.record(&tx_ids, TxStatus::Dropped { reason });
+                }
             }
-            Err(err) => log::error!("{}", err),
         }
     }
+
+    /// Asks the peer for its blockchain, starting a new sync session
+    pub fn request_chain(&mut self, peer: PeerId) {
+        let session = self.sessions.start();
+        log::info!("[{}] requesting blockchain from {}", session, peer);
+        let latest_block = self
+            .tetherion
+            .blocks()
+            .last()
+            .expect("there is a latest block");
+        let req = SyncHandshake {
+            requester: self.peer_id.to_string(),
+            responder: peer.to_string(),
+            tip: BlockRef {
+                height: latest_block.id,
+                hash: latest_block.hash.clone(),
+            },
+            protocol_version: String::from(PROTOCOL_VERSION),
+            session,
+        };
+        self.publish(self.topics.chain.clone(), &Message::ChainRequest(req));
+    }
+
+    /// Starts the initial sync with the peer once it subscribes to the topic the node's requests
+    /// are published to, the blockchain's one or the block headers' one for light clients, so
+    /// that the request is not published before anyone listens to it
+    fn subscribed(&mut self, peer: PeerId, topic: &Topic) {
+        let requests = match self.light {
+            Some(_) => &self.topics.header,
+            None => &self.topics.chain,
+        };
+        if self.initial_sync != InitialSync::Pending || topic != requests {
+            return;
+        }
+        self.initial_sync = InitialSync::Requested(peer);
+        if self.light.is_some() {
+            log::info!("requesting block headers from {}", peer);
+            let req = HeadersRequest {
+                from_peer_id: peer.to_string(),
+            };
+            self.publish(self.topics.header.clone(), &Message::HeadersRequest(req));
+        } else {
+            self.request_chain(peer);
+        }
+    }
+
+    /// Checks if the producer of the block with the given header signed another block at the same
+    /// height, in which case the evidence is broadcast and queued to be stored in a block
+    fn observe_header(&mut self, header: &BlockHeader) {
+        let validators = self.tetherion.validators(header.id);
+        let producer = match self.tetherion.consensus().producer(header, &validators) {
+            Some(producer) => producer,
+            None => return,
+        };
+        if let Some(evidence) = self.evidence.observe(&producer, header) {
+            log::warn!(
+                "block producer {} signed two blocks at height {}",
+                producer,
+                header.id
+            );
+            let message = Message::Evidence(Box::new(evidence.clone()));
+            self.publish(self.topics.evidence.clone(), &message);
+            self.store_evidence(evidence);
+        }
+    }
+
+    /// Queues the evidence of a block producer's equivocation to be stored in a block
+    fn store_evidence(&mut self, evidence: Evidence) {
+        if let Err(err) = self.mine(Payload::Evidence(Box::new(evidence))) {
+            log::error!("cannot store evidence: {}", err);
+        }
+    }
+
+    /// Adds a block produced by this node to the local blockchain and broadcasts it
+    fn add_local_block(&mut self, block: Block<Payload>) -> Result<(), InvalidBlockError> {
+        let (id, trace) = (block.id, trace::block(&block.hash));
+        self.observe_header(&block.header());
+        let message = Message::Block(BlockFrame {
+            header: block.header(),
+            data: self
+                .wire_format
+                .encode(block.data())
+                .expect("can encode block data"),
+            published_at: Some(chrono::Utc::now().timestamp_millis()),
+        });
+        self.tetherion.add_block(block)?;
+        self.store_latest_block();
+
+        log::info!("[{}] broadcasting new block {}", trace, id);
+        self.publish(self.topics.block.clone(), &message);
+        self.resume_mining();
+        self.vote();
+        Ok(())
+    }
+
+    /// Appends the latest block to the store, if any
+    fn store_latest_block(&self) {
+        if let Some(store) = &self.store {
+            let latest_block = self
+                .tetherion
+                .blocks()
+                .last()
+                .expect("there is at least one block");
+            if let Err(err) = store.append(latest_block) {
+                log::error!("cannot store block {}: {}", latest_block.id, err);
+            }
+        }
+    }
+
+    /// Replaces the stored blocks with the ones of the local blockchain, if there is a store
+    fn store_blockchain(&self) {
+        if let Some(store) = &self.store {
+            if let Err(err) = store.save(&self.tetherion) {
+                log::error!("cannot store blockchain: {}", err);
+            }
+        }
+    }
+
+    /// Votes for the latest block and broadcasts the vote in case the node is a finality voter
+    fn vote(&mut self) {
+        let address = self.address();
+        let finality = match &mut self.finality {
+            Some(finality) if finality.is_voter(&address) => finality,
+            _ => return,
+        };
+
+        let latest_block = self
+            .tetherion
+            .blocks()
+            .last()
+            .expect("there is at least one block");
+        let vote = Vote::new(latest_block, &self.keys);
+        if finality.add_vote(&vote) {
+            self.publish(self.topics.vote.clone(), &Message::Vote(vote));
+            self.update_finality();
+        }
+    }
+
+    /// Finalizes the highest block of the local blockchain voted for by a quorum
+    fn update_finality(&mut self) {
+        if let Some(finality) = &mut self.finality {
+            if let Some(finalized) = finality.update(&self.tetherion) {
+                log::info!("finalized block {} ({})", finalized.height, finalized.hash);
+            }
+        }
+    }
+
+    /// Restarts the miner on top of the local blockchain in case its latest block has changed
+    fn resume_mining(&mut self) {
+        let latest_block = self
+            .tetherion
+            .blocks()
+            .last()
+            .expect("there is at least one block");
+        self.miner.resume(
+            latest_block,
+            self.tetherion.difficulty_at(latest_block.id + 1),
+            self.tetherion.state(),
+        );
+    }
+
+    /// Records the event to the journal, if any
+    pub fn record(&mut self, event: JournalEvent) {
+        if let Some(journal) = &mut self.journal {
+            if let Err(err) = journal.record(event) {
+                log::error!("cannot record to the journal: {}", err);
+            }
+        }
+    }
+
+    /// Checks whether the node has nothing left to do with the events it went through, i.e. no
+    /// data received from the peers is being validated and no block is being mined, so that the
+    /// next event of a journal can be replayed
+    pub fn is_idle(&self) -> bool {
+        self.validation.pending() == 0 && !self.miner.is_mining()
+    }
+
+    /// Feeds an event recorded in a journal back through the node, as if it just happened. The
+    /// node takes the peer ID the journal was recorded with, so that the messages addressed to the
+    /// recording node are handled the same way. Commands are run by the node's event loop.
+    pub fn replay(&mut self, event: JournalEvent) -> Result<(), String> {
+        match event {
+            JournalEvent::Start { peer_id } => {
+                let peer_id = peer_id
+                    .parse()
+                    .map_err(|err| format!("invalid peer ID {}: {}", peer_id, err))?;
+                log::info!("replaying the events of {}", peer_id);
+                self.peer_id = peer_id;
+                self.sessions = Sessions::new(&peer_id.to_string());
+            }
+            JournalEvent::Message {
+                source,
+                topics,
+                data,
+            } => {
+                let msg = FloodsubMessage {
+                    source: source
+                        .parse()
+                        .map_err(|err| format!("invalid peer ID {}: {}", source, err))?,
+                    data: hex::decode(&data).map_err(|err| format!("invalid message: {}", err))?,
+                    sequence_number: Vec::new(),
+                    topics: topics.into_iter().map(Topic::new).collect(),
+                };
+                // The recorded messages outlived their TTL, so their stamps are not checked
+                if let Some(envelope) = self.decode_gossip(&msg) {
+                    self.handle_gossip(msg, envelope.message);
+                }
+            }
+            JournalEvent::Command { .. } | JournalEvent::Rejected { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Resumes mining the candidate block snapshotted before the node restarted, if any, or
+    /// rebuilds it on top of the local blockchain
+    fn restore_candidate(&mut self) {
+        let latest_block = self
+            .tetherion
+            .blocks()
+            .last()
+            .expect("there is at least one block");
+        self.miner.restore(
+            latest_block,
+            self.tetherion.difficulty_at(latest_block.id + 1),
+            self.tetherion.state(),
+        );
+    }
+
+    /// Queues the payloads of the blocks disconnected by a reorg to be stored in a block again,
+    /// ahead of the pending ones
+    fn resurrect(&mut self, payloads: Vec<Payload>) {
+        if payloads.is_empty() {
+            return;
+        }
+        log::info!(
+            "returning {} payloads of disconnected blocks to the mempool",
+            payloads.len()
+        );
+        for payload in &payloads {
+            self.receipts.record(&payload.tx_ids(), TxStatus::InMempool);
+        }
+        if self.tetherion.consensus().is_mined() {
+            self.miner.resurrect(payloads);
+        } else {
+            for payload in payloads.into_iter().rev() {
+                self.sealing.push_front(payload);
+            }
+        }
+    }
+
+    /// Overrides the difficulty of the blocks from the given height on, restarting mining with
+    /// it. Returns `false` if blocks are not mined.
+    pub fn override_difficulty(&mut self, height: u64, difficulty: usize) -> bool {
+        if !self.tetherion.override_difficulty(height, difficulty) {
+            return false;
+        }
+        self.resume_mining();
+        true
+    }
+
+    /// Keeps only the given number of the oldest blocks of the local blockchain, storing the
+    /// remaining ones and mining on top of them
+    pub fn truncate(&mut self, len: usize) -> Result<(), InvalidBlockError> {
+        self.tetherion.truncate(len)?;
+        self.store_blockchain();
+        self.resume_mining();
+        Ok(())
+    }
+
+    /// Applies the blocks and the remote blockchains received from the peers once the validation
+    /// workers are done with them, in the order they were received in
+    pub fn handle_validated(&mut self, checked: Checked) {
+        for (origin, outcome) in self.validation.complete(checked) {
+            let (source, trace) = (origin.peer.to_string(), origin.trace);
+            let validated = match outcome {
+                Ok(validated) => validated,
+                Err(rejected) => {
+                    self.errors.log(
+                        log::Level::Error,
+                        &source,
+                        rejected.kind,
+                        &format_args!("[{}] {}", trace, rejected),
+                    );
+                    if let Some(error) = rejected.error {
+                        self.record_rejected(&source, error);
+                    }
+                    continue;
+                }
+            };
+            match &validated {
+                Validated::Block(block) => self.alerts.advertised(block.id),
+                Validated::Chain { remote, .. } => self.alerts.advertised(remote.height()),
+            }
+            match validated {
+                Validated::Block(block) => {
+                    let (id, hash) = (block.id, block.hash.clone());
+                    match self
+                        .tetherion
+                        .add_block_with(block, &|block| self.validation.has_valid_hash(block))
+                    {
+                        Ok(()) => {
+                            log::info!("[{}] applied block {} from {}", trace, id, source);
+                            self.relay_block(&hash);
+                            self.store_latest_block();
+                            self.resume_mining();
+                            self.vote();
+                        }
+                        Err(err) => {
+                            self.errors.log(
+                                log::Level::Error,
+                                &source,
+                                err.kind(),
+                                &format_args!("[{}] Error {}", trace, err),
+                            );
+                            self.relay.reject(&hash);
+                            self.record_rejected(&source, err);
+                        }
+                    }
+                }
+                Validated::Chain { remote, validity } => {
+                    if let Some(finality) = &self.finality {
+                        if !finality.is_compatible(&remote) {
+                            self.errors.log(
+                                log::Level::Error,
+                                &source,
+                                "reverts_finalized",
+                                &format_args!(
+                                    "[{}] Remote blockchain reverts the finalized block",
+                                    trace
+                                ),
+                            );
+                            continue;
+                        }
+                    }
+
+                    if let Err(err) = &validity {
+                        self.record_rejected(&source, err.clone());
+                    }
+                    if self.is_better_than(&remote, validity) {
+                        log::info!(
+                            "[{}] keeping the local blockchain over the one from {}",
+                            trace,
+                            source
+                        );
+                    } else {
+                        log::info!(
+                            "[{}] adopted the blockchain of height {} from {}",
+                            trace,
+                            remote.height(),
+                            source
+                        );
+                        let disconnected = mempool::disconnected(&self.tetherion, &remote);
+                        self.tetherion = *remote;
+                        self.store_blockchain();
+                        self.resurrect(disconnected);
+                        self.resume_mining();
+                        self.update_finality();
+                        self.vote();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records to the journal, if any, the block the peer sent getting rejected, along with the
+    /// context of the violated rule
+    fn record_rejected(&mut self, peer: &str, error: InvalidBlockError) {
+        self.record(JournalEvent::Rejected {
+            peer: peer.to_owned(),
+            error,
+        });
+    }
+
+    /// Checks whether remote blockchain, having the given validity, is worse than the local one:
+    /// 1. by the validity
+    /// 2. in case both blockchains are valid, by the work their blocks took, from the highest of
+    ///    their first blocks on, which is the height unless blocks are mined
+    /// 3. in case both blockchains took the same work, by the olderness
+    fn is_better_than(
+        &self,
+        remote: &Tetherion<Payload>,
+        remote_validity: Result<(), InvalidBlockError>,
+    ) -> bool {
+        let local_validity = self
+            .tetherion
+            .is_valid_with(&|block| self.validation.has_valid_hash(block));
+        match (local_validity, remote_validity) {
+            (Ok(()), Ok(())) => self.tetherion.is_better_than(remote),
+            (Ok(()), Err(err)) => {
+                log::debug!("Remote blockchain is invalid: {}", err);
+                true
+            }
+            (Err(err), Ok(())) => {
+                log::debug!("Local blockchain is invalid: {}", err);
+                false
+            }
+            (Err(local_err), Err(remote_err)) => {
+                panic!(
+                    "Local blockchain is invalid: {}, remote blockchain is invalid: {}",
+                    local_err, remote_err
+                );
+            }
+        }
+    }
+
+    /// Prints where the local chain and the one of the peer, given by its headers, diverge
+    fn report_divergence(&self, peer: PeerId, headers: &[BlockHeader], output: Output) {
+        let local: Vec<_> = match &self.light {
+            Some(light) => light.headers().clone(),
+            None => self
+                .tetherion
+                .blocks()
+                .iter()
+                .map(|block| block.header())
+                .collect(),
+        };
+        let diff = match divergence::diverge(
+            &local,
+            headers,
+            |header| self.tetherion.work_at(header.id),
+            |header| self.tetherion.has_valid_work(header),
+        ) {
+            Some(diff) => diff,
+            None => {
+                eprintln!("{} sent no headers", peer);
+                return;
+            }
+        };
+        if output == Output::Json {
+            println!(
+                "{}",
+                serde_json::to_string(&diff).expect("can jsonify divergence")
+            );
+            return;
+        }
+        print_divergence(peer, &diff);
+    }
+
+    /// Handles a message exchanged with light clients
+    fn handle_headers_message(&mut self, source: PeerId, message: Message) {
+        match message {
+            Message::ProofResponse(resp) => {
+                if let Some(light) = &self.light {
+                    if resp.receiver == self.peer_id.to_string() {
+                        match resp.proof {
+                            Some(proof) if light.verify_proof(&resp.block_hash, &proof) => {
+                                println!(
+                                    "transaction {} is included in block {}",
+                                    resp.tx_id, resp.block_hash
+                                )
+                            }
+                            Some(_) => eprintln!(
+                                "invalid proof of transaction {} in block {}",
+                                resp.tx_id, resp.block_hash
+                            ),
+                            None => println!(
+                                "transaction {} is not included in block {}",
+                                resp.tx_id, resp.block_hash
+                            ),
+                        }
+                    }
+                }
+            }
+            Message::ProofRequest(req)
+                if self.light.is_none() && req.from_peer_id == self.peer_id.to_string() =>
+            {
+                log::info!("sending proof to {}", source);
+                let resp = ProofResponse {
+                    proof: self
+                        .tetherion
+                        .blocks()
+                        .iter()
+                        .find(|block| block.hash == req.block_hash)
+                        .and_then(|block| block.prove(&req.tx_id)),
+                    block_hash: req.block_hash,
+                    tx_id: req.tx_id,
+                    receiver: source.to_string(),
+                };
+                self.publish(self.topics.header.clone(), &Message::ProofResponse(resp));
+            }
+            Message::HeadersResponse(resp) => {
+                if resp.receiver != self.peer_id.to_string() {
+                    return;
+                }
+                if let Some(output) = self.pending_diffs.remove(&source) {
+                    self.report_divergence(source, &resp.headers, output);
+                }
+                if let Some(light) = &mut self.light {
+                    if resp.receiver == self.peer_id.to_string() {
+                        log::info!("Headers response from {}", source);
+                        if light.sync(resp.headers) {
+                            log::info!("synced {} headers", light.headers().len());
+                        }
+                    }
+                }
+            }
+            Message::HeadersRequest(req)
+                if self.light.is_none() && req.from_peer_id == self.peer_id.to_string() =>
+            {
+                let peer = source.to_string();
+                if let Err(err) = self.serving.admit(&peer, std::time::Instant::now()) {
+                    self.errors.log(
+                        log::Level::Warn,
+                        &peer,
+                        err.kind(),
+                        &format_args!("not sending local headers to {}: {}", source, err),
+                    );
+                    return;
+                }
+                // The headers are published right away, so they are served already
+                self.serving.served(&peer);
+                log::info!("sending local headers to {}", source);
+                let resp = HeadersResponse {
+                    headers: self
+                        .tetherion
+                        .blocks()
+                        .iter()
+                        .map(|block| block.header())
+                        .collect(),
+                    receiver: source.to_string(),
+                };
+                self.publish(self.topics.header.clone(), &Message::HeadersResponse(resp));
+            }
+            _ => {}
+        }
+    }
+}
+
+// incoming event handler
+impl NetworkBehaviourEventProcess<FloodsubEvent> for TetherionBehaviour {
+    fn inject_event(&mut self, event: FloodsubEvent) {
+        if let FloodsubEvent::Subscribed { peer_id, topic } = &event {
+            self.subscribed(*peer_id, topic);
+        }
+        if let FloodsubEvent::Message(msg) = event {
+            let envelope = match self.decode_gossip(&msg) {
+                Some(envelope) => envelope,
+                None => return,
+            };
+            let now = chrono::Utc::now().timestamp_millis();
+            if let Err(err) = self.replays.check(&envelope.stamp, now) {
+                log::debug!(
+                    "dropping message {} of {} from {}: {}",
+                    envelope.stamp.seq,
+                    envelope.stamp.origin,
+                    msg.source,
+                    err
+                );
+                return;
+            }
+            let topics: Vec<_> = msg
+                .topics
+                .iter()
+                .map(|topic| topic.id().to_owned())
+                .collect();
+            // Blocks are relayed once validated
+            let fresh = match &envelope.message {
+                Message::Block(frame) => self.relay.hold(&frame.header.hash, &topics, &msg.data),
+                _ => self
+                    .relay
+                    .received(&topics, &msg.data, std::time::Instant::now()),
+            };
+            if !fresh {
+                log::debug!("dropping message from {} received already", msg.source);
+                return;
+            }
+            self.record(JournalEvent::Message {
+                source: msg.source.to_string(),
+                topics,
+                data: hex::encode(&msg.data),
+            });
+            self.handle_gossip(msg, envelope.message);
+            self.relay_now();
+        }
+    }
+}
+
+impl TetherionBehaviour {
+    /// Decodes a message gossiped by a peer, penalizing the peer if it cannot be decoded
+    fn decode_gossip(&mut self, msg: &FloodsubMessage) -> Option<Envelope<Message>> {
+        match self.wire_format.decode(&msg.data) {
+            Ok(envelope) => Some(envelope),
+            Err(err) => {
+                self.errors.log(
+                    log::Level::Warn,
+                    &msg.source.to_string(),
+                    "undecodable",
+                    &format_args!("dropping message from {}: {}", msg.source, err),
+                );
+                self.peer_book
+                    .penalize(msg.source, peers::UNDECODABLE_PENALTY);
+                None
+            }
+        }
+    }
+
+    /// Checks the sync handshake received from the peer, returning whether it is addressed to
+    /// the node
+    fn accept_handshake(
+        &self,
+        req: &SyncHandshake,
+        source: PeerId,
+    ) -> Result<bool, HandshakeError> {
+        if req.responder()? != self.peer_id {
+            return Ok(false);
+        }
+        req.validate(&source, PROTOCOL_VERSION)?;
+        Ok(true)
+    }
+
+    /// Sends the local blockchain to the peer that asked for it with the handshake, unless the
+    /// peer has the same tip already
+    fn respond_chain(&mut self, req: &SyncHandshake, source: PeerId) {
+        let latest_block = self
+            .tetherion
+            .blocks()
+            .last()
+            .expect("there is a latest block");
+        if req.tip.hash == latest_block.hash {
+            log::info!(
+                "[{}] {} is in sync already, not sending local chain",
+                req.session,
+                source
+            );
+            return;
+        }
+        let peer = source.to_string();
+        if let Err(err) = self.serving.admit_chain(&peer, std::time::Instant::now()) {
+            self.errors.log(
+                log::Level::Warn,
+                &peer,
+                err.kind(),
+                &format_args!(
+                    "[{}] not sending local chain to {}: {}",
+                    req.session, source, err
+                ),
+            );
+            return;
+        }
+        log::info!("[{}] sending local chain to {}", req.session, source);
+        let resp = ChainResponse {
+            tetherion: self.tetherion.clone(),
+            receiver: source.to_string(),
+            session: req.session.clone(),
+        };
+        match self.response_sender.try_send(resp) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.serving.served(&peer);
+                self.errors.log(
+                    log::Level::Warn,
+                    &peer,
+                    "queue_full",
+                    &format_args!(
+                        "[{}] dropping chain request from {}, {} responses are queued already",
+                        req.session,
+                        source,
+                        self.response_sender.max_capacity()
+                    ),
+                )
+            }
+            Err(e) => {
+                self.serving.served(&peer);
+                log::error!("error sending response via channel, {}", e)
+            }
+        }
+    }
+
+    /// Handles a message gossiped by a peer
+    fn handle_gossip(&mut self, msg: FloodsubMessage, message: Message) {
+        if msg.topics.contains(&self.topics.header) {
+            self.handle_headers_message(msg.source, message);
+            return;
+        }
+        if msg.topics.contains(&self.topics.pex) {
+            if let Message::PeerExchange(exchange) = message {
+                self.handle_peer_exchange(msg.source, exchange);
+            }
+            return;
+        }
+        match message {
+            Message::Evidence(evidence)
+                if self.light.is_none() && self.evidence.add((*evidence).clone()) =>
+            {
+                log::warn!(
+                    "received evidence of block producer {} signing two blocks at height {}",
+                    evidence.offender,
+                    evidence.height()
+                );
+                self.store_evidence(*evidence);
+            }
+            Message::Vote(vote) => {
+                if let Some(finality) = &mut self.finality {
+                    if finality.add_vote(&vote) {
+                        self.update_finality();
+                    }
+                }
+            }
+            Message::ChainFrame(frame) if frame.receiver == self.peer_id.to_string() => {
+                if frame.chain_id != self.chain_id {
+                    self.errors.log(
+                        log::Level::Error,
+                        &msg.source.to_string(),
+                        "chain_id_mismatch",
+                        &format_args!(
+                            "[{}] dropping blockchain of chain {} from {}",
+                            frame.session, frame.chain_id, msg.source
+                        ),
+                    );
+                    return;
+                }
+                let (difficulty, session) = (frame.difficulty, frame.session.clone());
+                let blocks = match self.incoming.add(&msg.source.to_string(), frame) {
+                    Some(blocks) if !blocks.is_empty() => blocks,
+                    _ => return,
+                };
+                log::info!(
+                    "[{}] received blockchain of {} blocks from {}",
+                    session,
+                    blocks.len(),
+                    msg.source
+                );
+
+                let job = Job::Chain {
+                    blocks,
+                    difficulty,
+                    local_difficulty: self.tetherion.difficulty(),
+                    // A checkpoint, if any, is checked instead
+                    genesis: match &self.checkpoint {
+                        Some(_) => None,
+                        None => Some(self.tetherion.blocks()[0].hash.clone()),
+                    },
+                    consensus: self.tetherion.consensus().clone(),
+                    initial_state: Box::new(self.tetherion.initial_state().clone()),
+                    checkpoint: self.checkpoint.clone().map(Box::new),
+                };
+                let origin = Origin {
+                    peer: msg.source,
+                    trace: session.clone(),
+                };
+                if let Err(err) = self.validation.submit(job, origin) {
+                    self.errors.log(
+                        log::Level::Warn,
+                        &msg.source.to_string(),
+                        "queue_full",
+                        &format_args!(
+                            "[{}] dropping blockchain from {}: {}",
+                            session, msg.source, err
+                        ),
+                    );
+                }
+            }
+            Message::ChainRequest(req) => match self.accept_handshake(&req, msg.source) {
+                Ok(true) => self.respond_chain(&req, msg.source),
+                Ok(false) => {}
+                Err(err) => {
+                    self.errors.log(
+                        log::Level::Warn,
+                        &msg.source.to_string(),
+                        err.kind(),
+                        &format_args!("dropping chain request from {}: {}", msg.source, err),
+                    );
+                    if err.is_misbehaviour() {
+                        self.peer_book
+                            .penalize(msg.source, peers::INVALID_RECORD_PENALTY);
+                    }
+                }
+            },
+            Message::Block(frame) => {
+                let header = frame.header;
+                let trace = trace::block(&header.hash);
+                log::info!(
+                    "[{}] received block {} from {}",
+                    trace,
+                    header.id,
+                    msg.source
+                );
+                // The data is only decoded once the header proves the work put into the block
+                if !self.tetherion.has_valid_work(&header) {
+                    self.errors.log(
+                        log::Level::Warn,
+                        &msg.source.to_string(),
+                        "insufficient_work",
+                        &format_args!(
+                            "[{}] dropping block from {}: header lacks Proof of Work",
+                            trace, msg.source
+                        ),
+                    );
+                    self.peer_book
+                        .penalize(msg.source, peers::INVALID_HEADER_PENALTY);
+                    self.relay.reject(&header.hash);
+                    return;
+                }
+                if let Some(published_at) = frame.published_at {
+                    self.propagation.record(
+                        header.id,
+                        &header.hash,
+                        &msg.source.to_string(),
+                        published_at,
+                        chrono::Utc::now().timestamp_millis(),
+                    );
+                }
+                if let Some(light) = &mut self.light {
+                    self.alerts.advertised(header.id);
+                    let hash = header.hash.clone();
+                    match light.add_header(header) {
+                        Ok(()) => self.relay_block(&hash),
+                        Err(err) => {
+                            self.errors.log(
+                                log::Level::Error,
+                                &msg.source.to_string(),
+                                err.kind(),
+                                &format_args!("[{}] Error {}", trace, err),
+                            );
+                            self.peer_book
+                                .penalize(msg.source, peers::INVALID_HEADER_PENALTY);
+                            self.relay.reject(&hash);
+                        }
+                    }
+                    return;
+                }
+
+                let data = match self.wire_format.decode::<Payload>(&frame.data) {
+                    Ok(data) => data,
+                    Err(err) => {
+                        self.errors.log(
+                            log::Level::Warn,
+                            &msg.source.to_string(),
+                            "undecodable",
+                            &format_args!(
+                                "[{}] dropping block from {}: {}",
+                                trace, msg.source, err
+                            ),
+                        );
+                        self.peer_book
+                            .penalize(msg.source, peers::UNDECODABLE_PENALTY);
+                        self.relay.reject(&header.hash);
+                        return;
+                    }
+                };
+                self.observe_header(&header);
+                let origin = Origin {
+                    peer: msg.source,
+                    trace: trace.clone(),
+                };
+                let block = Block::from_header(header, data);
+                let hash = block.hash.clone();
+                if let Err(err) = self.validation.submit(Job::Block(block), origin) {
+                    self.errors.log(
+                        log::Level::Warn,
+                        &msg.source.to_string(),
+                        "queue_full",
+                        &format_args!("[{}] dropping block from {}: {}", trace, msg.source, err),
+                    );
+                    self.relay.reject(&hash);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<PingEvent> for TetherionBehaviour {
+    fn inject_event(&mut self, event: PingEvent) {
+        match event.result {
+            Ok(PingSuccess::Ping { rtt }) => self.peer_book.measured(event.peer, Some(rtt)),
+            Ok(PingSuccess::Pong) => {}
+            Err(err) => {
+                log::debug!("cannot ping {}: {}", event.peer, err);
+                self.peer_book.measured(event.peer, None);
+            }
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<IdentifyEvent> for TetherionBehaviour {
+    fn inject_event(&mut self, event: IdentifyEvent) {
+        if let IdentifyEvent::Received { peer_id, info } = event {
+            self.peer_book.identified(peer_id, info);
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<MdnsEvent> for TetherionBehaviour {
+    fn inject_event(&mut self, event: MdnsEvent) {
+        match event {
+            MdnsEvent::Discovered(discovered_list) => {
+                let mut discovered = HashSet::new();
+                for (peer, addr) in discovered_list {
+                    if !self.allows(&peer) || self.ban_of(&peer, Some(&addr)).is_some() {
+                        continue;
+                    }
+                    if self.watch_peers {
+                        println!("{} peer discovered {} at {}", watch_time(), peer, addr);
+                    }
+                    self.peer_book.discovered(peer, addr);
+                    discovered.insert(peer);
+                }
+                // The peers are dialed at all the addresses they are known at, the ones of the
+                // preferred IP version first
+                for peer in discovered {
+                    if !self.gossip.contains(&peer) {
+                        self.dialer.dial(peer, self.peer_book.addresses(&peer));
+                    }
+                }
+            }
+            MdnsEvent::Expired(expired_list) => {
+                for (peer, _addr) in expired_list {
+                    let expired = !self.mdns.as_ref().is_some_and(|mdns| mdns.has_node(&peer));
+                    if expired && self.watch_peers {
+                        println!("{} peer expired {}", watch_time(), peer);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Gets the local time an event is printed at by the `watch` commands, with milliseconds so that
+/// the propagation across nodes can be followed
+pub fn watch_time() -> String {
+    chrono::Local::now().format("[%H:%M:%S%.3f]").to_string()
+}
+
+pub fn get_peers(swarm: &Swarm<TetherionBehaviour>) -> Vec<String> {
+    let unique_peers = swarm.behaviour().known_peers();
+    unique_peers.iter().map(|p| p.to_string()).collect()
+}
+
+/// Handles `ls p`, printing the discovered peers
+pub fn handle_print_peers(swarm: &Swarm<TetherionBehaviour>, output: Output) {
+    let peers = get_peers(swarm);
+    if output == Output::Json {
+        println!(
+            "{}",
+            serde_json::to_string(&peers).expect("can jsonify peers")
+        );
+        return;
+    }
+
+    println!("Peers:");
+    peers.iter().for_each(|p| println!("{}", p));
+}
+
+/// Handles `ls p -v`, printing the details of each peer the node learned about
+pub fn handle_print_peer_details(swarm: &Swarm<TetherionBehaviour>, output: Output) {
+    let behaviour = swarm.behaviour();
+    let details = behaviour.peer_book.details(
+        &behaviour.peer_filter,
+        &behaviour.reconnect,
+        std::time::Instant::now(),
+    );
+    if output == Output::Json {
+        println!(
+            "{}",
+            serde_json::to_string(&details).expect("can jsonify peer details")
+        );
+        return;
+    }
+
+    println!("Peers:");
+    for peer in details {
+        let direction = match peer.direction {
+            Some(peers::Direction::Inbound) => "inbound",
+            Some(peers::Direction::Outbound) => "outbound",
+            None => "not connected",
+        };
+        let rtt = peer
+            .rtt_ms
+            .map_or_else(|| String::from("-"), |rtt| format!("{} ms", rtt));
+        println!("{}", peer.peer_id);
+        println!("  addresses: {}", peer.addresses.join(", "));
+        println!("  direction: {}", direction);
+        println!("  protocols: {}", peer.protocols.join(", "));
+        println!(
+            "  agent: {}",
+            peer.agent_version.as_deref().unwrap_or("unknown")
+        );
+        println!("  rtt: {}", rtt);
+        println!("  score: {}", peer.score);
+        println!("  tag: {}", peer.tag.name());
+        if let Some(retry) = peer.reconnect {
+            println!(
+                "  reconnect: {} retries made, next one in {} ms",
+                retry.attempts, retry.next_retry_ms
+            );
+        }
+    }
+}
+
+/// Publishes the local blockchain to the peer requesting it, frame by frame
+pub fn publish_chain_response(resp: ChainResponse, swarm: &mut Swarm<TetherionBehaviour>) {
+    let blocks = resp.tetherion.blocks();
+    log::info!(
+        "[{}] sending {} blocks to {}",
+        resp.session,
+        blocks.len(),
+        resp.receiver
+    );
+    let difficulty = resp.tetherion.difficulty();
+    let topic = swarm.behaviour().topics.chain.clone();
+    let chain_id = swarm.behaviour().chain_id.clone();
+    for frame in sync::frames(blocks, &chain_id, difficulty, &resp.receiver, &resp.session) {
+        swarm
+            .behaviour_mut()
+            .publish(topic.clone(), &Message::ChainFrame(frame));
+    }
+    swarm.behaviour_mut().serving.served(&resp.receiver);
+}
+
+/// Handles `ls c [--full]`, printing the local blockchain, or the block headers of a light
+/// client, as a table, or in full as JSON
+pub fn handle_print_chain(cmd: &str, swarm: &Swarm<TetherionBehaviour>, output: Output) {
+    if output == Output::Json {
+        // The blocks are written to stdout as they get jsonified, without buffering the document
+        let mut stdout = io::stdout().lock();
+        let written = match &swarm.behaviour().light {
+            Some(light) => serde_json::to_writer(&mut stdout, light.headers()),
+            None => serde_json::to_writer(&mut stdout, swarm.behaviour().tetherion.blocks()),
+        };
+        if let Err(err) = written
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(stdout))
+        {
+            eprintln!("cannot print the blockchain: {}", err);
+        }
+        return;
+    }
+
+    if !cmd.ends_with("--full") {
+        let now = chrono::Utc::now().timestamp();
+        let colored = io::stdout().is_terminal();
+        let printout = match &swarm.behaviour().light {
+            Some(light) => printout::format_headers(light.headers(), now, colored),
+            None => {
+                let tetherion = &swarm.behaviour().tetherion;
+                printout::format_blocks(tetherion.blocks(), &tetherion.verify(), now, colored)
+            }
+        };
+        print!("{}", printout);
+        return;
+    }
+
+    if let Some(light) = &swarm.behaviour().light {
+        println!("Local Tetherion block headers:");
+        let json = logfmt::to_json(light.headers()).expect("Headers should be jsonified");
+        println!("{}", json);
+        return;
+    }
+
+    // Blocks are printed one by one, so that the whole blockchain is never jsonified at once
+    println!("Local Tetherion blockchain:");
+    for block in swarm.behaviour().tetherion.blocks() {
+        let json = logfmt::to_json(block).expect("Block should be jsonified");
+        println!("{}", json);
+    }
+}
+
+/// Gets the latest block of the local blockchain and the latest finalized one, if any
+pub fn chain_tips(swarm: &Swarm<TetherionBehaviour>) -> (BlockRef, Option<BlockRef>) {
+    let behaviour = swarm.behaviour();
+    let latest_block = behaviour
+        .tetherion
+        .blocks()
+        .last()
+        .expect("there is at least one block");
+    let best = BlockRef {
+        height: latest_block.id,
+        hash: latest_block.hash.clone(),
+    };
+    let finalized = behaviour
+        .finality
+        .as_ref()
+        .and_then(|finality| finality.finalized().cloned());
+    (best, finalized)
+}
+
+/// Rebuilds the state derived from the blocks of the local blockchain, validating the blocks
+/// along the way. Returns whether the rebuilt state differs from the one it replaces.
+pub fn reindex(swarm: &mut Swarm<TetherionBehaviour>) -> Result<bool, InvalidBlockError> {
+    let behaviour = swarm.behaviour_mut();
+    let changed = behaviour.tetherion.reindex()?;
+    behaviour.resume_mining();
+    behaviour.publish_snapshot(true);
+    Ok(changed)
+}
+
+/// Handles `reindex`, rebuilding the state derived from the blocks of the local blockchain
+pub fn handle_reindex(swarm: &mut Swarm<TetherionBehaviour>) {
+    let blocks = swarm.behaviour().tetherion.blocks().len();
+    match reindex(swarm) {
+        Ok(true) => println!(
+            "reindexed {} blocks, the previous state was inconsistent",
+            blocks
+        ),
+        Ok(false) => println!("reindexed {} blocks, the state is consistent", blocks),
+        Err(err) => eprintln!("cannot reindex: {}", err),
+    }
+}
+
+/// Handles `verify chain [--fix]`, validating every block of the local blockchain and printing
+/// each invalid one along with the rule it violates. Returns the number of blocks to keep in case
+/// the blockchain should be truncated at the first invalid block once the user confirms it.
+pub fn handle_verify_chain(cmd: &str, swarm: &Swarm<TetherionBehaviour>) -> Option<usize> {
+    let fix = match cmd.strip_prefix("verify chain").unwrap_or_default().trim() {
+        "" => false,
+        "--fix" => true,
+        arg => {
+            eprintln!("invalid argument: {}", arg);
+            return None;
+        }
+    };
+
+    let tetherion = &swarm.behaviour().tetherion;
+    let violations = tetherion.verify();
+    if violations.is_empty() {
+        println!("all {} blocks are valid", tetherion.blocks().len());
+        return None;
+    }
+    for (_, err) in &violations {
+        println!("{}", err);
+    }
+    println!(
+        "{} of {} blocks are invalid",
+        violations.len(),
+        tetherion.blocks().len()
+    );
+
+    let first = violations[0].0;
+    if !fix {
+        return None;
+    }
+    if first == 0 {
+        eprintln!("cannot fix a blockchain whose first block is invalid");
+        return None;
+    }
+    println!(
+        "type `yes` to drop the {} blocks following block {}",
+        tetherion.blocks().len() - first,
+        tetherion.blocks()[first - 1].id
+    );
+    Some(first)
+}
+
+/// Handles `difficulty show`, printing the difficulty of the next block along with the heights
+/// the difficulty changed at
+pub fn handle_difficulty_show(swarm: &Swarm<TetherionBehaviour>) {
+    let tetherion = &swarm.behaviour().tetherion;
+    let pow = match tetherion.consensus().proof_of_work() {
+        Some(pow) => pow,
+        None => {
+            println!(
+                "blocks are not mined by the {} engine",
+                tetherion.consensus().name()
+            );
+            return;
+        }
+    };
+
+    let next = tetherion.height() + 1;
+    println!(
+        "difficulty of block {}: {}",
+        next,
+        tetherion.difficulty_at(next)
+    );
+    for (height, difficulty) in pow.schedule() {
+        println!("  from block {}: {}", height, difficulty);
+    }
+}
+
+/// Handles `difficulty set <n>`, overriding the difficulty of the blocks following the latest one
+/// on dev networks. Returns the height the difficulty is overridden from along with the difficulty,
+/// for the other nodes of the dev network to override it from the same height whatever their own
+/// tips.
+pub fn handle_difficulty_set(
+    cmd: &str,
+    dev: bool,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Option<(u64, usize)> {
+    if !dev {
+        eprintln!("the difficulty can only be set on dev networks");
+        return None;
+    }
+    let arg = cmd
+        .strip_prefix("difficulty set")
+        .unwrap_or_default()
+        .trim();
+    let difficulty = match arg.parse::<usize>() {
+        Ok(difficulty) => difficulty,
+        Err(_) => {
+            eprintln!("invalid difficulty: {}", arg);
+            return None;
+        }
+    };
+
+    let behaviour = swarm.behaviour_mut();
+    let height = behaviour.tetherion.height() + 1;
+    if !behaviour.override_difficulty(height, difficulty) {
+        eprintln!(
+            "blocks are not mined by the {} engine",
+            behaviour.tetherion.consensus().name()
+        );
+        return None;
+    }
+    println!("difficulty set to {} from block {}", difficulty, height);
+    Some((height, difficulty))
+}
+
+/// Truncates the local blockchain to the given number of blocks, as confirmed by the user
+pub fn handle_truncate(len: usize, swarm: &mut Swarm<TetherionBehaviour>) {
+    match swarm.behaviour_mut().truncate(len) {
+        Ok(()) => println!(
+            "truncated the blockchain to block {}",
+            swarm.behaviour().tetherion.height()
+        ),
+        Err(err) => eprintln!("cannot truncate the blockchain: {}", err),
+    }
+}
+
+/// Compacts the store the blocks are kept in, if any
+pub fn compact_storage(swarm: &Swarm<TetherionBehaviour>) {
+    let behaviour = swarm.behaviour();
+    if let Some(store) = &behaviour.store {
+        match store.compact(&behaviour.tetherion) {
+            Ok(0) => {}
+            Ok(reclaimed) => log::info!("compacted the storage, reclaiming {} bytes", reclaimed),
+            Err(err) => log::error!("cannot compact the storage: {}", err),
+        }
+    }
+}
+
+/// Gets the disk usage of the store the blocks are kept in, by component. Returns `None` if the
+/// blocks are not stored on disk.
+pub fn storage_stats(swarm: &Swarm<TetherionBehaviour>) -> Option<io::Result<StorageStats>> {
+    swarm.behaviour().store.as_ref().map(BlockStore::stats)
+}
+
+/// Handles `storage stats`, printing the disk usage of the store by component
+pub fn handle_storage_stats(swarm: &Swarm<TetherionBehaviour>) {
+    match storage_stats(swarm) {
+        Some(Ok(stats)) => println!(
+            "Storage: {} bytes (blocks: {}, WAL: {}, temporary: {}, backups: {}, archive: {})",
+            stats.total(),
+            stats.blocks,
+            stats.wal,
+            stats.temporary,
+            stats.backups,
+            stats.archive
+        ),
+        Some(Err(err)) => eprintln!("cannot get storage stats: {}", err),
+        None => println!("the blockchain is not stored on disk"),
+    }
+}
+
+/// Handles `archive verify`, checking the archived segments of the store against their index
+pub fn handle_archive_verify(swarm: &Swarm<TetherionBehaviour>) {
+    let store = match &swarm.behaviour().store {
+        Some(store) => store,
+        None => {
+            println!("the blockchain is not stored on disk");
+            return;
+        }
+    };
+
+    match store.verify_archive() {
+        Ok(segments) if segments.is_empty() => println!("there are no archived blocks"),
+        Ok(segments) => {
+            for (segment, result) in segments {
+                match result {
+                    Ok(()) => println!("Segment {}-{}: ok", segment.first, segment.last),
+                    Err(reason) => {
+                        eprintln!("Segment {}-{}: {}", segment.first, segment.last, reason)
+                    }
+                }
+            }
+        }
+        Err(err) => eprintln!("cannot read the archive: {}", err),
+    }
+}
+
+/// The progress of the synchronization with the peers
+#[derive(Serialize, Debug)]
+pub struct SyncStatus {
+    /// The latest block, or block header of a light client
+    pub best: BlockRef,
+
+    /// The latest finalized block, if any
+    pub finalized: Option<BlockRef>,
+
+    /// The number of discovered peers
+    pub peers: usize,
+
+    /// The number of remote blockchains being received frame by frame
+    pub receiving: usize,
+
+    /// The number of received blocks and blockchains waiting to be validated
+    pub validating: u64,
+
+    /// Whether the node is a light client, syncing only block headers
+    pub light: bool,
+
+    /// The peer the node synced with on startup, if any connected yet
+    pub initial_peer: Option<String>,
+}
+
+/// Gets the progress of the synchronization with the peers
+pub fn sync_status(swarm: &Swarm<TetherionBehaviour>) -> SyncStatus {
+    let (best, finalized) = chain_tips(swarm);
+    let behaviour = swarm.behaviour();
+    let best = match behaviour
+        .light
+        .as_ref()
+        .and_then(|light| light.headers().last())
+    {
+        Some(header) => BlockRef {
+            height: header.id,
+            hash: header.hash.clone(),
+        },
+        None => best,
+    };
+    SyncStatus {
+        best,
+        finalized,
+        peers: get_peers(swarm).len(),
+        receiving: behaviour.incoming.pending(),
+        validating: behaviour.validation.pending(),
+        light: behaviour.light.is_some(),
+        initial_peer: match behaviour.initial_sync {
+            InitialSync::Pending => None,
+            InitialSync::Requested(peer) => Some(peer.to_string()),
+        },
+    }
+}
+
+/// Handles `sync status`, printing the progress of the synchronization with the peers
+pub fn handle_sync_status(swarm: &Swarm<TetherionBehaviour>, output: Output) {
+    let status = sync_status(swarm);
+    if output == Output::Json {
+        println!(
+            "{}",
+            serde_json::to_string(&status).expect("can jsonify sync status")
+        );
+        return;
+    }
+
+    println!(
+        "Synced up to block {} ({}) from {} peers",
+        status.best.height, status.best.hash, status.peers
+    );
+    println!(
+        "Receiving {} blockchains, validating {} blocks and blockchains",
+        status.receiving, status.validating
+    );
+    match &status.initial_peer {
+        Some(peer) => println!("Synced with {} on startup", peer),
+        None => println!("Waiting for a peer to sync with on startup"),
+    }
+}
+
+/// Handles `propagation stats`, printing the histogram of the delays the blocks received from the
+/// peers took to propagate, along with the latest measured blocks
+pub fn handle_propagation_stats(swarm: &Swarm<TetherionBehaviour>, output: Output) {
+    let stats = &swarm.behaviour().propagation;
+    if output == Output::Json {
+        println!(
+            "{}",
+            serde_json::to_string(stats).expect("can jsonify propagation stats")
+        );
+        return;
+    }
+
+    let mean = match stats.mean_ms() {
+        Some(mean) => mean,
+        None => return println!("No block received from the peers yet"),
+    };
+    let percentile = |share| match stats.percentile_ms(share) {
+        Some(bound) => format!("<= {}ms", bound),
+        None => format!(
+            "> {}ms",
+            propagation::BUCKET_BOUNDS[propagation::BUCKET_BOUNDS.len() - 1]
+        ),
+    };
+    println!(
+        "{} blocks, mean {}ms, max {}ms, p50 {}, p90 {}, p99 {}",
+        stats.count,
+        mean,
+        stats.max_ms,
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99)
+    );
+    let mut lower = 0;
+    for (bound, count) in propagation::BUCKET_BOUNDS.iter().zip(&stats.buckets) {
+        println!("{:>6}-{}ms: {}", lower, bound, count);
+        lower = *bound;
+    }
+    println!(
+        "{:>6}ms and over: {}",
+        lower,
+        stats.buckets[propagation::BUCKET_BOUNDS.len()]
+    );
+    for delay in &stats.recent {
+        println!(
+            "block {} ({}) from {} in {}ms",
+            delay.height, delay.hash, delay.source, delay.delay_ms
+        );
+    }
+}
+
+/// Handles `ls stats`, printing the best and finalized tips of the local blockchain
+pub fn handle_print_stats(swarm: &Swarm<TetherionBehaviour>) {
+    let (best, finalized) = chain_tips(swarm);
+    println!("Best tip: block {} ({})", best.height, best.hash);
+    match finalized {
+        Some(finalized) => println!(
+            "Finalized tip: block {} ({})",
+            finalized.height, finalized.hash
+        ),
+        None => println!("Finalized tip: none"),
+    }
+    let listeners: Vec<_> = swarm.listeners().map(|addr| addr.to_string()).collect();
+    println!("Listening on: {}", listeners.join(", "));
+    let external: Vec<_> = swarm
+        .external_addresses()
+        .map(|record| record.addr.to_string())
+        .collect();
+    if !external.is_empty() {
+        println!("Advertised addresses: {}", external.join(", "));
+    }
+    let behaviour = swarm.behaviour();
+    println!(
+        "Validation queue: {} pending",
+        behaviour.validation.pending()
+    );
+    let cache = behaviour.validation.cache_stats();
+    println!(
+        "Validation cache: {} blocks, {} hits, {} misses",
+        cache.len, cache.hits, cache.misses
+    );
+    println!(
+        "Evidence recorded: {} equivocations",
+        behaviour.evidence.recorded().count()
+    );
+    let response_sender = &behaviour.response_sender;
+    println!(
+        "Chain response queue: {} of {} pending",
+        response_sender.max_capacity() - response_sender.capacity(),
+        response_sender.max_capacity()
+    );
+    println!(
+        "Chain responses served: {} in flight, {} bulk messages waiting to be published",
+        behaviour.serving.in_flight(),
+        behaviour.queued_bulk()
+    );
+    println!(
+        "Gossip: publishing to {} of {} connected peers, relay {}, {} messages waiting to be relayed, messages of {} origins within their TTL",
+        behaviour.gossip.peers().len(),
+        behaviour.gossip.connections(),
+        match behaviour.relay.mode() {
+            RelayMode::Immediate => "immediate",
+            RelayMode::Delayed => "delayed",
+            RelayMode::Off => "off",
+        },
+        behaviour.relay.pending(),
+        behaviour.replays.origins()
+    );
+    println!(
+        "Peer exchange: {} peers known, {} of them connected to",
+        behaviour.pex.len(),
+        behaviour.pex.good()
+    );
+    let alerts: Vec<_> = behaviour
+        .alerts
+        .counts()
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind))
+        .collect();
+    println!(
+        "Alerts raised: {}",
+        if alerts.is_empty() {
+            String::from("none")
+        } else {
+            alerts.join(", ")
+        }
+    );
+}
+
+/// Checks the health of the node, returning the alerts raised since the previous check
+pub fn check_alerts(swarm: &mut Swarm<TetherionBehaviour>) -> Vec<Alert> {
+    let peers = swarm.network_info().num_peers();
+    let height = sync_status(swarm).best.height;
+    swarm
+        .behaviour_mut()
+        .alerts
+        .check(peers, height, std::time::Instant::now())
+}
+
+/// Handles `proof <block_hash> <tx_id>`, asking a full node for the proof that the transaction
+/// is stored in the block
+pub fn handle_request_proof(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    let (block_hash, tx_id) = match args[..] {
+        [block_hash, tx_id] => (block_hash, tx_id),
+        _ => {
+            eprintln!("usage: proof <block_hash> <tx_id>");
+            return;
+        }
+    };
+
+    let peer = match get_peers(swarm).pop() {
+        Some(peer) => peer,
+        None => {
+            eprintln!("no peers to ask for the proof");
+            return;
+        }
+    };
+
+    let req = ProofRequest {
+        block_hash: block_hash.to_owned(),
+        tx_id: tx_id.to_owned(),
+        from_peer_id: peer,
+    };
+    let topic = swarm.behaviour().topics.header.clone();
+    swarm
+        .behaviour_mut()
+        .publish(topic, &Message::ProofRequest(req));
+}
+
+/// Handles `anchor <file>`, mining a block storing the SHA256 digest of the file
+pub fn handle_anchor(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
+    let path = cmd.strip_prefix("anchor").unwrap_or_default().trim();
+    match anchor::digest_file(Path::new(path)) {
+        Ok(digest) => {
+            println!("anchoring digest {}", digest);
+            create_block(Payload::Anchor { digest }, swarm);
+        }
+        Err(err) => eprintln!("cannot read {}: {}", path, err),
+    }
+}
+
+/// Handles `verify-anchor <file>`, locating the block storing the SHA256 digest of the file
+pub fn handle_verify_anchor(cmd: &str, swarm: &Swarm<TetherionBehaviour>) {
+    let path = cmd.strip_prefix("verify-anchor").unwrap_or_default().trim();
+    let digest = match anchor::digest_file(Path::new(path)) {
+        Ok(digest) => digest,
+        Err(err) => {
+            eprintln!("cannot read {}: {}", path, err);
+            return;
+        }
+    };
+
+    match anchor::locate(&swarm.behaviour().tetherion, &digest) {
+        Some(receipt) => {
+            let timestamp = chrono::DateTime::from_timestamp(receipt.timestamp, 0)
+                .map_or_else(|| receipt.timestamp.to_string(), |time| time.to_rfc3339());
+            println!(
+                "{} existed at {}, anchored in block {} ({})",
+                path, timestamp, receipt.block_id, receipt.block_hash
+            );
+            let json = logfmt::to_json(&receipt.proof).expect("Proof should be jsonified");
+            println!("Merkle proof: {}", json);
+        }
+        None => println!("digest {} of {} is not anchored", digest, path),
+    }
+}
+
+/// Handles `register <name> <value>`, mining a block registering the name owned by this node
+pub fn handle_register(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
+    let args = cmd.strip_prefix("register").unwrap_or_default().trim();
+    let (name, value) = match args.split_once(char::is_whitespace) {
+        Some((name, value)) => (name, value.trim()),
+        None => {
+            eprintln!("usage: register <name> <value>");
+            return;
+        }
+    };
+
+    let behaviour = swarm.behaviour();
+    let state = behaviour.tetherion.state();
+    let revision = state.next_revision(name);
+    let payload = Payload::register(
+        state.chain_id(),
+        name.to_owned(),
+        value.to_owned(),
+        revision,
+        &behaviour.keys,
+    );
+    create_block(payload, swarm);
+}
+
+/// Handles `resolve <name>`, printing the latest value of the name and its owner
+pub fn handle_resolve(cmd: &str, swarm: &Swarm<TetherionBehaviour>) {
+    let name = cmd.strip_prefix("resolve").unwrap_or_default().trim();
+    match swarm.behaviour().tetherion.state().resolve(name) {
+        Some(record) => println!(
+            "{} resolves to '{}', owned by {}",
+            name, record.value, record.owner
+        ),
+        None => println!("{} is not registered", name),
+    }
+}
+
+/// Handles `search <term>`, printing the transactions the account takes part in, if the term is
+/// an address, or else the transactions whose data contains a word starting with the term
+pub fn handle_search(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>, output: Output) {
+    let term = cmd.strip_prefix("search").unwrap_or_default().trim();
+    if term.is_empty() {
+        eprintln!("usage: search <address or word>");
+        return;
+    }
+    let behaviour = swarm.behaviour_mut();
+    behaviour.update_search_index();
+    let hits = behaviour.search.search(term);
+    if output == Output::Json {
+        println!(
+            "{}",
+            serde_json::to_string(&hits).expect("can jsonify search hits")
+        );
+        return;
+    }
+    if hits.is_empty() {
+        println!("no transactions found");
+    }
+    for hit in hits {
+        println!("block {} tx {}: {}", hit.height, hit.tx_id, hit.payload);
+    }
+}
+
+/// Gets the status of the transaction with the given ID, if the node knows about it
+pub fn tx_status(tx_id: &str, swarm: &mut Swarm<TetherionBehaviour>) -> Option<TxStatus> {
+    let behaviour = swarm.behaviour_mut();
+    behaviour.update_search_index();
+    let in_mempool = behaviour
+        .miner
+        .pending()
+        .chain(behaviour.miner.orphans())
+        .chain(behaviour.sealing.iter())
+        .chain(behaviour.sealing.orphans())
+        .any(|data| data.tx_ids().iter().any(|id| id == tx_id));
+    behaviour.receipts.status(
+        tx_id,
+        behaviour.search.locate(tx_id),
+        behaviour.tetherion.height(),
+        in_mempool,
+    )
+}
+
+/// A transaction stored in the local blockchain or waiting in the mempool, along with where it
+/// is on its way into the blockchain
+#[derive(Serialize, Debug, Clone)]
+pub struct Transaction {
+    pub tx_id: String,
+    pub payload: Payload,
+
+    #[serde(flatten)]
+    pub status: TxStatus,
+}
+
+/// Gets the transactions waiting in the mempool, the ones of a batch one by one, each along with
+/// its ID, in the order they are going to be stored in
+pub fn mempool_transactions(behaviour: &TetherionBehaviour) -> Vec<(String, &Payload)> {
+    behaviour
+        .miner
+        .pending()
+        .chain(behaviour.sealing.iter())
+        .chain(behaviour.miner.orphans())
+        .chain(behaviour.sealing.orphans())
+        .flat_map(search::transactions)
+        .map(|payload| (merkle::leaf_hash(payload.to_string().as_bytes()), payload))
+        .collect()
+}
+
+/// Gets the transaction with the given ID, if it is stored in the local blockchain or waits in
+/// the mempool
+pub fn get_transaction(tx_id: &str, swarm: &mut Swarm<TetherionBehaviour>) -> Option<Transaction> {
+    let status = tx_status(tx_id, swarm)?;
+    let behaviour = swarm.behaviour();
+    let payload = match behaviour.search.payload(tx_id) {
+        Some(payload) => payload,
+        None => {
+            mempool_transactions(behaviour)
+                .into_iter()
+                .find(|(id, _)| id == tx_id)?
+                .1
+        }
+    };
+    Some(Transaction {
+        tx_id: tx_id.to_owned(),
+        payload: payload.clone(),
+        status,
+    })
+}
+
+/// Handles `tx status <tx_id>`, printing where the transaction is on its way into the blockchain
+pub fn handle_tx_status(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>, output: Output) {
+    let tx_id = cmd.strip_prefix("tx status").unwrap_or_default().trim();
+    if tx_id.is_empty() {
+        eprintln!("usage: tx status <tx_id>");
+        return;
+    }
+    match tx_status(tx_id, swarm) {
+        Some(status) if output == Output::Json => println!(
+            "{}",
+            serde_json::to_string(&status).expect("can jsonify transaction status")
+        ),
+        Some(status) => println!("{}", status),
+        None => eprintln!("unknown transaction {}", tx_id),
+    }
+}
+
+/// Handles the `token` commands:
+/// - `token create <token> <supply> [mintable]`
+/// - `token mint <token> <amount>`
+/// - `token transfer <token> <to> <amount>`
+/// - `token lock <token> <amount> <script as JSON>`
+/// - `token unlock <lock_id> <to> <witness as JSON>`
+/// - `token sign <lock_id> <to>`, printing the node's signature for a multisig witness
+/// - `token locked <lock_id>`, printing the tokens locked under the lock ID
+/// - `token balance <address> <token> [pending|latest|confirmed]`
+/// - `token bump <nonce> <fee>`, replacing the node's pending operation with one paying the fee
+pub fn handle_token(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
+    let args: Vec<&str> = cmd.splitn(5, ' ').skip(1).collect();
+    let op = match args[..] {
+        ["balance", address, token] | ["balance", address, token, _] => {
+            let view = match args
+                .get(3)
+                .map_or(Ok(BalanceView::default()), |view| view.parse())
+            {
+                Ok(view) => view,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return;
+                }
+            };
+            let balance = token_balance(address, token, view, swarm);
+            println!("{} owns {} {} ({})", address, balance, token, view);
+            return;
+        }
+        ["locked", lock_id] => {
+            match swarm.behaviour().tetherion.state().lock(lock_id) {
+                Some(lock) => {
+                    let json = serde_json::to_string(lock).expect("can jsonify lock");
+                    println!("{}", json);
+                }
+                None => println!("lock {} does not exist or is already spent", lock_id),
+            }
+            return;
+        }
+        ["bump", nonce, fee] => {
+            match (nonce.parse(), fee.parse()) {
+                (Ok(nonce), Ok(fee)) => bump_fee(nonce, fee, swarm),
+                _ => eprintln!("usage: token bump <nonce> <fee>"),
+            }
+            return;
+        }
+        ["sign", lock_id, to] => {
+            let message = TokenOp::unlock_message(lock_id, to);
+            let behaviour = swarm.behaviour();
+            let signature = script::KeySignature {
+                key: behaviour.address(),
+                signature: hex::encode(behaviour.keys.sign(&message)),
+            };
+            let json = serde_json::to_string(&signature).expect("can jsonify signature");
+            println!("{}", json);
+            return;
+        }
+        ["create", token, supply] | ["create", token, supply, "mintable"] => supply
+            .parse()
+            .map(|supply| TokenOp::Create {
+                token: token.to_owned(),
+                supply,
+                mintable: args.len() == 4,
+            })
+            .map_err(|err| err.to_string()),
+        ["mint", token, amount] => amount
+            .parse()
+            .map(|amount| TokenOp::Mint {
+                token: token.to_owned(),
+                amount,
+            })
+            .map_err(|err| err.to_string()),
+        ["transfer", token, to, amount] => amount
+            .parse()
+            .map(|amount| TokenOp::Transfer {
+                token: token.to_owned(),
+                to: to.to_owned(),
+                amount,
+            })
+            .map_err(|err| err.to_string()),
+        ["lock", token, amount, script] => match (amount.parse(), serde_json::from_str(script)) {
+            (Ok(amount), Ok(script)) => Ok(TokenOp::Lock {
+                token: token.to_owned(),
+                amount,
+                script,
+            }),
+            (Err(err), _) => Err(err.to_string()),
+            (_, Err(err)) => Err(err.to_string()),
+        },
+        ["unlock", lock_id, to, witness] => serde_json::from_str(witness)
+            .map(|witness| TokenOp::Unlock {
+                lock_id: lock_id.to_owned(),
+                to: to.to_owned(),
+                witness,
+            })
+            .map_err(|err| err.to_string()),
+        _ => {
+            eprintln!(
+                "usage: token <create|mint|transfer|lock|unlock|locked|sign|balance|bump> <args>"
+            );
+            return;
+        }
+    };
+
+    match op {
+        Ok(op) => {
+            let behaviour = swarm.behaviour();
+            let chain_id = behaviour.tetherion.state().chain_id();
+            let payload = Payload::token(chain_id, op, behaviour.next_nonce(), &behaviour.keys);
+            create_block(payload, swarm);
+        }
+        Err(err) => eprintln!("invalid token command: {}", err),
+    }
+}
+
+/// Replaces the node's pending token operation having the given nonce with the same operation
+/// paying the given fee
+fn bump_fee(nonce: u64, fee: u64, swarm: &mut Swarm<TetherionBehaviour>) {
+    let behaviour = swarm.behaviour();
+    let address = behaviour.address();
+    let op = behaviour
+        .miner
+        .pending()
+        .chain(behaviour.miner.orphans())
+        .chain(behaviour.sealing.iter())
+        .chain(behaviour.sealing.orphans())
+        .find_map(|data| match data {
+            Payload::Token {
+                op,
+                nonce: pending,
+                from,
+                ..
+            } if *pending == nonce && *from == address => Some(op.clone()),
+            _ => None,
+        });
+
+    match op {
+        Some(op) => {
+            let chain_id = behaviour.tetherion.state().chain_id();
+            let payload = Payload::token_with_fee(chain_id, op, nonce, fee, &behaviour.keys);
+            create_block(payload, swarm);
+        }
+        None => eprintln!("there is no pending token operation with nonce {}", nonce),
+    }
+}
+
+/// Gets the amount of the token owned by the account with the given address, as of the given
+/// view of the local blockchain
+pub fn token_balance(
+    address: &str,
+    token: &str,
+    view: BalanceView,
+    swarm: &Swarm<TetherionBehaviour>,
+) -> u64 {
+    let behaviour = swarm.behaviour();
+    let pending = behaviour.miner.pending().chain(behaviour.sealing.iter());
+    view_balance(
+        &behaviour.tetherion,
+        view,
+        behaviour.receipts.depth(),
+        pending,
+        address,
+        token,
+    )
+}
+
+/// Gets the amount of the token owned by the account with the given address as of the given view
+/// of the blockchain: confirmed balances lag the given number of blocks behind the latest block,
+/// pending ones count the given pending data
+pub fn view_balance<'a>(
+    tetherion: &Tetherion<Payload>,
+    view: BalanceView,
+    depth: u64,
+    pending: impl Iterator<Item = &'a Payload>,
+    address: &str,
+    token: &str,
+) -> u64 {
+    match view {
+        BalanceView::Latest => tetherion.state().balance(address, token),
+        BalanceView::Confirmed => tetherion
+            .confirmed_state(depth)
+            .map_or(0, |state| state.balance(address, token)),
+        BalanceView::Pending => tetherion.pending_state(pending).balance(address, token),
+    }
+}
+
+/// Estimates the fee a token operation should pay to be stored within the target number of blocks
+pub fn estimate_fee(target_blocks: u64, swarm: &Swarm<TetherionBehaviour>) -> FeeEstimate {
+    let behaviour = swarm.behaviour();
+    let pending = behaviour.miner.pending().chain(behaviour.sealing.iter());
+    fees::estimate(behaviour.tetherion.blocks(), pending, target_blocks)
+}
+
+/// Handles `estimate fee <target_blocks>`, printing the fee a token operation should pay to be
+/// stored within the target number of blocks
+pub fn handle_estimate_fee(cmd: &str, swarm: &Swarm<TetherionBehaviour>, output: Output) {
+    let arg = cmd.strip_prefix("estimate fee").unwrap_or_default().trim();
+    let target_blocks = match arg.parse::<u64>() {
+        Ok(target_blocks) if target_blocks > 0 => target_blocks,
+        _ => {
+            eprintln!("usage: estimate fee <target_blocks>");
+            return;
+        }
+    };
+    let estimate = estimate_fee(target_blocks, swarm);
+    if output == Output::Json {
+        println!(
+            "{}",
+            serde_json::to_string(&estimate).expect("can jsonify fee estimate")
+        );
+        return;
+    }
+    println!(
+        "suggested fee {} to be stored within {} blocks ({} recent fees, {} pending operations)",
+        estimate.fee, estimate.target_blocks, estimate.samples, estimate.pending
+    );
+}
+
+/// Handles `dial <multiaddr>`, connecting to the peer listening on the address
+pub fn handle_dial(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
+    let addr = cmd.strip_prefix("dial ").unwrap_or_default().trim();
+    match addr.parse::<Multiaddr>() {
+        Ok(addr) => match swarm.dial_addr(addr.clone()) {
+            Ok(()) => println!("dialing {}", addr),
+            Err(err) => eprintln!("cannot dial {}: {}", addr, err),
+        },
+        Err(err) => eprintln!("invalid address {}: {}", addr, err),
+    }
+}
+
+/// Handles `diff chain <peer_id>`, asking the peer for its headers so that where its chain and
+/// the local one diverge is printed once they arrive
+pub fn handle_diff_chain(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>, output: Output) {
+    let peer = cmd.strip_prefix("diff chain ").unwrap_or_default().trim();
+    let peer: PeerId = match peer.parse() {
+        Ok(peer) => peer,
+        Err(err) => {
+            eprintln!("invalid peer ID {}: {}", peer, err);
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour_mut();
+    if !behaviour
+        .peer_book
+        .connected_peers()
+        .any(|connected| *connected == peer)
+    {
+        eprintln!("not connected to {}", peer);
+        return;
+    }
+    behaviour.pending_diffs.insert(peer, output);
+    let req = HeadersRequest {
+        from_peer_id: peer.to_string(),
+    };
+    let topic = behaviour.topics.header.clone();
+    behaviour.publish(topic, &Message::HeadersRequest(req));
+    println!("asked {} for its headers", peer);
+}
+
+/// Prints the divergence of the chain of the peer from the local one
+fn print_divergence(peer: PeerId, diff: &Divergence) {
+    let summary = match diff.verdict {
+        Verdict::Identical => String::from("the chains are identical"),
+        Verdict::LocalAhead => format!("the chain of {} is behind the local one", peer),
+        Verdict::RemoteAhead => format!("the local chain is behind the one of {}", peer),
+        Verdict::Forked => format!("the local chain and the one of {} forked", peer),
+        Verdict::Unrelated => format!(
+            "the local chain and the one of {} share no block, not even the genesis block",
+            peer
+        ),
+    };
+    println!("{}", summary);
+    if let Some(ancestor) = &diff.ancestor {
+        println!(
+            "Common ancestor: block {} ({}) at {}",
+            ancestor.height,
+            ancestor.hash,
+            format_time(ancestor.timestamp)
+        );
+    }
+    for (name, branch) in [("Local", &diff.local), ("Remote", &diff.remote)] {
+        print_branch(name, branch);
+    }
+    if diff.verdict == Verdict::Forked {
+        let more_work = match diff.local.work.cmp(&diff.remote.work) {
+            std::cmp::Ordering::Greater => "the local branch",
+            std::cmp::Ordering::Less => "the remote branch",
+            std::cmp::Ordering::Equal => "neither branch",
+        };
+        println!("{} took more work", more_work);
+    }
+}
+
+/// Prints the blocks of a chain past the common ancestor
+fn print_branch(name: &str, branch: &Branch) {
+    println!(
+        "{} branch: {} blocks, work {}, tip block {} ({}) at {}",
+        name,
+        branch.blocks,
+        branch.work,
+        branch.tip.height,
+        branch.tip.hash,
+        format_time(branch.tip.timestamp)
+    );
+    if let Some(first) = &branch.first {
+        println!(
+            "  diverges at block {} ({}) at {}",
+            first.height,
+            first.hash,
+            format_time(first.timestamp)
+        );
+    }
+    if let Some(invalid) = &branch.invalid {
+        println!("  invalid: {}", invalid);
+    }
+}
+
+/// Handles `ban <peer ID or IP address> [duration] [reason]`, disconnecting from the banned peers
+/// and keeping them from connecting again until the ban expires
+pub fn handle_ban(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
+    let args = cmd.strip_prefix("ban ").unwrap_or_default();
+    let ban = match bans::parse_ban(args, chrono::Utc::now().timestamp()) {
+        Ok(ban) => ban,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour_mut();
+    let target = ban.target;
+    let until = ban.until;
+    if let Err(err) = behaviour.bans.ban(ban) {
+        log::error!("cannot save the bans: {}", err);
+    }
+    let banned: Vec<PeerId> = behaviour
+        .peer_book
+        .connected_peers()
+        .filter(|peer| match target {
+            BanTarget::Peer(banned) => **peer == banned,
+            BanTarget::Ip(ip) => behaviour
+                .peer_book
+                .addresses(peer)
+                .iter()
+                .any(|addr| bans::ip_of(addr) == Some(ip)),
+        })
+        .copied()
+        .collect();
+    for peer in banned {
+        log::info!("disconnecting from {}, banned", peer);
+        let _ = swarm.disconnect_peer_id(peer);
+    }
+    match until {
+        Some(until) => println!("banned {} until {}", target, format_time(until)),
+        None => println!("banned {}", target),
+    }
+}
+
+/// Handles `unban <peer ID or IP address>`, letting the peer connect again
+pub fn handle_unban(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
+    let target = match cmd
+        .strip_prefix("unban ")
+        .unwrap_or_default()
+        .trim()
+        .parse()
+    {
+        Ok(target) => target,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    match swarm.behaviour_mut().bans.unban(&target) {
+        Ok(true) => println!("unbanned {}", target),
+        Ok(false) => println!("{} is not banned", target),
+        Err(err) => log::error!("cannot save the bans: {}", err),
+    }
+}
+
+/// Handles `ls bans`, printing the bans in effect
+pub fn handle_print_bans(swarm: &Swarm<TetherionBehaviour>, output: Output) {
+    let bans = swarm
+        .behaviour()
+        .bans
+        .active(chrono::Utc::now().timestamp());
+    if output == Output::Json {
+        println!(
+            "{}",
+            serde_json::to_string(&bans).expect("can jsonify bans")
+        );
+        return;
+    }
+
+    println!("Bans:");
+    for ban in bans {
+        let until = ban.until.map_or_else(
+            || String::from("permanently"),
+            |until| format!("until {}", format_time(until)),
+        );
+        println!("{} {}: {}", ban.target, until, ban.reason);
+    }
+}
+
+/// Formats the time, given in seconds since the Unix epoch
+fn format_time(secs: i64) -> String {
+    chrono::DateTime::from_timestamp(secs, 0)
+        .map_or_else(|| secs.to_string(), |time| time.to_rfc3339())
+}
+
+pub fn handle_create_block(cmd: &str, swarm: &mut Swarm<TetherionBehaviour>) {
+    if let Some(data) = cmd.strip_prefix("create b") {
+        create_block(Payload::Text(data.to_owned()), swarm);
+    }
+}
+
+/// Starts mining a block containing the given data on top of the local chain, logging why the
+/// data is rejected, if it is
+pub fn create_block(data: Payload, swarm: &mut Swarm<TetherionBehaviour>) {
+    if let Err(err) = submit_payload(data, swarm) {
+        log::error!("{}", err);
+    }
+}
+
+/// Queues the data to be stored in a block on top of the local chain
+pub fn submit_payload(
+    data: Payload,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<(), MempoolError> {
+    swarm.behaviour_mut().mine(data)
+}
+
+/// Adds the block produced by the miner to the local chain and broadcasts it
+pub fn handle_mined_block(mined: MinedBlock, swarm: &mut Swarm<TetherionBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let block = match behaviour.miner.mined(mined) {
+        Some(block) => block,
+        None => {
+            log::info!("discarding block mined from an abandoned candidate");
+            return;
+        }
+    };
+
+    let data = block.data().clone();
+    match behaviour.add_local_block(block) {
+        Ok(()) => {}
+        Err(
+            err @ (InvalidBlockError::InvalidData { .. } | InvalidBlockError::Overweight { .. }),
+        ) => {
+            log::error!("{}", err);
+            let reason = err.to_string();
+            behaviour
+                .receipts
+                .record(&data.tx_ids(), TxStatus::Dropped { reason });
+            behaviour.resume_mining();
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            behaviour.miner.requeue(data);
+            behaviour.resume_mining();
+        }
+    }
+}
+
+/// Creates a template for external miners of a block containing the given data on top of the
+/// local chain
+pub fn get_work(data: Payload, swarm: &mut Swarm<TetherionBehaviour>) -> WorkTemplate {
+    let behaviour = swarm.behaviour_mut();
+    let latest_block = behaviour
+        .tetherion
+        .blocks()
+        .last()
+        .expect("there is at least one block");
+    behaviour.work.get_work(
+        latest_block,
+        data,
+        behaviour.tetherion.difficulty_at(latest_block.id + 1),
+    )
+}
+
+/// Gets a template for an external block producer on top of the local tip, selecting the
+/// pending transactions that apply on top of it
+pub fn get_block_template(swarm: &Swarm<TetherionBehaviour>) -> BlockTemplate {
+    let behaviour = swarm.behaviour();
+    let tetherion = &behaviour.tetherion;
+    let latest_block = tetherion
+        .blocks()
+        .last()
+        .expect("there is at least one block");
+    let difficulty = tetherion
+        .consensus()
+        .is_mined()
+        .then(|| tetherion.difficulty_at(latest_block.id + 1));
+    BlockTemplate::new(
+        latest_block,
+        difficulty,
+        behaviour.miner.pending().chain(behaviour.sealing.iter()),
+        tetherion.state(),
+    )
+}
+
+/// Adds the block an external miner has found the nonce for to the local chain and broadcasts
+/// it, returning the block's hash
+pub fn submit_work(
+    work_id: u64,
+    nonce: u64,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<String, SubmitWorkError> {
+    let behaviour = swarm.behaviour_mut();
+    let block = behaviour
+        .work
+        .get(work_id)
+        .ok_or(SubmitWorkError::UnknownWork { work_id })?
+        .block(nonce);
+
+    // The template stays outstanding until a block is accepted, so that a bad submission does
+    // not take it away from the other miners
+    let hash = block.hash.clone();
+    behaviour
+        .add_local_block(block)
+        .map_err(SubmitWorkError::InvalidBlock)?;
+    behaviour.work.take(work_id);
+
+    log::info!("accepted block {} mined externally", hash);
+    Ok(hash)
+}
+
+/// Adds the block constructed externally to the local chain and broadcasts it, returning the
+/// block's hash, or every consensus rule the block violates if it is rejected
+pub fn submit_block(
+    block: Block<Payload>,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<String, Vec<InvalidBlockError>> {
+    let behaviour = swarm.behaviour_mut();
+    let violations = behaviour.tetherion.check_block(&block);
+    if !violations.is_empty() {
+        log::info!(
+            "rejected block {} submitted externally: {} rule violations",
+            block.id,
+            violations.len()
+        );
+        return Err(violations);
+    }
+
+    let hash = block.hash.clone();
+    behaviour.add_local_block(block).map_err(|err| vec![err])?;
+
+    log::info!("accepted block {} submitted externally", hash);
+    Ok(hash)
 }
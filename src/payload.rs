@@ -0,0 +1,386 @@
+/// Copyright (c) 2022 Tetherion
+#[cfg(feature = "contracts")]
+use crate::contracts::ContractOp;
+use {
+    crate::{
+        evidence::Evidence,
+        script::{Script, Witness},
+    },
+    libp2p::identity::ed25519,
+    serde::{Deserialize, Serialize},
+    std::fmt,
+};
+
+/// The ID of the chain nodes join unless configured otherwise
+pub const DEFAULT_CHAIN_ID: &str = "main";
+
+/// The data stored in a block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    /// Arbitrary text
+    Text(String),
+
+    /// Arbitrary binary data, encoded in HEX format
+    #[cfg(feature = "binary-payload")]
+    Binary(#[serde(with = "hex")] Vec<u8>),
+
+    /// Multiple payloads stored in the same block, each of them being a separate transaction
+    Batch(Vec<Payload>),
+
+    /// The SHA256 digest, in HEX format, of a document proven to exist when the block was created
+    Anchor { digest: String },
+
+    /// A block of another chain, committed to so that rewriting the other chain up to that block
+    /// also takes rewriting this one
+    ChainAnchor {
+        /// The ID of the anchored chain
+        chain_id: String,
+
+        /// The ID of the anchored block
+        height: u64,
+
+        /// The hash of the anchored block
+        hash: String,
+    },
+
+    /// The registration of a name, or the update of its value by the name's owner
+    Register {
+        name: String,
+        value: String,
+
+        /// The number of times the name has been registered or updated before
+        revision: u64,
+
+        /// The owner's Ed25519 public key, in HEX format
+        owner: String,
+
+        /// The owner's signature of the name, value and revision, in HEX format
+        signature: String,
+    },
+
+    /// An operation on tokens performed by an account
+    Token {
+        op: TokenOp,
+
+        /// The number of token operations the account has performed before
+        nonce: u64,
+
+        /// The amount of the operation's token the account burns to get the operation included,
+        /// letting it replace a pending operation with the same nonce by paying more
+        #[serde(default)]
+        fee: u64,
+
+        /// The account's Ed25519 public key, in HEX format
+        from: String,
+
+        /// The account's signature of the operation, nonce and fee, in HEX format
+        signature: String,
+    },
+
+    /// An operation on WebAssembly contracts performed by an account
+    #[cfg(feature = "contracts")]
+    Contract {
+        op: Box<ContractOp>,
+
+        /// The number of contract operations the account has performed before
+        nonce: u64,
+
+        /// The most fuel the execution of the operation can consume, failing once it runs out
+        fuel: u64,
+
+        /// The account's Ed25519 public key, in HEX format
+        from: String,
+
+        /// The account's signature of the operation, nonce and fuel, in HEX format
+        signature: String,
+    },
+
+    /// The proof that a block producer signed two different blocks at the same height, which
+    /// excludes the producer from producing blocks for a while
+    Evidence(Box<Evidence>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenOp {
+    /// Creates a token with the given supply, credited to its creator, who becomes its owner
+    Create {
+        token: String,
+        supply: u64,
+
+        /// Whether the owner can mint new tokens afterwards
+        mintable: bool,
+    },
+
+    /// Mints new tokens, credited to the owner of the token
+    Mint { token: String, amount: u64 },
+
+    /// Transfers tokens to another account
+    Transfer {
+        token: String,
+        to: String,
+        amount: u64,
+    },
+
+    /// Locks tokens until the script is satisfied. The lock is identified by the account's
+    /// address and the operation's nonce, as `<address>:<nonce>`.
+    Lock {
+        token: String,
+        amount: u64,
+        script: Script,
+    },
+
+    /// Spends locked tokens by satisfying the lock's script, transferring them to an account
+    Unlock {
+        lock_id: String,
+        to: String,
+        witness: Witness,
+    },
+}
+
+impl TokenOp {
+    /// Creates the message to be signed by the keys of a multisig script to spend the lock,
+    /// binding the signatures to the receiving account
+    pub fn unlock_message(lock_id: &str, to: &str) -> Vec<u8> {
+        serde_json::to_vec(&(lock_id, to)).expect("can jsonify unlock")
+    }
+}
+
+/// The kind of a payload, which determines how the payload gets validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PayloadKind {
+    Text,
+    #[cfg(feature = "binary-payload")]
+    Binary,
+    Batch,
+    Anchor,
+    ChainAnchor,
+    Register,
+    Token,
+    #[cfg(feature = "contracts")]
+    Contract,
+    Evidence,
+}
+
+impl Payload {
+    /// Gets the kind of the payload
+    pub fn kind(&self) -> PayloadKind {
+        match self {
+            Payload::Text(_) => PayloadKind::Text,
+            #[cfg(feature = "binary-payload")]
+            Payload::Binary(_) => PayloadKind::Binary,
+            Payload::Batch(_) => PayloadKind::Batch,
+            Payload::Anchor { .. } => PayloadKind::Anchor,
+            Payload::ChainAnchor { .. } => PayloadKind::ChainAnchor,
+            Payload::Register { .. } => PayloadKind::Register,
+            Payload::Token { .. } => PayloadKind::Token,
+            #[cfg(feature = "contracts")]
+            Payload::Contract { .. } => PayloadKind::Contract,
+            Payload::Evidence(_) => PayloadKind::Evidence,
+        }
+    }
+
+    /// Creates the registration of the name on the chain with the given ID, signed by the owner
+    pub fn register(
+        chain_id: &str,
+        name: String,
+        value: String,
+        revision: u64,
+        keys: &ed25519::Keypair,
+    ) -> Self {
+        let message = Payload::registration_message(chain_id, &name, &value, revision);
+        let signature = keys.sign(&message);
+        Payload::Register {
+            name,
+            value,
+            revision,
+            owner: hex::encode(keys.public().encode()),
+            signature: hex::encode(signature),
+        }
+    }
+
+    /// Creates the token operation on the chain with the given ID without any fee, signed by the
+    /// account performing it
+    pub fn token(chain_id: &str, op: TokenOp, nonce: u64, keys: &ed25519::Keypair) -> Self {
+        Payload::token_with_fee(chain_id, op, nonce, 0, keys)
+    }
+
+    /// Creates the token operation on the chain with the given ID paying the given fee, signed by
+    /// the account performing it
+    pub fn token_with_fee(
+        chain_id: &str,
+        op: TokenOp,
+        nonce: u64,
+        fee: u64,
+        keys: &ed25519::Keypair,
+    ) -> Self {
+        let signature = keys.sign(&Payload::token_message(chain_id, &op, nonce, fee));
+        Payload::Token {
+            op,
+            nonce,
+            fee,
+            from: hex::encode(keys.public().encode()),
+            signature: hex::encode(signature),
+        }
+    }
+
+    /// Creates the contract operation on the chain with the given ID, given the fuel its execution
+    /// can consume, signed by the account performing it
+    #[cfg(feature = "contracts")]
+    pub fn contract(
+        chain_id: &str,
+        op: ContractOp,
+        nonce: u64,
+        fuel: u64,
+        keys: &ed25519::Keypair,
+    ) -> Self {
+        let signature = keys.sign(&Payload::contract_message(chain_id, &op, nonce, fuel));
+        Payload::Contract {
+            op: Box::new(op),
+            nonce,
+            fuel,
+            from: hex::encode(keys.public().encode()),
+            signature: hex::encode(signature),
+        }
+    }
+
+    /// Checks if the payload is signed by its owner for the chain with the given ID, if it has an
+    /// owner. A signature made for another chain does not count, so that a payload cannot be
+    /// replayed on another chain.
+    pub fn is_signed(&self, chain_id: &str) -> bool {
+        match self {
+            // Evidence is signed by the offender, which is checked along with the rest of it
+            Payload::Text(_)
+            | Payload::Anchor { .. }
+            | Payload::ChainAnchor { .. }
+            | Payload::Evidence(_) => true,
+            #[cfg(feature = "binary-payload")]
+            Payload::Binary(_) => true,
+            Payload::Batch(payloads) => payloads.iter().all(|payload| payload.is_signed(chain_id)),
+            Payload::Register {
+                name,
+                value,
+                revision,
+                owner,
+                signature,
+            } => verify(
+                owner,
+                &Payload::registration_message(chain_id, name, value, *revision),
+                signature,
+            ),
+            Payload::Token {
+                op,
+                nonce,
+                fee,
+                from,
+                signature,
+            } => verify(
+                from,
+                &Payload::token_message(chain_id, op, *nonce, *fee),
+                signature,
+            ),
+            #[cfg(feature = "contracts")]
+            Payload::Contract {
+                op,
+                nonce,
+                fuel,
+                from,
+                signature,
+            } => verify(
+                from,
+                &Payload::contract_message(chain_id, op, *nonce, *fuel),
+                signature,
+            ),
+        }
+    }
+
+    /// Creates the message signed by the owner of the name
+    fn registration_message(chain_id: &str, name: &str, value: &str, revision: u64) -> Vec<u8> {
+        serde_json::to_vec(&(chain_id, name, value, revision)).expect("can jsonify registration")
+    }
+
+    /// Creates the message signed by the account performing the token operation
+    fn token_message(chain_id: &str, op: &TokenOp, nonce: u64, fee: u64) -> Vec<u8> {
+        serde_json::to_vec(&(chain_id, op, nonce, fee)).expect("can jsonify token operation")
+    }
+
+    /// Creates the message signed by the account performing the contract operation
+    #[cfg(feature = "contracts")]
+    fn contract_message(chain_id: &str, op: &ContractOp, nonce: u64, fuel: u64) -> Vec<u8> {
+        serde_json::to_vec(&(chain_id, op, nonce, fuel)).expect("can jsonify contract operation")
+    }
+}
+
+/// Checks if the message is signed by the given Ed25519 public key, both in HEX format
+pub fn verify(public_key: &str, message: &[u8], signature: &str) -> bool {
+    let public_key = hex::decode(public_key)
+        .ok()
+        .and_then(|key| ed25519::PublicKey::decode(&key).ok());
+    match (public_key, hex::decode(signature)) {
+        (Some(public_key), Ok(signature)) => public_key.verify(message, &signature),
+        _ => false,
+    }
+}
+
+/// Formats the payload as its JSON encoding, which keeps payloads of different types from
+/// sharing the same Merkle leaf
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Payload::Text(String::from("data")).to_string(),
+            r#"{"text":"data"}"#
+        );
+        assert_ne!(
+            Payload::Text(String::from("00ab")).to_string(),
+            Payload::Anchor {
+                digest: String::from("00ab")
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn signed_registration() {
+        let keys = ed25519::Keypair::generate();
+        let payload = Payload::register(
+            DEFAULT_CHAIN_ID,
+            String::from("name"),
+            String::from("value"),
+            0,
+            &keys,
+        );
+        assert!(payload.is_signed(DEFAULT_CHAIN_ID));
+        // The signature does not hold on another chain
+        assert!(!payload.is_signed("other"));
+
+        if let Payload::Register {
+            name,
+            revision,
+            owner,
+            signature,
+            ..
+        } = payload
+        {
+            let forged = Payload::Register {
+                name,
+                value: String::from("other value"),
+                revision,
+                owner,
+                signature,
+            };
+            assert!(!forged.is_signed(DEFAULT_CHAIN_ID));
+        }
+    }
+}
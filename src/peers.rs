@@ -0,0 +1,579 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::config::{ConnectionMode, IpPreference, PeersConfig, ReconnectConfig},
+    libp2p::{core::ConnectedPoint, identify::IdentifyInfo, Multiaddr, PeerId},
+    serde::Serialize,
+    std::{
+        collections::{hash_map::RandomState, BTreeSet, HashMap, HashSet},
+        hash::{BuildHasher, Hash},
+        time::{Duration, Instant},
+    },
+};
+
+/// The penalty of a message that cannot be decoded
+pub const UNDECODABLE_PENALTY: u32 = 10;
+
+/// The penalty of a block header not following the local header chain
+pub const INVALID_HEADER_PENALTY: u32 = 5;
+
+/// The penalty of peer records that are forged, or more than a sample holds
+pub const INVALID_RECORD_PENALTY: u32 = 10;
+
+/// The direction of a connection to a peer
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// The peer dialed the node
+    Inbound,
+
+    /// The node dialed the peer
+    Outbound,
+}
+
+/// How the node treats a peer, as configured in the `[peers]` section
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerTag {
+    /// The peer is kept out of the peer exchange, and always published to, e.g. a validator
+    /// behind a sentry node
+    Private,
+
+    /// The peer is one of the only peers the node keeps connections with
+    Allowed,
+
+    /// Any other peer
+    Public,
+}
+
+impl PeerTag {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Private => "private",
+            Self::Allowed => "allowed",
+            Self::Public => "public",
+        }
+    }
+}
+
+/// What the node learned about a peer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// The addresses the peer was discovered or connected at
+    pub addresses: BTreeSet<Multiaddr>,
+
+    /// The direction of the connection to the peer, if connected
+    pub direction: Option<Direction>,
+
+    /// The protocols the peer supports, as reported by the identify protocol
+    pub protocols: Vec<String>,
+
+    /// The agent version of the peer, as reported by the identify protocol
+    pub agent_version: Option<String>,
+
+    /// The latest round-trip time measured to the peer, if any
+    pub rtt: Option<Duration>,
+
+    /// The sum of the penalties of the peer's misbehaviour, such as undecodable messages
+    pub score: u32,
+}
+
+/// The details of a peer printed by `ls p -v`
+#[derive(Serialize, Debug)]
+pub struct PeerDetails {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+    pub direction: Option<Direction>,
+    pub protocols: Vec<String>,
+    pub agent_version: Option<String>,
+    pub rtt_ms: Option<u128>,
+    pub score: u32,
+    pub tag: PeerTag,
+    pub reconnect: Option<RetryState>,
+}
+
+/// The state of the retries of dialing a peer the node lost
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryState {
+    /// The number of retries made already
+    pub attempts: u32,
+
+    /// The time left before the next retry, in milliseconds
+    pub next_retry_ms: u128,
+}
+
+/// What the node learned about each peer, gathered from the discovery, the connections, the
+/// identify and ping protocols, and the messages received
+#[derive(Debug, Default)]
+pub struct PeerBook {
+    peers: HashMap<PeerId, PeerInfo>,
+}
+
+impl PeerBook {
+    /// Records an address the peer was discovered at
+    pub fn discovered(&mut self, peer: PeerId, addr: Multiaddr) {
+        self.peers.entry(peer).or_default().addresses.insert(addr);
+    }
+
+    /// Records a connection established to the peer
+    pub fn connected(&mut self, peer: PeerId, endpoint: &ConnectedPoint) {
+        let info = self.peers.entry(peer).or_default();
+        let (direction, addr) = match endpoint {
+            ConnectedPoint::Dialer { address } => (Direction::Outbound, address),
+            ConnectedPoint::Listener { send_back_addr, .. } => (Direction::Inbound, send_back_addr),
+        };
+        info.direction = Some(direction);
+        info.addresses.insert(addr.clone());
+    }
+
+    /// Records that the last connection to the peer got closed
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        if let Some(info) = self.peers.get_mut(peer) {
+            info.direction = None;
+            info.rtt = None;
+        }
+    }
+
+    /// Records what the peer reported about itself through the identify protocol
+    pub fn identified(&mut self, peer: PeerId, identify: IdentifyInfo) {
+        let info = self.peers.entry(peer).or_default();
+        info.protocols = identify.protocols;
+        info.agent_version = Some(identify.agent_version);
+        info.addresses.extend(identify.listen_addrs);
+    }
+
+    /// Records the latest round-trip time measured to the peer, or `None` if pinging it failed
+    pub fn measured(&mut self, peer: PeerId, rtt: Option<Duration>) {
+        self.peers.entry(peer).or_default().rtt = rtt;
+    }
+
+    /// Adds the penalty to the misbehaviour score of the peer
+    pub fn penalize(&mut self, peer: PeerId, penalty: u32) {
+        let info = self.peers.entry(peer).or_default();
+        info.score = info.score.saturating_add(penalty);
+    }
+
+    /// Gets the peers the node is connected to
+    pub fn connected_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, info)| info.direction.is_some())
+            .map(|(peer, _)| peer)
+    }
+
+    /// Gets the addresses the peer was discovered or connected at
+    pub fn addresses(&self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.peers
+            .get(peer)
+            .map(|info| info.addresses.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Gets the directions the peer opens connections in, as reported by the identify protocol
+    pub fn mode(&self, peer: &PeerId) -> ConnectionMode {
+        self.peers
+            .get(peer)
+            .and_then(|info| info.agent_version.as_deref())
+            .map_or(ConnectionMode::Both, ConnectionMode::of_agent)
+    }
+
+    /// Gets the latest round-trip time measured to the peer, if any
+    pub fn rtt(&self, peer: &PeerId) -> Option<Duration> {
+        self.peers.get(peer).and_then(|info| info.rtt)
+    }
+
+    /// Gets the details of the peers at the given time, sorted by their IDs
+    pub fn details(
+        &self,
+        filter: &PeerFilter,
+        reconnect: &Reconnector<PeerId>,
+        now: Instant,
+    ) -> Vec<PeerDetails> {
+        let mut details: Vec<_> = self
+            .peers
+            .iter()
+            .map(|(peer, info)| PeerDetails {
+                peer_id: peer.to_string(),
+                addresses: info.addresses.iter().map(Multiaddr::to_string).collect(),
+                direction: info.direction,
+                protocols: info.protocols.clone(),
+                agent_version: info.agent_version.clone(),
+                rtt_ms: info.rtt.map(|rtt| rtt.as_millis()),
+                score: info.score,
+                tag: filter.tag(peer),
+                reconnect: reconnect.state(peer, now),
+            })
+            .collect();
+        details.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        details
+    }
+}
+
+/// Dials the discovered peers, trying their addresses one after the other, in the order of the
+/// preferred IP version, until a connection is established
+#[derive(Debug, Default)]
+pub struct Dialer {
+    prefer_ip: IpPreference,
+
+    /// The address being dialed and the ones left to try, for each peer being dialed
+    dialing: HashMap<PeerId, (Multiaddr, Vec<Multiaddr>)>,
+
+    /// The addresses to be dialed
+    queued: Vec<Multiaddr>,
+}
+
+impl Dialer {
+    pub fn new(prefer_ip: IpPreference) -> Self {
+        Self {
+            prefer_ip,
+            ..Self::default()
+        }
+    }
+
+    /// Starts dialing the peer at its addresses, unless it is being dialed already
+    pub fn dial(&mut self, peer: PeerId, mut addrs: Vec<Multiaddr>) {
+        if self.dialing.contains_key(&peer) || addrs.is_empty() {
+            return;
+        }
+        self.prefer_ip.order(&mut addrs);
+        // The addresses left to try are popped from the end
+        addrs.reverse();
+        let first = addrs.pop().expect("there is an address");
+        self.queued.push(first.clone());
+        self.dialing.insert(peer, (first, addrs));
+    }
+
+    /// Records that dialing the address failed, dialing the next address of the peer, if any
+    pub fn failed(&mut self, addr: &Multiaddr) {
+        let peer = self
+            .dialing
+            .iter()
+            .find(|(_, (dialed, _))| dialed == addr)
+            .map(|(peer, _)| *peer);
+        let Some(peer) = peer else {
+            return;
+        };
+        let (dialed, rest) = self.dialing.get_mut(&peer).expect("peer is being dialed");
+        match rest.pop() {
+            Some(next) => {
+                *dialed = next.clone();
+                self.queued.push(next);
+            }
+            None => {
+                self.dialing.remove(&peer);
+            }
+        }
+    }
+
+    /// Records that a connection to the peer got established, whatever its address
+    pub fn connected(&mut self, peer: &PeerId) {
+        self.dialing.remove(peer);
+    }
+
+    /// Takes the addresses to be dialed
+    pub fn take(&mut self) -> Vec<Multiaddr> {
+        std::mem::take(&mut self.queued)
+    }
+}
+
+/// A retry of dialing a target
+#[derive(Debug, Clone, Copy)]
+struct Retry {
+    /// The number of retries made already
+    attempts: u32,
+    due: Instant,
+}
+
+/// Dials again the targets the node lost, peers or addresses, with exponential backoff and
+/// jitter, until a connection to them is established
+#[derive(Debug)]
+pub struct Reconnector<K> {
+    config: ReconnectConfig,
+    retries: HashMap<K, Retry>,
+
+    /// The random state the jitter of the retries is derived from, so that the nodes losing the
+    /// same peer do not dial it in step
+    random: RandomState,
+}
+
+impl<K: Hash + Eq + Clone> Reconnector<K> {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Self {
+            config,
+            retries: HashMap::new(),
+            random: RandomState::new(),
+        }
+    }
+
+    /// Schedules the first retry of dialing the target lost at the given time, unless retries
+    /// are scheduled already
+    pub fn schedule(&mut self, target: K, now: Instant) {
+        if !self.config.enabled || self.retries.contains_key(&target) {
+            return;
+        }
+        let due = now + self.delay(&target, 0);
+        self.retries.insert(target, Retry { attempts: 0, due });
+    }
+
+    /// Takes the targets due to be dialed again at the given time, scheduling their next retry
+    /// unless the node gives up on them
+    pub fn due(&mut self, now: Instant) -> Vec<K> {
+        let due: Vec<_> = self
+            .retries
+            .iter()
+            .filter(|(_, retry)| retry.due <= now)
+            .map(|(target, _)| target.clone())
+            .collect();
+        for target in &due {
+            let attempts = self.retries[target].attempts + 1;
+            if self.config.max_attempts.is_some_and(|max| attempts >= max) {
+                self.retries.remove(target);
+                continue;
+            }
+            let due = now + self.delay(target, attempts);
+            self.retries.insert(target.clone(), Retry { attempts, due });
+        }
+        due
+    }
+
+    /// Records that a connection to the target got established, cancelling its retries
+    pub fn connected(&mut self, target: &K) {
+        self.retries.remove(target);
+    }
+
+    /// Gets the state of the retries of dialing the target at the given time, if any
+    pub fn state(&self, target: &K, now: Instant) -> Option<RetryState> {
+        self.retries.get(target).map(|retry| RetryState {
+            attempts: retry.attempts,
+            next_retry_ms: retry.due.saturating_duration_since(now).as_millis(),
+        })
+    }
+
+    /// Gets the time before the retry following the given number of retries: the initial delay
+    /// multiplied at each retry up to the maximum one, less a random share of it
+    fn delay(&self, target: &K, attempts: u32) -> Duration {
+        let growth = self.config.multiplier.saturating_pow(attempts);
+        let delay = Duration::from_secs(self.config.initial_delay)
+            .saturating_mul(growth)
+            .min(Duration::from_secs(self.config.max_delay));
+        let spread = delay.as_millis() as u64 * u64::from(self.config.jitter_percent) / 100;
+        if spread == 0 {
+            return delay;
+        }
+        let random = self.random.hash_one((target, attempts));
+        delay - Duration::from_millis(random % (spread + 1))
+    }
+}
+
+/// The peers the node keeps connections with, as configured in the `[peers]` section
+#[derive(Debug, Default)]
+pub struct PeerFilter {
+    allow: HashSet<PeerId>,
+    deny: HashSet<PeerId>,
+    private: HashSet<PeerId>,
+
+    /// Whether no peer is allowed at all
+    isolated: bool,
+}
+
+impl PeerFilter {
+    /// Creates the filter of the configured peers, skipping the invalid IDs
+    pub fn new(config: &PeersConfig) -> Self {
+        let parse = |ids: &[String]| ids.iter().filter_map(|id| id.parse().ok()).collect();
+        Self {
+            allow: parse(&config.allow),
+            deny: parse(&config.deny),
+            private: parse(&config.private),
+            isolated: false,
+        }
+    }
+
+    /// Creates the filter of a node keeping connections with no peer
+    pub fn isolated() -> Self {
+        Self {
+            isolated: true,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the node may keep connections with the peer
+    pub fn allows(&self, peer: &PeerId) -> bool {
+        !self.isolated
+            && !self.deny.contains(peer)
+            && (self.allow.is_empty() || self.allow.contains(peer))
+    }
+
+    /// Whether the peer's addresses are kept out of the peer exchange
+    pub fn is_private(&self, peer: &PeerId) -> bool {
+        self.private.contains(peer)
+    }
+
+    /// Gets how the node treats the peer
+    pub fn tag(&self, peer: &PeerId) -> PeerTag {
+        if self.is_private(peer) {
+            PeerTag::Private
+        } else if self.allow.contains(peer) {
+            PeerTag::Allowed
+        } else {
+            PeerTag::Public
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, libp2p::identity::Keypair};
+
+    #[test]
+    fn gather_peer_details() {
+        let peer = PeerId::random();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let mut book = PeerBook::default();
+        book.discovered(peer, addr.clone());
+        book.connected(
+            peer,
+            &ConnectedPoint::Dialer {
+                address: addr.clone(),
+            },
+        );
+        book.identified(
+            peer,
+            IdentifyInfo {
+                public_key: Keypair::generate_ed25519().public(),
+                protocol_version: String::from("/tetherion/1.0.0"),
+                agent_version: String::from("tetherion/0.1.0"),
+                listen_addrs: vec![addr],
+                protocols: vec![String::from("/ipfs/ping/1.0.0")],
+                observed_addr: Multiaddr::empty(),
+            },
+        );
+        book.measured(peer, Some(Duration::from_millis(12)));
+        book.penalize(peer, UNDECODABLE_PENALTY);
+
+        let reconnect = Reconnector::new(ReconnectConfig::default());
+        let now = Instant::now();
+        let details = &book.details(&PeerFilter::default(), &reconnect, now)[0];
+        assert_eq!(details.addresses, vec!["/ip4/127.0.0.1/tcp/4001"]);
+        assert_eq!(details.direction, Some(Direction::Outbound));
+        assert_eq!(details.agent_version.as_deref(), Some("tetherion/0.1.0"));
+        assert_eq!(details.rtt_ms, Some(12));
+        assert_eq!(details.score, UNDECODABLE_PENALTY);
+        assert_eq!(book.mode(&peer), ConnectionMode::Both);
+
+        book.disconnected(&peer);
+        assert_eq!(
+            book.details(&PeerFilter::default(), &reconnect, now)[0].direction,
+            None
+        );
+        assert_eq!(book.rtt(&peer), None);
+    }
+
+    #[test]
+    fn dial_preferred_addresses() {
+        let addr = |addr: &str| addr.parse::<Multiaddr>().unwrap();
+        let (v4, v6) = (
+            addr("/ip4/10.0.0.1/tcp/4001"),
+            addr("/ip6/fd00::1/tcp/4001"),
+        );
+        let peer = PeerId::random();
+
+        let mut dialer = Dialer::new(IpPreference::V6);
+        dialer.dial(peer, vec![v4.clone(), v6.clone()]);
+        dialer.dial(peer, vec![v4.clone()]);
+        assert_eq!(dialer.take(), vec![v6.clone()]);
+        dialer.failed(&v6);
+        assert_eq!(dialer.take(), vec![v4.clone()]);
+        dialer.failed(&v4);
+        assert!(dialer.take().is_empty());
+
+        // A peer connected to is not dialed at its other addresses
+        dialer.dial(peer, vec![v4.clone(), v6.clone()]);
+        dialer.connected(&peer);
+        dialer.failed(&v6);
+        assert_eq!(dialer.take(), vec![v6]);
+
+        let mut dialer = Dialer::new(IpPreference::V4);
+        dialer.dial(peer, vec![addr("/ip6/fd00::2/tcp/1"), v4.clone()]);
+        assert_eq!(dialer.take(), vec![v4]);
+    }
+
+    #[test]
+    fn back_off_retries() {
+        let start = Instant::now();
+        let mut reconnect = Reconnector::new(ReconnectConfig {
+            initial_delay: 1,
+            max_delay: 4,
+            jitter_percent: 0,
+            max_attempts: Some(4),
+            ..ReconnectConfig::default()
+        });
+        reconnect.schedule("a", start);
+        reconnect.schedule("a", start + Duration::from_secs(10));
+        assert!(reconnect.due(start).is_empty());
+
+        // The retries are spaced by 1, 2, 4 and 4 seconds
+        let mut at = start;
+        for delay in [1, 2, 4] {
+            at += Duration::from_secs(delay);
+            assert_eq!(reconnect.due(at), vec!["a"]);
+        }
+        assert_eq!(
+            reconnect.state(&"a", at),
+            Some(RetryState {
+                attempts: 3,
+                next_retry_ms: 4000
+            })
+        );
+        at += Duration::from_secs(4);
+        assert_eq!(reconnect.due(at), vec!["a"]);
+        assert_eq!(reconnect.state(&"a", at), None);
+
+        reconnect.schedule("b", start);
+        reconnect.connected(&"b");
+        assert!(reconnect.due(at).is_empty());
+
+        // The jitter takes at most its share off the delay
+        let reconnect = Reconnector::new(ReconnectConfig {
+            initial_delay: 10,
+            jitter_percent: 50,
+            ..ReconnectConfig::default()
+        });
+        let delay = reconnect.delay(&"a", 0);
+        assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(10));
+        assert!(reconnect.delay(&"a", 20) <= Duration::from_secs(300));
+
+        let disabled = ReconnectConfig {
+            enabled: false,
+            ..ReconnectConfig::default()
+        };
+        let mut reconnect = Reconnector::new(disabled);
+        reconnect.schedule("a", start);
+        assert_eq!(reconnect.state(&"a", start), None);
+    }
+
+    #[test]
+    fn filter_peers() {
+        let (friend, stranger, foe) = (PeerId::random(), PeerId::random(), PeerId::random());
+        assert!(PeerFilter::default().allows(&stranger));
+
+        let filter = PeerFilter::new(&PeersConfig {
+            allow: vec![friend.to_string(), foe.to_string()],
+            deny: vec![foe.to_string()],
+            private: vec![friend.to_string()],
+        });
+        assert!(filter.allows(&friend));
+        assert!(!filter.allows(&stranger));
+        assert!(!filter.allows(&foe));
+        assert!(filter.is_private(&friend));
+        assert_eq!(filter.tag(&friend), PeerTag::Private);
+        assert_eq!(filter.tag(&foe), PeerTag::Allowed);
+        assert_eq!(filter.tag(&stranger), PeerTag::Public);
+
+        let filter = PeerFilter::new(&PeersConfig {
+            allow: Vec::new(),
+            deny: vec![foe.to_string()],
+            private: Vec::new(),
+        });
+        assert!(filter.allows(&stranger));
+        assert!(!filter.allows(&foe));
+    }
+}
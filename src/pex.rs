@@ -0,0 +1,446 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::config::{self, PexConfig},
+    libp2p::{
+        identity::{self, ed25519},
+        Multiaddr, PeerId,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fmt,
+        time::{Duration, Instant},
+    },
+};
+
+/// The prefix of the signed content of the peer records, so that no other content signed with the
+/// keys of a node can pass for one of its records
+const RECORD_DOMAIN: &[u8] = b"tetherion/peer-record";
+
+/// The time, in seconds, records may be signed ahead of the local clock at
+const MAX_CLOCK_SKEW: i64 = 60;
+
+/// The share of the address book the peers learned about from a single peer may fill, so that no
+/// peer can fill it on its own
+const SOURCE_SHARE: usize = 4;
+
+/// The addresses a peer reached itself at, signed by the peer so that no other peer can make them
+/// up, e.g. to direct the network to a host being attacked
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+    /// The hex-encoded public key of the peer, which its ID derives from
+    pub public_key: String,
+
+    pub addresses: Vec<Multiaddr>,
+
+    /// When the peer signed the record, in seconds since the epoch
+    pub timestamp: i64,
+
+    /// The hex-encoded signature of the addresses and the timestamp by the peer
+    pub signature: String,
+}
+
+impl PeerRecord {
+    /// Creates the record of the node with the given keys, signed at the given time
+    pub fn new(keys: &ed25519::Keypair, addresses: Vec<Multiaddr>, timestamp: i64) -> Self {
+        let signature = keys.sign(&signed_content(&addresses, timestamp));
+        Self {
+            public_key: hex::encode(keys.public().encode()),
+            addresses,
+            timestamp,
+            signature: hex::encode(signature),
+        }
+    }
+
+    /// Gets the ID of the peer the record is about, checking that the peer signed it
+    pub fn verify(&self) -> Result<PeerId, PexError> {
+        let public_key = hex::decode(&self.public_key)
+            .ok()
+            .and_then(|bytes| ed25519::PublicKey::decode(&bytes).ok())
+            .ok_or(PexError::InvalidRecord("invalid public key"))?;
+        let signature = hex::decode(&self.signature)
+            .map_err(|_| PexError::InvalidRecord("invalid signature"))?;
+        if !public_key.verify(&signed_content(&self.addresses, self.timestamp), &signature) {
+            return Err(PexError::InvalidRecord("invalid signature"));
+        }
+        Ok(PeerId::from(identity::PublicKey::Ed25519(public_key)))
+    }
+}
+
+/// Gets the content signed by the peer a record is about
+fn signed_content(addresses: &[Multiaddr], timestamp: i64) -> Vec<u8> {
+    let mut content = RECORD_DOMAIN.to_vec();
+    content.extend_from_slice(&timestamp.to_be_bytes());
+    for addr in addresses {
+        let bytes = addr.to_vec();
+        content.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        content.extend_from_slice(&bytes);
+    }
+    content
+}
+
+/// The reason the records sent by a peer are rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PexError {
+    /// The peer sent its sample without being asked for it
+    Unsolicited,
+
+    /// The peer asked for a sample again before half the exchange interval was over
+    TooFrequent,
+
+    /// The peer sent more records than a sample holds
+    TooManyRecords(usize),
+
+    /// A record sent by the peer is not a valid one, whoever signed it
+    InvalidRecord(&'static str),
+}
+
+impl PexError {
+    /// Gets the short name of the error, which the log is throttled by
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Unsolicited => "unsolicited_records",
+            Self::TooFrequent => "frequent_exchange",
+            Self::TooManyRecords(_) => "too_many_records",
+            Self::InvalidRecord(_) => "invalid_record",
+        }
+    }
+
+    /// Checks whether the error can only be caused by a misbehaving peer, rather than by a slow
+    /// or restarted one
+    pub fn is_misbehaviour(&self) -> bool {
+        matches!(self, Self::TooManyRecords(_) | Self::InvalidRecord(_))
+    }
+}
+
+impl fmt::Display for PexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unsolicited => write!(f, "records sent without being asked for"),
+            Self::TooFrequent => write!(f, "records asked for too frequently"),
+            Self::TooManyRecords(count) => write!(f, "{} records sent at once", count),
+            Self::InvalidRecord(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// A peer record kept in the address book
+#[derive(Debug)]
+struct Entry {
+    record: PeerRecord,
+
+    /// The peer the record was learned from
+    source: PeerId,
+
+    /// Whether the node connected to the peer, only the records of such peers being shared
+    good: bool,
+}
+
+/// The addresses of the peers learned from the exchanges with the peers, bounded so that hostile
+/// peers cannot poison it
+#[derive(Debug)]
+pub struct AddressBook {
+    config: PexConfig,
+    local: PeerId,
+
+    /// The addresses the node advertises in its own record
+    addresses: Vec<Multiaddr>,
+
+    entries: HashMap<PeerId, Entry>,
+
+    /// The peers asked for their sample, along with when they were asked
+    asked: HashMap<PeerId, Instant>,
+
+    /// When each peer last asked for the node's sample
+    asking: HashMap<PeerId, Instant>,
+
+    /// The number of exchanges started, picking the peer of the next one
+    rounds: usize,
+}
+
+impl AddressBook {
+    pub fn new(config: PexConfig, local: PeerId) -> Self {
+        Self {
+            config,
+            local,
+            addresses: Vec::new(),
+            entries: HashMap::new(),
+            asked: HashMap::new(),
+            asking: HashMap::new(),
+            rounds: 0,
+        }
+    }
+
+    pub fn config(&self) -> &PexConfig {
+        &self.config
+    }
+
+    /// Sets the addresses the node advertises in its own record
+    pub fn advertise(&mut self, addresses: Vec<Multiaddr>) {
+        self.addresses = addresses;
+    }
+
+    /// Picks the connected peer to exchange with next, taking turns, and records that it was
+    /// asked for its sample
+    pub fn pick(&mut self, mut connected: Vec<PeerId>, now: Instant) -> Option<PeerId> {
+        if connected.is_empty() {
+            return None;
+        }
+        connected.sort_by_cached_key(PeerId::to_bytes);
+        let peer = connected[self.rounds % connected.len()];
+        self.rounds += 1;
+        self.asked.insert(peer, now);
+        Some(peer)
+    }
+
+    /// Gets the records shared at the given time: the node's own one, then the ones of the peers
+    /// the node connected to which may be shared, the most recent first
+    pub fn sample(
+        &self,
+        keys: &ed25519::Keypair,
+        time: i64,
+        shared: impl Fn(&PeerId) -> bool,
+    ) -> Vec<PeerRecord> {
+        let mut good: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(peer, entry)| {
+                entry.good && shared(peer) && !self.is_stale(&entry.record, time)
+            })
+            .map(|(_, entry)| &entry.record)
+            .collect();
+        good.sort_by_key(|record| std::cmp::Reverse(record.timestamp));
+
+        let own = (!self.addresses.is_empty())
+            .then(|| PeerRecord::new(keys, self.addresses.clone(), time));
+        own.into_iter()
+            .chain(good.into_iter().cloned())
+            .take(self.config.sample_size)
+            .collect()
+    }
+
+    /// Adds the records the peer sent at the given time, either asking for the node's sample or
+    /// replying to the node asking for its own. Returns the peers learned about, along with their
+    /// addresses, or the reason the records are rejected as a whole.
+    pub fn received(
+        &mut self,
+        source: PeerId,
+        records: Vec<PeerRecord>,
+        request: bool,
+        now: Instant,
+        time: i64,
+    ) -> Result<Vec<(PeerId, Vec<Multiaddr>)>, PexError> {
+        if request {
+            let min_gap = self.config.interval() / 2;
+            if self
+                .asking
+                .get(&source)
+                .is_some_and(|at| now.duration_since(*at) < min_gap)
+            {
+                return Err(PexError::TooFrequent);
+            }
+            self.asking.insert(source, now);
+        } else if self.asked.remove(&source).is_none() {
+            return Err(PexError::Unsolicited);
+        }
+
+        if records.len() > self.config.sample_size {
+            return Err(PexError::TooManyRecords(records.len()));
+        }
+        let mut verified = Vec::with_capacity(records.len());
+        for record in records {
+            let peer = record.verify()?;
+            if record.addresses.len() > self.config.max_addresses {
+                return Err(PexError::InvalidRecord("too many addresses"));
+            }
+            if record.timestamp - time > MAX_CLOCK_SKEW {
+                return Err(PexError::InvalidRecord("signed in the future"));
+            }
+            verified.push((peer, record));
+        }
+
+        let mut learned = Vec::new();
+        for (peer, mut record) in verified {
+            record.addresses.retain(config::is_supported_address);
+            if peer == self.local || record.addresses.is_empty() || self.is_stale(&record, time) {
+                continue;
+            }
+            // The record of the peer sending it is the one of a peer the node is connected to
+            let good = peer == source;
+            if let Some(entry) = self.entries.get_mut(&peer) {
+                if record.timestamp > entry.record.timestamp {
+                    entry.record = record;
+                }
+                entry.good |= good;
+                continue;
+            }
+            if !good && !self.has_room(&source) {
+                continue;
+            }
+            learned.push((peer, record.addresses.clone()));
+            self.entries.insert(
+                peer,
+                Entry {
+                    record,
+                    source,
+                    good,
+                },
+            );
+        }
+        Ok(learned)
+    }
+
+    /// Records that a connection to the peer got established, its record being shared from now on
+    pub fn connected(&mut self, peer: &PeerId) {
+        if let Some(entry) = self.entries.get_mut(peer) {
+            entry.good = true;
+        }
+    }
+
+    /// Gets the number of peers in the address book
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks whether the address book is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Gets the number of peers in the address book the node connected to
+    pub fn good(&self) -> usize {
+        self.entries.values().filter(|entry| entry.good).count()
+    }
+
+    /// Forgets about the exchanges older than the exchange interval
+    pub fn expire(&mut self, now: Instant) {
+        let interval = self.config.interval();
+        let recent = |at: &mut Instant| now.duration_since(*at) < interval;
+        self.asked.retain(|_, at| recent(at));
+        self.asking.retain(|_, at| recent(at));
+    }
+
+    fn is_stale(&self, record: &PeerRecord, time: i64) -> bool {
+        let age = Duration::from_secs(time.saturating_sub(record.timestamp).max(0) as u64);
+        age > Duration::from_secs(self.config.max_record_age)
+    }
+
+    /// Checks whether a peer learned about from the source can be added, making room for it if
+    /// the address book is full by evicting the oldest record of a peer never connected to
+    fn has_room(&mut self, source: &PeerId) -> bool {
+        let share = (self.config.max_peers / SOURCE_SHARE).max(1);
+        let from_source = self
+            .entries
+            .values()
+            .filter(|entry| !entry.good && entry.source == *source)
+            .count();
+        if from_source >= share {
+            return false;
+        }
+        if self.entries.len() < self.config.max_peers {
+            return true;
+        }
+        let oldest = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| !entry.good)
+            .min_by_key(|(_, entry)| entry.record.timestamp)
+            .map(|(peer, _)| *peer);
+        match oldest {
+            Some(peer) => {
+                self.entries.remove(&peer);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_signed_records() {
+        let addr = |addr: &str| addr.parse::<Multiaddr>().unwrap();
+        let keys = |_| ed25519::Keypair::generate();
+        let peer_id =
+            |keys: &ed25519::Keypair| PeerId::from(identity::PublicKey::Ed25519(keys.public()));
+        let (local, source, other) = (keys(0), keys(1), keys(2));
+        let start = Instant::now();
+        let time = 1_700_000_000;
+        let config = PexConfig {
+            max_peers: 4,
+            ..PexConfig::default()
+        };
+        let mut book = AddressBook::new(config, peer_id(&local));
+
+        let source_record = PeerRecord::new(&source, vec![addr("/ip4/10.0.0.1/tcp/4001")], time);
+        let other_record = PeerRecord::new(&other, vec![addr("/ip4/10.0.0.2/tcp/4001")], time);
+        assert_eq!(source_record.verify(), Ok(peer_id(&source)));
+
+        // Replies are only accepted from the peers asked for them
+        let records = vec![source_record.clone(), other_record.clone()];
+        assert_eq!(
+            book.received(peer_id(&source), records.clone(), false, start, time),
+            Err(PexError::Unsolicited)
+        );
+        assert_eq!(
+            book.pick(vec![peer_id(&source)], start),
+            Some(peer_id(&source))
+        );
+        let learned = book
+            .received(peer_id(&source), records.clone(), false, start, time)
+            .unwrap();
+        assert_eq!(learned.len(), 2);
+        assert_eq!((book.len(), book.good()), (2, 1));
+        assert_eq!(
+            book.received(peer_id(&source), records.clone(), true, start, time),
+            Ok(Vec::new())
+        );
+        assert_eq!(
+            book.received(peer_id(&source), records, true, start, time),
+            Err(PexError::TooFrequent)
+        );
+
+        // Only the records of the peers connected to are shared
+        assert_eq!(book.sample(&local, time, |_| true).len(), 1);
+        book.advertise(vec![addr("/ip4/10.0.0.3/tcp/4001")]);
+        book.connected(&peer_id(&other));
+        let sample = book.sample(&local, time, |_| true);
+        assert_eq!(sample.len(), 3);
+        assert_eq!(sample[0].verify(), Ok(peer_id(&local)));
+        let private = peer_id(&other);
+        assert_eq!(book.sample(&local, time, |peer| peer != &private).len(), 2);
+
+        // A record whose addresses were changed by another peer is rejected
+        let mut forged = PeerRecord::new(&keys(3), vec![addr("/ip4/10.0.0.4/tcp/4001")], time);
+        forged.addresses = vec![addr("/ip4/192.0.2.1/tcp/80")];
+        let later = start + config.interval();
+        assert_eq!(
+            book.received(peer_id(&source), vec![forged], true, later, time),
+            Err(PexError::InvalidRecord("invalid signature"))
+        );
+
+        // A single peer cannot fill the address book
+        let flooded: Vec<_> = (0..4)
+            .map(|i| {
+                let addrs = vec![addr(&format!("/ip4/10.0.1.{}/tcp/4001", i))];
+                PeerRecord::new(&keys(i), addrs, time)
+            })
+            .collect();
+        let later = later + config.interval();
+        let learned = book
+            .received(peer_id(&source), flooded, true, later, time)
+            .unwrap();
+        assert_eq!(learned.len(), 1);
+
+        let stale = PeerRecord::new(&keys(4), vec![addr("/ip4/10.0.0.5/tcp/4001")], time);
+        let later = later + config.interval();
+        let aged = time + config.max_record_age as i64 + 1;
+        assert_eq!(
+            book.received(peer_id(&other), vec![stale], true, later, aged),
+            Ok(Vec::new())
+        );
+    }
+}
@@ -0,0 +1,208 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::{Block, BlockHeader},
+        payload::Payload,
+        tetherion::{BlockData, InvalidBlockError},
+    },
+    crossterm::style::{Color, Stylize},
+    std::sync::Arc,
+};
+
+/// The number of leading characters of a hash shown
+const HASH_WIDTH: usize = 12;
+
+/// The number of characters of a payload shown
+const PREVIEW_WIDTH: usize = 40;
+
+/// A line of the chain printout
+struct Row {
+    id: u64,
+    hash: String,
+    timestamp: i64,
+
+    /// The number of transactions stored in the block, unknown for block headers
+    txs: Option<usize>,
+
+    /// The beginning of the data stored in the block, unknown for block headers
+    preview: Option<String>,
+
+    /// The rule the block violates, if any
+    violation: Option<String>,
+}
+
+/// Colors the text, unless colors are disabled
+fn paint(text: &str, color: Color, colored: bool) -> String {
+    if colored {
+        text.with(color).to_string()
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Shortens the text to the given number of characters, marking the cut with an ellipsis
+fn truncate(text: &str, width: usize) -> String {
+    let mut chars = text.chars();
+    let shortened: String = chars.by_ref().take(width).collect();
+    if chars.next().is_some() {
+        format!("{}…", shortened)
+    } else {
+        shortened
+    }
+}
+
+/// Describes how long ago the timestamp is, e.g. `2m ago`
+pub fn relative_time(timestamp: i64, now: i64) -> String {
+    let elapsed = now - timestamp;
+    let (value, unit) = match elapsed.abs() {
+        0..=4 => return String::from("just now"),
+        secs @ 5..=59 => (secs, "s"),
+        secs @ 60..=3599 => (secs / 60, "m"),
+        secs @ 3600..=86399 => (secs / 3600, "h"),
+        secs => (secs / 86400, "d"),
+    };
+    if elapsed < 0 {
+        format!("in {}{}", value, unit)
+    } else {
+        format!("{}{} ago", value, unit)
+    }
+}
+
+/// Gets the beginning of the data stored in the block, on a single line
+fn preview(payload: &Payload) -> String {
+    let text = match payload {
+        Payload::Text(text) => text.clone(),
+        payload => payload.to_string(),
+    };
+    truncate(&text.replace(['\n', '\r'], " "), PREVIEW_WIDTH)
+}
+
+/// Formats the rows as a table, one block per line, the validity of each block shown first
+fn format_rows(title: &str, rows: &[Row], now: i64, colored: bool) -> String {
+    let mut printout = format!(
+        "{}\n  {:>8}  {:<13}  {:>10}  {:>4}  DATA\n",
+        title, "HEIGHT", "HASH", "AGE", "TXS"
+    );
+    let invalid = rows.iter().filter(|row| row.violation.is_some()).count();
+    for row in rows {
+        let mark = match row.violation {
+            Some(_) => paint("✗", Color::Red, colored),
+            None => paint("✓", Color::Green, colored),
+        };
+        printout.push_str(&format!(
+            "{} {:>8}  {}  {:>10}  {:>4}  {}\n",
+            mark,
+            row.id,
+            // Padded before being colored, so that the escape codes do not count as characters
+            paint(
+                &format!("{:<13}", truncate(&row.hash, HASH_WIDTH)),
+                Color::Yellow,
+                colored
+            ),
+            relative_time(row.timestamp, now),
+            row.txs
+                .map_or_else(|| String::from("-"), |txs| txs.to_string()),
+            row.preview.as_deref().unwrap_or_default(),
+        ));
+        if let Some(violation) = &row.violation {
+            printout.push_str(&format!("  └ {}\n", paint(violation, Color::Red, colored)));
+        }
+    }
+    if invalid > 0 {
+        printout.push_str(&paint(
+            &format!("{} of {} blocks are invalid\n", invalid, rows.len()),
+            Color::Red,
+            colored,
+        ));
+    }
+    printout
+}
+
+/// Formats the blocks as a table with truncated hashes, relative timestamps and payload
+/// previews, marking each block as valid or not following the given violations, which are
+/// positions in the blockchain along with the rule violated
+pub fn format_blocks(
+    blocks: &[Arc<Block<Payload>>],
+    violations: &[(usize, InvalidBlockError)],
+    now: i64,
+    colored: bool,
+) -> String {
+    let rows: Vec<_> = blocks
+        .iter()
+        .enumerate()
+        .map(|(position, block)| Row {
+            id: block.id,
+            hash: block.hash.clone(),
+            timestamp: block.timestamp(),
+            txs: Some(block.data().tx_ids().len()),
+            preview: Some(preview(block.data())),
+            violation: violations
+                .iter()
+                .find(|(invalid, _)| *invalid == position)
+                .map(|(_, err)| err.to_string()),
+        })
+        .collect();
+    format_rows("Local Tetherion blockchain:", &rows, now, colored)
+}
+
+/// Formats the block headers of a light client as a table, marking the ones whose hash does
+/// not match their content as invalid
+pub fn format_headers(headers: &[BlockHeader], now: i64, colored: bool) -> String {
+    let rows: Vec<_> = headers
+        .iter()
+        .map(|header| Row {
+            id: header.id,
+            hash: header.hash.clone(),
+            timestamp: header.timestamp,
+            txs: None,
+            preview: None,
+            violation: (!header.has_valid_hash())
+                .then(|| InvalidBlockError::invalid_header_hash(header).to_string()),
+        })
+        .collect();
+    format_rows("Local Tetherion block headers:", &rows, now, colored)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::tetherion::Tetherion};
+
+    #[test]
+    fn format_chain() {
+        assert_eq!(relative_time(100, 102), "just now");
+        assert_eq!(relative_time(100, 130), "30s ago");
+        assert_eq!(relative_time(0, 150), "2m ago");
+        assert_eq!(relative_time(0, 7200), "2h ago");
+        assert_eq!(relative_time(200_000, 0), "in 2d");
+
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        let genesis = tetherion.blocks()[0].clone();
+        let data = Payload::Text("x".repeat(50));
+        let block = Block::new(1, &genesis.hash, data, 1);
+        tetherion.add_block(block).unwrap();
+
+        let now = genesis.timestamp() + 120;
+        let invalid_hash = InvalidBlockError::InvalidHash {
+            id: 1,
+            expected: String::from("00ab"),
+            actual: String::from("00cd"),
+        };
+        let violations = vec![(1, invalid_hash)];
+        let printout = format_blocks(tetherion.blocks(), &violations, now, false);
+        let lines: Vec<_> = printout.lines().collect();
+        assert_eq!(
+            lines[2],
+            format!(
+                "✓        0  {}…      2m ago     1  genesis",
+                &genesis.hash[..HASH_WIDTH]
+            )
+        );
+        assert!(lines[3].starts_with("✗        1"));
+        assert!(lines[3].ends_with(&format!("{}…", "x".repeat(PREVIEW_WIDTH))));
+        assert_eq!(
+            lines[4],
+            "  └ Block with ID 1 has a hash 00cd not matching its content, whose hash is 00ab"
+        );
+        assert_eq!(lines[5], "1 of 2 blocks are invalid");
+    }
+}
@@ -0,0 +1,82 @@
+/// Copyright (c) 2022 Tetherion
+use std::collections::VecDeque;
+
+/// How urgently a message is sent to the peers or the data received from them is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Small, latency-sensitive messages, e.g. new blocks and votes
+    High,
+
+    /// Bulk transfers, e.g. the frames of a blockchain sent to a syncing peer
+    Low,
+}
+
+/// A queue handing out its high-priority items before its low-priority ones, the items of each
+/// priority in the order they were pushed in
+#[derive(Debug)]
+pub struct PriorityQueue<T> {
+    high: VecDeque<T>,
+    low: VecDeque<T>,
+}
+
+impl<T> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        Self {
+            high: VecDeque::new(),
+            low: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> PriorityQueue<T> {
+    pub fn push(&mut self, priority: Priority, item: T) {
+        match priority {
+            Priority::High => self.high.push_back(item),
+            Priority::Low => self.low.push_back(item),
+        }
+    }
+
+    /// Takes the oldest item of the highest priority out of the queue
+    pub fn pop(&mut self) -> Option<T> {
+        self.high.pop_front().or_else(|| self.low.pop_front())
+    }
+
+    /// Gets the number of queued items of the priority
+    pub fn queued(&self, priority: Priority) -> usize {
+        match priority {
+            Priority::High => self.high.len(),
+            Priority::Low => self.low.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.high.len() + self.low.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.low.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pop_high_priority_first() {
+        let mut queue = PriorityQueue::default();
+        queue.push(Priority::Low, "frame 0");
+        queue.push(Priority::Low, "frame 1");
+        queue.push(Priority::High, "block");
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.queued(Priority::Low), 2);
+
+        assert_eq!(queue.pop(), Some("block"));
+        queue.push(Priority::High, "vote");
+        assert_eq!(queue.pop(), Some("vote"));
+        assert_eq!(queue.pop(), Some("frame 0"));
+        assert_eq!(queue.pop(), Some("frame 1"));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+/// Copyright (c) 2022 Tetherion
+use {serde::Serialize, std::collections::VecDeque};
+
+/// The upper bounds, in milliseconds, of the buckets the propagation delays are counted in, the
+/// delays exceeding the last bound being counted in an extra bucket
+pub const BUCKET_BOUNDS: [u64; 8] = [50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000];
+
+/// The number of the latest measured blocks kept along with their delay
+const RECENT_BLOCKS: usize = 10;
+
+/// The time a block took to reach the node since it was published by the node producing it
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockDelay {
+    /// The ID of the block
+    pub height: u64,
+
+    /// The hash of the block
+    pub hash: String,
+
+    /// The peer the block was received from
+    pub source: String,
+
+    /// The delay, in milliseconds
+    pub delay_ms: u64,
+}
+
+/// The histogram of the delays the blocks received from the peers took to propagate to the node
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PropagationStats {
+    /// The number of delays in each bucket, as bounded by `BUCKET_BOUNDS`
+    pub buckets: [u64; BUCKET_BOUNDS.len() + 1],
+
+    /// The number of measured blocks
+    pub count: u64,
+
+    /// The sum of the delays, in milliseconds
+    pub total_ms: u64,
+
+    /// The longest delay, in milliseconds
+    pub max_ms: u64,
+
+    /// The latest measured blocks, the latest last
+    pub recent: VecDeque<BlockDelay>,
+}
+
+impl PropagationStats {
+    /// Records the delay of a block, published at the given time, received at the given time, both
+    /// in milliseconds since the epoch. Clocks running ahead of the node's count as no delay.
+    pub fn record(
+        &mut self,
+        height: u64,
+        hash: &str,
+        source: &str,
+        published_at: i64,
+        received_at: i64,
+    ) {
+        let delay_ms = received_at.saturating_sub(published_at).max(0) as u64;
+        let bucket = BUCKET_BOUNDS
+            .iter()
+            .position(|bound| delay_ms <= *bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total_ms = self.total_ms.saturating_add(delay_ms);
+        self.max_ms = self.max_ms.max(delay_ms);
+
+        if self.recent.len() == RECENT_BLOCKS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(BlockDelay {
+            height,
+            hash: hash.to_owned(),
+            source: source.to_owned(),
+            delay_ms,
+        });
+    }
+
+    /// Gets the mean delay, in milliseconds, if any block was measured
+    pub fn mean_ms(&self) -> Option<u64> {
+        self.total_ms.checked_div(self.count)
+    }
+
+    /// Gets the upper bound of the bucket the given share of the delays falls within, e.g. 0.9 for
+    /// the 90th percentile, or `None` if no block was measured or the delays exceed every bound
+    pub fn percentile_ms(&self, share: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (share * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BUCKET_BOUNDS.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_delays() {
+        let mut stats = PropagationStats::default();
+        assert_eq!(stats.mean_ms(), None);
+        assert_eq!(stats.percentile_ms(0.5), None);
+
+        for (height, delay) in [(1, 30), (2, 80), (3, 90), (4, 20_000)] {
+            stats.record(height, "00ab", "peer", 1_000, 1_000 + delay);
+        }
+        stats.record(5, "00cd", "peer", 2_000, 1_500);
+
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.buckets[0], 2);
+        assert_eq!(stats.buckets[1], 2);
+        assert_eq!(stats.buckets[BUCKET_BOUNDS.len()], 1);
+        assert_eq!(stats.max_ms, 20_000);
+        assert_eq!(stats.mean_ms(), Some(4_040));
+        assert_eq!(stats.percentile_ms(0.5), Some(100));
+        assert_eq!(stats.percentile_ms(1.0), None);
+        assert_eq!(stats.recent.back().unwrap().delay_ms, 0);
+
+        for height in 6..20 {
+            stats.record(height, "00ef", "peer", 0, 0);
+        }
+        assert_eq!(stats.recent.len(), RECENT_BLOCKS);
+        assert_eq!(stats.recent.front().unwrap().height, 10);
+    }
+}
@@ -0,0 +1,406 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    libp2p::{
+        core::{connection::ConnectionId, Multiaddr, PeerId},
+        floodsub::{
+            protocol::{
+                FloodsubMessage, FloodsubProtocol, FloodsubRpc, FloodsubSubscription,
+                FloodsubSubscriptionAction,
+            },
+            FloodsubEvent, Topic,
+        },
+        swarm::{
+            NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, OneShotHandler,
+            PollParameters, ProtocolsHandler,
+        },
+    },
+    std::{
+        collections::{HashMap, HashSet, VecDeque},
+        task::{Context, Poll},
+    },
+};
+
+/// The number of received messages remembered by their source and sequence number, so that a
+/// message sent twice by a peer is handled once
+const RECEIVED_CAPACITY: usize = 4096;
+
+/// Publish/subscribe speaking the floodsub protocol, except that the received messages are never
+/// passed on to the other peers: the node publishes the messages, its own and the relayed ones,
+/// to the peers it picks
+pub struct Pubsub {
+    /// The ID of the local peer, the source of the published messages
+    local_peer_id: PeerId,
+
+    /// The frames waiting to be sent and the events waiting to be handled by the node
+    events: VecDeque<NetworkBehaviourAction<FloodsubRpc, FloodsubEvent>>,
+
+    /// The connected peers along with the topics they are subscribed to
+    connected: HashMap<PeerId, Vec<Topic>>,
+
+    /// The topics the node is subscribed to
+    subscribed: Vec<Topic>,
+
+    /// The sequence number of the next published message
+    sequence_number: u64,
+
+    /// The sources and sequence numbers of the messages received recently, the oldest first
+    received: VecDeque<(PeerId, Vec<u8>)>,
+
+    /// The same sources and sequence numbers, for looking them up
+    received_set: HashSet<(PeerId, Vec<u8>)>,
+}
+
+impl Pubsub {
+    pub fn new(local_peer_id: PeerId) -> Self {
+        Self {
+            local_peer_id,
+            events: VecDeque::new(),
+            connected: HashMap::new(),
+            subscribed: Vec::new(),
+            // Starting from the time the node started at, the messages published after a restart
+            // are not taken by the peers for the ones published before it
+            sequence_number: chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64,
+            received: VecDeque::new(),
+            received_set: HashSet::new(),
+        }
+    }
+
+    /// Subscribes to the topic, announcing it to the connected peers. Returns false in case the
+    /// node is subscribed to it already.
+    pub fn subscribe(&mut self, topic: Topic) -> bool {
+        if self.subscribed.contains(&topic) {
+            return false;
+        }
+        let peers: Vec<_> = self.connected.keys().copied().collect();
+        for peer in peers {
+            self.send(peer, Vec::new(), vec![subscription(topic.clone())]);
+        }
+        self.subscribed.push(topic);
+        true
+    }
+
+    /// Publishes the message to the given peers which are connected and subscribed to any of the
+    /// topics, the other peers left out
+    pub fn publish<'a>(
+        &mut self,
+        peers: impl IntoIterator<Item = &'a PeerId>,
+        topics: Vec<Topic>,
+        data: Vec<u8>,
+    ) {
+        let message = FloodsubMessage {
+            source: self.local_peer_id,
+            data,
+            sequence_number: self.sequence_number.to_be_bytes().to_vec(),
+            topics,
+        };
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        for peer in peers {
+            let subscribed = self.connected.get(peer).is_some_and(|subscribed| {
+                subscribed
+                    .iter()
+                    .any(|topic| message.topics.contains(topic))
+            });
+            if subscribed {
+                self.send(*peer, vec![message.clone()], Vec::new());
+            }
+        }
+    }
+
+    /// Queues the frame to be sent to the peer
+    fn send(
+        &mut self,
+        peer: PeerId,
+        messages: Vec<FloodsubMessage>,
+        subscriptions: Vec<FloodsubSubscription>,
+    ) {
+        self.events
+            .push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer,
+                handler: NotifyHandler::Any,
+                event: FloodsubRpc {
+                    messages,
+                    subscriptions,
+                },
+            });
+    }
+
+    /// Remembers the message, returning false in case it was remembered already
+    fn remember(&mut self, message: &FloodsubMessage) -> bool {
+        let key = (message.source, message.sequence_number.clone());
+        if !self.received_set.insert(key.clone()) {
+            return false;
+        }
+        self.received.push_back(key);
+        if self.received.len() > RECEIVED_CAPACITY {
+            if let Some(oldest) = self.received.pop_front() {
+                self.received_set.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+fn subscription(topic: Topic) -> FloodsubSubscription {
+    FloodsubSubscription {
+        topic,
+        action: FloodsubSubscriptionAction::Subscribe,
+    }
+}
+
+impl NetworkBehaviour for Pubsub {
+    type ProtocolsHandler = OneShotHandler<FloodsubProtocol, FloodsubRpc, HandlerEvent>;
+    type OutEvent = FloodsubEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Default::default()
+    }
+
+    fn addresses_of_peer(&mut self, _peer: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, peer: &PeerId) {
+        // The topics are announced to every connected peer, so that all of them publish their
+        // messages to the node, whichever peers the node publishes to
+        if !self.subscribed.is_empty() {
+            let subscriptions = self.subscribed.iter().cloned().map(subscription).collect();
+            self.send(*peer, Vec::new(), subscriptions);
+        }
+        self.connected.insert(*peer, Vec::new());
+    }
+
+    fn inject_disconnected(&mut self, peer: &PeerId) {
+        self.connected.remove(peer);
+    }
+
+    fn inject_event(&mut self, source: PeerId, _connection: ConnectionId, event: HandlerEvent) {
+        let rpc = match event {
+            HandlerEvent::Received(rpc) => rpc,
+            HandlerEvent::Sent => return,
+        };
+
+        if let Some(topics) = self.connected.get_mut(&source) {
+            for subscription in rpc.subscriptions {
+                let event = match subscription.action {
+                    FloodsubSubscriptionAction::Subscribe => {
+                        if !topics.contains(&subscription.topic) {
+                            topics.push(subscription.topic.clone());
+                        }
+                        FloodsubEvent::Subscribed {
+                            peer_id: source,
+                            topic: subscription.topic,
+                        }
+                    }
+                    FloodsubSubscriptionAction::Unsubscribe => {
+                        topics.retain(|topic| topic != &subscription.topic);
+                        FloodsubEvent::Unsubscribed {
+                            peer_id: source,
+                            topic: subscription.topic,
+                        }
+                    }
+                };
+                self.events
+                    .push_back(NetworkBehaviourAction::GenerateEvent(event));
+            }
+        }
+
+        for message in rpc.messages {
+            let subscribed = message
+                .topics
+                .iter()
+                .any(|topic| self.subscribed.contains(topic));
+            if subscribed && self.remember(&message) {
+                self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    FloodsubEvent::Message(message),
+                ));
+            }
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+        _params: &mut impl PollParameters,
+    ) -> Poll<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        match self.events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// What the connection handler reports: a frame received from the peer, or one sent to it
+#[derive(Debug)]
+pub enum HandlerEvent {
+    Received(FloodsubRpc),
+    Sent,
+}
+
+impl From<FloodsubRpc> for HandlerEvent {
+    fn from(rpc: FloodsubRpc) -> Self {
+        HandlerEvent::Received(rpc)
+    }
+}
+
+impl From<()> for HandlerEvent {
+    fn from(_: ()) -> Self {
+        HandlerEvent::Sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            config::GossipConfig,
+            gossip::{GossipView, Relay},
+        },
+        std::time::Instant,
+    };
+
+    /// Takes the frames queued so far, counting the ones carrying messages per peer
+    fn sent(pubsub: &mut Pubsub) -> HashMap<PeerId, usize> {
+        let mut sent = HashMap::new();
+        for event in pubsub.events.drain(..) {
+            if let NetworkBehaviourAction::NotifyHandler { peer_id, event, .. } = event {
+                if !event.messages.is_empty() {
+                    *sent.entry(peer_id).or_default() += 1;
+                }
+            }
+        }
+        sent
+    }
+
+    fn connect(pubsub: &mut Pubsub, peer: PeerId, topic: &Topic) {
+        pubsub.inject_connected(&peer);
+        let rpc = FloodsubRpc {
+            messages: Vec::new(),
+            subscriptions: vec![subscription(topic.clone())],
+        };
+        pubsub.inject_event(peer, ConnectionId::new(0), HandlerEvent::Received(rpc));
+    }
+
+    #[test]
+    fn never_forward() {
+        let topic = Topic::new("blocks");
+        let mut pubsub = Pubsub::new(PeerId::random());
+        pubsub.subscribe(topic.clone());
+        let peers: Vec<_> = (0..3).map(|_| PeerId::random()).collect();
+        for peer in &peers {
+            connect(&mut pubsub, *peer, &topic);
+        }
+        // Every connected peer is told about the node's topics
+        let announced = pubsub
+            .events
+            .iter()
+            .filter(|event| matches!(event, NetworkBehaviourAction::NotifyHandler { .. }))
+            .count();
+        assert_eq!(announced, 3);
+        pubsub.events.clear();
+
+        let message = FloodsubMessage {
+            source: peers[0],
+            data: b"block".to_vec(),
+            sequence_number: vec![1],
+            topics: vec![topic.clone()],
+        };
+        for _ in 0..2 {
+            let rpc = FloodsubRpc {
+                messages: vec![message.clone()],
+                subscriptions: Vec::new(),
+            };
+            pubsub.inject_event(peers[0], ConnectionId::new(0), HandlerEvent::Received(rpc));
+        }
+        let handled = pubsub
+            .events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Message(_))
+                )
+            })
+            .count();
+        assert_eq!(handled, 1, "a message sent twice is handled once");
+        assert!(
+            sent(&mut pubsub).is_empty(),
+            "received messages are never passed on"
+        );
+    }
+
+    #[test]
+    fn publish_to_fanout() {
+        let topic = Topic::new("blocks");
+        let mut pubsub = Pubsub::new(PeerId::random());
+        pubsub.subscribe(topic.clone());
+        let mut gossip = GossipView::new(Some(2));
+        let peers: Vec<_> = (0..4).map(|_| PeerId::random()).collect();
+        for peer in &peers {
+            connect(&mut pubsub, *peer, &topic);
+            gossip.connected(*peer, false);
+        }
+        // A peer not subscribed to the topic gets nothing even when picked
+        let unsubscribed = PeerId::random();
+        pubsub.inject_connected(&unsubscribed);
+        pubsub.events.clear();
+
+        for _ in 0..3 {
+            let picked = gossip.peers().iter().chain([&unsubscribed]);
+            pubsub.publish(picked, vec![topic.clone()], b"block".to_vec());
+        }
+        let sent = sent(&mut pubsub);
+        assert_eq!(sent.get(&peers[0]), Some(&3));
+        assert_eq!(sent.get(&peers[1]), Some(&3));
+        assert_eq!(sent.get(&peers[2]), None);
+        assert_eq!(sent.get(&peers[3]), None);
+        assert_eq!(sent.get(&unsubscribed), None);
+    }
+
+    #[test]
+    fn relay_off() {
+        let topic = Topic::new("txs");
+        let mut pubsub = Pubsub::new(PeerId::random());
+        pubsub.subscribe(topic.clone());
+        let gossip_config = GossipConfig {
+            relay: false,
+            ..GossipConfig::default()
+        };
+        let mut relay = Relay::new(gossip_config);
+        let mut gossip = GossipView::new(None);
+        let peers: Vec<_> = (0..3).map(|_| PeerId::random()).collect();
+        for peer in &peers {
+            connect(&mut pubsub, *peer, &topic);
+            gossip.connected(*peer, false);
+        }
+        pubsub.events.clear();
+
+        let message = FloodsubMessage {
+            source: peers[0],
+            data: b"tx".to_vec(),
+            sequence_number: vec![1],
+            topics: vec![topic.clone()],
+        };
+        let rpc = FloodsubRpc {
+            messages: vec![message],
+            subscriptions: Vec::new(),
+        };
+        pubsub.inject_event(peers[0], ConnectionId::new(0), HandlerEvent::Received(rpc));
+        assert!(relay.received(&[String::from("txs")], b"tx", Instant::now()));
+        for (topics, data) in relay.due(Instant::now()) {
+            let topics = topics.into_iter().map(Topic::new).collect();
+            pubsub.publish(gossip.peers(), topics, data);
+        }
+        assert!(sent(&mut pubsub).is_empty(), "nothing is relayed");
+
+        // The node's own messages still go out
+        pubsub.publish(gossip.peers(), vec![topic], b"own".to_vec());
+        let sent = sent(&mut pubsub);
+        assert!(peers.iter().all(|peer| sent.get(peer) == Some(&1)));
+    }
+}
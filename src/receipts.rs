@@ -0,0 +1,214 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    serde::Serialize,
+    std::{
+        collections::{HashMap, VecDeque},
+        fmt,
+    },
+};
+
+/// The maximum number of transactions whose statuses are tracked, the oldest ones are forgotten
+/// first
+const MAX_TRACKED: usize = 10_000;
+
+/// Where a transaction submitted to the node is on its way into the blockchain
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TxStatus {
+    /// The transaction was received but not queued yet
+    Received,
+
+    /// The transaction waits in the mempool to be stored in a block
+    InMempool,
+
+    /// The transaction is stored in a block not deep enough to be confirmed yet
+    Mined {
+        height: u64,
+        block_hash: String,
+
+        /// The number of blocks on top of the one storing the transaction
+        confirmations: u64,
+    },
+
+    /// The transaction is stored in a block with enough blocks on top of it
+    Confirmed {
+        height: u64,
+        block_hash: String,
+
+        /// The number of blocks on top of the one storing the transaction
+        confirmations: u64,
+    },
+
+    /// The transaction will not be stored in a block
+    Dropped { reason: String },
+
+    /// The transaction was replaced by another one with the same account and nonce
+    Replaced {
+        /// The ID of the replacing transaction
+        by: String,
+    },
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxStatus::Received => write!(f, "received"),
+            TxStatus::InMempool => write!(f, "in mempool"),
+            TxStatus::Mined {
+                height,
+                confirmations,
+                ..
+            } => write!(
+                f,
+                "mined in block {} ({} confirmations)",
+                height, confirmations
+            ),
+            TxStatus::Confirmed {
+                height,
+                confirmations,
+                ..
+            } => write!(
+                f,
+                "confirmed in block {} ({} confirmations)",
+                height, confirmations
+            ),
+            TxStatus::Dropped { reason } => write!(f, "dropped: {}", reason),
+            TxStatus::Replaced { by } => write!(f, "replaced by {}", by),
+        }
+    }
+}
+
+/// The statuses of the transactions submitted to the node before they get stored in a block.
+/// Whether a transaction is mined or confirmed is read from the blockchain instead, so that it
+/// follows reorgs.
+#[derive(Debug)]
+pub struct Receipts {
+    /// The number of blocks on top of the one storing a transaction for it to be confirmed
+    depth: u64,
+
+    /// The latest recorded status of each tracked transaction, by ID
+    statuses: HashMap<String, TxStatus>,
+
+    /// The IDs of the tracked transactions, the oldest first
+    order: VecDeque<String>,
+}
+
+impl Receipts {
+    pub fn new(depth: u64) -> Self {
+        Self {
+            depth,
+            statuses: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Gets the number of blocks on top of the one storing a transaction for it to be confirmed
+    pub fn depth(&self) -> u64 {
+        self.depth
+    }
+
+    /// Records the status of the transactions with the given IDs
+    pub fn record(&mut self, tx_ids: &[String], status: TxStatus) {
+        for tx_id in tx_ids {
+            if self
+                .statuses
+                .insert(tx_id.clone(), status.clone())
+                .is_none()
+            {
+                self.order.push_back(tx_id.clone());
+            }
+        }
+        while self.order.len() > MAX_TRACKED {
+            if let Some(tx_id) = self.order.pop_front() {
+                self.statuses.remove(&tx_id);
+            }
+        }
+    }
+
+    /// Gets the status of the transaction, given the height and the hash of the block storing
+    /// it, if any, the height of the local blockchain and whether the transaction still waits in
+    /// the mempool. Returns `None` for transactions the node knows nothing about.
+    pub fn status(
+        &self,
+        tx_id: &str,
+        block: Option<(u64, String)>,
+        tip: u64,
+        in_mempool: bool,
+    ) -> Option<TxStatus> {
+        if let Some((height, block_hash)) = block {
+            let confirmations = tip.saturating_sub(height);
+            return Some(if confirmations >= self.depth {
+                TxStatus::Confirmed {
+                    height,
+                    block_hash,
+                    confirmations,
+                }
+            } else {
+                TxStatus::Mined {
+                    height,
+                    block_hash,
+                    confirmations,
+                }
+            });
+        }
+
+        match self.statuses.get(tx_id) {
+            _ if in_mempool => Some(TxStatus::InMempool),
+            Some(TxStatus::Received | TxStatus::InMempool) => Some(TxStatus::Dropped {
+                reason: String::from("no longer in the mempool, it expired or was evicted"),
+            }),
+            status => status.cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_transactions() {
+        let mut receipts = Receipts::new(2);
+        let ids = |id: &str| vec![id.to_owned()];
+        receipts.record(&ids("a"), TxStatus::InMempool);
+        receipts.record(&ids("b"), TxStatus::InMempool);
+        receipts.record(&ids("b"), TxStatus::Replaced { by: "c".to_owned() });
+        receipts.record(
+            &ids("d"),
+            TxStatus::Dropped {
+                reason: "invalid".to_owned(),
+            },
+        );
+
+        assert_eq!(
+            receipts.status("a", None, 5, true),
+            Some(TxStatus::InMempool)
+        );
+        assert!(matches!(
+            receipts.status("a", None, 5, false),
+            Some(TxStatus::Dropped { .. })
+        ));
+        assert_eq!(
+            receipts.status("b", None, 5, false),
+            Some(TxStatus::Replaced { by: "c".to_owned() })
+        );
+        assert_eq!(receipts.status("x", None, 5, false), None);
+
+        let block = Some((4, "hash".to_owned()));
+        assert!(matches!(
+            receipts.status("a", block.clone(), 5, false),
+            Some(TxStatus::Mined {
+                confirmations: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            receipts.status("a", block, 6, false),
+            Some(TxStatus::Confirmed {
+                confirmations: 2,
+                ..
+            })
+        ));
+        assert_eq!(receipts.order.len(), 3);
+    }
+}
@@ -0,0 +1,309 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        payload::{Payload, PayloadKind, DEFAULT_CHAIN_ID},
+        state::{State, StateError},
+    },
+    std::{collections::HashMap, fmt, sync::Arc},
+};
+
+/// Validates a payload of a specific kind stored in the block at the given height and applies it
+/// to the state, leaving the state untouched if the payload is invalid
+pub type Validator = fn(&Payload, u64, &mut State) -> Result<(), StateError>;
+
+/// Application-specific rules the data stored in blocks has to follow on top of the built-in ones,
+/// e.g. schema checks or business rules. They are enforced wherever blocks are validated, so every
+/// node of the network has to apply the same rules, or the nodes will disagree on which blocks
+/// are valid.
+pub trait PayloadValidator: Send + Sync {
+    /// Checks the payload stored in the block at the given height, given the state built by the
+    /// preceding payloads, returning the reason it breaks the rules, if it does. The payloads of
+    /// a batch are checked one by one after the batch itself, and the genesis block's payload is
+    /// checked at height 0.
+    fn validate(&self, payload: &Payload, height: u64, state: &State) -> Result<(), String>;
+}
+
+/// The payload kinds a chain accepts, along with their validators and the application-specific
+/// rules. It is built once at startup and carried by the state, so the payloads get validated
+/// with the same rules whichever copy of the state they are applied to.
+#[derive(Clone)]
+pub struct PayloadRegistry {
+    validators: HashMap<PayloadKind, Validator>,
+
+    /// The application-specific rules, in the order they were added
+    app_validators: Vec<Arc<dyn PayloadValidator>>,
+
+    /// The ID of the chain the signed payloads must be signed for
+    chain_id: String,
+}
+
+impl Default for PayloadRegistry {
+    /// Creates the registry of the built-in payload kinds, including the ones of the enabled
+    /// features
+    fn default() -> Self {
+        let mut registry = Self {
+            validators: HashMap::new(),
+            app_validators: Vec::new(),
+            chain_id: String::from(DEFAULT_CHAIN_ID),
+        };
+        registry.register(PayloadKind::Text, accept);
+        #[cfg(feature = "binary-payload")]
+        registry.register(PayloadKind::Binary, accept);
+        registry.register(PayloadKind::Batch, validate_batch);
+        registry.register(PayloadKind::Anchor, accept);
+        registry.register(PayloadKind::ChainAnchor, accept);
+        registry.register(PayloadKind::Register, validate_register);
+        registry.register(PayloadKind::Token, validate_token);
+        #[cfg(feature = "contracts")]
+        registry.register(PayloadKind::Contract, validate_contract);
+        registry.register(PayloadKind::Evidence, validate_evidence);
+        registry
+    }
+}
+
+impl fmt::Debug for PayloadRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PayloadRegistry")
+            .field("kinds", &self.validators.keys().collect::<Vec<_>>())
+            .field("app_validators", &self.app_validators.len())
+            .field("chain_id", &self.chain_id)
+            .finish()
+    }
+}
+
+impl PayloadRegistry {
+    /// Registers the validator of the payload kind, replacing the previous one, if any
+    pub fn register(&mut self, kind: PayloadKind, validator: Validator) {
+        self.validators.insert(kind, validator);
+    }
+
+    /// Stops accepting the payloads of the given kind
+    pub fn unregister(&mut self, kind: PayloadKind) {
+        self.validators.remove(&kind);
+    }
+
+    /// Accepts the payloads signed for the chain with the given ID only, instead of the default
+    /// chain
+    pub fn set_chain_id(&mut self, chain_id: &str) {
+        self.chain_id = chain_id.to_owned();
+    }
+
+    /// Gets the ID of the chain the signed payloads must be signed for
+    pub fn chain_id(&self) -> &str {
+        &self.chain_id
+    }
+
+    /// Adds application-specific rules, checked after the ones added before them
+    pub fn add_validator(&mut self, validator: Arc<dyn PayloadValidator>) {
+        self.app_validators.push(validator);
+    }
+
+    /// Validates the payload with the application-specific rules, then with the validator of its
+    /// kind, and applies it to the state
+    pub fn apply(
+        &self,
+        payload: &Payload,
+        height: u64,
+        state: &mut State,
+    ) -> Result<(), StateError> {
+        for validator in &self.app_validators {
+            validator
+                .validate(payload, height, state)
+                .map_err(|reason| StateError::Rejected { reason })?;
+        }
+
+        let kind = payload.kind();
+        match self.validators.get(&kind) {
+            Some(validator) => validator(payload, height, state),
+            None => Err(StateError::UnsupportedPayload { kind }),
+        }
+    }
+}
+
+/// Accepts payloads that do not affect the state
+fn accept(_payload: &Payload, _height: u64, _state: &mut State) -> Result<(), StateError> {
+    Ok(())
+}
+
+/// Validates each of the payloads in the batch. The batch is applied as a whole, so the state is
+/// left untouched if any of the payloads is invalid.
+fn validate_batch(payload: &Payload, height: u64, state: &mut State) -> Result<(), StateError> {
+    let payloads = match payload {
+        Payload::Batch(payloads) => payloads,
+        _ => return Err(StateError::InvalidBatch),
+    };
+    if payloads.is_empty() || payloads.iter().any(|p| p.kind() == PayloadKind::Batch) {
+        return Err(StateError::InvalidBatch);
+    }
+
+    let registry = Arc::clone(state.registry());
+    let mut updated = state.clone();
+    for payload in payloads {
+        registry.apply(payload, height, &mut updated)?;
+    }
+    *state = updated;
+    Ok(())
+}
+
+/// Validates the name registration signed by the name's owner
+fn validate_register(payload: &Payload, _height: u64, state: &mut State) -> Result<(), StateError> {
+    match payload {
+        Payload::Register {
+            name,
+            value,
+            revision,
+            owner,
+            ..
+        } => {
+            if !payload.is_signed(state.chain_id()) {
+                return Err(StateError::InvalidSignature {
+                    signer: owner.clone(),
+                });
+            }
+            state.register(name, value, *revision, owner)
+        }
+        _ => Err(StateError::UnsupportedPayload {
+            kind: payload.kind(),
+        }),
+    }
+}
+
+/// Validates the token operation signed by the account performing it
+fn validate_token(payload: &Payload, height: u64, state: &mut State) -> Result<(), StateError> {
+    match payload {
+        Payload::Token {
+            op,
+            nonce,
+            fee,
+            from,
+            ..
+        } => {
+            if !payload.is_signed(state.chain_id()) {
+                return Err(StateError::InvalidSignature {
+                    signer: from.clone(),
+                });
+            }
+            state.apply_token_op(op, *nonce, *fee, from, height)
+        }
+        _ => Err(StateError::UnsupportedPayload {
+            kind: payload.kind(),
+        }),
+    }
+}
+
+/// Validates the contract operation signed by the account performing it, executing the contract
+#[cfg(feature = "contracts")]
+fn validate_contract(payload: &Payload, _height: u64, state: &mut State) -> Result<(), StateError> {
+    match payload {
+        Payload::Contract {
+            op,
+            nonce,
+            fuel,
+            from,
+            ..
+        } => {
+            if !payload.is_signed(state.chain_id()) {
+                return Err(StateError::InvalidSignature {
+                    signer: from.clone(),
+                });
+            }
+            state.apply_contract_op(op, *nonce, *fuel, from)
+        }
+        _ => Err(StateError::UnsupportedPayload {
+            kind: payload.kind(),
+        }),
+    }
+}
+
+/// Validates the evidence of a block producer's equivocation, recording the offense
+fn validate_evidence(payload: &Payload, height: u64, state: &mut State) -> Result<(), StateError> {
+    match payload {
+        Payload::Evidence(evidence) => {
+            if !evidence.is_valid() {
+                return Err(StateError::InvalidEvidence {
+                    offender: evidence.offender.clone(),
+                });
+            }
+            state.punish(evidence, height)
+        }
+        _ => Err(StateError::UnsupportedPayload {
+            kind: payload.kind(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::tetherion::BlockData};
+
+    #[test]
+    fn unsupported_kind() {
+        let mut registry = PayloadRegistry::default();
+        registry.unregister(PayloadKind::Anchor);
+
+        let anchor = Payload::Anchor {
+            digest: String::from("00ab"),
+        };
+        assert_eq!(
+            registry.apply(&anchor, 1, &mut State::default()),
+            Err(StateError::UnsupportedPayload {
+                kind: PayloadKind::Anchor
+            })
+        );
+    }
+
+    #[test]
+    fn batch() {
+        let registry = PayloadRegistry::default();
+        let text = Payload::Text(String::from("data"));
+        let batch = Payload::Batch(vec![text.clone(), text.clone()]);
+        registry.apply(&batch, 1, &mut State::default()).unwrap();
+
+        let nested = Payload::Batch(vec![text, batch]);
+        assert_eq!(
+            registry.apply(&nested, 1, &mut State::default()),
+            Err(StateError::InvalidBatch)
+        );
+    }
+
+    #[test]
+    fn app_validator() {
+        /// Rejects the texts with the reserved prefix
+        struct ReservedPrefix;
+
+        impl PayloadValidator for ReservedPrefix {
+            fn validate(
+                &self,
+                payload: &Payload,
+                _height: u64,
+                _state: &State,
+            ) -> Result<(), String> {
+                match payload {
+                    Payload::Text(text) if text.starts_with("reserved:") => {
+                        Err(String::from("the prefix is reserved"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        let mut registry = PayloadRegistry::default();
+        registry.add_validator(Arc::new(ReservedPrefix));
+        let mut state = State::new(Arc::new(registry));
+        let reserved = Payload::Text(String::from("reserved:data"));
+        let expected = Err(StateError::Rejected {
+            reason: String::from("the prefix is reserved"),
+        });
+        assert_eq!(reserved.apply(1, &mut state), expected);
+
+        // The payloads of a batch are checked with the rules of the state they are applied to
+        let batch = Payload::Batch(vec![Payload::Text(String::from("data")), reserved.clone()]);
+        assert_eq!(batch.apply(1, &mut state), expected);
+        Payload::Text(String::from("data"))
+            .apply(1, &mut state)
+            .unwrap();
+
+        // Other states keep their own rules
+        reserved.apply(1, &mut State::default()).unwrap();
+    }
+}
@@ -0,0 +1,1743 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::Block,
+        codec::Format,
+        config::{ApiScope, ApiToken, RpcConfig},
+        p2p::{self, ChainSnapshot, TetherionBehaviour},
+        payload::Payload,
+        state::{BalanceView, State, StateView},
+        tetherion::{BlockData, InvalidBlockError, Tetherion},
+        tls::ServerTls,
+        version::BuildInfo,
+        work::SubmitWorkError,
+    },
+    hyper::{
+        body::HttpBody,
+        header::{self, HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+        server::conn::Http,
+        service::{make_service_fn, service_fn},
+        Body, Method, Request, Response, Server, StatusCode,
+    },
+    libp2p::swarm::Swarm,
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    serde_json::Value,
+    sha2::{Digest, Sha256},
+    std::{
+        collections::BTreeMap,
+        convert::Infallible,
+        fmt,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    },
+    tokio::{
+        net::TcpListener,
+        sync::{
+            mpsc::{self, error::TrySendError},
+            oneshot, watch,
+        },
+    },
+    utoipa::{OpenApi, ToSchema},
+};
+
+/// The methods served by the JSON-RPC server along with their positional parameters, listed in
+/// the OpenAPI document
+const METHODS: &[(&str, &str)] = &[
+    (
+        "getchaininfo",
+        "Gets the best and finalized tips of the local blockchain",
+    ),
+    (
+        "tokenbalance",
+        "`[address, token, view]` Gets the amount of the token owned by the account as of the `latest` (default), `confirmed` or `pending` state",
+    ),
+    (
+        "getwork",
+        "`[data]` Gets a block template for an external miner, the data is optional",
+    ),
+    (
+        "submitwork",
+        "`[work_id, nonce]` Adds the externally mined block to the blockchain",
+    ),
+    (
+        "getblocktemplate",
+        "Gets the height, previous hash, target, suggested timestamp and mempool transactions of the next block for an external producer",
+    ),
+    (
+        "submitblock",
+        "`[block]` Adds the externally constructed block, as JSON or HEX bincode, to the blockchain, or lists the rules it violates",
+    ),
+    (
+        "reindex",
+        "Rebuilds the state derived from the blocks, returning the number of blocks replayed",
+    ),
+    (
+        "storagestats",
+        "Gets the disk usage of the store by component",
+    ),
+    (
+        "propagationstats",
+        "Gets the histogram of the delays the blocks received from the peers took to propagate",
+    ),
+    (
+        "submitpayload",
+        "`[payload]` Mines a block storing the payload, returning its transaction IDs",
+    ),
+    (
+        "search",
+        "`[term]` Gets the transactions of the account, or the ones whose data has a word starting with the term",
+    ),
+    (
+        "estimatefee",
+        "`[target_blocks]` Gets the fee a token operation should pay to be stored within the target number of blocks",
+    ),
+    (
+        "txstatus",
+        "`[tx_id]` Gets where the transaction is on its way into the blockchain",
+    ),
+    (
+        "getmempool",
+        "`[offset, limit]` Gets a page of the transactions waiting in the mempool, both are optional",
+    ),
+    (
+        "gettransaction",
+        "`[tx_id]` Gets the transaction stored in the blockchain or waiting in the mempool, along with where it is on its way into the blockchain",
+    ),
+];
+
+/// The methods any token may call, the others requiring the admin scope
+const READ_METHODS: &[&str] = &[
+    "getchaininfo",
+    "tokenbalance",
+    "getblocktemplate",
+    "storagestats",
+    "propagationstats",
+    "search",
+    "estimatefee",
+    "txstatus",
+    "getmempool",
+    "gettransaction",
+];
+
+/// The number of items a page of a listing holds unless given a limit
+const DEFAULT_PAGE_LIMIT: u64 = 50;
+
+/// The most items a page of a listing holds
+const MAX_PAGE_LIMIT: u64 = 100;
+
+/// The number of blocks between the states kept to serve historical states from
+const STATE_SNAPSHOT_INTERVAL: u64 = 1_000;
+
+/// The most states kept to serve historical states from, the lowest ones being dropped first
+const MAX_STATE_SNAPSHOTS: usize = 100;
+
+/// The largest request body accepted, leaving room for a block of the maximum weight encoded in
+/// HEX or JSON
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Gets the scope needed to call the method
+fn required_scope(method: &str) -> ApiScope {
+    if READ_METHODS.contains(&method) {
+        ApiScope::Read
+    } else {
+        ApiScope::Admin
+    }
+}
+
+/// The OpenAPI document of the server
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Tetherion JSON-RPC"),
+    paths(
+        handle_call_request,
+        handle_api_docs,
+        handle_get_state,
+        handle_get_search,
+        handle_get_version,
+        handle_get_mempool,
+        handle_get_blocks,
+        handle_get_transaction
+    ),
+    components(schemas(RpcRequest, RpcResponse, RpcError))
+)]
+struct ApiDoc;
+
+/// Generates the OpenAPI document of the server, listing the supported JSON-RPC methods
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    let methods: Vec<_> = METHODS
+        .iter()
+        .map(|(method, description)| format!("- `{}` {}", method, description))
+        .collect();
+    doc.info.description = Some(format!(
+        "JSON-RPC 2.0 requests are sent as HTTP POST requests to `/`. The state as of a block is served at `GET /state`, the search at `GET /search` and the version of the node at `GET /version`. The mempool, the blocks and the transactions are listed at `GET /mempool`, `GET /blocks` and `GET /transactions/{{txid}}`, tagged with an `ETag` to send back in `If-None-Match`. Supported methods:\n\n{}",
+        methods.join("\n")
+    ));
+    doc
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct RpcError {
+    /// The JSON-RPC error code
+    pub code: i64,
+
+    /// The human-readable description of the error
+    pub message: String,
+
+    /// The details of the error, e.g. the block validation error a block got rejected with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+    pub const SERVER_ERROR: i64 = -32000;
+    pub const UNAUTHORIZED: i64 = -32001;
+
+    pub fn new(code: i64, message: impl fmt::Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+/// A JSON-RPC call forwarded to the node's event loop, which sends the result back
+#[derive(Debug)]
+pub struct RpcCall {
+    pub method: String,
+    pub params: Value,
+    pub reply: oneshot::Sender<Result<Value, RpcError>>,
+}
+
+impl RpcCall {
+    /// Parses the call's parameters, given either positionally or by name
+    fn params<P: DeserializeOwned>(&self) -> Result<P, RpcError> {
+        parse_params(&self.params)
+    }
+}
+
+/// Parses the parameters of a call, given either positionally or by name
+fn parse_params<P: DeserializeOwned>(params: &Value) -> Result<P, RpcError> {
+    serde_json::from_value(params.clone())
+        .map_err(|err| RpcError::new(RpcError::INVALID_PARAMS, err))
+}
+
+/// The channels the server reaches the node through
+#[derive(Clone)]
+struct Node {
+    /// The channel the calls needing the node's event loop are forwarded through, refusing calls
+    /// while it is full
+    sender: mpsc::Sender<RpcCall>,
+
+    /// The latest snapshot of the local blockchain, which read-only calls are served from
+    snapshots: watch::Receiver<Arc<ChainSnapshot>>,
+
+    /// The number of blocks confirmed balances lag behind the latest block
+    confirmations: u64,
+
+    /// The origins allowed to call the server and its certificate
+    config: Arc<RpcConfig>,
+
+    /// The tokens granting access to the server, which change when the configuration is reloaded
+    tokens: watch::Receiver<Vec<ApiToken>>,
+
+    /// Whether the server listens on localhost only, the only case it is served without tokens
+    loopback: bool,
+
+    /// The states kept to serve historical states from
+    states: Arc<StateSnapshots>,
+}
+
+/// The states of the local blockchain as of every few blocks, so that a historical state is
+/// replayed from the nearest state below it rather than from the first block
+struct StateSnapshots {
+    /// The number of blocks between the kept states
+    interval: u64,
+
+    /// The kept states by height, along with the hash of the block they are the state as of, which
+    /// tells whether a reorg replaced the block since
+    states: Mutex<BTreeMap<u64, (String, Arc<State>)>>,
+}
+
+impl StateSnapshots {
+    fn new(interval: u64) -> Self {
+        Self {
+            interval,
+            states: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Gets the state as of the block of the blockchain at the given height, replaying the blocks
+    /// from the nearest kept state below it and keeping the states passed by on the way. Returns
+    /// `None` if the blockchain has no block at the given height.
+    fn state_at(&self, tetherion: &Tetherion<Payload>, height: u64) -> Option<State> {
+        let blocks = tetherion.blocks();
+        let first = blocks.first()?.id;
+        let hash_at = |height: u64| {
+            let index = usize::try_from(height.checked_sub(first)?).ok()?;
+            blocks.get(index).map(|block| &block.hash)
+        };
+        hash_at(height)?;
+
+        let nearest = self
+            .states
+            .lock()
+            .expect("state snapshots lock")
+            .range(first..=height)
+            .rev()
+            .find(|(kept, (hash, _))| hash_at(**kept) == Some(hash))
+            .map(|(kept, (_, state))| (*kept, state.clone()));
+        let (mut from, mut state) = match nearest {
+            Some((kept, state)) => (kept, State::clone(&state)),
+            None => (first, tetherion.state_at(first)?),
+        };
+
+        while from < height {
+            let to = height.min((from / self.interval + 1) * self.interval);
+            state = tetherion.replay_state(state, from, to)?;
+            if to.is_multiple_of(self.interval) {
+                let mut states = self.states.lock().expect("state snapshots lock");
+                states.insert(to, (hash_at(to)?.clone(), Arc::new(state.clone())));
+                if states.len() > MAX_STATE_SNAPSHOTS {
+                    states.pop_first();
+                }
+            }
+            from = to;
+        }
+        Some(state)
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct RpcRequest {
+    /// Always `2.0`
+    #[schema(example = "2.0")]
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    /// One of the supported methods
+    #[schema(example = "getchaininfo")]
+    method: String,
+    /// The parameters, either positional or by name
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+struct RpcResponse {
+    jsonrpc: String,
+    id: Value,
+    /// The result of the call, absent in case of an error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        Self {
+            jsonrpc: String::from("2.0"),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Serves JSON-RPC 2.0 requests, sent as HTTP POST requests, on the given address. Read-only
+/// requests are served from the snapshots of the local blockchain, the others are forwarded to
+/// the node through the given channel. The requests are authorized with the latest tokens, in
+/// place of the configured ones.
+pub async fn serve(
+    addr: SocketAddr,
+    config: RpcConfig,
+    tokens: watch::Receiver<Vec<ApiToken>>,
+    sender: mpsc::Sender<RpcCall>,
+    snapshots: watch::Receiver<Arc<ChainSnapshot>>,
+    confirmations: u64,
+) {
+    let loopback = addr.ip().is_loopback();
+    if tokens.borrow().is_empty() && !loopback {
+        log::error!(
+            "cannot start RPC server on {}: tokens are required to listen beyond localhost",
+            addr
+        );
+        return;
+    }
+    let node = Node {
+        sender,
+        snapshots,
+        confirmations,
+        config: Arc::new(config),
+        tokens,
+        loopback,
+        states: Arc::new(StateSnapshots::new(STATE_SNAPSHOT_INTERVAL)),
+    };
+    if let Some(tls) = &node.config.tls {
+        let names = vec![String::from("localhost"), addr.ip().to_string()];
+        match ServerTls::new(tls, names) {
+            Ok(tls) => serve_tls(addr, tls, node).await,
+            Err(err) => log::error!("cannot start RPC server on {}: {}", addr, err),
+        }
+        return;
+    }
+
+    let make_service = make_service_fn(move |_| {
+        let node = node.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(request, node.clone()))) }
+    });
+
+    match Server::try_bind(&addr) {
+        Ok(builder) => {
+            log::info!("RPC server listening on {}", addr);
+            if let Err(err) = builder.serve(make_service).await {
+                log::error!("RPC server error: {}", err);
+            }
+        }
+        Err(err) => log::error!("cannot start RPC server on {}: {}", addr, err),
+    }
+}
+
+/// Serves the requests over HTTPS, each connection being handled once its TLS handshake succeeds
+async fn serve_tls(addr: SocketAddr, tls: ServerTls, node: Node) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("cannot start RPC server on {}: {}", addr, err);
+            return;
+        }
+    };
+    log::info!(
+        "RPC server listening on https://{}, certificate fingerprint {}",
+        addr,
+        tls.fingerprint
+    );
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                log::error!("RPC server error: {}", err);
+                continue;
+            }
+        };
+        let (acceptor, node) = (tls.acceptor.clone(), node.clone());
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::debug!("TLS handshake with {} failed: {}", peer, err);
+                    return;
+                }
+            };
+            let service = service_fn(move |request| handle(request, node.clone()));
+            if let Err(err) = Http::new().serve_connection(stream, service).await {
+                log::debug!("RPC connection with {} failed: {}", peer, err);
+            }
+        });
+    }
+}
+
+/// Handles a single HTTP request, carrying either a JSON-RPC request or a request for the OpenAPI
+/// document, once the caller is authorized
+async fn handle(request: Request<Body>, node: Node) -> Result<Response<Body>, Infallible> {
+    let origin = request.headers().get(header::ORIGIN).cloned();
+    let scope = authorize(request.headers(), &node.tokens.borrow(), node.loopback);
+    let response = match scope {
+        // Pages of other origins must not drive the node through the browsers visiting them
+        _ if origin
+            .as_ref()
+            .is_some_and(|origin| !is_allowed_origin(origin, &node.config.cors_origins)) =>
+        {
+            Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("origin not allowed"))
+                .expect("response should be built")
+        }
+        _ if request.method() == Method::OPTIONS => Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST, OPTIONS")
+            .header(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                "Authorization, Content-Type, If-None-Match",
+            )
+            .header(header::ACCESS_CONTROL_MAX_AGE, "3600")
+            .body(Body::empty())
+            .expect("response should be built"),
+        None => Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .body(Body::from("missing or invalid bearer token"))
+            .expect("response should be built"),
+        Some(scope) => match (request.method(), request.uri().path()) {
+            (&Method::GET, "/api-docs") => handle_api_docs(),
+            (&Method::GET, "/state") => handle_get_state(request.uri().query(), &node).await,
+            (&Method::GET, "/search") => handle_get_search(request.uri().query(), &node).await,
+            (&Method::GET, "/version") => handle_get_version(),
+            (&Method::GET, "/mempool") => {
+                let if_none_match = request.headers().get(header::IF_NONE_MATCH);
+                handle_get_mempool(request.uri().query(), if_none_match, &node).await
+            }
+            (&Method::GET, "/blocks") => handle_get_blocks(
+                request.uri().query(),
+                request.headers().get(header::IF_NONE_MATCH),
+                &node.snapshots.borrow(),
+            ),
+            (&Method::GET, path) if path.starts_with("/transactions/") => {
+                let tx_id = path.trim_start_matches("/transactions/");
+                let if_none_match = request.headers().get(header::IF_NONE_MATCH);
+                handle_get_transaction(tx_id, if_none_match, &node).await
+            }
+            (&Method::POST, _) if !is_json(request.headers()) => Response::builder()
+                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .body(Body::from("expected Content-Type: application/json"))
+                .expect("response should be built"),
+            (&Method::POST, _) => handle_call_request(request, node.clone(), scope).await,
+            _ => Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::empty())
+                .expect("response should be built"),
+        },
+    };
+    Ok(allow_origin(response, origin, &node.config.cors_origins))
+}
+
+/// Gets the scope granted by the bearer token the request carries, or `None` if the server
+/// requires a token and the request lacks a known one. Without tokens, only a server listening on
+/// localhost grants full access, which holds even when the tokens are removed by a reload.
+fn authorize(headers: &HeaderMap, tokens: &[ApiToken], loopback: bool) -> Option<ApiScope> {
+    if tokens.is_empty() {
+        return loopback.then_some(ApiScope::Admin);
+    }
+    let token = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?
+        .trim();
+    tokens
+        .iter()
+        .filter(|known| secrets_match(known.token.expose().as_bytes(), token.as_bytes()))
+        .map(|known| known.scope)
+        .max()
+}
+
+/// Compares the secrets in constant time, so that the time taken does not leak how much of a
+/// guess is right
+fn secrets_match(known: &[u8], given: &[u8]) -> bool {
+    known.len() == given.len()
+        && known
+            .iter()
+            .zip(given)
+            .fold(0, |diff, (known, given)| diff | (known ^ given))
+            == 0
+}
+
+/// Checks whether the request body is declared as JSON, which browsers cannot send to another
+/// origin without a preflight request
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Checks whether the origin is allowed to call the server
+fn is_allowed_origin(origin: &HeaderValue, allowed: &[String]) -> bool {
+    allowed
+        .iter()
+        .any(|allowed| allowed == "*" || origin.to_str().is_ok_and(|origin| origin == allowed))
+}
+
+/// Lets browsers read the response in case the origin calling the server is allowed to
+fn allow_origin(
+    mut response: Response<Body>,
+    origin: Option<HeaderValue>,
+    allowed: &[String],
+) -> Response<Body> {
+    let origin = match origin {
+        Some(origin) => origin,
+        None => return response,
+    };
+    if is_allowed_origin(&origin, allowed) {
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static("ETag"),
+        );
+    }
+    response
+}
+
+/// Serves the OpenAPI document of the server
+#[utoipa::path(
+    get,
+    path = "/api-docs",
+    responses((status = 200, description = "The OpenAPI document", content_type = "application/json"))
+)]
+fn handle_api_docs() -> Response<Body> {
+    let json = openapi().to_json().expect("can jsonify OpenAPI document");
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .expect("response should be built")
+}
+
+/// Serves the version, the git commit and the build profile of the node
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses((status = 200, description = "What the node is built from", content_type = "application/json"))
+)]
+fn handle_get_version() -> Response<Body> {
+    let json = serde_json::to_string(&BuildInfo::current()).expect("can jsonify build info");
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .expect("response should be built")
+}
+
+/// Gets the value of the parameter of the URL query, if given
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+/// Gets the state of the local blockchain as of the given height, the latest block by default,
+/// rebuilt by replaying the blocks up to it
+fn get_state(
+    query: Option<&str>,
+    snapshot: &ChainSnapshot,
+    states: &StateSnapshots,
+) -> Result<StateView, String> {
+    let tip = snapshot.tetherion.height();
+    let height = match query_param(query, "height") {
+        Some(height) => height
+            .parse::<u64>()
+            .map_err(|_| format!("invalid height: {}", height))?,
+        None => tip,
+    };
+    if height == tip {
+        return Ok(snapshot.tetherion.state().view(height));
+    }
+    states
+        .state_at(&snapshot.tetherion, height)
+        .map(|state| state.view(height))
+        .ok_or_else(|| format!("no block at height {}, the tip is at {}", height, tip))
+}
+
+/// Serves the names, tokens, balances and locks as of a block of the local blockchain
+#[utoipa::path(
+    get,
+    path = "/state",
+    params(("height" = Option<u64>, Query, description = "The height of the block, the latest one by default")),
+    responses(
+        (status = 200, description = "The state as of the block", content_type = "application/json"),
+        (status = 400, description = "There is no block at the height"),
+        (status = 500, description = "The state could not be rebuilt")
+    )
+)]
+async fn handle_get_state(query: Option<&str>, node: &Node) -> Response<Body> {
+    // Replaying the blocks takes a while for old heights, so it is kept off the server's threads
+    let query = query.map(str::to_owned);
+    let snapshot = node.snapshots.borrow().clone();
+    let states = node.states.clone();
+    let result =
+        tokio::task::spawn_blocking(move || get_state(query.as_deref(), &snapshot, &states)).await;
+    match result {
+        Ok(Ok(view)) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::to_string(&view).expect("can jsonify state"),
+            ))
+            .expect("response should be built"),
+        Ok(Err(err)) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(err))
+            .expect("response should be built"),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(format!("cannot rebuild the state: {}", err)))
+            .expect("response should be built"),
+    }
+}
+
+/// Serves the latest transactions of an account, or the ones whose data contains a word starting
+/// with the term, from the node's search index
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(("q" = String, Query, description = "An address or the beginning of a word")),
+    responses(
+        (status = 200, description = "The matching transactions, the latest first", content_type = "application/json"),
+        (status = 400, description = "The search term is missing")
+    )
+)]
+async fn handle_get_search(query: Option<&str>, node: &Node) -> Response<Body> {
+    let term = match query_param(query, "q").filter(|term| !term.is_empty()) {
+        Some(term) => term,
+        None => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("missing search term q"))
+                .expect("response should be built")
+        }
+    };
+    let term = percent_decode(term);
+    let result = forward(node, String::from("search"), serde_json::json!([term])).await;
+    match result {
+        Ok(hits) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(hits.to_string()))
+            .expect("response should be built"),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(err.message))
+            .expect("response should be built"),
+    }
+}
+
+/// Decodes the percent-encoded value of a URL query parameter, leaving malformed escapes as is
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (None, b'+') => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (None, byte) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// A page of a listing, along with the query of the next page, if any
+#[derive(Serialize, Debug)]
+struct Page<T> {
+    items: Vec<T>,
+
+    /// The number of items across all the pages
+    total: u64,
+
+    /// The path and query to get the next page at, `None` on the last page
+    next: Option<String>,
+}
+
+/// Parses the numeric parameter of the URL query, if given
+fn numeric_param(query: Option<&str>, name: &str) -> Result<Option<u64>, String> {
+    query_param(query, name)
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid {}: {}", name, value))
+        })
+        .transpose()
+}
+
+/// Gets the number of items a page holds given the requested limit, which must be positive
+fn page_limit(limit: Option<u64>) -> Result<u64, String> {
+    match limit {
+        Some(0) => Err(String::from("the limit must be positive")),
+        Some(limit) => Ok(limit.min(MAX_PAGE_LIMIT)),
+        None => Ok(DEFAULT_PAGE_LIMIT),
+    }
+}
+
+/// Builds the JSON response of a listing, tagged with the hash of its body, or a `304 Not
+/// Modified` one if the caller already has the same body, so that polling an unchanged listing
+/// does not transfer it again
+fn tagged_json(json: String, if_none_match: Option<&HeaderValue>) -> Response<Body> {
+    let etag = format!(
+        "\"{}\"",
+        hex::encode(&Sha256::digest(json.as_bytes())[..16])
+    );
+    let unchanged = if_none_match
+        .and_then(|tags| tags.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        });
+    let response = Response::builder().header(header::ETAG, etag);
+    if unchanged {
+        return response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .expect("response should be built");
+    }
+    response
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .expect("response should be built")
+}
+
+/// Serves a page of the transactions waiting in the mempool, from the node
+#[utoipa::path(
+    get,
+    path = "/mempool",
+    params(
+        ("offset" = Option<u64>, Query, description = "The number of transactions to skip, none by default"),
+        ("limit" = Option<u64>, Query, description = "The most transactions to get, 50 by default and 100 at most")
+    ),
+    responses(
+        (status = 200, description = "The page of transactions, in the order they are going to be stored in, along with their total number and the next page", content_type = "application/json"),
+        (status = 304, description = "The page is the same as the one tagged in If-None-Match"),
+        (status = 400, description = "The offset or the limit is invalid")
+    )
+)]
+async fn handle_get_mempool(
+    query: Option<&str>,
+    if_none_match: Option<&HeaderValue>,
+    node: &Node,
+) -> Response<Body> {
+    let params = (
+        numeric_param(query, "offset"),
+        numeric_param(query, "limit"),
+    );
+    let (offset, limit) = match params {
+        (Ok(offset), Ok(limit)) => (offset, limit),
+        (Err(err), _) | (_, Err(err)) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(err))
+                .expect("response should be built")
+        }
+    };
+    let params = serde_json::json!([offset.unwrap_or_default(), limit]);
+    match forward(node, String::from("getmempool"), params).await {
+        Ok(page) => tagged_json(page.to_string(), if_none_match),
+        Err(err) => Response::builder()
+            .status(match err.code {
+                RpcError::INVALID_PARAMS => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            })
+            .body(Body::from(err.message))
+            .expect("response should be built"),
+    }
+}
+
+/// Gets the blocks of the local blockchain from one height to another, both included, by default
+/// from the first block to the latest one, the page holding as many blocks as it may
+fn get_blocks(
+    query: Option<&str>,
+    snapshot: &ChainSnapshot,
+) -> Result<Page<Arc<Block<Payload>>>, String> {
+    let blocks = snapshot.tetherion.blocks();
+    let (first, tip) = (blocks[0].id, snapshot.tetherion.height());
+    let from = numeric_param(query, "from")?.unwrap_or(first).max(first);
+    let to = numeric_param(query, "to")?;
+    if let Some(to) = to.filter(|to| *to < from) {
+        return Err(format!("from {} is past to {}", from, to));
+    }
+    let last = to.unwrap_or(tip).min(tip);
+    let end = last.min(from.saturating_add(MAX_PAGE_LIMIT - 1));
+    let items = if from <= end {
+        blocks[(from - first) as usize..=(end - first) as usize].to_vec()
+    } else {
+        Vec::new()
+    };
+    let next = (end < last).then(|| match to {
+        Some(to) => format!("/blocks?from={}&to={}", end + 1, to),
+        None => format!("/blocks?from={}", end + 1),
+    });
+    Ok(Page {
+        items,
+        total: blocks.len() as u64,
+        next,
+    })
+}
+
+/// Serves the blocks of the local blockchain from one height to another, a page at a time
+#[utoipa::path(
+    get,
+    path = "/blocks",
+    params(
+        ("from" = Option<u64>, Query, description = "The height of the first block, the first stored one by default"),
+        ("to" = Option<u64>, Query, description = "The height of the last block, the latest one by default")
+    ),
+    responses(
+        (status = 200, description = "The page of blocks, 100 at most, along with the number of stored blocks and the next page", content_type = "application/json"),
+        (status = 304, description = "The page is the same as the one tagged in If-None-Match"),
+        (status = 400, description = "A height is invalid")
+    )
+)]
+fn handle_get_blocks(
+    query: Option<&str>,
+    if_none_match: Option<&HeaderValue>,
+    snapshot: &ChainSnapshot,
+) -> Response<Body> {
+    match get_blocks(query, snapshot) {
+        Ok(page) => tagged_json(
+            serde_json::to_string(&page).expect("can jsonify blocks"),
+            if_none_match,
+        ),
+        Err(err) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(err))
+            .expect("response should be built"),
+    }
+}
+
+/// Serves the transaction stored in the local blockchain or waiting in the mempool, from the node
+#[utoipa::path(
+    get,
+    path = "/transactions/{txid}",
+    params(("txid" = String, Path, description = "The ID of the transaction")),
+    responses(
+        (status = 200, description = "The transaction, its payload and where it is on its way into the blockchain", content_type = "application/json"),
+        (status = 304, description = "The transaction is the same as the one tagged in If-None-Match"),
+        (status = 404, description = "The node knows no such transaction")
+    )
+)]
+async fn handle_get_transaction(
+    tx_id: &str,
+    if_none_match: Option<&HeaderValue>,
+    node: &Node,
+) -> Response<Body> {
+    let params = serde_json::json!([percent_decode(tx_id)]);
+    match forward(node, String::from("gettransaction"), params).await {
+        Ok(transaction) => tagged_json(transaction.to_string(), if_none_match),
+        Err(err) => Response::builder()
+            .status(match err.code {
+                RpcError::SERVER_ERROR => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            })
+            .body(Body::from(err.message))
+            .expect("response should be built"),
+    }
+}
+
+/// Executes the JSON-RPC request carried by the HTTP request
+#[utoipa::path(
+    post,
+    path = "/",
+    request_body = RpcRequest,
+    responses((status = 200, description = "The JSON-RPC response", body = RpcResponse))
+)]
+async fn handle_call_request(
+    request: Request<Body>,
+    node: Node,
+    scope: ApiScope,
+) -> Response<Body> {
+    let declared = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > MAX_BODY_SIZE) {
+        return payload_too_large();
+    }
+    let response = match read_body(request.into_body(), MAX_BODY_SIZE).await {
+        Ok(Some(body)) => dispatch(&body, &node, scope).await,
+        Ok(None) => return payload_too_large(),
+        Err(err) => RpcResponse::new(Value::Null, Err(RpcError::new(RpcError::PARSE_ERROR, err))),
+    };
+
+    let json = serde_json::to_string(&response).expect("can jsonify response");
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .expect("response should be built")
+}
+
+/// Reads the body chunk by chunk, giving up with `None` once it grows past the limit, so that a
+/// body without a length, or lying about it, is not buffered whole
+async fn read_body(mut body: Body, limit: usize) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+/// Answers a request whose body exceeds the size the server accepts
+fn payload_too_large() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(Body::from(format!(
+            "request body exceeds {} bytes",
+            MAX_BODY_SIZE
+        )))
+        .expect("response should be built")
+}
+
+/// Serves the JSON-RPC request from the latest snapshot of the local blockchain, if it is
+/// read-only, or forwards it to the node and waits for its result otherwise, provided the caller's
+/// scope allows the method
+async fn dispatch(body: &[u8], node: &Node, scope: ApiScope) -> RpcResponse {
+    let request = match serde_json::from_slice::<RpcRequest>(body) {
+        Ok(request) => request,
+        Err(err) => {
+            return RpcResponse::new(Value::Null, Err(RpcError::new(RpcError::PARSE_ERROR, err)))
+        }
+    };
+
+    if request.jsonrpc != "2.0" {
+        return RpcResponse::new(
+            request.id,
+            Err(RpcError::new(
+                RpcError::INVALID_REQUEST,
+                "Only JSON-RPC 2.0 is supported",
+            )),
+        );
+    }
+
+    if required_scope(&request.method) > scope {
+        return RpcResponse::new(
+            request.id,
+            Err(RpcError::new(
+                RpcError::UNAUTHORIZED,
+                format!("Method {} requires the admin scope", request.method),
+            )),
+        );
+    }
+
+    let read = match request.method.as_str() {
+        "getchaininfo" => Some(Ok(handle_get_chain_info(&node.snapshots.borrow()))),
+        // Pending balances count the mempool, which only the node knows about
+        "tokenbalance" => match parse_params::<TokenBalanceParams>(&request.params) {
+            Ok(TokenBalanceParams(_, _, BalanceView::Pending)) => None,
+            Ok(params) => Some(Ok(handle_token_balance(
+                params,
+                &node.snapshots.borrow(),
+                node.confirmations,
+            ))),
+            Err(err) => Some(Err(err)),
+        },
+        _ => None,
+    };
+    if let Some(result) = read {
+        return RpcResponse::new(request.id, result);
+    }
+
+    let result = forward(node, request.method, request.params).await;
+    RpcResponse::new(request.id, result)
+}
+
+/// Forwards the call to the node and waits for its result. The call is refused right away if
+/// too many calls wait for the node already, rather than piling up while the node is busy.
+async fn forward(node: &Node, method: String, params: Value) -> Result<Value, RpcError> {
+    let (reply, reply_rcv) = oneshot::channel();
+    let call = RpcCall {
+        method,
+        params,
+        reply,
+    };
+
+    match node.sender.try_send(call) {
+        Ok(()) => reply_rcv
+            .await
+            .unwrap_or_else(|_| Err(RpcError::new(RpcError::INTERNAL_ERROR, "Node is gone"))),
+        Err(TrySendError::Full(call)) => {
+            log::warn!(
+                "refusing RPC call {}, {} calls are queued already",
+                call.method,
+                node.sender.max_capacity()
+            );
+            Err(RpcError::new(
+                RpcError::SERVER_ERROR,
+                "Node is busy, try again later",
+            ))
+        }
+        Err(TrySendError::Closed(_)) => {
+            Err(RpcError::new(RpcError::INTERNAL_ERROR, "Node is gone"))
+        }
+    }
+}
+
+/// Executes the JSON-RPC call on the node and sends the result back to the server
+pub fn handle_call(call: RpcCall, swarm: &mut Swarm<TetherionBehaviour>) {
+    let result = match call.method.as_str() {
+        "getwork" | "submitwork" if swarm.behaviour().light.is_some() => Err(RpcError::new(
+            RpcError::SERVER_ERROR,
+            "Light clients do not mine blocks",
+        )),
+        "getwork" | "submitwork" if !swarm.behaviour().tetherion.consensus().is_mined() => {
+            Err(RpcError::new(
+                RpcError::SERVER_ERROR,
+                "Blocks are sealed by authorities instead of being mined",
+            ))
+        }
+        "getwork" => handle_get_work(&call, swarm),
+        "getblocktemplate" if swarm.behaviour().light.is_some() => Err(RpcError::new(
+            RpcError::SERVER_ERROR,
+            "Light clients do not store blocks",
+        )),
+        "getblocktemplate" => {
+            let template = p2p::get_block_template(swarm);
+            Ok(serde_json::to_value(template).expect("can jsonify block template"))
+        }
+        "submitwork" => handle_submit_work(&call, swarm),
+        "submitblock" if swarm.behaviour().light.is_some() => Err(RpcError::new(
+            RpcError::SERVER_ERROR,
+            "Light clients do not store blocks",
+        )),
+        "submitblock" => handle_submit_block(&call, swarm),
+        "reindex" => handle_reindex(swarm),
+        "storagestats" => handle_storage_stats(swarm),
+        "propagationstats" => Ok(serde_json::to_value(&swarm.behaviour().propagation)
+            .expect("can jsonify propagation stats")),
+        "submitpayload" if swarm.behaviour().light.is_some() => Err(RpcError::new(
+            RpcError::SERVER_ERROR,
+            "Light clients do not mine blocks",
+        )),
+        "submitpayload" => handle_submit_payload(&call, swarm),
+        "search" => handle_search(&call, swarm),
+        "txstatus" => handle_tx_status(&call, swarm),
+        "getmempool" => handle_get_mempool_page(&call, swarm),
+        "gettransaction" => handle_get_transaction_call(&call, swarm),
+        "tokenbalance" => handle_pending_balance(&call, swarm),
+        "estimatefee" => handle_estimate_fee(&call, swarm),
+        method => Err(RpcError::new(
+            RpcError::METHOD_NOT_FOUND,
+            format!("Unknown method {}", method),
+        )),
+    };
+
+    if call.reply.send(result).is_err() {
+        log::error!("cannot reply to RPC call {}", call.method);
+    }
+}
+
+/// Handles `getwork [data]`, returning a block template for an external miner
+fn handle_get_work(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let data = match call.params {
+        Value::Null => String::new(),
+        _ => call.params::<(String,)>()?.0,
+    };
+
+    let template = p2p::get_work(Payload::Text(data), swarm);
+    Ok(serde_json::to_value(template).expect("can jsonify template"))
+}
+
+/// Handles `submitwork <work_id> <nonce>`, adding the externally mined block to the chain
+fn handle_submit_work(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let (work_id, nonce) = call.params::<(u64, u64)>()?;
+
+    match p2p::submit_work(work_id, nonce, swarm) {
+        Ok(hash) => Ok(serde_json::json!({ "hash": hash })),
+        Err(SubmitWorkError::InvalidBlock(err)) => {
+            Err(RpcError::new(RpcError::SERVER_ERROR, &err).with_data(violation(&err)))
+        }
+        Err(err) => Err(RpcError::new(RpcError::SERVER_ERROR, err)),
+    }
+}
+
+/// Handles `submitblock <block>`, adding the externally constructed block to the chain, or
+/// returning every consensus rule it violates, by kind, if it is rejected
+fn handle_submit_block(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let (block,) = call.params::<(Value,)>()?;
+    let block = parse_block(block)?;
+
+    match p2p::submit_block(block, swarm) {
+        Ok(hash) => Ok(serde_json::json!({ "accepted": true, "hash": hash })),
+        Err(violations) => {
+            let violations: Vec<_> = violations.iter().map(violation).collect();
+            Ok(serde_json::json!({ "accepted": false, "violations": violations }))
+        }
+    }
+}
+
+/// Describes the consensus rule the block violates with the error's kind, its context, e.g. the
+/// expected and actual hashes, and its message
+fn violation(err: &InvalidBlockError) -> Value {
+    let mut violation = serde_json::to_value(err).expect("can jsonify block error");
+    if let Value::Object(fields) = &mut violation {
+        fields.insert(String::from("message"), Value::String(err.to_string()));
+    }
+    violation
+}
+
+/// Parses a block given either as a JSON object or as its bincode encoding in HEX format
+fn parse_block(block: Value) -> Result<Block<Payload>, RpcError> {
+    let invalid = |err: &dyn fmt::Display| RpcError::new(RpcError::INVALID_PARAMS, err);
+    match block {
+        Value::String(encoded) => {
+            let bytes = hex::decode(encoded.trim()).map_err(|err| invalid(&err))?;
+            Format::Bincode.decode(&bytes).map_err(|err| invalid(&err))
+        }
+        block => serde_json::from_value(block).map_err(|err| invalid(&err)),
+    }
+}
+
+/// Handles `getchaininfo`, returning the best and finalized tips of the local blockchain
+fn handle_get_chain_info(snapshot: &ChainSnapshot) -> Value {
+    serde_json::json!({ "best": snapshot.best(), "finalized": snapshot.finalized })
+}
+
+/// Handles `reindex`, rebuilding the state derived from the blocks and returning the number of
+/// blocks replayed and whether the previous state was inconsistent
+fn handle_reindex(swarm: &mut Swarm<TetherionBehaviour>) -> Result<Value, RpcError> {
+    let blocks = swarm.behaviour().tetherion.blocks().len();
+    match p2p::reindex(swarm) {
+        Ok(repaired) => Ok(serde_json::json!({ "blocks": blocks, "repaired": repaired })),
+        Err(err) => Err(RpcError::new(RpcError::SERVER_ERROR, err)),
+    }
+}
+
+/// Handles `storagestats`, returning the disk usage of the store by component
+fn handle_storage_stats(swarm: &mut Swarm<TetherionBehaviour>) -> Result<Value, RpcError> {
+    match p2p::storage_stats(swarm) {
+        Some(Ok(stats)) => Ok(serde_json::json!({
+            "total": stats.total(),
+            "components": stats,
+        })),
+        Some(Err(err)) => Err(RpcError::new(RpcError::INTERNAL_ERROR, err)),
+        None => Err(RpcError::new(
+            RpcError::SERVER_ERROR,
+            "The blockchain is not stored on disk",
+        )),
+    }
+}
+
+/// The parameters of `tokenbalance`: the address, the token and the optional view
+#[derive(Deserialize, Debug)]
+struct TokenBalanceParams(String, String, #[serde(default)] BalanceView);
+
+/// Handles `tokenbalance <address> <token> [view]`, returning the amount of the token owned by
+/// the account as of the latest or the confirmed state of the snapshot
+fn handle_token_balance(
+    TokenBalanceParams(address, token, view): TokenBalanceParams,
+    snapshot: &ChainSnapshot,
+    confirmations: u64,
+) -> Value {
+    let balance = p2p::view_balance(
+        &snapshot.tetherion,
+        view,
+        confirmations,
+        std::iter::empty(),
+        &address,
+        &token,
+    );
+    serde_json::json!({ "balance": balance })
+}
+
+/// Handles `tokenbalance <address> <token> pending`, returning the amount of the token owned by
+/// the account once the operations waiting in the mempool are stored
+fn handle_pending_balance(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let TokenBalanceParams(address, token, view) = call.params()?;
+    let balance = p2p::token_balance(&address, &token, view, swarm);
+    Ok(serde_json::json!({ "balance": balance }))
+}
+
+/// Handles `search <term>`, returning the latest transactions the account takes part in, if the
+/// term is an address, or else the ones whose data contains a word starting with the term
+fn handle_search(call: &RpcCall, swarm: &mut Swarm<TetherionBehaviour>) -> Result<Value, RpcError> {
+    let (term,) = call.params::<(String,)>()?;
+    let behaviour = swarm.behaviour_mut();
+    behaviour.update_search_index();
+    Ok(serde_json::to_value(behaviour.search.search(&term)).expect("can jsonify search hits"))
+}
+
+/// Handles `estimatefee <target_blocks>`, returning the fee a token operation should pay to be
+/// stored within the target number of blocks
+fn handle_estimate_fee(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let (target_blocks,) = call.params::<(u64,)>()?;
+    if target_blocks == 0 {
+        return Err(RpcError::new(
+            RpcError::INVALID_PARAMS,
+            "The target number of blocks must be positive",
+        ));
+    }
+    let estimate = p2p::estimate_fee(target_blocks, swarm);
+    Ok(serde_json::to_value(estimate).expect("can jsonify fee estimate"))
+}
+
+/// Handles `txstatus <tx_id>`, returning where the transaction is on its way into the blockchain
+fn handle_tx_status(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let (tx_id,) = call.params::<(String,)>()?;
+    match p2p::tx_status(&tx_id, swarm) {
+        Some(status) => Ok(serde_json::to_value(status).expect("can jsonify transaction status")),
+        None => Err(RpcError::new(
+            RpcError::SERVER_ERROR,
+            format!("Unknown transaction {}", tx_id),
+        )),
+    }
+}
+
+/// The parameters of `getmempool`: the number of transactions to skip and the most to get
+#[derive(Deserialize, Debug, Default)]
+struct MempoolParams(#[serde(default)] u64, #[serde(default)] Option<u64>);
+
+/// Handles `getmempool [offset] [limit]`, returning a page of the transactions waiting in the
+/// mempool, in the order they are going to be stored in
+fn handle_get_mempool_page(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let MempoolParams(offset, limit) = match call.params {
+        Value::Null => MempoolParams::default(),
+        _ => call.params()?,
+    };
+    let limit = page_limit(limit).map_err(|err| RpcError::new(RpcError::INVALID_PARAMS, err))?;
+    let transactions = p2p::mempool_transactions(swarm.behaviour());
+    let total = transactions.len() as u64;
+    let items: Vec<_> = transactions
+        .into_iter()
+        .skip(usize::try_from(offset).unwrap_or(usize::MAX))
+        .take(limit as usize)
+        .map(|(tx_id, payload)| serde_json::json!({ "tx_id": tx_id, "payload": payload }))
+        .collect();
+    let next = offset
+        .checked_add(limit)
+        .filter(|next| *next < total)
+        .map(|next| format!("/mempool?offset={}&limit={}", next, limit));
+    Ok(serde_json::to_value(Page { items, total, next }).expect("can jsonify mempool"))
+}
+
+/// Handles `gettransaction <tx_id>`, returning the transaction stored in the local blockchain or
+/// waiting in the mempool, along with where it is on its way into the blockchain
+fn handle_get_transaction_call(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let (tx_id,) = call.params::<(String,)>()?;
+    match p2p::get_transaction(&tx_id, swarm) {
+        Some(transaction) => {
+            Ok(serde_json::to_value(transaction).expect("can jsonify transaction"))
+        }
+        None => Err(RpcError::new(
+            RpcError::SERVER_ERROR,
+            format!("Unknown transaction {}", tx_id),
+        )),
+    }
+}
+
+/// Handles `submitpayload <payload>`, mining a block storing a payload of any supported kind and
+/// returning the IDs of the transactions it consists of
+fn handle_submit_payload(
+    call: &RpcCall,
+    swarm: &mut Swarm<TetherionBehaviour>,
+) -> Result<Value, RpcError> {
+    let (payload,) = call.params::<(Payload,)>()?;
+    let tx_ids = payload.tx_ids();
+    p2p::submit_payload(payload, swarm)
+        .map_err(|err| RpcError::new(RpcError::SERVER_ERROR, err))?;
+    Ok(serde_json::json!({ "tx_ids": tx_ids }))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{payload::Payload, secret::Secret, tetherion::Tetherion},
+    };
+
+    #[tokio::test]
+    async fn dispatch_request() {
+        let (sender, mut receiver) = mpsc::channel::<RpcCall>(1);
+        tokio::spawn(async move {
+            while let Some(call) = receiver.recv().await {
+                let result = match call.method.as_str() {
+                    "echo" => Ok(call.params.clone()),
+                    _ => Err(RpcError::new(RpcError::METHOD_NOT_FOUND, "Unknown")),
+                };
+                call.reply.send(result).unwrap();
+            }
+        });
+        let (_, snapshots) = watch::channel(Arc::new(ChainSnapshot {
+            tetherion: Tetherion::new(Payload::Text(String::from("genesis")), 1),
+            finalized: None,
+        }));
+        let node = Node {
+            sender,
+            snapshots,
+            confirmations: 6,
+            config: Arc::default(),
+            tokens: watch::channel(Vec::new()).1,
+            loopback: true,
+            states: Arc::new(StateSnapshots::new(STATE_SNAPSHOT_INTERVAL)),
+        };
+
+        let response = dispatch(
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[7]}"#,
+            &node,
+            ApiScope::Admin,
+        )
+        .await;
+        assert_eq!(response.id, serde_json::json!(1));
+        assert_eq!(response.result, Some(serde_json::json!([7])));
+        assert!(response.error.is_none());
+
+        // Methods not known to be read-only need the admin scope
+        let response = dispatch(
+            br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[7]}"#,
+            &node,
+            ApiScope::Read,
+        )
+        .await;
+        assert_eq!(response.error.unwrap().code, RpcError::UNAUTHORIZED);
+
+        let response = dispatch(
+            br#"{"jsonrpc":"2.0","id":2,"method":"nope"}"#,
+            &node,
+            ApiScope::Admin,
+        )
+        .await;
+        assert_eq!(response.error.unwrap().code, RpcError::METHOD_NOT_FOUND);
+
+        let response = dispatch(
+            br#"{"jsonrpc":"1.0","id":3,"method":"echo"}"#,
+            &node,
+            ApiScope::Admin,
+        )
+        .await;
+        assert_eq!(response.error.unwrap().code, RpcError::INVALID_REQUEST);
+
+        let response = dispatch(b"not json", &node, ApiScope::Admin).await;
+        assert_eq!(response.error.unwrap().code, RpcError::PARSE_ERROR);
+
+        let response = dispatch(
+            br#"{"jsonrpc":"2.0","id":4,"method":"getchaininfo"}"#,
+            &node,
+            ApiScope::Read,
+        )
+        .await;
+        let best = node.snapshots.borrow().best();
+        assert_eq!(
+            response.result,
+            Some(serde_json::json!({ "best": best, "finalized": null }))
+        );
+
+        let balance = |params: &str| {
+            let body = format!(
+                r#"{{"jsonrpc":"2.0","id":5,"method":"tokenbalance","params":{}}}"#,
+                params
+            );
+            let node = node.clone();
+            async move { dispatch(body.as_bytes(), &node, ApiScope::Admin).await }
+        };
+        let zero = Some(serde_json::json!({ "balance": 0 }));
+        assert_eq!(balance(r#"["a","TTH"]"#).await.result, zero);
+        assert_eq!(balance(r#"["a","TTH","confirmed"]"#).await.result, zero);
+        // Pending balances are forwarded to the node
+        let response = balance(r#"["a","TTH","pending"]"#).await;
+        assert_eq!(response.error.unwrap().code, RpcError::METHOD_NOT_FOUND);
+        let response = balance(r#"["a","TTH","final"]"#).await;
+        assert_eq!(response.error.unwrap().code, RpcError::INVALID_PARAMS);
+
+        let snapshot = node.snapshots.borrow();
+        let states = &node.states;
+        assert_eq!(get_state(None, &snapshot, states).unwrap().height, 0);
+        let view = get_state(Some("height=0"), &snapshot, states).unwrap();
+        assert_eq!(view.height, 0);
+        assert!(get_state(Some("height=1"), &snapshot, states).is_err());
+        assert!(get_state(Some("height=x"), &snapshot, states).is_err());
+        assert_eq!(percent_decode("hello%2C+world%"), "hello, world%");
+    }
+
+    #[test]
+    fn authorize_requests() {
+        assert_eq!(
+            authorize(&HeaderMap::new(), &[], true),
+            Some(ApiScope::Admin)
+        );
+        // Beyond localhost, a server whose tokens were removed by a reload grants no access
+        assert_eq!(authorize(&HeaderMap::new(), &[], false), None);
+
+        let config = RpcConfig {
+            tokens: vec![
+                ApiToken {
+                    token: Secret::new(String::from("reader")),
+                    scope: ApiScope::Read,
+                },
+                ApiToken {
+                    token: Secret::new(String::from("admin")),
+                    scope: ApiScope::Admin,
+                },
+            ],
+            cors_origins: vec![String::from("https://explorer.example")],
+            ..RpcConfig::default()
+        };
+        let bearer = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static(value));
+            headers
+        };
+        assert_eq!(authorize(&HeaderMap::new(), &config.tokens, false), None);
+        assert_eq!(
+            authorize(&bearer("Bearer reade"), &config.tokens, false),
+            None
+        );
+        assert_eq!(
+            authorize(&bearer("Basic reader"), &config.tokens, false),
+            None
+        );
+        assert_eq!(
+            authorize(&bearer("Bearer reader"), &config.tokens, false),
+            Some(ApiScope::Read)
+        );
+        assert_eq!(
+            authorize(&bearer("Bearer admin"), &config.tokens, false),
+            Some(ApiScope::Admin)
+        );
+
+        let allowed = |origin: &'static str| {
+            let response = Response::new(Body::empty());
+            let origin = Some(HeaderValue::from_static(origin));
+            allow_origin(response, origin, &config.cors_origins)
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        };
+        assert!(allowed("https://explorer.example"));
+        assert!(!allowed("https://evil.example"));
+    }
+
+    #[tokio::test]
+    async fn reject_unsafe_requests() {
+        let (sender, _receiver) = mpsc::channel::<RpcCall>(1);
+        let (_, snapshots) = watch::channel(Arc::new(ChainSnapshot {
+            tetherion: Tetherion::new(Payload::Text(String::from("genesis")), 1),
+            finalized: None,
+        }));
+        let node = Node {
+            sender,
+            snapshots,
+            confirmations: 6,
+            config: Arc::new(RpcConfig {
+                cors_origins: vec![String::from("https://explorer.example")],
+                ..RpcConfig::default()
+            }),
+            tokens: watch::channel(Vec::new()).1,
+            loopback: true,
+            states: Arc::new(StateSnapshots::new(STATE_SNAPSHOT_INTERVAL)),
+        };
+        let call = |origin: Option<&'static str>, content_type: &'static str, body: Vec<u8>| {
+            let mut request = Request::post("/").header(CONTENT_TYPE, content_type);
+            if let Some(origin) = origin {
+                request = request.header(header::ORIGIN, origin);
+            }
+            let request = request.body(Body::from(body)).unwrap();
+            let node = node.clone();
+            async move { handle(request, node).await.unwrap().status() }
+        };
+        let body = br#"{"jsonrpc":"2.0","id":1,"method":"getchaininfo"}"#.to_vec();
+
+        assert_eq!(
+            call(None, "application/json", body.clone()).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(None, "application/json; charset=utf-8", body.clone()).await,
+            StatusCode::OK
+        );
+        // Forms are sent by browsers across origins without a preflight request
+        assert_eq!(
+            call(None, "application/x-www-form-urlencoded", body.clone()).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            call(
+                Some("https://evil.example"),
+                "application/json",
+                body.clone()
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            call(Some("https://explorer.example"), "application/json", body).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(None, "application/json", vec![b' '; MAX_BODY_SIZE + 1]).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        // The declared length is checked before reading the body
+        let request = Request::post("/")
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_LENGTH, MAX_BODY_SIZE + 1)
+            .body(Body::empty())
+            .unwrap();
+        let response = handle(request, node.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let body = read_body(Body::from(vec![0; 8]), 8).await.unwrap();
+        assert_eq!(body, Some(vec![0; 8]));
+        assert_eq!(read_body(Body::from(vec![0; 9]), 8).await.unwrap(), None);
+
+        // Calls needing the node are refused while the queue is full
+        let (reply, _) = oneshot::channel();
+        let queued = RpcCall {
+            method: String::from("reindex"),
+            params: Value::Null,
+            reply,
+        };
+        node.sender.try_send(queued).unwrap();
+        let err = forward(&node, String::from("reindex"), Value::Null)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, RpcError::SERVER_ERROR);
+    }
+
+    #[test]
+    fn parse_submitted_block() {
+        let block = Block::new(1, "previous", Payload::Text(String::from("data")), 1);
+        let json = serde_json::to_value(&block).unwrap();
+        assert_eq!(parse_block(json).unwrap(), block);
+        let encoded = hex::encode(Format::Bincode.encode(&block).unwrap());
+        assert_eq!(parse_block(Value::String(encoded)).unwrap(), block);
+        let err = parse_block(Value::String(String::from("zz"))).unwrap_err();
+        assert_eq!(err.code, RpcError::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn paginate_blocks() {
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        for id in 1..=2 {
+            let previous_hash = tetherion.blocks().last().unwrap().hash.clone();
+            let data = Payload::Text(format!("block {}", id));
+            tetherion
+                .add_block(Block::new(id, &previous_hash, data, 1))
+                .unwrap();
+        }
+        let snapshot = ChainSnapshot {
+            tetherion,
+            finalized: None,
+        };
+
+        let page = get_blocks(None, &snapshot).unwrap();
+        assert_eq!((page.items.len(), page.total, page.next), (3, 3, None));
+        let page = get_blocks(Some("from=1&to=1"), &snapshot).unwrap();
+        assert_eq!(page.items[0].id, 1);
+        assert_eq!(page.items.len(), 1);
+        // Polling past the latest block gets an empty page rather than an error
+        assert!(get_blocks(Some("from=3"), &snapshot)
+            .unwrap()
+            .items
+            .is_empty());
+        assert!(get_blocks(Some("from=2&to=1"), &snapshot).is_err());
+        assert!(get_blocks(Some("from=x"), &snapshot).is_err());
+
+        assert_eq!(page_limit(None), Ok(DEFAULT_PAGE_LIMIT));
+        assert_eq!(page_limit(Some(1000)), Ok(MAX_PAGE_LIMIT));
+        assert!(page_limit(Some(0)).is_err());
+    }
+
+    #[test]
+    fn replay_from_kept_states() {
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        let extend = |tetherion: &mut Tetherion<Payload>, data: &str| {
+            let previous = tetherion.blocks().last().unwrap().clone();
+            let data = Payload::Text(data.to_owned());
+            tetherion
+                .add_block(Block::new(previous.id + 1, &previous.hash, data, 1))
+                .unwrap();
+        };
+        for id in 1..=5 {
+            extend(&mut tetherion, &id.to_string());
+        }
+
+        let snapshots = StateSnapshots::new(2);
+        for height in [5, 3, 0, 4] {
+            assert_eq!(
+                snapshots.state_at(&tetherion, height),
+                tetherion.state_at(height)
+            );
+        }
+        let kept: Vec<_> = snapshots.states.lock().unwrap().keys().copied().collect();
+        assert_eq!(kept, vec![2, 4]);
+        assert!(snapshots.state_at(&tetherion, 6).is_none());
+
+        // A state kept for a block replaced by a reorg is not replayed from
+        tetherion.truncate(4).unwrap();
+        extend(&mut tetherion, "fork");
+        assert_eq!(snapshots.state_at(&tetherion, 4), tetherion.state_at(4));
+        let (hash, _) = snapshots.states.lock().unwrap()[&4].clone();
+        assert_eq!(hash, tetherion.blocks()[4].hash);
+    }
+
+    #[test]
+    fn tag_listings() {
+        let response = tagged_json(String::from("[]"), None);
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+
+        let weak = HeaderValue::from_str(&format!("W/{}", etag.to_str().unwrap())).unwrap();
+        for if_none_match in [etag.clone(), weak, HeaderValue::from_static("*")] {
+            let response = tagged_json(String::from("[]"), Some(&if_none_match));
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers()[header::ETAG], etag);
+        }
+        // A changed listing is sent again, under another tag
+        let response = tagged_json(String::from("[1]"), Some(&etag));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag);
+    }
+
+    #[test]
+    fn api_docs() {
+        let doc = openapi();
+        assert!(doc.paths.paths.contains_key("/"));
+        assert!(doc.paths.paths.contains_key("/api-docs"));
+        assert!(doc.paths.paths.contains_key("/state"));
+        assert!(doc.paths.paths.contains_key("/search"));
+        assert!(doc.paths.paths.contains_key("/version"));
+        assert!(doc.paths.paths.contains_key("/blocks"));
+        assert!(doc.paths.paths.contains_key("/transactions/{txid}"));
+        let description = doc.info.description.unwrap();
+        assert!(METHODS
+            .iter()
+            .all(|(method, _)| description.contains(&format!("`{}`", method))));
+        let schemas = doc.components.unwrap().schemas;
+        assert!(schemas.contains_key("RpcRequest") && schemas.contains_key("RpcError"));
+    }
+}
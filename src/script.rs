@@ -0,0 +1,121 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{block::hash, payload},
+    serde::{Deserialize, Serialize},
+};
+
+/// The condition that has to be satisfied for locked tokens to be spent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Script {
+    /// Satisfied once the blockchain reaches the given height
+    After { height: u64 },
+
+    /// Satisfied by the preimage, in HEX format, of the given SHA256 hash
+    HashLock { hash: String },
+
+    /// Satisfied by the signatures of at least `threshold` of the Ed25519 public keys
+    MultiSig { threshold: usize, keys: Vec<String> },
+
+    /// Satisfied if all of the scripts are satisfied
+    All(Vec<Script>),
+
+    /// Satisfied if any of the scripts is satisfied
+    Any(Vec<Script>),
+}
+
+/// The data provided by the spender to satisfy a script
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Witness {
+    /// The preimage, in HEX format, satisfying hash locks
+    pub preimage: Option<String>,
+
+    /// The signatures satisfying multisig conditions
+    pub signatures: Vec<KeySignature>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeySignature {
+    /// The Ed25519 public key, in HEX format
+    pub key: String,
+
+    /// The signature of the spend message, in HEX format
+    pub signature: String,
+}
+
+impl Script {
+    /// Evaluates the script against the witness at the given height. The signatures in the witness
+    /// have to sign the given spend message.
+    pub fn evaluate(&self, witness: &Witness, height: u64, message: &[u8]) -> bool {
+        match self {
+            Script::After { height: after } => height >= *after,
+            Script::HashLock { hash: expected } => witness
+                .preimage
+                .as_ref()
+                .and_then(|preimage| hex::decode(preimage).ok())
+                .is_some_and(|preimage| hex::encode(hash(&preimage)) == *expected),
+            Script::MultiSig { threshold, keys } => {
+                let signed = keys
+                    .iter()
+                    .filter(|key| {
+                        witness.signatures.iter().any(|signature| {
+                            signature.key == **key
+                                && payload::verify(key, message, &signature.signature)
+                        })
+                    })
+                    .count();
+                signed >= *threshold
+            }
+            Script::All(scripts) => scripts
+                .iter()
+                .all(|script| script.evaluate(witness, height, message)),
+            Script::Any(scripts) => scripts
+                .iter()
+                .any(|script| script.evaluate(witness, height, message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, libp2p::identity::ed25519};
+
+    #[test]
+    fn hash_time_lock() {
+        let refund = ed25519::Keypair::generate();
+        let refund_key = hex::encode(refund.public().encode());
+
+        // Spendable with the preimage, or by the refund key after height 10
+        let script = Script::Any(vec![
+            Script::HashLock {
+                hash: hex::encode(hash(b"secret")),
+            },
+            Script::All(vec![
+                Script::After { height: 10 },
+                Script::MultiSig {
+                    threshold: 1,
+                    keys: vec![refund_key.clone()],
+                },
+            ]),
+        ]);
+        let message = b"spend";
+
+        let with_preimage = Witness {
+            preimage: Some(hex::encode(b"secret")),
+            ..Default::default()
+        };
+        assert!(script.evaluate(&with_preimage, 0, message));
+
+        let with_signature = Witness {
+            preimage: Some(hex::encode(b"guess")),
+            signatures: vec![KeySignature {
+                key: refund_key,
+                signature: hex::encode(refund.sign(message)),
+            }],
+        };
+        assert!(!script.evaluate(&with_signature, 9, message));
+        assert!(script.evaluate(&with_signature, 10, message));
+        assert!(!script.evaluate(&with_signature, 10, b"other spend"));
+    }
+}
@@ -0,0 +1,321 @@
+/// Copyright (c) 2022 Tetherion
+#[cfg(feature = "contracts")]
+use crate::contracts::ContractOp;
+use {
+    crate::{
+        block::Block,
+        merkle,
+        payload::{Payload, TokenOp},
+    },
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+    },
+};
+
+/// The maximum number of transactions returned by a search
+pub const MAX_HITS: usize = 100;
+
+/// A transaction matching a search
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    /// The height of the block storing the transaction
+    pub height: u64,
+
+    /// The hash of the block storing the transaction
+    pub block_hash: String,
+
+    /// The ID of the transaction, i.e. its leaf in the block's Merkle tree
+    pub tx_id: String,
+
+    /// The payload of the transaction
+    pub payload: Payload,
+}
+
+/// The accounts taking part in the transaction, in HEX format
+fn addresses(payload: &Payload) -> Vec<&str> {
+    match payload {
+        Payload::Register { owner, .. } => vec![owner],
+        Payload::Evidence(evidence) => vec![&evidence.offender],
+        Payload::Token { op, from, .. } => match op {
+            TokenOp::Transfer { to, .. } | TokenOp::Unlock { to, .. } => vec![from, to],
+            _ => vec![from],
+        },
+        #[cfg(feature = "contracts")]
+        Payload::Contract { from, .. } => vec![from],
+        _ => Vec::new(),
+    }
+}
+
+/// The words of the data stored in the transaction, lowercased, which it can be searched by
+fn words(payload: &Payload) -> Vec<String> {
+    let text = match payload {
+        Payload::Text(text) => text.clone(),
+        #[cfg(feature = "binary-payload")]
+        Payload::Binary(data) => hex::encode(data),
+        Payload::Batch(_) | Payload::Evidence(_) => String::new(),
+        Payload::Anchor { digest } => digest.clone(),
+        Payload::ChainAnchor { chain_id, hash, .. } => format!("{} {}", chain_id, hash),
+        Payload::Register { name, value, .. } => format!("{} {}", name, value),
+        Payload::Token { op, .. } => match op {
+            TokenOp::Create { token, .. }
+            | TokenOp::Mint { token, .. }
+            | TokenOp::Transfer { token, .. }
+            | TokenOp::Lock { token, .. } => token.clone(),
+            TokenOp::Unlock { lock_id, .. } => lock_id.clone(),
+        },
+        #[cfg(feature = "contracts")]
+        Payload::Contract { op, .. } => match op.as_ref() {
+            ContractOp::Deploy { .. } => String::new(),
+            ContractOp::Call {
+                contract, function, ..
+            } => format!("{} {}", contract, function),
+        },
+    };
+    let mut words: Vec<_> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words.sort();
+    words.dedup();
+    words
+}
+
+/// Splits the data stored in a block into its transactions
+pub fn transactions(payload: &Payload) -> Vec<&Payload> {
+    match payload {
+        Payload::Batch(payloads) => payloads.iter().collect(),
+        payload => vec![payload],
+    }
+}
+
+/// An indexed transaction
+#[derive(Debug)]
+struct Entry {
+    height: u64,
+    block_hash: String,
+    tx_id: String,
+    payload: Payload,
+
+    /// The keys the transaction is indexed under, along with whether they are addresses
+    keys: Vec<(String, bool)>,
+}
+
+/// The index of the transactions of the local blockchain by the accounts taking part in them and
+/// by the words of their data, kept up to date block by block, so that searching does not scan
+/// the whole blockchain
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// The indexed blocks, by ID and hash, the oldest first
+    blocks: Vec<(u64, String)>,
+
+    /// The indexed transactions, in the order of the blocks storing them
+    entries: Vec<Entry>,
+
+    /// The positions of the transactions each account takes part in, in increasing order
+    by_address: HashMap<String, Vec<usize>>,
+
+    /// The positions of the transactions whose data contains each word, in increasing order
+    by_word: BTreeMap<String, Vec<usize>>,
+
+    /// The position of the latest transaction with each ID
+    by_tx: HashMap<String, usize>,
+}
+
+impl SearchIndex {
+    /// Brings the index up to date with the blocks of the local blockchain, dropping the blocks
+    /// no longer part of it and indexing the new ones
+    pub fn update(&mut self, blocks: &[Arc<Block<Payload>>]) {
+        let same = |(id, hash): &(u64, String), block: &Arc<Block<Payload>>| {
+            *id == block.id && *hash == block.hash
+        };
+        // Blocks are chained by their hashes, so the blocks preceding a kept one are kept as well
+        let mut kept = self.blocks.len().min(blocks.len());
+        while kept > 0 && !same(&self.blocks[kept - 1], &blocks[kept - 1]) {
+            kept -= 1;
+        }
+        if kept == self.blocks.len() && kept == blocks.len() {
+            return;
+        }
+
+        if let Some((first_dropped, _)) = self.blocks.get(kept) {
+            let first_dropped = *first_dropped;
+            while self
+                .entries
+                .last()
+                .is_some_and(|entry| entry.height >= first_dropped)
+            {
+                self.drop_last();
+            }
+            self.blocks.truncate(kept);
+        }
+        for block in &blocks[kept..] {
+            self.index(block);
+        }
+    }
+
+    /// Indexes the transactions of the block
+    fn index(&mut self, block: &Block<Payload>) {
+        for payload in transactions(block.data()) {
+            let position = self.entries.len();
+            let mut keys: Vec<_> = addresses(payload)
+                .into_iter()
+                .map(|address| (address.to_owned(), true))
+                .collect();
+            keys.dedup();
+            keys.extend(words(payload).into_iter().map(|word| (word, false)));
+            for (key, is_address) in &keys {
+                let positions = if *is_address {
+                    self.by_address.entry(key.clone()).or_default()
+                } else {
+                    self.by_word.entry(key.clone()).or_default()
+                };
+                positions.push(position);
+            }
+            let tx_id = merkle::leaf_hash(payload.to_string().as_bytes());
+            self.by_tx.insert(tx_id.clone(), position);
+            self.entries.push(Entry {
+                height: block.id,
+                block_hash: block.hash.clone(),
+                tx_id,
+                payload: payload.clone(),
+                keys,
+            });
+        }
+        self.blocks.push((block.id, block.hash.clone()));
+    }
+
+    /// Drops the latest indexed transaction
+    fn drop_last(&mut self) {
+        let entry = self.entries.pop().expect("there is an indexed transaction");
+        if self.by_tx.get(&entry.tx_id) == Some(&self.entries.len()) {
+            // The same transaction may be stored in an earlier block as well
+            match self
+                .entries
+                .iter()
+                .rposition(|kept| kept.tx_id == entry.tx_id)
+            {
+                Some(position) => self.by_tx.insert(entry.tx_id.clone(), position),
+                None => self.by_tx.remove(&entry.tx_id),
+            };
+        }
+        for (key, is_address) in entry.keys {
+            let index = if is_address {
+                self.by_address.get_mut(&key)
+            } else {
+                self.by_word.get_mut(&key)
+            };
+            if let Some(positions) = index {
+                positions.pop();
+                if positions.is_empty() {
+                    if is_address {
+                        self.by_address.remove(&key);
+                    } else {
+                        self.by_word.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gets the height and the hash of the latest block storing the transaction with the given ID
+    pub fn locate(&self, tx_id: &str) -> Option<(u64, String)> {
+        let entry = &self.entries[*self.by_tx.get(tx_id)?];
+        Some((entry.height, entry.block_hash.clone()))
+    }
+
+    /// Gets the payload of the latest transaction with the given ID
+    pub fn payload(&self, tx_id: &str) -> Option<&Payload> {
+        Some(&self.entries[*self.by_tx.get(tx_id)?].payload)
+    }
+
+    /// Gets the transactions the account takes part in, if the term is an address, or else the
+    /// transactions whose data contains a word starting with the term, the latest first
+    pub fn search(&self, term: &str) -> Vec<Hit> {
+        let mut positions: Vec<usize> = match self.by_address.get(term) {
+            Some(positions) => positions.clone(),
+            None => {
+                let prefix = term.to_lowercase();
+                self.by_word
+                    .range(prefix.clone()..)
+                    .take_while(|(word, _)| word.starts_with(&prefix))
+                    .flat_map(|(_, positions)| positions.iter().copied())
+                    .collect()
+            }
+        };
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+            .into_iter()
+            .rev()
+            .take(MAX_HITS)
+            .map(|position| {
+                let entry = &self.entries[position];
+                Hit {
+                    height: entry.height,
+                    block_hash: entry.block_hash.clone(),
+                    tx_id: entry.tx_id.clone(),
+                    payload: entry.payload.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{payload::DEFAULT_CHAIN_ID, tetherion::Tetherion},
+        libp2p::identity::ed25519,
+    };
+
+    #[test]
+    fn search_transactions() {
+        let keys = ed25519::Keypair::generate();
+        let address = hex::encode(keys.public().encode());
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        let extend = |tetherion: &mut Tetherion<Payload>, data: Payload| {
+            let previous = tetherion.blocks().last().unwrap().clone();
+            let block = Block::new(previous.id + 1, &previous.hash, data, 1);
+            tetherion.add_block(block).unwrap();
+        };
+        extend(&mut tetherion, Payload::Text(String::from("Hello, world")));
+        let op = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 100,
+            mintable: false,
+        };
+        extend(
+            &mut tetherion,
+            Payload::Batch(vec![
+                Payload::token(DEFAULT_CHAIN_ID, op, 0, &keys),
+                Payload::Text(String::from("help wanted")),
+            ]),
+        );
+
+        let mut index = SearchIndex::default();
+        index.update(tetherion.blocks());
+        let heights = |hits: Vec<Hit>| hits.iter().map(|hit| hit.height).collect::<Vec<_>>();
+        assert_eq!(heights(index.search("hel")), vec![2, 1]);
+        assert_eq!(heights(index.search("WORLD")), vec![1]);
+        assert_eq!(heights(index.search("tth")), vec![2]);
+        assert_eq!(
+            index.search(&address)[0].tx_id,
+            tetherion.blocks()[2].tx_ids()[0]
+        );
+
+        // A block replaced by another one at the same height drops its transactions
+        let tx_id = tetherion.blocks()[2].tx_ids()[0].clone();
+        assert_eq!(index.locate(&tx_id).map(|(height, _)| height), Some(2));
+        tetherion.truncate(2).unwrap();
+        extend(&mut tetherion, Payload::Text(String::from("other")));
+        index.update(tetherion.blocks());
+        assert!(index.locate(&tx_id).is_none());
+        assert!(index.search(&address).is_empty());
+        assert_eq!(heights(index.search("hel")), vec![1]);
+        assert_eq!(heights(index.search("other")), vec![2]);
+    }
+}
@@ -0,0 +1,66 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    serde::Deserialize,
+    std::fmt::{self, Debug, Display},
+};
+
+/// What a secret is printed as
+const REDACTED: &str = "[redacted]";
+
+/// A value that must never end up in the logs, e.g. a private key or an API token. Its `Debug`
+/// and `Display` only show that it is redacted, so that printing a structure holding it, or
+/// panicking on it, does not leak it; the value is read with `expose`.
+#[derive(Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Gets the secret value, which should only be passed on to where it is used
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_output() {
+        #[derive(Deserialize, Debug)]
+        struct Credentials {
+            user: String,
+            password: Secret<String>,
+        }
+
+        let credentials: Credentials =
+            serde_json::from_str(r#"{"user":"alice","password":"hunter2"}"#).unwrap();
+        let printed = format!("{:?} {}", credentials, credentials.password);
+        assert!(!printed.contains("hunter2"));
+        assert!(printed.contains("alice") && printed.contains(REDACTED));
+        assert_eq!(credentials.user, "alice");
+        assert_eq!(credentials.password.expose(), "hunter2");
+    }
+}
@@ -0,0 +1,182 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::config::ServingConfig,
+    std::{
+        collections::{HashMap, VecDeque},
+        fmt,
+        time::{Duration, Instant},
+    },
+};
+
+/// The window the requests of a peer are counted over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// The reason a request for the local blockchain is not served
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServingError {
+    /// The peer made too many requests during the last minute
+    RateLimited,
+
+    /// As many responses as allowed are being served to the peer already
+    PeerBusy,
+
+    /// As many responses as allowed are being served already
+    Busy,
+
+    /// The node runs on a metered connection, serving no full blockchain
+    Metered,
+}
+
+impl ServingError {
+    /// Gets the short name of the error, which the log is throttled by
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::RateLimited => "rate_limited",
+            Self::PeerBusy => "peer_busy",
+            Self::Busy => "busy",
+            Self::Metered => "metered",
+        }
+    }
+}
+
+impl fmt::Display for ServingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::RateLimited => write!(f, "too many requests during the last minute"),
+            Self::PeerBusy => write!(f, "responses to the peer are being served already"),
+            Self::Busy => write!(f, "too many responses are being served already"),
+            Self::Metered => write!(f, "the connection is metered"),
+        }
+    }
+}
+
+/// Keeps track of the responses the node serves to the peers requesting its blockchain, so that
+/// a handful of syncing, or malicious, peers cannot keep the node busy serving them
+#[derive(Debug)]
+pub struct ServingLimiter {
+    config: ServingConfig,
+
+    /// The times each peer made its requests during the last minute at, the oldest first
+    requests: HashMap<String, VecDeque<Instant>>,
+
+    /// The number of responses being served to each peer
+    serving: HashMap<String, usize>,
+
+    /// Whether the requests for the full blockchain are all rejected
+    metered: bool,
+}
+
+impl ServingLimiter {
+    pub fn new(config: ServingConfig) -> Self {
+        Self {
+            config,
+            requests: HashMap::new(),
+            serving: HashMap::new(),
+            metered: false,
+        }
+    }
+
+    /// Rejects every request for the full blockchain, as a node on a metered connection does
+    pub fn with_metered(self, metered: bool) -> Self {
+        Self { metered, ..self }
+    }
+
+    /// Checks whether the request for the full blockchain the peer made at the given time is
+    /// served, the same way as [`Self::admit`] unless the connection is metered
+    pub fn admit_chain(&mut self, peer: &str, now: Instant) -> Result<(), ServingError> {
+        if self.metered {
+            return Err(ServingError::Metered);
+        }
+        self.admit(peer, now)
+    }
+
+    /// Checks whether the request the peer made at the given time is served, counting the
+    /// response being served if so; every request counts towards the rate of the peer, even the
+    /// rejected ones
+    pub fn admit(&mut self, peer: &str, now: Instant) -> Result<(), ServingError> {
+        let requests = self.requests.entry(peer.to_owned()).or_default();
+        while requests
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW)
+        {
+            requests.pop_front();
+        }
+        requests.push_back(now);
+        if requests.len() > self.config.requests_per_minute as usize {
+            return Err(ServingError::RateLimited);
+        }
+
+        if self.serving.get(peer).copied().unwrap_or(0) >= self.config.max_responses_per_peer {
+            return Err(ServingError::PeerBusy);
+        }
+        if self.in_flight() >= self.config.max_responses {
+            return Err(ServingError::Busy);
+        }
+        *self.serving.entry(peer.to_owned()).or_default() += 1;
+        Ok(())
+    }
+
+    /// Records that a response to the peer got served
+    pub fn served(&mut self, peer: &str) {
+        if let Some(serving) = self.serving.get_mut(peer) {
+            *serving -= 1;
+            if *serving == 0 {
+                self.serving.remove(peer);
+            }
+        }
+    }
+
+    /// Gets the number of responses being served
+    pub fn in_flight(&self) -> usize {
+        self.serving.values().sum()
+    }
+
+    /// Forgets about the peers that made no requests during the last minute
+    pub fn expire(&mut self, now: Instant) {
+        self.requests.retain(|_, requests| {
+            requests
+                .back()
+                .is_some_and(|at| now.duration_since(*at) < RATE_WINDOW)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_responses() {
+        let mut limiter = ServingLimiter::new(ServingConfig {
+            max_responses: 2,
+            max_responses_per_peer: 1,
+            requests_per_minute: 3,
+        });
+        let start = Instant::now();
+        assert_eq!(limiter.admit("a", start), Ok(()));
+        assert_eq!(limiter.admit("a", start), Err(ServingError::PeerBusy));
+        assert_eq!(limiter.admit("b", start), Ok(()));
+        assert_eq!(limiter.admit("c", start), Err(ServingError::Busy));
+        assert_eq!(limiter.in_flight(), 2);
+
+        limiter.served("a");
+        assert_eq!(limiter.admit("a", start), Ok(()));
+        limiter.served("a");
+        // The rejected requests count towards the rate as well
+        assert_eq!(limiter.admit("a", start), Err(ServingError::RateLimited));
+        assert_eq!(
+            limiter.admit("a", start + RATE_WINDOW / 2),
+            Err(ServingError::RateLimited)
+        );
+        assert_eq!(limiter.admit("a", start + RATE_WINDOW), Ok(()));
+
+        limiter.served("b");
+        limiter.expire(start + RATE_WINDOW);
+        assert!(!limiter.requests.contains_key("b"));
+        assert!(limiter.requests.contains_key("a"));
+
+        let mut limiter = ServingLimiter::new(ServingConfig::default()).with_metered(true);
+        assert_eq!(limiter.admit_chain("a", start), Err(ServingError::Metered));
+        assert_eq!(limiter.admit("a", start), Ok(()));
+    }
+}
@@ -0,0 +1,682 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{block::Block, tetherion::Tetherion},
+    serde::Serialize,
+    std::{cmp::Reverse, collections::BinaryHeap, fmt, sync::Arc},
+};
+
+/// The difficulty of the virtual nodes' blockchains, none, as finding a block is drawn at random
+/// instead of being hashed for; every block then takes the same work
+const DIFFICULTY: usize = 0;
+
+/// The settings of a simulated network of virtual nodes, mining and gossiping blocks in virtual
+/// time instead of running actual nodes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimConfig {
+    /// The number of virtual nodes
+    pub nodes: usize,
+
+    /// The number of random peers each node is connected to, on top of the previous node
+    pub degree: usize,
+
+    /// The number of blocks mined before the simulation stops
+    pub blocks: u64,
+
+    /// The mean interval, in milliseconds, between two blocks mined by the whole network
+    pub block_interval_ms: u64,
+
+    /// The lowest and the highest latency, in milliseconds, of a message between two peers
+    pub latency_ms: (u64, u64),
+
+    /// How unevenly the mining power is distributed: the i-th node gets a power of 1 / i^skew, 0
+    /// spreading it evenly
+    pub power_skew: f64,
+
+    /// The split of the network, if any
+    pub partition: Option<Partition>,
+
+    /// The seed of the pseudorandom numbers, the same seed replaying the same simulation
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            nodes: 100,
+            degree: 8,
+            blocks: 200,
+            block_interval_ms: 10_000,
+            latency_ms: (20, 200),
+            power_skew: 0.0,
+            partition: None,
+            seed: 0,
+        }
+    }
+}
+
+/// A split of the network into two sides exchanging no messages for a while; the messages held
+/// back are delivered once the split heals, as the nodes would sync with each other
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Partition {
+    /// When the network splits, in milliseconds of virtual time
+    pub start_ms: u64,
+
+    /// How long the network stays split, in milliseconds
+    pub duration_ms: u64,
+
+    /// The share of the nodes on the first side of the split
+    pub share: f64,
+}
+
+impl Partition {
+    /// Gets when the split heals
+    fn end_ms(&self) -> u64 {
+        self.start_ms.saturating_add(self.duration_ms)
+    }
+}
+
+/// The outcome of a simulation
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SimReport {
+    /// The number of mined blocks
+    pub mined: u64,
+
+    /// The height of the blockchain most nodes ended up with
+    pub height: u64,
+
+    /// The share of the mined blocks left out of that blockchain
+    pub fork_rate: f64,
+
+    /// The number of times a node switched to a blockchain not building on its own
+    pub reorgs: u64,
+
+    /// The largest number of blocks a node dropped switching blockchains
+    pub max_reorg_depth: u64,
+
+    /// The mean time, in milliseconds, from a block being mined to every node building on it
+    pub convergence_mean_ms: Option<u64>,
+
+    /// The 90th percentile of the convergence times, in milliseconds
+    pub convergence_p90_ms: Option<u64>,
+
+    /// The longest convergence time, in milliseconds
+    pub convergence_max_ms: Option<u64>,
+
+    /// The share of the nodes ending up with the blockchain most nodes ended up with
+    pub agreement: f64,
+
+    /// The share of the blocks of that blockchain mined by the first node, the most powerful one
+    /// when the mining power is skewed
+    pub first_miner_share: f64,
+
+    /// The virtual time the simulation took, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl fmt::Display for SimReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |value: Option<u64>| value.map_or_else(|| String::from("-"), |ms| ms.to_string());
+        writeln!(
+            f,
+            "Mined {} blocks in {}s of virtual time, {} on the final blockchain",
+            self.mined,
+            self.duration_ms / 1_000,
+            self.height
+        )?;
+        writeln!(
+            f,
+            "Fork rate: {:.2}%, {} reorgs, the deepest of {} blocks",
+            self.fork_rate * 100.0,
+            self.reorgs,
+            self.max_reorg_depth
+        )?;
+        writeln!(
+            f,
+            "Convergence: mean {}ms, p90 {}ms, max {}ms",
+            ms(self.convergence_mean_ms),
+            ms(self.convergence_p90_ms),
+            ms(self.convergence_max_ms)
+        )?;
+        writeln!(
+            f,
+            "First miner: {:.2}% of the final blockchain",
+            self.first_miner_share * 100.0
+        )?;
+        write!(f, "Agreement: {:.2}% of the nodes", self.agreement * 100.0)
+    }
+}
+
+/// Runs the simulation to completion: once the configured number of blocks is mined, the blocks
+/// still on their way are delivered before the outcome is computed
+pub fn run(config: SimConfig) -> Result<SimReport, String> {
+    if config.nodes == 0 || config.blocks == 0 || config.block_interval_ms == 0 {
+        return Err(String::from(
+            "The number of nodes, the number of blocks and the block interval should be positive",
+        ));
+    }
+    if config.latency_ms.0 > config.latency_ms.1 {
+        return Err(String::from(
+            "The lowest latency should not exceed the highest one",
+        ));
+    }
+    if config.power_skew < 0.0 {
+        return Err(String::from("The power skew should not be negative"));
+    }
+    if let Some(partition) = config.partition {
+        if !(partition.share > 0.0 && partition.share < 1.0) {
+            return Err(String::from(
+                "The partitioned share of the nodes should be between 0 and 1",
+            ));
+        }
+    }
+    Ok(Simulation::new(config).run())
+}
+
+/// Something happening at a point of virtual time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    /// The network finds the next block
+    Mine,
+
+    /// The block reaches the node
+    Deliver { node: usize, block: usize },
+}
+
+/// A mined block, along with what the nodes know of it
+#[derive(Debug)]
+struct SimBlock {
+    /// The block, timestamped with the virtual time it was mined at, in seconds
+    block: Arc<Block<String>>,
+
+    /// The index of the previous block, none for the genesis block
+    parent: Option<usize>,
+
+    /// The node that mined the block
+    miner: usize,
+
+    mined_at: u64,
+
+    /// Whether each node received the block
+    seen: Vec<bool>,
+
+    /// The number of nodes whose blockchain includes the block
+    adopted_by: usize,
+
+    /// When every node's blockchain first included the block
+    converged_at: Option<u64>,
+}
+
+/// The state of a running simulation
+struct Simulation {
+    config: SimConfig,
+    rng: Rng,
+
+    /// The current virtual time, in milliseconds
+    now: u64,
+
+    /// The events to come, by time, then by scheduling order
+    events: BinaryHeap<Reverse<(u64, u64, Event)>>,
+    scheduled: u64,
+
+    blocks: Vec<SimBlock>,
+    mined: u64,
+
+    /// The blockchain of each node
+    chains: Vec<Tetherion<String>>,
+
+    /// The index of the latest block of each node's blockchain
+    tips: Vec<usize>,
+
+    /// The peers each node relays the blocks to
+    peers: Vec<Vec<usize>>,
+
+    /// The cumulative mining power of the nodes, the last one's being 1
+    power: Vec<f64>,
+
+    reorgs: u64,
+    max_reorg_depth: u64,
+}
+
+impl Simulation {
+    fn new(config: SimConfig) -> Self {
+        let mut rng = Rng(config.seed);
+        let nodes = config.nodes;
+
+        // Each node is connected to the previous one, so that the network is connected, and to
+        // random others
+        let mut peers = vec![Vec::new(); nodes];
+        for node in 1..nodes {
+            let mut links = vec![node - 1];
+            for _ in 0..config.degree.min(nodes - 1) {
+                links.push(rng.below(nodes as u64) as usize);
+            }
+            for peer in links {
+                if peer != node && !peers[node].contains(&peer) {
+                    peers[node].push(peer);
+                    peers[peer].push(node);
+                }
+            }
+        }
+
+        let weights: Vec<f64> = (1..=nodes)
+            .map(|i| (i as f64).powf(-config.power_skew))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        let power = weights
+            .iter()
+            .scan(0.0, |sum, weight| {
+                *sum += weight / total;
+                Some(*sum)
+            })
+            .collect();
+
+        let genesis = Block::with_nonce(0, "", 0, 0, String::from("genesis"));
+        let chain =
+            Tetherion::from_genesis(genesis.clone(), DIFFICULTY).expect("genesis block is valid");
+        let genesis = SimBlock {
+            block: Arc::new(genesis),
+            parent: None,
+            miner: 0,
+            mined_at: 0,
+            seen: vec![true; nodes],
+            adopted_by: nodes,
+            converged_at: Some(0),
+        };
+        Self {
+            config,
+            rng,
+            now: 0,
+            events: BinaryHeap::new(),
+            scheduled: 0,
+            blocks: vec![genesis],
+            mined: 0,
+            chains: vec![chain; nodes],
+            tips: vec![0; nodes],
+            peers,
+            power,
+            reorgs: 0,
+            max_reorg_depth: 0,
+        }
+    }
+
+    fn run(mut self) -> SimReport {
+        self.schedule_mining();
+        while let Some(Reverse((time, _, event))) = self.events.pop() {
+            self.now = time;
+            match event {
+                Event::Mine => self.mine(),
+                Event::Deliver { node, block } => self.deliver(node, block),
+            }
+        }
+        self.report()
+    }
+
+    fn schedule(&mut self, time: u64, event: Event) {
+        self.events.push(Reverse((time, self.scheduled, event)));
+        self.scheduled += 1;
+    }
+
+    /// Schedules the next block to be found, the intervals between blocks being exponentially
+    /// distributed as with Proof of Work
+    fn schedule_mining(&mut self) {
+        let interval = self.rng.exponential(self.config.block_interval_ms as f64);
+        self.schedule(self.now + interval.round() as u64, Event::Mine);
+    }
+
+    /// Mines a block on top of the blockchain of a node picked by its mining power
+    fn mine(&mut self) {
+        let draw = self.rng.next_f64();
+        let miner = self
+            .power
+            .partition_point(|power| *power <= draw)
+            .min(self.config.nodes - 1);
+        self.mine_on(miner);
+
+        self.mined += 1;
+        if self.mined < self.config.blocks {
+            self.schedule_mining();
+        }
+    }
+
+    /// Adds a new block to the node's blockchain and relays it to the node's peers
+    fn mine_on(&mut self, miner: usize) {
+        let parent = self.tips[miner];
+        let previous = &self.blocks[parent].block;
+        let block = Arc::new(Block::with_nonce(
+            previous.id + 1,
+            &previous.hash,
+            (self.now / 1_000) as i64,
+            0,
+            format!("block {}", self.blocks.len()),
+        ));
+        self.chains[miner]
+            .add_block_with(block.clone(), &|block| block.has_valid_hash())
+            .expect("block extends the miner's blockchain");
+
+        let mut seen = vec![false; self.config.nodes];
+        seen[miner] = true;
+        self.blocks.push(SimBlock {
+            block,
+            parent: Some(parent),
+            miner,
+            mined_at: self.now,
+            seen,
+            adopted_by: 0,
+            converged_at: None,
+        });
+        let block = self.blocks.len() - 1;
+        self.adopt(miner, block);
+        self.relay(miner, block);
+    }
+
+    /// Delivers the block to the node, which relays it if it is new. A block extending the node's
+    /// blockchain is added to it; otherwise, the node switches to the blockchain ending with the
+    /// block in case it is better than its own, as a node syncing with the sender would.
+    fn deliver(&mut self, node: usize, block: usize) {
+        if self.blocks[block].seen[node] {
+            return;
+        }
+        self.blocks[block].seen[node] = true;
+        if self.blocks[block].parent == Some(self.tips[node]) {
+            self.chains[node]
+                .add_block_with(self.blocks[block].block.clone(), &|block| {
+                    block.has_valid_hash()
+                })
+                .expect("block extends the node's blockchain");
+            self.adopt(node, block);
+        } else {
+            let remote = Tetherion::from_blocks(self.branch(block), DIFFICULTY)
+                .expect("mined blocks are valid");
+            if !self.chains[node].is_better_than(&remote) {
+                self.chains[node] = remote;
+                self.adopt(node, block);
+            }
+        }
+        self.relay(node, block);
+    }
+
+    /// Gets the blocks of the blockchain ending with the block, from the genesis block on
+    fn branch(&self, tip: usize) -> Vec<Arc<Block<String>>> {
+        let mut blocks = Vec::new();
+        let mut current = Some(tip);
+        while let Some(block) = current {
+            blocks.push(self.blocks[block].block.clone());
+            current = self.blocks[block].parent;
+        }
+        blocks.reverse();
+        blocks
+    }
+
+    /// Sends the block to the peers of the node that did not receive it yet
+    fn relay(&mut self, node: usize, block: usize) {
+        let (min, max) = self.config.latency_ms;
+        for i in 0..self.peers[node].len() {
+            let peer = self.peers[node][i];
+            if self.blocks[block].seen[peer] {
+                continue;
+            }
+            let latency = self.rng.between(min, max);
+            let sent_at = match self.config.partition {
+                Some(partition) if self.is_split(&partition, node, peer) => partition.end_ms(),
+                _ => self.now,
+            };
+            self.schedule(sent_at + latency, Event::Deliver { node: peer, block });
+        }
+    }
+
+    /// Checks whether the partition currently separates the nodes
+    fn is_split(&self, partition: &Partition, node: usize, peer: usize) -> bool {
+        let first_side = (partition.share * self.config.nodes as f64) as usize;
+        (partition.start_ms..partition.end_ms()).contains(&self.now)
+            && (node < first_side) != (peer < first_side)
+    }
+
+    /// Records the node's switch to the blockchain ending with the block, counting a reorg if the
+    /// new blockchain does not build on the previous one
+    fn adopt(&mut self, node: usize, tip: usize) {
+        let previous = self.tips[node];
+        let (mut dropped, mut added) = (previous, tip);
+        while dropped != added {
+            if self.blocks[dropped].block.id >= self.blocks[added].block.id {
+                self.blocks[dropped].adopted_by -= 1;
+                dropped = self.blocks[dropped].parent.expect("genesis is common");
+            } else {
+                let block = &mut self.blocks[added];
+                block.adopted_by += 1;
+                if block.adopted_by == self.config.nodes && block.converged_at.is_none() {
+                    block.converged_at = Some(self.now);
+                }
+                added = block.parent.expect("genesis is common");
+            }
+        }
+
+        let depth = self.blocks[previous].block.id - self.blocks[dropped].block.id;
+        if depth > 0 {
+            self.reorgs += 1;
+            self.max_reorg_depth = self.max_reorg_depth.max(depth);
+        }
+        self.tips[node] = tip;
+    }
+
+    fn report(&self) -> SimReport {
+        let mut counts = vec![0; self.blocks.len()];
+        for tip in &self.tips {
+            counts[*tip] += 1;
+        }
+        let final_tip = (0..self.blocks.len())
+            .max_by_key(|block| (counts[*block], Reverse(*block)))
+            .expect("there is at least one block");
+
+        let mut convergence = Vec::new();
+        let mut mined_by_first = 0;
+        let mut block = &self.blocks[final_tip];
+        while let Some(parent) = block.parent {
+            if let Some(converged_at) = block.converged_at {
+                convergence.push(converged_at - block.mined_at);
+            }
+            if block.miner == 0 {
+                mined_by_first += 1;
+            }
+            block = &self.blocks[parent];
+        }
+        convergence.sort_unstable();
+        let count = convergence.len() as u64;
+        let p90 = (count * 9).div_ceil(10).max(1) - 1;
+
+        let height = self.blocks[final_tip].block.id;
+        SimReport {
+            mined: self.mined,
+            height,
+            fork_rate: (self.mined - height) as f64 / self.mined as f64,
+            reorgs: self.reorgs,
+            max_reorg_depth: self.max_reorg_depth,
+            convergence_mean_ms: convergence.iter().sum::<u64>().checked_div(count),
+            convergence_p90_ms: convergence.get(p90 as usize).copied(),
+            convergence_max_ms: convergence.last().copied(),
+            agreement: counts[final_tip] as f64 / self.config.nodes as f64,
+            first_miner_share: match height {
+                0 => 0.0,
+                _ => mined_by_first as f64 / height as f64,
+            },
+            duration_ms: self.now,
+        }
+    }
+}
+
+/// A SplitMix64 generator of pseudorandom numbers, reproducible from its seed
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Gets a number within [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Gets a number within [0, n)
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Gets a number within [min, max]
+    fn between(&mut self, min: u64, max: u64) -> u64 {
+        min + self.below(max - min + 1)
+    }
+
+    /// Gets an exponentially distributed number with the given mean
+    fn exponential(&mut self, mean: f64) -> f64 {
+        -mean * (1.0 - self.next_f64()).ln()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulate_network() {
+        let config = SimConfig {
+            nodes: 50,
+            blocks: 100,
+            ..Default::default()
+        };
+        let report = run(config).unwrap();
+        assert_eq!(report, run(config).unwrap());
+        assert_eq!(report.mined, 100);
+        assert_eq!(report.agreement, 1.0);
+        assert!(report.fork_rate < 0.1);
+        assert!(report.convergence_max_ms.unwrap() >= report.convergence_mean_ms.unwrap());
+
+        assert!(run(SimConfig {
+            latency_ms: (200, 20),
+            ..config
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn choose_tips() {
+        let mut sim = Simulation::new(SimConfig {
+            nodes: 2,
+            ..Default::default()
+        });
+
+        // A fork as good as the node's own blockchain is ignored, the older one winning a tie
+        sim.mine_on(0);
+        sim.mine_on(1);
+        sim.deliver(0, 2);
+        assert_eq!(sim.tips, vec![1, 2]);
+        assert_eq!(sim.reorgs, 0);
+
+        // A fork that took more work replaces the node's blockchain
+        sim.now = 10_000;
+        sim.mine_on(1);
+        sim.deliver(0, 3);
+        assert_eq!(sim.tips, vec![3, 3]);
+        assert_eq!((sim.reorgs, sim.max_reorg_depth), (1, 1));
+        assert_eq!(sim.chains[0].height(), 2);
+        assert_eq!(sim.chains[0].blocks(), sim.chains[1].blocks());
+        assert_eq!(sim.chains[0].blocks()[2].timestamp(), 10);
+
+        // Blocks delivered again change nothing
+        sim.deliver(0, 1);
+        assert_eq!(sim.tips, vec![3, 3]);
+        assert_eq!(sim.blocks[1].adopted_by, 0);
+        assert_eq!(sim.blocks[3].converged_at, Some(10_000));
+    }
+
+    #[test]
+    fn partitioned_network() {
+        let config = SimConfig {
+            nodes: 50,
+            blocks: 100,
+            ..Default::default()
+        };
+        let partition = Partition {
+            start_ms: 100_000,
+            duration_ms: 300_000,
+            share: 0.4,
+        };
+        let mut sim = Simulation::new(SimConfig {
+            partition: Some(partition),
+            ..config
+        });
+        sim.now = 200_000;
+        assert!(sim.is_split(&partition, 0, 49));
+        assert!(!sim.is_split(&partition, 0, 19));
+        assert!(!sim.is_split(&partition, 20, 49));
+        sim.now = partition.end_ms();
+        assert!(!sim.is_split(&partition, 0, 49));
+
+        // Both sides mine their own blockchain, one of which is dropped once the split heals
+        let report = run(config).unwrap();
+        let split = run(SimConfig {
+            partition: Some(partition),
+            ..config
+        })
+        .unwrap();
+        assert_eq!(
+            split,
+            run(SimConfig {
+                partition: Some(partition),
+                ..config
+            })
+            .unwrap()
+        );
+        assert_eq!(split.agreement, 1.0);
+        assert!(split.fork_rate > report.fork_rate);
+        assert!(split.max_reorg_depth > 1);
+
+        assert!(run(SimConfig {
+            partition: Some(Partition {
+                share: 1.0,
+                ..partition
+            }),
+            ..config
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn skewed_power() {
+        let config = SimConfig {
+            nodes: 50,
+            blocks: 100,
+            ..Default::default()
+        };
+        let even = Simulation::new(config);
+        assert!((even.power[0] - 0.02).abs() < 1e-9);
+        assert!((even.power[49] - 1.0).abs() < 1e-9);
+
+        // With a skew of 2, the first node gets most of the mining power
+        let skewed = Simulation::new(SimConfig {
+            power_skew: 2.0,
+            ..config
+        });
+        assert!(skewed.power[0] > 0.6);
+        assert!((skewed.power[49] - 1.0).abs() < 1e-9);
+
+        // The blocks are mined by the nodes according to their power, and still agreed on
+        let report = run(SimConfig {
+            power_skew: 2.0,
+            ..config
+        })
+        .unwrap();
+        assert_eq!(report.agreement, 1.0);
+        assert!(report.first_miner_share > 0.5);
+        assert!(run(config).unwrap().first_miner_share < 0.1);
+
+        assert!(run(SimConfig {
+            power_skew: -1.0,
+            ..config
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,291 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    libp2p::{
+        core::{
+            multiaddr::Protocol,
+            transport::{ListenerEvent, TransportError},
+            Transport,
+        },
+        futures::{
+            future::{self, BoxFuture},
+            stream, AsyncRead, AsyncWrite, FutureExt,
+        },
+        Multiaddr,
+    },
+    std::{
+        io,
+        net::{IpAddr, SocketAddr},
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt, ReadBuf},
+        net::TcpStream,
+    },
+};
+
+/// The version of the SOCKS protocol spoken to the proxy
+const VERSION: u8 = 5;
+
+/// The authentication method asking for no authentication
+const NO_AUTHENTICATION: u8 = 0;
+
+/// The command asking the proxy to connect to the target
+const CONNECT: u8 = 1;
+
+const IPV4: u8 = 1;
+const DOMAIN: u8 = 3;
+const IPV6: u8 = 4;
+
+/// The host a connection is established to through the proxy, resolved by the proxy in case of
+/// a domain name, e.g. an onion address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+/// Gets the target of the TCP address, over an IP address or a domain name, if it is one
+pub fn target(addr: &Multiaddr) -> Option<Target> {
+    let mut protocols = addr.iter();
+    let host = protocols.next()?;
+    let port = match protocols.next()? {
+        Protocol::Tcp(port) => port,
+        _ => return None,
+    };
+    if protocols.next().is_some() {
+        return None;
+    }
+    match host {
+        Protocol::Ip4(ip) => Some(Target::Ip(SocketAddr::new(IpAddr::V4(ip), port))),
+        Protocol::Ip6(ip) => Some(Target::Ip(SocketAddr::new(IpAddr::V6(ip), port))),
+        Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => {
+            Some(Target::Domain(name.into_owned(), port))
+        }
+        _ => None,
+    }
+}
+
+/// Asks the SOCKS5 proxy at the other end of the stream to connect to the target, after which
+/// the stream carries the connection to the target
+pub async fn handshake<S>(stream: &mut S, target: &Target) -> io::Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    stream.write_all(&[VERSION, 1, NO_AUTHENTICATION]).await?;
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice != [VERSION, NO_AUTHENTICATION] {
+        return Err(io::Error::other(
+            "the proxy requires an authentication method not supported",
+        ));
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    let port = match target {
+        Target::Ip(SocketAddr::V4(addr)) => {
+            request.push(IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Ip(SocketAddr::V6(addr)) => {
+            request.push(IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Domain(name, port) => {
+            let len = u8::try_from(name.len())
+                .map_err(|_| io::Error::other(format!("domain name {} is too long", name)))?;
+            request.extend_from_slice(&[DOMAIN, len]);
+            request.extend_from_slice(name.as_bytes());
+            *port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(io::Error::other(format!(
+            "unexpected SOCKS version {}",
+            reply[0]
+        )));
+    }
+    if reply[1] != 0 {
+        let reason = match reply[1] {
+            1 => "general failure",
+            2 => "connection not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "unknown error",
+        };
+        return Err(io::Error::other(format!(
+            "the proxy cannot connect: {}",
+            reason
+        )));
+    }
+    // The address the proxy connected from is of no use
+    let bound = match reply[3] {
+        IPV4 => 4,
+        IPV6 => 16,
+        DOMAIN => usize::from(stream.read_u8().await?),
+        atyp => {
+            return Err(io::Error::other(format!(
+                "unexpected address type {}",
+                atyp
+            )))
+        }
+    };
+    let mut bound_addr = vec![0; bound + 2];
+    stream.read_exact(&mut bound_addr).await?;
+    Ok(())
+}
+
+/// A connection established through the proxy
+#[derive(Debug)]
+pub struct ProxiedStream(TcpStream);
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut read = ReadBuf::new(buf);
+        match tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, &mut read) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(read.filled().len())),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.0), cx)
+    }
+}
+
+/// Dials the TCP addresses through a SOCKS5 proxy, e.g. Tor, so that the peers never learn the
+/// node's address; listening is left to the plain TCP transport
+#[derive(Debug, Clone, Copy)]
+pub struct Socks5Transport {
+    proxy: SocketAddr,
+}
+
+impl Socks5Transport {
+    pub fn new(proxy: SocketAddr) -> Self {
+        Self { proxy }
+    }
+}
+
+impl Transport for Socks5Transport {
+    type Output = ProxiedStream;
+    type Error = io::Error;
+    type Listener =
+        stream::Pending<Result<ListenerEvent<Self::ListenerUpgrade, Self::Error>, Self::Error>>;
+    type ListenerUpgrade = future::Pending<Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let target = match target(&addr) {
+            Some(target) => target,
+            None => return Err(TransportError::MultiaddrNotSupported(addr)),
+        };
+        let proxy = self.proxy;
+        Ok(async move {
+            let mut stream = TcpStream::connect(proxy).await?;
+            handshake(&mut stream, &target).await?;
+            Ok(ProxiedStream(stream))
+        }
+        .boxed())
+    }
+
+    fn address_translation(&self, _listen: &Multiaddr, _observed: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, tokio::io::duplex};
+
+    #[tokio::test]
+    async fn connect_through_proxy() {
+        let addr = |addr: &str| addr.parse::<Multiaddr>().unwrap();
+        assert_eq!(
+            target(&addr("/ip4/10.0.0.1/tcp/4001")),
+            Some(Target::Ip("10.0.0.1:4001".parse().unwrap()))
+        );
+        let onion = "abcdefghijklmnop.onion";
+        let target = target(&addr(&format!("/dns/{}/tcp/4001", onion))).unwrap();
+        assert_eq!(target, Target::Domain(onion.to_owned(), 4001));
+        assert_eq!(super::target(&addr("/ip4/10.0.0.1/udp/4001")), None);
+
+        let (mut client, mut proxy) = duplex(1024);
+        let proxied = tokio::spawn(async move {
+            let mut greeting = [0; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [VERSION, 1, NO_AUTHENTICATION]);
+            proxy
+                .write_all(&[VERSION, NO_AUTHENTICATION])
+                .await
+                .unwrap();
+
+            let mut request = vec![0; 5 + onion.len() + 2];
+            proxy.read_exact(&mut request).await.unwrap();
+            assert_eq!(
+                request[..5],
+                [VERSION, CONNECT, 0, DOMAIN, onion.len() as u8]
+            );
+            assert_eq!(&request[5..5 + onion.len()], onion.as_bytes());
+            assert_eq!(request[5 + onion.len()..], 4001u16.to_be_bytes());
+            proxy
+                .write_all(&[VERSION, 0, 0, IPV4, 127, 0, 0, 1, 0, 80])
+                .await
+                .unwrap();
+            proxy.write_all(b"hello").await.unwrap();
+        });
+        handshake(&mut client, &target).await.unwrap();
+        let mut hello = [0; 5];
+        client.read_exact(&mut hello).await.unwrap();
+        assert_eq!(&hello, b"hello");
+        proxied.await.unwrap();
+
+        let (mut client, mut proxy) = duplex(1024);
+        tokio::spawn(async move {
+            let mut greeting = [0; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            proxy
+                .write_all(&[VERSION, NO_AUTHENTICATION])
+                .await
+                .unwrap();
+            let mut request = [0; 10];
+            proxy.read_exact(&mut request).await.unwrap();
+            proxy.write_all(&[VERSION, 5, 0, IPV4]).await.unwrap();
+        });
+        let target = Target::Ip("10.0.0.1:4001".parse().unwrap());
+        assert!(handshake(&mut client, &target).await.is_err());
+    }
+}
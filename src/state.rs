@@ -0,0 +1,1055 @@
+/// Copyright (c) 2022 Tetherion
+#[cfg(feature = "contracts")]
+use crate::contracts::{self, Contract, ContractOp};
+use {
+    crate::{
+        consensus::Stakes,
+        evidence::Evidence,
+        merkle,
+        payload::{Payload, PayloadKind, TokenOp},
+        registry::PayloadRegistry,
+        script::Script,
+        tetherion::BlockData,
+        weight,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+        fmt,
+        sync::Arc,
+    },
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    InvalidName {
+        name: String,
+    },
+    InvalidSignature {
+        signer: String,
+    },
+    NameTaken {
+        name: String,
+    },
+    InvalidRevision {
+        name: String,
+        revision: u64,
+    },
+    InvalidNonce {
+        account: String,
+        nonce: u64,
+    },
+    TokenExists {
+        token: String,
+    },
+    UnknownToken {
+        token: String,
+    },
+    NotMintable {
+        token: String,
+    },
+    InsufficientBalance {
+        token: String,
+        account: String,
+    },
+    SupplyOverflow {
+        token: String,
+    },
+    UnknownLock {
+        lock_id: String,
+    },
+    UnsatisfiedScript {
+        lock_id: String,
+    },
+    UnsupportedPayload {
+        kind: PayloadKind,
+    },
+    InvalidBatch,
+    InvalidEvidence {
+        offender: String,
+    },
+    EvidenceKnown {
+        offender: String,
+        height: u64,
+    },
+    #[cfg(feature = "contracts")]
+    InvalidContractNonce {
+        account: String,
+        nonce: u64,
+    },
+    #[cfg(feature = "contracts")]
+    UnknownContract {
+        contract: String,
+    },
+    #[cfg(feature = "contracts")]
+    ContractFailed {
+        contract: String,
+        reason: String,
+    },
+    Rejected {
+        reason: String,
+    },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::InvalidName { name } => write!(f, "Name '{}' is not valid", name),
+            StateError::InvalidSignature { signer } => {
+                write!(f, "Payload has an invalid signature of {}", signer)
+            }
+            StateError::NameTaken { name } => {
+                write!(f, "Name '{}' is already registered by another owner", name)
+            }
+            StateError::InvalidRevision { name, revision } => write!(
+                f,
+                "Registration of name '{}' has the wrong revision {}",
+                name, revision
+            ),
+            StateError::InvalidNonce { account, nonce } => write!(
+                f,
+                "Token operation of account {} has the wrong nonce {}",
+                account, nonce
+            ),
+            StateError::TokenExists { token } => write!(f, "Token '{}' already exists", token),
+            StateError::UnknownToken { token } => write!(f, "Token '{}' does not exist", token),
+            StateError::NotMintable { token } => {
+                write!(f, "Token '{}' cannot be minted by the account", token)
+            }
+            StateError::InsufficientBalance { token, account } => write!(
+                f,
+                "Account {} does not have enough of token '{}'",
+                account, token
+            ),
+            StateError::SupplyOverflow { token } => {
+                write!(f, "Supply of token '{}' would overflow", token)
+            }
+            StateError::UnknownLock { lock_id } => {
+                write!(f, "Lock {} does not exist or is already spent", lock_id)
+            }
+            StateError::UnsatisfiedScript { lock_id } => {
+                write!(f, "Witness does not satisfy the script of lock {}", lock_id)
+            }
+            StateError::UnsupportedPayload { kind } => {
+                write!(f, "Payloads of kind {:?} are not supported", kind)
+            }
+            StateError::InvalidBatch => write!(f, "Batch is either empty or nested"),
+            StateError::InvalidEvidence { offender } => {
+                write!(
+                    f,
+                    "Evidence against {} does not prove equivocation",
+                    offender
+                )
+            }
+            StateError::EvidenceKnown { offender, height } => write!(
+                f,
+                "Evidence against {} at height {} is already stored",
+                offender, height
+            ),
+            #[cfg(feature = "contracts")]
+            StateError::InvalidContractNonce { account, nonce } => write!(
+                f,
+                "Contract operation of account {} has the wrong nonce {}",
+                account, nonce
+            ),
+            #[cfg(feature = "contracts")]
+            StateError::UnknownContract { contract } => {
+                write!(f, "Contract {} does not exist", contract)
+            }
+            #[cfg(feature = "contracts")]
+            StateError::ContractFailed { contract, reason } => {
+                write!(f, "Contract {} failed: {}", contract, reason)
+            }
+            StateError::Rejected { reason } => {
+                write!(f, "Payload breaks an application rule: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Which state a balance is read from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceView {
+    /// The state of the latest block followed by the operations waiting in the mempool
+    Pending,
+
+    /// The state of the latest block, which a reorg may revert
+    #[default]
+    Latest,
+
+    /// The state of the block the configured number of blocks below the latest one
+    Confirmed,
+}
+
+impl fmt::Display for BalanceView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalanceView::Pending => write!(f, "pending"),
+            BalanceView::Latest => write!(f, "latest"),
+            BalanceView::Confirmed => write!(f, "confirmed"),
+        }
+    }
+}
+
+impl std::str::FromStr for BalanceView {
+    type Err = String;
+
+    fn from_str(view: &str) -> Result<Self, Self::Err> {
+        match view {
+            "pending" => Ok(BalanceView::Pending),
+            "latest" => Ok(BalanceView::Latest),
+            "confirmed" => Ok(BalanceView::Confirmed),
+            view => Err(format!(
+                "unknown balance view '{}', expected pending, latest or confirmed",
+                view
+            )),
+        }
+    }
+}
+
+/// The latest registration of a name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NameRecord {
+    /// The value the name resolves to
+    pub value: String,
+
+    /// The owner's Ed25519 public key, in HEX format
+    pub owner: String,
+
+    /// The number of times the name has been updated since its registration
+    pub revision: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+    /// The Ed25519 public key, in HEX format, of the account that created the token
+    pub owner: String,
+
+    /// The total amount of the token in circulation
+    pub supply: u64,
+
+    /// Whether the owner can mint new tokens
+    pub mintable: bool,
+}
+
+/// The state built by applying the payloads of all the blocks in the blockchain
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    /// The payload kinds accepted and the rules they are validated with, shared with the copies
+    /// of the state
+    registry: Arc<PayloadRegistry>,
+
+    /// The registered names, mapped to their latest registrations
+    names: HashMap<String, NameRecord>,
+
+    /// The created tokens
+    tokens: HashMap<String, TokenInfo>,
+
+    /// The balances of the accounts, per token and account
+    balances: HashMap<(String, String), u64>,
+
+    /// The number of token operations performed by each of the accounts
+    nonces: HashMap<String, u64>,
+
+    /// The locked tokens, mapped by their lock IDs
+    locks: HashMap<String, Lock>,
+
+    /// The heights of the blocks storing the evidence of each block producer caught
+    /// equivocating, all of them kept so that the blocks produced while an earlier offense barred
+    /// the producer stay valid
+    offenses: HashMap<String, BTreeSet<u64>>,
+
+    /// The offenders and the heights they equivocated at whose evidence is stored already
+    punished: HashSet<(String, u64)>,
+
+    /// The deployed contracts, mapped by their IDs
+    #[cfg(feature = "contracts")]
+    contracts: HashMap<String, Contract>,
+
+    /// The number of contract operations performed by each of the accounts
+    #[cfg(feature = "contracts")]
+    contract_nonces: HashMap<String, u64>,
+}
+
+/// Tokens locked until their script is satisfied
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Lock {
+    pub token: String,
+    pub amount: u64,
+    pub script: Script,
+}
+
+/// The state as of a block, sorted so that it reads well once jsonified. The state can be restored
+/// from it, e.g. to start from a checkpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StateView {
+    /// The height of the block the state is as of
+    pub height: u64,
+
+    /// The registered names, mapped to their latest registrations
+    pub names: BTreeMap<String, NameRecord>,
+
+    /// The created tokens
+    pub tokens: BTreeMap<String, TokenInfo>,
+
+    /// The balances of the accounts, per token and account
+    pub balances: BTreeMap<String, BTreeMap<String, u64>>,
+
+    /// The locked tokens, mapped by their lock IDs
+    pub locks: BTreeMap<String, Lock>,
+
+    /// The number of token operations performed by each of the accounts
+    #[serde(default)]
+    pub nonces: BTreeMap<String, u64>,
+
+    /// The heights of the blocks storing the evidence of each block producer caught equivocating
+    #[serde(default)]
+    pub offenses: BTreeMap<String, BTreeSet<u64>>,
+
+    /// The heights each block producer equivocated at whose evidence is stored already
+    #[serde(default)]
+    pub punished: BTreeMap<String, BTreeSet<u64>>,
+
+    /// The deployed contracts, mapped by their IDs
+    #[cfg(feature = "contracts")]
+    #[serde(default)]
+    pub contracts: BTreeMap<String, Contract>,
+
+    /// The number of contract operations performed by each of the accounts
+    #[cfg(feature = "contracts")]
+    #[serde(default)]
+    pub contract_nonces: BTreeMap<String, u64>,
+}
+
+/// States are compared by their content, whatever the registry they validate the payloads with
+impl PartialEq for State {
+    fn eq(&self, other: &Self) -> bool {
+        #[cfg(feature = "contracts")]
+        if self.contracts != other.contracts || self.contract_nonces != other.contract_nonces {
+            return false;
+        }
+        self.names == other.names
+            && self.tokens == other.tokens
+            && self.balances == other.balances
+            && self.nonces == other.nonces
+            && self.locks == other.locks
+            && self.offenses == other.offenses
+            && self.punished == other.punished
+    }
+}
+
+impl Eq for State {}
+
+impl State {
+    /// Creates the empty state whose payloads get validated with the given registry, the one of
+    /// the built-in payload kinds being used by default
+    pub fn new(registry: Arc<PayloadRegistry>) -> Self {
+        Self {
+            registry,
+            ..Self::default()
+        }
+    }
+
+    /// Gets the registry the payloads applied to the state are validated with
+    pub fn registry(&self) -> &Arc<PayloadRegistry> {
+        &self.registry
+    }
+
+    /// Gets the ID of the chain the signed payloads applied to the state must be signed for
+    pub fn chain_id(&self) -> &str {
+        self.registry.chain_id()
+    }
+
+    /// Gets a sorted copy of the state, which is as of the block at the given height
+    pub fn view(&self, height: u64) -> StateView {
+        let mut balances = BTreeMap::<String, BTreeMap<String, u64>>::new();
+        for ((token, account), amount) in &self.balances {
+            balances
+                .entry(token.clone())
+                .or_default()
+                .insert(account.clone(), *amount);
+        }
+        let mut punished = BTreeMap::<String, BTreeSet<u64>>::new();
+        for (offender, height) in &self.punished {
+            punished
+                .entry(offender.clone())
+                .or_default()
+                .insert(*height);
+        }
+        StateView {
+            height,
+            names: self.names.clone().into_iter().collect(),
+            tokens: self.tokens.clone().into_iter().collect(),
+            balances,
+            locks: self.locks.clone().into_iter().collect(),
+            nonces: self.nonces.clone().into_iter().collect(),
+            offenses: self.offenses.clone().into_iter().collect(),
+            punished,
+            #[cfg(feature = "contracts")]
+            contracts: self.contracts.clone().into_iter().collect(),
+            #[cfg(feature = "contracts")]
+            contract_nonces: self.contract_nonces.clone().into_iter().collect(),
+        }
+    }
+
+    /// Restores the state out of its copy, validating the payloads applied to it with the given
+    /// registry
+    pub fn from_view(registry: Arc<PayloadRegistry>, view: StateView) -> Self {
+        Self {
+            registry,
+            names: view.names.into_iter().collect(),
+            tokens: view.tokens.into_iter().collect(),
+            balances: view
+                .balances
+                .into_iter()
+                .flat_map(|(token, balances)| {
+                    balances
+                        .into_iter()
+                        .map(move |(account, amount)| ((token.clone(), account), amount))
+                })
+                .collect(),
+            nonces: view.nonces.into_iter().collect(),
+            locks: view.locks.into_iter().collect(),
+            offenses: view.offenses.into_iter().collect(),
+            punished: view
+                .punished
+                .into_iter()
+                .flat_map(|(offender, heights)| {
+                    heights
+                        .into_iter()
+                        .map(move |height| (offender.clone(), height))
+                })
+                .collect(),
+            #[cfg(feature = "contracts")]
+            contracts: view.contracts.into_iter().collect(),
+            #[cfg(feature = "contracts")]
+            contract_nonces: view.contract_nonces.into_iter().collect(),
+        }
+    }
+
+    /// Resolves the name to its latest registration, if any
+    pub fn resolve(&self, name: &str) -> Option<&NameRecord> {
+        self.names.get(name)
+    }
+
+    /// Gets the revision the next registration of the name should have
+    pub fn next_revision(&self, name: &str) -> u64 {
+        self.resolve(name).map_or(0, |record| record.revision + 1)
+    }
+
+    /// Gets the token with the given name, if created
+    pub fn token(&self, token: &str) -> Option<&TokenInfo> {
+        self.tokens.get(token)
+    }
+
+    /// Gets the amount of the token owned by the account
+    pub fn balance(&self, account: &str, token: &str) -> u64 {
+        self.balances
+            .get(&(token.to_owned(), account.to_owned()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Gets the tokens locked under the given lock ID, if not spent yet
+    pub fn lock(&self, lock_id: &str) -> Option<&Lock> {
+        self.locks.get(lock_id)
+    }
+
+    /// Gets the nonce the next token operation of the account should have
+    pub fn next_nonce(&self, account: &str) -> u64 {
+        self.nonces.get(account).copied().unwrap_or_default()
+    }
+
+    /// Gets the contract with the given ID, if deployed
+    #[cfg(feature = "contracts")]
+    pub fn contract(&self, contract: &str) -> Option<&Contract> {
+        self.contracts.get(contract)
+    }
+
+    /// Gets the nonce the next contract operation of the account should have
+    #[cfg(feature = "contracts")]
+    pub fn next_contract_nonce(&self, account: &str) -> u64 {
+        self.contract_nonces
+            .get(account)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Performs the contract operation on behalf of the account, executing the contract with the
+    /// given fuel and storing the entries it leaves in its storage. A failed execution leaves the
+    /// state untouched.
+    #[cfg(feature = "contracts")]
+    pub fn apply_contract_op(
+        &mut self,
+        op: &ContractOp,
+        nonce: u64,
+        fuel: u64,
+        from: &str,
+    ) -> Result<(), StateError> {
+        if nonce != self.next_contract_nonce(from) {
+            return Err(StateError::InvalidContractNonce {
+                account: from.to_owned(),
+                nonce,
+            });
+        }
+
+        let (id, contract) = match op {
+            ContractOp::Deploy { code, input } => {
+                let id = format!("{}:{}", from, nonce);
+                let storage = contracts::execute(
+                    code,
+                    &BTreeMap::new(),
+                    contracts::INIT_FUNCTION,
+                    input,
+                    fuel,
+                )
+                .map_err(|reason| StateError::ContractFailed {
+                    contract: id.clone(),
+                    reason,
+                })?;
+                let contract = Contract {
+                    owner: from.to_owned(),
+                    code: code.clone(),
+                    storage,
+                };
+                (id, contract)
+            }
+            ContractOp::Call {
+                contract: id,
+                function,
+                input,
+            } => {
+                let mut contract =
+                    self.contract(id)
+                        .cloned()
+                        .ok_or_else(|| StateError::UnknownContract {
+                            contract: id.clone(),
+                        })?;
+                contract.storage =
+                    contracts::execute(&contract.code, &contract.storage, function, input, fuel)
+                        .map_err(|reason| StateError::ContractFailed {
+                            contract: id.clone(),
+                            reason,
+                        })?;
+                (id.clone(), contract)
+            }
+        };
+        self.contracts.insert(id, contract);
+        self.contract_nonces.insert(from.to_owned(), nonce + 1);
+        Ok(())
+    }
+
+    /// Records the offense proven by the evidence stored in the block at the given height, once
+    /// per offender and height the offender equivocated at
+    pub fn punish(&mut self, evidence: &Evidence, height: u64) -> Result<(), StateError> {
+        let key = (evidence.offender.clone(), evidence.height());
+        if self.punished.contains(&key) {
+            return Err(StateError::EvidenceKnown {
+                offender: key.0,
+                height: key.1,
+            });
+        }
+        self.offenses
+            .entry(key.0.clone())
+            .or_default()
+            .insert(height);
+        self.punished.insert(key);
+        Ok(())
+    }
+
+    /// Registers the name on a first-come-first-served basis, only its owner being allowed to
+    /// update it afterwards
+    pub fn register(
+        &mut self,
+        name: &str,
+        value: &str,
+        revision: u64,
+        owner: &str,
+    ) -> Result<(), StateError> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(StateError::InvalidName {
+                name: name.to_owned(),
+            });
+        }
+
+        if let Some(record) = self.resolve(name) {
+            if record.owner != owner {
+                return Err(StateError::NameTaken {
+                    name: name.to_owned(),
+                });
+            }
+        }
+
+        if revision != self.next_revision(name) {
+            return Err(StateError::InvalidRevision {
+                name: name.to_owned(),
+                revision,
+            });
+        }
+
+        self.names.insert(
+            name.to_owned(),
+            NameRecord {
+                value: value.to_owned(),
+                owner: owner.to_owned(),
+                revision,
+            },
+        );
+        Ok(())
+    }
+
+    /// Performs the token operation on behalf of the account. Every check happens before the
+    /// state gets updated, so an invalid operation leaves the state untouched.
+    pub fn apply_token_op(
+        &mut self,
+        op: &TokenOp,
+        nonce: u64,
+        fee: u64,
+        from: &str,
+        height: u64,
+    ) -> Result<(), StateError> {
+        if fee == 0 {
+            return self.apply_op(op, nonce, from, height);
+        }
+
+        // The fee is burned after the operation, which may be what provides the tokens to pay it
+        let token = match op {
+            TokenOp::Unlock { lock_id, .. } => self.lock(lock_id).map(|lock| lock.token.clone()),
+            TokenOp::Create { token, .. }
+            | TokenOp::Mint { token, .. }
+            | TokenOp::Transfer { token, .. }
+            | TokenOp::Lock { token, .. } => Some(token.clone()),
+        };
+        let mut next = self.clone();
+        next.apply_op(op, nonce, from, height)?;
+        if let Some(token) = token {
+            next.burn(from, &token, fee)?;
+        }
+        *self = next;
+        Ok(())
+    }
+
+    /// Applies the token operation, without any fee, to the state
+    fn apply_op(
+        &mut self,
+        op: &TokenOp,
+        nonce: u64,
+        from: &str,
+        height: u64,
+    ) -> Result<(), StateError> {
+        if nonce != self.next_nonce(from) {
+            return Err(StateError::InvalidNonce {
+                account: from.to_owned(),
+                nonce,
+            });
+        }
+
+        match op {
+            TokenOp::Create {
+                token,
+                supply,
+                mintable,
+            } => {
+                if self.tokens.contains_key(token) {
+                    return Err(StateError::TokenExists {
+                        token: token.clone(),
+                    });
+                }
+
+                let info = TokenInfo {
+                    owner: from.to_owned(),
+                    supply: *supply,
+                    mintable: *mintable,
+                };
+                self.tokens.insert(token.clone(), info);
+                self.set_balance(from, token, *supply);
+            }
+            TokenOp::Mint { token, amount } => {
+                let info = self.token(token).ok_or_else(|| StateError::UnknownToken {
+                    token: token.clone(),
+                })?;
+                if !info.mintable || info.owner != from {
+                    return Err(StateError::NotMintable {
+                        token: token.clone(),
+                    });
+                }
+
+                // No balance exceeds the supply, so neither overflows if the supply does not
+                let supply =
+                    info.supply
+                        .checked_add(*amount)
+                        .ok_or_else(|| StateError::SupplyOverflow {
+                            token: token.clone(),
+                        })?;
+                let balance = self.balance(from, token) + amount;
+                if let Some(info) = self.tokens.get_mut(token) {
+                    info.supply = supply;
+                }
+                self.set_balance(from, token, balance);
+            }
+            TokenOp::Transfer { token, to, amount } => {
+                if self.token(token).is_none() {
+                    return Err(StateError::UnknownToken {
+                        token: token.clone(),
+                    });
+                }
+
+                let balance = self.balance(from, token);
+                if balance < *amount {
+                    return Err(StateError::InsufficientBalance {
+                        token: token.clone(),
+                        account: from.to_owned(),
+                    });
+                }
+                self.set_balance(from, token, balance - amount);
+                self.set_balance(to, token, self.balance(to, token) + amount);
+            }
+            TokenOp::Lock {
+                token,
+                amount,
+                script,
+            } => {
+                if self.token(token).is_none() {
+                    return Err(StateError::UnknownToken {
+                        token: token.clone(),
+                    });
+                }
+
+                let balance = self.balance(from, token);
+                if balance < *amount {
+                    return Err(StateError::InsufficientBalance {
+                        token: token.clone(),
+                        account: from.to_owned(),
+                    });
+                }
+                self.set_balance(from, token, balance - amount);
+
+                let lock = Lock {
+                    token: token.clone(),
+                    amount: *amount,
+                    script: script.clone(),
+                };
+                self.locks.insert(format!("{}:{}", from, nonce), lock);
+            }
+            TokenOp::Unlock {
+                lock_id,
+                to,
+                witness,
+            } => {
+                let lock = self
+                    .locks
+                    .get(lock_id)
+                    .ok_or_else(|| StateError::UnknownLock {
+                        lock_id: lock_id.clone(),
+                    })?;
+                let message = TokenOp::unlock_message(lock_id, to);
+                if !lock.script.evaluate(witness, height, &message) {
+                    return Err(StateError::UnsatisfiedScript {
+                        lock_id: lock_id.clone(),
+                    });
+                }
+
+                let lock = self.locks.remove(lock_id).expect("Lock should exist!");
+                self.set_balance(to, &lock.token, self.balance(to, &lock.token) + lock.amount);
+            }
+        }
+
+        self.nonces.insert(from.to_owned(), nonce + 1);
+        Ok(())
+    }
+
+    /// Destroys the given amount of the token owned by the account
+    fn burn(&mut self, account: &str, token: &str, amount: u64) -> Result<(), StateError> {
+        let balance = self.balance(account, token);
+        if balance < amount {
+            return Err(StateError::InsufficientBalance {
+                token: token.to_owned(),
+                account: account.to_owned(),
+            });
+        }
+
+        self.set_balance(account, token, balance - amount);
+        if let Some(info) = self.tokens.get_mut(token) {
+            info.supply -= amount;
+        }
+        Ok(())
+    }
+
+    /// Sets the amount of the token owned by the account
+    fn set_balance(&mut self, account: &str, token: &str, balance: u64) {
+        self.balances
+            .insert((token.to_owned(), account.to_owned()), balance);
+    }
+}
+
+impl Stakes for State {
+    fn stakes(&self, token: &str) -> Vec<(String, u64)> {
+        let mut stakes: Vec<(String, u64)> = self
+            .balances
+            .iter()
+            .filter(|((owned, _), amount)| owned == token && **amount > 0)
+            .map(|((_, account), amount)| (account.clone(), *amount))
+            .collect();
+        stakes.sort();
+        stakes
+    }
+
+    fn offenses(&self) -> Vec<(String, u64)> {
+        let mut offenses: Vec<(String, u64)> = self
+            .offenses
+            .iter()
+            .flat_map(|(offender, heights)| {
+                heights
+                    .iter()
+                    .map(move |height| (offender.clone(), *height))
+            })
+            .collect();
+        offenses.sort();
+        offenses
+    }
+}
+
+impl BlockData for Payload {
+    type State = State;
+    type Error = StateError;
+
+    fn apply(&self, height: u64, state: &mut State) -> Result<(), StateError> {
+        Arc::clone(state.registry()).apply(self, height, state)
+    }
+
+    fn tx_ids(&self) -> Vec<String> {
+        match self {
+            Payload::Batch(payloads) => payloads.iter().flat_map(Payload::tx_ids).collect(),
+            _ => vec![merkle::leaf_hash(self.to_string().as_bytes())],
+        }
+    }
+
+    fn weight(&self) -> u64 {
+        weight::weight(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::payload::DEFAULT_CHAIN_ID, libp2p::identity::ed25519};
+
+    fn register(name: &str, value: &str, revision: u64, keys: &ed25519::Keypair) -> Payload {
+        Payload::register(
+            DEFAULT_CHAIN_ID,
+            name.to_owned(),
+            value.to_owned(),
+            revision,
+            keys,
+        )
+    }
+
+    #[test]
+    fn first_come_first_served() {
+        let owner = ed25519::Keypair::generate();
+        let other = ed25519::Keypair::generate();
+        let mut state = State::default();
+
+        register("alice", "1", 0, &owner)
+            .apply(1, &mut state)
+            .unwrap();
+        assert_eq!(
+            register("alice", "2", 1, &other).apply(1, &mut state),
+            Err(StateError::NameTaken {
+                name: String::from("alice")
+            })
+        );
+
+        register("alice", "2", 1, &owner)
+            .apply(1, &mut state)
+            .unwrap();
+        assert_eq!(state.resolve("alice").unwrap().value, "2");
+        assert_eq!(
+            register("alice", "1", 0, &owner).apply(1, &mut state),
+            Err(StateError::InvalidRevision {
+                name: String::from("alice"),
+                revision: 0
+            })
+        );
+        assert!(matches!(
+            register("al ice", "1", 0, &owner).apply(1, &mut state),
+            Err(StateError::InvalidName { .. })
+        ));
+    }
+
+    #[test]
+    fn token_operations() {
+        let owner = ed25519::Keypair::generate();
+        let other = ed25519::Keypair::generate();
+        let owner_address = hex::encode(owner.public().encode());
+        let other_address = hex::encode(other.public().encode());
+        let mut state = State::default();
+
+        let create = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 100,
+            mintable: true,
+        };
+        Payload::token(DEFAULT_CHAIN_ID, create.clone(), 0, &owner)
+            .apply(1, &mut state)
+            .unwrap();
+        assert_eq!(
+            Payload::token(DEFAULT_CHAIN_ID, create, 1, &owner).apply(1, &mut state),
+            Err(StateError::TokenExists {
+                token: String::from("TTH")
+            })
+        );
+
+        let transfer = TokenOp::Transfer {
+            token: String::from("TTH"),
+            to: other_address.clone(),
+            amount: 60,
+        };
+        Payload::token(DEFAULT_CHAIN_ID, transfer.clone(), 1, &owner)
+            .apply(1, &mut state)
+            .unwrap();
+        assert!(matches!(
+            Payload::token(DEFAULT_CHAIN_ID, transfer.clone(), 1, &owner).apply(1, &mut state),
+            Err(StateError::InvalidNonce { .. })
+        ));
+        assert!(matches!(
+            Payload::token(DEFAULT_CHAIN_ID, transfer, 2, &owner).apply(1, &mut state),
+            Err(StateError::InsufficientBalance { .. })
+        ));
+
+        let mint = TokenOp::Mint {
+            token: String::from("TTH"),
+            amount: 10,
+        };
+        assert!(matches!(
+            Payload::token(DEFAULT_CHAIN_ID, mint.clone(), 0, &other).apply(1, &mut state),
+            Err(StateError::NotMintable { .. })
+        ));
+        Payload::token(DEFAULT_CHAIN_ID, mint, 2, &owner)
+            .apply(1, &mut state)
+            .unwrap();
+
+        assert_eq!(state.balance(&owner_address, "TTH"), 50);
+        assert_eq!(state.balance(&other_address, "TTH"), 60);
+        assert_eq!(state.token("TTH").unwrap().supply, 110);
+
+        let transfer = TokenOp::Transfer {
+            token: String::from("TTH"),
+            to: owner_address.clone(),
+            amount: 50,
+        };
+        assert!(matches!(
+            Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer.clone(), 0, 20, &other)
+                .apply(1, &mut state),
+            Err(StateError::InsufficientBalance { .. })
+        ));
+        Payload::token_with_fee(DEFAULT_CHAIN_ID, transfer, 0, 5, &other)
+            .apply(1, &mut state)
+            .unwrap();
+
+        assert_eq!(state.balance(&owner_address, "TTH"), 100);
+        assert_eq!(state.balance(&other_address, "TTH"), 5);
+        assert_eq!(state.token("TTH").unwrap().supply, 105);
+        assert_eq!(State::from_view(Arc::default(), state.view(1)), state);
+    }
+
+    #[test]
+    fn locked_tokens() {
+        let owner = ed25519::Keypair::generate();
+        let owner_address = hex::encode(owner.public().encode());
+        let mut state = State::default();
+
+        let create = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 100,
+            mintable: false,
+        };
+        Payload::token(DEFAULT_CHAIN_ID, create, 0, &owner)
+            .apply(1, &mut state)
+            .unwrap();
+
+        let lock = TokenOp::Lock {
+            token: String::from("TTH"),
+            amount: 40,
+            script: Script::After { height: 5 },
+        };
+        Payload::token(DEFAULT_CHAIN_ID, lock, 1, &owner)
+            .apply(2, &mut state)
+            .unwrap();
+        let lock_id = format!("{}:1", owner_address);
+        assert_eq!(state.balance(&owner_address, "TTH"), 60);
+
+        let unlock = TokenOp::Unlock {
+            lock_id: lock_id.clone(),
+            to: String::from("receiver"),
+            witness: Default::default(),
+        };
+        assert_eq!(
+            Payload::token(DEFAULT_CHAIN_ID, unlock.clone(), 2, &owner).apply(4, &mut state),
+            Err(StateError::UnsatisfiedScript {
+                lock_id: lock_id.clone()
+            })
+        );
+        Payload::token(DEFAULT_CHAIN_ID, unlock, 2, &owner)
+            .apply(5, &mut state)
+            .unwrap();
+        assert_eq!(state.balance("receiver", "TTH"), 40);
+        assert!(state.lock(&lock_id).is_none());
+    }
+
+    #[cfg(feature = "contracts")]
+    #[test]
+    fn contract_operations() {
+        // Stores its input under key 0, when deployed and when called
+        const STORE: &str = r#"
+            (module
+                (import "env" "input_len" (func $input_len (result i32)))
+                (import "env" "read_input" (func $read_input (param i32)))
+                (import "env" "storage_set" (func $set (param i32 i32 i32 i32)))
+                (memory (export "memory") 1)
+                (func $store
+                    (call $read_input (i32.const 1))
+                    (call $set (i32.const 0) (i32.const 1) (i32.const 1) (call $input_len)))
+                (export "init" (func $store))
+                (export "store" (func $store)))
+        "#;
+        let keys = ed25519::Keypair::generate();
+        let mut state = State::default();
+
+        let deploy = ContractOp::Deploy {
+            code: wat::parse_str(STORE).unwrap(),
+            input: vec![1],
+        };
+        Payload::contract(DEFAULT_CHAIN_ID, deploy.clone(), 0, 10_000, &keys)
+            .apply(1, &mut state)
+            .unwrap();
+        let id = format!("{}:0", hex::encode(keys.public().encode()));
+        let stored = |state: &State| state.contract(&id).unwrap().storage["00"].clone();
+        assert_eq!(stored(&state), "01");
+        assert!(matches!(
+            Payload::contract(DEFAULT_CHAIN_ID, deploy, 0, 10_000, &keys).apply(1, &mut state),
+            Err(StateError::InvalidContractNonce { .. })
+        ));
+
+        // Running out of fuel leaves the state untouched
+        let call = ContractOp::Call {
+            contract: id.clone(),
+            function: String::from("store"),
+            input: vec![2],
+        };
+        let original = state.clone();
+        assert!(matches!(
+            Payload::contract(DEFAULT_CHAIN_ID, call.clone(), 1, 1, &keys).apply(2, &mut state),
+            Err(StateError::ContractFailed { .. })
+        ));
+        assert_eq!(state, original);
+
+        Payload::contract(DEFAULT_CHAIN_ID, call, 1, 10_000, &keys)
+            .apply(2, &mut state)
+            .unwrap();
+        assert_eq!(stored(&state), "02");
+        assert_eq!(State::from_view(Arc::default(), state.view(2)), state);
+    }
+}
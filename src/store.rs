@@ -0,0 +1,703 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        archive::{Archive, Segment},
+        block::Block,
+        codec::Format,
+        payload::Payload,
+        tetherion::Tetherion,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{self, File, OpenOptions},
+        io::{self, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+/// The write-ahead log entry of a block being appended to the store
+#[derive(Serialize, Deserialize)]
+struct WalRecord<B> {
+    /// The size of the blocks file before the block gets appended
+    offset: u64,
+
+    /// The block being appended
+    block: B,
+}
+
+/// The write-ahead log entry of blocks being moved from the blocks file to the archive
+#[derive(Serialize, Deserialize)]
+struct ArchiveRecord {
+    /// The ID of the last block being archived
+    last: u64,
+}
+
+/// The disk usage of the store, in bytes, by component
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    /// The stored blocks
+    pub blocks: u64,
+
+    /// The write-ahead log entries of an append or archiving in progress or interrupted
+    pub wal: u64,
+
+    /// The leftovers of interrupted rewrites of the stored blocks
+    pub temporary: u64,
+
+    /// The backups of corrupted blocks
+    pub backups: u64,
+
+    /// The compressed segments the oldest blocks are archived to, along with their index
+    pub archive: u64,
+}
+
+impl StorageStats {
+    /// Gets the disk usage of all the components
+    pub fn total(&self) -> u64 {
+        self.blocks + self.wal + self.temporary + self.backups + self.archive
+    }
+}
+
+/// Keeps the blocks of the local blockchain on disk, one record per block in the configured
+/// format, so that the node does not have to download the whole blockchain again after a restart.
+///
+/// Blocks are appended through a write-ahead log, so that a crash in the middle of a write never
+/// leaves a partially written block behind: a complete log entry is replayed on startup and an
+/// incomplete one is rolled back. Rewriting all the blocks goes through a temporary file instead.
+///
+/// When archiving is enabled, the blocks deep enough in the blockchain are moved from the blocks
+/// file to compressed segments on compaction, keeping the blocks file small.
+#[derive(Debug, Clone)]
+pub struct BlockStore {
+    /// The directory the files are stored in
+    dir: PathBuf,
+
+    /// The format the blocks are stored in
+    format: Format,
+
+    /// The segments the oldest blocks are archived to
+    archive: Archive,
+
+    /// The number of blocks a block needs to be buried under to get archived; blocks are not
+    /// archived if not set
+    archive_depth: Option<u64>,
+
+    /// The number of blocks per archived segment
+    segment_size: u64,
+}
+
+impl BlockStore {
+    /// The prefix of the files the corrupted blocks are backed up to
+    const BACKUP_PREFIX: &'static str = "damaged-";
+
+    /// The directory of the archive, relative to the store's directory
+    const ARCHIVE_DIR: &'static str = "archive";
+
+    /// Whether the file or directory with the given name, in a store's directory, belongs to a
+    /// store of any format
+    pub fn owns(name: &str) -> bool {
+        name.starts_with("blocks.")
+            || name.starts_with("wal.")
+            || name.starts_with("archiving.")
+            || name.starts_with(BlockStore::BACKUP_PREFIX)
+            || name == BlockStore::ARCHIVE_DIR
+    }
+
+    /// Opens the store in the given directory, creating the directory if needed
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            format: Format::Json,
+            archive: Archive::new(&dir.join(BlockStore::ARCHIVE_DIR), Format::Json),
+            archive_depth: None,
+            segment_size: 1000,
+        })
+    }
+
+    /// Stores the blocks in the given format instead of JSON. Blocks stored in another format
+    /// cannot be read back.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self.archive = Archive::new(&self.dir.join(BlockStore::ARCHIVE_DIR), format);
+        self
+    }
+
+    /// Archives the blocks buried under the given number of blocks on compaction, in segments of
+    /// the given number of blocks
+    pub fn with_archiving(mut self, depth: u64, segment_size: u64) -> Self {
+        self.archive_depth = Some(depth);
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// Loads the stored blocks on top of the given blockchain, which should consist of the genesis
+    /// or the checkpoint block only. A stored genesis block replaces the given one.
+    ///
+    /// The stored blocks are validated while being loaded. In case they turn out to be corrupted,
+    /// the store is truncated to the last valid block and the damaged blocks are backed up to a
+    /// separate file, so that the rest can be synced from the peers. An archived segment that
+    /// cannot be read fails loading instead, leaving the store untouched.
+    pub fn load(&self, mut tetherion: Tetherion<Payload>) -> io::Result<Tetherion<Payload>> {
+        self.recover()?;
+
+        // The number of records read up to the end of each of the segments
+        let mut records = Vec::new();
+        let mut segment_ends = Vec::new();
+        for segment in self.archive.segments()? {
+            let segment_records = self.archive.read(&segment).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!(
+                        "cannot read archived blocks {}-{}: {}",
+                        segment.first, segment.last, err
+                    ),
+                )
+            })?;
+            records.extend(segment_records);
+            segment_ends.push(records.len());
+        }
+
+        match fs::read(self.blocks_path()) {
+            Ok(content) => records.extend(self.format.split_records(&content)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && records.is_empty() => {
+                self.save(&tetherion)?;
+                return Ok(tetherion);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let first = tetherion.blocks()[0].clone();
+        let mut valid = 0;
+        let mut damage = None;
+        for record in &records {
+            let result = self
+                .format
+                .decode::<Block<Payload>>(record)
+                .map_err(|err| err.to_string())
+                .and_then(|block| match valid {
+                    0 if block.hash == first.hash => Ok(()),
+                    0 if block.id == 0 && first.id == 0 && block.has_valid_hash() => {
+                        let consensus = tetherion.consensus().clone();
+                        Tetherion::from_blocks_with(
+                            vec![Arc::new(block)],
+                            tetherion.difficulty(),
+                            tetherion.initial_state().clone(),
+                        )
+                        .map(|genesis| tetherion = genesis.with_consensus(consensus))
+                        .map_err(|err| err.to_string())
+                    }
+                    0 => Err(String::from("Block does not match the first block")),
+                    _ => tetherion.add_block(block).map_err(|err| err.to_string()),
+                });
+            if let Err(reason) = result {
+                damage = Some(reason);
+                break;
+            }
+            valid += 1;
+        }
+
+        if let Some(reason) = damage {
+            let backup = self.dir.join(format!(
+                "{}{}.{}",
+                BlockStore::BACKUP_PREFIX,
+                chrono::Utc::now().timestamp_millis(),
+                self.format.records_extension()
+            ));
+            log::warn!(
+                "Stored blockchain is corrupted after {} blocks ({}), backing up the rest to {}",
+                valid,
+                reason,
+                backup.display()
+            );
+            let damaged: Vec<u8> = records[valid..]
+                .iter()
+                .flat_map(|record| self.format.frame(record))
+                .collect();
+            fs::write(&backup, damaged)?;
+            let intact = segment_ends.iter().take_while(|end| **end <= valid).count();
+            self.archive.truncate(intact)?;
+            self.save(&tetherion)?;
+        }
+        Ok(tetherion)
+    }
+
+    /// Appends the block to the stored ones
+    pub fn append(&self, block: &Block<Payload>) -> io::Result<()> {
+        let offset = match fs::metadata(self.blocks_path()) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        let record = WalRecord { offset, block };
+
+        let mut wal = File::create(self.wal_path())?;
+        wal.write_all(&self.format.encode(&record).map_err(io::Error::other)?)?;
+        wal.sync_all()?;
+
+        self.redo(&record)?;
+        fs::remove_file(self.wal_path())
+    }
+
+    /// Archives the blocks buried deep enough, if archiving is enabled, and rewrites the stored
+    /// blocks in case the store holds anything besides the blocks of the given blockchain, e.g.
+    /// the leftovers of failed writes. Returns the number of bytes reclaimed.
+    pub fn compact(&self, tetherion: &Tetherion<Payload>) -> io::Result<u64> {
+        let before = self.stats()?;
+        let archived = self.archive_blocks(tetherion)?;
+        let size: u64 = self
+            .hot_blocks(tetherion)?
+            .iter()
+            .map(|block| {
+                self.format
+                    .encode_record(block)
+                    .expect("can encode block")
+                    .len() as u64
+            })
+            .sum();
+        if archived == 0 && before.blocks == size && before.wal == 0 && before.temporary == 0 {
+            return Ok(0);
+        }
+
+        self.save(tetherion)?;
+        for path in [self.wal_path(), self.archiving_path()] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        let after = self.stats()?;
+        Ok(before.total().saturating_sub(after.total()))
+    }
+
+    /// Gets the disk usage of the store by component
+    pub fn stats(&self) -> io::Result<StorageStats> {
+        let mut stats = StorageStats::default();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let size = entry.metadata()?.len();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name == self.blocks_file() {
+                stats.blocks += size;
+            } else if name == self.wal_file() || name == self.archiving_file() {
+                stats.wal += size;
+            } else if name.ends_with(".tmp") {
+                stats.temporary += size;
+            } else if name.starts_with(BlockStore::BACKUP_PREFIX) {
+                stats.backups += size;
+            } else if name == BlockStore::ARCHIVE_DIR {
+                stats.archive += self.archive.size()?;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Verifies each of the archived segments, returning the segments along with the reason for
+    /// being invalid, if they are
+    pub fn verify_archive(&self) -> io::Result<Vec<(Segment, Result<(), String>)>> {
+        let segments = self.archive.segments()?;
+        Ok(segments
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| {
+                let previous = i.checked_sub(1).map(|i| &segments[i]);
+                (segment.clone(), self.archive.verify(segment, previous))
+            })
+            .collect())
+    }
+
+    /// Moves the blocks buried deep enough from the blocks file to new archived segments, in case
+    /// archiving is enabled. Returns the number of new segments.
+    fn archive_blocks(&self, tetherion: &Tetherion<Payload>) -> io::Result<usize> {
+        let depth = match self.archive_depth {
+            Some(depth) => depth,
+            None => return Ok(0),
+        };
+
+        let size = self.segment_size as usize;
+        let segments: Vec<_> = self
+            .hot_blocks(tetherion)?
+            .chunks_exact(size)
+            .take_while(|segment| segment[size - 1].id + depth <= tetherion.height())
+            .collect();
+        let last = match segments.last() {
+            Some(segment) => segment[size - 1].id,
+            None => return Ok(0),
+        };
+
+        // The archived blocks are dropped from the blocks file by the recovery in case of a crash
+        // before the blocks file gets rewritten
+        let mut wal = File::create(self.archiving_path())?;
+        wal.write_all(
+            &self
+                .format
+                .encode(&ArchiveRecord { last })
+                .map_err(io::Error::other)?,
+        )?;
+        wal.sync_all()?;
+
+        for segment in &segments {
+            let segment = self.archive.push(segment)?;
+            log::info!("archived blocks {}-{}", segment.first, segment.last);
+        }
+        self.save(tetherion)?;
+        fs::remove_file(self.archiving_path())?;
+        Ok(segments.len())
+    }
+
+    /// Gets the blocks of the blockchain stored in the blocks file, i.e. the ones not archived
+    fn hot_blocks<'a>(
+        &self,
+        tetherion: &'a Tetherion<Payload>,
+    ) -> io::Result<&'a [Arc<Block<Payload>>]> {
+        let blocks = tetherion.blocks();
+        let first = blocks[0].id;
+        Ok(match self.archive.segments()?.last() {
+            Some(segment) => blocks
+                .get((segment.last + 1).saturating_sub(first) as usize..)
+                .unwrap_or_default(),
+            None => blocks,
+        })
+    }
+
+    /// Replays the write-ahead log entries left behind by an interrupted archiving and append, if
+    /// they were written completely, or rolls them back otherwise
+    fn recover(&self) -> io::Result<()> {
+        self.recover_archiving()?;
+
+        let content = match fs::read(self.wal_path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        match self.format.decode::<WalRecord<Block<Payload>>>(&content) {
+            Ok(record) => {
+                log::info!(
+                    "Replaying the interrupted append of block {}",
+                    record.block.id
+                );
+                self.redo(&WalRecord {
+                    offset: record.offset,
+                    block: &record.block,
+                })?;
+            }
+            Err(_) => log::warn!("Rolling back the incomplete write-ahead log entry"),
+        }
+        fs::remove_file(self.wal_path())
+    }
+
+    /// Drops the blocks already archived by an interrupted archiving from the blocks file, so that
+    /// they are not stored twice. Blocks not archived yet stay in the blocks file.
+    fn recover_archiving(&self) -> io::Result<()> {
+        let content = match fs::read(self.archiving_path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if let Ok(record) = self.format.decode::<ArchiveRecord>(&content) {
+            log::info!(
+                "Completing the interrupted archiving of blocks up to {}",
+                record.last
+            );
+        }
+
+        let archived = match self.archive.segments()?.last() {
+            Some(segment) => segment.last,
+            None => return fs::remove_file(self.archiving_path()),
+        };
+        let content = match fs::read(self.blocks_path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let path = self.blocks_path();
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for record in self.format.split_records(&content) {
+            let stale = self
+                .format
+                .decode::<Block<Payload>>(&record)
+                .is_ok_and(|block| block.id <= archived);
+            if !stale {
+                file.write_all(&self.format.frame(&record))?;
+            }
+        }
+        file.sync_all()?;
+        fs::rename(tmp, path)?;
+        fs::remove_file(self.archiving_path())
+    }
+
+    /// Appends the block of the write-ahead log entry at the offset it was logged at, dropping
+    /// whatever an interrupted attempt left behind
+    fn redo(&self, record: &WalRecord<&Block<Payload>>) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(self.blocks_path())?;
+        file.set_len(record.offset)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let block = self
+            .format
+            .encode_record(record.block)
+            .expect("can encode block");
+        file.write_all(&block)?;
+        file.sync_all()
+    }
+
+    /// Replaces the stored blocks with the blocks of the given blockchain. Archived segments not
+    /// part of the blockchain anymore are dropped.
+    pub fn save(&self, tetherion: &Tetherion<Payload>) -> io::Result<()> {
+        let blocks = tetherion.blocks();
+        let first = blocks[0].id;
+        let kept = self
+            .archive
+            .segments()?
+            .iter()
+            .take_while(|segment| {
+                segment.first >= first
+                    && blocks
+                        .get((segment.last - first) as usize)
+                        .is_some_and(|block| block.hash == segment.last_hash)
+            })
+            .count();
+        self.archive.truncate(kept)?;
+
+        let path = self.blocks_path();
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for block in self.hot_blocks(tetherion)? {
+            let block = self.format.encode_record(block).expect("can encode block");
+            file.write_all(&block)?;
+        }
+        file.sync_all()?;
+        fs::rename(tmp, path)
+    }
+
+    /// Gets the path of the file the candidate block being mined is snapshotted to, so that mining
+    /// it survives restarts
+    pub fn candidate_path(&self) -> PathBuf {
+        self.dir.join("candidate.json")
+    }
+
+    /// Gets the name of the file the blocks are stored in, relative to the store's directory
+    fn blocks_file(&self) -> String {
+        format!("blocks.{}", self.format.records_extension())
+    }
+
+    /// Gets the name of the write-ahead log file, relative to the store's directory
+    fn wal_file(&self) -> String {
+        format!("wal.{}", self.format.extension())
+    }
+
+    /// Gets the name of the write-ahead log file of archiving, relative to the store's directory
+    fn archiving_file(&self) -> String {
+        format!("archiving.{}", self.format.extension())
+    }
+
+    /// Gets the path of the file the blocks are stored in
+    fn blocks_path(&self) -> PathBuf {
+        self.dir.join(self.blocks_file())
+    }
+
+    /// Gets the path of the write-ahead log file
+    fn wal_path(&self) -> PathBuf {
+        self.dir.join(self.wal_file())
+    }
+
+    /// Gets the path of the write-ahead log file of archiving
+    fn archiving_path(&self) -> PathBuf {
+        self.dir.join(self.archiving_file())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFFICULTY: usize = 1;
+
+    #[test]
+    fn truncate_corrupted_tail() {
+        let dir = std::env::temp_dir().join(format!("tetherion-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = BlockStore::open(&dir).unwrap();
+
+        let genesis = || Tetherion::new(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let mut tetherion = store.load(genesis()).unwrap();
+        for id in 1..=3 {
+            let previous_hash = tetherion.blocks().last().unwrap().hash.clone();
+            let block = Block::new(
+                id,
+                &previous_hash,
+                Payload::Text(id.to_string()),
+                DIFFICULTY,
+            );
+            tetherion.add_block(block).unwrap();
+            store.append(tetherion.blocks().last().unwrap()).unwrap();
+        }
+        assert_eq!(store.load(genesis()).unwrap().height(), 3);
+
+        let content = fs::read_to_string(store.blocks_path()).unwrap();
+        let mut lines: Vec<&str> = content.lines().collect();
+        let tampered = lines[2].replace("\"2\"", "\"tampered\"");
+        lines[2] = &tampered;
+        fs::write(store.blocks_path(), lines.join("\n")).unwrap();
+
+        assert_eq!(store.load(genesis()).unwrap().height(), 1);
+        let tetherion = store.load(genesis()).unwrap();
+        assert_eq!(tetherion.height(), 1);
+        let stats = store.stats().unwrap();
+        assert!(stats.backups > 0);
+
+        store.append(&tetherion.blocks()[1]).unwrap();
+        assert!(store.compact(&tetherion).unwrap() > 0);
+        assert_eq!(store.compact(&tetherion).unwrap(), 0);
+        assert_eq!(store.stats().unwrap(), stats);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recover_interrupted_append() {
+        let dir = std::env::temp_dir().join(format!("tetherion-wal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = BlockStore::open(&dir).unwrap();
+
+        let tetherion = store
+            .load(Tetherion::new(
+                Payload::Text(String::from("genesis")),
+                DIFFICULTY,
+            ))
+            .unwrap();
+        let genesis = tetherion.blocks()[0].clone();
+        let block = Block::new(1, &genesis.hash, Payload::Text(String::new()), DIFFICULTY);
+
+        // The crash happens after logging the block, while the block is being appended
+        let offset = fs::metadata(store.blocks_path()).unwrap().len();
+        let record = WalRecord {
+            offset,
+            block: &block,
+        };
+        fs::write(store.wal_path(), serde_json::to_vec(&record).unwrap()).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(store.blocks_path())
+            .unwrap();
+        write!(file, "{{\"id\":1,").unwrap();
+
+        let tetherion = store.load(tetherion.clone()).unwrap();
+        assert_eq!(tetherion.blocks().last().unwrap().hash, block.hash);
+        assert!(!store.wal_path().exists());
+
+        // The crash happens while the block is being logged
+        fs::write(store.wal_path(), b"{\"offset\":").unwrap();
+        assert_eq!(store.load(tetherion).unwrap().height(), 1);
+        assert!(!store.wal_path().exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn store_in_each_format() {
+        for format in [Format::Json, Format::Cbor, Format::Bincode, Format::Msgpack] {
+            let dir = std::env::temp_dir().join(format!(
+                "tetherion-format-{}-{}",
+                format,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            let store = BlockStore::open(&dir).unwrap().with_format(format);
+
+            let genesis = || Tetherion::new(Payload::Text(String::from("genesis")), DIFFICULTY);
+            let mut tetherion = store.load(genesis()).unwrap();
+            for id in 1..=3 {
+                let previous_hash = tetherion.blocks().last().unwrap().hash.clone();
+                let block = Block::new(
+                    id,
+                    &previous_hash,
+                    Payload::Text(id.to_string()),
+                    DIFFICULTY,
+                );
+                tetherion.add_block(block).unwrap();
+                store.append(tetherion.blocks().last().unwrap()).unwrap();
+            }
+            assert_eq!(store.load(genesis()).unwrap().height(), 3);
+
+            let content = fs::read(store.blocks_path()).unwrap();
+            fs::write(store.blocks_path(), &content[..content.len() - 2]).unwrap();
+            assert_eq!(store.load(genesis()).unwrap().height(), 2, "{}", format);
+            assert!(store.stats().unwrap().backups > 0);
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn archive_old_blocks() {
+        let dir = std::env::temp_dir().join(format!("tetherion-archive-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = BlockStore::open(&dir).unwrap().with_archiving(1, 2);
+
+        let genesis = || Tetherion::new(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let mut tetherion = store.load(genesis()).unwrap();
+        for id in 1..=5 {
+            let previous_hash = tetherion.blocks().last().unwrap().hash.clone();
+            let block = Block::new(
+                id,
+                &previous_hash,
+                Payload::Text(id.to_string()),
+                DIFFICULTY,
+            );
+            tetherion.add_block(block).unwrap();
+            store.append(tetherion.blocks().last().unwrap()).unwrap();
+        }
+
+        store.compact(&tetherion).unwrap();
+        let segments = store.verify_archive().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert!(segments.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(store.hot_blocks(&tetherion).unwrap().len(), 2);
+        assert_eq!(store.load(genesis()).unwrap().height(), 5);
+
+        // The crash happens after archiving, while the blocks file is being rewritten
+        let segments = store.archive.segments().unwrap();
+        let blocks = &tetherion.blocks()[3..];
+        fs::write(
+            store.archiving_path(),
+            serde_json::to_vec(&ArchiveRecord { last: 4 }).unwrap(),
+        )
+        .unwrap();
+        let content: Vec<u8> = blocks
+            .iter()
+            .flat_map(|block| store.format.encode_record(block).unwrap())
+            .collect();
+        fs::write(store.blocks_path(), content).unwrap();
+        assert_eq!(store.load(genesis()).unwrap().height(), 5);
+        assert_eq!(store.hot_blocks(&tetherion).unwrap().len(), 2);
+        assert_eq!(
+            fs::read(store.blocks_path()).unwrap().len(),
+            blocks[1..]
+                .iter()
+                .map(|block| store.format.encode_record(block).unwrap().len())
+                .sum::<usize>()
+        );
+        assert!(!store.archiving_path().exists());
+
+        let segment = segments[1].clone();
+        let content = fs::read(store.archive.path(&segment)).unwrap();
+        fs::write(store.archive.path(&segment), b"tampered").unwrap();
+        assert!(store.verify_archive().unwrap()[1].1.is_err());
+        assert!(store.load(genesis()).is_err());
+        assert_eq!(store.archive.segments().unwrap().len(), 2);
+        fs::write(store.archive.path(&segment), content).unwrap();
+        assert_eq!(store.load(genesis()).unwrap().height(), 5);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{block::Block, finality::BlockRef, payload::Payload},
+    libp2p::PeerId,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, fmt, sync::Arc},
+};
+
+/// The maximum number of blocks sent in a single frame of a chain response
+pub const FRAME_BLOCKS: usize = 100;
+
+/// The maximum length of the correlation ID of a sync session, which gets logged
+const MAX_SESSION_LEN: usize = 64;
+
+/// The handshake opening a sync session, asking a peer for its blockchain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SyncHandshake {
+    /// The peer asking for the blockchain
+    pub requester: String,
+
+    /// The peer the blockchain is asked for
+    pub responder: String,
+
+    /// The latest block of the requester's blockchain
+    pub tip: BlockRef,
+
+    /// The version of the protocol the requester speaks
+    pub protocol_version: String,
+
+    /// The correlation ID of the sync session, chosen by the requester
+    pub session: String,
+}
+
+/// The reason a sync handshake got rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// A peer ID of the handshake is not a valid one
+    InvalidPeerId(String),
+
+    /// The handshake was sent by another peer than the requester
+    Spoofed { requester: PeerId },
+
+    /// The requester speaks another version of the protocol
+    UnsupportedVersion(String),
+
+    /// The correlation ID of the session is empty or too long
+    InvalidSession,
+}
+
+impl HandshakeError {
+    /// Gets the short name of the error, which the log is throttled by
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidPeerId(_) => "invalid_peer_id",
+            Self::Spoofed { .. } => "spoofed_handshake",
+            Self::UnsupportedVersion(_) => "unsupported_version",
+            Self::InvalidSession => "invalid_session",
+        }
+    }
+
+    /// Checks whether the error can only be caused by a misbehaving peer, rather than by an
+    /// outdated one
+    pub fn is_misbehaviour(&self) -> bool {
+        !matches!(self, Self::UnsupportedVersion(_))
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidPeerId(id) => write!(f, "invalid peer ID {}", id),
+            Self::Spoofed { requester } => write!(f, "handshake sent on behalf of {}", requester),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            Self::InvalidSession => write!(f, "invalid sync session ID"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl SyncHandshake {
+    /// Gets the peer the blockchain is asked for
+    pub fn responder(&self) -> Result<PeerId, HandshakeError> {
+        parse_peer_id(&self.responder)
+    }
+
+    /// Checks the handshake received from the given peer, which must be the requester, against
+    /// the version of the protocol the node speaks, returning the requester
+    pub fn validate(
+        &self,
+        source: &PeerId,
+        protocol_version: &str,
+    ) -> Result<PeerId, HandshakeError> {
+        let requester = parse_peer_id(&self.requester)?;
+        if requester != *source {
+            return Err(HandshakeError::Spoofed { requester });
+        }
+        if self.protocol_version != protocol_version {
+            return Err(HandshakeError::UnsupportedVersion(
+                self.protocol_version.clone(),
+            ));
+        }
+        if self.session.is_empty() || self.session.len() > MAX_SESSION_LEN {
+            return Err(HandshakeError::InvalidSession);
+        }
+        Ok(requester)
+    }
+}
+
+fn parse_peer_id(id: &str) -> Result<PeerId, HandshakeError> {
+    id.parse()
+        .map_err(|_| HandshakeError::InvalidPeerId(id.to_owned()))
+}
+
+/// A part of the blockchain sent in response to a chain request. The blockchain is sent as
+/// consecutive frames, so that it never gets jsonified at once.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChainFrame {
+    /// The peer the blockchain is sent to
+    pub receiver: String,
+
+    /// The position of the frame among the frames of the blockchain
+    pub index: usize,
+
+    /// Whether the frame is the last one of the blockchain
+    pub last: bool,
+
+    /// The ID of the chain the blockchain belongs to
+    pub chain_id: String,
+
+    /// The difficulty of the blockchain
+    pub difficulty: usize,
+
+    /// The correlation ID of the sync session the blockchain is sent in
+    #[serde(default)]
+    pub session: String,
+
+    /// The consecutive blocks sent in the frame
+    pub blocks: Vec<Arc<Block<Payload>>>,
+}
+
+/// Splits the blocks of a blockchain into the frames sent to the receiver
+pub fn frames<'a>(
+    blocks: &'a [Arc<Block<Payload>>],
+    chain_id: &'a str,
+    difficulty: usize,
+    receiver: &'a str,
+    session: &'a str,
+) -> impl Iterator<Item = ChainFrame> + 'a {
+    let count = blocks.len().div_ceil(FRAME_BLOCKS);
+    blocks
+        .chunks(FRAME_BLOCKS)
+        .enumerate()
+        .map(move |(index, blocks)| ChainFrame {
+            receiver: receiver.to_owned(),
+            index,
+            last: index + 1 == count,
+            chain_id: chain_id.to_owned(),
+            difficulty,
+            session: session.to_owned(),
+            blocks: blocks.to_vec(),
+        })
+}
+
+/// A blockchain whose frames are still arriving
+#[derive(Debug, Default)]
+struct PartialChain {
+    /// The index of the next expected frame
+    next_index: usize,
+
+    /// The blocks received so far
+    blocks: Vec<Arc<Block<Payload>>>,
+}
+
+/// Puts together the blockchains received frame by frame, by sending peer
+#[derive(Debug, Default)]
+pub struct ChainAssembler {
+    /// The blockchains being received, by sending peer
+    chains: HashMap<String, PartialChain>,
+}
+
+impl ChainAssembler {
+    /// Adds the frame received from the peer, returning the blocks of the blockchain once its
+    /// last frame arrived. A frame not following the previous one drops the partial blockchain.
+    pub fn add(&mut self, source: &str, frame: ChainFrame) -> Option<Vec<Arc<Block<Payload>>>> {
+        let mut chain = self.chains.remove(source).unwrap_or_default();
+        if frame.index == 0 {
+            chain = PartialChain::default();
+        }
+        if frame.index != chain.next_index {
+            log::warn!(
+                "dropping blockchain from {}, frame {} is missing",
+                source,
+                chain.next_index
+            );
+            return None;
+        }
+
+        chain.blocks.extend(frame.blocks);
+        if frame.last {
+            return Some(chain.blocks);
+        }
+        chain.next_index += 1;
+        self.chains.insert(source.to_owned(), chain);
+        None
+    }
+
+    /// Gets the number of blockchains being received
+    pub fn pending(&self) -> usize {
+        self.chains.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_handshake() {
+        let (requester, responder) = (PeerId::random(), PeerId::random());
+        let handshake = SyncHandshake {
+            requester: requester.to_string(),
+            responder: responder.to_string(),
+            tip: BlockRef {
+                height: 0,
+                hash: String::from("00ab"),
+            },
+            protocol_version: String::from("/tetherion/1.3.0"),
+            session: String::from("sync-abcdef-1"),
+        };
+        assert_eq!(handshake.responder(), Ok(responder));
+        assert_eq!(
+            handshake.validate(&requester, "/tetherion/1.3.0"),
+            Ok(requester)
+        );
+
+        let err = handshake
+            .validate(&responder, "/tetherion/1.3.0")
+            .unwrap_err();
+        assert_eq!(err, HandshakeError::Spoofed { requester });
+        let err = handshake
+            .validate(&requester, "/tetherion/1.4.0")
+            .unwrap_err();
+        assert!(!err.is_misbehaviour());
+
+        let malformed = SyncHandshake {
+            responder: String::from("not-a-peer"),
+            session: String::new(),
+            ..handshake.clone()
+        };
+        assert_eq!(malformed.responder().unwrap_err().kind(), "invalid_peer_id");
+        assert_eq!(
+            malformed.validate(&requester, "/tetherion/1.3.0"),
+            Err(HandshakeError::InvalidSession)
+        );
+    }
+
+    #[test]
+    fn assemble_frames() {
+        let block = Arc::new(Block::genesis(Payload::Text(String::from("genesis")), 1));
+        let blocks = vec![block; 2 * FRAME_BLOCKS + 1];
+
+        let frames: Vec<_> = frames(&blocks, "main", 1, "receiver", "sync").collect();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[2].last && frames[2].blocks.len() == 1);
+        assert!(frames.iter().all(|frame| frame.session == "sync"));
+
+        let mut assembler = ChainAssembler::default();
+        let mut frames = frames.into_iter();
+        assert!(assembler.add("peer", frames.next().unwrap()).is_none());
+        let second = frames.next().unwrap();
+        assert!(assembler.add("peer", frames.next().unwrap()).is_none());
+        assert!(assembler.add("peer", second).is_none());
+
+        let mut frames = super::frames(&blocks, "main", 1, "receiver", "sync");
+        assert!(assembler.add("peer", frames.next().unwrap()).is_none());
+        assert!(assembler.add("peer", frames.next().unwrap()).is_none());
+        let assembled = assembler.add("peer", frames.next().unwrap()).unwrap();
+        assert_eq!(assembled.len(), blocks.len());
+    }
+}
@@ -1,68 +1,572 @@
 /// Copyright (c) 2022 Tetherion
 use {
-    crate::block::Block,
+    crate::{
+        block::{Block, BlockHeader},
+        consensus::{ConsensusEngine, ProofOfWork, Stakes, Validators},
+        merkle,
+        weight::MAX_BLOCK_WEIGHT,
+    },
     serde::{Deserialize, Serialize},
-    std::{fmt, result},
+    std::{collections::BTreeMap, convert::TryFrom, fmt, result, sync::Arc},
 };
 
-#[derive(Debug)]
+/// A consensus rule violated by a block, along with the context needed to tell why, e.g. the
+/// expected and the actual hashes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
 pub enum InvalidBlockError {
-    InvalidBlockId { id: u64, previous_id: u64 },
-    InvalidPreviousHash { id: u64 },
-    InvalidDifficulty { id: u64, difficulty: usize },
+    InvalidBlockId {
+        id: u64,
+        previous_id: u64,
+    },
+    InvalidPreviousHash {
+        id: u64,
+        /// The hash of the block the block follows up
+        expected: String,
+        actual: String,
+    },
+    InvalidDifficulty {
+        id: u64,
+        difficulty: usize,
+        hash: String,
+    },
+    MissingPreviousBlock {
+        id: u64,
+        previous_hash: String,
+    },
+    InvalidHash {
+        id: u64,
+        /// The hash computed from the block's content
+        expected: String,
+        actual: String,
+    },
+    InvalidData {
+        id: u64,
+        reason: String,
+    },
+    InvalidSeal {
+        id: u64,
+        timestamp: i64,
+        previous_timestamp: i64,
+    },
+    Overweight {
+        id: u64,
+        weight: u64,
+    },
+    GenesisMismatch {
+        /// The hash of the configured genesis block
+        expected: String,
+        actual: String,
+    },
+    EmptyChain,
 }
 
 impl fmt::Display for InvalidBlockError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
+        match self {
             InvalidBlockError::InvalidBlockId { id, previous_id } => write!(
                 f,
                 "Block with ID {} does not follow up previous block's ID {}",
                 id, previous_id
             ),
-            InvalidBlockError::InvalidPreviousHash { id } => {
-                write!(f, "Block with ID {} has the wrong previous hash", id)
+            InvalidBlockError::InvalidPreviousHash {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Block with ID {} has the wrong previous hash {}, expected {}",
+                id, actual, expected
+            ),
+            InvalidBlockError::InvalidDifficulty {
+                id,
+                difficulty,
+                hash,
+            } => write!(
+                f,
+                "Block with ID {} does not satisfy difficulty of {} with hash {}",
+                id, difficulty, hash
+            ),
+            InvalidBlockError::MissingPreviousBlock { id, previous_hash } => write!(
+                f,
+                "Block with ID {} does not follow up any known block, its previous hash being {}",
+                id, previous_hash
+            ),
+            InvalidBlockError::InvalidHash {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Block with ID {} has a hash {} not matching its content, whose hash is {}",
+                id, actual, expected
+            ),
+            InvalidBlockError::InvalidData { id, reason } => {
+                write!(f, "Block with ID {} stores invalid data: {}", id, reason)
             }
-            InvalidBlockError::InvalidDifficulty { id, difficulty } => write!(
+            InvalidBlockError::InvalidSeal {
+                id,
+                timestamp,
+                previous_timestamp,
+            } => write!(
                 f,
-                "Block with ID {} does not satisfy difficulty of {}",
-                id, difficulty
+                "Block with ID {} and timestamp {} is not sealed by its scheduled producer after \
+                 the previous block's timestamp {}",
+                id, timestamp, previous_timestamp
             ),
+            InvalidBlockError::Overweight { id, weight } => write!(
+                f,
+                "Block with ID {} weighs {}, exceeding the budget of {}",
+                id, weight, MAX_BLOCK_WEIGHT
+            ),
+            InvalidBlockError::GenesisMismatch { expected, actual } => write!(
+                f,
+                "Genesis block {} is not the configured genesis block {}",
+                actual, expected
+            ),
+            InvalidBlockError::EmptyChain => write!(f, "Blockchain has no blocks"),
+        }
+    }
+}
+
+impl InvalidBlockError {
+    /// Creates the error of a block whose hash does not match its content
+    pub fn invalid_hash<T: BlockData>(block: &Block<T>) -> Self {
+        InvalidBlockError::InvalidHash {
+            id: block.id,
+            expected: block.compute_hash(),
+            actual: block.hash.clone(),
+        }
+    }
+
+    /// Creates the error of a header whose hash does not match its content
+    pub fn invalid_header_hash(header: &BlockHeader) -> Self {
+        InvalidBlockError::InvalidHash {
+            id: header.id,
+            expected: header.compute_hash(),
+            actual: header.hash.clone(),
+        }
+    }
+
+    /// Creates the error of a block not following up the block with the given hash
+    pub fn invalid_previous_hash(id: u64, expected: &str, actual: &str) -> Self {
+        InvalidBlockError::InvalidPreviousHash {
+            id,
+            expected: expected.to_owned(),
+            actual: actual.to_owned(),
+        }
+    }
+
+    /// Gets the ID of the invalid block
+    pub fn id(&self) -> u64 {
+        match self {
+            InvalidBlockError::InvalidBlockId { id, .. }
+            | InvalidBlockError::InvalidPreviousHash { id, .. }
+            | InvalidBlockError::InvalidDifficulty { id, .. }
+            | InvalidBlockError::MissingPreviousBlock { id, .. }
+            | InvalidBlockError::InvalidHash { id, .. }
+            | InvalidBlockError::InvalidData { id, .. }
+            | InvalidBlockError::InvalidSeal { id, .. }
+            | InvalidBlockError::Overweight { id, .. } => *id,
+            InvalidBlockError::GenesisMismatch { .. } | InvalidBlockError::EmptyChain => 0,
+        }
+    }
+
+    /// Gets the kind of the violated rule, the same for all the blocks violating it
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InvalidBlockError::InvalidBlockId { .. } => "invalid_block_id",
+            InvalidBlockError::InvalidPreviousHash { .. } => "invalid_previous_hash",
+            InvalidBlockError::InvalidDifficulty { .. } => "invalid_difficulty",
+            InvalidBlockError::MissingPreviousBlock { .. } => "missing_previous_block",
+            InvalidBlockError::InvalidHash { .. } => "invalid_hash",
+            InvalidBlockError::InvalidData { .. } => "invalid_data",
+            InvalidBlockError::InvalidSeal { .. } => "invalid_seal",
+            InvalidBlockError::Overweight { .. } => "overweight",
+            InvalidBlockError::GenesisMismatch { .. } => "genesis_mismatch",
+            InvalidBlockError::EmptyChain => "empty_chain",
         }
     }
 }
 
 impl std::error::Error for InvalidBlockError {}
 
+/// The data stored in blocks, whose validity may depend on the data stored in the preceding
+/// blocks
+pub trait BlockData: fmt::Display {
+    /// The state built by applying the data of all the blocks, in order
+    type State: Default + Clone + PartialEq + fmt::Debug + Stakes;
+
+    /// The reason for the data being invalid
+    type Error: fmt::Display;
+
+    /// Applies the data stored in the block at the given height to the state, leaving the state
+    /// untouched if the data is invalid
+    fn apply(&self, height: u64, state: &mut Self::State) -> result::Result<(), Self::Error>;
+
+    /// Gets the IDs of the transactions the data consists of, which are the leaves of the
+    /// block's Merkle tree. By default, the whole data is a single transaction.
+    fn tx_ids(&self) -> Vec<String> {
+        vec![merkle::leaf_hash(self.to_string().as_bytes())]
+    }
+
+    /// Gets the weight of the data, which the data stored in a block must not exceed the budget
+    /// with. By default, the data weighs nothing.
+    fn weight(&self) -> u64 {
+        0
+    }
+}
+
+impl BlockData for String {
+    type State = ();
+    type Error = std::convert::Infallible;
+
+    fn apply(&self, _height: u64, _state: &mut ()) -> result::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Tetherion<T: fmt::Display> {
-    /// Blocks in the blockchain
-    blocks: Vec<Block<T>>,
+#[serde(try_from = "Chain<T>")]
+#[serde(bound(deserialize = "T: Deserialize<'de>"))]
+pub struct Tetherion<T: BlockData> {
+    /// Blocks in the blockchain, shared with the copies of the blockchain so that copying it
+    /// does not copy the data stored in the blocks
+    blocks: Vec<Arc<Block<T>>>,
 
     /// The difficulty of the blockchain, i.e. measure of how difficult it is to mine a block
     difficulty: usize,
+
+    /// The state built by applying the data of all the blocks
+    #[serde(skip)]
+    state: T::State,
+
+    /// The state the data of the first block is applied to, which carries the rules the data is
+    /// validated with, if the state has any. In case the first block is a checkpoint block, the
+    /// state as of the checkpoint block, its data applied already.
+    #[serde(skip)]
+    initial_state: T::State,
+
+    /// The rules deciding which blocks are allowed to extend the blockchain
+    #[serde(skip)]
+    consensus: Arc<dyn ConsensusEngine>,
+
+    /// The validators of each epoch, by epoch number, in case block producers are selected by
+    /// their stakes
+    #[serde(skip)]
+    epochs: BTreeMap<u64, Validators>,
+}
+
+/// The serialized blockchain, whose state gets rebuilt on deserialization
+#[derive(Deserialize)]
+struct Chain<T: BlockData> {
+    blocks: Vec<Arc<Block<T>>>,
+    difficulty: usize,
 }
 
-impl<T: fmt::Display> Tetherion<T> {
+impl<T: BlockData> TryFrom<Chain<T>> for Tetherion<T> {
+    type Error = InvalidBlockError;
+
+    fn try_from(chain: Chain<T>) -> result::Result<Self, Self::Error> {
+        Tetherion::from_blocks(chain.blocks, chain.difficulty)
+    }
+}
+
+impl<T: BlockData> Tetherion<T> {
     pub fn new(genesis_data: T, difficulty: usize) -> Self {
         let genesis = Block::<T>::genesis(genesis_data, difficulty);
+        Tetherion::from_genesis(genesis, difficulty).expect("genesis data should be valid")
+    }
+
+    /// Creates the blockchain starting from an existing genesis block
+    pub fn from_genesis(
+        genesis: Block<T>,
+        difficulty: usize,
+    ) -> result::Result<Self, InvalidBlockError> {
+        Tetherion::from_blocks(vec![Arc::new(genesis)], difficulty)
+    }
+
+    /// Creates the blockchain out of existing blocks, building the state from their data
+    pub fn from_blocks(
+        blocks: Vec<Arc<Block<T>>>,
+        difficulty: usize,
+    ) -> result::Result<Self, InvalidBlockError> {
+        Tetherion::from_blocks_with(blocks, difficulty, T::State::default())
+    }
+
+    /// Creates the blockchain out of existing blocks, building the state by applying their data to
+    /// the given initial state, e.g. one validating the data with the local rules. In case the
+    /// blocks start with a checkpoint block, the initial state is the state as of that block.
+    /// Fails if there are no blocks at all.
+    pub fn from_blocks_with(
+        blocks: Vec<Arc<Block<T>>>,
+        difficulty: usize,
+        initial_state: T::State,
+    ) -> result::Result<Self, InvalidBlockError> {
+        if blocks.is_empty() {
+            return Err(InvalidBlockError::EmptyChain);
+        }
+        let mut state = initial_state.clone();
+        for (position, block) in blocks.iter().enumerate() {
+            match position {
+                0 => Tetherion::<T>::apply_first(block, &mut state)?,
+                _ => Tetherion::<T>::apply_block(block, &mut state)?,
+            }
+        }
+
+        Ok(Self {
+            blocks,
+            consensus: Arc::new(ProofOfWork::new(difficulty)),
+            difficulty,
+            state,
+            initial_state,
+            epochs: BTreeMap::new(),
+        })
+    }
 
-        Self {
-            blocks: vec![genesis],
-            difficulty: difficulty,
+    /// Creates the blockchain starting from a trusted checkpoint block instead of the genesis
+    /// block, along with the state as of the checkpoint block, e.g. loaded from a snapshot
+    pub fn from_checkpoint(
+        checkpoint: Block<T>,
+        difficulty: usize,
+        state: T::State,
+    ) -> result::Result<Self, InvalidBlockError> {
+        Tetherion::from_blocks_with(vec![Arc::new(checkpoint)], difficulty, state)
+    }
+
+    /// Sets the rules deciding which blocks are allowed to extend the blockchain, Proof of Work
+    /// with the blockchain's difficulty by default
+    pub fn with_consensus(mut self, consensus: Arc<dyn ConsensusEngine>) -> Self {
+        self.consensus = consensus;
+        self.epochs = BTreeMap::new();
+        if self.consensus.staking().is_some() {
+            // The validators depend on the stakes at the end of each epoch, so replay the state,
+            // which got built out of the same blocks already
+            let mut state = self.initial_state.clone();
+            for (position, block) in self.blocks.iter().enumerate() {
+                match position {
+                    0 => Tetherion::<T>::apply_first(block, &mut state),
+                    _ => Tetherion::<T>::apply_block(block, &mut state),
+                }
+                .expect("The data of the blocks should have been applied already!");
+                Tetherion::<T>::end_epoch(self.consensus.as_ref(), &mut self.epochs, block, &state);
+            }
         }
+        self
+    }
+
+    /// Sets the state the data of the first block is applied to, e.g. one validating the data
+    /// with the rules configured at startup, and rebuilds the state from it. Fails if the data of
+    /// a block breaks the rules.
+    pub fn with_initial_state(
+        mut self,
+        initial_state: T::State,
+    ) -> result::Result<Self, InvalidBlockError> {
+        self.initial_state = initial_state;
+        self.reindex().map(|_| self)
+    }
+
+    /// Gets the state the data of the first block is applied to
+    pub fn initial_state(&self) -> &T::State {
+        &self.initial_state
     }
 
     /// Gets all the blocks of the blockchain
-    pub fn blocks(&self) -> &Vec<Block<T>> {
+    pub fn blocks(&self) -> &[Arc<Block<T>>] {
         &self.blocks
     }
 
+    /// Gets the state built by applying the data of all the blocks
+    pub fn state(&self) -> &T::State {
+        &self.state
+    }
+
+    /// Rebuilds the state as of the block at the given height by replaying the data of the blocks
+    /// up to it. Returns `None` if the blockchain has no block at the given height.
+    pub fn state_at(&self, height: u64) -> Option<T::State> {
+        let first = self
+            .blocks
+            .first()
+            .expect("There should be at least one block in the blockchain!");
+        let len = usize::try_from(height.checked_sub(first.id)?).ok()? + 1;
+        if len >= self.blocks.len() {
+            return (len == self.blocks.len()).then(|| self.state.clone());
+        }
+
+        let mut state = self.initial_state.clone();
+        Tetherion::<T>::apply_first(first, &mut state)
+            .expect("The data of the blocks should have been applied already!");
+        self.replay_state(state, first.id, height)
+    }
+
+    /// Advances the state as of the block at height `from` to the block at height `to` by
+    /// replaying the data of the blocks in between. Returns `None` if the blockchain has no block
+    /// at either height or `to` is below `from`.
+    pub fn replay_state(&self, mut state: T::State, from: u64, to: u64) -> Option<T::State> {
+        let first = self
+            .blocks
+            .first()
+            .expect("There should be at least one block in the blockchain!");
+        let start = usize::try_from(from.checked_sub(first.id)?).ok()?;
+        let end = usize::try_from(to.checked_sub(first.id)?).ok()?;
+        if start > end || end >= self.blocks.len() {
+            return None;
+        }
+
+        for block in &self.blocks[start + 1..=end] {
+            Tetherion::<T>::apply_block(block, &mut state)
+                .expect("The data of the blocks should have been applied already!");
+        }
+        Some(state)
+    }
+
+    /// Gets the state as of the block the given number of blocks below the latest one, i.e. the
+    /// state unlikely to be reverted by a reorg. Returns `None` if the block is not kept.
+    pub fn confirmed_state(&self, depth: u64) -> Option<T::State> {
+        self.state_at(self.height().saturating_sub(depth))
+    }
+
+    /// Gets the state the pending data leads to once stored in the next block, in order,
+    /// skipping the data that does not apply
+    pub fn pending_state<'a>(&self, pending: impl Iterator<Item = &'a T>) -> T::State
+    where
+        T: 'a,
+    {
+        let height = self.height() + 1;
+        let mut state = self.state.clone();
+        for data in pending {
+            // Invalid data leaves the state untouched
+            let _ = data.apply(height, &mut state);
+        }
+        state
+    }
+
     /// Gets the blockchain's difficulty
     pub fn difficulty(&self) -> usize {
         self.difficulty
     }
 
+    /// Gets the difficulty the block at the given height must satisfy, which differs from the
+    /// blockchain's one if overridden
+    pub fn difficulty_at(&self, height: u64) -> usize {
+        self.consensus
+            .proof_of_work()
+            .map_or(self.difficulty, |pow| pow.difficulty_at(height))
+    }
+
+    /// Gets the work the block at the given height takes: the expected number of hashes computed
+    /// to mine it, or 1 in case blocks are not mined
+    pub fn work_at(&self, height: u64) -> u128 {
+        match self.consensus.proof_of_work() {
+            Some(pow) => 256u128.saturating_pow(pow.difficulty_at(height) as u32),
+            None => 1,
+        }
+    }
+
+    /// Gets the work the blocks from the given height on took, added up, which tells apart the
+    /// blockchain that took the most work to produce whatever its height
+    pub fn work_from(&self, height: u64) -> u128 {
+        self.blocks
+            .iter()
+            .filter(|block| block.id >= height)
+            .fold(0, |total, block| {
+                total.saturating_add(self.work_at(block.id))
+            })
+    }
+
+    /// Checks whether the blockchain is to be kept over the other one, both being valid: the one
+    /// whose blocks took more work from the highest of their first blocks on, which is the height
+    /// unless blocks are mined, wins, the older one winning a tie
+    pub fn is_better_than(&self, other: &Tetherion<T>) -> bool {
+        // Either blockchain may start from a checkpoint
+        let start = self.blocks[0].id.max(other.blocks[0].id);
+        let work = self.work_from(start);
+        let other_work = other.work_from(start);
+        if work == other_work {
+            return self.creation_timestamp() <= other.creation_timestamp();
+        }
+        work > other_work
+    }
+
+    /// Checks if the header's hash is the one of its content and, in case blocks are mined,
+    /// satisfies the difficulty of the header's height. It is cheap enough to be checked before
+    /// anything else about a received block, e.g. before its data is even decoded.
+    pub fn has_valid_work(&self, header: &BlockHeader) -> bool {
+        match self.consensus.proof_of_work() {
+            Some(pow) => header.is_valid(pow.difficulty_at(header.id)),
+            None => header.has_valid_hash(),
+        }
+    }
+
+    /// Overrides the difficulty of the blocks starting at the given height. Returns `false`, not
+    /// overriding anything, if blocks are not mined.
+    pub fn override_difficulty(&mut self, height: u64, difficulty: usize) -> bool {
+        match self.consensus.proof_of_work() {
+            Some(pow) => {
+                self.consensus = Arc::new(pow.clone().with_override(height, difficulty));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gets the rules deciding which blocks are allowed to extend the blockchain
+    pub fn consensus(&self) -> &Arc<dyn ConsensusEngine> {
+        &self.consensus
+    }
+
+    /// Gets the validators allowed to produce the block with the given ID
+    pub fn validators(&self, id: u64) -> Validators {
+        Tetherion::<T>::epoch_validators(self.consensus.as_ref(), &self.epochs, &self.state, id)
+    }
+
+    /// Gets the validators, among the ones of each epoch, allowed to produce the block with the
+    /// given ID, leaving out the producers whose offenses recorded in the state still bar them
+    fn epoch_validators(
+        consensus: &dyn ConsensusEngine,
+        epochs: &BTreeMap<u64, Validators>,
+        state: &T::State,
+        id: u64,
+    ) -> Validators {
+        let exclusion = consensus.exclusion();
+        let excluded = state
+            .offenses()
+            .into_iter()
+            .filter(|(_, height)| *height < id && id <= height.saturating_add(exclusion))
+            .map(|(offender, _)| offender)
+            .collect();
+        consensus
+            .staking()
+            .and_then(|staking| epochs.get(&(id / staking.epoch_length)))
+            .cloned()
+            .unwrap_or_default()
+            .exclude(excluded)
+    }
+
+    /// Gets the ID of the latest block in the blockchain
+    pub fn height(&self) -> u64 {
+        self.blocks
+            .last()
+            .expect("There should be at least one block in the blockchain!")
+            .id
+    }
+
+    /// Drops the blocks preceding the trusted checkpoint block with the given ID and hash, so that
+    /// only the blocks following it get replayed and validated. Returns `None` if there is no such
+    /// block.
+    pub fn trim_to_checkpoint(
+        mut blocks: Vec<Arc<Block<T>>>,
+        height: u64,
+        hash: &str,
+    ) -> Option<Vec<Arc<Block<T>>>> {
+        let index = blocks
+            .iter()
+            .position(|block| block.id == height && block.hash == hash)?;
+        blocks.drain(..index);
+        Some(blocks)
+    }
+
     /// Gets the blockchain's creation timestamp
     pub fn creation_timestamp(&self) -> i64 {
         let genesis_block = self
@@ -74,29 +578,237 @@ impl<T: fmt::Display> Tetherion<T> {
 
     /// Adds a new block to the blockchain
     pub fn add_block(&mut self, block: Block<T>) -> result::Result<(), InvalidBlockError> {
+        self.add_block_with(Arc::new(block), &|block| block.has_valid_hash())
+    }
+
+    /// Adds a new block to the blockchain, checking whether the block's hash matches its content
+    /// with the given function, e.g. to skip hashing blocks validated already
+    pub fn add_block_with(
+        &mut self,
+        block: Arc<Block<T>>,
+        has_valid_hash: &dyn Fn(&Arc<Block<T>>) -> bool,
+    ) -> result::Result<(), InvalidBlockError> {
         let previous_block = self
             .blocks
             .last()
             .expect("There should be at least one block in the blockchain!");
-        match Tetherion::<T>::is_valid_block(previous_block, &block, self.difficulty) {
-            Ok(()) => {
-                self.blocks.push(block);
-                Ok(())
+        let validators = self.validators(block.id);
+        Tetherion::<T>::is_valid_block(
+            previous_block,
+            &block,
+            has_valid_hash,
+            self.consensus.as_ref(),
+            &validators,
+        )?;
+        Tetherion::<T>::apply_block(&block, &mut self.state)?;
+        Tetherion::<T>::end_epoch(
+            self.consensus.as_ref(),
+            &mut self.epochs,
+            &block,
+            &self.state,
+        );
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    /// Rebuilds the state and the validators of each epoch from scratch by replaying all the
+    /// blocks, validating each of them along the way. Returns whether the rebuilt ones differ
+    /// from the ones they replace; nothing gets replaced if a block turns out to be invalid.
+    pub fn reindex(&mut self) -> result::Result<bool, InvalidBlockError> {
+        let consensus = self.consensus.as_ref();
+        let mut state = self.initial_state.clone();
+        let mut epochs = BTreeMap::new();
+
+        let first = self
+            .blocks
+            .first()
+            .expect("There should be at least one block in the blockchain!");
+        Tetherion::<T>::apply_first(first, &mut state)?;
+        Tetherion::<T>::end_epoch(consensus, &mut epochs, first, &state);
+
+        for pair in self.blocks.windows(2) {
+            let (previous_block, block) = (&pair[0], &pair[1]);
+            let validators = Tetherion::<T>::epoch_validators(consensus, &epochs, &state, block.id);
+            Tetherion::<T>::is_valid_block(
+                previous_block,
+                block,
+                &|block| block.has_valid_hash(),
+                consensus,
+                &validators,
+            )?;
+            Tetherion::<T>::apply_block(block, &mut state)?;
+            Tetherion::<T>::end_epoch(consensus, &mut epochs, block, &state);
+        }
+
+        let changed = state != self.state || epochs != self.epochs;
+        self.state = state;
+        self.epochs = epochs;
+        Ok(changed)
+    }
+
+    /// Validates every block of the blockchain regarding the previous one and replays their data,
+    /// carrying on past the invalid blocks so that all of them get reported. Returns the position
+    /// of each invalid block in the blockchain along with the rule it violates, the oldest first.
+    pub fn verify(&self) -> Vec<(usize, InvalidBlockError)> {
+        let consensus = self.consensus.as_ref();
+        let mut state = self.initial_state.clone();
+        let mut epochs = BTreeMap::new();
+        let mut violations = Vec::new();
+
+        let first = self
+            .blocks
+            .first()
+            .expect("There should be at least one block in the blockchain!");
+        if !first.has_valid_hash() {
+            violations.push((0, InvalidBlockError::invalid_hash(first)));
+        }
+        if let Err(err) = Tetherion::<T>::apply_first(first, &mut state) {
+            violations.push((0, err));
+        }
+        Tetherion::<T>::end_epoch(consensus, &mut epochs, first, &state);
+
+        for (position, pair) in self.blocks.windows(2).enumerate() {
+            let (previous_block, block) = (&pair[0], &pair[1]);
+            let validators = Tetherion::<T>::epoch_validators(consensus, &epochs, &state, block.id);
+            let validity = Tetherion::<T>::is_valid_block(
+                previous_block,
+                block,
+                &|block| block.has_valid_hash(),
+                consensus,
+                &validators,
+            )
+            .and_then(|()| Tetherion::<T>::apply_block(block, &mut state));
+            if let Err(err) = validity {
+                violations.push((position + 1, err));
+            }
+            Tetherion::<T>::end_epoch(consensus, &mut epochs, block, &state);
+        }
+        violations
+    }
+
+    /// Checks the block against all the consensus rules for following up the latest block, without
+    /// adding it, returning every rule the block violates instead of stopping at the first one.
+    /// The consensus engine's rules are only checked if the block's hash matches its content.
+    pub fn check_block(&self, block: &Block<T>) -> Vec<InvalidBlockError> {
+        let previous_block = self
+            .blocks
+            .last()
+            .expect("There should be at least one block in the blockchain!");
+        let mut violations = Vec::new();
+
+        if block.id != previous_block.id + 1 {
+            violations.push(InvalidBlockError::InvalidBlockId {
+                id: block.id,
+                previous_id: previous_block.id,
+            });
+        }
+        if block.previous_hash != previous_block.hash {
+            violations.push(InvalidBlockError::invalid_previous_hash(
+                block.id,
+                &previous_block.hash,
+                &block.previous_hash,
+            ));
+        }
+        if !block.has_valid_hash() {
+            violations.push(InvalidBlockError::invalid_hash(block));
+        } else if let Err(err) = self.consensus.verify(
+            &previous_block.header(),
+            &block.header(),
+            &self.validators(block.id),
+        ) {
+            violations.push(err);
+        }
+        let mut state = self.state.clone();
+        if let Err(err) = Tetherion::<T>::apply_block(block, &mut state) {
+            violations.push(err);
+        }
+        violations
+    }
+
+    /// Keeps only the given number of the oldest blocks, at least one, and rebuilds the state and
+    /// the validators of each epoch from them
+    pub fn truncate(&mut self, len: usize) -> result::Result<(), InvalidBlockError> {
+        self.blocks.truncate(len.max(1));
+        self.reindex().map(|_| ())
+    }
+
+    /// Freezes the stakes of the validators of the next epoch in case the block ends an epoch
+    fn end_epoch(
+        consensus: &dyn ConsensusEngine,
+        epochs: &mut BTreeMap<u64, Validators>,
+        block: &Block<T>,
+        state: &T::State,
+    ) {
+        if let Some(staking) = consensus.staking() {
+            let next_id = block.id + 1;
+            if next_id.is_multiple_of(staking.epoch_length) {
+                let validators = Validators::new(block.hash.clone(), state.stakes(&staking.token));
+                epochs.insert(next_id / staking.epoch_length, validators);
             }
-            Err(err) => return Err(err),
         }
     }
 
-    /// Checks if blockchain is valid by validating each of the blocks regarding the previous block
-    pub fn is_valid(&self) -> result::Result<(), InvalidBlockError> {
+    /// Applies the data stored in the first block of the blockchain to the initial state. The
+    /// data of a checkpoint block is part of the initial state already, so only the data of the
+    /// genesis block gets applied.
+    fn apply_first(
+        first: &Block<T>,
+        state: &mut T::State,
+    ) -> result::Result<(), InvalidBlockError> {
+        match first.id {
+            0 => Tetherion::<T>::apply_block(first, state),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the data stored in the block to the state
+    fn apply_block(
+        block: &Block<T>,
+        state: &mut T::State,
+    ) -> result::Result<(), InvalidBlockError> {
+        let weight = block.data().weight();
+        if weight > MAX_BLOCK_WEIGHT {
+            return Err(InvalidBlockError::Overweight {
+                id: block.id,
+                weight,
+            });
+        }
+        block
+            .data()
+            .apply(block.id, state)
+            .map_err(|err| InvalidBlockError::InvalidData {
+                id: block.id,
+                reason: err.to_string(),
+            })
+    }
+
+    /// Checks if blockchain is valid by validating each of the blocks regarding the previous block,
+    /// checking whether the hashes of the blocks match their content with the given function
+    pub fn is_valid_with(
+        &self,
+        has_valid_hash: &dyn Fn(&Arc<Block<T>>) -> bool,
+    ) -> result::Result<(), InvalidBlockError> {
         // Blockchain has at least genesis block
-        debug_assert!(self.blocks.len() >= 1);
+        debug_assert!(!self.blocks.is_empty());
+
+        // The first block has no previous block to be checked against, but its content still has
+        // to match its hash
+        let first = &self.blocks[0];
+        if !has_valid_hash(first) {
+            return Err(InvalidBlockError::invalid_hash(first.as_ref()));
+        }
 
         for i in 1..self.blocks.len() {
             let previous_block = self.blocks.get(i - 1).expect("Block should exist!");
             let current_block = self.blocks.get(i).expect("Block should exist!");
 
-            match Tetherion::<T>::is_valid_block(previous_block, current_block, self.difficulty) {
+            match Tetherion::<T>::is_valid_block(
+                previous_block,
+                current_block,
+                has_valid_hash,
+                self.consensus.as_ref(),
+                &self.validators(current_block.id),
+            ) {
                 Ok(()) => continue,
                 Err(err) => return Err(err),
             };
@@ -108,8 +820,10 @@ impl<T: fmt::Display> Tetherion<T> {
     /// Checks if the block to be added is valid regarding the previous block in the blockchain
     fn is_valid_block(
         previous_block: &Block<T>,
-        block: &Block<T>,
-        difficulty: usize,
+        block: &Arc<Block<T>>,
+        has_valid_hash: &dyn Fn(&Arc<Block<T>>) -> bool,
+        consensus: &dyn ConsensusEngine,
+        validators: &Validators,
     ) -> result::Result<(), InvalidBlockError> {
         if block.id != previous_block.id + 1 {
             return Err(InvalidBlockError::InvalidBlockId {
@@ -117,20 +831,21 @@ impl<T: fmt::Display> Tetherion<T> {
                 previous_id: previous_block.id,
             });
         } else if block.previous_hash != previous_block.hash {
-            return Err(InvalidBlockError::InvalidPreviousHash { id: block.id });
-        } else if !block.is_valid(difficulty) {
-            return Err(InvalidBlockError::InvalidDifficulty {
-                id: block.id,
-                difficulty: difficulty,
-            });
+            return Err(InvalidBlockError::invalid_previous_hash(
+                block.id,
+                &previous_block.hash,
+                &block.previous_hash,
+            ));
+        } else if !has_valid_hash(block) {
+            return Err(InvalidBlockError::invalid_hash(block));
         }
-        Ok(())
+        consensus.verify(&previous_block.header(), &block.header(), validators)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use {super::*, crate::payload::DEFAULT_CHAIN_ID};
 
     #[test]
     fn creation() {
@@ -145,5 +860,476 @@ mod tests {
             "Only genesis block should be present in the blockchain on its creation"
         );
         assert_eq!(tetherion.blocks.last().unwrap().data(), GENESIS_DATA);
+        assert_eq!(tetherion.work_from(0), 256 * 256);
+        assert_eq!(tetherion.work_from(1), 0);
+
+        // A blockchain has at least a genesis block, whichever way it gets created
+        assert_eq!(
+            Tetherion::<String>::from_blocks(Vec::new(), DIFFICULTY).err(),
+            Some(InvalidBlockError::EmptyChain)
+        );
+        assert!(
+            serde_json::from_str::<Tetherion<String>>(r#"{"blocks":[],"difficulty":2}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn verify_and_truncate() {
+        let mut tetherion = Tetherion::<String>::new(String::from("genesis"), 1);
+        for id in 1..5 {
+            let previous_hash = tetherion.blocks.last().unwrap().hash.clone();
+            let block = Block::new(id, &previous_hash, format!("block {}", id), 1);
+            tetherion.add_block(block).unwrap();
+        }
+        assert!(tetherion.verify().is_empty());
+
+        let tampered = |block: &Block<String>| {
+            let mut json = serde_json::to_value(block).unwrap();
+            json["data"] = serde_json::json!("tampered");
+            Arc::new(serde_json::from_value::<Block<String>>(json).unwrap())
+        };
+        tetherion.blocks[2] = tampered(&tetherion.blocks[2]);
+        tetherion.blocks[4] = tampered(&tetherion.blocks[4]);
+        assert_eq!(
+            tetherion.verify(),
+            vec![
+                (2, InvalidBlockError::invalid_hash(&tetherion.blocks[2])),
+                (4, InvalidBlockError::invalid_hash(&tetherion.blocks[4]))
+            ]
+        );
+        let err = InvalidBlockError::invalid_hash(&tetherion.blocks[2]);
+        assert_eq!(err.id(), 2);
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "kind": "invalid_hash",
+                "id": 2,
+                "expected": tetherion.blocks[2].compute_hash(),
+                "actual": tetherion.blocks[2].hash,
+            })
+        );
+        assert_ne!(tetherion.blocks[2].compute_hash(), tetherion.blocks[2].hash);
+
+        tetherion.truncate(2).unwrap();
+        assert_eq!(tetherion.height(), 1);
+        assert!(tetherion.verify().is_empty());
+
+        // The genesis block's data is checked too, even though its hash is the expected one
+        tetherion.blocks[0] = tampered(&tetherion.blocks[0]);
+        assert_eq!(
+            tetherion.is_valid_with(&|block| block.has_valid_hash()),
+            Err(InvalidBlockError::invalid_hash(&tetherion.blocks[0]))
+        );
+    }
+
+    #[test]
+    fn check_block() {
+        let mut tetherion = Tetherion::<String>::new(String::from("genesis"), 1);
+        let genesis_hash = tetherion.blocks[0].hash.clone();
+        let block = Block::new(1, &genesis_hash, String::from("block 1"), 1);
+        assert!(tetherion.check_block(&block).is_empty());
+        assert!(tetherion.has_valid_work(&block.header()));
+        let unworked = (0..)
+            .map(|nonce| Block::with_nonce(1, &genesis_hash, 0, nonce, String::new()))
+            .find(|block| !block.hash.starts_with("00"))
+            .unwrap();
+        assert!(unworked.has_valid_hash());
+        assert!(!tetherion.has_valid_work(&unworked.header()));
+        tetherion.add_block(block.clone()).unwrap();
+
+        // Resubmitting the block breaks the linkage twice over, tampering with it breaks its hash
+        let mut json = serde_json::to_value(&block).unwrap();
+        json["data"] = serde_json::json!("tampered");
+        let tampered = serde_json::from_value::<Block<String>>(json).unwrap();
+        assert_eq!(
+            tetherion.check_block(&tampered),
+            vec![
+                InvalidBlockError::InvalidBlockId {
+                    id: 1,
+                    previous_id: 1
+                },
+                InvalidBlockError::invalid_previous_hash(1, &block.hash, &genesis_hash),
+                InvalidBlockError::invalid_hash(&tampered),
+            ]
+        );
+    }
+
+    #[test]
+    fn trim_to_checkpoint() {
+        const DIFFICULTY: usize = 1;
+
+        let mut tetherion = Tetherion::<String>::new(String::from("genesis"), DIFFICULTY);
+        for id in 1..4 {
+            let previous_hash = tetherion.blocks.last().unwrap().hash.clone();
+            let block = Block::new(id, &previous_hash, format!("block {}", id), DIFFICULTY);
+            tetherion.add_block(block).unwrap();
+        }
+
+        let checkpoint = tetherion.blocks[2].clone();
+        let blocks = tetherion.blocks.clone();
+        assert!(Arc::ptr_eq(&blocks[2], &checkpoint));
+        assert!(Tetherion::trim_to_checkpoint(blocks.clone(), 2, "other_hash").is_none());
+
+        let trimmed = Tetherion::trim_to_checkpoint(blocks, 2, &checkpoint.hash).unwrap();
+        let trimmed = Tetherion::<String>::from_blocks(trimmed, DIFFICULTY).unwrap();
+        assert_eq!(trimmed.blocks.len(), 2);
+        assert_eq!(trimmed.height(), 3);
+        assert!(trimmed
+            .is_valid_with(&|block| block.has_valid_hash())
+            .is_ok());
+    }
+
+    #[test]
+    fn checkpoint_state() {
+        use {
+            crate::{
+                payload::{Payload, TokenOp},
+                state::State,
+            },
+            libp2p::identity::ed25519,
+        };
+
+        const DIFFICULTY: usize = 1;
+
+        let keys = ed25519::Keypair::generate();
+        let to = hex::encode(ed25519::Keypair::generate().public().encode());
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let create = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 100,
+            mintable: false,
+        };
+        let transfer = |amount| TokenOp::Transfer {
+            token: String::from("TTH"),
+            to: to.clone(),
+            amount,
+        };
+        let ops = [create, transfer(40), transfer(10)];
+        for (nonce, op) in ops.into_iter().enumerate() {
+            let previous_hash = tetherion.blocks.last().unwrap().hash.clone();
+            let data = Payload::token(DEFAULT_CHAIN_ID, op, nonce as u64, &keys);
+            let block = Block::new(nonce as u64 + 1, &previous_hash, data, DIFFICULTY);
+            tetherion.add_block(block).unwrap();
+        }
+
+        // The blocks following the checkpoint spend the tokens created before it
+        let checkpoint = (*tetherion.blocks[1]).clone();
+        let state = tetherion.state_at(1).unwrap().view(1);
+        let state = State::from_view(
+            Arc::default(),
+            serde_json::from_value(serde_json::to_value(state).unwrap()).unwrap(),
+        );
+        let mut local =
+            Tetherion::from_checkpoint(checkpoint.clone(), DIFFICULTY, state.clone()).unwrap();
+        for block in &tetherion.blocks[2..] {
+            local.add_block((**block).clone()).unwrap();
+        }
+        assert_eq!(local.state(), tetherion.state());
+        assert!(matches!(local.reindex(), Ok(false)));
+        assert!(local.verify().is_empty());
+        assert_eq!(local.state_at(1), Some(state.clone()));
+        assert_eq!(local.state_at(2), tetherion.state_at(2));
+        local.truncate(2).unwrap();
+        assert_eq!(local.state().balance(&to, "TTH"), 40);
+
+        // Remote blockchains are replayed from the checkpoint on, on top of its state
+        let blocks = Tetherion::trim_to_checkpoint(tetherion.blocks.clone(), 1, &checkpoint.hash);
+        let remote =
+            Tetherion::from_blocks_with(blocks.unwrap(), DIFFICULTY, local.initial_state().clone())
+                .unwrap();
+        assert_eq!(remote.state(), tetherion.state());
+
+        let mut empty =
+            Tetherion::from_checkpoint(checkpoint, DIFFICULTY, State::default()).unwrap();
+        assert!(matches!(
+            empty.add_block((*tetherion.blocks[2]).clone()),
+            Err(InvalidBlockError::InvalidData { id: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn reindex() {
+        use {
+            crate::payload::{Payload, TokenOp},
+            libp2p::identity::ed25519,
+        };
+
+        const DIFFICULTY: usize = 1;
+
+        let keys = ed25519::Keypair::generate();
+        let address = hex::encode(keys.public().encode());
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let op = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 100,
+            mintable: false,
+        };
+        let genesis_hash = tetherion.blocks[0].hash.clone();
+        let block = Block::new(
+            1,
+            &genesis_hash,
+            Payload::token(DEFAULT_CHAIN_ID, op, 0, &keys),
+            DIFFICULTY,
+        );
+        tetherion.add_block(block).unwrap();
+        assert!(matches!(tetherion.reindex(), Ok(false)));
+        assert_eq!(tetherion.state_at(0).unwrap().balance(&address, "TTH"), 0);
+        assert_eq!(tetherion.state_at(1).as_ref(), Some(tetherion.state()));
+        assert!(tetherion.state_at(2).is_none());
+        let genesis_state = tetherion.state_at(0).unwrap();
+        assert_eq!(
+            tetherion.replay_state(genesis_state.clone(), 0, 1).as_ref(),
+            Some(tetherion.state())
+        );
+        assert!(tetherion.replay_state(genesis_state, 1, 0).is_none());
+        assert_eq!(
+            tetherion
+                .confirmed_state(1)
+                .unwrap()
+                .balance(&address, "TTH"),
+            0
+        );
+        assert_eq!(
+            tetherion.confirmed_state(0).as_ref(),
+            Some(tetherion.state())
+        );
+
+        let to = hex::encode(ed25519::Keypair::generate().public().encode());
+        let transfer = |amount, nonce| {
+            let op = TokenOp::Transfer {
+                token: String::from("TTH"),
+                to: to.clone(),
+                amount,
+            };
+            Payload::token(DEFAULT_CHAIN_ID, op, nonce, &keys)
+        };
+        // The overspending transfer is skipped, the following one still applies
+        let pending = [transfer(40, 1), transfer(100, 2), transfer(10, 2)];
+        let state = tetherion.pending_state(pending.iter());
+        assert_eq!(state.balance(&address, "TTH"), 50);
+        assert_eq!(state.balance(&to, "TTH"), 50);
+
+        tetherion.state = Default::default();
+        assert!(matches!(tetherion.reindex(), Ok(true)));
+        assert_eq!(tetherion.state().balance(&address, "TTH"), 100);
+
+        Arc::make_mut(&mut tetherion.blocks[1]).previous_hash = String::from("00");
+        assert!(matches!(
+            tetherion.reindex(),
+            Err(InvalidBlockError::InvalidPreviousHash { id: 1, .. })
+        ));
+        assert_eq!(tetherion.state().balance(&address, "TTH"), 100);
+    }
+
+    #[test]
+    fn initial_state() {
+        use crate::{
+            payload::Payload,
+            registry::{PayloadRegistry, PayloadValidator},
+            state::State,
+        };
+
+        struct NoReserved;
+
+        impl PayloadValidator for NoReserved {
+            fn validate(&self, payload: &Payload, _: u64, _: &State) -> Result<(), String> {
+                match payload {
+                    Payload::Text(text) if text == "reserved" => Err(String::from("reserved")),
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        let mut registry = PayloadRegistry::default();
+        registry.add_validator(Arc::new(NoReserved));
+        let initial_state = State::new(Arc::new(registry));
+
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        let genesis_hash = tetherion.blocks[0].hash.clone();
+        let reserved = Block::new(1, &genesis_hash, Payload::Text(String::from("reserved")), 1);
+        tetherion.add_block(reserved.clone()).unwrap();
+
+        // The blocks stored before the rules were set get validated with them
+        assert!(matches!(
+            tetherion.with_initial_state(initial_state.clone()),
+            Err(InvalidBlockError::InvalidData { id: 1, .. })
+        ));
+
+        let mut tetherion = Tetherion::new(Payload::Text(String::from("genesis")), 1)
+            .with_initial_state(initial_state)
+            .unwrap();
+        assert!(tetherion.add_block(reserved.clone()).is_err());
+
+        // The remote blockchains get validated with the same rules
+        let blocks = vec![tetherion.blocks[0].clone(), Arc::new(reserved)];
+        let initial_state = tetherion.initial_state().clone();
+        assert!(Tetherion::from_blocks_with(blocks, 1, initial_state).is_err());
+    }
+
+    #[test]
+    fn signatures_bound_to_chain() {
+        use {
+            crate::{
+                payload::{Payload, TokenOp},
+                registry::PayloadRegistry,
+                state::State,
+            },
+            libp2p::identity::ed25519,
+        };
+
+        let chain = |chain_id: &str| {
+            let mut registry = PayloadRegistry::default();
+            registry.set_chain_id(chain_id);
+            Tetherion::new(Payload::Text(String::from("genesis")), 1)
+                .with_initial_state(State::new(Arc::new(registry)))
+                .unwrap()
+        };
+        let (mut a, mut b) = (chain("a"), chain("b"));
+
+        let keys = ed25519::Keypair::generate();
+        let op = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 100,
+            mintable: false,
+        };
+        let registration = Payload::register("a", String::from("name"), String::new(), 0, &keys);
+        for data in [Payload::token("a", op, 0, &keys), registration] {
+            let extend = |tetherion: &Tetherion<Payload>| {
+                let previous_hash = &tetherion.blocks.last().unwrap().hash;
+                Block::new(tetherion.height() + 1, previous_hash, data.clone(), 1)
+            };
+            a.add_block(extend(&a)).unwrap();
+            assert!(matches!(
+                b.add_block(extend(&b)),
+                Err(InvalidBlockError::InvalidData { id: 1, .. })
+            ));
+        }
+
+        // Neither does chain A's history replay on chain B
+        let blocks = a.blocks.clone();
+        assert!(Tetherion::from_blocks_with(blocks, 1, b.initial_state().clone()).is_err());
+    }
+
+    #[test]
+    fn stake_epochs() {
+        use {
+            crate::{
+                consensus::{ProofOfStake, Staking},
+                payload::{Payload, TokenOp},
+            },
+            libp2p::identity::ed25519,
+        };
+
+        let keys = ed25519::Keypair::generate();
+        let address = hex::encode(keys.public().encode());
+        let staking = Staking {
+            token: String::from("TTH"),
+            epoch_length: 2,
+        };
+        let genesis = Block::with_nonce(0, "", 0, 0, Payload::Text(String::from("genesis")));
+        let mut tetherion = Tetherion::from_genesis(genesis.clone(), 0)
+            .unwrap()
+            .with_consensus(Arc::new(ProofOfStake::new(
+                staking,
+                1,
+                vec![address.clone()],
+            )));
+
+        let op = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 100,
+            mintable: false,
+        };
+        let data = Payload::token(DEFAULT_CHAIN_ID, op, 0, &keys);
+        let timestamp = chrono::Utc::now().timestamp();
+        let other = ed25519::Keypair::generate();
+        let block = Block::sealed(1, &genesis.hash, timestamp, data.clone(), &other);
+        assert!(matches!(
+            tetherion.add_block(block),
+            Err(InvalidBlockError::InvalidSeal { id: 1, .. })
+        ));
+
+        let block = Block::sealed(1, &genesis.hash, timestamp, data, &keys);
+        tetherion.add_block(block).unwrap();
+        assert_eq!(tetherion.validators(1), Validators::default());
+        assert_eq!(
+            tetherion.validators(2),
+            Validators::new(tetherion.blocks[1].hash.clone(), vec![(address, 100)])
+        );
+
+        let json = serde_json::to_string(&tetherion).unwrap();
+        let remote: Tetherion<Payload> = serde_json::from_str(&json).unwrap();
+        let remote = remote.with_consensus(tetherion.consensus.clone());
+        assert_eq!(remote.validators(2), tetherion.validators(2));
+        assert!(remote
+            .is_valid_with(&|block| block.has_valid_hash())
+            .is_ok());
+    }
+
+    #[test]
+    fn repeated_offenses() {
+        use {
+            crate::{consensus::ProofOfAuthority, evidence::Evidence, payload::Payload},
+            libp2p::identity::ed25519,
+        };
+
+        let offender = ed25519::Keypair::generate();
+        let other = ed25519::Keypair::generate();
+        let address = |keys: &ed25519::Keypair| hex::encode(keys.public().encode());
+        // The offender is scheduled in every even slot, the other authority taking its turns
+        // while it is left out, i.e. for the 2 blocks following the evidence
+        let engine = ProofOfAuthority::new(vec![address(&offender), address(&other)], 1);
+        let genesis = Block::with_nonce(0, "", 0, 0, Payload::Text(String::from("genesis")));
+        let mut tetherion = Tetherion::from_genesis(genesis, 0)
+            .unwrap()
+            .with_consensus(Arc::new(engine.with_exclusion(1)));
+
+        let evidence = |height: u64| {
+            let header = |data: &str| {
+                let data = Payload::Text(data.to_owned());
+                Block::sealed(height, "", 0, data, &offender).header()
+            };
+            Payload::Evidence(Box::new(Evidence {
+                offender: address(&offender),
+                first: header("first"),
+                second: header("second"),
+            }))
+        };
+        let start = chrono::Utc::now().timestamp() / 2 * 2 - 100;
+        for id in 1..9 {
+            let data = match id {
+                1 => evidence(50),
+                5 => evidence(60),
+                _ => Payload::Text(format!("block {}", id)),
+            };
+            let previous_hash = tetherion.blocks.last().unwrap().hash.clone();
+            let timestamp = start + 2 * id as i64;
+            let block = Block::with_nonce(id, &previous_hash, timestamp, 0, data.clone());
+            let producer = tetherion
+                .consensus
+                .producer(&block.header(), &tetherion.validators(id))
+                .unwrap();
+            let keys = if producer == address(&offender) {
+                &offender
+            } else {
+                &other
+            };
+            let block = Block::sealed(id, &previous_hash, timestamp, data, keys);
+            tetherion.add_block(block).unwrap();
+        }
+        assert_eq!(
+            tetherion.state.offenses(),
+            vec![(address(&offender), 1), (address(&offender), 5)]
+        );
+        assert!(tetherion.validators(3).is_excluded(&address(&offender)));
+        assert!(!tetherion.validators(4).is_excluded(&address(&offender)));
+
+        // The blocks sealed while the first offense barred the offender stay valid after the
+        // second offense
+        assert!(tetherion
+            .is_valid_with(&|block| block.has_valid_hash())
+            .is_ok());
+        assert_eq!(tetherion.reindex(), Ok(false));
+        assert!(tetherion.verify().is_empty());
     }
 }
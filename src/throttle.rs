@@ -0,0 +1,133 @@
+/// Copyright (c) 2022 Tetherion
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// The messages of a kind logged for a peer during the current interval
+#[derive(Debug)]
+struct Window {
+    /// When the interval started
+    start: Instant,
+
+    /// The number of messages logged
+    logged: u32,
+
+    /// The number of messages counted instead of being logged
+    suppressed: u64,
+}
+
+/// Logs repeated errors sparingly, so that a peer flooding the node with invalid data does not
+/// flood the log as well: only the first messages of each kind from each peer are logged during
+/// an interval, the following ones being counted and summarized once the interval is over
+#[derive(Debug)]
+pub struct LogThrottle {
+    /// The number of messages of a kind from a peer logged per interval
+    burst: u32,
+
+    /// The length of the intervals
+    interval: Duration,
+
+    /// The current interval of each peer and kind of message
+    windows: HashMap<(String, &'static str), Window>,
+}
+
+impl LogThrottle {
+    pub fn new(burst: u32, interval: Duration) -> Self {
+        Self {
+            burst,
+            interval,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Logs the message of the given kind from the peer at the given level, unless enough
+    /// messages of the same kind from the peer were logged during the current interval already
+    pub fn log(
+        &mut self,
+        level: log::Level,
+        peer: &str,
+        kind: &'static str,
+        message: &dyn fmt::Display,
+    ) {
+        if self.admit(peer, kind, Instant::now()) {
+            log::log!(level, "{}", message);
+        }
+    }
+
+    /// Logs how many messages got suppressed during the intervals that are over
+    pub fn flush(&mut self) {
+        for ((peer, kind), suppressed) in self.expire(Instant::now()) {
+            log::warn!(
+                "suppressed {} more {} errors from {} in the last {}s",
+                suppressed,
+                kind,
+                peer,
+                self.interval.as_secs()
+            );
+        }
+    }
+
+    /// Checks whether the message of the given kind from the peer gets logged, counting it if not
+    fn admit(&mut self, peer: &str, kind: &'static str, now: Instant) -> bool {
+        let window = self
+            .windows
+            .entry((peer.to_owned(), kind))
+            .or_insert(Window {
+                start: now,
+                logged: 0,
+                suppressed: 0,
+            });
+        if window.logged < self.burst {
+            window.logged += 1;
+            true
+        } else {
+            window.suppressed += 1;
+            false
+        }
+    }
+
+    /// Forgets about the intervals that are over, returning the number of messages suppressed
+    /// during each of them, if any
+    fn expire(&mut self, now: Instant) -> Vec<((String, &'static str), u64)> {
+        let interval = self.interval;
+        let mut expired = Vec::new();
+        self.windows.retain(|key, window| {
+            if now.duration_since(window.start) < interval {
+                return true;
+            }
+            if window.suppressed > 0 {
+                expired.push((key.clone(), window.suppressed));
+            }
+            false
+        });
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppress_repeated_errors() {
+        const INTERVAL: Duration = Duration::from_secs(60);
+
+        let mut throttle = LogThrottle::new(2, INTERVAL);
+        let start = Instant::now();
+        let admitted: Vec<_> = (0..5)
+            .map(|_| throttle.admit("peer", "invalid_hash", start))
+            .collect();
+        assert_eq!(admitted, vec![true, true, false, false, false]);
+        assert!(throttle.admit("peer", "undecodable", start));
+        assert!(throttle.admit("other", "invalid_hash", start));
+
+        assert!(throttle.expire(start + INTERVAL / 2).is_empty());
+        assert_eq!(
+            throttle.expire(start + INTERVAL),
+            vec![((String::from("peer"), "invalid_hash"), 3)]
+        );
+        assert!(throttle.admit("peer", "invalid_hash", start + INTERVAL));
+    }
+}
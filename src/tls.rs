@@ -0,0 +1,137 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{block, config::TlsConfig},
+    std::{
+        fmt,
+        fs::File,
+        io::{self, BufReader},
+        path::Path,
+        sync::Arc,
+    },
+    tokio_rustls::{
+        rustls::{Certificate, PrivateKey, ServerConfig},
+        TlsAcceptor,
+    },
+};
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(io::Error),
+    NoCertificate,
+    NoPrivateKey,
+    Generate(rcgen::Error),
+    Rustls(tokio_rustls::rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsError::Io(err) => write!(f, "Cannot read the certificate or key: {}", err),
+            TlsError::NoCertificate => write!(f, "The certificate file holds no certificate"),
+            TlsError::NoPrivateKey => write!(f, "The key file holds no private key"),
+            TlsError::Generate(err) => {
+                write!(f, "Cannot generate a self-signed certificate: {}", err)
+            }
+            TlsError::Rustls(err) => write!(f, "Invalid certificate or key: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+/// The TLS settings of a server along with the SHA-256 fingerprint of its certificate, in HEX
+/// format, which clients of a self-signed certificate can pin
+pub struct ServerTls {
+    pub acceptor: TlsAcceptor,
+    pub fingerprint: String,
+}
+
+impl ServerTls {
+    /// Loads the configured certificate and key, or else generates a self-signed certificate
+    /// valid for the given host names and addresses
+    pub fn new(config: &TlsConfig, names: Vec<String>) -> Result<Self, TlsError> {
+        let (certs, key) = match (&config.cert_file, &config.key_file) {
+            (Some(cert_file), Some(key_file)) => (load_certs(cert_file)?, load_key(key_file)?),
+            _ => self_signed(names)?,
+        };
+        let fingerprint = hex::encode(block::hash(&certs[0].0));
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(TlsError::Rustls)?;
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            fingerprint,
+        })
+    }
+}
+
+/// Reads the certificate chain from the PEM file, the server's certificate first
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let mut reader = BufReader::new(File::open(path).map_err(TlsError::Io)?);
+    let certs = rustls_pemfile::certs(&mut reader).map_err(TlsError::Io)?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate);
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Reads the first PKCS#8, RSA or EC private key from the PEM file
+fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let mut reader = BufReader::new(File::open(path).map_err(TlsError::Io)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader).map_err(TlsError::Io)? {
+        if let rustls_pemfile::Item::PKCS8Key(key)
+        | rustls_pemfile::Item::RSAKey(key)
+        | rustls_pemfile::Item::ECKey(key) = item
+        {
+            return Ok(PrivateKey(key));
+        }
+    }
+    Err(TlsError::NoPrivateKey)
+}
+
+/// Generates a self-signed certificate valid for the given host names and addresses
+fn self_signed(names: Vec<String>) -> Result<(Vec<Certificate>, PrivateKey), TlsError> {
+    let cert = rcgen::generate_simple_self_signed(names).map_err(TlsError::Generate)?;
+    let der = cert.serialize_der().map_err(TlsError::Generate)?;
+    Ok((
+        vec![Certificate(der)],
+        PrivateKey(cert.serialize_private_key_der()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::fs};
+
+    #[test]
+    fn server_certificates() {
+        let generated = ServerTls::new(&TlsConfig::default(), vec![String::from("localhost")]);
+        assert_eq!(generated.unwrap().fingerprint.len(), 64);
+
+        let dir = std::env::temp_dir().join(format!("tetherion-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("node")]).unwrap();
+        let config = TlsConfig {
+            cert_file: Some(dir.join("cert.pem")),
+            key_file: Some(dir.join("key.pem")),
+        };
+        fs::write(dir.join("cert.pem"), cert.serialize_pem().unwrap()).unwrap();
+        fs::write(dir.join("key.pem"), cert.serialize_private_key_pem()).unwrap();
+        let loaded = ServerTls::new(&config, Vec::new()).unwrap();
+        let der = &load_certs(&dir.join("cert.pem")).unwrap()[0].0;
+        assert_eq!(loaded.fingerprint, hex::encode(block::hash(der)));
+
+        // A certificate file is no key file
+        let config = TlsConfig {
+            key_file: config.cert_file.clone(),
+            ..config
+        };
+        assert!(matches!(
+            ServerTls::new(&config, Vec::new()),
+            Err(TlsError::NoPrivateKey)
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
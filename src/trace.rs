@@ -0,0 +1,54 @@
+/// Copyright (c) 2022 Tetherion
+/// The number of leading characters of a block hash kept in the correlation ID of the block
+const BLOCK_HASH_CHARS: usize = 8;
+
+/// The number of trailing characters of a peer ID kept in the correlation IDs of its sessions
+const PEER_ID_CHARS: usize = 6;
+
+/// Gets the correlation ID of the block with the given hash, the same on every node, so that the
+/// journey of a block through the network can be followed in the logs of all the nodes
+pub fn block(hash: &str) -> String {
+    format!("block-{}", &hash[..hash.len().min(BLOCK_HASH_CHARS)])
+}
+
+/// Hands out the correlation IDs of the sync sessions started by a node, carried along with the
+/// requests and the responses, so that a session can be followed in the logs of both nodes
+#[derive(Debug)]
+pub struct Sessions {
+    /// The end of the node's peer ID, telling the sessions of different nodes apart
+    prefix: String,
+
+    /// The number of sessions started so far
+    started: u64,
+}
+
+impl Sessions {
+    pub fn new(peer_id: &str) -> Self {
+        let start = peer_id.len().saturating_sub(PEER_ID_CHARS);
+        Self {
+            prefix: peer_id[start..].to_owned(),
+            started: 0,
+        }
+    }
+
+    /// Gets the correlation ID of a new sync session
+    pub fn start(&mut self) -> String {
+        self.started += 1;
+        format!("sync-{}-{}", self.prefix, self.started)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correlation_ids() {
+        assert_eq!(block("0123456789abcdef"), "block-01234567");
+        assert_eq!(block("0123"), "block-0123");
+
+        let mut sessions = Sessions::new("12D3KooWAbCdEf");
+        assert_eq!(sessions.start(), "sync-AbCdEf-1");
+        assert_eq!(sessions.start(), "sync-AbCdEf-2");
+    }
+}
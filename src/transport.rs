@@ -0,0 +1,280 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::config::{BandwidthConfig, ConnectionMode},
+    libp2p::{
+        core::{transport::TransportError, Transport},
+        futures::{AsyncRead, AsyncWrite},
+        Multiaddr,
+    },
+    std::{
+        future::Future,
+        io,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{ready, Context, Poll},
+        time::{Duration, Instant},
+    },
+    tokio::time::Sleep,
+};
+
+/// The share of a second's worth of data a capped connection waits for at least before letting
+/// more flow, so that it is not woken up for every few bytes
+const MIN_CHUNK_DIVISOR: f64 = 10.0;
+
+/// Restricts the transport to the directions of the connection mode, so that an outbound-only
+/// node cannot listen, and an inbound-only one cannot dial, whichever part of the node asks for it
+#[derive(Debug, Clone)]
+pub struct Restricted<T> {
+    inner: T,
+    mode: ConnectionMode,
+}
+
+impl<T> Restricted<T> {
+    pub fn new(inner: T, mode: ConnectionMode) -> Self {
+        Self { inner, mode }
+    }
+}
+
+impl<T: Transport> Transport for Restricted<T> {
+    type Output = T::Output;
+    type Error = T::Error;
+    type Listener = T::Listener;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = T::Dial;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        if !self.mode.accepts_inbound() {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        }
+        self.inner.listen_on(addr)
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        if !self.mode.dials_out() {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        }
+        self.inner.dial(addr)
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
+
+/// Caps the rate data flows at in one direction, letting at most a second's worth of data flow
+/// at once after being idle
+#[derive(Debug)]
+pub struct Bucket {
+    /// The rate, in bytes per second
+    rate: f64,
+
+    /// The number of bytes that may flow right away
+    available: f64,
+
+    /// The time the bucket was last refilled at
+    refilled: Instant,
+}
+
+impl Bucket {
+    pub fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            available: rate as f64,
+            refilled: now,
+        }
+    }
+
+    /// Takes out of the bucket at most the wanted number of bytes at the given time, returning
+    /// the number of bytes that may flow, or the time to wait before some may
+    pub fn take(&mut self, wanted: usize, now: Instant) -> Result<usize, Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.available = (self.available + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+        if wanted == 0 {
+            return Ok(0);
+        }
+        let chunk = (wanted as f64).min(self.rate / MIN_CHUNK_DIVISOR).max(1.0);
+        if self.available < chunk {
+            let missing = chunk - self.available;
+            return Err(Duration::from_secs_f64(missing / self.rate));
+        }
+        let taken = (wanted as f64).min(self.available.floor());
+        self.available -= taken;
+        Ok(taken as usize)
+    }
+
+    /// Puts back the bytes taken out of the bucket that did not flow after all
+    pub fn refund(&mut self, bytes: usize) {
+        self.available = (self.available + bytes as f64).min(self.rate);
+    }
+}
+
+/// The upload and download rate caps shared by all the connections to the peers
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    upload: Option<Arc<Mutex<Bucket>>>,
+    download: Option<Arc<Mutex<Bucket>>>,
+}
+
+impl Throttle {
+    pub fn new(config: &BandwidthConfig) -> Self {
+        let bucket = |rate: Option<u64>| {
+            rate.map(|rate| Arc::new(Mutex::new(Bucket::new(rate, Instant::now()))))
+        };
+        Self {
+            upload: bucket(config.upload_rate()),
+            download: bucket(config.download_rate()),
+        }
+    }
+}
+
+/// A connection whose traffic counts towards the rate caps of the throttle, its reads and writes
+/// waiting while the caps are reached
+#[derive(Debug)]
+pub struct Throttled<S> {
+    inner: S,
+    throttle: Throttle,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, throttle: Throttle) -> Self {
+        Self {
+            inner,
+            throttle,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+}
+
+/// Takes the wanted number of bytes out of the bucket, if the direction is capped, waiting until
+/// some may flow; returns the number of bytes that may flow
+fn poll_take(
+    bucket: Option<&Mutex<Bucket>>,
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    wanted: usize,
+    cx: &mut Context<'_>,
+) -> Poll<usize> {
+    let bucket = match bucket {
+        Some(bucket) => bucket,
+        None => return Poll::Ready(wanted),
+    };
+    loop {
+        if let Some(sleep) = delay {
+            ready!(sleep.as_mut().poll(cx));
+            *delay = None;
+        }
+        let taken = bucket
+            .lock()
+            .expect("bucket lock is not poisoned")
+            .take(wanted, Instant::now());
+        match taken {
+            Ok(allowed) => return Poll::Ready(allowed),
+            Err(wait) => *delay = Some(Box::pin(tokio::time::sleep(wait))),
+        }
+    }
+}
+
+/// Puts back into the bucket the bytes taken that did not flow
+fn refund(bucket: Option<&Mutex<Bucket>>, taken: usize, result: &Poll<io::Result<usize>>) {
+    let flowed = match result {
+        Poll::Ready(Ok(flowed)) => *flowed,
+        _ => 0,
+    };
+    if let Some(bucket) = bucket.filter(|_| taken > flowed) {
+        bucket
+            .lock()
+            .expect("bucket lock is not poisoned")
+            .refund(taken - flowed);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let bucket = this.throttle.download.as_deref();
+        let allowed = ready!(poll_take(bucket, &mut this.read_delay, buf.len(), cx));
+        let result = Pin::new(&mut this.inner).poll_read(cx, &mut buf[..allowed]);
+        refund(bucket, allowed, &result);
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let bucket = this.throttle.upload.as_deref();
+        let allowed = ready!(poll_take(bucket, &mut this.write_delay, buf.len(), cx));
+        let result = Pin::new(&mut this.inner).poll_write(cx, &buf[..allowed]);
+        refund(bucket, allowed, &result);
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, libp2p::core::transport::MemoryTransport};
+
+    #[test]
+    fn restrict_directions() {
+        let addr: Multiaddr = "/memory/4001".parse().unwrap();
+        let inbound = Restricted::new(MemoryTransport, ConnectionMode::InboundOnly);
+        assert!(matches!(
+            inbound.clone().dial(addr.clone()),
+            Err(TransportError::MultiaddrNotSupported(_))
+        ));
+        assert!(inbound.listen_on(addr.clone()).is_ok());
+
+        let outbound = Restricted::new(MemoryTransport, ConnectionMode::OutboundOnly);
+        assert!(matches!(
+            outbound.clone().listen_on(addr.clone()),
+            Err(TransportError::MultiaddrNotSupported(_))
+        ));
+        // Nothing listens on the address, but dialing it is attempted
+        assert!(!matches!(
+            outbound.dial(addr),
+            Err(TransportError::MultiaddrNotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn cap_rate() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(1000, start);
+        assert_eq!(bucket.take(600, start), Ok(600));
+        assert_eq!(bucket.take(600, start), Ok(400));
+        // A tenth of a second's worth of data is waited for at least
+        assert_eq!(bucket.take(600, start), Err(Duration::from_millis(100)));
+        assert_eq!(
+            bucket.take(600, start + Duration::from_millis(250)),
+            Ok(250)
+        );
+
+        bucket.refund(50);
+        assert_eq!(
+            bucket.take(600, start + Duration::from_millis(250)),
+            Err(Duration::from_millis(50))
+        );
+        // An idle bucket lets a second's worth of data flow at most
+        assert_eq!(bucket.take(5000, start + Duration::from_secs(10)), Ok(1000));
+        assert_eq!(bucket.take(0, start + Duration::from_secs(10)), Ok(0));
+    }
+}
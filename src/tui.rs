@@ -0,0 +1,369 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        handle::{NodeHandle, NodeStatus},
+        p2p::ChainSnapshot,
+        tetherion::BlockData,
+    },
+    ratatui::{
+        backend::CrosstermBackend,
+        crossterm::{
+            event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+            execute,
+            terminal::{
+                disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+            },
+        },
+        layout::{Constraint, Layout, Rect},
+        style::{Color, Modifier, Style},
+        text::Line,
+        widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table},
+        Frame, Terminal,
+    },
+    std::{
+        collections::VecDeque,
+        fs::File,
+        io::{self, BufRead, BufReader},
+        os::fd::{FromRawFd, RawFd},
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    },
+    tokio::runtime::Runtime,
+};
+
+/// The time waited for a key press before refreshing the dashboard
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The number of latest blocks listed
+const RECENT_BLOCKS: usize = 10;
+
+/// The number of lines of command output kept
+const OUTPUT_LINES: usize = 500;
+
+/// The lines printed by the node, the oldest first
+type Output = Arc<Mutex<VecDeque<String>>>;
+
+/// Checks the result of a libc call, which returns -1 on failure
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// The redirection of stdout and stderr into a pipe, so that the results of the commands and the
+/// log are shown in a panel instead of being written over the dashboard
+struct Capture {
+    /// The original stdout, which the dashboard is drawn on
+    stdout: RawFd,
+
+    /// The original stderr
+    stderr: RawFd,
+}
+
+impl Capture {
+    /// Redirects stdout and stderr, appending the lines written to them to the output
+    fn start(output: Output) -> io::Result<Self> {
+        let mut fds = [0; 2];
+        // SAFETY: the file descriptors are owned by this process and only duplicated, the read
+        // end of the pipe being handed over to the reader thread
+        unsafe {
+            check(libc::pipe(fds.as_mut_ptr()))?;
+            let capture = Self {
+                stdout: check(libc::dup(libc::STDOUT_FILENO))?,
+                stderr: check(libc::dup(libc::STDERR_FILENO))?,
+            };
+            check(libc::dup2(fds[1], libc::STDOUT_FILENO))?;
+            check(libc::dup2(fds[1], libc::STDERR_FILENO))?;
+            libc::close(fds[1]);
+
+            let reader = BufReader::new(File::from_raw_fd(fds[0]));
+            thread::Builder::new()
+                .name(String::from("tetherion-tui-output"))
+                .spawn(move || {
+                    for line in reader.lines().map_while(Result::ok) {
+                        let mut output = output.lock().expect("output lock is not poisoned");
+                        if output.len() == OUTPUT_LINES {
+                            output.pop_front();
+                        }
+                        output.push_back(line);
+                    }
+                })?;
+            Ok(capture)
+        }
+    }
+
+    /// Opens the original stdout for drawing the dashboard
+    fn terminal(&self) -> io::Result<File> {
+        // SAFETY: the duplicate is a valid descriptor owned by the returned file
+        unsafe { Ok(File::from_raw_fd(check(libc::dup(self.stdout))?)) }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        // SAFETY: the original descriptors are still open, being closed only here
+        unsafe {
+            libc::dup2(self.stdout, libc::STDOUT_FILENO);
+            libc::dup2(self.stderr, libc::STDERR_FILENO);
+            libc::close(self.stdout);
+            libc::close(self.stderr);
+        }
+    }
+}
+
+/// The state of the dashboard
+struct Dashboard {
+    /// The latest state of the node, if it could be fetched
+    status: Option<NodeStatus>,
+
+    /// The latest snapshot of the local blockchain
+    snapshot: Arc<ChainSnapshot>,
+
+    /// The lines printed by the node
+    output: Output,
+
+    /// The command being typed
+    input: String,
+}
+
+/// Formats the hashrate with a unit prefix
+fn format_hashrate(hashrate: f64) -> String {
+    let units = ["H/s", "kH/s", "MH/s", "GH/s"];
+    let mut value = hashrate;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < units.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+/// Shortens the hash to its first characters
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(16)]
+}
+
+impl Dashboard {
+    fn draw(&self, frame: &mut Frame) {
+        let [top, middle, bottom, input] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Min(8),
+            Constraint::Percentage(35),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+        let [chain, sync, mining] = Layout::horizontal([Constraint::Ratio(1, 3); 3]).areas(top);
+        let [blocks, peers] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(middle);
+
+        self.draw_chain(frame, chain);
+        self.draw_sync(frame, sync);
+        self.draw_mining(frame, mining);
+        self.draw_blocks(frame, blocks);
+        self.draw_peers(frame, peers);
+        self.draw_output(frame, bottom);
+
+        let prompt = Paragraph::new(format!("> {}", self.input)).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Command (Enter runs it, Esc quits)"),
+        );
+        frame.render_widget(prompt, input);
+        frame.set_cursor_position((input.x + 3 + self.input.len() as u16, input.y + 1));
+    }
+
+    fn draw_chain(&self, frame: &mut Frame, area: Rect) {
+        let best = self.snapshot.best();
+        let mut lines = vec![
+            Line::from(format!("Height: {}", best.height)),
+            Line::from(format!("Tip: {}", short_hash(&best.hash))),
+        ];
+        if let Some(finalized) = &self.snapshot.finalized {
+            lines.push(Line::from(format!("Finalized: {}", finalized.height)));
+        }
+        let paragraph =
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Chain"));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_sync(&self, frame: &mut Frame, area: Rect) {
+        let lines = match &self.status {
+            Some(status) => vec![
+                Line::from(format!(
+                    "Synced up to block {}{}",
+                    status.sync.best.height,
+                    if status.sync.light { " (light)" } else { "" }
+                )),
+                Line::from(format!("Receiving: {}", status.sync.receiving)),
+                Line::from(format!("Validating: {}", status.sync.validating)),
+            ],
+            None => vec![Line::from("The node is not running")],
+        };
+        let paragraph =
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Sync"));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_mining(&self, frame: &mut Frame, area: Rect) {
+        let lines = match &self.status {
+            Some(status) => vec![
+                Line::from(format!(
+                    "Hashrate: {}",
+                    status
+                        .hashrate
+                        .map_or_else(|| String::from("-"), format_hashrate)
+                )),
+                Line::from(format!("Mempool: {} payloads", status.mempool)),
+            ],
+            None => Vec::new(),
+        };
+        let paragraph =
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Mining"));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_blocks(&self, frame: &mut Frame, area: Rect) {
+        let rows = self
+            .snapshot
+            .tetherion
+            .blocks()
+            .iter()
+            .rev()
+            .take(RECENT_BLOCKS)
+            .map(|block| {
+                let time = chrono::DateTime::from_timestamp(block.timestamp(), 0)
+                    .map_or_else(String::new, |time| time.format("%H:%M:%S").to_string());
+                Row::new(vec![
+                    Cell::from(block.id.to_string()),
+                    Cell::from(short_hash(&block.hash).to_owned()),
+                    Cell::from(time),
+                    Cell::from(block.data().tx_ids().len().to_string()),
+                ])
+            });
+        let header = Row::new(vec!["Height", "Hash", "Time", "Txs"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(8),
+                Constraint::Length(18),
+                Constraint::Length(10),
+                Constraint::Length(5),
+            ],
+        )
+        .header(header)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Recent blocks"),
+        );
+        frame.render_widget(table, area);
+    }
+
+    fn draw_peers(&self, frame: &mut Frame, area: Rect) {
+        let peers = self.status.as_ref().map_or(&[][..], |status| &status.peers);
+        let items: Vec<_> = peers
+            .iter()
+            .map(|(peer, latency)| {
+                let latency = latency.map_or_else(
+                    || String::from("-"),
+                    |latency| format!("{} ms", latency.as_millis()),
+                );
+                ListItem::new(format!("{} {:>8}", short_hash(peer), latency))
+            })
+            .collect();
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Peers ({})", peers.len())),
+        );
+        frame.render_widget(list, area);
+    }
+
+    fn draw_output(&self, frame: &mut Frame, area: Rect) {
+        let output = self.output.lock().expect("output lock is not poisoned");
+        let shown = area.height.saturating_sub(2) as usize;
+        let lines: Vec<_> = output
+            .iter()
+            .skip(output.len().saturating_sub(shown))
+            .map(|line| Line::from(line.as_str()))
+            .collect();
+        let paragraph = Paragraph::new(lines)
+            .style(Style::default().fg(Color::Gray))
+            .block(Block::default().borders(Borders::ALL).title("Output"));
+        frame.render_widget(paragraph, area);
+    }
+}
+
+/// Shows the dashboard of the node, refreshed live, until the user quits. The commands typed in
+/// the input bar are executed by the node, their results shown in the output panel.
+pub fn run(runtime: &Runtime, node: &NodeHandle) -> io::Result<()> {
+    let output = Output::default();
+    let capture = Capture::start(output.clone())?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(capture.terminal()?))?;
+    enable_raw_mode()?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+
+    let mut dashboard = Dashboard {
+        status: None,
+        snapshot: node.snapshot(),
+        output,
+        input: String::new(),
+    };
+    let result = (|| loop {
+        dashboard.status = runtime.block_on(node.status()).ok();
+        dashboard.snapshot = node.snapshot();
+        terminal.draw(|frame| dashboard.draw(frame))?;
+
+        if !event::poll(REFRESH_INTERVAL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Char(c) => dashboard.input.push(c),
+            KeyCode::Backspace => {
+                dashboard.input.pop();
+            }
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut dashboard.input);
+                if !line.trim().is_empty() {
+                    println!("> {}", line);
+                    if let Err(err) = node.execute(line.trim()) {
+                        eprintln!("{}", err);
+                    }
+                }
+            }
+            _ => {}
+        }
+    })();
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    drop(terminal);
+    drop(capture);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_hashrates() {
+        assert_eq!(format_hashrate(12.0), "12.0 H/s");
+        assert_eq!(format_hashrate(12_345.0), "12.3 kH/s");
+        assert_eq!(format_hashrate(3.5e12), "3500.0 GH/s");
+    }
+}
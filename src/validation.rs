@@ -0,0 +1,571 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::Block,
+        cache::{CacheStats, HashCache},
+        config::{CheckpointConfig, ValidationConfig},
+        consensus::ConsensusEngine,
+        payload::Payload,
+        priority::{Priority, PriorityQueue},
+        state::State,
+        tetherion::{InvalidBlockError, Tetherion},
+    },
+    libp2p::PeerId,
+    std::{
+        collections::BTreeMap,
+        fmt,
+        sync::{Arc, Condvar, Mutex},
+        thread,
+    },
+    tokio::sync::mpsc,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFullError;
+
+impl fmt::Display for QueueFullError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The validation queue is full")
+    }
+}
+
+impl std::error::Error for QueueFullError {}
+
+/// The reason the data received from a peer got dropped during validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    /// The kind of the violated rule, the same for all the data violating it
+    pub kind: &'static str,
+
+    /// The violated rule, readable by humans
+    pub reason: String,
+
+    /// The block validation error the data got rejected with, along with its context, if any
+    pub error: Option<InvalidBlockError>,
+}
+
+impl From<InvalidBlockError> for Rejected {
+    fn from(err: InvalidBlockError) -> Self {
+        Self {
+            kind: err.kind(),
+            reason: err.to_string(),
+            error: Some(err),
+        }
+    }
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// The data received from a peer, to be validated off the node's event loop
+pub enum Job {
+    /// A block gossiped by a peer
+    Block(Block<Payload>),
+
+    /// A remote blockchain sent by a peer
+    Chain {
+        /// The blocks of the blockchain
+        blocks: Vec<Arc<Block<Payload>>>,
+
+        /// The difficulty of the blockchain
+        difficulty: usize,
+
+        /// The difficulty of the local blockchain, which the remote one must have as well
+        local_difficulty: usize,
+
+        /// The hash of the local genesis block, which the remote blockchain must start with, if
+        /// checked
+        genesis: Option<String>,
+
+        /// The rules the blockchain is validated with
+        consensus: Arc<dyn ConsensusEngine>,
+
+        /// The local initial state, carrying the rules the data of the blocks is validated with
+        initial_state: Box<State>,
+
+        /// The trusted checkpoint the blockchain is cut at, if any
+        checkpoint: Option<Box<CheckpointConfig>>,
+    },
+}
+
+impl Job {
+    /// Gets how urgently the job is run: gossiped blocks are validated before the blockchains
+    /// sent in bulk
+    pub fn priority(&self) -> Priority {
+        match self {
+            Job::Block(_) => Priority::High,
+            Job::Chain { .. } => Priority::Low,
+        }
+    }
+
+    /// Validates the data as far as possible without the local blockchain, skipping the blocks
+    /// found in the cache. Returns the reason the data should be dropped if it turns out to be
+    /// invalid.
+    fn run(self, cache: &Mutex<HashCache<Payload>>) -> Result<Validated, Rejected> {
+        match self {
+            Job::Block(block) => {
+                let block = Arc::new(block);
+                if !has_valid_hash(cache, &block) {
+                    return Err(InvalidBlockError::invalid_hash(&*block).into());
+                }
+                Ok(Validated::Block(block))
+            }
+            Job::Chain {
+                blocks,
+                difficulty,
+                local_difficulty,
+                genesis,
+                consensus,
+                initial_state,
+                checkpoint,
+            } => {
+                check_origin(&blocks, difficulty, local_difficulty, genesis.as_deref())?;
+                // The blocks preceding the checkpoint are not replayed, the local initial state
+                // being the state as of the checkpoint block
+                let blocks = match &checkpoint {
+                    Some(checkpoint) => {
+                        Tetherion::trim_to_checkpoint(blocks, checkpoint.height, &checkpoint.hash)
+                            .ok_or_else(|| Rejected {
+                            kind: "missing_checkpoint",
+                            reason: String::from(
+                                "Remote blockchain does not contain the checkpoint",
+                            ),
+                            error: None,
+                        })?
+                    }
+                    None => blocks,
+                };
+                let remote = Tetherion::from_blocks_with(blocks, difficulty, *initial_state)
+                    .map_err(|err| Rejected {
+                        kind: err.kind(),
+                        reason: format!("Remote blockchain is invalid: {}", err),
+                        error: Some(err),
+                    })?
+                    .with_consensus(consensus);
+
+                let validity = remote.is_valid_with(&|block| has_valid_hash(cache, block));
+                Ok(Validated::Chain {
+                    remote: Box::new(remote),
+                    validity,
+                })
+            }
+        }
+    }
+}
+
+/// Checks that the remote blockchain belongs to the same network as the local one, i.e. that
+/// it has the same difficulty and starts with the same genesis block, if given, before anything
+/// else about it is validated
+fn check_origin(
+    blocks: &[Arc<Block<Payload>>],
+    difficulty: usize,
+    local_difficulty: usize,
+    genesis: Option<&str>,
+) -> Result<(), Rejected> {
+    if difficulty != local_difficulty {
+        return Err(Rejected {
+            kind: "invalid_chain_difficulty",
+            reason: format!(
+                "Remote blockchain has a difficulty of {}, the network's being {}",
+                difficulty, local_difficulty
+            ),
+            error: None,
+        });
+    }
+    // The genesis block's content is checked too, as its hash alone could be copied
+    let first = blocks.first();
+    match genesis {
+        Some(genesis)
+            if first.is_none_or(|first| {
+                first.id != 0 || first.hash != genesis || !first.has_valid_hash()
+            }) =>
+        {
+            Err(Rejected {
+                kind: "genesis_mismatch",
+                reason: format!(
+                    "Remote blockchain does not start with the local genesis block {}",
+                    genesis
+                ),
+                error: None,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The data received from a peer, validated as far as possible without the local blockchain
+#[derive(Debug)]
+pub enum Validated {
+    /// A block whose hash matches its content
+    Block(Arc<Block<Payload>>),
+
+    /// A remote blockchain along with the result of its validation
+    Chain {
+        remote: Box<Tetherion<Payload>>,
+        validity: Result<(), InvalidBlockError>,
+    },
+}
+
+/// Where the data of a job came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// The peer the data was received from
+    pub peer: PeerId,
+
+    /// The correlation ID the data is logged with, from its receipt to its application
+    pub trace: String,
+}
+
+/// The position of a job among the submitted jobs of the same priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ticket {
+    pub priority: Priority,
+    pub seq: u64,
+}
+
+/// The outcome of the job with the given ticket, run on the data of the given origin
+pub type Checked = (Ticket, Origin, Result<Validated, Rejected>);
+
+/// The jobs waiting for a worker, shared by the pool and its workers
+struct JobQueue {
+    /// The jobs, tagged with their tickets and the origins of their data
+    jobs: PriorityQueue<(Ticket, Origin, Job)>,
+
+    /// Whether the pool got dropped, stopping the workers
+    closed: bool,
+}
+
+/// Waits for the next job to run, the oldest of the highest priority, or for the pool to be
+/// dropped
+fn next_job((queue, ready): &(Mutex<JobQueue>, Condvar)) -> Option<(Ticket, Origin, Job)> {
+    let mut queue = queue
+        .lock()
+        .expect("validation queue should not be poisoned");
+    loop {
+        if queue.closed {
+            return None;
+        }
+        if let Some(job) = queue.jobs.pop() {
+            return Some(job);
+        }
+        queue = ready
+            .wait(queue)
+            .expect("validation queue should not be poisoned");
+    }
+}
+
+/// The jobs of a priority whose outcomes are released in the order the jobs were submitted in
+#[derive(Default)]
+struct Lane {
+    /// The sequence number of the next submitted job
+    next_seq: u64,
+
+    /// The sequence number of the next outcome to be released
+    next_release: u64,
+
+    /// The outcomes completed ahead of the preceding ones, by sequence number
+    completed: BTreeMap<u64, (Origin, Result<Validated, Rejected>)>,
+}
+
+/// Checks whether the block's hash matches its content, unless the block is in the cache. The
+/// cache is not locked while hashing, so that the workers can hash in parallel.
+fn has_valid_hash(cache: &Mutex<HashCache<Payload>>, block: &Arc<Block<Payload>>) -> bool {
+    let lock = || {
+        cache
+            .lock()
+            .expect("validation cache should not be poisoned")
+    };
+    if lock().contains(block) {
+        return true;
+    }
+
+    let valid = block.has_valid_hash();
+    if valid {
+        lock().insert(block.clone());
+    }
+    valid
+}
+
+/// Validates the blocks and blockchains received from the peers on a pool of worker threads, so
+/// that hashing them does not stall the node's event loop.
+///
+/// The gossiped blocks are validated before the blockchains sent in bulk, so that a large
+/// blockchain does not hold up the new blocks. Outcomes are reported through the channel given at
+/// creation time and released in the order the jobs of the same priority were submitted in.
+pub struct ValidationPool {
+    /// The jobs waiting for a worker, along with the condition the workers wait for jobs on
+    queue: Arc<(Mutex<JobQueue>, Condvar)>,
+
+    /// The number of jobs that may wait for a worker
+    queue_size: usize,
+
+    /// The jobs of high priority
+    high: Lane,
+
+    /// The jobs of low priority
+    low: Lane,
+
+    /// The recently validated blocks, shared with the workers
+    cache: Arc<Mutex<HashCache<Payload>>>,
+}
+
+impl ValidationPool {
+    pub fn new(config: ValidationConfig, sender: mpsc::UnboundedSender<Checked>) -> Self {
+        let queue = Arc::new((
+            Mutex::new(JobQueue {
+                jobs: PriorityQueue::default(),
+                closed: false,
+            }),
+            Condvar::new(),
+        ));
+        let cache = Arc::new(Mutex::new(HashCache::new(config.cache_size)));
+        for _ in 0..config.workers {
+            let (queue, sender, cache) = (queue.clone(), sender.clone(), cache.clone());
+            thread::spawn(move || loop {
+                let (ticket, origin, job) = match next_job(&queue) {
+                    Some(job) => job,
+                    None => break,
+                };
+                let outcome = job.run(&cache);
+                match &outcome {
+                    Ok(_) => log::debug!("[{}] validated data from {}", origin.trace, origin.peer),
+                    Err(rejected) => log::debug!(
+                        "[{}] invalid data from {}: {}",
+                        origin.trace,
+                        origin.peer,
+                        rejected
+                    ),
+                }
+                if sender.send((ticket, origin, outcome)).is_err() {
+                    break;
+                }
+            });
+        }
+
+        Self {
+            queue,
+            queue_size: config.queue_size,
+            high: Lane::default(),
+            low: Lane::default(),
+            cache,
+        }
+    }
+
+    fn lane(&mut self, priority: Priority) -> &mut Lane {
+        match priority {
+            Priority::High => &mut self.high,
+            Priority::Low => &mut self.low,
+        }
+    }
+
+    /// Queues the job on the data of the given origin to be validated, unless the queue is full
+    pub fn submit(&mut self, job: Job, origin: Origin) -> Result<(), QueueFullError> {
+        let priority = job.priority();
+        let seq = self.lane(priority).next_seq;
+        let (queue, ready) = &*self.queue;
+        let mut queue = queue
+            .lock()
+            .expect("validation queue should not be poisoned");
+        if queue.jobs.len() >= self.queue_size {
+            return Err(QueueFullError);
+        }
+        queue
+            .jobs
+            .push(priority, (Ticket { priority, seq }, origin, job));
+        ready.notify_one();
+        drop(queue);
+        self.lane(priority).next_seq += 1;
+        Ok(())
+    }
+
+    /// Accepts the outcome of a job, returning the outcomes ready to be applied, along with the
+    /// origins of their data, in the order the jobs of the same priority were submitted in
+    pub fn complete(
+        &mut self,
+        (ticket, origin, outcome): Checked,
+    ) -> Vec<(Origin, Result<Validated, Rejected>)> {
+        let lane = self.lane(ticket.priority);
+        lane.completed.insert(ticket.seq, (origin, outcome));
+
+        let mut ready = Vec::new();
+        while let Some(outcome) = lane.completed.remove(&lane.next_release) {
+            lane.next_release += 1;
+            ready.push(outcome);
+        }
+        ready
+    }
+
+    /// Gets the number of jobs whose outcomes have not been released yet
+    pub fn pending(&self) -> u64 {
+        [&self.high, &self.low]
+            .iter()
+            .map(|lane| lane.next_seq - lane.next_release)
+            .sum()
+    }
+
+    /// Checks whether the block's hash matches its content, unless the block was validated
+    /// recently
+    pub fn has_valid_hash(&self, block: &Arc<Block<Payload>>) -> bool {
+        has_valid_hash(&self.cache, block)
+    }
+
+    /// Gets the lookup counters of the cache of recently validated blocks
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache
+            .lock()
+            .expect("validation cache should not be poisoned")
+            .stats()
+    }
+}
+
+impl Drop for ValidationPool {
+    fn drop(&mut self) {
+        let (queue, ready) = &*self.queue;
+        if let Ok(mut queue) = queue.lock() {
+            queue.closed = true;
+        }
+        ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_in_order() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let config = ValidationConfig {
+            workers: 2,
+            queue_size: 8,
+            cache_size: 8,
+        };
+        let mut pool = ValidationPool::new(config, sender);
+
+        let genesis = Block::genesis(Payload::Text(String::from("genesis")), 1);
+        let mut forged = Block::new(1, &genesis.hash, Payload::Text(String::from("a")), 1);
+        forged.hash = String::from("00");
+        let block = Block::new(2, &forged.hash, Payload::Text(String::from("b")), 1);
+        let peer = PeerId::random();
+        for block in [genesis, forged, block] {
+            let trace = crate::trace::block(&block.hash);
+            pool.submit(Job::Block(block), Origin { peer, trace })
+                .unwrap();
+        }
+        assert_eq!(pool.pending(), 3);
+
+        let mut released = Vec::new();
+        while pool.pending() > 0 {
+            let checked = receiver.blocking_recv().unwrap();
+            released.extend(
+                pool.complete(checked)
+                    .into_iter()
+                    .map(|(_, outcome)| match outcome {
+                        Ok(Validated::Block(block)) => Ok(block.id),
+                        Ok(Validated::Chain { .. }) => unreachable!(),
+                        Err(rejected) => Err(rejected.kind),
+                    }),
+            );
+        }
+        assert_eq!(released, vec![Ok(0), Err("invalid_hash"), Ok(2)]);
+    }
+
+    #[test]
+    fn validate_blocks_first() {
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let config = ValidationConfig {
+            workers: 0,
+            queue_size: 8,
+            cache_size: 8,
+        };
+        let mut pool = ValidationPool::new(config, sender);
+        let origin = Origin {
+            peer: PeerId::random(),
+            trace: String::from("test"),
+        };
+
+        let genesis = Arc::new(Block::genesis(Payload::Text(String::from("genesis")), 1));
+        let chain = Job::Chain {
+            blocks: vec![genesis.clone()],
+            difficulty: 1,
+            local_difficulty: 1,
+            genesis: Some(genesis.hash.clone()),
+            consensus: Arc::new(crate::consensus::InstantSeal),
+            initial_state: Box::default(),
+            checkpoint: None,
+        };
+        pool.submit(chain, origin.clone()).unwrap();
+        pool.submit(Job::Block((*genesis).clone()), origin.clone())
+            .unwrap();
+
+        // The block submitted last is picked first, and released before the blockchain
+        let (ticket, origin, job) = next_job(&pool.queue).unwrap();
+        assert_eq!(ticket.priority, Priority::High);
+        let released = pool.complete((ticket, origin, job.run(&pool.cache)));
+        assert!(matches!(released[..], [(_, Ok(Validated::Block(_)))]));
+        assert_eq!(pool.pending(), 1);
+
+        let (ticket, origin, job) = next_job(&pool.queue).unwrap();
+        assert_eq!(ticket.priority, Priority::Low);
+        let released = pool.complete((ticket, origin, job.run(&pool.cache)));
+        assert_eq!(released.len(), 1);
+        assert_eq!(pool.pending(), 0);
+    }
+
+    #[test]
+    fn reject_foreign_chains() {
+        let genesis = Arc::new(Block::genesis(Payload::Text(String::from("genesis")), 1));
+        let other = Arc::new(Block::genesis(Payload::Text(String::from("other")), 1));
+        let check = |blocks: &[Arc<Block<Payload>>], difficulty| {
+            check_origin(blocks, difficulty, 1, Some(genesis.hash.as_str())).map_err(|err| err.kind)
+        };
+        assert_eq!(check(std::slice::from_ref(&genesis), 1), Ok(()));
+        assert_eq!(
+            check(std::slice::from_ref(&genesis), 0),
+            Err("invalid_chain_difficulty")
+        );
+        assert_eq!(
+            check(std::slice::from_ref(&other), 1),
+            Err("genesis_mismatch")
+        );
+        assert_eq!(check(&[], 1), Err("genesis_mismatch"));
+        // A genesis block whose hash is copied but whose data differs is foreign too
+        let forged = Arc::new(Block::from_header(
+            genesis.header(),
+            Payload::Text(String::from("forged")),
+        ));
+        assert_eq!(check(&[forged], 1), Err("genesis_mismatch"));
+        // Without a genesis block to match, e.g. past a checkpoint, any first block goes
+        assert_eq!(check_origin(&[other], 1, 1, None), Ok(()));
+    }
+
+    #[test]
+    fn reject_when_full() {
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let config = ValidationConfig {
+            workers: 1,
+            queue_size: 1,
+            cache_size: 0,
+        };
+        let mut pool = ValidationPool::new(config, sender);
+
+        // Hashing a large block keeps the only worker busy while the queue fills up
+        let block = Block::genesis(Payload::Text("a".repeat(1 << 24)), 1);
+        let submitted: Vec<_> = (0..3)
+            .map(|_| {
+                let origin = Origin {
+                    peer: PeerId::random(),
+                    trace: String::from("test"),
+                };
+                pool.submit(Job::Block(block.clone()), origin)
+            })
+            .collect();
+        assert_eq!(submitted[0], Ok(()));
+        assert!(submitted.contains(&Err(QueueFullError)));
+    }
+}
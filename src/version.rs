@@ -0,0 +1,67 @@
+/// Copyright (c) 2022 Tetherion
+use serde::Serialize;
+
+/// The version of the crate
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The git commit the binary is built from, `unknown` when built outside of a git checkout
+pub const COMMIT: &str = env!("TETHERION_COMMIT");
+
+/// The cargo profile the binary is built with, e.g. `release`
+pub const PROFILE: &str = env!("TETHERION_PROFILE");
+
+/// The version along with the commit and the profile, as printed by `--version`
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("TETHERION_COMMIT"),
+    ", ",
+    env!("TETHERION_PROFILE"),
+    ")"
+);
+
+/// The agent version the node reports to the peers through identify
+pub const AGENT_VERSION: &str = concat!(
+    "tetherion/",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("TETHERION_COMMIT"),
+    ", ",
+    env!("TETHERION_PROFILE"),
+    ")"
+);
+
+/// What the binary is built from
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub profile: &'static str,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: VERSION,
+            commit: COMMIT,
+            profile: PROFILE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_info() {
+        let info = BuildInfo::current();
+        assert_eq!(info.version, "0.1.0");
+        assert!(!info.commit.is_empty());
+        assert_eq!(
+            LONG_VERSION,
+            format!("{} ({}, {})", info.version, info.commit, info.profile)
+        );
+        assert_eq!(AGENT_VERSION, format!("tetherion/{}", LONG_VERSION));
+    }
+}
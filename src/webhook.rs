@@ -0,0 +1,464 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        alerts::Alert,
+        block::Block,
+        config::{WebhookConfig, WebhookEvent},
+        finality::BlockRef,
+        merkle,
+        p2p::ChainSnapshot,
+        payload::{Payload, TokenOp},
+        tetherion::Tetherion,
+    },
+    hmac::{Hmac, Mac, NewMac},
+    hyper::{client::HttpConnector, header::CONTENT_TYPE, Body, Client, Method, Request},
+    hyper_rustls::{HttpsConnector, HttpsConnectorBuilder},
+    serde::Serialize,
+    sha2::Sha256,
+    std::{fmt, sync::Arc, time::Duration},
+    tokio::{
+        select,
+        sync::{
+            mpsc::{self, error::TrySendError},
+            watch,
+        },
+        time::sleep,
+    },
+};
+
+/// The header carrying the HMAC-SHA256 signature, in HEX format, of a request's timestamp and body
+const SIGNATURE_HEADER: &str = "X-Tetherion-Signature";
+
+/// The header carrying the time, in seconds since the Unix epoch, a request was signed at
+const TIMESTAMP_HEADER: &str = "X-Tetherion-Timestamp";
+
+/// The number of events that may wait to be delivered to a webhook, beyond which new events are
+/// dropped
+const WEBHOOK_QUEUE_SIZE: usize = 256;
+
+/// The most blocks a change of the local blockchain may add to be reported block by block, a
+/// larger change being reported by a single reset event
+pub const MAX_EVENT_BLOCKS: u64 = 100;
+
+/// The time waited before retrying a failed request for the first time
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A change of the local blockchain reported to the webhooks
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ChainEvent {
+    /// A block was added to the local blockchain
+    NewBlock { block: BlockRef },
+
+    /// The local blockchain was replaced by one forking below its latest block
+    Reorg {
+        /// The latest block of the replaced blockchain
+        previous: BlockRef,
+
+        /// The latest block of the new blockchain
+        best: BlockRef,
+
+        /// The number of blocks of the replaced blockchain dropped
+        depth: u64,
+    },
+
+    /// A token operation moving a large amount was stored in a block
+    LargeTransaction {
+        block: BlockRef,
+        tx_id: String,
+        from: String,
+        token: String,
+        amount: u64,
+    },
+
+    /// An alert on the health of the node was raised
+    Alert { alert: Alert },
+
+    /// The local blockchain changed too much to be reported block by block: it was replaced by
+    /// one sharing no block with it, or more than `MAX_EVENT_BLOCKS` blocks were added at once
+    Reset {
+        /// The latest block of the replaced blockchain
+        previous: BlockRef,
+
+        /// The latest block of the new blockchain
+        best: BlockRef,
+    },
+}
+
+impl ChainEvent {
+    /// Gets the kind of the event
+    pub fn kind(&self) -> WebhookEvent {
+        match self {
+            ChainEvent::NewBlock { .. } => WebhookEvent::NewBlock,
+            ChainEvent::Reorg { .. } => WebhookEvent::Reorg,
+            ChainEvent::LargeTransaction { .. } => WebhookEvent::LargeTransaction,
+            ChainEvent::Alert { .. } => WebhookEvent::Alert,
+            ChainEvent::Reset { .. } => WebhookEvent::Reset,
+        }
+    }
+
+    /// Checks whether the webhook is notified of the event. Resets stand in for the new blocks
+    /// and reorgs they cover, so they are posted to the webhooks notified of these as well.
+    fn is_wanted_by(&self, webhook: &WebhookConfig) -> bool {
+        let wanted = |kind| webhook.events.contains(&kind);
+        match self {
+            ChainEvent::LargeTransaction { amount, .. } => {
+                *amount >= webhook.large_transaction && wanted(WebhookEvent::LargeTransaction)
+            }
+            ChainEvent::Reset { .. } => {
+                wanted(WebhookEvent::Reset)
+                    || wanted(WebhookEvent::NewBlock)
+                    || wanted(WebhookEvent::Reorg)
+            }
+            event => wanted(event.kind()),
+        }
+    }
+}
+
+impl fmt::Display for ChainEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainEvent::NewBlock { block } => write!(f, "block {} {}", block.height, block.hash),
+            ChainEvent::Reorg {
+                previous,
+                best,
+                depth,
+            } => write!(
+                f,
+                "reorg from block {} to block {} {}, dropping {} blocks",
+                previous.height, best.height, best.hash, depth
+            ),
+            ChainEvent::LargeTransaction {
+                block,
+                tx_id,
+                from,
+                token,
+                amount,
+            } => write!(
+                f,
+                "large transaction {} in block {}: {} {} from {}",
+                tx_id, block.height, amount, token, from
+            ),
+            ChainEvent::Alert { alert } => write!(f, "{}", alert),
+            ChainEvent::Reset { previous, best } => write!(
+                f,
+                "reset from block {} to block {} {}",
+                previous.height, best.height, best.hash
+            ),
+        }
+    }
+}
+
+/// Gets the reference to the block
+fn block_ref(block: &Block<Payload>) -> BlockRef {
+    BlockRef {
+        height: block.id,
+        hash: block.hash.clone(),
+    }
+}
+
+/// Gets the block of the blockchain with the given ID, if any
+fn block_at(tetherion: &Tetherion<Payload>, id: u64) -> Option<&Block<Payload>> {
+    let blocks = tetherion.blocks();
+    let first = blocks[0].id;
+    id.checked_sub(first)
+        .and_then(|index| blocks.get(index as usize))
+        .map(|block| block.as_ref())
+}
+
+/// Gets the token operations stored in the payload, along with their transaction IDs and the
+/// amounts they move
+fn token_amounts(payload: &Payload) -> Vec<(String, String, String, u64)> {
+    match payload {
+        Payload::Batch(payloads) => payloads.iter().flat_map(token_amounts).collect(),
+        Payload::Token { op, from, .. } => {
+            let moved = match op {
+                TokenOp::Create { token, supply, .. } => Some((token, *supply)),
+                TokenOp::Mint { token, amount }
+                | TokenOp::Transfer { token, amount, .. }
+                | TokenOp::Lock { token, amount, .. } => Some((token, *amount)),
+                TokenOp::Unlock { .. } => None,
+            };
+            moved
+                .map(|(token, amount)| {
+                    let tx_id = merkle::leaf_hash(payload.to_string().as_bytes());
+                    (tx_id, from.clone(), token.clone(), amount)
+                })
+                .into_iter()
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Gets the events that turned the previous blockchain into the current one, the oldest first. A
+/// current blockchain sharing no block with the previous one, or adding more than
+/// `MAX_EVENT_BLOCKS` blocks to the latest block they share, is reported by a single reset event.
+pub fn events(previous: &Tetherion<Payload>, current: &Tetherion<Payload>) -> Vec<ChainEvent> {
+    let previous_best = previous
+        .blocks()
+        .last()
+        .expect("there is at least one block");
+    let best = current
+        .blocks()
+        .last()
+        .expect("there is at least one block");
+
+    // The latest block both blockchains share
+    let ancestor = previous
+        .blocks()
+        .iter()
+        .rev()
+        .find(|block| block_at(current, block.id).is_some_and(|other| other.hash == block.hash))
+        .map(|block| block.id);
+    let ancestor = match ancestor {
+        Some(ancestor) if best.id - ancestor <= MAX_EVENT_BLOCKS => ancestor,
+        _ => {
+            return vec![ChainEvent::Reset {
+                previous: block_ref(previous_best),
+                best: block_ref(best),
+            }]
+        }
+    };
+
+    let mut events = Vec::new();
+    if ancestor < previous_best.id {
+        events.push(ChainEvent::Reorg {
+            previous: block_ref(previous_best),
+            best: block_ref(best),
+            depth: previous_best.id - ancestor,
+        });
+    }
+    for block in current.blocks().iter().filter(|block| block.id > ancestor) {
+        events.push(ChainEvent::NewBlock {
+            block: block_ref(block),
+        });
+        for (tx_id, from, token, amount) in token_amounts(block.data()) {
+            events.push(ChainEvent::LargeTransaction {
+                block: block_ref(block),
+                tx_id,
+                from,
+                token,
+                amount,
+            });
+        }
+    }
+    events
+}
+
+/// Computes the HMAC-SHA256 signature, in HEX format, of the body with the secret
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Gets the message signed for a request sent at the given time, in seconds since the Unix epoch:
+/// the timestamp and the body joined by a dot, so that receivers can reject a captured request
+/// replayed later
+pub fn signed_message(timestamp: i64, body: &[u8]) -> Vec<u8> {
+    [timestamp.to_string().as_bytes(), b".", body].concat()
+}
+
+/// Posts the event to the webhook, retrying with an exponential backoff until it is accepted or
+/// the retries run out. Each attempt is signed along with the time it is made at.
+async fn deliver(
+    client: &Client<HttpsConnector<HttpConnector>>,
+    webhook: &WebhookConfig,
+    body: &[u8],
+) {
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 0..=webhook.retries {
+        if attempt > 0 {
+            sleep(delay).await;
+            delay *= 2;
+        }
+
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = sign(webhook.secret.expose(), &signed_message(timestamp, body));
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(&webhook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(SIGNATURE_HEADER, &signature)
+            .body(Body::from(body.to_vec()))
+            .expect("webhook URL is validated");
+        match client.request(request).await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => log::warn!(
+                "webhook {} answered {}, attempt {}",
+                webhook.url,
+                response.status(),
+                attempt + 1
+            ),
+            Err(err) => log::warn!(
+                "cannot reach webhook {}: {}, attempt {}",
+                webhook.url,
+                err,
+                attempt + 1
+            ),
+        }
+    }
+    log::error!("giving up notifying webhook {}", webhook.url);
+}
+
+/// Delivers the events queued for the webhook one at a time, each one once the previous one is
+/// accepted or given up on, so that the webhook receives them in the order they happened in
+async fn drain(
+    client: Client<HttpsConnector<HttpConnector>>,
+    webhook: WebhookConfig,
+    mut queue: mpsc::Receiver<Arc<Vec<u8>>>,
+) {
+    while let Some(body) = queue.recv().await {
+        deliver(&client, &webhook, &body).await;
+    }
+}
+
+/// Queues the event to be delivered to the webhooks notified of it, dropping it for the webhooks
+/// whose queue is full
+fn post(webhooks: &[(WebhookConfig, mpsc::Sender<Arc<Vec<u8>>>)], event: &ChainEvent) {
+    let body = Arc::new(serde_json::to_vec(event).expect("can jsonify event"));
+    for (webhook, queue) in webhooks
+        .iter()
+        .filter(|(webhook, _)| event.is_wanted_by(webhook))
+    {
+        if let Err(TrySendError::Full(_)) = queue.try_send(body.clone()) {
+            log::warn!(
+                "dropping {} for webhook {}, {} events are queued already",
+                event,
+                webhook.url,
+                WEBHOOK_QUEUE_SIZE
+            );
+        }
+    }
+}
+
+/// Notifies the webhooks of the chain events, derived from consecutive snapshots of the local
+/// blockchain, and of the alerts raised, until the node stops publishing snapshots
+pub async fn notify(
+    webhooks: Vec<WebhookConfig>,
+    mut snapshots: watch::Receiver<Arc<ChainSnapshot>>,
+    mut alerts: mpsc::UnboundedReceiver<Alert>,
+) {
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder().build::<_, Body>(connector);
+    let webhooks: Vec<_> = webhooks
+        .into_iter()
+        .map(|webhook| {
+            let (queue, queued) = mpsc::channel(WEBHOOK_QUEUE_SIZE);
+            tokio::spawn(drain(client.clone(), webhook.clone(), queued));
+            (webhook, queue)
+        })
+        .collect();
+
+    let mut previous = snapshots.borrow_and_update().clone();
+    loop {
+        select! {
+            changed = snapshots.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let current = snapshots.borrow_and_update().clone();
+                for event in events(&previous.tetherion, &current.tetherion) {
+                    post(&webhooks, &event);
+                }
+                previous = current;
+            }
+            Some(alert) = alerts.recv() => post(&webhooks, &ChainEvent::Alert { alert }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{payload::DEFAULT_CHAIN_ID, secret::Secret},
+        libp2p::identity::ed25519,
+    };
+
+    #[test]
+    fn derive_events() {
+        let keys = ed25519::Keypair::generate();
+        let genesis = Tetherion::new(Payload::Text(String::from("genesis")), 1);
+        let extend = |tetherion: &Tetherion<Payload>, data: Payload| {
+            let mut tetherion = tetherion.clone();
+            let previous = tetherion.blocks().last().unwrap().clone();
+            let block = Block::new(previous.id + 1, &previous.hash, data, 1);
+            tetherion.add_block(block).unwrap();
+            tetherion
+        };
+
+        let local = extend(&genesis, Payload::Text(String::from("a")));
+        assert_eq!(
+            events(&genesis, &local),
+            vec![ChainEvent::NewBlock {
+                block: block_ref(&local.blocks()[1])
+            }]
+        );
+
+        let op = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 5_000_000,
+            mintable: false,
+        };
+        let fork = extend(&genesis, Payload::token(DEFAULT_CHAIN_ID, op, 0, &keys));
+        let fork = extend(&fork, Payload::Text(String::from("c")));
+        let kinds: Vec<_> = events(&local, &fork).iter().map(ChainEvent::kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                WebhookEvent::Reorg,
+                WebhookEvent::NewBlock,
+                WebhookEvent::LargeTransaction,
+                WebhookEvent::NewBlock
+            ]
+        );
+        assert!(events(&fork, &fork).is_empty());
+        assert_eq!(
+            events(&local, &fork)[0].to_string(),
+            format!(
+                "reorg from block 1 to block 2 {}, dropping 1 blocks",
+                fork.blocks()[2].hash
+            )
+        );
+
+        // Blockchains sharing no block, or too far apart, are reported by a single reset
+        let unrelated = Tetherion::new(Payload::Text(String::from("other genesis")), 1);
+        let reset = ChainEvent::Reset {
+            previous: block_ref(&fork.blocks()[2]),
+            best: block_ref(&unrelated.blocks()[0]),
+        };
+        assert_eq!(events(&fork, &unrelated), vec![reset]);
+        let mut long = local.clone();
+        for id in 2..=MAX_EVENT_BLOCKS + 1 {
+            long = extend(&long, Payload::Text(id.to_string()));
+        }
+        assert_eq!(events(&local, &long).len(), MAX_EVENT_BLOCKS as usize);
+        long = extend(&long, Payload::Text(String::from("one too many")));
+        let kinds: Vec<_> = events(&local, &long).iter().map(ChainEvent::kind).collect();
+        assert_eq!(kinds, vec![WebhookEvent::Reset]);
+
+        let webhook = WebhookConfig {
+            url: String::from("https://hooks.example.com"),
+            secret: Secret::new(String::from("secret")),
+            events: vec![WebhookEvent::NewBlock],
+            large_transaction: 1,
+            retries: 0,
+        };
+        assert!(events(&local, &long)[0].is_wanted_by(&webhook));
+        assert_eq!(signed_message(1700000000, b"{}"), b"1700000000.{}");
+
+        // The HMAC-SHA256 test vector of RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+/// Copyright (c) 2022 Tetherion
+#[cfg(feature = "contracts")]
+use crate::contracts;
+use {
+    crate::{
+        fees,
+        payload::{Payload, PayloadKind},
+        state::State,
+        tetherion::BlockData,
+    },
+    std::cmp::Ordering,
+};
+
+/// The highest total weight of the data stored in a block, which blocks exceeding are invalid
+pub const MAX_BLOCK_WEIGHT: u64 = 1_000_000;
+
+/// Gets the weight of the payload, i.e. what it costs the nodes to store and validate it: a base
+/// weight depending on its kind, accounting for the signatures to check and the contracts to
+/// execute, plus its size in bytes. A batch weighs as much as the payloads it consists of.
+pub fn weight(payload: &Payload) -> u64 {
+    let base = match payload.kind() {
+        PayloadKind::Batch => {
+            return match payload {
+                Payload::Batch(payloads) => payloads.iter().map(weight).sum(),
+                _ => 0,
+            }
+        }
+        PayloadKind::Text | PayloadKind::Anchor | PayloadKind::ChainAnchor => 100,
+        #[cfg(feature = "binary-payload")]
+        PayloadKind::Binary => 100,
+        PayloadKind::Register | PayloadKind::Token => 1_000,
+        #[cfg(feature = "contracts")]
+        PayloadKind::Contract => match payload {
+            Payload::Contract { fuel, .. } => 1_000 + fuel / contracts::FUEL_PER_WEIGHT,
+            _ => 1_000,
+        },
+        PayloadKind::Evidence => 5_000,
+    };
+    base + payload.to_string().len() as u64
+}
+
+/// Compares the fees per weight paid by the payloads
+pub fn compare_fee_rates(a: &Payload, b: &Payload) -> Ordering {
+    let rate = |fee: u64, weight: u64| u128::from(fee) * u128::from(weight.max(1));
+    rate(fees::fee(a), weight(b)).cmp(&rate(fees::fee(b), weight(a)))
+}
+
+/// Selects the pending payloads to be stored in the block at the given height, on top of the
+/// given state, so that their total weight fits within the budget, favouring the ones paying the
+/// highest fee per weight, the oldest first among equals. Returns the positions of the selected
+/// payloads among the pending ones, in the order they apply in.
+///
+/// Only the payloads applying on top of the ones selected before them are selected, so the token
+/// operations of an account are selected in nonce order whatever their fees. A batch, which
+/// cannot be nested in the batch storing the selected payloads, is only selected on its own.
+pub fn pack<'a>(
+    pending: impl Iterator<Item = &'a Payload>,
+    height: u64,
+    state: &State,
+    budget: u64,
+) -> Vec<usize> {
+    let mut candidates: Vec<_> = pending.enumerate().collect();
+    candidates.sort_by(|(_, a), (_, b)| compare_fee_rates(b, a));
+
+    let mut state = state.clone();
+    let mut selected = Vec::new();
+    let mut total = 0;
+    // Payloads failing to apply may apply once the ones they depend on are selected
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for (position, payload) in &candidates {
+            let payload_weight = weight(payload);
+            if selected.contains(position) || total + payload_weight > budget {
+                continue;
+            }
+            let is_batch = matches!(payload, Payload::Batch(_));
+            if is_batch && !selected.is_empty() {
+                continue;
+            }
+            if payload.apply(height, &mut state).is_ok() {
+                selected.push(*position);
+                total += payload_weight;
+                progress = true;
+                if is_batch {
+                    return selected;
+                }
+            }
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::payload::{TokenOp, DEFAULT_CHAIN_ID},
+        libp2p::identity::ed25519,
+    };
+
+    #[test]
+    fn pack_by_fee_rate() {
+        let keys = ed25519::Keypair::generate();
+        let operation = |nonce, fee| {
+            let op = TokenOp::Create {
+                token: format!("T{}", nonce),
+                supply: 1_000,
+                mintable: false,
+            };
+            Payload::token_with_fee(DEFAULT_CHAIN_ID, op, nonce, fee, &keys)
+        };
+        let text = |text: &str| Payload::Text(text.to_owned());
+        let pending = [
+            text("a"),
+            operation(0, 1),
+            operation(1, 50),
+            text("b"),
+            Payload::Batch(vec![text("c"), text("d")]),
+            operation(3, 100),
+        ];
+
+        // The operation paying the most waits for the one it depends on, the gap at nonce 2 keeps
+        // the last one out, and the batch cannot be nested
+        let packed = pack(pending.iter(), 1, &State::default(), MAX_BLOCK_WEIGHT);
+        assert_eq!(packed, vec![1, 0, 3, 2]);
+
+        let budget = weight(&pending[0]) + weight(&pending[3]);
+        assert_eq!(
+            pack(pending.iter(), 1, &State::default(), budget),
+            vec![0, 3]
+        );
+
+        let batch = &pending[4];
+        assert_eq!(weight(batch), weight(&text("c")) + weight(&text("d")));
+        assert_eq!(
+            pack(pending[4..5].iter(), 1, &State::default(), MAX_BLOCK_WEIGHT),
+            vec![0]
+        );
+    }
+}
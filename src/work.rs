@@ -0,0 +1,279 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    crate::{
+        block::Block,
+        payload::Payload,
+        state::State,
+        tetherion::InvalidBlockError,
+        weight::{self, MAX_BLOCK_WEIGHT},
+    },
+    serde::{Deserialize, Serialize},
+    std::{collections::VecDeque, fmt},
+};
+
+/// The maximum number of mempool transactions selected for a block template
+pub const MAX_TEMPLATE_TRANSACTIONS: usize = 100;
+
+/// Gets the highest valid hash, in HEX format, of a block mined with the given difficulty
+pub fn target(difficulty: usize) -> String {
+    const HASH_SIZE: usize = 64;
+
+    let zeros = (difficulty * 2).min(HASH_SIZE);
+    format!("{}{}", "0".repeat(zeros), "f".repeat(HASH_SIZE - zeros))
+}
+
+#[derive(Debug)]
+pub enum SubmitWorkError {
+    UnknownWork { work_id: u64 },
+    InvalidBlock(InvalidBlockError),
+}
+
+impl fmt::Display for SubmitWorkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubmitWorkError::UnknownWork { work_id } => {
+                write!(f, "Work with ID {} is unknown or expired", work_id)
+            }
+            SubmitWorkError::InvalidBlock(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SubmitWorkError {}
+
+/// A block template handed out to an external miner.
+///
+/// The miner searches for a nonce such that the SHA256 hash, in HEX format, of
+/// `id || previous_hash || timestamp || nonce || merkle_root` (numbers in decimal) does not
+/// exceed the target.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WorkTemplate {
+    /// The identifier to refer to the template when submitting work
+    pub work_id: u64,
+
+    /// The ID of the block to be mined
+    pub id: u64,
+
+    /// The hash of the block to be mined on top of
+    pub previous_hash: String,
+
+    /// The timestamp of the block to be mined
+    pub timestamp: i64,
+
+    /// The data of the block to be mined
+    pub data: Payload,
+
+    /// The root of the Merkle tree built on top of the data
+    pub merkle_root: String,
+
+    /// The difficulty of the blockchain
+    pub difficulty: usize,
+
+    /// The highest valid hash of the block, in HEX format
+    pub target: String,
+}
+
+impl WorkTemplate {
+    /// Creates the block the nonce has been found for
+    pub fn block(&self, nonce: u64) -> Block<Payload> {
+        Block::with_nonce(
+            self.id,
+            &self.previous_hash,
+            self.timestamp,
+            nonce,
+            self.data.clone(),
+        )
+    }
+}
+
+/// What an external block producer needs to assemble a candidate block on top of the local tip
+/// that is compatible with the local consensus rules
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockTemplate {
+    /// The ID of the block to be produced
+    pub height: u64,
+
+    /// The hash of the block to be produced on top of
+    pub previous_hash: String,
+
+    /// The highest valid hash of the block, in HEX format, in case blocks are mined
+    pub target: Option<String>,
+
+    /// The suggested timestamp of the block, not preceding the one of the previous block
+    pub timestamp: i64,
+
+    /// The mempool transactions to be stored in the block, in order, as a batch if there are
+    /// several of them
+    pub transactions: Vec<Payload>,
+}
+
+impl BlockTemplate {
+    /// Creates a template on top of the given block, having the given state, selecting the
+    /// pending transactions that apply in order, packed within the block weight budget by fee per
+    /// weight. Batches are left out since they cannot be nested in the batch storing the selected
+    /// transactions.
+    pub fn new<'a>(
+        tip: &Block<Payload>,
+        difficulty: Option<usize>,
+        pending: impl Iterator<Item = &'a Payload>,
+        state: &State,
+    ) -> Self {
+        let height = tip.id + 1;
+        let pending: Vec<_> = pending
+            .filter(|payload| !matches!(payload, Payload::Batch(_)))
+            .collect();
+        let transactions = weight::pack(pending.iter().copied(), height, state, MAX_BLOCK_WEIGHT)
+            .into_iter()
+            .take(MAX_TEMPLATE_TRANSACTIONS)
+            .map(|position| pending[position].clone())
+            .collect();
+
+        Self {
+            height,
+            previous_hash: tip.hash.clone(),
+            target: difficulty.map(target),
+            timestamp: chrono::Utc::now().timestamp().max(tip.header().timestamp),
+            transactions,
+        }
+    }
+}
+
+/// Hands out block templates to external miners and keeps track of the outstanding ones
+#[derive(Default)]
+pub struct WorkProvider {
+    /// The outstanding templates, the oldest first
+    templates: VecDeque<WorkTemplate>,
+
+    /// The identifier of the latest template
+    work_id: u64,
+}
+
+impl WorkProvider {
+    /// The maximum number of outstanding templates, the oldest ones are forgotten first
+    const MAX_TEMPLATES: usize = 16;
+
+    /// Creates a template for a block containing the given data on top of the given block
+    pub fn get_work(
+        &mut self,
+        tip: &Block<Payload>,
+        data: Payload,
+        difficulty: usize,
+    ) -> WorkTemplate {
+        self.work_id += 1;
+        let template = WorkTemplate {
+            work_id: self.work_id,
+            id: tip.id + 1,
+            previous_hash: tip.hash.clone(),
+            timestamp: chrono::Utc::now().timestamp(),
+            merkle_root: Block::merkle_root_of(&data),
+            data,
+            difficulty,
+            target: target(difficulty),
+        };
+
+        if self.templates.len() == WorkProvider::MAX_TEMPLATES {
+            self.templates.pop_front();
+        }
+        self.templates.push_back(template.clone());
+
+        template
+    }
+
+    /// Gets the outstanding template with the given identifier, if any, leaving it outstanding
+    pub fn get(&self, work_id: u64) -> Option<&WorkTemplate> {
+        self.templates
+            .iter()
+            .find(|template| template.work_id == work_id)
+    }
+
+    /// Takes the outstanding template with the given identifier, if any
+    pub fn take(&mut self, work_id: u64) -> Option<WorkTemplate> {
+        let index = self
+            .templates
+            .iter()
+            .position(|template| template.work_id == work_id)?;
+        self.templates.remove(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::payload::{TokenOp, DEFAULT_CHAIN_ID},
+        libp2p::identity::ed25519,
+    };
+
+    #[test]
+    fn submit_work() {
+        const DIFFICULTY: usize = 1;
+
+        let genesis = Block::genesis(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let mut provider = WorkProvider::default();
+        let template = provider.get_work(&genesis, Payload::Text(String::from("data")), DIFFICULTY);
+
+        assert_eq!(template.id, 1);
+        assert_eq!(template.target, format!("00{}", "f".repeat(62)));
+
+        let block = (0..)
+            .map(|nonce| template.block(nonce))
+            .find(|block| block.hash <= template.target)
+            .unwrap();
+        assert!(block.header().is_valid(DIFFICULTY));
+
+        assert_eq!(provider.get(template.work_id), Some(&template));
+        assert_eq!(provider.take(template.work_id), Some(template.clone()));
+        assert_eq!(provider.get(template.work_id), None);
+        assert_eq!(provider.take(template.work_id), None);
+    }
+
+    #[test]
+    fn block_template() {
+        let keys = ed25519::Keypair::generate();
+        let recipient = hex::encode(ed25519::Keypair::generate().public().encode());
+        let genesis = Block::genesis(Payload::Text(String::from("genesis")), 1);
+        let create = TokenOp::Create {
+            token: String::from("TTH"),
+            supply: 10,
+            mintable: false,
+        };
+        let transfer = |nonce| {
+            let op = TokenOp::Transfer {
+                token: String::from("TTH"),
+                to: recipient.clone(),
+                amount: 10,
+            };
+            Payload::token(DEFAULT_CHAIN_ID, op, nonce, &keys)
+        };
+        let pending = [
+            Payload::token(DEFAULT_CHAIN_ID, create, 0, &keys),
+            Payload::Batch(vec![Payload::Text(String::from("batched"))]),
+            transfer(1),
+            // Overspends the balance left by the previous transfer
+            transfer(2),
+        ];
+
+        let template = BlockTemplate::new(&genesis, Some(1), pending.iter(), &State::default());
+        assert_eq!(template.height, 1);
+        assert_eq!(template.previous_hash, genesis.hash);
+        assert_eq!(template.target, Some(format!("00{}", "f".repeat(62))));
+        assert_eq!(template.transactions, vec![pending[0].clone(), transfer(1)]);
+
+        let template = BlockTemplate::new(&genesis, None, pending.iter(), &State::default());
+        assert!(template.target.is_none());
+    }
+
+    #[test]
+    fn forget_oldest_templates() {
+        const DIFFICULTY: usize = 1;
+
+        let genesis = Block::genesis(Payload::Text(String::from("genesis")), DIFFICULTY);
+        let mut provider = WorkProvider::default();
+        let first = provider.get_work(&genesis, Payload::Text(String::new()), DIFFICULTY);
+        for _ in 0..WorkProvider::MAX_TEMPLATES {
+            provider.get_work(&genesis, Payload::Text(String::new()), DIFFICULTY);
+        }
+
+        assert!(provider.take(first.work_id).is_none());
+    }
+}
@@ -0,0 +1,13 @@
+/// Copyright (c) 2022 Tetherion
+use std::{env, path::PathBuf};
+
+/// Generates the C header declaring the exported functions
+fn main() {
+    let crate_dir =
+        PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("cargo sets the crate dir"));
+    cbindgen::generate(&crate_dir)
+        .expect("C header should be generated")
+        .write_to_file(crate_dir.join("include").join("tetherion.h"));
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
@@ -0,0 +1,124 @@
+#ifndef TETHERION_H
+#define TETHERION_H
+
+/* Generated by cbindgen from tetherion-ffi, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The outcome of a call
+typedef enum TetherionStatus {
+  // The call succeeded
+  TETHERION_STATUS_OK = 0,
+  // A pointer argument is null
+  TETHERION_STATUS_NULL_POINTER = 1,
+  // A string argument is not valid UTF-8
+  TETHERION_STATUS_INVALID_STRING = 2,
+  // A string argument is not valid JSON of the expected kind
+  TETHERION_STATUS_INVALID_JSON = 3,
+  // A block does not follow the rules of the blockchain
+  TETHERION_STATUS_INVALID_BLOCK = 4,
+  // The library panicked, leaving the blockchain in an unspecified state
+  TETHERION_STATUS_PANIC = 5,
+} TetherionStatus;
+
+// A blockchain storing payloads, owned by the caller
+typedef struct TetherionChain TetherionChain;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates a blockchain whose genesis block stores the given text. Returns null if the text is
+// null or not valid UTF-8, or if mining the genesis block panics.
+//
+// # Safety
+//
+// `genesis_data` must be null or point to a NUL-terminated string.
+struct TetherionChain *tetherion_chain_new(const char *genesis_data, uintptr_t difficulty);
+
+// Creates a blockchain out of its JSON serialization, as produced by `tetherion_chain_to_json`,
+// rebuilding its state from the blocks. Returns null if the JSON is not a valid blockchain.
+//
+// # Safety
+//
+// `json` must be null or point to a NUL-terminated string.
+struct TetherionChain *tetherion_chain_from_json(const char *json);
+
+// Frees the blockchain
+//
+// # Safety
+//
+// `chain` must be null or a blockchain created by this library and not freed yet.
+void tetherion_chain_free(struct TetherionChain *chain);
+
+// Gets the ID of the latest block of the blockchain, or 0 if the blockchain is null
+//
+// # Safety
+//
+// `chain` must be null or a live blockchain created by this library.
+uint64_t tetherion_chain_height(const struct TetherionChain *chain);
+
+// Gets the hash, in HEX format, of the latest block of the blockchain, or null if the
+// blockchain is null. The string is freed with `tetherion_string_free`.
+//
+// # Safety
+//
+// `chain` must be null or a live blockchain created by this library.
+char *tetherion_chain_tip_hash(const struct TetherionChain *chain);
+
+// Mines a block storing the given text on top of the blockchain and adds it to the blockchain.
+// Blocks the calling thread until a valid nonce is found.
+//
+// # Safety
+//
+// `chain` must be null or a live blockchain created by this library, and `data` null or a
+// NUL-terminated string.
+enum TetherionStatus tetherion_chain_mine_block(struct TetherionChain *chain, const char *data);
+
+// Validates the block, given in JSON format, against the blockchain and adds it on top of it
+//
+// # Safety
+//
+// `chain` must be null or a live blockchain created by this library, and `block_json` null or a
+// NUL-terminated string.
+enum TetherionStatus tetherion_chain_add_block(struct TetherionChain *chain,
+                                               const char *block_json);
+
+// Validates each block of the blockchain regarding the previous one
+//
+// # Safety
+//
+// `chain` must be null or a live blockchain created by this library.
+enum TetherionStatus tetherion_chain_validate(const struct TetherionChain *chain);
+
+// Serializes the blockchain to JSON, or returns null if the blockchain is null. The string is
+// freed with `tetherion_string_free`.
+//
+// # Safety
+//
+// `chain` must be null or a live blockchain created by this library.
+char *tetherion_chain_to_json(const struct TetherionChain *chain);
+
+// Serializes the block with the given ID to JSON, or returns null if there is no such block.
+// The string is freed with `tetherion_string_free`.
+//
+// # Safety
+//
+// `chain` must be null or a live blockchain created by this library.
+char *tetherion_block_to_json(const struct TetherionChain *chain, uint64_t id);
+
+// Frees a string returned by this library
+//
+// # Safety
+//
+// `text` must be null or a string returned by this library and not freed yet.
+void tetherion_string_free(char *text);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TETHERION_H */
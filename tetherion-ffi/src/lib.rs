@@ -0,0 +1,331 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    std::{
+        ffi::{c_char, CStr, CString},
+        panic::{self, AssertUnwindSafe},
+        ptr,
+    },
+    tetherion::{
+        block::Block,
+        payload::Payload,
+        tetherion::{InvalidBlockError, Tetherion},
+    },
+};
+
+/// The outcome of a call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TetherionStatus {
+    /// The call succeeded
+    Ok = 0,
+
+    /// A pointer argument is null
+    NullPointer = 1,
+
+    /// A string argument is not valid UTF-8
+    InvalidString = 2,
+
+    /// A string argument is not valid JSON of the expected kind
+    InvalidJson = 3,
+
+    /// A block does not follow the rules of the blockchain
+    InvalidBlock = 4,
+
+    /// The library panicked, leaving the blockchain in an unspecified state
+    Panic = 5,
+}
+
+impl From<InvalidBlockError> for TetherionStatus {
+    fn from(_: InvalidBlockError) -> Self {
+        TetherionStatus::InvalidBlock
+    }
+}
+
+/// A blockchain storing payloads, owned by the caller
+pub struct TetherionChain(Tetherion<Payload>);
+
+/// Reads the C string as a string slice
+///
+/// # Safety
+///
+/// `text` must be null or point to a NUL-terminated string living as long as the slice.
+unsafe fn read_str<'a>(text: *const c_char) -> Result<&'a str, TetherionStatus> {
+    if text.is_null() {
+        return Err(TetherionStatus::NullPointer);
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map_err(|_| TetherionStatus::InvalidString)
+}
+
+/// Hands the string over to the caller, who frees it with `tetherion_string_free`
+fn into_c_string(text: String) -> *mut c_char {
+    CString::new(text)
+        .expect("JSON and HEX strings do not contain NUL bytes")
+        .into_raw()
+}
+
+/// Validates each block of the blockchain regarding the previous one, hashing all of them
+fn is_valid(tetherion: &Tetherion<Payload>) -> Result<(), InvalidBlockError> {
+    tetherion.is_valid_with(&|block| block.has_valid_hash())
+}
+
+/// Runs the body of an exported function, returning the fallback instead if the body panics, so
+/// that panics never unwind into the caller
+fn guard<R>(fallback: R, body: impl FnOnce() -> R) -> R {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(fallback)
+}
+
+/// Runs an operation on the blockchain and turns its outcome into a status
+fn status(operation: impl FnOnce() -> Result<(), TetherionStatus>) -> TetherionStatus {
+    guard(TetherionStatus::Panic, || {
+        operation().err().unwrap_or(TetherionStatus::Ok)
+    })
+}
+
+/// Creates a blockchain whose genesis block stores the given text. Returns null if the text is
+/// null or not valid UTF-8, or if mining the genesis block panics.
+///
+/// # Safety
+///
+/// `genesis_data` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_new(
+    genesis_data: *const c_char,
+    difficulty: usize,
+) -> *mut TetherionChain {
+    guard(ptr::null_mut(), || match read_str(genesis_data) {
+        Ok(data) => Box::into_raw(Box::new(TetherionChain(Tetherion::new(
+            Payload::Text(data.to_owned()),
+            difficulty,
+        )))),
+        Err(_) => ptr::null_mut(),
+    })
+}
+
+/// Creates a blockchain out of its JSON serialization, as produced by `tetherion_chain_to_json`,
+/// rebuilding its state from the blocks. Returns null if the JSON is not a valid blockchain.
+///
+/// # Safety
+///
+/// `json` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_from_json(json: *const c_char) -> *mut TetherionChain {
+    guard(ptr::null_mut(), || {
+        read_str(json)
+            .ok()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .filter(|tetherion: &Tetherion<Payload>| is_valid(tetherion).is_ok())
+            .map_or(ptr::null_mut(), |tetherion| {
+                Box::into_raw(Box::new(TetherionChain(tetherion)))
+            })
+    })
+}
+
+/// Frees the blockchain
+///
+/// # Safety
+///
+/// `chain` must be null or a blockchain created by this library and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_free(chain: *mut TetherionChain) {
+    guard((), || {
+        if !chain.is_null() {
+            drop(Box::from_raw(chain));
+        }
+    })
+}
+
+/// Gets the ID of the latest block of the blockchain, or 0 if the blockchain is null
+///
+/// # Safety
+///
+/// `chain` must be null or a live blockchain created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_height(chain: *const TetherionChain) -> u64 {
+    guard(0, || chain.as_ref().map_or(0, |chain| chain.0.height()))
+}
+
+/// Gets the hash, in HEX format, of the latest block of the blockchain, or null if the
+/// blockchain is null. The string is freed with `tetherion_string_free`.
+///
+/// # Safety
+///
+/// `chain` must be null or a live blockchain created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_tip_hash(chain: *const TetherionChain) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        chain.as_ref().map_or(ptr::null_mut(), |chain| {
+            let tip = chain
+                .0
+                .blocks()
+                .last()
+                .expect("there is at least one block");
+            into_c_string(tip.hash.clone())
+        })
+    })
+}
+
+/// Mines a block storing the given text on top of the blockchain and adds it to the blockchain.
+/// Blocks the calling thread until a valid nonce is found.
+///
+/// # Safety
+///
+/// `chain` must be null or a live blockchain created by this library, and `data` null or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_mine_block(
+    chain: *mut TetherionChain,
+    data: *const c_char,
+) -> TetherionStatus {
+    status(|| {
+        let chain = chain.as_mut().ok_or(TetherionStatus::NullPointer)?;
+        let data = Payload::Text(read_str(data)?.to_owned());
+        let previous = chain
+            .0
+            .blocks()
+            .last()
+            .expect("there is at least one block");
+        let block = Block::new(previous.id + 1, &previous.hash, data, chain.0.difficulty());
+        Ok(chain.0.add_block(block)?)
+    })
+}
+
+/// Validates the block, given in JSON format, against the blockchain and adds it on top of it
+///
+/// # Safety
+///
+/// `chain` must be null or a live blockchain created by this library, and `block_json` null or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_add_block(
+    chain: *mut TetherionChain,
+    block_json: *const c_char,
+) -> TetherionStatus {
+    status(|| {
+        let chain = chain.as_mut().ok_or(TetherionStatus::NullPointer)?;
+        let block: Block<Payload> = serde_json::from_str(read_str(block_json)?)
+            .map_err(|_| TetherionStatus::InvalidJson)?;
+        Ok(chain.0.add_block(block)?)
+    })
+}
+
+/// Validates each block of the blockchain regarding the previous one
+///
+/// # Safety
+///
+/// `chain` must be null or a live blockchain created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_validate(chain: *const TetherionChain) -> TetherionStatus {
+    status(|| {
+        let chain = chain.as_ref().ok_or(TetherionStatus::NullPointer)?;
+        Ok(is_valid(&chain.0)?)
+    })
+}
+
+/// Serializes the blockchain to JSON, or returns null if the blockchain is null. The string is
+/// freed with `tetherion_string_free`.
+///
+/// # Safety
+///
+/// `chain` must be null or a live blockchain created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_chain_to_json(chain: *const TetherionChain) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        chain.as_ref().map_or(ptr::null_mut(), |chain| {
+            into_c_string(serde_json::to_string(&chain.0).expect("can jsonify blockchain"))
+        })
+    })
+}
+
+/// Serializes the block with the given ID to JSON, or returns null if there is no such block.
+/// The string is freed with `tetherion_string_free`.
+///
+/// # Safety
+///
+/// `chain` must be null or a live blockchain created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_block_to_json(
+    chain: *const TetherionChain,
+    id: u64,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        chain
+            .as_ref()
+            .and_then(|chain| chain.0.blocks().iter().find(|block| block.id == id))
+            .map_or(ptr::null_mut(), |block| {
+                into_c_string(serde_json::to_string(block.as_ref()).expect("can jsonify block"))
+            })
+    })
+}
+
+/// Frees a string returned by this library
+///
+/// # Safety
+///
+/// `text` must be null or a string returned by this library and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn tetherion_string_free(text: *mut c_char) {
+    guard((), || {
+        if !text.is_null() {
+            drop(CString::from_raw(text));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drive_chain_through_ffi() {
+        let text = |text: &str| CString::new(text).unwrap();
+        let take = |text: *mut c_char| unsafe {
+            let owned = CStr::from_ptr(text).to_str().unwrap().to_owned();
+            tetherion_string_free(text);
+            owned
+        };
+
+        unsafe {
+            let chain = tetherion_chain_new(text("genesis").as_ptr(), 1);
+            assert_eq!(
+                tetherion_chain_mine_block(chain, text("a").as_ptr()),
+                TetherionStatus::Ok
+            );
+            assert_eq!(tetherion_chain_height(chain), 1);
+            assert_eq!(tetherion_chain_validate(chain), TetherionStatus::Ok);
+
+            let block = take(tetherion_block_to_json(chain, 1));
+            assert_eq!(
+                tetherion_chain_add_block(chain, text(&block).as_ptr()),
+                TetherionStatus::InvalidBlock
+            );
+            assert_eq!(
+                tetherion_chain_add_block(chain, text("{}").as_ptr()),
+                TetherionStatus::InvalidJson
+            );
+
+            let json = take(tetherion_chain_to_json(chain));
+            let copy = tetherion_chain_from_json(text(&json).as_ptr());
+            assert!(!copy.is_null());
+            assert_eq!(
+                take(tetherion_chain_tip_hash(copy)),
+                take(tetherion_chain_tip_hash(chain))
+            );
+            assert!(tetherion_chain_from_json(text("[]").as_ptr()).is_null());
+            let empty = r#"{"blocks":[],"difficulty":1}"#;
+            assert!(tetherion_chain_from_json(text(empty).as_ptr()).is_null());
+
+            tetherion_chain_free(copy);
+            tetherion_chain_free(chain);
+        }
+        assert_eq!(
+            unsafe { tetherion_chain_mine_block(ptr::null_mut(), ptr::null()) },
+            TetherionStatus::NullPointer
+        );
+        assert_eq!(
+            status(|| panic!("unexpected state")),
+            TetherionStatus::Panic
+        );
+    }
+}
@@ -0,0 +1,237 @@
+/// Copyright (c) 2022 Tetherion
+use {
+    pyo3::{create_exception, exceptions::PyValueError, prelude::*},
+    tetherion_core::{
+        block::Block,
+        payload::Payload,
+        tetherion::{InvalidBlockError as ChainError, Tetherion},
+    },
+};
+
+create_exception!(
+    tetherion,
+    InvalidBlockError,
+    PyValueError,
+    "A block does not follow the rules of the blockchain"
+);
+
+/// Raises the Python exception matching the invalid block
+fn invalid_block(err: ChainError) -> PyErr {
+    InvalidBlockError::new_err(err.to_string())
+}
+
+/// Raises a `ValueError` for JSON not describing the expected value
+fn invalid_json(err: serde_json::Error) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+/// Parses the payload, given either in JSON format, e.g. `{"binary":"00ff"}`, or as plain text
+fn parse_payload(data: &str) -> Payload {
+    serde_json::from_str(data).unwrap_or_else(|_| Payload::Text(data.to_owned()))
+}
+
+/// A block storing a payload
+#[pyclass(name = "Block", module = "tetherion", frozen)]
+#[derive(Clone)]
+struct PyBlock(Block<Payload>);
+
+#[pymethods]
+impl PyBlock {
+    /// Mines a block storing the data on top of the block with the given ID and hash. The data
+    /// is either a payload in JSON format or plain text.
+    #[new]
+    fn new(py: Python, id: u64, previous_hash: &str, data: &str, difficulty: usize) -> Self {
+        let data = parse_payload(data);
+        Self(py.allow_threads(|| Block::new(id, previous_hash, data, difficulty)))
+    }
+
+    /// Parses a block from its JSON format
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Self> {
+        serde_json::from_str(json).map(Self).map_err(invalid_json)
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).expect("can jsonify block")
+    }
+
+    #[getter]
+    fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    #[getter]
+    fn hash(&self) -> &str {
+        &self.0.hash
+    }
+
+    #[getter]
+    fn previous_hash(&self) -> &str {
+        &self.0.previous_hash
+    }
+
+    #[getter]
+    fn timestamp(&self) -> i64 {
+        self.0.timestamp()
+    }
+
+    #[getter]
+    fn nonce(&self) -> u64 {
+        self.0.header().nonce
+    }
+
+    /// The payload of the block, in JSON format
+    #[getter]
+    fn data(&self) -> String {
+        serde_json::to_string(self.0.data()).expect("can jsonify payload")
+    }
+
+    /// Checks whether the block's hash matches its content
+    fn has_valid_hash(&self) -> bool {
+        self.0.has_valid_hash()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Block(id={}, hash='{}')", self.0.id, self.0.hash)
+    }
+}
+
+/// A blockchain storing payloads
+#[pyclass(name = "Tetherion", module = "tetherion")]
+#[derive(Clone)]
+struct PyTetherion(Tetherion<Payload>);
+
+#[pymethods]
+impl PyTetherion {
+    /// Creates a blockchain whose genesis block stores the data
+    #[new]
+    #[pyo3(signature = (genesis_data, difficulty = 2))]
+    fn new(py: Python, genesis_data: &str, difficulty: usize) -> Self {
+        let data = parse_payload(genesis_data);
+        Self(py.allow_threads(|| Tetherion::new(data, difficulty)))
+    }
+
+    /// Parses a blockchain from its JSON format, rebuilding its state from the blocks, raising
+    /// `InvalidBlockError` if a block does not follow the previous one
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Self> {
+        let tetherion: Tetherion<Payload> = serde_json::from_str(json).map_err(invalid_json)?;
+        tetherion
+            .is_valid_with(&|block| block.has_valid_hash())
+            .map_err(invalid_block)?;
+        Ok(Self(tetherion))
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).expect("can jsonify blockchain")
+    }
+
+    #[getter]
+    fn blocks(&self) -> Vec<PyBlock> {
+        self.0
+            .blocks()
+            .iter()
+            .map(|block| PyBlock(block.as_ref().clone()))
+            .collect()
+    }
+
+    #[getter]
+    fn height(&self) -> u64 {
+        self.0.height()
+    }
+
+    #[getter]
+    fn difficulty(&self) -> usize {
+        self.0.difficulty()
+    }
+
+    /// Mines a block storing the data on top of the blockchain and adds it to the blockchain
+    fn mine(&mut self, py: Python, data: &str) -> PyResult<PyBlock> {
+        let previous = self.0.blocks().last().expect("there is at least one block");
+        let (id, previous_hash) = (previous.id + 1, previous.hash.clone());
+        let difficulty = self.0.difficulty();
+        let block =
+            py.allow_threads(|| Block::new(id, &previous_hash, parse_payload(data), difficulty));
+        self.add_block(&PyBlock(block.clone()))?;
+        Ok(PyBlock(block))
+    }
+
+    /// Adds the block on top of the blockchain, raising `InvalidBlockError` if it does not follow
+    /// the latest block
+    fn add_block(&mut self, block: &PyBlock) -> PyResult<()> {
+        self.0.add_block(block.0.clone()).map_err(invalid_block)
+    }
+
+    /// Validates each block of the blockchain regarding the previous one, raising
+    /// `InvalidBlockError` at the first invalid block
+    fn validate(&self) -> PyResult<()> {
+        self.0
+            .is_valid_with(&|block| block.has_valid_hash())
+            .map_err(invalid_block)
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.blocks().len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Tetherion(height={}, difficulty={})",
+            self.0.height(),
+            self.0.difficulty()
+        )
+    }
+}
+
+/// Blocks and blockchains of Tetherion, for scripting chain experiments
+#[pymodule]
+fn tetherion(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyBlock>()?;
+    m.add_class::<PyTetherion>()?;
+    m.add("InvalidBlockError", m.py().get_type::<InvalidBlockError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, pyo3::types::PyDict};
+
+    #[test]
+    fn script_chain() {
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "tetherion").unwrap();
+            tetherion(&module).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("tetherion", module).unwrap();
+            py.run(
+                cr#"
+chain = tetherion.Tetherion("genesis", 1)
+block = chain.mine("a")
+assert chain.height == 1 and len(chain) == 2
+assert block.data == '{"text":"a"}' and block.has_valid_hash()
+chain.mine('{"binary":"00ff"}')
+chain.validate()
+
+copy = tetherion.Tetherion.from_json(chain.to_json())
+assert [b.hash for b in copy.blocks] == [b.hash for b in chain.blocks]
+for tampered in [chain.to_json().replace('"text":"a"', '"text":"x"'), '{"blocks":[],"difficulty":1}']:
+    try:
+        tetherion.Tetherion.from_json(tampered)
+        raise AssertionError("the blockchain should be rejected")
+    except ValueError:
+        pass
+
+fork = tetherion.Block(1, chain.blocks[0].hash, "b", 1)
+try:
+    chain.add_block(fork)
+    raise AssertionError("the block should be rejected")
+except tetherion.InvalidBlockError:
+    pass
+"#,
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+}